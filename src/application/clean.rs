@@ -0,0 +1,155 @@
+//! Removing the files dotstrap keeps around between runs.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::errors::Result;
+use crate::infrastructure::lock::RunLock;
+use crate::infrastructure::repository;
+use crate::infrastructure::state;
+
+/// Summary of a clean.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct CleanReport {
+    /// Directories removed, or planned in dry-run mode.
+    pub removed: Vec<PathBuf>,
+    pub dry_run: bool,
+}
+
+/// Remove the staging root of `home`, the clones kept under
+/// `~/.dotstrap/sources` for sources other than the last applied one, and,
+/// with `backups`, the `.dotstrap-backups` directories next to the
+/// destinations recorded in the state.
+///
+/// Links into the staging root dangle until the next run renders the
+/// templates again.
+pub fn clean(
+    home: &Path,
+    stage_root: &Path,
+    backups: bool,
+    dry_run: bool,
+    wait: bool,
+) -> Result<CleanReport> {
+    let _lock = if dry_run {
+        None
+    } else {
+        Some(RunLock::acquire(home, wait)?)
+    };
+    let state = state::load_state(home)?;
+    let mut removed = vec![stage_root.to_path_buf()];
+    let current = state.last_source.as_ref().map(|last| {
+        let (source, _) = repository::split_subdir(&last.source);
+        repository::cache_name(source)
+    });
+    if let Ok(entries) = fs::read_dir(home.join(".dotstrap/sources")) {
+        let mut stale: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| current.as_deref() != entry.file_name().to_str())
+            .map(|entry| entry.path())
+            .collect();
+        stale.sort();
+        removed.extend(stale);
+    }
+    if backups {
+        let dirs: BTreeSet<PathBuf> = state
+            .templates
+            .keys()
+            .filter_map(|destination| home.join(destination).parent().map(Path::to_path_buf))
+            .map(|dir| dir.join(".dotstrap-backups"))
+            .collect();
+        removed.extend(dirs);
+    }
+    removed.retain(|path| path.is_dir());
+    if !dry_run {
+        for path in &removed {
+            fs::remove_dir_all(path)?;
+        }
+    }
+    Ok(CleanReport { removed, dry_run })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::state::{SourceState, State, TemplateOrigin};
+    use crate::services::linker;
+    use tempfile::TempDir;
+
+    /// Home with a staging root, the clones of two sources, the last applied
+    /// one being `https://github.com/me/dotfiles`, and a backup next to
+    /// `.config/git/config`.
+    fn setup() -> TempDir {
+        let home = TempDir::new().unwrap();
+        let stage_root = linker::default_stage_root(home.path());
+        fs::create_dir_all(&stage_root).unwrap();
+        fs::write(stage_root.join(".zshrc"), "export EDITOR=vim\n").unwrap();
+        for clone in ["https-github-com-me-dotfiles", "https-github-com-me-old"] {
+            fs::create_dir_all(home.path().join(".dotstrap/sources").join(clone)).unwrap();
+        }
+        fs::create_dir_all(home.path().join(".config/git/.dotstrap-backups")).unwrap();
+        let mut recorded = State {
+            last_source: Some(SourceState {
+                source: "https://github.com/me/dotfiles.git//conf".into(),
+                git_ref: None,
+                subdir: None,
+            }),
+            ..State::default()
+        };
+        recorded.templates.insert(
+            PathBuf::from(".config/git/config"),
+            TemplateOrigin {
+                source: "https://github.com/me/dotfiles.git".into(),
+                template: PathBuf::from("gitconfig"),
+                profile: None,
+                inputs: None,
+                output: None,
+                merged: false,
+                mode: None,
+                create_only: false,
+            },
+        );
+        state::save_state(home.path(), &recorded).unwrap();
+        home
+    }
+
+    #[test]
+    fn clean_removes_staging_and_stale_clones_but_keeps_backups() {
+        let home = setup();
+        let stage_root = linker::default_stage_root(home.path());
+
+        let planned = clean(home.path(), &stage_root, false, true, false).unwrap();
+        assert!(stage_root.exists());
+        let report = clean(home.path(), &stage_root, false, false, false).unwrap();
+
+        assert_eq!(planned.removed, report.removed);
+        assert_eq!(
+            report.removed,
+            [
+                stage_root.clone(),
+                home.path()
+                    .join(".dotstrap/sources/https-github-com-me-old"),
+            ]
+        );
+        assert!(!stage_root.exists());
+        assert!(
+            home.path()
+                .join(".dotstrap/sources/https-github-com-me-dotfiles")
+                .exists()
+        );
+        assert!(home.path().join(".config/git/.dotstrap-backups").exists());
+    }
+
+    #[test]
+    fn clean_removes_the_backups_of_managed_files_when_asked() {
+        let home = setup();
+        let stage_root = linker::default_stage_root(home.path());
+
+        let report = clean(home.path(), &stage_root, true, false, false).unwrap();
+
+        assert_eq!(report.removed.len(), 3);
+        assert!(!home.path().join(".config/git/.dotstrap-backups").exists());
+    }
+}
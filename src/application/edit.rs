@@ -0,0 +1,123 @@
+//! Editing the template of a managed file, for `dotstrap edit`.
+
+use std::path::{Path, PathBuf};
+
+use super::owner::owner;
+use super::source::source_path;
+use super::watch::render_local;
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::CommandExecutor;
+use crate::infrastructure::lock::RunLock;
+use crate::infrastructure::repository;
+use crate::services::linker;
+use crate::services::outcome::LinkOutcome;
+use crate::services::templating::Permissions;
+
+/// Template to edit for a managed file, located in the working copy of the
+/// layer declaring it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct EditTarget {
+    /// Managed file, relative to the home directory.
+    pub destination: PathBuf,
+    /// Template source file.
+    pub template: PathBuf,
+    /// Working copy of the source or base layer declaring the template.
+    pub layer: PathBuf,
+    /// Profile the file was applied with.
+    pub profile: Option<String>,
+}
+
+/// Find the template of `destination`, resolved from `home` when relative,
+/// as `dotstrap owner` does, in the working copy `dotstrap source-path`
+/// reports for its layer.
+pub fn edit_target(
+    home: &Path,
+    destination: &Path,
+    source: Option<&str>,
+    profile: Option<&str>,
+    executor: &dyn CommandExecutor,
+) -> Result<EditTarget> {
+    let owner = owner(home, destination, source, profile, executor)?;
+    let layer = source_path(home, &owner.source, None, executor)?;
+    let destination = owner
+        .destination
+        .strip_prefix(home)
+        .map_err(|_| DotstrapError::Unmanaged(owner.destination.clone()))?
+        .to_path_buf();
+    Ok(EditTarget {
+        destination,
+        template: layer.join(&owner.template),
+        layer,
+        profile: owner.profile.or_else(|| profile.map(str::to_string)),
+    })
+}
+
+/// Render the template of `target` again and link it into `home`, staged
+/// under `stage_root`, leaving the other entries of the manifest alone.
+///
+/// The template is rendered with the manifest and values of its layer.
+pub fn apply_target(
+    target: &EditTarget,
+    home: &Path,
+    stage_root: &Path,
+    permissions: Permissions,
+    executor: &dyn CommandExecutor,
+) -> Result<Vec<LinkOutcome>> {
+    let repo = repository::resolve_repository(&target.layer.to_string_lossy(), None, executor)?;
+    let mut rendered = render_local(
+        &repo,
+        home,
+        target.profile.as_deref(),
+        permissions,
+        executor,
+    )?;
+    rendered
+        .templates
+        .retain(|item| item.template.destination == target.destination);
+    if rendered.templates.is_empty() {
+        return Err(DotstrapError::Unmanaged(home.join(&target.destination)));
+    }
+    let _lock = RunLock::acquire(home, true)?;
+    linker::link_templates(home, stage_root, &rendered, false)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use crate::services::outcome::ItemStatus;
+    use tempfile::TempDir;
+
+    #[test]
+    fn edit_target_locates_the_template_and_apply_links_only_it() {
+        let home = TempDir::new().unwrap();
+        let executor = RecordingCommandExecutor::default();
+        let source_dir = Path::new("tests/empty-config").canonicalize().unwrap();
+
+        let target = edit_target(
+            home.path(),
+            Path::new(".zshrc"),
+            Some("tests/empty-config"),
+            None,
+            &executor,
+        )
+        .unwrap();
+        assert_eq!(target.destination, PathBuf::from(".zshrc"));
+        assert_eq!(target.template, source_dir.join("templates/zshrc.hbs"));
+        assert_eq!(target.layer, source_dir);
+
+        let stage_root = linker::default_stage_root(home.path());
+        let links = apply_target(
+            &target,
+            home.path(),
+            &stage_root,
+            Permissions::default(),
+            &executor,
+        )
+        .unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].status, ItemStatus::Created);
+        assert!(home.path().join(".zshrc").exists());
+        assert!(!home.path().join(".gitconfig").exists());
+    }
+}
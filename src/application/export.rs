@@ -0,0 +1,34 @@
+//! Exporting the package lists of a source to the formats of other tools.
+
+use std::path::Path;
+
+use crate::config;
+use crate::errors::Result;
+
+/// Brewfile equivalent to the Homebrew packages declared by `repo`, in its
+/// package files and its manifest's `packages` section.
+pub fn brewfile(repo: &Path) -> Result<String> {
+    let mut spec = config::load_packages_spec(repo)?.unwrap_or_default();
+    if repo.join(config::MANIFEST_NAME).exists()
+        && let Some(inline) = config::load_manifest(repo)?.packages
+    {
+        spec = spec.layer(inline);
+    }
+    Ok(format!(
+        "# Generated by `dotstrap export brewfile`.\n{}",
+        config::format_brewfile(&spec.brew)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brewfile_lists_legacy_brew_packages() {
+        let brewfile = brewfile(Path::new("tests/config-brewfile")).unwrap();
+
+        assert!(brewfile.starts_with("# Generated by"));
+        assert!(brewfile.contains("cask \"iterm2\"\n"));
+    }
+}
@@ -0,0 +1,577 @@
+//! Rendering templates against fixture contexts and comparing them with the
+//! expected outputs committed next to them, for `dotstrap test`.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{ModuleSelection, directory_facts, layer_manifests, resolve_bases};
+use crate::config;
+use crate::config::values::LayeredValues;
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::CommandExecutor;
+use crate::infrastructure::paths::{IgnoreRules, KnownDirs};
+use crate::infrastructure::repository::RepoHandle;
+use crate::services::templating::Permissions;
+use crate::services::{diff, templating};
+
+/// Directory of the source holding the test cases, one directory each.
+pub const TESTS_DIR: &str = "tests";
+/// File of a test case describing the context to render with.
+pub const CONTEXT_NAME: &str = "context.yaml";
+/// Directory of a test case holding the expected outputs, laid out as the
+/// home directory they are linked into.
+pub const EXPECTED_DIR: &str = "expected";
+
+/// Home the templates of a test case are rendered for, unless it sets one.
+const DEFAULT_HOME: &str = "/home/dotstrap";
+
+/// Context of a test case, as declared in its `context.yaml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Fixture {
+    /// Profile to render with.
+    profile: Option<String>,
+    /// Operating system templates are selected and rendered for, this host's
+    /// when omitted.
+    os: Option<String>,
+    /// Home directory destinations and `system` directories are resolved in.
+    home: Option<PathBuf>,
+    /// Values overriding those of the source, its profile, and its questions.
+    #[serde(default)]
+    values: HashMap<String, Value>,
+    /// Values of the secrets, which are never read from this host.
+    #[serde(default)]
+    secrets: HashMap<String, Value>,
+    /// Facts overriding the `system` ones derived from `os`, `home`, and
+    /// `profile`.
+    #[serde(default)]
+    system: serde_json::Map<String, Value>,
+}
+
+/// How a rendered file compares with its expected output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mismatch {
+    /// Rendered differently from its expected output.
+    Differs,
+    /// Rendered without an expected output.
+    NotExpected,
+    /// Expected output of a file no template renders.
+    NotRendered,
+}
+
+/// File of a test case whose rendering does not match its expected output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileMismatch {
+    /// Destination, relative to the home of the test case.
+    pub destination: PathBuf,
+    pub mismatch: Mismatch,
+    /// Lines of the expected output changed by the rendering.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub diff: String,
+}
+
+/// Outcome of a test case.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CaseReport {
+    pub name: String,
+    pub mismatches: Vec<FileMismatch>,
+    /// Expected outputs written or removed by `--update`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub updated: Vec<PathBuf>,
+    /// Why the templates could not be rendered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CaseReport {
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty() && self.error.is_none()
+    }
+}
+
+/// Render the templates of `repo`, layered over its bases, for each test
+/// case under its `tests` directory, or those named in `only`, and compare
+/// them with the expected outputs of the case.
+///
+/// With `update`, the expected outputs are replaced by the rendered files
+/// instead, and those no template renders any more are removed. A case that
+/// cannot be rendered is reported and the others still run.
+pub fn test_templates(
+    repo: &RepoHandle,
+    only: &[String],
+    update: bool,
+    permissions: Permissions,
+    executor: &dyn CommandExecutor,
+) -> Result<Vec<CaseReport>> {
+    let mut manifest = config::load_manifest(repo.path())?;
+    let bases = resolve_bases(repo, &mut manifest, &[], executor)?;
+    let mut layers: Vec<&Path> = bases.iter().map(|base| base.handle.path()).collect();
+    layers.push(repo.path());
+    let manifest = layer_manifests(&layers, &bases, manifest, &ModuleSelection::default())?;
+    let mut values = LayeredValues::default();
+    values.layer("module defaults", manifest.module_values.clone());
+    for layer in &layers {
+        config::layer_values(&mut values, layer)?;
+    }
+    let ignore = config::load_ignore(repo.path())?;
+
+    let mut reports = Vec::new();
+    for (name, dir) in cases(repo.path())? {
+        if !only.is_empty() && !only.contains(&name) {
+            continue;
+        }
+        let mut report = CaseReport {
+            name,
+            mismatches: Vec::new(),
+            updated: Vec::new(),
+            error: None,
+        };
+        let run = || {
+            let rendered =
+                render_case(repo.path(), &dir, &manifest, &values, permissions, executor)?;
+            let expected = ExpectedDir {
+                path: dir.join(EXPECTED_DIR),
+                repo: repo.path(),
+                ignore: &ignore,
+            };
+            if update {
+                update_expected(&expected, &rendered).map(|updated| (Vec::new(), updated))
+            } else {
+                compare(&expected, &rendered).map(|mismatches| (mismatches, Vec::new()))
+            }
+        };
+        match run() {
+            Ok((mismatches, updated)) => {
+                report.mismatches = mismatches;
+                report.updated = updated;
+            }
+            Err(err) => report.error = Some(err.to_string()),
+        }
+        reports.push(report);
+    }
+    if let Some(missing) = only
+        .iter()
+        .find(|name| !reports.iter().any(|report| &report.name == *name))
+    {
+        return Err(DotstrapError::UnknownTestCase {
+            name: missing.clone(),
+            dir: repo.path().join(TESTS_DIR),
+        });
+    }
+    Ok(reports)
+}
+
+/// Test cases of `source`, sorted by name: the directories of its `tests`
+/// directory holding a `context.yaml`.
+fn cases(source: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let dir = source.join(TESTS_DIR);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut cases = Vec::new();
+    for entry in std::fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.join(CONTEXT_NAME).is_file() {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            cases.push((name, path));
+        }
+    }
+    cases.sort();
+    Ok(cases)
+}
+
+/// Files rendered for the test case in `dir`, as destinations relative to
+/// its home with their contents.
+fn render_case(
+    source: &Path,
+    dir: &Path,
+    manifest: &config::Manifest,
+    values: &LayeredValues,
+    permissions: Permissions,
+    executor: &dyn CommandExecutor,
+) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+    let path = dir.join(CONTEXT_NAME);
+    let fixture: Fixture = serde_yaml::from_slice(&std::fs::read(&path)?)
+        .map_err(|source| DotstrapError::Yaml { source, path })?;
+    let os = fixture.os.as_deref().unwrap_or(std::env::consts::OS);
+    let home = fixture
+        .home
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_HOME));
+    let profile = fixture.profile.as_deref();
+
+    let mut manifest = manifest.clone();
+    let mut values = values.clone();
+    if let Some(name) = profile {
+        let selected =
+            manifest
+                .profiles
+                .get(name)
+                .ok_or_else(|| DotstrapError::UnknownProfile {
+                    name: name.to_string(),
+                    available: manifest.profiles.keys().cloned().collect(),
+                })?;
+        values.layer(&format!("profile `{name}`"), selected.values.clone());
+    }
+    let merged = values.values();
+    let defaults = manifest
+        .questions
+        .iter()
+        .filter(|question| !merged.contains_key(&question.name))
+        .map(|question| {
+            let default = question.default.clone().unwrap_or(Value::Null);
+            (question.name.clone(), default)
+        })
+        .collect();
+    values.layer("question defaults", defaults);
+    let layer = dir.join(CONTEXT_NAME).display().to_string();
+    values.layer(&layer, fixture.values);
+    let dirs = KnownDirs::for_os(&home, os);
+    manifest.apply_conditions(os, profile);
+    manifest.expand_destinations(&dirs)?;
+    manifest.templates.retain(|template| !template.copy);
+    let mut system = directory_facts(&dirs, os, profile);
+    system.insert("brew_prefix".into(), Value::Null);
+    system.extend(fixture.system);
+    let context = templating::build_context(&values.values(), &fixture.secrets, &system);
+    let origin = source.join(config::MANIFEST_NAME);
+    manifest.apply_enabled(|expression| templating::is_enabled(expression, &context, &origin))?;
+
+    let rendered =
+        templating::render_templates(source, &manifest, &context, permissions, executor)?;
+    rendered
+        .templates
+        .iter()
+        .map(|item| {
+            let destination = item
+                .template
+                .destination
+                .strip_prefix(&home)
+                .unwrap_or(&item.template.destination)
+                .components()
+                .filter(|component| matches!(component, Component::Normal(_)))
+                .collect();
+            Ok((destination, std::fs::read(&item.rendered_path)?))
+        })
+        .collect()
+}
+
+/// Directory of the expected outputs of a test case, in the source `repo`
+/// whose ignore rules apply to it.
+struct ExpectedDir<'a> {
+    path: PathBuf,
+    repo: &'a Path,
+    ignore: &'a IgnoreRules,
+}
+
+impl ExpectedDir<'_> {
+    fn join(&self, destination: &Path) -> PathBuf {
+        self.path.join(destination)
+    }
+
+    /// Files of the directory, relative to it and sorted, leaving out those
+    /// the source ignores, such as editor swap files.
+    fn files(&self) -> Result<Vec<PathBuf>> {
+        if !self.path.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut files = Vec::new();
+        for entry in walkdir::WalkDir::new(&self.path).sort_by_file_name() {
+            let entry = entry.map_err(std::io::Error::from)?;
+            let ignored = entry
+                .path()
+                .strip_prefix(self.repo)
+                .is_ok_and(|path| self.ignore.is_ignored(path, entry.file_type().is_dir()));
+            if entry.file_type().is_file()
+                && !ignored
+                && let Ok(relative) = entry.path().strip_prefix(&self.path)
+            {
+                files.push(relative.to_path_buf());
+            }
+        }
+        Ok(files)
+    }
+}
+
+fn compare(expected: &ExpectedDir, rendered: &[(PathBuf, Vec<u8>)]) -> Result<Vec<FileMismatch>> {
+    let mut mismatches = Vec::new();
+    for (destination, contents) in rendered {
+        let mismatch = |mismatch, diff| FileMismatch {
+            destination: destination.clone(),
+            mismatch,
+            diff,
+        };
+        match std::fs::read(expected.join(destination)) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                mismatches.push(mismatch(Mismatch::NotExpected, String::new()))
+            }
+            Err(err) => return Err(err.into()),
+            Ok(old) if old != *contents => {
+                let diff = match (String::from_utf8(old), std::str::from_utf8(contents)) {
+                    (Ok(old), Ok(new)) => diff::line_diff(&old, new),
+                    _ => "binary files differ\n".to_string(),
+                };
+                mismatches.push(mismatch(Mismatch::Differs, diff));
+            }
+            Ok(_) => {}
+        }
+    }
+    for file in expected.files()? {
+        if !rendered.iter().any(|(destination, _)| *destination == file) {
+            mismatches.push(FileMismatch {
+                destination: file,
+                mismatch: Mismatch::NotRendered,
+                diff: String::new(),
+            });
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Write the `rendered` files into `expected` and remove the others,
+/// returning the files changed.
+fn update_expected(
+    expected: &ExpectedDir,
+    rendered: &[(PathBuf, Vec<u8>)],
+) -> Result<Vec<PathBuf>> {
+    let mut updated = Vec::new();
+    for file in expected.files()? {
+        if !rendered.iter().any(|(destination, _)| *destination == file) {
+            std::fs::remove_file(expected.join(&file))?;
+            updated.push(file);
+        }
+    }
+    for (destination, contents) in rendered {
+        let path = expected.join(destination);
+        if std::fs::read(&path).ok().as_ref() != Some(contents) {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&path, contents)?;
+            updated.push(destination.clone());
+        }
+    }
+    updated.sort();
+    Ok(updated)
+}
+
+/// One line per test case, the mismatches of the failed ones, and a summary.
+pub fn format_reports(reports: &[CaseReport]) -> String {
+    if reports.is_empty() {
+        return format!("No test cases found under `{TESTS_DIR}/`.\n");
+    }
+    let mut out = String::new();
+    for report in reports {
+        let outcome = match (&report.error, report.passed()) {
+            (Some(_), _) | (None, false) => "FAILED".to_string(),
+            (None, true) if !report.updated.is_empty() => {
+                format!("updated ({} file(s))", report.updated.len())
+            }
+            (None, true) => "ok".to_string(),
+        };
+        let _ = writeln!(out, "test {} ... {outcome}", report.name);
+    }
+    let failed: Vec<&CaseReport> = reports.iter().filter(|report| !report.passed()).collect();
+    if !failed.is_empty() {
+        out.push_str("\nfailures:\n");
+    }
+    for report in &failed {
+        let _ = writeln!(out, "\n---- {} ----", report.name);
+        if let Some(error) = &report.error {
+            let _ = writeln!(out, "{error}");
+        }
+        for mismatch in &report.mismatches {
+            let destination = mismatch.destination.display();
+            let _ = match mismatch.mismatch {
+                Mismatch::Differs => writeln!(out, "{destination} differs:"),
+                Mismatch::NotExpected => writeln!(out, "{destination} has no expected output"),
+                Mismatch::NotRendered => {
+                    writeln!(out, "{destination} is expected but not rendered")
+                }
+            };
+            out.push_str(&mismatch.diff);
+        }
+    }
+    let _ = writeln!(
+        out,
+        "\ntest result: {} passed; {} failed.",
+        reports.len() - failed.len(),
+        failed.len()
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use crate::infrastructure::repository;
+    use tempfile::TempDir;
+
+    fn source() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let files = [
+            (
+                "manifest.yaml",
+                "version: 2\n\
+                 templates:\n\
+                 \x20 - source: zshrc.hbs\n    destination: .zshrc\n\
+                 \x20 - source: alacritty.hbs\n    destination: \"{appdata}/alacritty.toml\"\n\
+                 \x20 - source: work.hbs\n    destination: .work\n    when:\n      profile: [work]\n\
+                 profiles:\n  work:\n    values:\n      email: me@work.example\n",
+            ),
+            ("values.yaml", "email: me@example.com\n"),
+            ("zshrc.hbs", "EMAIL={{email}}\nTOKEN={{secrets.token}}\n"),
+            ("alacritty.hbs", "# {{system.os}} {{system.appdata}}\n"),
+            ("work.hbs", "work\n"),
+            (
+                "tests/personal/context.yaml",
+                "os: linux\nsecrets:\n  token: dummy\n",
+            ),
+            (
+                "tests/work/context.yaml",
+                "profile: work\nos: macos\nhome: /Users/me\nvalues:\n  extra: 1\n",
+            ),
+        ];
+        for (path, contents) in files {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn run(dir: &TempDir, only: &[String], update: bool) -> Result<Vec<CaseReport>> {
+        let executor = RecordingCommandExecutor::default();
+        let repo =
+            repository::resolve_repository(dir.path().to_str().unwrap(), None, &executor).unwrap();
+        test_templates(&repo, only, update, Permissions::default(), &executor)
+    }
+
+    #[test]
+    fn update_writes_the_expected_outputs_of_each_case() {
+        let dir = source();
+
+        let reports = run(&dir, &[], true).unwrap();
+
+        assert!(reports.iter().all(CaseReport::passed));
+        let expected = |case: &str, file: &str| {
+            std::fs::read_to_string(
+                dir.path()
+                    .join("tests")
+                    .join(case)
+                    .join("expected")
+                    .join(file),
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            expected("personal", ".zshrc"),
+            "EMAIL=me@example.com\nTOKEN=dummy\n"
+        );
+        assert_eq!(
+            expected("personal", ".config/alacritty.toml"),
+            "# linux /home/dotstrap/.config\n"
+        );
+        assert_eq!(
+            expected("work", ".zshrc"),
+            "EMAIL=me@work.example\nTOKEN=\n"
+        );
+        assert_eq!(
+            expected("work", "Library/Application Support/alacritty.toml"),
+            "# macos /Users/me/Library/Application Support\n"
+        );
+        assert_eq!(expected("work", ".work"), "work\n");
+        assert!(!dir.path().join("tests/personal/expected/.work").exists());
+        assert_eq!(reports[1].updated.len(), 3);
+        assert!(
+            run(&dir, &[], false)
+                .unwrap()
+                .iter()
+                .all(CaseReport::passed)
+        );
+    }
+
+    #[test]
+    fn test_templates_reports_files_differing_from_their_expected_output() {
+        let dir = source();
+        run(&dir, &[], true).unwrap();
+        let expected = dir.path().join("tests/personal/expected");
+        std::fs::write(
+            expected.join(".zshrc"),
+            "EMAIL=old@example.com\nTOKEN=dummy\n",
+        )
+        .unwrap();
+        std::fs::remove_file(expected.join(".config/alacritty.toml")).unwrap();
+        std::fs::write(expected.join(".stale"), "").unwrap();
+
+        let reports = run(&dir, &["personal".to_string()], false).unwrap();
+
+        assert_eq!(reports.len(), 1);
+        assert_eq!(
+            reports[0].mismatches,
+            [
+                FileMismatch {
+                    destination: PathBuf::from(".zshrc"),
+                    mismatch: Mismatch::Differs,
+                    diff: "-EMAIL=old@example.com\n+EMAIL=me@example.com\n TOKEN=dummy\n"
+                        .to_string(),
+                },
+                FileMismatch {
+                    destination: PathBuf::from(".config/alacritty.toml"),
+                    mismatch: Mismatch::NotExpected,
+                    diff: String::new(),
+                },
+                FileMismatch {
+                    destination: PathBuf::from(".stale"),
+                    mismatch: Mismatch::NotRendered,
+                    diff: String::new(),
+                },
+            ]
+        );
+        let text = format_reports(&reports);
+        assert!(text.starts_with("test personal ... FAILED\n"));
+        assert!(text.contains(".zshrc differs:\n-EMAIL=old@example.com\n"));
+        assert!(text.ends_with("test result: 0 passed; 1 failed.\n"));
+    }
+
+    #[test]
+    fn test_templates_skips_ignored_expected_files() {
+        let dir = source();
+        run(&dir, &[], true).unwrap();
+        std::fs::write(dir.path().join(".dotstrapignore"), "*.swp\n").unwrap();
+        let swap = dir.path().join("tests/personal/expected/.zshrc.swp");
+        std::fs::write(&swap, "").unwrap();
+
+        assert!(run(&dir, &[], false).unwrap()[0].passed());
+        assert!(run(&dir, &[], true).unwrap()[0].updated.is_empty());
+        assert!(swap.exists());
+    }
+
+    #[test]
+    fn test_templates_reports_cases_that_cannot_render() {
+        let dir = source();
+        std::fs::write(
+            dir.path().join("tests/work/context.yaml"),
+            "profile: missing\n",
+        )
+        .unwrap();
+
+        let reports = run(&dir, &[], false).unwrap();
+
+        assert!(reports[1].error.as_ref().unwrap().contains("missing"));
+        assert!(matches!(
+            run(&dir, &["nope".to_string()], false),
+            Err(DotstrapError::UnknownTestCase { .. })
+        ));
+        assert_eq!(format_reports(&[]), "No test cases found under `tests/`.\n");
+    }
+}
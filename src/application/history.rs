@@ -0,0 +1,197 @@
+//! Recording and display of past runs.
+
+use std::fmt::Write;
+use std::path::Path;
+
+use super::ExecutionReport;
+use crate::errors::Result;
+use crate::infrastructure::history::{self, HistoryEntry};
+use crate::services::outcome::ItemStatus;
+
+/// Append the outcome of a run of `source` started at `started_at` to the
+/// history of `home`, returning the identifier of the new entry.
+pub fn record(
+    home: &Path,
+    started_at: u64,
+    source: &str,
+    result: &Result<ExecutionReport>,
+) -> Result<u64> {
+    let mut entry = HistoryEntry {
+        id: 0,
+        started_at,
+        source: source.to_string(),
+        revision: None,
+        error: None,
+        created: 0,
+        updated: 0,
+        unchanged: 0,
+        linked: Vec::new(),
+        package_commands: Vec::new(),
+        warnings: Vec::new(),
+        rollback_of: None,
+    };
+    match result {
+        Ok(report) => {
+            entry.revision = report.revision.clone();
+            entry.linked = report
+                .linked
+                .iter()
+                .chain(&report.binaries)
+                .chain(&report.downloads)
+                .cloned()
+                .collect();
+            let count = |status| {
+                entry
+                    .linked
+                    .iter()
+                    .filter(|link| link.status == status)
+                    .count()
+            };
+            entry.created = count(ItemStatus::Created);
+            entry.updated = count(ItemStatus::Updated);
+            entry.unchanged = count(ItemStatus::Unchanged);
+            entry.package_commands = report.package_commands.clone();
+            entry.warnings = report.warnings.clone();
+        }
+        Err(err) => entry.error = Some(err.to_string()),
+    }
+    history::append_entry(home, entry)
+}
+
+/// One line per run: identifier, date, outcome, source, and link counts.
+pub fn format_list(entries: &[HistoryEntry]) -> String {
+    let mut out = String::new();
+    for entry in entries {
+        let outcome = match (entry.rollback_of, entry.succeeded()) {
+            (Some(_), _) => "undo",
+            (None, true) => "ok",
+            (None, false) => "failed",
+        };
+        let _ = writeln!(
+            out,
+            "{:>4}  {}  {outcome:<6}  {}  {} created, {} updated, {} unchanged",
+            entry.id,
+            format_timestamp(entry.started_at),
+            source_at_revision(entry),
+            entry.created,
+            entry.updated,
+            entry.unchanged,
+        );
+    }
+    out
+}
+
+/// Every recorded detail of a run.
+pub fn format_entry(entry: &HistoryEntry) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "Run {}", entry.id);
+    let _ = writeln!(out, "Started: {}", format_timestamp(entry.started_at));
+    let _ = writeln!(out, "Source:  {}", source_at_revision(entry));
+    if let Some(id) = entry.rollback_of {
+        let _ = writeln!(out, "Rolled back run {id}");
+    }
+    match &entry.error {
+        Some(error) => {
+            let _ = writeln!(out, "Outcome: failed: {error}");
+        }
+        None => {
+            let _ = writeln!(
+                out,
+                "Outcome: ok, {} created, {} updated, {} unchanged",
+                entry.created, entry.updated, entry.unchanged
+            );
+        }
+    }
+    for link in &entry.linked {
+        let status = serde_json::to_value(link.status)
+            .ok()
+            .and_then(|value| value.as_str().map(str::to_string))
+            .unwrap_or_default();
+        let _ = write!(out, "  {status:<9} {}", link.path.display());
+        if let Some(backup) = &link.backup {
+            let _ = write!(out, " (backup: {})", backup.display());
+        }
+        out.push('\n');
+    }
+    for command in &entry.package_commands {
+        let _ = writeln!(out, "  ran       {command}");
+    }
+    for warning in &entry.warnings {
+        let _ = writeln!(out, "  warning   {warning}");
+    }
+    out
+}
+
+fn source_at_revision(entry: &HistoryEntry) -> String {
+    match &entry.revision {
+        Some(revision) => format!("{}@{}", entry.source, &revision[..revision.len().min(12)]),
+        None => entry.source.clone(),
+    }
+}
+
+/// Format seconds since the UNIX epoch as a UTC date and time.
+fn format_timestamp(secs: u64) -> String {
+    let days = secs / 86_400;
+    let rem = secs % 86_400;
+    // Civil-from-days conversion, see http://howardhinnant.github.io/date_algorithms.html.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::DotstrapError;
+    use crate::services::outcome::LinkOutcome;
+    use tempfile::TempDir;
+
+    #[test]
+    fn format_timestamp_renders_utc_dates() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(1_709_210_096), "2024-02-29 12:34:56 UTC");
+    }
+
+    #[test]
+    fn record_summarises_reports_and_errors() {
+        let home = TempDir::new().unwrap();
+        let report = ExecutionReport {
+            revision: Some("0123456789abcdef".into()),
+            linked: vec![LinkOutcome::new(
+                home.path().join(".zshrc"),
+                ItemStatus::Created,
+            )],
+            ..ExecutionReport::default()
+        };
+
+        record(home.path(), 0, "dotfiles", &Ok(report)).unwrap();
+        record(
+            home.path(),
+            60,
+            "dotfiles",
+            &Err(DotstrapError::SourceMissing),
+        )
+        .unwrap();
+
+        let entries = history::load_history(home.path()).unwrap();
+        assert_eq!(
+            format_list(&entries),
+            "   1  1970-01-01 00:00:00 UTC  ok      dotfiles@0123456789ab  1 created, 0 updated, 0 unchanged\n   \
+             2  1970-01-01 00:01:00 UTC  failed  dotfiles  0 created, 0 updated, 0 unchanged\n"
+        );
+        assert!(format_entry(&entries[0]).contains("  created   "));
+        assert!(format_entry(&entries[1]).contains("Outcome: failed: no source given"));
+    }
+}
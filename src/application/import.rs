@@ -0,0 +1,643 @@
+//! Converting the dotfiles of another manager into a dotstrap source.
+//!
+//! chezmoi source states, GNU stow directories, and dotbot configurations
+//! are read into a list of files, each copied under `templates/` and linked
+//! to the same place in the home directory, plus the shell commands run
+//! after applying. File contents are escaped so they render unchanged;
+//! features without a dotstrap equivalent are skipped with a warning, and
+//! files matching the `.dotstrapignore` of the new source are left out.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use serde::Serialize;
+use serde_yaml::Value;
+use walkdir::WalkDir;
+
+use crate::cli::ImportTool;
+use crate::config::{self, MANIFEST_NAME, MANIFEST_VERSION, Manifest};
+use crate::errors::{DotstrapError, Result};
+use crate::services::templating;
+
+/// Directory imported files are copied to.
+const TEMPLATES_DIR: &str = "templates";
+/// Configuration file read when a dotbot directory is given.
+const DOTBOT_CONFIG: &str = "install.conf.yaml";
+
+/// Files and hooks imported, or planned in dry-run mode, into a source.
+#[derive(Debug, Serialize)]
+pub struct Import {
+    pub from: &'static str,
+    /// Source directory the manifest was written to.
+    pub path: PathBuf,
+    pub templates: Vec<ImportedFile>,
+    /// Commands added as `post_apply` hooks.
+    pub hooks: Vec<String>,
+    /// What could not be converted.
+    pub warnings: Vec<String>,
+    pub dry_run: bool,
+}
+
+/// File of the other manager turned into a template.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImportedFile {
+    /// File read, relative to the imported directory.
+    pub original: PathBuf,
+    /// Template written, relative to the new source.
+    pub source: PathBuf,
+    /// Destination, relative to the home directory.
+    pub destination: PathBuf,
+    pub mode: Option<u32>,
+}
+
+/// Import the dotfiles managed by `tool` at `path` into the source directory
+/// `into`, which must not have a manifest yet.
+pub fn import(tool: ImportTool, path: &Path, into: &Path, dry_run: bool) -> Result<Import> {
+    let failed = |reason: String| DotstrapError::Import {
+        path: path.to_path_buf(),
+        reason,
+    };
+    if into.join(MANIFEST_NAME).exists() {
+        return Err(DotstrapError::Import {
+            path: into.to_path_buf(),
+            reason: format!("`{MANIFEST_NAME}` already exists"),
+        });
+    }
+    let mut import = Import {
+        from: tool.name(),
+        path: into.to_path_buf(),
+        templates: Vec::new(),
+        hooks: Vec::new(),
+        warnings: Vec::new(),
+        dry_run,
+    };
+    let root = match tool {
+        ImportTool::Chezmoi => chezmoi(path, &mut import)?,
+        ImportTool::Stow => stow(path, &mut import)?,
+        ImportTool::Dotbot => dotbot(path, &mut import)?,
+    };
+    // Patterns may name either the imported file or its template.
+    let ignore = config::load_ignore(into)?;
+    import.templates.retain(|file| {
+        !ignore.is_ignored(&file.original, false) && !ignore.is_ignored(&file.source, false)
+    });
+    let mut sources: Vec<&Path> = import.templates.iter().map(|file| &*file.source).collect();
+    sources.sort();
+    if let Some(pair) = sources.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(failed(format!(
+            "two files would be imported as `{}`",
+            pair[0].display()
+        )));
+    }
+
+    let manifest = manifest_text(&import);
+    // Never write a manifest that would not load.
+    serde_yaml::from_str::<Manifest>(&manifest)
+        .map_err(|err| failed(format!("the generated manifest does not parse: {err}")))?;
+    if !dry_run {
+        for file in &import.templates {
+            let target = into.join(&file.source);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let bytes = fs::read(root.join(&file.original))?;
+            match String::from_utf8(bytes) {
+                Ok(text) => fs::write(&target, templating::escape_literal(&text))?,
+                Err(binary) => fs::write(&target, binary.into_bytes())?,
+            }
+        }
+        fs::create_dir_all(into)?;
+        fs::write(into.join(MANIFEST_NAME), manifest)?;
+    }
+    Ok(import)
+}
+
+impl ImportTool {
+    fn name(self) -> &'static str {
+        match self {
+            ImportTool::Chezmoi => "chezmoi",
+            ImportTool::Stow => "stow",
+            ImportTool::Dotbot => "dotbot",
+        }
+    }
+}
+
+/// Manifest declaring the imported templates and hooks.
+fn manifest_text(import: &Import) -> String {
+    let mut text = format!(
+        "# Imported from {} by `dotstrap import`.\nversion: {MANIFEST_VERSION}\ntemplates:\n",
+        import.from
+    );
+    for file in &import.templates {
+        text.push_str(&format!(
+            "  - source: {}\n    destination: {}\n",
+            scalar(&file.source.to_string_lossy()),
+            scalar(&file.destination.to_string_lossy())
+        ));
+        if let Some(mode) = file.mode {
+            text.push_str(&format!("    mode: 0o{mode:o}\n"));
+        }
+    }
+    if !import.hooks.is_empty() {
+        text.push_str("hooks:\n  post_apply:\n");
+        for hook in &import.hooks {
+            text.push_str(&format!("    - {}\n", scalar(hook)));
+        }
+    }
+    text
+}
+
+/// `value` as a YAML scalar, quoted when needed.
+fn scalar(value: &str) -> String {
+    serde_yaml::to_string(value)
+        .map(|text| text.trim_end().to_string())
+        .unwrap_or_else(|_| format!("{value:?}"))
+}
+
+/// Files below `dir`, relative to it and sorted, skipping VCS metadata.
+fn files_below(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let entries = WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| !matches!(entry.file_name().to_str(), Some(".git" | ".hg" | ".svn")));
+    for entry in entries {
+        let entry = entry.map_err(std::io::Error::from)?;
+        if entry.file_type().is_file() {
+            let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+            files.push(relative.to_path_buf());
+        }
+    }
+    Ok(files)
+}
+
+/// `path` under `templates/`, with the leading dot of each component removed
+/// so the files are not hidden.
+fn visible_template(path: &Path) -> PathBuf {
+    let mut template = PathBuf::from(TEMPLATES_DIR);
+    for component in path.components() {
+        let name = component.as_os_str().to_string_lossy();
+        template.push(name.strip_prefix('.').unwrap_or(&name));
+    }
+    template
+}
+
+/// Read a chezmoi source directory, honoring `.chezmoiroot`.
+fn chezmoi(path: &Path, import: &mut Import) -> Result<PathBuf> {
+    let root = match fs::read_to_string(path.join(".chezmoiroot")) {
+        Ok(subdir) => path.join(subdir.trim()),
+        Err(_) => path.to_path_buf(),
+    };
+    for special in [
+        ".chezmoiignore",
+        ".chezmoiexternal.toml",
+        ".chezmoiexternal.yaml",
+    ] {
+        if root.join(special).exists() {
+            import
+                .warnings
+                .push(format!("{special} is not imported, review it by hand"));
+        }
+    }
+    'files: for original in files_below(&root)? {
+        let mut destination = PathBuf::new();
+        let mut mode = None;
+        let components: Vec<_> = original.components().collect();
+        for (index, component) in components.iter().enumerate() {
+            let name = component.as_os_str().to_string_lossy();
+            // chezmoi ignores its own files and every other hidden entry.
+            if name.starts_with('.') {
+                continue 'files;
+            }
+            let is_file = index + 1 == components.len();
+            match chezmoi_name(&name, is_file) {
+                Ok((name, file_mode)) => {
+                    destination.push(name);
+                    mode = file_mode;
+                }
+                Err(reason) => {
+                    import
+                        .warnings
+                        .push(format!("skipped {}: {reason}", original.display()));
+                    continue 'files;
+                }
+            }
+        }
+        if original.extension().is_some_and(|ext| ext == "tmpl") {
+            import.warnings.push(format!(
+                "{} uses chezmoi template syntax, rewrite it with Handlebars",
+                original.display()
+            ));
+        }
+        import.templates.push(ImportedFile {
+            source: visible_template(&destination),
+            original,
+            destination,
+            mode,
+        });
+    }
+    Ok(root)
+}
+
+/// Target name and mode of a chezmoi source entry named `name`, or why it
+/// cannot be imported.
+fn chezmoi_name(name: &str, is_file: bool) -> std::result::Result<(String, Option<u32>), String> {
+    if name.starts_with("encrypted_") {
+        return Err("decrypt it and add it as a secret or template".into());
+    }
+    let (mut private, mut readonly, mut executable) = (false, false, false);
+    let mut name = name;
+    let mut literal = false;
+    loop {
+        let (rest, flag) = if let Some(rest) = name.strip_prefix("private_") {
+            (rest, Some(&mut private))
+        } else if let Some(rest) = name.strip_prefix("readonly_") {
+            (rest, Some(&mut readonly))
+        } else if let Some(rest) = name.strip_prefix("executable_") {
+            (rest, Some(&mut executable))
+        } else if let Some(rest) = ["empty_", "exact_", "external_"]
+            .iter()
+            .find_map(|prefix| name.strip_prefix(prefix))
+        {
+            (rest, None)
+        } else {
+            break;
+        };
+        if let Some(flag) = flag {
+            *flag = true;
+        }
+        name = rest;
+    }
+    for (prefix, reason) in [
+        ("run_", "scripts can be added as hooks"),
+        ("create_", "create-only files are not supported"),
+        ("modify_", "modify scripts are not supported"),
+        ("remove_", "removals are not supported"),
+        ("symlink_", "symlink entries are not supported"),
+    ] {
+        if name.starts_with(prefix) && (is_file || prefix == "remove_") {
+            return Err(reason.into());
+        }
+    }
+    // `literal_` stops the parsing of attributes, including `dot_`.
+    if let Some(rest) = name.strip_prefix("literal_") {
+        name = rest;
+        literal = true;
+    }
+    let mut target = match name.strip_prefix("dot_").filter(|_| !literal) {
+        Some(rest) => format!(".{rest}"),
+        None => name.to_string(),
+    };
+    if is_file {
+        for suffix in [".tmpl", ".literal"] {
+            if let Some(rest) = target.strip_suffix(suffix) {
+                target = rest.to_string();
+            }
+        }
+    }
+    let mut mode = if executable { 0o755 } else { 0o644 };
+    if private {
+        mode &= 0o700;
+    }
+    if readonly {
+        mode &= !0o222;
+    }
+    let custom = is_file && (private || readonly || executable);
+    Ok((target, custom.then_some(mode)))
+}
+
+/// Read a stow directory, each subdirectory being a package.
+fn stow(path: &Path, import: &mut Import) -> Result<PathBuf> {
+    let mut packages: Vec<_> = fs::read_dir(path)?
+        .collect::<std::io::Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    packages.sort();
+    for package in packages {
+        let dir = path.join(&package);
+        if dir.join(".stow-local-ignore").exists() {
+            import.warnings.push(format!(
+                "{package}/.stow-local-ignore is not applied, only the default ignore list"
+            ));
+        }
+        for relative in files_below(&dir)? {
+            if stow_ignores(&relative) {
+                continue;
+            }
+            let destination: PathBuf = relative
+                .components()
+                .map(|component| {
+                    let name = component.as_os_str().to_string_lossy();
+                    match name.strip_prefix("dot-") {
+                        Some(rest) => format!(".{rest}"),
+                        None => name.into_owned(),
+                    }
+                })
+                .collect();
+            let original = Path::new(&package).join(&relative);
+            import.templates.push(ImportedFile {
+                source: Path::new(TEMPLATES_DIR).join(&original),
+                original,
+                destination,
+                mode: None,
+            });
+        }
+    }
+    Ok(path.to_path_buf())
+}
+
+/// Whether stow's default ignore list skips `relative`, a path inside a
+/// package.
+fn stow_ignores(relative: &Path) -> bool {
+    let top_level = relative.components().count() == 1;
+    relative.components().any(|component| {
+        let name = component.as_os_str().to_string_lossy();
+        matches!(
+            &*name,
+            ".gitignore" | ".gitmodules" | ".cvsignore" | ".stow-local-ignore" | "CVS" | "RCS"
+        ) || name.ends_with('~')
+            || (name.starts_with('#') && name.ends_with('#'))
+    }) || (top_level && {
+        let name = relative.to_string_lossy();
+        name.starts_with("README") || name.starts_with("LICENSE") || name == "COPYING"
+    })
+}
+
+/// Read a dotbot configuration, or the `install.conf.yaml` of a directory.
+fn dotbot(path: &Path, import: &mut Import) -> Result<PathBuf> {
+    let (config, root) = if path.is_dir() {
+        (path.join(DOTBOT_CONFIG), path.to_path_buf())
+    } else {
+        let root = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        (path.to_path_buf(), root)
+    };
+    let bytes = fs::read(&config)?;
+    let directives: Vec<serde_yaml::Mapping> =
+        serde_yaml::from_slice(&bytes).map_err(|source| DotstrapError::Yaml {
+            source,
+            path: config.clone(),
+        })?;
+    for directive in directives {
+        for (name, value) in directive {
+            match name.as_str().unwrap_or_default() {
+                "link" => dotbot_links(&root, &value, import)?,
+                "shell" => dotbot_shell(&value, import),
+                // Links are created with their parents and never pruned.
+                "defaults" | "clean" | "create" => {}
+                other => import
+                    .warnings
+                    .push(format!("skipped the `{other}` directive")),
+            }
+        }
+    }
+    Ok(root)
+}
+
+fn dotbot_links(root: &Path, links: &Value, import: &mut Import) -> Result<()> {
+    let Some(links) = links.as_mapping() else {
+        return Ok(());
+    };
+    for (target, options) in links {
+        let Some(target) = target.as_str() else {
+            continue;
+        };
+        let Some(destination) = home_relative(target) else {
+            import.warnings.push(format!(
+                "skipped {target}: it is outside the home directory"
+            ));
+            continue;
+        };
+        let source = match options {
+            Value::String(source) => Some(source.as_str()),
+            Value::Mapping(options) => {
+                for unsupported in ["glob", "if"] {
+                    if options.contains_key(unsupported) {
+                        import.warnings.push(format!(
+                            "{target}: the `{unsupported}` option is not supported"
+                        ));
+                    }
+                }
+                options.get("path").and_then(Value::as_str)
+            }
+            _ => None,
+        };
+        // dotbot links the file named after the target, without its dot.
+        let source = PathBuf::from(source.unwrap_or_else(|| {
+            let name = target.rsplit('/').next().unwrap_or(target);
+            name.strip_prefix('.').unwrap_or(name)
+        }));
+        if root.join(&source).is_dir() {
+            for relative in files_below(&root.join(&source))? {
+                let original = source.join(&relative);
+                import.templates.push(ImportedFile {
+                    source: visible_template(&original),
+                    destination: destination.join(&relative),
+                    original,
+                    mode: None,
+                });
+            }
+        } else {
+            import.templates.push(ImportedFile {
+                source: visible_template(&source),
+                original: source,
+                destination,
+                mode: None,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn dotbot_shell(commands: &Value, import: &mut Import) {
+    let Some(commands) = commands.as_sequence() else {
+        return;
+    };
+    for command in commands {
+        let command = match command {
+            Value::String(command) => Some(command.as_str()),
+            // `[command, description]`
+            Value::Sequence(parts) => parts.first().and_then(Value::as_str),
+            Value::Mapping(options) => options.get("command").and_then(Value::as_str),
+            _ => None,
+        };
+        if let Some(command) = command {
+            import.hooks.push(command.to_string());
+        }
+    }
+}
+
+/// `target` relative to the home directory, for `~/` and relative targets.
+fn home_relative(target: &str) -> Option<PathBuf> {
+    let relative = Path::new(target.strip_prefix("~/").unwrap_or(target));
+    relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        .then(|| relative.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn destinations(import: &Import) -> Vec<(String, String)> {
+        import
+            .templates
+            .iter()
+            .map(|file| {
+                (
+                    file.source.to_string_lossy().into_owned(),
+                    file.destination.to_string_lossy().into_owned(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chezmoi_name_decodes_attributes() {
+        assert_eq!(chezmoi_name("dot_zshrc", true), Ok((".zshrc".into(), None)));
+        assert_eq!(
+            chezmoi_name("private_executable_dot_local.tmpl", true),
+            Ok((".local".into(), Some(0o700)))
+        );
+        assert_eq!(
+            chezmoi_name("literal_dot_file", true),
+            Ok(("dot_file".into(), None))
+        );
+        assert_eq!(
+            chezmoi_name("exact_private_dot_ssh", false),
+            Ok((".ssh".into(), None))
+        );
+        assert!(chezmoi_name("run_once_install.sh", true).is_err());
+    }
+
+    #[test]
+    fn import_chezmoi_source_state() {
+        let from = TempDir::new().unwrap();
+        write(
+            from.path(),
+            "dot_gitconfig.tmpl",
+            "[user]\n  name = {{ .name }}\n",
+        );
+        write(from.path(), "private_dot_ssh/config", "Host *\n");
+        write(from.path(), "run_once_install.sh", "brew bundle\n");
+        write(from.path(), ".chezmoiignore", "README.md\n");
+        let into = TempDir::new().unwrap();
+
+        let import = import(ImportTool::Chezmoi, from.path(), into.path(), false).unwrap();
+
+        assert_eq!(
+            destinations(&import),
+            vec![
+                ("templates/gitconfig".into(), ".gitconfig".into()),
+                ("templates/ssh/config".into(), ".ssh/config".into()),
+            ]
+        );
+        assert_eq!(import.warnings.len(), 3, "{:?}", import.warnings);
+        assert_eq!(
+            fs::read_to_string(into.path().join("templates/gitconfig")).unwrap(),
+            "[user]\n  name = \\{{ .name }}\n"
+        );
+        let manifest = crate::config::load_manifest(into.path()).unwrap();
+        assert_eq!(manifest.templates.len(), 2);
+    }
+
+    #[test]
+    fn import_stow_packages() {
+        let from = TempDir::new().unwrap();
+        write(from.path(), "zsh/.zshrc", "");
+        write(from.path(), "zsh/README.md", "");
+        write(from.path(), "nvim/dot-config/nvim/init.lua", "");
+        let into = TempDir::new().unwrap();
+
+        let import = import(ImportTool::Stow, from.path(), into.path(), true).unwrap();
+
+        assert_eq!(
+            destinations(&import),
+            vec![
+                (
+                    "templates/nvim/dot-config/nvim/init.lua".into(),
+                    ".config/nvim/init.lua".into()
+                ),
+                ("templates/zsh/.zshrc".into(), ".zshrc".into()),
+            ]
+        );
+        assert!(!into.path().join(MANIFEST_NAME).exists());
+    }
+
+    #[test]
+    fn import_dotbot_links_and_shell_commands() {
+        let from = TempDir::new().unwrap();
+        write(
+            from.path(),
+            DOTBOT_CONFIG,
+            "- defaults:\n    link:\n      relink: true\n\
+             - link:\n    ~/.vimrc:\n    ~/.config/git:\n      path: git\n    /etc/hosts: hosts\n\
+             - shell:\n    - [git submodule update --init, Installing submodules]\n    - command: ./setup.sh\n",
+        );
+        write(from.path(), "vimrc", "set number\n");
+        write(from.path(), "git/config", "");
+        write(from.path(), "git/ignore", "");
+        let into = TempDir::new().unwrap();
+
+        let import = import(ImportTool::Dotbot, from.path(), into.path(), false).unwrap();
+
+        assert_eq!(
+            destinations(&import),
+            vec![
+                ("templates/vimrc".into(), ".vimrc".into()),
+                ("templates/git/config".into(), ".config/git/config".into()),
+                ("templates/git/ignore".into(), ".config/git/ignore".into()),
+            ]
+        );
+        assert_eq!(
+            import.hooks,
+            vec!["git submodule update --init", "./setup.sh"]
+        );
+        assert_eq!(import.warnings.len(), 1);
+        let manifest = crate::config::load_manifest(into.path()).unwrap();
+        assert_eq!(manifest.hooks.post_apply.len(), 2);
+    }
+
+    #[test]
+    fn import_skips_ignored_files() {
+        let from = TempDir::new().unwrap();
+        write(from.path(), "zsh/.zshrc", "");
+        write(from.path(), "zsh/.zshrc.swp", "");
+        write(from.path(), "nvim/.DS_Store", "");
+        write(from.path(), "nvim/cache/spell.add", "");
+        let into = TempDir::new().unwrap();
+        write(
+            into.path(),
+            config::IGNORE_NAME,
+            "*.swp\n.DS_Store\ntemplates/nvim/cache/\n",
+        );
+
+        let import = import(ImportTool::Stow, from.path(), into.path(), true).unwrap();
+
+        assert_eq!(
+            destinations(&import),
+            vec![("templates/zsh/.zshrc".into(), ".zshrc".into())]
+        );
+    }
+
+    #[test]
+    fn import_refuses_existing_manifests() {
+        let from = TempDir::new().unwrap();
+        let into = TempDir::new().unwrap();
+        write(into.path(), MANIFEST_NAME, "version: 2\n");
+
+        let error = import(ImportTool::Stow, from.path(), into.path(), false).unwrap_err();
+
+        assert!(matches!(error, DotstrapError::Import { .. }));
+    }
+}
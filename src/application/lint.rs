@@ -0,0 +1,326 @@
+//! Checking the variables templates read against the values, secrets, and
+//! facts they are rendered with, for `dotstrap lint`.
+
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use super::{ModuleSelection, layer_manifests, resolve_bases, system_facts};
+use crate::config;
+use crate::config::values::LayeredValues;
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::CommandExecutor;
+use crate::infrastructure::repository::RepoHandle;
+use crate::infrastructure::secrets;
+use crate::services::{lint, templating};
+
+/// How serious a finding is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Problem found in a template or values file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    /// File, relative to the layer declaring it.
+    pub file: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Check the templates of `repo` and the bases below it for variables that
+/// neither the values, the secrets, the manifest questions, nor the `system`
+/// facts define, and the values of `values.yaml` no template reads.
+///
+/// Values of `profile` are defined, or those of every profile without one.
+/// Secrets are only declared, never read. Templates are checked whatever
+/// their `when` condition, and values read by the inline templates of the
+/// manifest, such as hooks, are used.
+pub fn lint(
+    repo: &RepoHandle,
+    home: &Path,
+    profile: Option<&str>,
+    executor: &dyn CommandExecutor,
+) -> Result<Vec<Finding>> {
+    let mut manifest = config::load_manifest(repo.path())?;
+    let bases = resolve_bases(repo, &mut manifest, &[], executor)?;
+    let mut layers: Vec<&Path> = bases.iter().map(|base| base.handle.path()).collect();
+    layers.push(repo.path());
+    let selection = ModuleSelection {
+        profile,
+        ..Default::default()
+    };
+    let manifest = layer_manifests(&layers, &bases, manifest, &selection)?;
+
+    let mut values = LayeredValues::default();
+    values.layer("module defaults", manifest.module_values.clone());
+    let mut declared_secrets = std::collections::HashMap::new();
+    for layer in &layers {
+        config::layer_values(&mut values, layer)?;
+        for secret in secrets::declared_secrets(layer, home)? {
+            declared_secrets.insert(secret.name, Value::Null);
+        }
+    }
+    match profile {
+        Some(name) => match manifest.profiles.get(name) {
+            Some(profile) => values.layer(&format!("profile `{name}`"), profile.values.clone()),
+            None => {
+                return Err(DotstrapError::UnknownProfile {
+                    name: name.to_string(),
+                    available: manifest.profiles.keys().cloned().collect(),
+                });
+            }
+        },
+        None => {
+            for (name, profile) in &manifest.profiles {
+                values.layer(&format!("profile `{name}`"), profile.values.clone());
+            }
+        }
+    }
+    let mut values = values.values();
+    for question in &manifest.questions {
+        values
+            .entry(question.name.clone())
+            .or_insert_with(|| question.default.clone().unwrap_or(Value::Null));
+    }
+    let context =
+        templating::build_context(&values, &declared_secrets, &system_facts(profile, home));
+    let helpers: Vec<String> = manifest
+        .plugins
+        .iter()
+        .map(|plugin| plugin.name.clone())
+        .collect();
+    let relative = |path: &Path| {
+        layers
+            .iter()
+            .find_map(|layer| path.strip_prefix(layer).ok())
+            .unwrap_or(path)
+            .to_path_buf()
+    };
+
+    let mut findings = Vec::new();
+    let mut used = BTreeSet::new();
+    let mut dynamic = false;
+    for template in manifest.templates.iter().filter(|template| !template.copy) {
+        let path = repo.path().join(&template.source);
+        let file = relative(&path);
+        let references = match lint::references(&std::fs::read_to_string(&path)?, &helpers) {
+            Ok(references) => references,
+            Err(err) => {
+                let (line, column) = err.pos().unzip();
+                findings.push(Finding {
+                    file,
+                    line,
+                    column,
+                    severity: Severity::Error,
+                    message: format!("cannot parse the template: {}", err.reason()),
+                });
+                continue;
+            }
+        };
+        for reference in references {
+            let Some(first) = reference.path.first() else {
+                dynamic = true;
+                continue;
+            };
+            used.insert(first.clone());
+            if !lint::is_defined(&context, &reference.path) {
+                findings.push(Finding {
+                    file: file.clone(),
+                    line: Some(reference.line),
+                    column: Some(reference.column),
+                    severity: Severity::Error,
+                    message: format!("undefined variable `{}`", reference.path.join(".")),
+                });
+            }
+        }
+    }
+    for layer in &layers {
+        for manifest_file in manifest_files(layer) {
+            let text = std::fs::read_to_string(&manifest_file).unwrap_or_default();
+            for reference in lint::references(&text, &helpers).unwrap_or_default() {
+                dynamic |= reference.path.is_empty();
+                used.extend(reference.path.first().cloned());
+            }
+        }
+    }
+
+    // A template reading the whole context may read any value.
+    if !dynamic {
+        for layer in &layers {
+            let path = layer.join(config::VALUES_NAME);
+            let mut unused: Vec<String> = config::load_values(layer)?
+                .into_keys()
+                .filter(|key| !used.contains(key))
+                .collect();
+            unused.sort();
+            let text = std::fs::read_to_string(&path).unwrap_or_default();
+            for key in unused {
+                findings.push(Finding {
+                    file: relative(&path),
+                    line: key_line(&text, &key),
+                    column: None,
+                    severity: Severity::Warning,
+                    message: format!("value `{key}` is not used by any template"),
+                });
+            }
+        }
+    }
+    Ok(findings)
+}
+
+/// `manifest.yaml` of `layer`, the fragments of its `manifest.d`, and its
+/// `packages.yaml`, whose `enabled` expression may read values.
+fn manifest_files(layer: &Path) -> Vec<PathBuf> {
+    let mut files = vec![
+        layer.join(config::MANIFEST_NAME),
+        layer.join(config::PACKAGES_NAME),
+    ];
+    if let Ok(entries) = std::fs::read_dir(layer.join(config::MANIFEST_DIR)) {
+        files.extend(entries.flatten().map(|entry| entry.path()));
+    }
+    files
+}
+
+/// 1-based line of `text` declaring the top-level `key`.
+fn key_line(text: &str, key: &str) -> Option<usize> {
+    text.lines()
+        .position(|line| {
+            [key.to_string(), format!("\"{key}\""), format!("'{key}'")]
+                .iter()
+                .any(|quoted| {
+                    line.strip_prefix(quoted.as_str())
+                        .is_some_and(|rest| rest.trim_start().starts_with(':'))
+                })
+        })
+        .map(|index| index + 1)
+}
+
+/// One `file:line:column: severity: message` line per finding, followed by
+/// their count.
+pub fn format_findings(findings: &[Finding]) -> String {
+    let mut out = String::new();
+    for finding in findings {
+        let _ = write!(out, "{}", finding.file.display());
+        for position in [finding.line, finding.column].into_iter().flatten() {
+            let _ = write!(out, ":{position}");
+        }
+        let severity = match finding.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let _ = writeln!(out, ": {severity}: {}", finding.message);
+    }
+    let count = |severity| {
+        findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    };
+    if findings.is_empty() {
+        out.push_str("No problems found.\n");
+    } else {
+        let _ = writeln!(
+            out,
+            "{} error(s), {} warning(s).",
+            count(Severity::Error),
+            count(Severity::Warning)
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use crate::infrastructure::repository;
+    use tempfile::TempDir;
+
+    fn source(files: &[(&str, &str)]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for (path, contents) in files {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn run(dir: &TempDir, profile: Option<&str>) -> Result<Vec<Finding>> {
+        let home = TempDir::new().unwrap();
+        let executor = RecordingCommandExecutor::default();
+        let repo =
+            repository::resolve_repository(dir.path().to_str().unwrap(), None, &executor).unwrap();
+        lint(&repo, home.path(), profile, &executor)
+    }
+
+    #[test]
+    fn lint_reports_undefined_variables_and_unused_values() {
+        let dir = source(&[
+            (
+                "manifest.yaml",
+                "version: 2\n\
+                 templates:\n  - source: templates/zshrc.hbs\n    destination: .zshrc\n\
+                 profiles:\n  work:\n    values:\n      proxy: http://proxy\n\
+                 questions:\n  - name: email\n\
+                 hooks:\n  post_apply:\n    - run: echo {{greeting}}\n",
+            ),
+            ("values.yaml", "editor: nvim\ngreeting: hi\ntheme: dark\n"),
+            (
+                "secrets/secrets.yaml",
+                "token:\n  from: env\n  key: TOKEN\n",
+            ),
+            (
+                "templates/zshrc.hbs",
+                "export EDITOR={{editor}}\nexport PAGER={{pager}}\n\
+                 {{proxy}} {{email}} {{secrets.token}} {{system.os}} {{secrets.missing}}\n",
+            ),
+        ]);
+
+        let findings = run(&dir, None).unwrap();
+
+        assert_eq!(
+            format_findings(&findings),
+            "templates/zshrc.hbs:2:14: error: undefined variable `pager`\n\
+             templates/zshrc.hbs:3:53: error: undefined variable `secrets.missing`\n\
+             values.yaml:3: warning: value `theme` is not used by any template\n\
+             2 error(s), 1 warning(s).\n"
+        );
+    }
+
+    #[test]
+    fn lint_reports_syntax_errors_and_unknown_profiles() {
+        let dir = source(&[
+            (
+                "manifest.yaml",
+                "version: 2\ntemplates:\n  - source: a.hbs\n    destination: .a\n",
+            ),
+            ("a.hbs", "{{#if x}}\n"),
+        ]);
+
+        let findings = run(&dir, None).unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert!(findings[0].message.starts_with("cannot parse the template"));
+        assert!(matches!(
+            run(&dir, Some("work")),
+            Err(DotstrapError::UnknownProfile { .. })
+        ));
+    }
+
+    #[test]
+    fn format_findings_says_when_nothing_is_wrong() {
+        assert_eq!(format_findings(&[]), "No problems found.\n");
+    }
+}
@@ -0,0 +1,328 @@
+//! Listing the templates, packages, and secrets a source declares, with their
+//! status on this host.
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use serde::Serialize;
+
+use super::{ModuleSelection, layer_manifests, resolve_bases};
+use crate::cli::ListKind;
+use crate::config::{self, Condition, PackagesSpec};
+use crate::errors::Result;
+use crate::infrastructure::command::CommandExecutor;
+use crate::infrastructure::paths::KnownDirs;
+use crate::infrastructure::repository::RepoHandle;
+use crate::infrastructure::{secrets, state};
+use crate::services::linker;
+use crate::services::outcome::ItemStatus;
+
+/// Resources declared by a source, by kind. Kinds that were not requested
+/// are left out.
+#[derive(Debug, Default, Serialize)]
+pub struct Listing {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub templates: Option<Vec<Resource>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packages: Option<Vec<Resource>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secrets: Option<Vec<Resource>>,
+}
+
+/// A declared template, package, or secret.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Resource {
+    /// Destination, package, or secret name.
+    pub name: String,
+    /// Template path, package manager, or secret provider.
+    pub source: String,
+    /// The `when` condition of templates, e.g. `os=macos profile=work`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
+    pub status: String,
+}
+
+/// List the resources of `kind`, or of every kind, declared by `repo` and the
+/// bases below it, with their status in `home` for the given `profile`.
+pub fn list(
+    repo: &RepoHandle,
+    home: &Path,
+    stage_root: &Path,
+    profile: Option<&str>,
+    kind: Option<ListKind>,
+    executor: &dyn CommandExecutor,
+) -> Result<Listing> {
+    let mut manifest = config::load_manifest(repo.path())?;
+    let bases = resolve_bases(repo, &mut manifest, &[], executor)?;
+    let mut layers: Vec<&Path> = bases.iter().map(|base| base.handle.path()).collect();
+    layers.push(repo.path());
+    let selection = ModuleSelection {
+        profile,
+        ..Default::default()
+    };
+    let mut manifest = layer_manifests(&layers, &bases, manifest, &selection)?;
+    manifest.expand_destinations(&KnownDirs::for_home(home))?;
+    let wanted = |requested: ListKind| kind.is_none_or(|kind| kind == requested);
+    let mut listing = Listing::default();
+
+    if wanted(ListKind::Templates) {
+        let os = std::env::consts::OS;
+        let templates = manifest
+            .templates
+            .iter()
+            .map(|template| {
+                let applies = template
+                    .when
+                    .as_ref()
+                    .is_none_or(|when| when.matches(os, profile));
+                let destination = home.join(&template.destination);
+                let status = if !applies {
+                    "skipped"
+                } else if template.create_only() {
+                    match std::fs::symlink_metadata(&destination) {
+                        Ok(_) => "created",
+                        Err(_) => "missing",
+                    }
+                } else {
+                    let staged = stage_root.join(&template.destination);
+                    match linker::link_status(&staged, &destination) {
+                        ItemStatus::Unchanged => "linked",
+                        ItemStatus::Created => "missing",
+                        _ => "differs",
+                    }
+                };
+                let source = layers
+                    .iter()
+                    .find_map(|layer| template.source.strip_prefix(layer).ok())
+                    .unwrap_or(&template.source);
+                Resource {
+                    name: template.destination.display().to_string(),
+                    source: source.display().to_string(),
+                    when: template.when.as_ref().map(describe),
+                    status: status.to_string(),
+                }
+            })
+            .collect();
+        listing.templates = Some(templates);
+    }
+
+    if wanted(ListKind::Packages) {
+        let mut spec = PackagesSpec::default();
+        for layer in &layers {
+            if let Some(declared) = config::load_packages_spec(layer)? {
+                spec = spec.layer(declared);
+            }
+        }
+        if let Some(inline) = manifest.packages.clone() {
+            spec = spec.layer(inline);
+        }
+        listing.packages = Some(packages(&spec, &state::load_state(home)?.brew));
+    }
+
+    if wanted(ListKind::Secrets) {
+        let mut declared = Vec::new();
+        for layer in &layers {
+            for secret in secrets::declared_secrets(layer, home)? {
+                declared.retain(|known: &Resource| known.name != secret.name);
+                let status = match (secret.available, secret.optional) {
+                    (true, _) => "available",
+                    (false, true) => "unset",
+                    (false, false) => "missing",
+                };
+                declared.push(Resource {
+                    name: secret.name,
+                    source: secret.provider,
+                    when: None,
+                    status: status.to_string(),
+                });
+            }
+        }
+        listing.secrets = Some(declared);
+    }
+    Ok(listing)
+}
+
+/// Packages of `spec` by manager. Homebrew packages dotstrap installed are
+/// marked `installed`; the others are only `declared`.
+fn packages(spec: &PackagesSpec, brew: &state::BrewState) -> Vec<Resource> {
+    let mut packages = Vec::new();
+    let mut push = |manager: &str, name: String, installed: bool| {
+        packages.push(Resource {
+            name,
+            source: manager.to_string(),
+            when: None,
+            status: if installed { "installed" } else { "declared" }.to_string(),
+        });
+    };
+    for tap in &spec.brew.taps {
+        push("brew tap", tap.clone(), false);
+    }
+    for formula in &spec.brew.formulae {
+        let name = formula.installed_name();
+        let installed = brew.formulae.contains(&name);
+        push("brew", name, installed);
+    }
+    for cask in &spec.brew.casks {
+        push("brew cask", cask.clone(), brew.casks.contains(cask));
+    }
+    for app in &spec.brew.mas {
+        push("mas", app.name.clone(), false);
+    }
+    for (manager, list) in [
+        ("apt", &spec.apt),
+        ("pacman", &spec.pacman),
+        ("dnf", &spec.dnf),
+        ("cargo", &spec.cargo),
+        ("winget", &spec.winget),
+    ] {
+        for package in list {
+            push(manager, package.clone(), false);
+        }
+    }
+    for (manager, section) in &spec.extra {
+        for package in section.as_array().into_iter().flatten() {
+            if let Some(package) = package.as_str() {
+                push(manager, package.to_string(), false);
+            }
+        }
+    }
+    packages
+}
+
+/// `condition` written as `os=macos,linux profile=work`.
+fn describe(condition: &Condition) -> String {
+    let mut parts = Vec::new();
+    if !condition.os.is_empty() {
+        parts.push(format!("os={}", condition.os.join(",")));
+    }
+    if !condition.profile.is_empty() {
+        parts.push(format!("profile={}", condition.profile.join(",")));
+    }
+    parts.join(" ")
+}
+
+/// The listing as one aligned table per kind.
+pub fn format_listing(listing: &Listing) -> String {
+    let mut out = String::new();
+    for (title, header, resources) in [
+        ("Templates", "DESTINATION", &listing.templates),
+        ("Packages", "PACKAGE", &listing.packages),
+        ("Secrets", "SECRET", &listing.secrets),
+    ] {
+        let Some(resources) = resources else {
+            continue;
+        };
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        let _ = writeln!(out, "{title} ({})", resources.len());
+        if resources.is_empty() {
+            continue;
+        }
+        let rows: Vec<[&str; 4]> = std::iter::once([header, "SOURCE", "WHEN", "STATUS"])
+            .chain(resources.iter().map(|resource| {
+                [
+                    resource.name.as_str(),
+                    resource.source.as_str(),
+                    resource.when.as_deref().unwrap_or("-"),
+                    resource.status.as_str(),
+                ]
+            }))
+            .collect();
+        let mut widths = [0; 4];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        for row in &rows {
+            let _ = writeln!(
+                out,
+                "  {:<w0$}  {:<w1$}  {:<w2$}  {}",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use crate::infrastructure::repository;
+    use tempfile::TempDir;
+
+    fn listing(source: &str, profile: Option<&str>, kind: Option<ListKind>) -> Listing {
+        let home = TempDir::new().unwrap();
+        let executor = RecordingCommandExecutor::default();
+        let repo = repository::resolve_repository(source, None, &executor).unwrap();
+        list(
+            &repo,
+            home.path(),
+            &linker::default_stage_root(home.path()),
+            profile,
+            kind,
+            &executor,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn list_templates_shows_conditions_and_status() {
+        let listing = listing("tests/config-v2", None, Some(ListKind::Templates));
+
+        let templates = listing.templates.unwrap();
+        assert!(listing.packages.is_none());
+        assert!(
+            templates
+                .iter()
+                .any(|template| template.when.is_some() && template.status == "skipped")
+        );
+        assert!(
+            templates
+                .iter()
+                .filter(|template| template.when.is_none())
+                .all(|template| template.status == "missing")
+        );
+    }
+
+    #[test]
+    fn list_packages_includes_inline_sections() {
+        let listing = listing("tests/config-v2", Some("work"), Some(ListKind::Packages));
+
+        assert_eq!(
+            listing.packages.unwrap(),
+            vec![Resource {
+                name: "bat".into(),
+                source: "cargo".into(),
+                when: None,
+                status: "declared".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn format_listing_aligns_columns() {
+        let listing = Listing {
+            secrets: Some(vec![Resource {
+                name: "token".into(),
+                source: "env GITHUB_TOKEN".into(),
+                when: None,
+                status: "missing".into(),
+            }]),
+            ..Listing::default()
+        };
+
+        assert_eq!(
+            format_listing(&listing),
+            "Secrets (1)\n  SECRET  SOURCE            WHEN  STATUS\n  token   env GITHUB_TOKEN  -     missing\n"
+        );
+    }
+}
@@ -0,0 +1,314 @@
+//! Merging the edits made to managed files with the new output of their
+//! templates, instead of replacing them.
+//!
+//! The last template output linked for each destination is kept under
+//! `~/.dotstrap/base`, as the common ancestor of the file, which the user
+//! may have edited through its link, and the newly rendered output.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tempfile::TempDir;
+
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::state::TemplateOrigin;
+use crate::services::hooks;
+use crate::services::merge::{self, LOCAL_MARKER};
+use crate::services::templating::RenderedSet;
+
+/// Directory of the home keeping the last template output of every
+/// destination.
+const BASE_DIR: &str = ".dotstrap/base";
+
+/// Environment variable naming the command resolving conflicts.
+const MERGE_TOOL: &str = "MERGE_TOOL";
+
+/// Directory keeping the last template output linked for each destination.
+pub fn base_root(home: &Path) -> PathBuf {
+    home.join(BASE_DIR)
+}
+
+/// Local edits merged into the rendered templates of a run.
+#[derive(Debug, Default)]
+pub(crate) struct LocalEdits {
+    /// Why each merged destination has its contents.
+    pub reasons: BTreeMap<PathBuf, String>,
+    pub warnings: Vec<String>,
+    /// Destinations whose rendered file now merges local edits.
+    pub merged: BTreeSet<PathBuf>,
+    /// Template output of the merged destinations, their next base.
+    outputs: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+/// Merge the edits made to the destinations of `rendered` since the last
+/// run, as recorded in `previous`, into their rendered file.
+///
+/// A destination is merged when it differs from the last template output
+/// and the new output changed too, or when a previous run already merged
+/// it; otherwise the new output replaces it as before. Destinations linked
+/// elsewhere and files that are not UTF-8 are left out. Conflicting changes
+/// are resolved with `$MERGE_TOOL` when `interactive` and outside dry-run
+/// mode, or left between conflict markers.
+pub(crate) fn merge_local_edits(
+    home: &Path,
+    stage_root: &Path,
+    rendered: &RenderedSet,
+    previous: &BTreeMap<PathBuf, TemplateOrigin>,
+    interactive: bool,
+    dry_run: bool,
+) -> Result<LocalEdits> {
+    let mut edits = LocalEdits::default();
+    let tool = std::env::var(MERGE_TOOL)
+        .ok()
+        .filter(|tool| interactive && !dry_run && !tool.trim().is_empty());
+    for item in &rendered.templates {
+        let relative = &item.template.destination;
+        let Some(origin) = previous.get(relative) else {
+            continue;
+        };
+        let destination = home.join(relative);
+        if fs::read_link(&destination).is_ok_and(|target| target != stage_root.join(relative)) {
+            continue;
+        }
+        let (Ok(base), Ok(local), Ok(new)) = (
+            fs::read_to_string(base_root(home).join(relative)),
+            fs::read_to_string(&destination),
+            fs::read_to_string(&item.rendered_path),
+        ) else {
+            continue;
+        };
+        if local == base || local == new || (new == base && !origin.merged) {
+            continue;
+        }
+        let merge = merge::merge3(&base, &local, &new);
+        let mut text = merge.text;
+        let mut conflicts = merge.conflicts;
+        if conflicts > 0
+            && let Some(tool) = &tool
+        {
+            match run_tool(tool, relative, [&local, &base, &new, &text]) {
+                Ok(resolved) => {
+                    conflicts = resolved
+                        .lines()
+                        .filter(|line| *line == LOCAL_MARKER)
+                        .count();
+                    text = resolved;
+                }
+                Err(err) => edits.warnings.push(format!(
+                    "cannot merge {} with {tool}: {err}",
+                    relative.display()
+                )),
+            }
+        }
+        let reason = match conflicts {
+            _ if new == base => "local edits kept".to_string(),
+            0 => "local edits merged with the template changes".to_string(),
+            conflicts => {
+                if !dry_run {
+                    edits.warnings.push(format!(
+                        "the edits to {} conflict with the template in {conflicts} place(s), marked in the file",
+                        destination.display()
+                    ));
+                }
+                format!("local edits merged with {conflicts} conflict(s)")
+            }
+        };
+        fs::write(&item.rendered_path, text)?;
+        edits.reasons.insert(relative.clone(), reason);
+        edits.merged.insert(relative.clone());
+        edits.outputs.insert(relative.clone(), new.into_bytes());
+    }
+    Ok(edits)
+}
+
+/// Keep the template output of the linked destinations of `rendered` as
+/// the base of their next merge.
+pub(crate) fn save_bases(home: &Path, rendered: &RenderedSet, edits: &LocalEdits) -> Result<()> {
+    for item in rendered
+        .templates
+        .iter()
+        .filter(|item| !item.template.create_only())
+    {
+        let relative = &item.template.destination;
+        let base = base_root(home).join(relative);
+        if let Some(parent) = base.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match edits.outputs.get(relative) {
+            Some(output) => fs::write(&base, output)?,
+            None => {
+                fs::copy(&item.rendered_path, &base)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run `tool` through the shell to resolve the conflicts of `merged`, the
+/// merge of `local` and `new` from `base`, returning the file it wrote.
+///
+/// As with git's `mergetool.<tool>.cmd`, the files are passed in the
+/// `LOCAL`, `BASE`, `REMOTE`, and `MERGED` environment variables.
+fn run_tool(
+    tool: &str,
+    destination: &Path,
+    [local, base, new, merged]: [&str; 4],
+) -> Result<String> {
+    let dir = TempDir::new()?;
+    let name = destination
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut command = std::process::Command::new(hooks::shell().0);
+    command.arg(hooks::shell().1).arg(tool);
+    let mut files = Vec::new();
+    for (variable, contents) in [
+        ("LOCAL", local),
+        ("BASE", base),
+        ("REMOTE", new),
+        ("MERGED", merged),
+    ] {
+        let path = dir
+            .path()
+            .join(format!("{name}.{}", variable.to_lowercase()));
+        fs::write(&path, contents)?;
+        command.env(variable, &path);
+        files.push(path);
+    }
+    let status = command
+        .status()
+        .map_err(|err| DotstrapError::CommandIo(tool.to_string(), err))?;
+    if !status.success() {
+        return Err(DotstrapError::CommandFailed {
+            program: tool.to_string(),
+            status: status.code().unwrap_or(-1),
+        });
+    }
+    Ok(fs::read_to_string(&files[3])?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TemplateMapping;
+    use crate::services::templating::RenderedTemplate;
+
+    const BASE: &str = "export EDITOR=vim\nalias ll='ls -l'\n";
+
+    fn origin(merged: bool) -> TemplateOrigin {
+        TemplateOrigin {
+            source: "/src".into(),
+            template: PathBuf::from("zshrc.hbs"),
+            profile: None,
+            inputs: None,
+            output: None,
+            merged,
+            mode: None,
+            create_only: false,
+        }
+    }
+
+    /// Home whose `.zshrc` links to a staged file holding `local`, last
+    /// rendered as `BASE`, and a rendered set with `new` as its output.
+    fn setup(local: &str, new: &str) -> (TempDir, PathBuf, RenderedSet) {
+        let home = TempDir::new().unwrap();
+        let stage_root = home.path().join(".dotstrap/generated");
+        fs::create_dir_all(&stage_root).unwrap();
+        fs::write(stage_root.join(".zshrc"), local).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(stage_root.join(".zshrc"), home.path().join(".zshrc")).unwrap();
+        #[cfg(not(unix))]
+        fs::write(home.path().join(".zshrc"), local).unwrap();
+        fs::create_dir_all(base_root(home.path())).unwrap();
+        fs::write(base_root(home.path()).join(".zshrc"), BASE).unwrap();
+        let tempdir = TempDir::new().unwrap();
+        let rendered_path = tempdir.path().join(".zshrc");
+        fs::write(&rendered_path, new).unwrap();
+        let rendered = RenderedSet {
+            _tempdir: tempdir,
+            templates: vec![RenderedTemplate {
+                template: TemplateMapping {
+                    source: PathBuf::from("zshrc.hbs"),
+                    destination: PathBuf::from(".zshrc"),
+                    mode: None,
+                    when: None,
+                    enabled: None,
+                    copy: false,
+                    overwrite: Default::default(),
+                },
+                rendered_path,
+            }],
+        };
+        (home, stage_root, rendered)
+    }
+
+    fn merge(rendered: &RenderedSet, home: &Path, stage_root: &Path, merged: bool) -> LocalEdits {
+        let previous = BTreeMap::from([(PathBuf::from(".zshrc"), origin(merged))]);
+        merge_local_edits(home, stage_root, rendered, &previous, false, false).unwrap()
+    }
+
+    #[test]
+    fn merge_local_edits_merges_edits_with_template_changes() {
+        let (home, stage_root, rendered) = setup(
+            "export EDITOR=vim\nalias ll='ls -l'\nalias gs='git status'\n",
+            "export EDITOR=hx\nalias ll='ls -l'\n",
+        );
+
+        let edits = merge(&rendered, home.path(), &stage_root, false);
+
+        assert_eq!(
+            fs::read_to_string(&rendered.templates[0].rendered_path).unwrap(),
+            "export EDITOR=hx\nalias ll='ls -l'\nalias gs='git status'\n"
+        );
+        assert_eq!(
+            edits.reasons[Path::new(".zshrc")],
+            "local edits merged with the template changes"
+        );
+        assert!(edits.warnings.is_empty());
+
+        save_bases(home.path(), &rendered, &edits).unwrap();
+        assert_eq!(
+            fs::read_to_string(base_root(home.path()).join(".zshrc")).unwrap(),
+            "export EDITOR=hx\nalias ll='ls -l'\n"
+        );
+    }
+
+    #[test]
+    fn merge_local_edits_marks_conflicts() {
+        let (home, stage_root, rendered) = setup(
+            "export EDITOR=nano\nalias ll='ls -l'\n",
+            "export EDITOR=hx\nalias ll='ls -l'\n",
+        );
+
+        let edits = merge(&rendered, home.path(), &stage_root, false);
+
+        let merged = fs::read_to_string(&rendered.templates[0].rendered_path).unwrap();
+        assert!(merged.starts_with("<<<<<<< local edits\nexport EDITOR=nano\n"));
+        assert_eq!(
+            edits.reasons[Path::new(".zshrc")],
+            "local edits merged with 1 conflict(s)"
+        );
+        assert_eq!(edits.warnings.len(), 1);
+    }
+
+    #[test]
+    fn merge_local_edits_replaces_edits_unless_the_file_was_merged() {
+        let edited = "export EDITOR=nano\nalias ll='ls -l'\n";
+        let (home, stage_root, rendered) = setup(edited, BASE);
+
+        let edits = merge(&rendered, home.path(), &stage_root, false);
+        assert!(edits.merged.is_empty());
+        assert_eq!(
+            fs::read_to_string(&rendered.templates[0].rendered_path).unwrap(),
+            BASE
+        );
+
+        let edits = merge(&rendered, home.path(), &stage_root, true);
+        assert_eq!(edits.reasons[Path::new(".zshrc")], "local edits kept");
+        assert_eq!(
+            fs::read_to_string(&rendered.templates[0].rendered_path).unwrap(),
+            edited
+        );
+    }
+}
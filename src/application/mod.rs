@@ -4,88 +4,1634 @@
 //! templating, linking, and optional package installation steps to produce a
 //! single [`ExecutionReport`].
 
-use std::path::PathBuf;
+pub mod clean;
+pub mod edit;
+pub mod export;
+pub mod golden;
+pub mod history;
+pub mod import;
+pub mod lint;
+pub mod list;
+pub mod merge;
+pub mod observer;
+pub mod options;
+pub mod owner;
+pub mod plan;
+pub mod planfile;
+pub mod readd;
+pub mod rollback;
+pub mod snapshot;
+pub mod source;
+pub mod verify;
+pub mod watch;
 
-use crate::cli::Cli;
-use crate::config;
-use crate::errors::{DotstrapError, Result};
-use crate::infrastructure::command::{CommandExecutor, SystemCommandExecutor};
-use crate::infrastructure::{repository, secrets};
-use crate::services::{brew, linker, templating};
+pub use observer::{NoopObserver, Observer};
+pub use options::{Dotstrap, DotstrapBuilder, DotstrapOptions};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::cli::{Cli, RunMode};
+use crate::config::values::LayeredValues;
+use crate::config::{self, BrewSettings, Manifest, PackagesSpec, Profile, TemplateMapping};
+use crate::errors::{DotstrapError, Result, exit_code};
+#[cfg(feature = "tokio")]
+use crate::infrastructure::async_command::{AsyncCommandExecutor, BlockingExecutor};
+use crate::infrastructure::command::{
+    CommandExecutor, OfflineExecutor, RetryPolicy, RetryingExecutor, SystemCommandExecutor,
+};
+use crate::infrastructure::escalation::{self, Escalation};
+use crate::infrastructure::filesystem::{CopyingFileSystem, FileSystem, OsFileSystem};
+use crate::infrastructure::lock::RunLock;
+use crate::infrastructure::paths::KnownDirs;
+use crate::infrastructure::progress::Progress;
+use crate::infrastructure::repository::{AuthenticatedExecutor, GitAuth, RepoHandle};
+use crate::infrastructure::secrets::SecretProviderRegistry;
+use crate::infrastructure::state::{BrewState, SourceState, TemplateOrigin};
+use crate::infrastructure::{answers, download, paths, repository, secrets, state};
+use crate::services::brew::{self, BrewProvider};
+use crate::services::outcome::{ItemStatus, LinkOutcome};
+use crate::services::packages::{
+    self, InstallOptions, PackageOutcome, ProviderRegistry, ProviderReport,
+};
+use crate::services::questions::{
+    self, NonInteractive, Prompter, Review, Strict, TerminalPrompter,
+};
+use crate::services::templating::RenderedSet;
+use crate::services::{
+    binaries, cron, diff, downloads, gnome, hooks, linker, macos, repos, ssh, templating,
+};
+use observer::ObservedExecutor;
 
 /// Summary of the operations performed during a dotstrap run.
-#[derive(Debug, Default, PartialEq, Eq)]
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
 pub struct ExecutionReport {
+    /// Commit of the source that was applied, when it is a git repository.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    /// Upstream commits pulled by `dotstrap update`, one line each.
+    pub upstream_changes: Vec<String>,
     /// Template destinations rendered from the manifest.
     pub rendered: Vec<PathBuf>,
-    /// Fully qualified paths linked into the target home directory.
-    pub linked: Vec<PathBuf>,
-    /// Homebrew commands executed or planned.
-    pub brew_commands: Vec<String>,
+    /// Rendered templates linked into the target home directory.
+    pub linked: Vec<LinkOutcome>,
+    /// In dry-run mode, line diff from the current contents of each template
+    /// destination a real run would create or overwrite to its rendered
+    /// contents.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub diffs: BTreeMap<PathBuf, String>,
+    /// Modules of the manifest, in the order their entries applied.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub modules: Vec<String>,
+    /// Modules left out by `--disable` or the `modules` map of the values.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub disabled_modules: Vec<String>,
+    /// Release binaries linked into the target home directory.
+    pub binaries: Vec<LinkOutcome>,
+    /// Downloaded files linked into the target home directory.
+    pub downloads: Vec<LinkOutcome>,
+    /// Backups of files replaced by links during the run.
+    pub backups: Vec<PathBuf>,
+    /// Workspace repositories cloned into the target home directory.
+    pub repos: Vec<PathBuf>,
+    /// macOS defaults changed or planned, with their previous values.
+    pub defaults: Vec<String>,
+    /// GNOME settings changed or planned, with their previous values.
+    pub gnome_settings: Vec<String>,
+    /// Crontab lines installed or planned in the managed block.
+    pub cron: Vec<String>,
+    /// Hook commands run or planned, pre-apply hooks first.
+    pub hooks: Vec<String>,
+    /// Number of leading `hooks` run before the templates are linked.
+    #[serde(skip)]
+    pub pre_apply_hooks: usize,
+    /// SSH public key to display at the end of the run.
+    pub ssh_public_key: Option<String>,
+    /// Package manager commands executed or planned.
+    pub package_commands: Vec<String>,
+    /// Outcome of every package provider section.
+    pub packages: Vec<ProviderReport>,
+    /// Time spent in each phase of the run, in order.
+    pub phases: Vec<PhaseTiming>,
+    /// Commands that had to be retried, with their final outcome.
+    pub retries: Vec<String>,
+    /// Phases left out on request, by `--skip-templates` or `--only`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<&'static str>,
+    /// Non-fatal issues encountered while running, such as skipped steps.
+    pub warnings: Vec<String>,
+    /// Why each package command, hook, clone, and setting change above was
+    /// made, keyed by the command, path, or change as listed.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub reasons: BTreeMap<String, String>,
     /// Indicates that the run was executed in dry-run mode.
     pub dry_run: bool,
 }
 
+impl ExecutionReport {
+    /// One-line summary of the changes made by the run.
+    pub fn summary(&self) -> String {
+        let links: Vec<&LinkOutcome> = self
+            .linked
+            .iter()
+            .chain(&self.binaries)
+            .chain(&self.downloads)
+            .collect();
+        let count = |status| links.iter().filter(|link| link.status == status).count();
+        let mut summary = format!(
+            "Linked {} file(s): {} created, {} updated, {} unchanged; ran {} package command(s).",
+            links.len() - count(ItemStatus::Skipped),
+            count(ItemStatus::Created),
+            count(ItemStatus::Updated),
+            count(ItemStatus::Unchanged),
+            self.package_commands.len()
+        );
+        if !self.skipped.is_empty() {
+            summary.push_str(&format!(" Skipped {}.", self.skipped.join(", ")));
+        }
+        summary
+    }
+}
+
+/// Wall-clock duration of a phase of the run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PhaseTiming {
+    pub phase: &'static str,
+    pub millis: u64,
+}
+
+/// Records the time elapsed between consecutive phases.
+struct PhaseClock {
+    last: Instant,
+    phases: Vec<PhaseTiming>,
+}
+
+impl PhaseClock {
+    fn start() -> Self {
+        PhaseClock {
+            last: Instant::now(),
+            phases: Vec::new(),
+        }
+    }
+
+    /// Close `phase`, which started when the previous one ended.
+    fn lap(&mut self, phase: &'static str) {
+        let now = Instant::now();
+        let millis = now.duration_since(self.last).as_millis();
+        self.phases.push(PhaseTiming {
+            phase,
+            millis: u64::try_from(millis).unwrap_or(u64::MAX),
+        });
+        self.last = now;
+    }
+}
+
 /// Run dotstrap using the system command executor.
 pub fn run(cli: Cli) -> Result<ExecutionReport> {
-    let executor = SystemCommandExecutor;
-    run_with_executor(cli, &executor)
+    let executor = SystemCommandExecutor::with_timeout(cli.command_timeout());
+    run_with_executor(cli, &executor, &NoopObserver)
+}
+
+/// Run dotstrap using the provided [`CommandExecutor`], reporting the events
+/// of the run to `observer`.
+pub fn run_with_executor<E>(
+    cli: Cli,
+    executor: &E,
+    observer: &dyn Observer,
+) -> Result<ExecutionReport>
+where
+    E: CommandExecutor,
+{
+    run_with_providers(cli, executor, &ProviderRegistry::default(), observer)
 }
 
-/// Run dotstrap using the provided [`CommandExecutor`].
-pub fn run_with_executor<E>(cli: Cli, executor: &E) -> Result<ExecutionReport>
+/// Run dotstrap using the provided [`CommandExecutor`] and package providers.
+pub fn run_with_providers<E>(
+    cli: Cli,
+    executor: &E,
+    providers: &ProviderRegistry,
+    observer: &dyn Observer,
+) -> Result<ExecutionReport>
 where
     E: CommandExecutor,
 {
-    let Cli {
+    run_with_options(
+        DotstrapOptions::from(cli),
+        executor,
+        providers,
+        &SecretProviderRegistry::default(),
+        observer,
+    )
+}
+
+/// Apply the source of `options`, serving custom secrets from
+/// `secret_providers`, and report the failure, if any, to `observer`.
+fn run_with_options(
+    options: DotstrapOptions,
+    executor: &dyn CommandExecutor,
+    providers: &ProviderRegistry,
+    secret_providers: &SecretProviderRegistry,
+    observer: &dyn Observer,
+) -> Result<ExecutionReport> {
+    apply(options, executor, providers, secret_providers, observer)
+        .inspect_err(|err| observer.on_error(err))
+}
+
+/// Apply the source of `options` without blocking the calling task, running
+/// commands through `executor`.
+///
+/// The run itself happens on a blocking thread of the current Tokio runtime,
+/// while its commands, including concurrent package installs, are spawned as
+/// tasks of the runtime.
+#[cfg(feature = "tokio")]
+pub async fn run_async<E>(options: DotstrapOptions, executor: E) -> Result<ExecutionReport>
+where
+    E: AsyncCommandExecutor + 'static,
+{
+    let executor = BlockingExecutor::new(executor, tokio::runtime::Handle::current());
+    tokio::task::spawn_blocking(move || {
+        run_with_options(
+            options,
+            &executor,
+            &ProviderRegistry::default(),
+            &SecretProviderRegistry::default(),
+            &NoopObserver,
+        )
+    })
+    .await
+    .map_err(|err| DotstrapError::Io(std::io::Error::other(err)))?
+}
+
+fn apply(
+    options: DotstrapOptions,
+    executor: &dyn CommandExecutor,
+    providers: &ProviderRegistry,
+    secret_providers: &SecretProviderRegistry,
+    observer: &dyn Observer,
+) -> Result<ExecutionReport> {
+    let DotstrapOptions {
         source,
+        git_ref,
+        git_token,
+        ssh_key,
+        trusted_keys,
+        profile,
+        subdir,
         home,
+        staging_dir,
         skip_brew,
+        skip_templates,
+        install_brew,
+        no_brew_update,
+        sync_packages,
+        retries,
+        command_timeout: _,
+        jobs,
+        escalation,
+        mode,
         dry_run,
-        generate_completions: _,
-    } = cli;
-
-    let source = source.expect("source argument is validated by clap");
+        wait,
+        allow_plugins,
+        allow_template_exec,
+        only,
+        enable,
+        disable,
+        refresh,
+        interactive,
+        confirm,
+        review,
+        strict,
+        offline,
+        trace_values,
+        progress,
+    } = options;
+    let permissions = templating::Permissions {
+        plugins: allow_plugins,
+        exec: allow_template_exec,
+    };
+    let mut clock = PhaseClock::start();
+    let progress = if progress {
+        Progress::spinner()
+    } else {
+        Progress::hidden()
+    };
+    let phase = |message: &str| {
+        progress.phase(message);
+        observer.on_phase_start(message);
+    };
+    phase("Resolving source");
+    let source = paths::expand(&source.ok_or(DotstrapError::SourceMissing)?);
+    let auth = GitAuth {
+        token: git_token,
+        ssh_key: ssh_key.map(|key| paths::expand_path(&key.to_string_lossy())),
+    };
+    let executor = ObservedExecutor::new(executor, observer);
+    let executor = AuthenticatedExecutor::new(&executor, &auth, &source);
+    let offline_executor = OfflineExecutor::new(&executor);
+    let executor: &dyn CommandExecutor = if offline {
+        &offline_executor
+    } else {
+        &executor
+    };
+    let executor = RetryingExecutor::new(
+        executor,
+        RetryPolicy {
+            retries,
+            ..RetryPolicy::default()
+        },
+    );
+    let executor = &executor;
 
     let home_dir = match home {
-        Some(path) => path,
+        Some(path) => paths::expand_path(&path.to_string_lossy()),
         None => home::home_dir().ok_or(DotstrapError::HomeNotFound)?,
     };
+    let container = mode == RunMode::Container;
+    // Problems of a read-only or throwaway home a container run goes past.
+    let mut tolerated = Vec::new();
+    // Dry runs leave the staging area and backups alone.
+    let _lock = if dry_run {
+        None
+    } else {
+        match RunLock::acquire(&home_dir, wait) {
+            Ok(lock) => Some(lock),
+            Err(DotstrapError::Io(err)) if container => {
+                tolerated.push(format!("running without the lock of the home: {err}"));
+                None
+            }
+            Err(err) => return Err(err),
+        }
+    };
 
-    let repo = repository::resolve_repository(&source, executor)?;
-    let manifest = config::load_manifest(repo.path())?;
-    let values = config::load_values(repo.path())?;
-    let secrets = secrets::load_secrets(repo.path(), &home_dir)?;
-    let context = templating::build_context(&values, &secrets);
-    let rendered_set = templating::render_templates(repo.path(), &manifest, &context)?;
-    let linked = linker::link_templates(&home_dir, &rendered_set, dry_run)?;
+    let (source, embedded_subdir) = repository::split_subdir(&source);
+    let subdir = subdir.or_else(|| embedded_subdir.map(PathBuf::from));
+    let applied_source = SourceState {
+        // Relative local sources must resolve from any working directory.
+        source: match Path::new(source).canonicalize() {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(_) => source.to_string(),
+        },
+        git_ref: git_ref.clone(),
+        subdir: subdir.clone(),
+    };
+    let (mut repo, upstream_changes) = if offline {
+        (
+            repository::cached_repository(
+                source,
+                git_ref.as_deref(),
+                &home_dir.join(".dotstrap/sources"),
+                executor,
+            )?,
+            Vec::new(),
+        )
+    } else if refresh {
+        repository::refresh_repository(
+            source,
+            git_ref.as_deref(),
+            &home_dir.join(".dotstrap/sources"),
+            executor,
+            dry_run,
+        )?
+    } else {
+        (
+            repository::resolve_repository(source, git_ref.as_deref(), executor)?,
+            Vec::new(),
+        )
+    };
+    if let Some(subdir) = &subdir {
+        repo = repo.into_subdir(subdir)?;
+    }
+    let mut manifest = config::load_manifest(repo.path())?;
+    let is_archive = repository::is_archive_source(source);
+    if git_ref.is_none()
+        && repo.is_cloned()
+        && !is_archive
+        && let Some(default_ref) = manifest.git_ref.clone()
+    {
+        repo.checkout(&default_ref, executor)?;
+        manifest = config::load_manifest(repo.path())?;
+    }
+    // The source is only verified with the keys the user gave: whoever can
+    // push to it could add their own key to its manifest.
+    verify_source(&repo, source, &trusted_keys, executor)?;
+    let trusted_keys = trusted_layer_keys(trusted_keys, &manifest);
+    let revision = repo.revision(executor);
+    // Local working copies are only touched outside dry-run mode, and
+    // updating submodules fetches them.
+    if manifest.submodules && !is_archive && !offline && (repo.is_cloned() || !dry_run) {
+        repo.update_submodules(executor)?;
+    }
+
+    let bases = resolve_bases(&repo, &mut manifest, &trusted_keys, executor)?;
+    let mut layers: Vec<&Path> = bases.iter().map(|base| base.handle.path()).collect();
+    layers.push(repo.path());
+    let selection = ModuleSelection {
+        profile: profile.as_deref(),
+        enable: &enable,
+        disable: &disable,
+    };
+    let mut manifest = layer_manifests(&layers, &bases, manifest, &selection)?;
+    let (selected, mut dropped) = select_profile(&mut manifest, profile.as_deref(), &home_dir)?;
+    // `--only` narrows the run to some templates and skips the other steps.
+    let selective = !only.is_empty();
+    let skipped: Vec<&'static str> = if selective {
+        manifest.retain_only(&only)?;
+        vec![
+            "hooks",
+            "ssh",
+            "packages",
+            "binaries",
+            "downloads",
+            "repos",
+            "system",
+            "cron",
+        ]
+    } else if skip_templates {
+        manifest.templates.clear();
+        vec!["templates"]
+    } else {
+        Vec::new()
+    };
+    let (context, values) = if interactive {
+        // Keep the spinner from drawing over the questions.
+        progress.suspend(|| {
+            layered_context(
+                &layers,
+                &home_dir,
+                &manifest,
+                &selected,
+                secret_providers,
+                &mut TerminalPrompter,
+                !dry_run,
+            )
+        })?
+    } else {
+        let mut prompter: Box<dyn Prompter> = if strict {
+            Box::new(Strict)
+        } else {
+            Box::new(NonInteractive)
+        };
+        layered_context(
+            &layers,
+            &home_dir,
+            &manifest,
+            &selected,
+            secret_providers,
+            prompter.as_mut(),
+            !dry_run,
+        )?
+    };
+    for key in &trace_values {
+        tracing::info!("{}", values.trace(key));
+    }
+    clock.lap("source");
+    let origin = repo.path().join("manifest.yaml");
+    dropped.extend(disable_entries(
+        &mut manifest,
+        &context,
+        &home_dir,
+        &origin,
+    )?);
+    let mut hooks = Vec::new();
+    if !manifest.hooks.pre_apply.is_empty() {
+        phase("Running pre-apply hooks");
+        hooks = hooks::run_hooks(
+            "pre-apply",
+            &manifest.hooks.pre_apply,
+            &context,
+            &origin,
+            executor,
+            dry_run,
+        )?;
+        clock.lap("hooks");
+    }
+    let pre_apply_hooks = hooks.len();
+    let mut reasons: BTreeMap<String, String> = hooks
+        .iter()
+        .map(|hook| (hook.clone(), "pre-apply hook".to_string()))
+        .collect();
+    let stage_root = match staging_dir {
+        Some(path) => paths::expand_path(&path.to_string_lossy()),
+        None if container && !dry_run => container_stage_root(&home_dir, &mut tolerated),
+        None => linker::default_stage_root(&home_dir),
+    };
+    // Copies stand for the links of container runs.
+    let fs: &dyn FileSystem = if container {
+        &CopyingFileSystem
+    } else {
+        &OsFileSystem
+    };
+    // Templates whose inputs did not change since the last run keep their
+    // staged file and link.
+    let previous = state::load_state(&home_dir)
+        .map(|state| state.templates)
+        .unwrap_or_default();
+    let mut digests = Vec::with_capacity(manifest.templates.len());
+    let mut stale = Manifest {
+        max_template_size: manifest.max_template_size,
+        plugins: manifest.plugins.clone(),
+        ..Manifest::default()
+    };
+    for template in &manifest.templates {
+        let inputs = templating::input_digest(
+            repo.path(),
+            template,
+            &context,
+            manifest.template_size_limit(),
+        )?;
+        let destination = home_dir.join(&template.destination);
+        let current = if template.create_only() {
+            // Seeded on an earlier run, or by hand, and left alone since.
+            std::fs::symlink_metadata(&destination).is_ok()
+        } else {
+            is_current(
+                fs,
+                previous.get(&template.destination),
+                &inputs,
+                &stage_root.join(&template.destination),
+                &destination,
+            )
+        };
+        if !current {
+            stale.templates.push(template.clone());
+        }
+        digests.push((template.destination.clone(), inputs, current));
+    }
+    if !skip_templates {
+        phase(&format!("Rendering {} template(s)", stale.templates.len()));
+    }
+    tracing::debug!(
+        "{} template(s) unchanged since the last run",
+        manifest.templates.len() - stale.templates.len()
+    );
+    let mut rendered_set =
+        templating::render_templates(repo.path(), &stale, &context, permissions, executor)?;
+    let edits = progress.suspend(|| {
+        merge::merge_local_edits(
+            &home_dir,
+            &stage_root,
+            &rendered_set,
+            &previous,
+            interactive,
+            dry_run,
+        )
+    })?;
+    let mut prompter: Box<dyn Prompter> = if interactive || review {
+        Box::new(TerminalPrompter)
+    } else {
+        Box::new(NonInteractive)
+    };
+    let (declined, kept) = if dry_run {
+        (BTreeSet::new(), Vec::new())
+    } else if review {
+        review_changes(&home_dir, &rendered_set, &mut |change, details| {
+            progress.suspend(|| questions::review(change, details, prompter.as_mut()))
+        })?
+    } else {
+        let declined = declined_replacements(
+            &home_dir,
+            &rendered_set,
+            &previous,
+            &mut |action, details| {
+                progress.suspend(|| questions::confirm(confirm, action, details, prompter.as_mut()))
+            },
+        )?;
+        (declined, Vec::new())
+    };
+    let mut ask = |action: &str, details: &str| {
+        progress.suspend(|| questions::confirm(confirm, action, details, prompter.as_mut()))
+    };
+    rendered_set
+        .templates
+        .retain(|item| !declined.contains(&item.template.destination));
+    let mut warnings = tolerated;
+    warnings.extend(
+        declined
+            .iter()
+            .map(|destination| format!("left {} in place", destination.display())),
+    );
+    for item in &rendered_set.templates {
+        observer.on_template_rendered(&item.template.destination);
+        observer.on_template_contents(&item.template, &item.rendered_path);
+    }
+    for (template, (_, _, current)) in manifest.templates.iter().zip(&digests) {
+        if *current && !template.create_only() && !declined.contains(&template.destination) {
+            observer.on_template_contents(template, &stage_root.join(&template.destination));
+        }
+    }
+    if !skip_templates {
+        phase(&format!(
+            "Linking {} template(s)",
+            rendered_set.templates.len()
+        ));
+    }
+    let relinked = linker::link_templates_in(fs, &home_dir, &stage_root, &rendered_set, dry_run)?;
+    let mut diffs = BTreeMap::new();
+    if dry_run {
+        for (item, outcome) in rendered_set.templates.iter().zip(&relinked) {
+            // A destination that cannot be read, such as a directory, gets no diff.
+            if matches!(outcome.status, ItemStatus::Created | ItemStatus::Updated)
+                && let Ok(diff) = diff::file_diff(&outcome.path, &item.rendered_path)
+            {
+                diffs.insert(outcome.path.clone(), diff);
+            }
+        }
+    }
+    let mut relinked = relinked.into_iter();
+    if !dry_run && let Err(err) = merge::save_bases(&home_dir, &rendered_set, &edits) {
+        if !container {
+            return Err(err);
+        }
+        warnings.push(format!(
+            "cannot keep the merge bases of the templates: {err}"
+        ));
+    }
+    warnings.extend(edits.warnings);
+    let mut outputs = BTreeMap::new();
+    for item in &rendered_set.templates {
+        outputs.insert(
+            item.template.destination.clone(),
+            download::sha256_file(&item.rendered_path)?,
+        );
+    }
+    let mut recorded = if selective || skip_templates {
+        previous.clone()
+    } else {
+        BTreeMap::new()
+    };
+    let mut origins = template_origins(
+        &manifest.templates,
+        &bases,
+        &applied_source,
+        profile.as_deref(),
+    );
+    let mut linked = Vec::with_capacity(manifest.templates.len());
+    for (template, (destination, inputs, current)) in manifest.templates.iter().zip(digests) {
+        if declined.contains(&destination) {
+            match previous.get(&destination) {
+                Some(origin) => origins.insert(destination, origin.clone()),
+                None => origins.remove(&destination),
+            };
+            continue;
+        }
+        let (output, merged) =
+            if current {
+                let reason = if template.create_only() {
+                    linker::CREATED_ONCE
+                } else {
+                    "inputs unchanged since the last run"
+                };
+                linked.push(
+                    LinkOutcome::new(home_dir.join(&destination), ItemStatus::Unchanged)
+                        .because(reason),
+                );
+                let origin = previous.get(&destination);
+                (
+                    origin.and_then(|origin| origin.output.clone()),
+                    origin.is_some_and(|origin| origin.merged),
+                )
+            } else {
+                linked.extend(relinked.next().map(
+                    |outcome| match edits.reasons.get(&destination) {
+                        Some(reason) => outcome.because(reason),
+                        None => outcome,
+                    },
+                ));
+                (
+                    outputs.remove(&destination),
+                    edits.merged.contains(&destination),
+                )
+            };
+        if let Some(origin) = origins.get_mut(&destination) {
+            origin.inputs = Some(inputs);
+            origin.output = output;
+            origin.merged = merged;
+        }
+    }
+    if !selective && !skip_templates {
+        linked.extend(dropped);
+    }
+    for outcome in &linked {
+        tracing::debug!("{:?} {}", outcome.status, outcome.path.display());
+    }
     let rendered_destinations = manifest
         .templates
         .iter()
         .map(|t| t.destination.clone())
         .collect();
+    if !skip_templates {
+        clock.lap("templates");
+    }
+    // Failures from here on leave the dotfiles linked but the setup unfinished.
+    let partial = |err: DotstrapError| {
+        if dry_run {
+            err
+        } else {
+            DotstrapError::Partial(Box::new(err))
+        }
+    };
+
+    let mut ssh_public_key = None;
+    if let Some(settings) = &manifest.ssh {
+        phase("Checking SSH key");
+        let outcome = ssh::ensure_key(&home_dir, settings, executor, dry_run).map_err(partial)?;
+        if let Some(err) = &outcome.copy_error {
+            warnings.push(format!("could not copy the SSH public key: {err}"));
+        } else if settings.copy_public_key && outcome.copied_with.is_none() && !dry_run {
+            warnings.push("could not copy the SSH public key: no clipboard tool found".into());
+        }
+        if settings.print_public_key {
+            ssh_public_key = outcome.public_key;
+        }
+        clock.lap("ssh");
+    }
+
+    let mut packages_spec: Option<PackagesSpec> = None;
+    let inline = manifest.packages.clone();
+    // Layers whose package lists and system settings apply to this run.
+    let step_layers = if selective { &[][..] } else { &layers[..] };
+    for (spec, origin) in step_layers
+        .iter()
+        .map(|layer| {
+            (
+                config::load_packages_spec(layer),
+                layer.join("packages.yaml"),
+            )
+        })
+        .chain([(Ok(inline), origin.clone())])
+    {
+        if let Some(spec) = spec?
+            && spec.enabled.as_deref().map_or(Ok(true), |expression| {
+                templating::is_enabled(expression, &context, &origin)
+            })?
+        {
+            packages_spec = Some(match packages_spec {
+                Some(base) => base.layer(spec),
+                None => spec,
+            });
+        }
+    }
+    // Packages a previous run installed are uninstalled, or forgotten, once no
+    // packages are declared at all.
+    if packages_spec.is_none()
+        && !selective
+        && !skip_brew
+        && state::load_state(&home_dir)?.brew.has_packages()
+    {
+        packages_spec = Some(PackagesSpec::default());
+    }
+    let (package_commands, packages) = match packages_spec {
+        Some(mut spec) => {
+            if skip_brew {
+                spec.brew = Default::default();
+            }
+            let desktop_apps = spec.brew.casks.len() + spec.brew.mas.len();
+            if container && desktop_apps > 0 {
+                warnings.push(format!(
+                    "skipping {desktop_apps} cask(s) and Mac App Store app(s) in container mode"
+                ));
+                spec.brew.casks.clear();
+                spec.brew.mas.clear();
+            }
+            if offline {
+                // Installing, and `brew update`, need the network: the run
+                // only checks that nothing is missing.
+                phase("Checking packages");
+                let (missing, unchecked) =
+                    packages::missing_packages_on(std::env::consts::OS, &spec, executor);
+                if !missing.is_empty() {
+                    return Err(DotstrapError::PackageInstall(Box::new(
+                        DotstrapError::Offline(format!("installing {}", missing.join(", "))),
+                    )));
+                }
+                warnings.extend(unchecked);
+                clock.lap("packages");
+                (Vec::new(), Vec::new())
+            } else {
+                let settings = PackageSettings {
+                    brew: &manifest.brew,
+                    install_brew,
+                    no_brew_update,
+                    sync_packages,
+                    skip_brew,
+                    jobs,
+                    escalation: escalation_for(&spec, escalation, providers, executor),
+                    dry_run,
+                };
+                phase("Installing packages");
+                if !dry_run {
+                    progress.suspend(|| settings.escalation.authenticate(executor))?;
+                }
+                let outcome =
+                    install_packages(&home_dir, spec, &settings, providers, executor, &mut ask)
+                        .map_err(|err| match err.exit_code() {
+                            exit_code::FAILURE => DotstrapError::PackageInstall(Box::new(err)),
+                            _ => err,
+                        })?;
+                warnings.extend(outcome.warnings);
+                reasons.extend(outcome.reasons);
+                clock.lap("packages");
+                (outcome.commands, outcome.reports)
+            }
+        }
+        None => (Vec::new(), Vec::new()),
+    };
+
+    phase("Installing release binaries");
+    let binaries = binaries::install_binaries(&home_dir, &manifest.binaries, executor, dry_run)
+        .map_err(partial)?;
+    clock.lap("binaries");
+    phase("Fetching downloads");
+    let downloads = downloads::fetch_downloads(&home_dir, &manifest.downloads, executor, dry_run)
+        .map_err(partial)?;
+    clock.lap("downloads");
+    linked
+        .iter()
+        .chain(&binaries)
+        .chain(&downloads)
+        .filter(|outcome| matches!(outcome.status, ItemStatus::Created | ItemStatus::Updated))
+        .for_each(|outcome| observer.on_link_created(outcome));
+    let backups = linked
+        .iter()
+        .chain(&binaries)
+        .chain(&downloads)
+        .filter_map(|outcome| outcome.backup.clone())
+        .chain(kept)
+        .collect();
 
-    let brew_commands = if skip_brew {
+    phase("Cloning workspace repositories");
+    let repos =
+        repos::clone_repos(&home_dir, &manifest.repos, executor, dry_run).map_err(partial)?;
+    for path in &repos {
+        reasons.insert(path.display().to_string(), "not cloned yet".into());
+    }
+    clock.lap("repos");
+
+    phase("Applying system settings");
+    let mut mac_defaults = Vec::new();
+    let mut declared_settings = Vec::new();
+    for layer in step_layers {
+        mac_defaults.extend(config::load_macos_defaults(layer)?);
+        declared_settings.extend(config::load_gnome_settings(layer)?);
+    }
+    let defaults = if mac_defaults.is_empty() {
         Vec::new()
+    } else if container {
+        warnings.push(format!(
+            "skipping {} macOS default(s) in container mode",
+            mac_defaults.len()
+        ));
+        Vec::new()
+    } else if cfg!(target_os = "macos") {
+        macos::apply_defaults(&mac_defaults, executor, dry_run).map_err(partial)?
     } else {
-        match config::load_brew_spec(repo.path())? {
-            Some(spec) => brew::install_brew(&spec, executor, dry_run)?,
-            None => Vec::new(),
-        }
+        warnings.push(format!(
+            "skipping {} macOS default(s): not running on macOS",
+            mac_defaults.len()
+        ));
+        Vec::new()
+    };
+
+    let gnome_settings = if declared_settings.is_empty() {
+        Vec::new()
+    } else if executor.is_available("gsettings") {
+        gnome::apply_settings(&declared_settings, executor, dry_run).map_err(partial)?
+    } else {
+        warnings.push(format!(
+            "skipping {} GNOME setting(s): gsettings is not available on this host",
+            declared_settings.len()
+        ));
+        Vec::new()
+    };
+    clock.lap("system");
+
+    phase("Updating crontab");
+    // Without entries, the step still removes a managed block left behind.
+    let cron = if selective {
+        Vec::new()
+    } else {
+        cron::install_cron(&manifest.cron, &context, &origin, executor, dry_run).map_err(partial)?
     };
+    for change in defaults.iter().chain(&gnome_settings) {
+        reasons.insert(change.clone(), "current value differs".into());
+    }
+    for line in &cron {
+        reasons.insert(line.clone(), "declared in the cron entries".into());
+    }
+    clock.lap("cron");
+
+    if !manifest.hooks.post_apply.is_empty() {
+        phase("Running post-apply hooks");
+        let post_apply = hooks::run_hooks(
+            "post-apply",
+            &manifest.hooks.post_apply,
+            &context,
+            &origin,
+            executor,
+            dry_run,
+        )
+        .map_err(partial)?;
+        for hook in &post_apply {
+            reasons
+                .entry(hook.clone())
+                .or_insert_with(|| "post-apply hook".into());
+        }
+        hooks.extend(post_apply);
+        clock.lap("hooks");
+    }
+
+    let placed = linked
+        .iter()
+        .chain(&binaries)
+        .chain(&downloads)
+        .filter(|link| !link.strategy.is_symlink())
+        .count();
+    if placed > 0 && !container {
+        warnings.push(format!(
+            "symbolic links are not permitted for this user (enable Developer Mode on Windows); {placed} path(s) were copied or linked with junctions instead"
+        ));
+    }
+
+    // Templates left out by `--only` or `--skip-templates` keep what the last
+    // run recorded.
+    recorded.extend(origins);
+    if !dry_run && let Err(err) = remember_run(&home_dir, applied_source, recorded) {
+        warnings.push(format!("cannot record the run in the state file: {err}"));
+    }
+    if strict && !warnings.is_empty() {
+        return Err(DotstrapError::StrictWarnings(warnings));
+    }
 
     Ok(ExecutionReport {
+        revision,
+        upstream_changes,
         rendered: rendered_destinations,
         linked,
-        brew_commands,
+        diffs,
+        modules: manifest.module_order,
+        disabled_modules: manifest.disabled_modules,
+        binaries,
+        downloads,
+        backups,
+        repos,
+        defaults,
+        gnome_settings,
+        cron,
+        hooks,
+        pre_apply_hooks,
+        ssh_public_key,
+        package_commands,
+        packages,
+        phases: clock.phases,
+        retries: executor.retries(),
+        skipped,
+        warnings,
+        reasons,
         dry_run,
     })
 }
 
+/// Keys verifying the bases and modules of a source verified with
+/// `trusted_keys`: those and the ones its `manifest` lists, which are ignored
+/// when the source was not verified.
+fn trusted_layer_keys(trusted_keys: Vec<String>, manifest: &Manifest) -> Vec<String> {
+    if trusted_keys.is_empty() {
+        if !manifest.trusted_keys.is_empty() {
+            tracing::warn!(
+                "ignoring the trusted_keys of the manifest: the source itself is only verified \
+                 with --trusted-key"
+            );
+        }
+        return trusted_keys;
+    }
+    trusted_keys
+        .into_iter()
+        .chain(manifest.trusted_keys.iter().cloned())
+        .collect()
+}
+
+/// Refuse sources whose HEAD is not signed by one of `trusted_keys`.
+fn verify_source(
+    repo: &RepoHandle,
+    source: &str,
+    trusted_keys: &[String],
+    executor: &dyn CommandExecutor,
+) -> Result<()> {
+    if trusted_keys.is_empty() {
+        return Ok(());
+    }
+    if repository::is_archive_source(source) {
+        return Err(DotstrapError::UntrustedSource {
+            path: repo.path().to_path_buf(),
+            reason: "archive sources carry no signature to verify".into(),
+        });
+    }
+    repo.verify_signature(trusted_keys, executor)
+}
+
+/// Profile selected by `--profile` and the templates dropped by their
+/// condition, as returned by [`select_profile`].
+type Selection = (Option<(String, Profile)>, Vec<LinkOutcome>);
+
+/// Profile selected by `--profile`, after dropping the entries of `manifest`
+/// whose `when` condition does not hold and expanding the known directories
+/// of `home` in its destinations.
+///
+/// The dropped templates are returned as skipped links, with the reason.
+fn select_profile(manifest: &mut Manifest, name: Option<&str>, home: &Path) -> Result<Selection> {
+    let selected = match name {
+        Some(name) => match manifest.profiles.get(name) {
+            Some(profile) => Some((name.to_string(), profile.clone())),
+            None => {
+                return Err(DotstrapError::UnknownProfile {
+                    name: name.to_string(),
+                    available: manifest.profiles.keys().cloned().collect(),
+                });
+            }
+        },
+        None => None,
+    };
+    let dirs = KnownDirs::for_home(home);
+    let dropped = manifest
+        .apply_conditions(std::env::consts::OS, name)
+        .into_iter()
+        .map(|(template, reason)| {
+            let destination = dirs
+                .expand(&template.destination)
+                .unwrap_or(template.destination);
+            LinkOutcome::new(home.join(destination), ItemStatus::Skipped).because(&reason)
+        })
+        .collect();
+    manifest.expand_destinations(&dirs)?;
+    Ok((selected, dropped))
+}
+
+/// Drop the entries of `manifest` whose `enabled` expression, declared in
+/// `origin`, is false in `context`.
+///
+/// The dropped templates are returned as skipped links, with the reason.
+fn disable_entries(
+    manifest: &mut Manifest,
+    context: &serde_json::Value,
+    home: &Path,
+    origin: &Path,
+) -> Result<Vec<LinkOutcome>> {
+    let dropped = manifest
+        .apply_enabled(|expression| templating::is_enabled(expression, context, origin))?
+        .into_iter()
+        .map(|(template, reason)| {
+            LinkOutcome::new(home.join(template.destination), ItemStatus::Skipped).because(&reason)
+        })
+        .collect();
+    Ok(dropped)
+}
+
+/// Template context merging the values and secrets of every layer, later
+/// layers taking precedence, then the values of the selected profile and
+/// the answers to the manifest questions none of them define, with the
+/// values as layered. Values are deep-merged, while secrets are merged by
+/// name. New answers are cached when `save_answers` is set.
+fn layered_context(
+    layers: &[&Path],
+    home: &Path,
+    manifest: &Manifest,
+    profile: &Option<(String, Profile)>,
+    secret_providers: &SecretProviderRegistry,
+    prompter: &mut dyn Prompter,
+    save_answers: bool,
+) -> Result<(serde_json::Value, LayeredValues)> {
+    let mut values = LayeredValues::default();
+    values.layer("module defaults", manifest.module_values.clone());
+    let mut secrets = HashMap::new();
+    for layer in layers {
+        config::layer_values(&mut values, layer)?;
+        secrets.extend(secrets::load_secrets_with(layer, home, secret_providers)?);
+    }
+    if let Some((name, profile)) = profile {
+        values.layer(&format!("profile `{name}`"), profile.values.clone());
+    }
+    if !manifest.questions.is_empty() {
+        let mut cached = answers::load_answers(home)?;
+        let known = cached.len();
+        let answered = questions::answer_questions(
+            &manifest.questions,
+            &values.values(),
+            &mut cached,
+            prompter,
+        )?;
+        if save_answers && cached.len() != known {
+            answers::save_answers(home, &cached)?;
+        }
+        values.layer("answers to questions", answered);
+    }
+    let profile = profile.as_ref().map(|(name, _)| name.as_str());
+    let context =
+        templating::build_context(&values.values(), &secrets, &system_facts(profile, home));
+    Ok((context, values))
+}
+
+/// Layer resolved from the `base` of a manifest.
+struct Base {
+    /// `base` as declared, with local paths made absolute.
+    source: String,
+    handle: RepoHandle,
+    /// Manifest of the layer, with template sources anchored to `handle`.
+    manifest: Manifest,
+}
+
+/// `manifest` layered over the manifests of its `bases`, with the entries
+/// of its modules merged in dependency order.
+///
+/// Modules are turned on or off by the `modules` map of the values of
+/// every layer, then of the `profile`, then by `enable` and `disable`.
+fn layer_manifests(
+    layers: &[&Path],
+    bases: &[Base],
+    manifest: Manifest,
+    selection: &ModuleSelection,
+) -> Result<Manifest> {
+    let mut manifest = match bases
+        .iter()
+        .map(|base| base.manifest.clone())
+        .reduce(Manifest::layer)
+    {
+        Some(base) => base.layer(manifest),
+        None => manifest,
+    };
+    let toggles = module_toggles(layers, &manifest, selection)?;
+    manifest.flatten_modules(&toggles)?;
+    Ok(manifest)
+}
+
+/// Modules turned on or off for a run.
+#[derive(Debug, Default)]
+struct ModuleSelection<'a> {
+    /// Profile whose values may toggle modules.
+    profile: Option<&'a str>,
+    /// Modules turned on whatever the values say, see `--enable`.
+    enable: &'a [String],
+    /// Modules turned off whatever the values say, see `--disable`.
+    disable: &'a [String],
+}
+
+/// Whether each module is turned on or off, by name, from the `modules`
+/// map of the values of `layers` and the selected profile, then from the
+/// command line. Values toggling no module are ignored, but `--enable` and
+/// `--disable` must name a module of `manifest`.
+fn module_toggles(
+    layers: &[&Path],
+    manifest: &Manifest,
+    selection: &ModuleSelection,
+) -> Result<BTreeMap<String, bool>> {
+    let mut toggles = BTreeMap::new();
+    let mut extend = |values: &HashMap<String, serde_json::Value>| {
+        if let Some(serde_json::Value::Object(modules)) = values.get("modules") {
+            for (name, on) in modules {
+                if let Some(on) = on.as_bool() {
+                    toggles.insert(name.clone(), on);
+                }
+            }
+        }
+    };
+    for layer in layers {
+        extend(&config::load_values(layer)?);
+    }
+    if let Some(profile) = selection
+        .profile
+        .and_then(|name| manifest.profiles.get(name))
+    {
+        extend(&profile.values);
+    }
+    for (names, on) in [(selection.disable, false), (selection.enable, true)] {
+        for name in names {
+            if !manifest.modules.contains_key(name) {
+                return Err(DotstrapError::UnknownModuleToggle(name.clone()));
+            }
+            toggles.insert(name.clone(), on);
+        }
+    }
+    Ok(toggles)
+}
+
+/// Resolve the chain of `base` layers below `manifest`, lowest layer first,
+/// and fetch the modules with a `source` that every layer declares.
+///
+/// Relative local bases and module sources are resolved from the repository
+/// declaring them, and base manifests have their template sources anchored
+/// to their repository.
+fn resolve_bases(
+    repo: &RepoHandle,
+    manifest: &mut Manifest,
+    trusted_keys: &[String],
+    executor: &dyn CommandExecutor,
+) -> Result<Vec<Base>> {
+    let mut bases: Vec<Base> = Vec::new();
+    let mut seen = BTreeSet::from([repo.path().to_string_lossy().into_owned()]);
+    let mut next = manifest.base.clone();
+    while let Some(base) = next {
+        let declaring = bases
+            .last()
+            .map_or(repo.path(), |declaring| declaring.handle.path());
+        let local = declaring.join(&base);
+        let base = match local.canonicalize() {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(_) => base,
+        };
+        if !seen.insert(base.clone()) {
+            return Err(DotstrapError::ManifestBaseCycle(base));
+        }
+        let (source, subdir) = repository::split_subdir(&base);
+        let mut handle = repository::resolve_repository(source, None, executor)?;
+        if let Some(subdir) = subdir {
+            handle = handle.into_subdir(Path::new(subdir))?;
+        }
+        verify_source(&handle, source, trusted_keys, executor)?;
+        let mut base_manifest = config::load_manifest(handle.path())?;
+        base_manifest.anchor_templates(handle.path());
+        fetch_modules(handle.path(), &mut base_manifest, trusted_keys, executor)?;
+        next = base_manifest.base.clone();
+        bases.push(Base {
+            source: base,
+            handle,
+            manifest: base_manifest,
+        });
+    }
+    bases.reverse();
+    fetch_modules(repo.path(), manifest, trusted_keys, executor)?;
+    Ok(bases)
+}
+
+/// Load the modules of `manifest`, declared in `declaring`, that name a
+/// `source`, adding the entries the manifest declares for them.
+fn fetch_modules(
+    declaring: &Path,
+    manifest: &mut Manifest,
+    trusted_keys: &[String],
+    executor: &dyn CommandExecutor,
+) -> Result<()> {
+    for module in manifest.modules.values_mut() {
+        let Some(declared) = module.source.clone() else {
+            continue;
+        };
+        let (location, git_ref) = repository::split_module_source(&declared);
+        let (location, subdir) = repository::split_subdir(&location);
+        let local = declaring.join(location);
+        let location = match local.canonicalize() {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(_) => location.to_string(),
+        };
+        let mut handle = repository::resolve_repository(&location, git_ref, executor)?;
+        if let Some(subdir) = subdir {
+            handle = handle.into_subdir(Path::new(subdir))?;
+        }
+        verify_source(&handle, &location, trusted_keys, executor)?;
+        let mut fetched = config::load_module(handle.path())?.extend(std::mem::take(module));
+        fetched.checkout = Some(Arc::new(handle));
+        *module = fetched;
+    }
+    Ok(())
+}
+
+/// Run-wide options influencing package installation.
+struct PackageSettings<'a> {
+    brew: &'a BrewSettings,
+    install_brew: bool,
+    no_brew_update: bool,
+    sync_packages: bool,
+    skip_brew: bool,
+    jobs: u16,
+    escalation: Escalation,
+    dry_run: bool,
+}
+
+/// Escalation policy for installing `spec`: none when nothing needs root
+/// privileges or dotstrap already runs as root, otherwise `requested`.
+fn escalation_for(
+    spec: &PackagesSpec,
+    requested: Escalation,
+    providers: &ProviderRegistry,
+    executor: &dyn CommandExecutor,
+) -> Escalation {
+    if packages::needs_escalation(std::env::consts::OS, spec, providers)
+        && !escalation::is_root(executor)
+    {
+        requested
+    } else {
+        Escalation::None
+    }
+}
+
+/// Install the package specification and keep the Homebrew state in sync.
+///
+/// `brew update` is omitted when disabled or when it already ran within the
+/// configured interval. When `sync_packages` is set, Homebrew packages
+/// recorded by a previous run but no longer declared are uninstalled once
+/// `ask` confirms it; declined ones are kept and no longer managed. Only the
+/// packages absent before the run are recorded as installed by dotstrap.
+fn install_packages(
+    home: &Path,
+    mut spec: PackagesSpec,
+    settings: &PackageSettings<'_>,
+    providers: &ProviderRegistry,
+    executor: &dyn CommandExecutor,
+    ask: &mut dyn FnMut(&str, &str) -> Result<bool>,
+) -> Result<PackageOutcome> {
+    let mut state = state::load_state(home)?;
+    let now = unix_now();
+    spec.brew.skip_update = settings.no_brew_update
+        || !settings.brew.update
+        || !brew::update_due(
+            state.brew.last_update,
+            settings.brew.update_interval_hours,
+            now,
+        );
+    spec.brew.env = settings.brew.env.clone();
+    let options = InstallOptions {
+        dry_run: settings.dry_run,
+        bootstrap: settings.install_brew || settings.brew.install,
+        jobs: settings.jobs.into(),
+        escalation: settings.escalation,
+    };
+    let provider = BrewProvider::detect();
+    let declared = spec.brew.formulae.len() + spec.brew.casks.len() > 0;
+    let preinstalled = if declared && !settings.dry_run && !settings.skip_brew {
+        provider.installed(executor)?
+    } else {
+        BrewState::default()
+    };
+    let mut outcome = packages::install_packages(&spec, providers, executor, options)?;
+    let updated = outcome.providers.iter().any(|name| name == "brew");
+    // A brew section removed or emptied still leaves the recorded packages to
+    // uninstall or forget.
+    if settings.skip_brew || (!updated && !state.brew.has_packages()) {
+        return Ok(outcome);
+    }
+    let mut removals = if settings.sync_packages {
+        provider.plan_removals(&state.brew, &spec.brew)
+    } else {
+        Vec::new()
+    };
+    if !removals.is_empty() && !settings.dry_run {
+        let details: String = removals
+            .iter()
+            .map(|command| format!("  {command}\n"))
+            .collect();
+        let action = format!("uninstall {} package(s) no longer declared", removals.len());
+        if !ask(&action, &details)? {
+            outcome.warnings.push(format!(
+                "kept {} package(s) no longer declared, which are no longer managed",
+                removals.len()
+            ));
+            removals.clear();
+        }
+    }
+    let removal_commands: Vec<String> = removals.iter().map(ToString::to_string).collect();
+    for command in &removal_commands {
+        outcome.reasons.insert(
+            command.clone(),
+            "no longer declared in the brew section".to_string(),
+        );
+    }
+    outcome.commands.extend(removal_commands.iter().cloned());
+    if let Some(report) = outcome.reports.iter_mut().find(|r| r.provider == "brew") {
+        report.commands.extend(removal_commands);
+    }
+    if settings.dry_run {
+        return Ok(outcome);
+    }
+    for command in &removals {
+        command.run(executor)?;
+    }
+    brew::record_managed(&mut state.brew, &spec.brew, &preinstalled);
+    if updated && !spec.brew.skip_update {
+        state.brew.last_update = Some(now);
+    }
+    state::save_state(home, &state)?;
+    Ok(outcome)
+}
+
+/// Destinations of `rendered` the user declined to replace, asking with `ask`
+/// about the regular files in `home` no previous run linked, as recorded in
+/// `previous`, that differ from the rendered template.
+fn declined_replacements(
+    home: &Path,
+    rendered: &RenderedSet,
+    previous: &BTreeMap<PathBuf, TemplateOrigin>,
+    ask: &mut dyn FnMut(&str, &str) -> Result<bool>,
+) -> Result<BTreeSet<PathBuf>> {
+    let mut declined = BTreeSet::new();
+    for item in &rendered.templates {
+        let relative = &item.template.destination;
+        let destination = home.join(relative);
+        let unmanaged =
+            !previous.contains_key(relative) && destination.is_file() && !destination.is_symlink();
+        if !unmanaged || linker::same_contents(&OsFileSystem, &destination, &item.rendered_path)? {
+            continue;
+        }
+        let details = format!(
+            "{} is not managed by dotstrap:\n{}",
+            destination.display(),
+            diff::file_diff(&destination, &item.rendered_path)?
+        );
+        let action = format!("replace {} (a backup is kept)", destination.display());
+        if !ask(&action, &details)? {
+            declined.insert(relative.clone());
+        }
+    }
+    Ok(declined)
+}
+
+/// Walk the templates of `rendered` that would change a file of `home`,
+/// asking `review` what to do with each, as `--interactive` does.
+///
+/// Returns the skipped destinations and the copies kept of the files backed
+/// up before being replaced.
+fn review_changes(
+    home: &Path,
+    rendered: &RenderedSet,
+    review: &mut dyn FnMut(&str, &str) -> Review,
+) -> Result<(BTreeSet<PathBuf>, Vec<PathBuf>)> {
+    let mut skipped = BTreeSet::new();
+    let mut kept = Vec::new();
+    let mut quit = false;
+    for item in &rendered.templates {
+        let relative = &item.template.destination;
+        let destination = home.join(relative);
+        if quit {
+            skipped.insert(relative.clone());
+            continue;
+        }
+        if destination.is_symlink()
+            && linker::same_contents(&OsFileSystem, &destination, &item.rendered_path)?
+        {
+            continue;
+        }
+        let details = format!(
+            "{}:\n{}",
+            destination.display(),
+            diff::file_diff(&destination, &item.rendered_path)?
+        );
+        match review(&format!("link {}", destination.display()), &details) {
+            Review::Apply => {}
+            Review::Backup if destination.is_symlink() => {
+                kept.extend(linker::backup_copy(&destination)?)
+            }
+            // Linking moves regular files to a backup already.
+            Review::Backup => {}
+            Review::Skip => {
+                skipped.insert(relative.clone());
+            }
+            Review::Quit => {
+                skipped.insert(relative.clone());
+                quit = true;
+            }
+        }
+    }
+    Ok((skipped, kept))
+}
+
+/// Record `source` as the one reused by runs that do not name a source, and
+/// the origin of the templates it linked.
+fn remember_run(
+    home: &Path,
+    source: SourceState,
+    templates: BTreeMap<PathBuf, TemplateOrigin>,
+) -> Result<()> {
+    let mut state = state::load_state(home)?;
+    if state.last_source.as_ref() != Some(&source) || state.templates != templates {
+        state.last_source = Some(source);
+        state.templates = templates;
+        state::save_state(home, &state)?;
+    }
+    Ok(())
+}
+
+/// Whether the file staged by the last run for a template whose inputs now
+/// digest to `inputs` is still current: the inputs did not change, the staged
+/// file was not modified since, and `destination` still links to it, or is
+/// still a copy of it where `fs` places copies.
+fn is_current(
+    fs: &dyn FileSystem,
+    previous: Option<&TemplateOrigin>,
+    inputs: &str,
+    staged: &Path,
+    destination: &Path,
+) -> bool {
+    previous.is_some_and(|previous| {
+        previous.inputs.as_deref() == Some(inputs)
+            && previous.output.as_ref().is_some_and(|output| {
+                download::sha256_file(staged).is_ok_and(|actual| &actual == output)
+            })
+            && linker::link_status_in(fs, staged, destination) == ItemStatus::Unchanged
+    })
+}
+
+/// Staging root of a container run under `home`, or a temporary directory
+/// when the home is read-only.
+fn container_stage_root(home: &Path, warnings: &mut Vec<String>) -> PathBuf {
+    let stage_root = linker::default_stage_root(home);
+    match std::fs::create_dir_all(&stage_root) {
+        Ok(()) => stage_root,
+        Err(err) => {
+            let fallback = std::env::temp_dir().join("dotstrap/generated");
+            warnings.push(format!(
+                "staging templates in {}, as {} cannot be created: {err}",
+                fallback.display(),
+                stage_root.display()
+            ));
+            fallback
+        }
+    }
+}
+
+/// Layer and template producing each destination of `templates`.
+fn template_origins(
+    templates: &[TemplateMapping],
+    bases: &[Base],
+    source: &SourceState,
+    profile: Option<&str>,
+) -> BTreeMap<PathBuf, TemplateOrigin> {
+    let main = match &source.subdir {
+        Some(subdir) => format!("{}//{}", source.source, subdir.display()),
+        None => source.source.clone(),
+    };
+    templates
+        .iter()
+        .map(|item| {
+            let template = &item.source;
+            let (layer, template) = bases
+                .iter()
+                .find_map(|base| {
+                    let relative = template.strip_prefix(base.handle.path()).ok()?;
+                    Some((base.source.clone(), relative.to_path_buf()))
+                })
+                .unwrap_or_else(|| (main.clone(), template.clone()));
+            let origin = TemplateOrigin {
+                source: layer,
+                template,
+                profile: profile.map(str::to_string),
+                inputs: None,
+                output: None,
+                merged: false,
+                mode: item.mode,
+                create_only: item.create_only(),
+            };
+            (item.destination.clone(), origin)
+        })
+        .collect()
+}
+
+/// Host facts exposed to templates under the `system` namespace, including
+/// the known directories of `home`, the XDG ones grouped under `xdg`.
+fn system_facts(profile: Option<&str>, home: &Path) -> serde_json::Map<String, serde_json::Value> {
+    let mut system = directory_facts(&KnownDirs::for_home(home), std::env::consts::OS, profile);
+    let brew_prefix = brew::locate_prefix()
+        .map(|prefix| serde_json::Value::String(prefix.to_string_lossy().into_owned()))
+        .unwrap_or(serde_json::Value::Null);
+    system.insert("brew_prefix".into(), brew_prefix);
+    system
+}
+
+/// `system` facts describing `dirs`, their home, `os`, and `profile`, which
+/// do not depend on the tools installed on the host.
+fn directory_facts(
+    dirs: &KnownDirs,
+    os: &str,
+    profile: Option<&str>,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut system = serde_json::Map::new();
+    let mut xdg = serde_json::Map::new();
+    for (name, dir) in dirs.iter() {
+        let dir = dir.to_string_lossy().into_owned().into();
+        match name.strip_prefix("xdg_") {
+            Some(kind) => xdg.insert(kind.into(), dir),
+            None => system.insert(name.into(), dir),
+        };
+    }
+    system.insert("xdg".into(), xdg.into());
+    system.insert("home".into(), dirs.home().to_string_lossy().into());
+    system.insert("os".into(), os.into());
+    system.insert("profile".into(), profile.into());
+    system
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
 
+    use crate::infrastructure::command::RecordingCommandExecutor;
+
     struct MockExecutor();
 
     impl super::CommandExecutor for MockExecutor {
@@ -100,11 +1646,49 @@ mod tests {
         brew: bool,
     ) -> super::Cli {
         super::Cli {
+            command: None,
             source: Some("tests/".to_owned() + source.unwrap_or("empty-config")),
-            home: home_dir.to_owned(),
+            git_ref: None,
+            git_token: None,
+            ssh_key: None,
+            trusted_keys: Vec::new(),
+            profile: None,
+            subdir: None,
+            home: home_dir.into_iter().collect(),
+            staging_dir: None,
             skip_brew: brew,
+            skip_templates: false,
+            install_brew: false,
+            no_brew_update: false,
+            sync_packages: false,
+            retries: 0,
+            command_timeout: None,
+            jobs: 1,
+            escalation: crate::infrastructure::escalation::Escalation::Sudo,
+            mode: crate::cli::RunMode::Host,
+            offline: false,
+            trace_values: Vec::new(),
+            verbose: 0,
+            quiet: false,
+            output: crate::cli::OutputFormat::Text,
+            color: crate::cli::ColorChoice::Never,
             dry_run: true,
+            wait: false,
+            yes: false,
+            non_interactive: false,
+            strict: false,
+            ci: false,
+            interactive: false,
+            allow_plugins: false,
+            allow_template_exec: false,
+            only: Vec::new(),
+            enable: Vec::new(),
+            disable: Vec::new(),
+            notify: false,
+            explain: false,
             generate_completions: None,
+            install_completions: false,
+            generate_manpages: None,
         }
     }
 
@@ -121,6 +1705,7 @@ mod tests {
         let result = super::run_with_executor(
             create_test_cli(None, Some(PathBuf::from("/home/user")), true),
             &executor,
+            &super::NoopObserver,
         );
         assert!(result.is_ok());
     }
@@ -128,15 +1713,575 @@ mod tests {
     #[test]
     fn test_run_with_executor_brew_enabled() {
         let executor = MockExecutor();
-        let result =
-            super::run_with_executor(create_test_cli(Some("config-brew"), None, false), &executor);
+        let result = super::run_with_executor(
+            create_test_cli(Some("config-brew"), None, false),
+            &executor,
+            &super::NoopObserver,
+        );
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_run_with_executor_no_brew() {
         let executor = MockExecutor();
-        let result = super::run_with_executor(create_test_cli(None, None, false), &executor);
+        let result = super::run_with_executor(
+            create_test_cli(None, None, false),
+            &executor,
+            &super::NoopObserver,
+        );
         assert!(result.is_ok());
     }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_run_with_executor_skips_winget_on_non_windows() {
+        let executor = MockExecutor();
+        let report = super::run_with_executor(
+            create_test_cli(Some("config-winget"), None, true),
+            &executor,
+            &super::NoopObserver,
+        )
+        .expect("winget section should not fail on non-Windows hosts");
+        assert!(report.package_commands.is_empty());
+        assert_eq!(report.packages.len(), 1);
+        assert_eq!(
+            report.packages[0].status,
+            crate::services::outcome::ItemStatus::Skipped
+        );
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].contains("winget"));
+    }
+
+    #[test]
+    fn test_run_with_executor_sync_packages_uninstalls_dropped_formulae() {
+        let executor = MockExecutor();
+        let home = tempfile::TempDir::new().expect("failed to create home tempdir");
+        let mut state = crate::infrastructure::state::State::default();
+        state.brew.formulae.insert("git".into());
+        state.brew.formulae.insert("htop".into());
+        crate::infrastructure::state::save_state(home.path(), &state).unwrap();
+
+        let cli = super::Cli {
+            sync_packages: true,
+            ..create_test_cli(Some("config-brew"), Some(home.path().to_path_buf()), false)
+        };
+        let report = super::run_with_executor(cli, &executor, &super::NoopObserver)
+            .expect("sync dry run should succeed");
+
+        assert_eq!(
+            report.package_commands.last().unwrap(),
+            "brew uninstall htop"
+        );
+        assert!(
+            !report
+                .package_commands
+                .contains(&"brew uninstall git".to_string())
+        );
+    }
+
+    #[test]
+    fn test_run_with_executor_sync_packages_without_packages_section() {
+        let executor = MockExecutor();
+        let home = tempfile::TempDir::new().expect("failed to create home tempdir");
+        let mut state = crate::infrastructure::state::State::default();
+        state.brew.formulae.insert("htop".into());
+        crate::infrastructure::state::save_state(home.path(), &state).unwrap();
+
+        let cli = super::Cli {
+            sync_packages: true,
+            ..create_test_cli(None, Some(home.path().to_path_buf()), false)
+        };
+        let report = super::run_with_executor(cli, &executor, &super::NoopObserver)
+            .expect("sync dry run should succeed");
+        assert_eq!(report.package_commands, vec!["brew uninstall htop"]);
+
+        let cli = super::Cli {
+            sync_packages: true,
+            ..create_test_cli(None, Some(home.path().to_path_buf()), true)
+        };
+        let report = super::run_with_executor(cli, &executor, &super::NoopObserver)
+            .expect("dry run should succeed");
+        assert!(report.package_commands.is_empty(), "--skip-brew keeps them");
+    }
+
+    #[test]
+    fn test_trusted_layer_keys_ignore_manifest_keys_of_unverified_sources() {
+        let manifest = super::Manifest {
+            trusted_keys: vec!["ssh-ed25519 AAAA pusher".into()],
+            ..super::Manifest::default()
+        };
+
+        assert!(super::trusted_layer_keys(Vec::new(), &manifest).is_empty());
+        assert_eq!(
+            super::trusted_layer_keys(vec!["ABCD1234".into()], &manifest),
+            vec!["ABCD1234", "ssh-ed25519 AAAA pusher"]
+        );
+    }
+
+    #[test]
+    fn test_run_with_executor_no_brew_update() {
+        let executor = MockExecutor();
+        let cli = super::Cli {
+            no_brew_update: true,
+            ..create_test_cli(Some("config-brew"), None, false)
+        };
+
+        let report = super::run_with_executor(cli, &executor, &super::NoopObserver)
+            .expect("dry run should succeed");
+
+        assert!(!report.package_commands.contains(&"brew update".to_string()));
+        assert!(report.package_commands[0].ends_with("brew tap homebrew/cask --force"));
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn test_run_with_executor_skips_macos_defaults_elsewhere() {
+        let executor = MockExecutor();
+        let report = super::run_with_executor(
+            create_test_cli(Some("config-macos"), None, true),
+            &executor,
+            &super::NoopObserver,
+        )
+        .expect("macOS defaults should not fail on other hosts");
+        assert!(report.defaults.is_empty());
+        assert!(report.warnings.iter().any(|w| w.contains("macOS default")));
+    }
+
+    #[test]
+    fn test_run_with_executor_skips_desktop_steps_in_container_mode() {
+        let executor = MockExecutor();
+        let cli = super::Cli {
+            mode: crate::cli::RunMode::Container,
+            ..create_test_cli(Some("config-brew"), None, false)
+        };
+        let report = super::run_with_executor(cli, &executor, &super::NoopObserver)
+            .expect("dry run should succeed");
+        assert!(
+            !report
+                .package_commands
+                .iter()
+                .any(|command| command.contains("--cask"))
+        );
+        assert!(report.warnings.iter().any(|w| w.contains("cask(s)")));
+
+        let cli = super::Cli {
+            mode: crate::cli::RunMode::Container,
+            ..create_test_cli(Some("config-macos"), None, true)
+        };
+        let report = super::run_with_executor(cli, &executor, &super::NoopObserver)
+            .expect("dry run should succeed");
+        assert!(report.defaults.is_empty());
+        assert!(
+            report
+                .warnings
+                .iter()
+                .any(|w| w.ends_with("macOS default(s) in container mode"))
+        );
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn test_run_with_executor_fails_on_warnings_in_strict_mode() {
+        let cli = super::Cli {
+            strict: true,
+            ..create_test_cli(Some("config-macos"), None, true)
+        };
+
+        let err = super::run_with_executor(cli, &MockExecutor(), &super::NoopObserver)
+            .expect_err("the skipped macOS defaults should fail a strict run");
+
+        assert!(
+            matches!(err, super::DotstrapError::StrictWarnings(ref warnings)
+            if warnings.iter().any(|w| w.contains("macOS default")))
+        );
+    }
+
+    #[test]
+    fn test_run_with_executor_offline_requires_a_cached_clone() {
+        let home = tempfile::tempdir().unwrap();
+        let cli = super::Cli {
+            source: Some("https://github.com/me/dotfiles.git".into()),
+            offline: true,
+            ..create_test_cli(None, Some(home.path().to_path_buf()), true)
+        };
+        let executor = RecordingCommandExecutor::default();
+
+        let err = super::run_with_executor(cli, &executor, &super::NoopObserver)
+            .expect_err("an uncached remote source needs the network");
+
+        assert!(matches!(err, super::DotstrapError::Offline(_)));
+        assert!(executor.calls().is_empty(), "nothing was cloned");
+    }
+
+    #[test]
+    fn test_run_with_executor_offline_checks_packages_instead_of_installing() {
+        let home = tempfile::tempdir().unwrap();
+        let cli = super::Cli {
+            offline: true,
+            dry_run: false,
+            ..create_test_cli(
+                Some("config-packages"),
+                Some(home.path().to_path_buf()),
+                false,
+            )
+        };
+        let executor = RecordingCommandExecutor::default()
+            .with_output("brew list --formula -1", "ripgrep\n")
+            .with_output(
+                "dpkg-query --show --showformat ${Package}\n",
+                "fd-find\nripgrep\n",
+            )
+            .with_output("cargo install --list", "bat v0.24.0:\n    bat\n");
+
+        let report = super::run_with_executor(cli, &executor, &super::NoopObserver)
+            .expect("installed packages need no network");
+
+        assert!(report.package_commands.is_empty());
+        assert!(
+            executor
+                .calls()
+                .iter()
+                .all(|(program, args)| program == "cargo" || args[0] != "install"),
+            "nothing was installed"
+        );
+        assert!(!executor.calls().iter().any(|(_, args)| args == &["update"]));
+    }
+
+    #[test]
+    fn test_run_with_executor_update_pulls_local_source() {
+        let executor = MockExecutor();
+        let cli = super::Cli {
+            command: Some(crate::cli::Command::Update { source: None }),
+            ..create_test_cli(None, None, true)
+        };
+
+        let report = super::run_with_executor(cli, &executor, &super::NoopObserver)
+            .expect("update should succeed");
+
+        assert!(report.upstream_changes.is_empty());
+        assert!(report.dry_run);
+    }
+
+    #[test]
+    fn test_execution_report_summary_counts_statuses() {
+        use crate::services::outcome::{ItemStatus, LinkOutcome};
+
+        let report = super::ExecutionReport {
+            linked: vec![
+                LinkOutcome::new(PathBuf::from("/h/.zshrc"), ItemStatus::Created),
+                LinkOutcome::new(PathBuf::from("/h/.vimrc"), ItemStatus::Unchanged),
+            ],
+            package_commands: vec!["brew update".into()],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            report.summary(),
+            "Linked 2 file(s): 1 created, 0 updated, 1 unchanged; ran 1 package command(s)."
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_with_executor_skips_templates_with_unchanged_inputs() {
+        use crate::services::outcome::ItemStatus;
+
+        let home = tempfile::TempDir::new().unwrap();
+        let apply = || {
+            let mut cli = create_test_cli(None, Some(home.path().to_path_buf()), true);
+            cli.dry_run = false;
+            super::run_with_executor(cli, &MockExecutor(), &super::NoopObserver)
+                .expect("run should succeed")
+        };
+        apply();
+
+        let statuses = |report: &super::ExecutionReport| -> Vec<ItemStatus> {
+            report.linked.iter().map(|outcome| outcome.status).collect()
+        };
+        assert_eq!(statuses(&apply()), vec![ItemStatus::Unchanged; 3]);
+        let recorded = crate::infrastructure::state::load_state(home.path()).unwrap();
+        assert!(
+            recorded
+                .templates
+                .values()
+                .all(|origin| origin.inputs.is_some() && origin.output.is_some())
+        );
+
+        // Edits to the staged file are not kept by the next run.
+        std::fs::write(home.path().join(".zshrc"), "edited").unwrap();
+        assert_eq!(
+            statuses(&apply()),
+            vec![
+                ItemStatus::Unchanged,
+                ItemStatus::Updated,
+                ItemStatus::Unchanged
+            ]
+        );
+        assert_ne!(
+            std::fs::read_to_string(home.path().join(".zshrc")).unwrap(),
+            "edited"
+        );
+    }
+
+    #[derive(Default)]
+    struct EventLog(std::cell::RefCell<Vec<String>>);
+
+    impl super::Observer for EventLog {
+        fn on_phase_start(&self, phase: &str) {
+            self.0.borrow_mut().push(format!("phase {phase}"));
+        }
+
+        fn on_template_rendered(&self, destination: &std::path::Path) {
+            self.0
+                .borrow_mut()
+                .push(format!("rendered {}", destination.display()));
+        }
+
+        fn on_link_created(&self, link: &super::LinkOutcome) {
+            self.0.borrow_mut().push(format!(
+                "linked {}",
+                link.path.file_name().unwrap().to_string_lossy()
+            ));
+        }
+
+        fn on_error(&self, error: &super::DotstrapError) {
+            self.0.borrow_mut().push(format!("error {error}"));
+        }
+    }
+
+    #[test]
+    fn test_run_with_executor_reports_events_to_the_observer() {
+        let home = tempfile::TempDir::new().unwrap();
+        let observer = EventLog::default();
+
+        super::run_with_executor(
+            create_test_cli(None, Some(home.path().to_path_buf()), true),
+            &MockExecutor(),
+            &observer,
+        )
+        .expect("dry run should succeed");
+
+        let events = observer.0.take();
+        assert_eq!(events[0], "phase Resolving source");
+        assert!(events.contains(&"rendered .zshrc".to_string()));
+        assert!(events.contains(&"linked .zshrc".to_string()));
+        let rendered = events.iter().position(|e| e == "rendered .zshrc");
+        let linked = events.iter().position(|e| e == "linked .zshrc");
+        assert!(rendered < linked);
+
+        let mut cli = create_test_cli(None, Some(home.path().to_path_buf()), true);
+        cli.profile = Some("missing".into());
+        super::run_with_executor(cli, &MockExecutor(), &observer).unwrap_err();
+        assert!(observer.0.take().last().unwrap().starts_with("error "));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_run_async_applies_on_a_blocking_thread() {
+        use crate::infrastructure::async_command::TokioCommandExecutor;
+
+        let home = tempfile::TempDir::new().unwrap();
+        let options = super::DotstrapOptions {
+            source: Some("tests/empty-config".into()),
+            home: Some(home.path().to_path_buf()),
+            skip_brew: true,
+            dry_run: true,
+            ..Default::default()
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let report = runtime
+            .block_on(super::run_async(options, TokioCommandExecutor::default()))
+            .expect("dry run should succeed");
+
+        assert!(report.rendered.contains(&PathBuf::from(".zshrc")));
+        assert!(!home.path().join(".zshrc").exists());
+    }
+
+    #[test]
+    fn test_run_with_executor_layers_base_manifest() {
+        let executor = MockExecutor();
+        let report = super::run_with_executor(
+            create_test_cli(Some("config-layered"), None, true),
+            &executor,
+            &super::NoopObserver,
+        )
+        .expect("layered dry run should succeed");
+
+        assert_eq!(
+            report.rendered,
+            vec![
+                PathBuf::from(".gitconfig"),
+                PathBuf::from(".wezterm.lua"),
+                PathBuf::from(".zshrc"),
+            ]
+        );
+        let phases: Vec<&str> = report.phases.iter().map(|p| p.phase).collect();
+        assert_eq!(
+            phases,
+            vec![
+                "source",
+                "templates",
+                "binaries",
+                "downloads",
+                "repos",
+                "system",
+                "cron"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_with_executor_selects_profile_entries_and_hooks() {
+        let executor = MockExecutor();
+        let mut cli = create_test_cli(Some("config-v2"), None, true);
+        cli.profile = Some("work".into());
+        let report = super::run_with_executor(cli, &executor, &super::NoopObserver)
+            .expect("profile dry run should succeed");
+
+        assert_eq!(
+            report.rendered,
+            vec![PathBuf::from(".gitconfig"), PathBuf::from(".work-vpn")]
+        );
+        assert_eq!(
+            report.hooks,
+            vec!["echo applying work", "echo connected to the work VPN"]
+        );
+        assert!(report.package_commands.iter().any(|c| c.contains("bat")));
+
+        let report = super::run_with_executor(
+            create_test_cli(Some("config-v2"), None, true),
+            &executor,
+            &super::NoopObserver,
+        )
+        .expect("default dry run should succeed");
+        assert_eq!(report.rendered, vec![PathBuf::from(".gitconfig")]);
+        assert_eq!(report.hooks, vec!["echo applying"]);
+    }
+
+    #[test]
+    fn test_run_with_executor_rejects_unknown_profile() {
+        let mut cli = create_test_cli(Some("config-v2"), None, true);
+        cli.profile = Some("laptop".into());
+        let err = super::run_with_executor(cli, &MockExecutor(), &super::NoopObserver).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "profile `laptop` is not declared by the manifest (available: work)"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_system_facts_group_xdg_directories() {
+        let home = std::path::Path::new("/nonexistent/home");
+        let system = super::system_facts(Some("work"), home);
+
+        assert_eq!(system["profile"], "work");
+        assert_eq!(system["documents"], "/nonexistent/home/Documents");
+        assert_eq!(system["xdg"]["cache"], "/nonexistent/home/.cache");
+        assert!(!system.contains_key("xdg_cache"));
+    }
+
+    #[test]
+    fn test_run_with_executor_only_applies_matching_templates() {
+        let mut cli = create_test_cli(Some("config-v2"), None, true);
+        cli.profile = Some("work".into());
+        cli.only = vec!["templates/work.hbs".into()];
+        let report = super::run_with_executor(cli, &MockExecutor(), &super::NoopObserver)
+            .expect("selective dry run should succeed");
+
+        assert_eq!(report.rendered, vec![PathBuf::from(".work-vpn")]);
+        assert!(report.hooks.is_empty());
+        assert!(report.package_commands.is_empty());
+        assert!(report.skipped.contains(&"packages"));
+
+        let mut cli = create_test_cli(Some("config-layered"), None, true);
+        cli.only = vec!["~/.git*".into(), ".zsh*".into()];
+        let report = super::run_with_executor(cli, &MockExecutor(), &super::NoopObserver)
+            .expect("selective dry run should succeed");
+        assert_eq!(
+            report.rendered,
+            vec![PathBuf::from(".gitconfig"), PathBuf::from(".zshrc")]
+        );
+
+        let mut cli = create_test_cli(None, None, true);
+        cli.only = vec![".vimrc".into()];
+        let err = super::run_with_executor(cli, &MockExecutor(), &super::NoopObserver).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no template of the manifest matches --only .vimrc"
+        );
+    }
+
+    #[test]
+    fn test_run_with_executor_skip_templates_only_runs_other_steps() {
+        let mut cli = create_test_cli(Some("config-v2"), None, true);
+        cli.skip_templates = true;
+        let report = super::run_with_executor(cli, &MockExecutor(), &super::NoopObserver)
+            .expect("dry run without templates should succeed");
+
+        assert!(report.rendered.is_empty() && report.linked.is_empty());
+        assert!(report.package_commands.iter().any(|c| c.contains("bat")));
+        assert_eq!(report.skipped, vec!["templates"]);
+        assert!(report.phases.iter().all(|p| p.phase != "templates"));
+        assert!(report.summary().ends_with(" Skipped templates."));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_review_changes_follows_each_answer() {
+        use super::{RenderedSet, Review, TemplateMapping, review_changes};
+        use crate::services::templating::RenderedTemplate;
+
+        let home = tempfile::TempDir::new().unwrap();
+        let staged = tempfile::TempDir::new().unwrap();
+        std::fs::write(staged.path().join("zshrc.old"), "old\n").unwrap();
+        std::os::unix::fs::symlink(staged.path().join("zshrc.old"), home.path().join(".zshrc"))
+            .unwrap();
+        let templates = [".zshrc", ".gitconfig", ".vimrc", ".tmux.conf"]
+            .into_iter()
+            .map(|name| {
+                let rendered_path = staged.path().join(name);
+                std::fs::write(&rendered_path, "new\n").unwrap();
+                RenderedTemplate {
+                    template: TemplateMapping {
+                        source: PathBuf::from(name),
+                        destination: PathBuf::from(name),
+                        mode: None,
+                        when: None,
+                        enabled: None,
+                        copy: false,
+                        overwrite: Default::default(),
+                    },
+                    rendered_path,
+                }
+            })
+            .collect();
+        let rendered = RenderedSet {
+            _tempdir: tempfile::TempDir::new().unwrap(),
+            templates,
+        };
+        let mut answers = vec![Review::Backup, Review::Skip, Review::Quit].into_iter();
+        let mut shown = Vec::new();
+
+        let (skipped, kept) = review_changes(home.path(), &rendered, &mut |change, details| {
+            shown.push(format!("{details}{change}"));
+            answers.next().unwrap()
+        })
+        .unwrap();
+
+        assert_eq!(shown.len(), 3, "changes after quitting are not shown");
+        assert!(shown[0].contains("-old\n+new\n"));
+        assert!(shown[1].contains("+new\n"));
+        assert_eq!(
+            skipped.into_iter().collect::<Vec<_>>(),
+            [".gitconfig", ".tmux.conf", ".vimrc"].map(PathBuf::from)
+        );
+        assert_eq!(kept.len(), 1);
+        assert_eq!(std::fs::read_to_string(&kept[0]).unwrap(), "old\n");
+    }
 }
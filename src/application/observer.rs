@@ -0,0 +1,128 @@
+//! Events of a run, for embedders showing progress without parsing stdout.
+
+use std::path::Path;
+
+use crate::config::TemplateMapping;
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::{CapturedOutput, CommandExecutor, Invocation};
+use crate::services::outcome::LinkOutcome;
+
+/// Receives the events of a run as they happen.
+///
+/// Every method does nothing by default, so observers only implement the
+/// events they care about.
+pub trait Observer {
+    /// A phase of the run starts, e.g. `Installing packages`.
+    fn on_phase_start(&self, _phase: &str) {}
+
+    /// The template of `destination` was rendered; templates whose inputs
+    /// did not change are not rendered again.
+    fn on_template_rendered(&self, _destination: &Path) {}
+
+    /// `template` links to the file `contents`, rendered by this run or
+    /// staged by an earlier one when its inputs did not change. Rendered
+    /// files are removed once the run ends.
+    fn on_template_contents(&self, _template: &TemplateMapping, _contents: &Path) {}
+
+    /// A link was created or replaced, or would be in a dry run.
+    fn on_link_created(&self, _link: &LinkOutcome) {}
+
+    /// A command is about to run, once per attempt when retried.
+    fn on_command(&self, _program: &str, _args: &[&str]) {}
+
+    /// The run failed with `error`, which it then returns.
+    fn on_error(&self, _error: &DotstrapError) {}
+}
+
+/// Observer ignoring every event.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopObserver;
+
+impl Observer for NoopObserver {}
+
+/// Executor reporting the commands it runs to an [`Observer`].
+pub(crate) struct ObservedExecutor<'a> {
+    inner: &'a dyn CommandExecutor,
+    observer: &'a dyn Observer,
+}
+
+impl<'a> ObservedExecutor<'a> {
+    pub(crate) fn new(inner: &'a dyn CommandExecutor, observer: &'a dyn Observer) -> Self {
+        ObservedExecutor { inner, observer }
+    }
+}
+
+impl CommandExecutor for ObservedExecutor<'_> {
+    fn run(&self, program: &str, args: &[&str]) -> Result<()> {
+        self.observer.on_command(program, args);
+        self.inner.run(program, args)
+    }
+
+    fn output(&self, program: &str, args: &[&str]) -> Result<String> {
+        self.observer.on_command(program, args);
+        self.inner.output(program, args)
+    }
+
+    fn is_available(&self, program: &str) -> bool {
+        self.inner.is_available(program)
+    }
+
+    fn run_capture(&self, invocation: &Invocation) -> Result<CapturedOutput> {
+        self.observer
+            .on_command(&invocation.program, &invocation.args());
+        self.inner.run_capture(invocation)
+    }
+
+    fn run_invocation(&self, invocation: &Invocation) -> Result<()> {
+        self.observer
+            .on_command(&invocation.program, &invocation.args());
+        self.inner.run_invocation(invocation)
+    }
+
+    fn run_concurrently(&self, commands: &[Invocation], jobs: usize) -> Vec<Result<()>> {
+        for invocation in commands {
+            self.observer
+                .on_command(&invocation.program, &invocation.args());
+        }
+        self.inner.run_concurrently(commands, jobs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct Commands(RefCell<Vec<String>>);
+
+    impl Observer for Commands {
+        fn on_command(&self, program: &str, args: &[&str]) {
+            self.0
+                .borrow_mut()
+                .push(format!("{program} {}", args.join(" ")));
+        }
+    }
+
+    #[test]
+    fn observed_executor_reports_commands_but_not_probes() {
+        let recording = RecordingCommandExecutor::default();
+        let observer = Commands::default();
+        let executor = ObservedExecutor::new(&recording, &observer);
+
+        executor.run("git", &["clone", "repo"]).unwrap();
+        assert!(executor.is_available("brew"));
+        executor
+            .run_concurrently(&[Invocation::new("brew", &["install", "fd"])], 2)
+            .into_iter()
+            .collect::<Result<()>>()
+            .unwrap();
+
+        assert_eq!(
+            observer.0.into_inner(),
+            vec!["git clone repo", "brew install fd"]
+        );
+        assert_eq!(recording.calls().len(), 3);
+    }
+}
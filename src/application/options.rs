@@ -0,0 +1,492 @@
+//! Options of a run, and the builder embedding dotstrap in other tools
+//! without going through the command line.
+
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::planfile::{Plan, make_plan};
+use super::{ExecutionReport, NoopObserver, Observer, run_with_options};
+use crate::cli::{Cli, Command, OutputFormat, RunMode};
+use crate::errors::Result;
+use crate::infrastructure::command::{CommandExecutor, SystemCommandExecutor};
+use crate::infrastructure::escalation::Escalation;
+use crate::infrastructure::secrets::{SecretProvider, SecretProviderRegistry};
+use crate::services::packages::ProviderRegistry;
+use crate::services::questions::Confirm;
+
+/// Settings of a run, the library counterpart of the command-line flags.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DotstrapOptions {
+    /// Git repository URL, archive URL, or local path of the dotfiles.
+    pub source: Option<String>,
+    /// Branch, tag, or commit of the source to apply.
+    pub git_ref: Option<String>,
+    /// Access token used to clone private HTTPS sources.
+    pub git_token: Option<String>,
+    /// Private key used to clone SSH sources.
+    pub ssh_key: Option<PathBuf>,
+    /// Keys one of which must have signed the source's HEAD commit.
+    pub trusted_keys: Vec<String>,
+    pub profile: Option<String>,
+    /// Directory of the source containing the manifest.
+    pub subdir: Option<PathBuf>,
+    /// Target home directory, the current user's when unset.
+    pub home: Option<PathBuf>,
+    /// Directory rendered templates are staged in, `~/.dotstrap/generated`
+    /// when unset.
+    pub staging_dir: Option<PathBuf>,
+    pub skip_brew: bool,
+    /// Leave templates alone, see `--skip-templates`.
+    pub skip_templates: bool,
+    pub install_brew: bool,
+    pub no_brew_update: bool,
+    pub sync_packages: bool,
+    /// Number of times failed commands are retried.
+    pub retries: u32,
+    /// Time after which commands run by the default executor are killed.
+    pub command_timeout: Option<Duration>,
+    /// Homebrew packages installed at once.
+    pub jobs: u16,
+    pub escalation: Escalation,
+    /// Kind of machine the run sets up, see `--mode`.
+    pub mode: RunMode,
+    pub dry_run: bool,
+    /// Wait for another run against the same home instead of failing.
+    pub wait: bool,
+    /// Run the template plugins the manifest declares.
+    pub allow_plugins: bool,
+    /// Run the commands of the `exec` template helpers.
+    pub allow_template_exec: bool,
+    /// Globs selecting the templates of the run, see `--only`; every entry
+    /// and step runs when empty.
+    pub only: Vec<String>,
+    /// Modules applied whatever the values say, see `--enable`.
+    pub enable: Vec<String>,
+    /// Modules left out with those requiring them, see `--disable`.
+    pub disable: Vec<String>,
+    /// Pull the latest commits of a cached clone, as `dotstrap update` does.
+    pub refresh: bool,
+    /// Ask the manifest questions and confirmations on the terminal; the
+    /// defaults of the questions are used otherwise.
+    pub interactive: bool,
+    /// How replacing files dotstrap does not manage and uninstalling
+    /// packages are confirmed.
+    pub confirm: Confirm,
+    /// Ask before changing each file, see `--interactive`.
+    pub review: bool,
+    /// Fail on warnings and on questions only a default would answer, see
+    /// `--strict`.
+    pub strict: bool,
+    /// Refuse the commands reaching the network, see `--offline`.
+    pub offline: bool,
+    /// Dotted paths of the values whose layers are logged, see
+    /// `--trace-value`.
+    pub trace_values: Vec<String>,
+    /// Show a spinner with the current phase on stderr.
+    pub progress: bool,
+}
+
+impl Default for DotstrapOptions {
+    fn default() -> Self {
+        DotstrapOptions {
+            source: None,
+            git_ref: None,
+            git_token: None,
+            ssh_key: None,
+            trusted_keys: Vec::new(),
+            profile: None,
+            subdir: None,
+            home: None,
+            staging_dir: None,
+            skip_brew: false,
+            skip_templates: false,
+            install_brew: false,
+            no_brew_update: false,
+            sync_packages: false,
+            retries: 2,
+            command_timeout: None,
+            jobs: 1,
+            escalation: Escalation::default(),
+            mode: RunMode::default(),
+            dry_run: false,
+            wait: false,
+            allow_plugins: false,
+            allow_template_exec: false,
+            only: Vec::new(),
+            enable: Vec::new(),
+            disable: Vec::new(),
+            refresh: false,
+            interactive: false,
+            confirm: Confirm::Ask,
+            review: false,
+            strict: false,
+            offline: false,
+            trace_values: Vec::new(),
+            progress: false,
+        }
+    }
+}
+
+impl From<Cli> for DotstrapOptions {
+    /// Options of the command line, asking questions and showing a spinner
+    /// when attached to a terminal.
+    fn from(cli: Cli) -> Self {
+        let source = cli.effective_source().map(str::to_string);
+        let command_timeout = cli.command_timeout();
+        DotstrapOptions {
+            source,
+            git_ref: cli.git_ref,
+            git_token: cli.git_token,
+            ssh_key: cli.ssh_key,
+            trusted_keys: cli.trusted_keys,
+            profile: cli.profile,
+            subdir: cli.subdir,
+            home: cli.home.into_iter().next(),
+            staging_dir: cli.staging_dir,
+            skip_brew: cli.skip_brew,
+            skip_templates: cli.skip_templates,
+            install_brew: cli.install_brew,
+            no_brew_update: cli.no_brew_update,
+            sync_packages: cli.sync_packages,
+            retries: cli.retries,
+            command_timeout,
+            jobs: cli.jobs,
+            escalation: cli.escalation,
+            mode: cli.mode,
+            dry_run: cli.dry_run,
+            wait: cli.wait,
+            allow_plugins: cli.allow_plugins,
+            allow_template_exec: cli.allow_template_exec,
+            only: cli.only,
+            enable: cli.enable,
+            disable: cli.disable,
+            refresh: matches!(
+                cli.command,
+                Some(Command::Update { .. } | Command::Sync { .. })
+            ),
+            interactive: io::stdin().is_terminal() && !cli.non_interactive,
+            confirm: if cli.yes {
+                Confirm::Yes
+            } else if cli.non_interactive {
+                Confirm::Refuse
+            } else {
+                Confirm::Ask
+            },
+            review: cli.interactive,
+            strict: cli.strict,
+            offline: cli.offline,
+            trace_values: cli.trace_values,
+            // Spinners would garble diagnostics and machine-readable reports.
+            progress: io::stdout().is_terminal()
+                && cli.output == OutputFormat::Text
+                && cli.verbose == 0
+                && !cli.quiet,
+        }
+    }
+}
+
+/// A configured run of dotstrap, built with [`Dotstrap::builder`].
+///
+/// ```no_run
+/// let report = dotstrap::Dotstrap::builder()
+///     .source("git@github.com:me/dotfiles.git")
+///     .profile("work")
+///     .dry_run(true)
+///     .build()
+///     .run()?;
+/// println!("{}", report.summary());
+/// # Ok::<(), dotstrap::DotstrapError>(())
+/// ```
+pub struct Dotstrap<'a> {
+    options: DotstrapOptions,
+    executor: Option<&'a dyn CommandExecutor>,
+    providers: ProviderRegistry,
+    secret_providers: SecretProviderRegistry,
+    observer: Option<&'a dyn Observer>,
+}
+
+impl<'a> Dotstrap<'a> {
+    /// Start configuring a run with the default options.
+    pub fn builder() -> DotstrapBuilder<'a> {
+        DotstrapBuilder {
+            dotstrap: Dotstrap {
+                options: DotstrapOptions::default(),
+                executor: None,
+                providers: ProviderRegistry::default(),
+                secret_providers: SecretProviderRegistry::default(),
+                observer: None,
+            },
+        }
+    }
+
+    /// Options the run was configured with.
+    pub fn options(&self) -> &DotstrapOptions {
+        &self.options
+    }
+
+    /// Apply the source, as `dotstrap apply` does.
+    pub fn run(&self) -> Result<ExecutionReport> {
+        let system = SystemCommandExecutor::with_timeout(self.options.command_timeout);
+        run_with_options(
+            self.options.clone(),
+            self.executor.unwrap_or(&system),
+            &self.providers,
+            &self.secret_providers,
+            self.observer.unwrap_or(&NoopObserver),
+        )
+    }
+
+    /// Plan the run without changing anything, as a dry run does, keeping
+    /// the rendered files and the commands for [`apply_plan`].
+    ///
+    /// [`apply_plan`]: super::planfile::apply_plan
+    pub fn plan(&self) -> Result<Plan> {
+        let system = SystemCommandExecutor::with_timeout(self.options.command_timeout);
+        make_plan(
+            self.options.clone(),
+            self.executor.unwrap_or(&system),
+            &self.providers,
+            &self.secret_providers,
+            self.observer.unwrap_or(&NoopObserver),
+        )
+    }
+}
+
+/// Builder of a [`Dotstrap`] run.
+pub struct DotstrapBuilder<'a> {
+    dotstrap: Dotstrap<'a>,
+}
+
+impl<'a> DotstrapBuilder<'a> {
+    /// Replace every option at once.
+    pub fn options(mut self, options: DotstrapOptions) -> Self {
+        self.dotstrap.options = options;
+        self
+    }
+
+    pub fn source(mut self, source: impl Into<String>) -> Self {
+        self.dotstrap.options.source = Some(source.into());
+        self
+    }
+
+    pub fn git_ref(mut self, git_ref: impl Into<String>) -> Self {
+        self.dotstrap.options.git_ref = Some(git_ref.into());
+        self
+    }
+
+    pub fn profile(mut self, profile: impl Into<String>) -> Self {
+        self.dotstrap.options.profile = Some(profile.into());
+        self
+    }
+
+    pub fn subdir(mut self, subdir: impl Into<PathBuf>) -> Self {
+        self.dotstrap.options.subdir = Some(subdir.into());
+        self
+    }
+
+    pub fn home(mut self, home: impl Into<PathBuf>) -> Self {
+        self.dotstrap.options.home = Some(home.into());
+        self
+    }
+
+    pub fn staging_dir(mut self, staging_dir: impl Into<PathBuf>) -> Self {
+        self.dotstrap.options.staging_dir = Some(staging_dir.into());
+        self
+    }
+
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dotstrap.options.dry_run = dry_run;
+        self
+    }
+
+    pub fn skip_brew(mut self, skip_brew: bool) -> Self {
+        self.dotstrap.options.skip_brew = skip_brew;
+        self
+    }
+
+    pub fn skip_templates(mut self, skip_templates: bool) -> Self {
+        self.dotstrap.options.skip_templates = skip_templates;
+        self
+    }
+
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.dotstrap.options.retries = retries;
+        self
+    }
+
+    pub fn command_timeout(mut self, timeout: Duration) -> Self {
+        self.dotstrap.options.command_timeout = Some(timeout);
+        self
+    }
+
+    pub fn jobs(mut self, jobs: u16) -> Self {
+        self.dotstrap.options.jobs = jobs.max(1);
+        self
+    }
+
+    pub fn allow_plugins(mut self, allow_plugins: bool) -> Self {
+        self.dotstrap.options.allow_plugins = allow_plugins;
+        self
+    }
+
+    pub fn allow_template_exec(mut self, allow_template_exec: bool) -> Self {
+        self.dotstrap.options.allow_template_exec = allow_template_exec;
+        self
+    }
+
+    /// Only apply the templates matching `pattern`, see `--only`.
+    pub fn only(mut self, pattern: impl Into<String>) -> Self {
+        self.dotstrap.options.only.push(pattern.into());
+        self
+    }
+
+    /// Apply the module `name` even when the values turn it off, see
+    /// `--enable`.
+    pub fn enable_module(mut self, name: impl Into<String>) -> Self {
+        self.dotstrap.options.enable.push(name.into());
+        self
+    }
+
+    /// Leave out the module `name` and those requiring it, see `--disable`.
+    pub fn disable_module(mut self, name: impl Into<String>) -> Self {
+        self.dotstrap.options.disable.push(name.into());
+        self
+    }
+
+    /// Confirm destructive operations as `confirm` says; they go ahead
+    /// unasked by default, since builder runs are not interactive.
+    pub fn confirm(mut self, confirm: Confirm) -> Self {
+        self.dotstrap.options.confirm = confirm;
+        self
+    }
+
+    pub fn escalation(mut self, escalation: Escalation) -> Self {
+        self.dotstrap.options.escalation = escalation;
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.dotstrap.options.strict = strict;
+        self
+    }
+
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.dotstrap.options.offline = offline;
+        self
+    }
+
+    /// Log the layers setting the value at the dotted path `key`, see
+    /// `--trace-value`.
+    pub fn trace_value(mut self, key: impl Into<String>) -> Self {
+        self.dotstrap.options.trace_values.push(key.into());
+        self
+    }
+
+    pub fn mode(mut self, mode: RunMode) -> Self {
+        self.dotstrap.options.mode = mode;
+        self
+    }
+
+    /// Run commands through `executor` rather than on the host.
+    pub fn executor(mut self, executor: &'a dyn CommandExecutor) -> Self {
+        self.dotstrap.executor = Some(executor);
+        self
+    }
+
+    /// Install packages with `providers` rather than the built-in ones.
+    pub fn providers(mut self, providers: ProviderRegistry) -> Self {
+        self.dotstrap.providers = providers;
+        self
+    }
+
+    /// Serve the secrets declared with `from: <name>` of `provider`,
+    /// replacing the built-in provider of that name, if any.
+    pub fn secret_provider(mut self, provider: impl SecretProvider + 'static) -> Self {
+        self.dotstrap.secret_providers.register(provider);
+        self
+    }
+
+    /// Serve secrets from `registry` rather than the built-in providers.
+    pub fn secret_providers(mut self, registry: SecretProviderRegistry) -> Self {
+        self.dotstrap.secret_providers = registry;
+        self
+    }
+
+    /// Report the events of the run to `observer`.
+    pub fn observer(mut self, observer: &'a dyn Observer) -> Self {
+        self.dotstrap.observer = Some(observer);
+        self
+    }
+
+    pub fn build(self) -> Dotstrap<'a> {
+        self.dotstrap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use tempfile::TempDir;
+
+    #[test]
+    fn builder_runs_without_a_cli() {
+        let home = TempDir::new().unwrap();
+        let executor = RecordingCommandExecutor::default();
+
+        let report = Dotstrap::builder()
+            .source("tests/empty-config")
+            .home(home.path())
+            .skip_brew(true)
+            .dry_run(true)
+            .executor(&executor)
+            .build()
+            .run()
+            .unwrap();
+
+        assert!(report.dry_run);
+        assert!(report.rendered.contains(&PathBuf::from(".zshrc")));
+        assert!(!home.path().join(".zshrc").exists());
+    }
+
+    #[test]
+    fn builder_requires_a_source() {
+        let error = Dotstrap::builder()
+            .executor(&RecordingCommandExecutor::default())
+            .build()
+            .run()
+            .unwrap_err();
+
+        assert!(matches!(error, crate::DotstrapError::SourceMissing));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn builder_confirms_replacing_unmanaged_files() {
+        let home = TempDir::new().unwrap();
+        std::fs::write(home.path().join(".zshrc"), "# mine\n").unwrap();
+        let executor = RecordingCommandExecutor::default();
+        let run = |confirm| {
+            Dotstrap::builder()
+                .source("tests/empty-config")
+                .home(home.path())
+                .skip_brew(true)
+                .confirm(confirm)
+                .executor(&executor)
+                .build()
+                .run()
+        };
+
+        let error = run(Confirm::Refuse).unwrap_err();
+        assert!(matches!(error, crate::DotstrapError::NotConfirmed(_)));
+        assert!(!home.path().join(".zshrc").is_symlink());
+
+        let report = run(Confirm::Yes).unwrap();
+        assert!(home.path().join(".zshrc").is_symlink());
+        assert_eq!(report.backups.len(), 1);
+
+        // Files linked by a previous run are replaced without asking.
+        assert!(run(Confirm::Refuse).is_ok());
+    }
+}
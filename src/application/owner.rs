@@ -0,0 +1,171 @@
+//! Finding the template a managed file is rendered from.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use super::{ModuleSelection, layer_manifests, resolve_bases, select_profile};
+use crate::config;
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::CommandExecutor;
+use crate::infrastructure::{paths, repository, state};
+
+/// Template producing a destination.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Owner {
+    pub destination: PathBuf,
+    /// Source or base layer declaring the template.
+    pub source: String,
+    /// Template path, relative to `source`.
+    pub template: PathBuf,
+    pub profile: Option<String>,
+    /// Staged file the destination links to, if it is a link.
+    pub link: Option<PathBuf>,
+    /// Whether the owner was recorded by a run, rather than found in the
+    /// manifest of a source that was not applied yet.
+    pub applied: bool,
+}
+
+/// Find the template producing `destination`, resolved from `home` when
+/// relative.
+///
+/// The templates linked by the last run are looked up first. Otherwise the
+/// manifest of `source`, if given, is searched for the given `profile`.
+pub fn owner(
+    home: &Path,
+    destination: &Path,
+    source: Option<&str>,
+    profile: Option<&str>,
+    executor: &dyn CommandExecutor,
+) -> Result<Owner> {
+    let destination = home.join(paths::expand_path(&destination.to_string_lossy()));
+    let unmanaged = || DotstrapError::Unmanaged(destination.clone());
+    let relative = destination
+        .strip_prefix(home)
+        .map_err(|_| unmanaged())?
+        .to_path_buf();
+    let link = fs::read_link(&destination).ok();
+
+    if let Some(origin) = state::load_state(home)?.templates.remove(&relative) {
+        return Ok(Owner {
+            destination,
+            source: origin.source,
+            template: origin.template,
+            profile: origin.profile,
+            link,
+            applied: true,
+        });
+    }
+    let Some(source) = source else {
+        return Err(unmanaged());
+    };
+    let (source, subdir) = repository::split_subdir(source);
+    let mut repo = repository::resolve_repository(source, None, executor)?;
+    if let Some(subdir) = subdir {
+        repo = repo.into_subdir(Path::new(subdir))?;
+    }
+    let mut manifest = config::load_manifest(repo.path())?;
+    let bases = resolve_bases(&repo, &mut manifest, &[], executor)?;
+    let mut layers: Vec<&Path> = bases.iter().map(|base| base.handle.path()).collect();
+    layers.push(repo.path());
+    let selection = ModuleSelection {
+        profile,
+        ..Default::default()
+    };
+    let mut manifest = layer_manifests(&layers, &bases, manifest, &selection)?;
+    select_profile(&mut manifest, profile, home)?;
+    let template = manifest
+        .templates
+        .into_iter()
+        .find(|template| template.destination == relative)
+        .ok_or_else(unmanaged)?;
+    let (source, template) = bases
+        .iter()
+        .find_map(|base| {
+            let relative = template.source.strip_prefix(base.handle.path()).ok()?;
+            Some((base.source.clone(), relative.to_path_buf()))
+        })
+        .unwrap_or_else(|| (repo.path().to_string_lossy().into_owned(), template.source));
+    Ok(Owner {
+        destination,
+        source,
+        template,
+        profile: profile.map(str::to_string),
+        link,
+        applied: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use crate::infrastructure::state::{State, TemplateOrigin};
+    use tempfile::TempDir;
+
+    #[test]
+    fn owner_reads_the_state_of_the_last_run() {
+        let home = TempDir::new().unwrap();
+        let mut recorded = State::default();
+        recorded.templates.insert(
+            PathBuf::from(".config/starship.toml"),
+            TemplateOrigin {
+                source: "git@github.com:me/dotfiles.git".into(),
+                template: PathBuf::from("templates/starship.toml"),
+                profile: Some("work".into()),
+                inputs: None,
+                output: None,
+                merged: false,
+                mode: None,
+                create_only: false,
+            },
+        );
+        state::save_state(home.path(), &recorded).unwrap();
+
+        let found = owner(
+            home.path(),
+            &home.path().join(".config/starship.toml"),
+            None,
+            None,
+            &RecordingCommandExecutor::default(),
+        )
+        .unwrap();
+
+        assert!(found.applied);
+        assert_eq!(found.template, PathBuf::from("templates/starship.toml"));
+        assert_eq!(found.profile.as_deref(), Some("work"));
+    }
+
+    #[test]
+    fn owner_falls_back_to_the_manifest() {
+        let home = TempDir::new().unwrap();
+
+        let found = owner(
+            home.path(),
+            Path::new(".zshrc"),
+            Some("tests/empty-config"),
+            None,
+            &RecordingCommandExecutor::default(),
+        )
+        .unwrap();
+
+        assert!(!found.applied);
+        assert_eq!(found.template, PathBuf::from("templates/zshrc.hbs"));
+        assert!(
+            found
+                .source
+                .ends_with(&format!("tests{}empty-config", std::path::MAIN_SEPARATOR))
+        );
+
+        let error = owner(
+            home.path(),
+            Path::new(".bashrc"),
+            Some("tests/empty-config"),
+            None,
+            &RecordingCommandExecutor::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(error, DotstrapError::Unmanaged(_)));
+    }
+}
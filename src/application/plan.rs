@@ -0,0 +1,323 @@
+//! Human readable plan of the changes a dry run would make.
+
+use std::fmt::Write;
+use std::path::Path;
+
+use super::ExecutionReport;
+use crate::services::outcome::{ItemStatus, LinkOutcome};
+
+/// Kind of change a plan step describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Link a file where none exists.
+    Create,
+    /// Replace an existing file or link.
+    Overwrite,
+    /// Run an external command.
+    Run,
+    /// Clone a repository.
+    Clone,
+    /// Change a system setting or crontab entry.
+    Set,
+    /// Leave a link as it is; listed for templates, and for the other links
+    /// with `--explain`.
+    Keep,
+    /// Leave out a template whose condition does not hold, listed by
+    /// `--explain` only.
+    Skip,
+}
+
+impl Action {
+    fn verb(self) -> &'static str {
+        match self {
+            Action::Create => "create",
+            Action::Overwrite => "overwrite",
+            Action::Run => "run",
+            Action::Clone => "clone",
+            Action::Set => "set",
+            Action::Keep => "unchanged",
+            Action::Skip => "skip",
+        }
+    }
+
+    /// ANSI color code of the verb.
+    fn color(self) -> &'static str {
+        match self {
+            Action::Create => "32",
+            Action::Overwrite => "33",
+            Action::Run => "36",
+            Action::Clone => "32",
+            Action::Set => "35",
+            Action::Keep | Action::Skip => "2",
+        }
+    }
+}
+
+/// A single change listed in the plan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub action: Action,
+    pub target: String,
+    /// Whether an existing regular file would be backed up first.
+    pub backup: bool,
+    /// Why the step is taken, e.g. `destination missing`.
+    pub reason: Option<String>,
+    /// Lines the step changes in the file, see [`ExecutionReport::diffs`].
+    pub diff: Option<String>,
+}
+
+/// Changes described by a dry-run `report`, with paths under `home` shown
+/// relative to `~`.
+pub fn steps(report: &ExecutionReport, home: Option<&Path>) -> Vec<Step> {
+    collect_steps(report, home, false)
+}
+
+/// [`steps`], along with the other links left as they are and the templates
+/// skipped by their condition, for `--explain`.
+pub fn explained_steps(report: &ExecutionReport, home: Option<&Path>) -> Vec<Step> {
+    collect_steps(report, home, true)
+}
+
+fn collect_steps(report: &ExecutionReport, home: Option<&Path>, explain: bool) -> Vec<Step> {
+    let step = |action, target: String| Step {
+        reason: report.reasons.get(&target).cloned(),
+        action,
+        target,
+        backup: false,
+        diff: None,
+    };
+    let mut steps: Vec<Step> = report
+        .linked
+        .iter()
+        .filter_map(|outcome| link_step(report, outcome, home, true, explain))
+        .collect();
+    steps.extend(
+        report
+            .binaries
+            .iter()
+            .chain(&report.downloads)
+            .filter_map(|outcome| link_step(report, outcome, home, explain, explain)),
+    );
+    steps.extend(
+        report
+            .package_commands
+            .iter()
+            .chain(&report.hooks)
+            .map(|command| step(Action::Run, command.clone())),
+    );
+    steps.extend(report.repos.iter().map(|path| Step {
+        reason: report.reasons.get(&path.display().to_string()).cloned(),
+        ..step(Action::Clone, display_path(path, home))
+    }));
+    steps.extend(
+        report
+            .defaults
+            .iter()
+            .chain(&report.gnome_settings)
+            .chain(&report.cron)
+            .map(|change| step(Action::Set, change.clone())),
+    );
+    steps
+}
+
+/// Step of `outcome`, if any: links left as they are only have one with
+/// `keep`, and skipped templates with `skip`.
+fn link_step(
+    report: &ExecutionReport,
+    outcome: &LinkOutcome,
+    home: Option<&Path>,
+    keep: bool,
+    skip: bool,
+) -> Option<Step> {
+    let action = match outcome.status {
+        ItemStatus::Created => Action::Create,
+        ItemStatus::Updated => Action::Overwrite,
+        ItemStatus::Unchanged if keep => Action::Keep,
+        ItemStatus::Skipped if skip => Action::Skip,
+        ItemStatus::Unchanged | ItemStatus::Skipped => return None,
+    };
+    // Only regular files are backed up; stale links are simply replaced.
+    let backup = action == Action::Overwrite && outcome.path.exists() && !outcome.path.is_symlink();
+    Some(Step {
+        action,
+        target: display_path(&outcome.path, home),
+        backup,
+        reason: outcome.reason.clone(),
+        diff: report
+            .diffs
+            .get(&outcome.path)
+            .filter(|diff| !diff.is_empty())
+            .cloned(),
+    })
+}
+
+fn display_path(path: &Path, home: Option<&Path>) -> String {
+    match home.and_then(|home| path.strip_prefix(home).ok()) {
+        Some(relative) => format!("~/{}", relative.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// Render `steps` one per line, each followed by its diff indented, then a
+/// summary line, with the reason of each step when `explain` is set.
+pub fn format(steps: &[Step], templates: usize, color: bool, explain: bool) -> String {
+    let mut out = String::new();
+    for step in steps {
+        let verb = step.action.verb();
+        if color {
+            let _ = write!(
+                out,
+                "\x1b[{}m{verb}\x1b[0m {}",
+                step.action.color(),
+                step.target
+            );
+        } else {
+            let _ = write!(out, "{verb} {}", step.target);
+        }
+        let notes: Vec<&str> = [
+            step.backup.then_some("backup"),
+            step.reason.as_deref().filter(|_| explain),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if !notes.is_empty() {
+            let _ = write!(out, " ({})", notes.join("; "));
+        }
+        out.push('\n');
+        for line in step.diff.iter().flat_map(|diff| diff.lines()) {
+            let code = match line.chars().next() {
+                Some('-') => "31",
+                Some('+') => "32",
+                _ => "",
+            };
+            if color && !code.is_empty() {
+                let _ = writeln!(out, "    \x1b[{code}m{line}\x1b[0m");
+            } else {
+                let _ = writeln!(out, "    {line}");
+            }
+        }
+    }
+    let count = |action| steps.iter().filter(|step| step.action == action).count();
+    let _ = writeln!(
+        out,
+        "Dry run complete: {templates} templates evaluated. {} to create, {} to overwrite, {} command(s) to run, {} other change(s).",
+        count(Action::Create),
+        count(Action::Overwrite),
+        count(Action::Run),
+        count(Action::Clone) + count(Action::Set),
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    #[test]
+    fn steps_describe_links_commands_and_backups() {
+        let home = TempDir::new().unwrap();
+        let existing = home.path().join(".gitconfig");
+        fs::write(&existing, "[user]").unwrap();
+        let report = ExecutionReport {
+            linked: vec![
+                LinkOutcome::new(home.path().join(".zshrc"), ItemStatus::Created),
+                LinkOutcome::new(existing, ItemStatus::Updated),
+                LinkOutcome::new(home.path().join(".vimrc"), ItemStatus::Unchanged),
+            ],
+            package_commands: vec!["brew install fzf".into()],
+            repos: vec![PathBuf::from("/srv/code")],
+            dry_run: true,
+            ..ExecutionReport::default()
+        };
+
+        let plan = format(&steps(&report, Some(home.path())), 3, false, false);
+
+        assert_eq!(
+            plan,
+            "create ~/.zshrc\n\
+             overwrite ~/.gitconfig (backup)\n\
+             unchanged ~/.vimrc\n\
+             run brew install fzf\n\
+             clone /srv/code\n\
+             Dry run complete: 3 templates evaluated. 1 to create, 1 to overwrite, 1 command(s) to run, 1 other change(s).\n"
+        );
+    }
+
+    #[test]
+    fn steps_show_the_diff_of_changed_templates() {
+        let home = TempDir::new().unwrap();
+        let zshrc = home.path().join(".zshrc");
+        let report = ExecutionReport {
+            linked: vec![LinkOutcome::new(zshrc.clone(), ItemStatus::Updated)],
+            diffs: [(
+                zshrc,
+                " export EDITOR=vim\n-alias ll='ls -l'\n+alias ll='ls -la'\n".into(),
+            )]
+            .into(),
+            dry_run: true,
+            ..ExecutionReport::default()
+        };
+
+        let plan = format(&steps(&report, Some(home.path())), 1, false, false);
+
+        assert_eq!(
+            plan,
+            "overwrite ~/.zshrc\n     export EDITOR=vim\n    -alias ll='ls -l'\n    +alias ll='ls -la'\n\
+             Dry run complete: 1 templates evaluated. 0 to create, 1 to overwrite, 0 command(s) to run, 0 other change(s).\n"
+        );
+    }
+
+    #[test]
+    fn format_colors_verbs() {
+        let steps = [Step {
+            action: Action::Create,
+            target: "~/.zshrc".into(),
+            backup: false,
+            reason: None,
+            diff: None,
+        }];
+
+        let plan = format(&steps, 1, true, false);
+
+        assert!(plan.starts_with("\x1b[32mcreate\x1b[0m ~/.zshrc\n"));
+    }
+
+    #[test]
+    fn explained_steps_give_reasons_and_list_kept_and_skipped_links() {
+        let home = TempDir::new().unwrap();
+        let existing = home.path().join(".gitconfig");
+        fs::write(&existing, "[user]").unwrap();
+        let report = ExecutionReport {
+            linked: vec![
+                LinkOutcome::new(existing, ItemStatus::Updated).because("template changed"),
+                LinkOutcome::new(home.path().join(".vimrc"), ItemStatus::Unchanged)
+                    .because("already linked"),
+                LinkOutcome::new(home.path().join(".yabairc"), ItemStatus::Skipped)
+                    .because("platform mismatch: linux, not macos"),
+            ],
+            package_commands: vec!["brew install fzf".into()],
+            reasons: [(
+                "brew install fzf".into(),
+                "declared in the brew section".into(),
+            )]
+            .into(),
+            dry_run: true,
+            ..ExecutionReport::default()
+        };
+
+        let plan = format(&explained_steps(&report, Some(home.path())), 2, false, true);
+
+        assert_eq!(
+            plan,
+            "overwrite ~/.gitconfig (backup; template changed)\n\
+             unchanged ~/.vimrc (already linked)\n\
+             skip ~/.yabairc (platform mismatch: linux, not macos)\n\
+             run brew install fzf (declared in the brew section)\n\
+             Dry run complete: 2 templates evaluated. 0 to create, 1 to overwrite, 1 command(s) to run, 0 other change(s).\n"
+        );
+    }
+}
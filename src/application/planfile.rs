@@ -0,0 +1,637 @@
+//! Machine-readable plans saved by `dotstrap plan`, for review, and applied
+//! exactly as saved by `dotstrap apply --plan`.
+
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+
+use super::{DotstrapOptions, ExecutionReport, Observer, run_with_options};
+use crate::config::TemplateMapping;
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::CommandExecutor;
+use crate::infrastructure::download::sha256_file;
+use crate::infrastructure::encoding;
+use crate::infrastructure::lock::RunLock;
+use crate::infrastructure::paths;
+use crate::infrastructure::secrets::SecretProviderRegistry;
+use crate::services::hooks;
+use crate::services::linker;
+use crate::services::outcome::{ItemStatus, LinkOutcome};
+use crate::services::packages::ProviderRegistry;
+use crate::services::templating::{RenderedSet, RenderedTemplate};
+
+/// Format of the plans this version writes and applies.
+pub const PLAN_VERSION: u32 = 1;
+
+/// Changes a run would make, as planned by [`Dotstrap::plan`].
+///
+/// [`Dotstrap::plan`]: super::Dotstrap::plan
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Plan {
+    pub version: u32,
+    /// Source the plan was made from.
+    pub source: Option<String>,
+    /// Commit of the source, when it is a git repository.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Home directory the plan was made for.
+    pub home: PathBuf,
+    /// Rendered templates, in the order of the manifest.
+    pub files: Vec<PlannedFile>,
+    /// Commands to run through the shell, in order.
+    pub commands: Vec<PlannedCommand>,
+    /// Other changes of the run, which applying the plan does not make.
+    #[serde(default)]
+    pub not_planned: Vec<String>,
+}
+
+/// Rendered template and the link to it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlannedFile {
+    /// Destination, relative to the home directory unless absolute.
+    pub destination: PathBuf,
+    pub action: FileAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// SHA-256 digest of the rendered contents.
+    pub sha256: String,
+    /// SHA-256 digest of the file at the destination when the plan was made,
+    /// following links; `None` when there was none.
+    pub previous_sha256: Option<String>,
+    pub contents: Contents,
+    /// Why the destination has its action, e.g. `template changed`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// What applying the plan does to a destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileAction {
+    Create,
+    Overwrite,
+    Unchanged,
+}
+
+/// Rendered contents, as text unless they are not UTF-8.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Contents {
+    Text(String),
+    Base64(String),
+}
+
+impl Contents {
+    fn new(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(text) => Contents::Text(text),
+            Err(err) => Contents::Base64(encoding::base64_encode(err.as_bytes())),
+        }
+    }
+
+    fn bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            Contents::Text(text) => Some(text.clone().into_bytes()),
+            Contents::Base64(encoded) => encoding::base64_decode(encoded),
+        }
+    }
+}
+
+/// Command line of the plan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlannedCommand {
+    pub phase: CommandPhase,
+    pub command: String,
+    /// Why the command is run, e.g. `declared in the brew section`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Step of the run a command belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommandPhase {
+    /// Pre-apply hook, run before the files are linked.
+    PreApply,
+    /// Package manager command, run after the files are linked.
+    Packages,
+    /// Post-apply hook, run last.
+    PostApply,
+}
+
+/// Observer keeping the contents of every template, forwarding the events
+/// to the observer of the run.
+struct ContentsRecorder<'a> {
+    observer: &'a dyn Observer,
+    files: RefCell<Vec<(TemplateMapping, std::io::Result<Vec<u8>>)>>,
+}
+
+impl Observer for ContentsRecorder<'_> {
+    fn on_phase_start(&self, phase: &str) {
+        self.observer.on_phase_start(phase);
+    }
+
+    fn on_template_rendered(&self, destination: &Path) {
+        self.observer.on_template_rendered(destination);
+    }
+
+    fn on_template_contents(&self, template: &TemplateMapping, contents: &Path) {
+        self.observer.on_template_contents(template, contents);
+        self.files
+            .borrow_mut()
+            .push((template.clone(), std::fs::read(contents)));
+    }
+
+    fn on_link_created(&self, link: &LinkOutcome) {
+        self.observer.on_link_created(link);
+    }
+
+    fn on_command(&self, program: &str, args: &[&str]) {
+        self.observer.on_command(program, args);
+    }
+}
+
+/// Plan the run `options` describes with a dry run, recording the contents
+/// of the files it would link and the commands it would run.
+pub(crate) fn make_plan(
+    options: DotstrapOptions,
+    executor: &dyn CommandExecutor,
+    providers: &ProviderRegistry,
+    secret_providers: &SecretProviderRegistry,
+    observer: &dyn Observer,
+) -> Result<Plan> {
+    let home = match &options.home {
+        Some(path) => paths::expand_path(&path.to_string_lossy()),
+        None => home::home_dir().ok_or(DotstrapError::HomeNotFound)?,
+    };
+    let recorder = ContentsRecorder {
+        observer,
+        files: RefCell::new(Vec::new()),
+    };
+    let report = run_with_options(
+        DotstrapOptions {
+            dry_run: true,
+            ..options.clone()
+        },
+        executor,
+        providers,
+        secret_providers,
+        &recorder,
+    )?;
+
+    let mut files = Vec::new();
+    for (template, contents) in recorder.files.into_inner() {
+        let path = home.join(&template.destination);
+        let link = report.linked.iter().find(|link| link.path == path);
+        let action = match link.map(|link| link.status) {
+            Some(ItemStatus::Created) => FileAction::Create,
+            Some(ItemStatus::Updated) => FileAction::Overwrite,
+            _ => FileAction::Unchanged,
+        };
+        let contents = contents?;
+        files.push(PlannedFile {
+            destination: template.destination,
+            action,
+            mode: template.mode,
+            sha256: digest(&contents),
+            previous_sha256: current_digest(&path)?,
+            contents: Contents::new(contents),
+            reason: link.and_then(|link| link.reason.clone()),
+        });
+    }
+    let position = |file: &PlannedFile| {
+        report
+            .rendered
+            .iter()
+            .position(|destination| *destination == file.destination)
+    };
+    files.sort_by_key(position);
+    let (pre_apply, post_apply) = report.hooks.split_at(report.pre_apply_hooks);
+    let reasons = &report.reasons;
+    let commands = [
+        (CommandPhase::PreApply, pre_apply),
+        (CommandPhase::Packages, &report.package_commands[..]),
+        (CommandPhase::PostApply, post_apply),
+    ]
+    .into_iter()
+    .flat_map(|(phase, commands)| {
+        commands.iter().map(move |command| PlannedCommand {
+            phase,
+            command: command.clone(),
+            reason: reasons.get(command).cloned(),
+        })
+    })
+    .collect();
+    Ok(Plan {
+        version: PLAN_VERSION,
+        source: options.source,
+        revision: report.revision.clone(),
+        profile: options.profile,
+        home,
+        files,
+        commands,
+        not_planned: not_planned(&report),
+    })
+}
+
+/// Changes of a dry-run `report` other than templates and commands.
+fn not_planned(report: &ExecutionReport) -> Vec<String> {
+    let mut changes: Vec<String> = report
+        .binaries
+        .iter()
+        .chain(&report.downloads)
+        .filter(|link| matches!(link.status, ItemStatus::Created | ItemStatus::Updated))
+        .map(|link| format!("link {}", link.path.display()))
+        .collect();
+    changes.extend(
+        report
+            .repos
+            .iter()
+            .map(|repo| format!("clone {}", repo.display())),
+    );
+    changes.extend(
+        report
+            .defaults
+            .iter()
+            .chain(&report.gnome_settings)
+            .chain(&report.cron)
+            .map(|change| format!("set {change}")),
+    );
+    changes
+}
+
+fn digest(contents: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(contents))
+}
+
+/// Digest of the file at `path`, following links, or `None` when there is
+/// none.
+fn current_digest(path: &Path) -> Result<Option<String>> {
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => sha256_file(path).map(Some),
+        Ok(_) => Ok(None),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Read the plan saved at `path`.
+pub fn load_plan(path: &Path) -> Result<Plan> {
+    let invalid = |reason: String| DotstrapError::InvalidPlan {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let plan: Plan =
+        serde_json::from_slice(&std::fs::read(path)?).map_err(|err| invalid(err.to_string()))?;
+    if plan.version != PLAN_VERSION {
+        return Err(invalid(format!(
+            "version {} is not supported, expected {PLAN_VERSION}",
+            plan.version
+        )));
+    }
+    Ok(plan)
+}
+
+/// Save `plan` at `path`, as pretty-printed JSON.
+pub fn save_plan(plan: &Plan, path: &Path) -> Result<()> {
+    let json = serde_json::to_string_pretty(plan)
+        .map_err(|err| DotstrapError::Io(std::io::Error::other(err)))?;
+    std::fs::write(path, json + "\n")?;
+    Ok(())
+}
+
+/// Apply `plan`, loaded from `origin`, to `home`: run its pre-apply
+/// commands, link its files, staged under `stage_root`, then run the other
+/// commands, all through the shell.
+///
+/// Nothing changes when the contents of a file do not match their digest,
+/// or when a destination changed since the plan was made. Changes the plan
+/// does not make are reported as warnings.
+pub fn apply_plan(
+    plan: &Plan,
+    origin: &Path,
+    home: &Path,
+    stage_root: &Path,
+    dry_run: bool,
+    wait: bool,
+    executor: &dyn CommandExecutor,
+) -> Result<ExecutionReport> {
+    let tempdir = TempDir::new()?;
+    let mut templates = Vec::with_capacity(plan.files.len());
+    for (index, file) in plan.files.iter().enumerate() {
+        let contents = file
+            .contents
+            .bytes()
+            .filter(|contents| digest(contents) == file.sha256)
+            .ok_or_else(|| DotstrapError::InvalidPlan {
+                path: origin.to_path_buf(),
+                reason: format!(
+                    "the contents of {} do not match their digest",
+                    file.destination.display()
+                ),
+            })?;
+        let rendered_path = tempdir.path().join(format!("planned_{index}"));
+        std::fs::write(&rendered_path, contents)?;
+        templates.push(RenderedTemplate {
+            template: TemplateMapping {
+                source: file.destination.clone(),
+                destination: file.destination.clone(),
+                mode: file.mode,
+                when: None,
+                enabled: None,
+                copy: false,
+                overwrite: Default::default(),
+            },
+            rendered_path,
+        });
+    }
+    let _lock = if dry_run {
+        None
+    } else {
+        Some(RunLock::acquire(home, wait)?)
+    };
+    for file in &plan.files {
+        let path = home.join(&file.destination);
+        if current_digest(&path)? != file.previous_sha256 {
+            return Err(DotstrapError::StalePlan(path));
+        }
+    }
+
+    let run = |phase: CommandPhase| -> Result<Vec<String>> {
+        let mut commands = Vec::new();
+        for planned in plan
+            .commands
+            .iter()
+            .filter(|planned| planned.phase == phase)
+        {
+            if !dry_run {
+                let (shell, flag) = hooks::shell();
+                executor
+                    .run(shell, &[flag, &planned.command])
+                    .map_err(|source| match phase {
+                        CommandPhase::PreApply => DotstrapError::Hook {
+                            phase: "pre-apply",
+                            command: planned.command.clone(),
+                            source: Box::new(source),
+                        },
+                        CommandPhase::Packages => DotstrapError::PackageInstall(Box::new(source)),
+                        CommandPhase::PostApply => DotstrapError::Hook {
+                            phase: "post-apply",
+                            command: planned.command.clone(),
+                            source: Box::new(source),
+                        },
+                    })?;
+            }
+            commands.push(planned.command.clone());
+        }
+        Ok(commands)
+    };
+    let mut hooks = run(CommandPhase::PreApply)?;
+    let pre_apply_hooks = hooks.len();
+    let rendered = RenderedSet {
+        _tempdir: tempdir,
+        templates,
+    };
+    let linked = linker::link_templates(home, stage_root, &rendered, dry_run)?;
+    let partial = |err: DotstrapError| {
+        if dry_run {
+            err
+        } else {
+            DotstrapError::Partial(Box::new(err))
+        }
+    };
+    let package_commands = run(CommandPhase::Packages).map_err(partial)?;
+    hooks.extend(run(CommandPhase::PostApply).map_err(partial)?);
+
+    Ok(ExecutionReport {
+        revision: plan.revision.clone(),
+        rendered: plan
+            .files
+            .iter()
+            .map(|file| file.destination.clone())
+            .collect(),
+        backups: linked
+            .iter()
+            .filter_map(|link| link.backup.clone())
+            .collect(),
+        linked,
+        hooks,
+        pre_apply_hooks,
+        package_commands,
+        warnings: plan
+            .not_planned
+            .iter()
+            .map(|change| format!("not applied from the plan: {change}"))
+            .collect(),
+        dry_run,
+        ..ExecutionReport::default()
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::application::Dotstrap;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use crate::services::questions::Confirm;
+
+    fn source() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join("manifest.yaml"),
+            "version: 2\n\
+             templates:\n\
+             \x20 - source: rc.hbs\n    destination: .rc\n\
+             \x20 - source: logo.bin\n    destination: .logo\n    copy: true\n    mode: 0o600\n\
+             hooks:\n  pre_apply:\n    - echo before {{name}}\n  post_apply:\n    - echo after\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("values.yaml"), "name: me\n").unwrap();
+        std::fs::write(dir.path().join("rc.hbs"), "name={{name}}\n").unwrap();
+        std::fs::write(dir.path().join("logo.bin"), b"\x89PNG\xff").unwrap();
+        dir
+    }
+
+    fn plan(source: &Path, home: &Path, executor: &RecordingCommandExecutor) -> Plan {
+        Dotstrap::builder()
+            .source(source.to_string_lossy())
+            .home(home)
+            .confirm(Confirm::Yes)
+            .executor(executor)
+            .build()
+            .plan()
+            .unwrap()
+    }
+
+    #[test]
+    fn plan_records_files_and_commands_without_changing_anything() {
+        let source = source();
+        let home = TempDir::new().unwrap();
+        std::fs::write(home.path().join(".rc"), "old\n").unwrap();
+        let executor = RecordingCommandExecutor::default();
+
+        let plan = plan(source.path(), home.path(), &executor);
+
+        assert_eq!(plan.version, PLAN_VERSION);
+        assert_eq!(plan.home, home.path());
+        assert_eq!(
+            plan.files[0],
+            PlannedFile {
+                destination: PathBuf::from(".rc"),
+                action: FileAction::Overwrite,
+                mode: None,
+                sha256: digest(b"name=me\n"),
+                previous_sha256: Some(digest(b"old\n")),
+                contents: Contents::Text("name=me\n".into()),
+                reason: Some("destination is a different file".into()),
+            }
+        );
+        assert_eq!(plan.files[1].action, FileAction::Create);
+        assert_eq!(plan.files[1].mode, Some(0o600));
+        assert_eq!(plan.files[1].previous_sha256, None);
+        assert_eq!(
+            plan.files[1].contents,
+            Contents::Base64(encoding::base64_encode(b"\x89PNG\xff"))
+        );
+        assert_eq!(
+            plan.commands,
+            [
+                PlannedCommand {
+                    phase: CommandPhase::PreApply,
+                    command: "echo before me".into(),
+                    reason: Some("pre-apply hook".into()),
+                },
+                PlannedCommand {
+                    phase: CommandPhase::PostApply,
+                    command: "echo after".into(),
+                    reason: Some("post-apply hook".into()),
+                },
+            ]
+        );
+        assert!(executor.calls().iter().all(|(program, _)| program != "sh"));
+        assert_eq!(
+            std::fs::read_to_string(home.path().join(".rc")).unwrap(),
+            "old\n"
+        );
+    }
+
+    #[test]
+    fn apply_plan_links_the_planned_files_and_runs_the_commands() {
+        let source = source();
+        let home = TempDir::new().unwrap();
+        let plan = plan(
+            source.path(),
+            home.path(),
+            &RecordingCommandExecutor::default(),
+        );
+        let saved = home.path().join("plan.json");
+        save_plan(&plan, &saved).unwrap();
+        // The plan stands on its own once made.
+        drop(source);
+
+        let loaded = load_plan(&saved).unwrap();
+        assert_eq!(loaded, plan);
+        let executor = RecordingCommandExecutor::default();
+        let stage_root = linker::default_stage_root(home.path());
+        let report = apply_plan(
+            &loaded,
+            &saved,
+            home.path(),
+            &stage_root,
+            false,
+            false,
+            &executor,
+        )
+        .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(home.path().join(".rc")).unwrap(),
+            "name=me\n"
+        );
+        assert_eq!(
+            std::fs::read(home.path().join(".logo")).unwrap(),
+            b"\x89PNG\xff"
+        );
+        assert_eq!(report.hooks, ["echo before me", "echo after"]);
+        assert_eq!(report.pre_apply_hooks, 1);
+        assert_eq!(
+            executor.calls(),
+            [
+                (
+                    "sh".to_string(),
+                    vec!["-c".to_string(), "echo before me".to_string()]
+                ),
+                (
+                    "sh".to_string(),
+                    vec!["-c".to_string(), "echo after".to_string()]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_plan_refuses_stale_or_tampered_plans() {
+        let source = source();
+        let home = TempDir::new().unwrap();
+        let plan = plan(
+            source.path(),
+            home.path(),
+            &RecordingCommandExecutor::default(),
+        );
+        let executor = RecordingCommandExecutor::default();
+        let stage_root = linker::default_stage_root(home.path());
+        let origin = Path::new("plan.json");
+        let apply = |plan: &Plan| {
+            apply_plan(
+                plan,
+                origin,
+                home.path(),
+                &stage_root,
+                false,
+                false,
+                &executor,
+            )
+        };
+
+        let mut tampered = plan.clone();
+        tampered.files[0].contents = Contents::Text("name=evil\n".into());
+        assert!(matches!(
+            apply(&tampered),
+            Err(DotstrapError::InvalidPlan { .. })
+        ));
+
+        std::fs::write(home.path().join(".rc"), "edited\n").unwrap();
+        assert!(
+            matches!(apply(&plan), Err(DotstrapError::StalePlan(path)) if path == home.path().join(".rc"))
+        );
+        assert_eq!(
+            std::fs::read_to_string(home.path().join(".rc")).unwrap(),
+            "edited\n"
+        );
+        assert!(executor.calls().is_empty());
+    }
+
+    #[test]
+    fn load_plan_rejects_other_versions() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("plan.json");
+        std::fs::write(
+            &path,
+            r#"{"version": 2, "source": null, "home": "/h", "files": [], "commands": []}"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            load_plan(&path),
+            Err(DotstrapError::InvalidPlan { reason, .. }) if reason.contains("version 2")
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+//! Writing edits made to a managed file back into its template.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use super::watch::render_local;
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::CommandExecutor;
+use crate::infrastructure::filesystem::OsFileSystem;
+use crate::infrastructure::{paths, repository};
+use crate::services::templating::Permissions;
+use crate::services::{linker, readd};
+
+/// Outcome of re-adding a managed file.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct ReAdd {
+    /// Managed file whose edits were captured.
+    pub destination: PathBuf,
+    /// Template the edits were written to.
+    pub template: PathBuf,
+    /// Whether the file differed from the template output.
+    pub changed: bool,
+    pub dry_run: bool,
+}
+
+/// Carry the edits made to `destination`, a file managed in `home`, back
+/// into its template in the local checkout `source`.
+///
+/// `destination` is resolved from `home` when relative. The template is
+/// rendered for `profile` as a run would, and the differences between that
+/// output and the file are applied to the template source, which is left
+/// untouched in dry-run mode. Templates of sources declaring plugins cannot
+/// be re-added, since plugins only run when a run allows them.
+pub fn re_add(
+    source: &str,
+    subdir: Option<&Path>,
+    home: &Path,
+    destination: &Path,
+    profile: Option<&str>,
+    dry_run: bool,
+    executor: &dyn CommandExecutor,
+) -> Result<ReAdd> {
+    let destination = home.join(paths::expand_path(&destination.to_string_lossy()));
+    let failed = |reason: &str| DotstrapError::ReAdd {
+        path: destination.clone(),
+        reason: reason.to_string(),
+    };
+    let relative = destination
+        .strip_prefix(home)
+        .map_err(|_| failed("the file is outside the home directory"))?;
+
+    let mut repo = repository::resolve_repository(source, None, executor)?;
+    if repo.is_cloned() {
+        return Err(failed("the source is not a local checkout"));
+    }
+    if let Some(subdir) = subdir {
+        repo = repo.into_subdir(subdir)?;
+    }
+    let rendered = render_local(&repo, home, profile, Permissions::default(), executor)?;
+    let item = rendered
+        .templates
+        .iter()
+        .find(|item| item.template.destination == relative)
+        .ok_or_else(|| failed("the file is not managed by the manifest"))?;
+    let template = repo.path().join(&item.template.source);
+    if !template.starts_with(repo.path()) {
+        return Err(failed("its template comes from a base layer"));
+    }
+
+    // Copied files have no expressions to preserve.
+    if item.template.copy {
+        let changed = !linker::same_contents(&OsFileSystem, &destination, &template)?;
+        if changed && !dry_run {
+            fs::copy(&destination, &template)?;
+        }
+        return Ok(ReAdd {
+            destination,
+            template,
+            changed,
+            dry_run,
+        });
+    }
+    let edited = fs::read_to_string(&destination)?;
+    let output = fs::read_to_string(&item.rendered_path)?;
+    let changed = edited != output;
+    if changed {
+        let merged = readd::carry_edits(&fs::read_to_string(&template)?, &output, &edited)
+            .map_err(|reason| failed(&reason))?;
+        if !dry_run {
+            fs::write(&template, merged)?;
+        }
+    }
+    Ok(ReAdd {
+        destination,
+        template,
+        changed,
+        dry_run,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use tempfile::TempDir;
+
+    fn source() -> TempDir {
+        let source = TempDir::new().unwrap();
+        fs::write(
+            source.path().join("manifest.yaml"),
+            "version: 2\ntemplates:\n  - source: gitconfig.hbs\n    destination: .gitconfig\n",
+        )
+        .unwrap();
+        fs::write(source.path().join("values.yaml"), "name: Me\n").unwrap();
+        fs::write(
+            source.path().join("gitconfig.hbs"),
+            "[user]\n  name = {{name}}\n[pull]\n  rebase = false\n",
+        )
+        .unwrap();
+        source
+    }
+
+    #[test]
+    fn re_add_writes_edits_back_to_the_template() {
+        let source = source();
+        let home = TempDir::new().unwrap();
+        fs::write(
+            home.path().join(".gitconfig"),
+            "[user]\n  name = Me\n[pull]\n  rebase = true\n",
+        )
+        .unwrap();
+
+        let report = re_add(
+            &source.path().to_string_lossy(),
+            None,
+            home.path(),
+            Path::new(".gitconfig"),
+            None,
+            false,
+            &RecordingCommandExecutor::default(),
+        )
+        .unwrap();
+
+        assert!(report.changed);
+        assert_eq!(
+            fs::read_to_string(source.path().join("gitconfig.hbs")).unwrap(),
+            "[user]\n  name = {{name}}\n[pull]\n  rebase = true\n"
+        );
+    }
+
+    #[test]
+    fn re_add_rejects_unmanaged_files() {
+        let source = source();
+        let home = TempDir::new().unwrap();
+        fs::write(home.path().join(".zshrc"), "").unwrap();
+
+        let error = re_add(
+            &source.path().to_string_lossy(),
+            None,
+            home.path(),
+            Path::new(".zshrc"),
+            None,
+            true,
+            &RecordingCommandExecutor::default(),
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("not managed"), "{error}");
+    }
+}
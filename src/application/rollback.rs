@@ -0,0 +1,104 @@
+//! Reverting the links changed by the last applied run.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::history::{self, HistoryEntry};
+use crate::infrastructure::lock::RunLock;
+use crate::services::rollback;
+
+/// Summary of a rollback.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RollbackReport {
+    /// Identifier of the run that was rolled back.
+    pub run: u64,
+    /// Paths restored, or planned in dry-run mode.
+    pub restored: Vec<String>,
+    /// Paths left alone because they changed since the run.
+    pub warnings: Vec<String>,
+    pub dry_run: bool,
+}
+
+/// Restore the destinations changed by the last applied run in `home` and
+/// record the rollback in the history.
+///
+/// Packages and system settings changed by the run are not reverted.
+pub fn rollback(home: &Path, started_at: u64, dry_run: bool, wait: bool) -> Result<RollbackReport> {
+    let _lock = if dry_run {
+        None
+    } else {
+        Some(RunLock::acquire(home, wait)?)
+    };
+    let entries = history::load_history(home)?;
+    let entry = history::last_applied(&entries)
+        .ok_or_else(|| DotstrapError::NothingToRollBack(history::history_path(home)))?;
+    let outcome = rollback::rollback_links(&entry.linked, dry_run)?;
+    if !dry_run {
+        history::append_entry(
+            home,
+            HistoryEntry {
+                id: 0,
+                started_at,
+                source: entry.source.clone(),
+                revision: entry.revision.clone(),
+                error: None,
+                created: 0,
+                updated: 0,
+                unchanged: 0,
+                linked: Vec::new(),
+                package_commands: Vec::new(),
+                warnings: outcome.warnings.clone(),
+                rollback_of: Some(entry.id),
+            },
+        )?;
+    }
+    Ok(RollbackReport {
+        run: entry.id,
+        restored: outcome.restored,
+        warnings: outcome.warnings,
+        dry_run,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::outcome::{ItemStatus, LinkOutcome};
+    use tempfile::TempDir;
+
+    #[cfg(unix)]
+    #[test]
+    fn rollback_undoes_the_last_run_once() {
+        let home = TempDir::new().unwrap();
+        let link = home.path().join(".zshrc");
+        std::os::unix::fs::symlink(home.path().join("stage"), &link).unwrap();
+        history::append_entry(
+            home.path(),
+            HistoryEntry {
+                id: 0,
+                started_at: 0,
+                source: "dotfiles".into(),
+                revision: None,
+                error: None,
+                created: 1,
+                updated: 0,
+                unchanged: 0,
+                linked: vec![LinkOutcome::new(link.clone(), ItemStatus::Created)],
+                package_commands: Vec::new(),
+                warnings: Vec::new(),
+                rollback_of: None,
+            },
+        )
+        .unwrap();
+
+        let report = rollback(home.path(), 1, false, false).unwrap();
+
+        assert_eq!(report.run, 1);
+        assert_eq!(report.restored.len(), 1);
+        assert!(!link.is_symlink());
+        let error = rollback(home.path(), 2, false, false).expect_err("nothing left to undo");
+        assert!(matches!(error, DotstrapError::NothingToRollBack(_)));
+    }
+}
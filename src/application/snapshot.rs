@@ -0,0 +1,228 @@
+//! Seeding `packages.yaml` with the packages installed on the host.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+
+use crate::config::{self, Formula, PACKAGES_NAME, PackagesSpec};
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::CommandExecutor;
+use crate::services::snapshot::{self, InstalledSection};
+
+/// Packages added, or planned in dry-run mode, to `packages.yaml`.
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    pub path: PathBuf,
+    /// Packages added per section, e.g. `brew.casks`.
+    pub added: BTreeMap<String, Vec<String>>,
+    pub warnings: Vec<String>,
+    pub dry_run: bool,
+}
+
+/// Add the packages installed on the host that `repo` does not declare yet
+/// to its `packages.yaml`.
+///
+/// Packages declared by the manifest's `packages` section are not repeated.
+/// Existing entries are kept; new ones are appended to their section.
+pub fn snapshot(repo: &Path, executor: &dyn CommandExecutor, dry_run: bool) -> Result<Snapshot> {
+    let (installed, warnings) = snapshot::installed_packages(std::env::consts::OS, executor);
+    snapshot_sections(repo, installed, warnings, dry_run)
+}
+
+fn snapshot_sections(
+    repo: &Path,
+    installed: Vec<InstalledSection>,
+    warnings: Vec<String>,
+    dry_run: bool,
+) -> Result<Snapshot> {
+    let path = repo.join(PACKAGES_NAME);
+    let failed = |reason: String| DotstrapError::Snapshot {
+        path: path.clone(),
+        reason,
+    };
+    let mut document = if path.exists() {
+        let bytes = fs::read(&path)?;
+        serde_yaml::from_slice::<Value>(&bytes).map_err(|source| DotstrapError::Yaml {
+            source,
+            path: path.clone(),
+        })?
+    } else {
+        if let Some(legacy) = config::legacy_package_files(repo).first() {
+            // A Brewfile is read as is by `brew bundle`, so it is never migrated.
+            let hint = if legacy.ends_with("Brewfile") {
+                ""
+            } else {
+                "; run `dotstrap migrate` first"
+            };
+            return Err(failed(format!(
+                "packages are declared in `{}`, which `{PACKAGES_NAME}` would replace{hint}",
+                legacy.display()
+            )));
+        }
+        Value::Mapping(Mapping::new())
+    };
+    let mut declared = config::load_packages_spec(repo)?.unwrap_or_default();
+    if repo.join(config::MANIFEST_NAME).exists()
+        && let Some(inline) = config::load_manifest(repo)?.packages
+    {
+        declared = declared.layer(inline);
+    }
+
+    let mut added = BTreeMap::new();
+    for section in installed {
+        let known = declared_names(&declared, section.path);
+        let new: Vec<String> = section
+            .packages
+            .into_iter()
+            .filter(|package| !known.contains(package))
+            .collect();
+        if new.is_empty() {
+            continue;
+        }
+        let list = section_list(&mut document, section.path)
+            .ok_or_else(|| failed(format!("`{}` is not a list", section.path.join("."))))?;
+        list.extend(new.iter().cloned().map(Value::String));
+        added.insert(section.path.join("."), new);
+    }
+
+    if !dry_run && !added.is_empty() {
+        let text = serde_yaml::to_string(&document).map_err(|source| DotstrapError::Yaml {
+            source,
+            path: path.clone(),
+        })?;
+        fs::write(&path, text)?;
+    }
+    Ok(Snapshot {
+        path,
+        added,
+        warnings,
+        dry_run,
+    })
+}
+
+/// Packages `spec` declares in the section at `path`.
+fn declared_names(spec: &PackagesSpec, path: &[&str]) -> BTreeSet<String> {
+    match path {
+        ["brew", "taps"] => spec.brew.taps.iter().cloned().collect(),
+        ["brew", "formulae"] => spec
+            .brew
+            .formulae
+            .iter()
+            .map(Formula::installed_name)
+            .collect(),
+        ["brew", "casks"] => spec.brew.casks.iter().cloned().collect(),
+        ["apt"] => spec.apt.iter().cloned().collect(),
+        ["pacman"] => spec.pacman.iter().cloned().collect(),
+        ["dnf"] => spec.dnf.iter().cloned().collect(),
+        ["cargo"] => spec.cargo.iter().cloned().collect(),
+        ["winget"] => spec.winget.iter().cloned().collect(),
+        [section] => match spec.extra.get(*section) {
+            Some(serde_json::Value::Array(packages)) => packages
+                .iter()
+                .filter_map(|package| package.as_str().map(str::to_string))
+                .collect(),
+            _ => BTreeSet::new(),
+        },
+        _ => BTreeSet::new(),
+    }
+}
+
+/// The list at `path` in `document`, created when missing, or `None` when
+/// something else is there.
+fn section_list<'a>(document: &'a mut Value, path: &[&str]) -> Option<&'a mut Vec<Value>> {
+    let mut value = document;
+    for key in path {
+        if value.is_null() {
+            *value = Value::Mapping(Mapping::new());
+        }
+        value = value
+            .as_mapping_mut()?
+            .entry(Value::String(key.to_string()))
+            .or_insert(Value::Null);
+    }
+    if value.is_null() {
+        *value = Value::Sequence(Vec::new());
+    }
+    value.as_sequence_mut()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn installed() -> Vec<InstalledSection> {
+        vec![
+            InstalledSection {
+                path: &["brew", "formulae"],
+                packages: vec!["ripgrep".into(), "terraform@1.5.7".into()],
+            },
+            InstalledSection {
+                path: &["cargo"],
+                packages: vec!["bat".into()],
+            },
+            InstalledSection {
+                path: &["pipx"],
+                packages: vec!["black".into()],
+            },
+        ]
+    }
+
+    #[test]
+    fn snapshot_appends_undeclared_packages() {
+        let repo = TempDir::new().unwrap();
+        fs::write(
+            repo.path().join(PACKAGES_NAME),
+            "brew:\n  formulae:\n    - name: terraform\n      version: 1.5.7\ncargo: [bat]\n",
+        )
+        .unwrap();
+
+        let snapshot = snapshot_sections(repo.path(), installed(), Vec::new(), false).unwrap();
+
+        assert_eq!(
+            snapshot.added,
+            BTreeMap::from([
+                ("brew.formulae".to_string(), vec!["ripgrep".to_string()]),
+                ("pipx".to_string(), vec!["black".to_string()]),
+            ])
+        );
+        let spec = config::load_packages_spec(repo.path()).unwrap().unwrap();
+        assert_eq!(
+            spec.brew
+                .formulae
+                .iter()
+                .map(Formula::installed_name)
+                .collect::<Vec<_>>(),
+            vec!["terraform@1.5.7", "ripgrep"]
+        );
+        assert_eq!(spec.extra["pipx"], serde_json::json!(["black"]));
+    }
+
+    #[test]
+    fn snapshot_dry_run_creates_nothing() {
+        let repo = TempDir::new().unwrap();
+
+        let snapshot = snapshot_sections(repo.path(), installed(), Vec::new(), true).unwrap();
+
+        assert_eq!(snapshot.added.len(), 3);
+        assert!(!repo.path().join(PACKAGES_NAME).exists());
+    }
+
+    #[test]
+    fn snapshot_refuses_to_shadow_legacy_files() {
+        let repo = TempDir::new().unwrap();
+        fs::create_dir_all(repo.path().join("brew")).unwrap();
+        fs::write(
+            repo.path().join("brew/packages.yaml"),
+            "formulae: [ripgrep]\n",
+        )
+        .unwrap();
+
+        let error = snapshot_sections(repo.path(), installed(), Vec::new(), false).unwrap_err();
+
+        assert!(error.to_string().contains("dotstrap migrate"), "{error}");
+    }
+}
@@ -0,0 +1,95 @@
+//! Locating the source directory, for `dotstrap source-path` and
+//! `dotstrap cd`.
+
+use std::path::{Path, PathBuf};
+
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::CommandExecutor;
+use crate::infrastructure::repository;
+
+/// Directory of the manifest of `source`, within its working copy: the source
+/// itself when local, or the clone `dotstrap update` keeps of it in `home`.
+///
+/// `subdir` takes precedence over a `url//path` subdirectory in `source`.
+pub fn source_path(
+    home: &Path,
+    source: &str,
+    subdir: Option<&Path>,
+    executor: &dyn CommandExecutor,
+) -> Result<PathBuf> {
+    let (source, embedded_subdir) = repository::split_subdir(source);
+    let root = repository::working_copy(source, &home.join(".dotstrap/sources"), executor)?;
+    let Some(subdir) = subdir.or(embedded_subdir.map(Path::new)) else {
+        return Ok(root);
+    };
+    let path = root.join(subdir);
+    if !path.is_dir() {
+        return Err(DotstrapError::SubdirNotFound {
+            subdir: subdir.to_path_buf(),
+            source_path: root,
+        });
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use tempfile::TempDir;
+
+    #[test]
+    fn source_path_resolves_local_sources_and_subdirectories() {
+        let home = TempDir::new().unwrap();
+        let source = TempDir::new().unwrap();
+        std::fs::create_dir_all(source.path().join("conf/dotstrap")).unwrap();
+        let executor = RecordingCommandExecutor::default();
+        let root = source.path().canonicalize().unwrap();
+        let source = source.path().to_str().unwrap();
+
+        assert_eq!(
+            source_path(home.path(), source, None, &executor).unwrap(),
+            root
+        );
+        assert_eq!(
+            source_path(home.path(), &format!("{source}//conf"), None, &executor).unwrap(),
+            root.join("conf")
+        );
+        assert_eq!(
+            source_path(
+                home.path(),
+                &format!("{source}//conf"),
+                Some(Path::new("conf/dotstrap")),
+                &executor
+            )
+            .unwrap(),
+            root.join("conf/dotstrap")
+        );
+        assert!(matches!(
+            source_path(home.path(), source, Some(Path::new("missing")), &executor),
+            Err(DotstrapError::SubdirNotFound { .. })
+        ));
+        assert!(executor.calls().is_empty());
+    }
+
+    #[test]
+    fn source_path_uses_the_clone_kept_by_update() {
+        let home = TempDir::new().unwrap();
+        let executor = RecordingCommandExecutor::default();
+
+        let path = source_path(
+            home.path(),
+            "https://github.com/me/dotfiles.git",
+            None,
+            &executor,
+        )
+        .unwrap();
+
+        assert_eq!(
+            path,
+            home.path()
+                .join(".dotstrap/sources/https-github-com-me-dotfiles")
+        );
+        assert_eq!(executor.calls().len(), 1);
+    }
+}
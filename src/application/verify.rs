@@ -0,0 +1,225 @@
+//! Checking the destinations managed by the last run against the state it
+//! recorded, without loading the source.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::errors::Result;
+use crate::infrastructure::download;
+use crate::infrastructure::filesystem::{FileSystem, OsFileSystem};
+use crate::infrastructure::state::{self, TemplateOrigin};
+use crate::services::linker;
+use crate::services::outcome::ItemStatus;
+
+/// Outcome of `dotstrap verify`.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct VerifyReport {
+    /// Number of managed destinations checked.
+    pub checked: usize,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+/// Way a managed destination differs from what the last run left.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Discrepancy {
+    /// Destination, relative to the home directory.
+    pub destination: PathBuf,
+    pub problem: String,
+}
+
+/// Check every destination recorded in the state of `home`: that it still
+/// links to its staged file under `stage_root`, or is a copy of it, that the staged file has the
+/// digest and mode the last run gave it, and, for destinations created once,
+/// that they still exist.
+pub fn verify(home: &Path, stage_root: &Path) -> Result<VerifyReport> {
+    let fs = OsFileSystem;
+    let templates = state::load_state(home)?.templates;
+    let mut report = VerifyReport {
+        checked: templates.len(),
+        ..VerifyReport::default()
+    };
+    for (destination, origin) in &templates {
+        let problems = check(
+            &fs,
+            origin,
+            &stage_root.join(destination),
+            &home.join(destination),
+        );
+        report
+            .discrepancies
+            .extend(problems.into_iter().map(|problem| Discrepancy {
+                destination: destination.clone(),
+                problem,
+            }));
+    }
+    Ok(report)
+}
+
+/// Problems of the destination `origin` was linked to, staged at `staged`.
+fn check(
+    fs: &dyn FileSystem,
+    origin: &TemplateOrigin,
+    staged: &Path,
+    destination: &Path,
+) -> Vec<String> {
+    if origin.create_only {
+        return match fs.symlink_metadata(destination) {
+            Ok(_) => Vec::new(),
+            Err(_) => vec!["destination missing".to_string()],
+        };
+    }
+    let Ok(metadata) = fs.metadata(staged) else {
+        return vec![format!("staged file {} is missing", staged.display())];
+    };
+    let mut problems = Vec::new();
+    if let Some(expected) = &origin.output
+        && download::sha256_file(staged).ok().as_ref() != Some(expected)
+    {
+        problems.push("staged file changed since the last run".to_string());
+    }
+    if let (Some(expected), Some(actual)) = (origin.mode, metadata.mode)
+        && expected != actual
+    {
+        problems.push(format!(
+            "staged file has mode {actual:04o}, expected {expected:04o}"
+        ));
+    }
+    match linker::link_status_in(fs, staged, destination) {
+        ItemStatus::Unchanged | ItemStatus::Skipped => {}
+        // A copy placed by a container run.
+        ItemStatus::Updated
+            if !fs.is_symlink(destination)
+                && linker::same_contents(fs, destination, staged).unwrap_or(false) => {}
+        ItemStatus::Created => problems.push("destination missing".to_string()),
+        ItemStatus::Updated => problems.push(match fs.read_link(destination) {
+            Ok(target) => format!("links to {} instead of the staged file", target.display()),
+            Err(_) => "replaced by a different file".to_string(),
+        }),
+    }
+    problems
+}
+
+/// Human readable listing of the discrepancies of `report`.
+pub fn format_report(report: &VerifyReport) -> String {
+    let mut out = String::new();
+    for discrepancy in &report.discrepancies {
+        let _ = writeln!(
+            out,
+            "{}: {}",
+            discrepancy.destination.display(),
+            discrepancy.problem
+        );
+    }
+    let _ = writeln!(
+        out,
+        "Verified {} managed file(s): {} discrepancy(ies).",
+        report.checked,
+        report.discrepancies.len()
+    );
+    out
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::infrastructure::state::State;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    fn origin(output: Option<String>, mode: Option<u32>) -> TemplateOrigin {
+        TemplateOrigin {
+            source: "/src".into(),
+            template: PathBuf::from("zshrc.hbs"),
+            profile: None,
+            inputs: None,
+            output,
+            merged: false,
+            mode,
+            create_only: false,
+        }
+    }
+
+    /// Home with `.zshrc` and `.gitconfig` linked to their staged files, as
+    /// the last run recorded them.
+    fn setup() -> (TempDir, PathBuf) {
+        let home = TempDir::new().unwrap();
+        let stage_root = linker::default_stage_root(home.path());
+        std::fs::create_dir_all(&stage_root).unwrap();
+        let mut recorded = State::default();
+        for (name, mode) in [(".zshrc", None), (".gitconfig", Some(0o600))] {
+            let staged = stage_root.join(name);
+            std::fs::write(&staged, format!("{name}\n")).unwrap();
+            if let Some(mode) = mode {
+                std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(mode)).unwrap();
+            }
+            std::os::unix::fs::symlink(&staged, home.path().join(name)).unwrap();
+            let output = download::sha256_file(&staged).unwrap();
+            recorded
+                .templates
+                .insert(PathBuf::from(name), origin(Some(output), mode));
+        }
+        state::save_state(home.path(), &recorded).unwrap();
+        (home, stage_root)
+    }
+
+    #[test]
+    fn verify_passes_when_nothing_changed() {
+        let (home, stage_root) = setup();
+
+        let report = verify(home.path(), &stage_root).unwrap();
+
+        assert_eq!(report.checked, 2);
+        assert!(report.discrepancies.is_empty());
+    }
+
+    #[test]
+    fn verify_accepts_copies_of_the_staged_files() {
+        let (home, stage_root) = setup();
+        let zshrc = home.path().join(".zshrc");
+        std::fs::remove_file(&zshrc).unwrap();
+        std::fs::copy(stage_root.join(".zshrc"), &zshrc).unwrap();
+
+        assert!(
+            verify(home.path(), &stage_root)
+                .unwrap()
+                .discrepancies
+                .is_empty()
+        );
+        std::fs::write(&zshrc, "edited\n").unwrap();
+        assert_eq!(
+            verify(home.path(), &stage_root).unwrap().discrepancies[0].problem,
+            "replaced by a different file"
+        );
+    }
+
+    #[test]
+    fn verify_reports_targets_digests_and_modes() {
+        let (home, stage_root) = setup();
+        std::fs::write(stage_root.join(".zshrc"), "edited\n").unwrap();
+        let gitconfig = stage_root.join(".gitconfig");
+        std::fs::set_permissions(&gitconfig, std::fs::Permissions::from_mode(0o644)).unwrap();
+        std::fs::remove_file(home.path().join(".gitconfig")).unwrap();
+        std::os::unix::fs::symlink("/elsewhere", home.path().join(".gitconfig")).unwrap();
+
+        let report = verify(home.path(), &stage_root).unwrap();
+
+        let problems: Vec<String> = report
+            .discrepancies
+            .iter()
+            .map(|d| format!("{}: {}", d.destination.display(), d.problem))
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                ".gitconfig: staged file has mode 0644, expected 0600",
+                ".gitconfig: links to /elsewhere instead of the staged file",
+                ".zshrc: staged file changed since the last run",
+            ]
+        );
+        assert!(
+            format_report(&report).ends_with("Verified 2 managed file(s): 3 discrepancy(ies).\n")
+        );
+    }
+}
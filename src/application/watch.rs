@@ -0,0 +1,214 @@
+//! Re-applying the templates of a local source whenever its files change.
+
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use notify::{RecursiveMode, Watcher};
+
+use super::{
+    ModuleSelection, disable_entries, layer_manifests, layered_context, resolve_bases,
+    select_profile,
+};
+use crate::config;
+use crate::errors::Result;
+use crate::infrastructure::command::CommandExecutor;
+use crate::infrastructure::lock::RunLock;
+use crate::infrastructure::paths::IgnoreRules;
+use crate::infrastructure::repository::{self, RepoHandle};
+use crate::infrastructure::secrets::SecretProviderRegistry;
+use crate::services::outcome::LinkOutcome;
+use crate::services::questions::NonInteractive;
+use crate::services::templating::{Permissions, RenderedSet};
+use crate::services::{linker, templating};
+
+/// Quiet period collapsing the bursts of events editors emit on save.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Render and link the templates of `source` into `home`, staged under
+/// `stage_root` and layered over the bases the manifest declares, for the
+/// given `profile`, running the commands `permissions` allow.
+///
+/// Hooks and every other step of a full run are left out.
+pub fn apply_templates(
+    source: &Path,
+    home: &Path,
+    stage_root: &Path,
+    profile: Option<&str>,
+    permissions: Permissions,
+    executor: &dyn CommandExecutor,
+) -> Result<Vec<LinkOutcome>> {
+    let repo = repository::resolve_repository(&source.to_string_lossy(), None, executor)?;
+    let rendered = render_local(&repo, home, profile, permissions, executor)?;
+    let _lock = RunLock::acquire(home, true)?;
+    linker::link_templates(home, stage_root, &rendered, false)
+}
+
+/// Render the templates of `repo` layered over the bases its manifest
+/// declares, without prompting for unanswered questions.
+pub(crate) fn render_local(
+    repo: &RepoHandle,
+    home: &Path,
+    profile: Option<&str>,
+    permissions: Permissions,
+    executor: &dyn CommandExecutor,
+) -> Result<RenderedSet> {
+    let source = repo.path();
+    let mut manifest = config::load_manifest(source)?;
+    let bases = resolve_bases(repo, &mut manifest, &[], executor)?;
+    let mut layers: Vec<&Path> = bases.iter().map(|base| base.handle.path()).collect();
+    layers.push(source);
+    let selection = ModuleSelection {
+        profile,
+        ..Default::default()
+    };
+    let mut manifest = layer_manifests(&layers, &bases, manifest, &selection)?;
+    let (selected, _) = select_profile(&mut manifest, profile, home)?;
+    let (context, _) = layered_context(
+        &layers,
+        home,
+        &manifest,
+        &selected,
+        &SecretProviderRegistry::default(),
+        &mut NonInteractive,
+        false,
+    )?;
+    disable_entries(
+        &mut manifest,
+        &context,
+        home,
+        &source.join(config::MANIFEST_NAME),
+    )?;
+    templating::render_templates(source, &manifest, &context, permissions, executor)
+}
+
+/// Apply the templates of `source` once, then again after every change to
+/// its files, reporting each attempt to `on_apply`.
+///
+/// Failed attempts, e.g. a template with a syntax error, are reported and
+/// watching continues. Returns only if watching the files fails.
+pub fn watch(
+    source: &Path,
+    home: &Path,
+    stage_root: &Path,
+    profile: Option<&str>,
+    permissions: Permissions,
+    executor: &dyn CommandExecutor,
+    mut on_apply: impl FnMut(Result<Vec<LinkOutcome>>),
+) -> Result<()> {
+    let source = &source.canonicalize()?;
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(source, RecursiveMode::Recursive)?;
+    on_apply(apply_templates(
+        source,
+        home,
+        stage_root,
+        profile,
+        permissions,
+        executor,
+    ));
+    loop {
+        let Ok(event) = events.recv() else {
+            return Ok(());
+        };
+        // Read on every batch so edits to the ignore file apply at once.
+        let ignore = config::load_ignore(source)?;
+        let mut relevant = is_relevant(source, &ignore, &event?);
+        while let Ok(event) = events.recv_timeout(DEBOUNCE) {
+            relevant |= is_relevant(source, &ignore, &event?);
+        }
+        if relevant {
+            on_apply(apply_templates(
+                source,
+                home,
+                stage_root,
+                profile,
+                permissions,
+                executor,
+            ));
+        }
+    }
+}
+
+/// Whether `event` touches a source file rather than git metadata or a
+/// path `ignore` excludes.
+fn is_relevant(source: &Path, ignore: &IgnoreRules, event: &notify::Event) -> bool {
+    !event.kind.is_access()
+        && event.paths.iter().any(|path| {
+            !path.strip_prefix(source).is_ok_and(|relative| {
+                relative.starts_with(".git") || ignore.is_ignored(relative, path.is_dir())
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use crate::services::outcome::ItemStatus;
+    use notify::EventKind;
+    use notify::event::{CreateKind, ModifyKind};
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    #[test]
+    fn apply_templates_links_local_source() {
+        let home = TempDir::new().unwrap();
+        let source = PathBuf::from("tests/empty-config");
+
+        let linked = apply_templates(
+            &source,
+            home.path(),
+            &linker::default_stage_root(home.path()),
+            None,
+            Permissions::default(),
+            &RecordingCommandExecutor::default(),
+        )
+        .unwrap();
+
+        assert_eq!(linked.len(), 3);
+        assert!(linked.iter().all(|link| link.status == ItemStatus::Created));
+        assert!(home.path().join(".zshrc").is_symlink());
+    }
+
+    #[test]
+    fn is_relevant_ignores_git_metadata_and_reads() {
+        let source = Path::new("/src/dotfiles");
+        let ignore = IgnoreRules::parse("*.swp\n");
+        let event = |kind, path: &str| notify::Event::new(kind).add_path(PathBuf::from(path));
+
+        assert!(is_relevant(
+            source,
+            &ignore,
+            &event(
+                EventKind::Modify(ModifyKind::Any),
+                "/src/dotfiles/zshrc.tpl"
+            )
+        ));
+        assert!(!is_relevant(
+            source,
+            &ignore,
+            &event(
+                EventKind::Create(CreateKind::File),
+                "/src/dotfiles/.git/index.lock"
+            )
+        ));
+        assert!(!is_relevant(
+            source,
+            &ignore,
+            &event(
+                EventKind::Access(notify::event::AccessKind::Any),
+                "/src/dotfiles/x"
+            )
+        ));
+        assert!(!is_relevant(
+            source,
+            &ignore,
+            &event(
+                EventKind::Modify(ModifyKind::Any),
+                "/src/dotfiles/.zshrc.tpl.swp"
+            )
+        ));
+    }
+}
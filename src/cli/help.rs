@@ -0,0 +1,137 @@
+//! Help pages shipped with the binary: the `dotstrap help <topic>` topics and
+//! the manual pages of `--generate-manpages`.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clap::CommandFactory;
+use clap_mangen::Man;
+use clap_mangen::roff::{Roff, roman};
+
+use super::Cli;
+
+/// A page of `dotstrap help <topic>`.
+#[derive(Debug)]
+pub struct Topic {
+    pub name: &'static str,
+    /// One-line description, listed by `dotstrap help`.
+    pub summary: &'static str,
+    pub body: &'static str,
+}
+
+pub const TOPICS: &[Topic] = &[
+    Topic {
+        name: "manifest",
+        summary: "syntax of manifest.yaml and the layout of a source",
+        body: include_str!("topics/manifest.txt"),
+    },
+    Topic {
+        name: "secrets",
+        summary: "declaring secrets and serving them from providers",
+        body: include_str!("topics/secrets.txt"),
+    },
+];
+
+/// Topic named `name`, if any.
+pub fn topic(name: &str) -> Option<&'static Topic> {
+    TOPICS.iter().find(|topic| topic.name == name)
+}
+
+/// Text listing the topics, appended to the help of the command line.
+pub fn topics_help() -> String {
+    let mut help = String::from("Help topics (dotstrap help <TOPIC>):\n");
+    for topic in TOPICS {
+        help.push_str(&format!("  {:<10}{}\n", topic.name, topic.summary));
+    }
+    help
+}
+
+/// Write the manual pages of dotstrap into `dir`, which is created when
+/// missing: one per command in section 1, and one per topic in section 7.
+/// Returns the paths of the pages written.
+pub fn generate_manpages(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir)?;
+    let mut command = Cli::command().bin_name("dotstrap");
+    command.build();
+    let mut pages = Vec::new();
+    write_command_pages(command, dir, &mut pages)?;
+    for topic in TOPICS {
+        let path = dir.join(format!("dotstrap-{}.7", topic.name));
+        let mut file = std::fs::File::create(&path)?;
+        file.write_all(topic_page(topic).as_bytes())?;
+        pages.push(path);
+    }
+    Ok(pages)
+}
+
+fn write_command_pages(
+    command: clap::Command,
+    dir: &Path,
+    pages: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    for subcommand in command.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        write_command_pages(subcommand.clone(), dir, pages)?;
+    }
+    pages.push(Man::new(command).generate_to(dir)?);
+    Ok(())
+}
+
+/// Manual page of `topic`: paragraphs are filled, and indented blocks, the
+/// examples, are kept as written.
+fn topic_page(topic: &Topic) -> String {
+    let title = format!("dotstrap-{}", topic.name);
+    let mut page = Roff::new();
+    page.control("TH", [title.to_uppercase().as_str(), "7"])
+        .control("SH", ["NAME"])
+        .text([roman(format!("{title} - {}", topic.summary))])
+        .control("SH", ["DESCRIPTION"]);
+    for block in topic.body.trim_end().split("\n\n") {
+        if block.starts_with("    ") {
+            page.control("nf", []).control("RS", ["4"]);
+            for line in block.lines() {
+                page.text([roman(line.strip_prefix("    ").unwrap_or(line))]);
+            }
+            page.control("RE", []).control("fi", []);
+        } else {
+            page.control("PP", [])
+                .text([roman(block.replace('\n', " "))]);
+        }
+    }
+    page.render()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_manpages_covers_commands_and_topics() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let pages = generate_manpages(&dir.path().join("man")).unwrap();
+
+        let names: Vec<String> = pages
+            .iter()
+            .map(|page| page.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        for name in [
+            "dotstrap.1",
+            "dotstrap-apply.1",
+            "dotstrap-export-brewfile.1",
+            "dotstrap-manifest.7",
+            "dotstrap-secrets.7",
+        ] {
+            assert!(names.iter().any(|page| page == name), "{name} in {names:?}");
+        }
+        let manifest = std::fs::read_to_string(dir.path().join("man/dotstrap-manifest.7")).unwrap();
+        assert!(manifest.contains("\n.TH DOTSTRAP-MANIFEST 7\n"));
+        assert!(manifest.contains("\n.nf\n"));
+    }
+
+    #[test]
+    fn topic_finds_pages_by_name() {
+        assert!(topic("secrets").unwrap().body.contains("dotstrap-secret-"));
+        assert!(topic("unknown").is_none());
+        assert!(topics_help().contains("  manifest  syntax of manifest.yaml"));
+    }
+}
@@ -1,9 +1,18 @@
 //! Command-line interface definition for dotstrap.
 
+pub mod help;
+
 use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::{Parser, value_parser};
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, Parser, Subcommand, ValueEnum, value_parser};
 use clap_complete::Shell;
+use serde::Deserialize;
+
+use crate::infrastructure::escalation::Escalation;
+use crate::infrastructure::user_config::UserConfig;
+use crate::services::templating::Permissions;
 
 /// Command line interface definition for dotstrap.
 #[derive(Debug, Parser)]
@@ -11,34 +20,744 @@ use clap_complete::Shell;
     author,
     version,
     about = "Synchronise dotfiles from a template repository.",
-    long_about = None
+    long_about = None,
+    subcommand_negates_reqs = true,
+    disable_help_subcommand = true,
+    after_long_help = help::topics_help()
 )]
 pub struct Cli {
-    /// Git repository URL or local path containing dotstrap manifest and templates.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Git repository URL or local path containing dotstrap manifest and
+    /// templates, defaulting to `source` in the user configuration.
+    #[arg(value_name = "SOURCE", env = "DOTSTRAP_SOURCE")]
+    pub source: Option<String>,
+
+    /// Branch, tag, or commit of the source repository to apply.
+    #[arg(long = "ref", value_name = "REF", global = true)]
+    pub git_ref: Option<String>,
+
+    /// Access token used to clone private HTTPS sources.
     #[arg(
-        value_name = "SOURCE",
-        required_unless_present = "generate_completions"
+        long,
+        value_name = "TOKEN",
+        env = "DOTSTRAP_GIT_TOKEN",
+        hide_env_values = true,
+        global = true
     )]
-    pub source: Option<String>,
+    pub git_token: Option<String>,
 
-    /// Override the target home directory (defaults to the current user's home).
-    #[arg(long, value_name = "PATH")]
-    pub home: Option<PathBuf>,
+    /// Private key used to clone SSH sources.
+    #[arg(long, value_name = "PATH", global = true)]
+    pub ssh_key: Option<PathBuf>,
+
+    /// Only apply sources whose HEAD commit is signed by this key (an OpenPGP
+    /// fingerprint or an SSH public key). May be repeated.
+    #[arg(long = "trusted-key", value_name = "KEY", global = true)]
+    pub trusted_keys: Vec<String>,
+
+    /// Profile of the manifest to apply, selecting its values and the entries
+    /// restricted to it.
+    #[arg(long, value_name = "NAME", env = "DOTSTRAP_PROFILE", global = true)]
+    pub profile: Option<String>,
+
+    /// Directory of the source repository containing the manifest.
+    #[arg(long, value_name = "PATH", global = true)]
+    pub subdir: Option<PathBuf>,
+
+    /// Override the target home directory (defaults to the current user's
+    /// home); repeat to apply the source to each home in turn.
+    #[arg(long, value_name = "PATH", env = "DOTSTRAP_HOME", global = true)]
+    pub home: Vec<PathBuf>,
+
+    /// Directory rendered templates are staged in before being linked
+    /// (defaults to `~/.dotstrap/generated`).
+    #[arg(long, value_name = "PATH", env = "DOTSTRAP_STAGING_DIR", global = true)]
+    pub staging_dir: Option<PathBuf>,
 
     /// Skip installing Homebrew packages.
-    #[arg(long)]
+    #[arg(long, env = "DOTSTRAP_SKIP_BREW", global = true)]
     pub skip_brew: bool,
 
+    /// Do not render or link templates, running the other steps, such as
+    /// package installs, only.
+    #[arg(
+        long,
+        env = "DOTSTRAP_SKIP_TEMPLATES",
+        global = true,
+        conflicts_with = "only"
+    )]
+    pub skip_templates: bool,
+
+    /// Install Homebrew with the official install script when it is missing.
+    #[arg(long, global = true)]
+    pub install_brew: bool,
+
+    /// Do not run `brew update` before installing Homebrew packages.
+    #[arg(long, global = true)]
+    pub no_brew_update: bool,
+
+    /// Uninstall Homebrew packages installed by a previous run that are no longer declared.
+    #[arg(long, global = true)]
+    pub sync_packages: bool,
+
+    /// Number of times failed commands (git clone, package installs) are retried.
+    #[arg(long, value_name = "COUNT", default_value_t = 2, global = true)]
+    pub retries: u32,
+
+    /// Kill commands (git clone, package installs, …) still running after
+    /// this many seconds.
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = "DOTSTRAP_COMMAND_TIMEOUT",
+        value_parser = value_parser!(u64).range(1..),
+        global = true
+    )]
+    pub command_timeout: Option<u64>,
+
+    /// Install up to this many Homebrew formulae and casks at once, after the
+    /// taps.
+    #[arg(
+        long,
+        value_name = "COUNT",
+        env = "DOTSTRAP_JOBS",
+        default_value_t = 1,
+        value_parser = value_parser!(u16).range(1..),
+        global = true
+    )]
+    pub jobs: u16,
+
+    /// How to run the package commands needing root privileges;
+    /// `prompt-once` asks for the `sudo` password before the first of them.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POLICY",
+        default_value_t = Escalation::Sudo,
+        env = "DOTSTRAP_ESCALATION",
+        global = true
+    )]
+    pub escalation: Escalation,
+
+    /// Kind of machine the run sets up; `container` copies files instead of
+    /// linking them and skips desktop-only steps.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        default_value_t = RunMode::Host,
+        env = "DOTSTRAP_MODE",
+        global = true
+    )]
+    pub mode: RunMode,
+
+    /// Never reach the network: use the cached clone of remote sources, skip
+    /// `brew update`, and fail when packages would need installing.
+    #[arg(long, env = "DOTSTRAP_OFFLINE", global = true)]
+    pub offline: bool,
+
+    /// Show the layers setting the value at this dotted path, such as
+    /// `git.email`, and the one that wins. May be repeated.
+    #[arg(long = "trace-value", value_name = "KEY", global = true)]
+    pub trace_values: Vec<String>,
+
+    /// Show each file and command (`-v`), or also rendered sizes and context
+    /// keys (`-vv`).
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Only print errors.
+    #[arg(short, long, conflicts_with = "verbose", global = true)]
+    pub quiet: bool,
+
+    /// Format of the run report printed on stdout.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, global = true)]
+    pub output: OutputFormat,
+
+    /// When to color the output; `auto` colors terminals unless `NO_COLOR`
+    /// is set.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = ColorChoice::Auto,
+        env = "DOTSTRAP_COLOR",
+        global = true
+    )]
+    pub color: ColorChoice,
+
     /// Print the operations without changing the system.
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub dry_run: bool,
 
-    /// Output shell completion scripts for the given shell and exit.
+    /// Replace files dotstrap does not manage and uninstall packages no
+    /// longer declared without asking.
+    #[arg(
+        short = 'y',
+        long,
+        visible_alias = "force",
+        env = "DOTSTRAP_YES",
+        global = true
+    )]
+    pub yes: bool,
+
+    /// Never prompt: unanswered questions take their default, and replacing
+    /// files dotstrap does not manage or uninstalling packages fails unless
+    /// `--yes` is given.
+    #[arg(long, env = "DOTSTRAP_NON_INTERACTIVE", global = true)]
+    pub non_interactive: bool,
+
+    /// Walk each file the run would change, showing its diff, and ask
+    /// whether to apply it, skip it or back up the current file first.
+    #[arg(
+        short = 'i',
+        long,
+        global = true,
+        conflicts_with_all = ["yes", "non_interactive"]
+    )]
+    pub interactive: bool,
+
+    /// Fail when the run reports warnings, or when a manifest question has
+    /// neither a value nor a saved answer.
+    #[arg(long, env = "DOTSTRAP_STRICT", global = true)]
+    pub strict: bool,
+
+    /// Validate the source in continuous integration: implies
+    /// `--non-interactive`, `--strict`, `--color never`, and JSON output
+    /// unless `--output` is given, and ignores the user configuration.
+    #[arg(
+        long,
+        env = "DOTSTRAP_CI",
+        global = true,
+        conflicts_with = "interactive"
+    )]
+    pub ci: bool,
+
+    /// Wait for another run against the same home to finish instead of
+    /// failing immediately.
+    #[arg(long, global = true)]
+    pub wait: bool,
+
+    /// Give the reason of every step of a dry run, and list the files left
+    /// as they are and the templates skipped by their condition.
+    #[arg(long, env = "DOTSTRAP_EXPLAIN", global = true)]
+    pub explain: bool,
+
+    /// Send a desktop notification summarizing the run once it finishes or
+    /// fails.
+    #[arg(long, env = "DOTSTRAP_NOTIFY", global = true)]
+    pub notify: bool,
+
+    /// Run the template plugins the manifest declares, which execute
+    /// commands of the source repository.
+    #[arg(long, env = "DOTSTRAP_ALLOW_PLUGINS", global = true)]
+    pub allow_plugins: bool,
+
+    /// Run the commands templates call with the `exec` helper, inlining
+    /// their output.
+    #[arg(long, env = "DOTSTRAP_ALLOW_TEMPLATE_EXEC", global = true)]
+    pub allow_template_exec: bool,
+
+    /// Only render and link the templates whose destination or source path
+    /// matches this glob, skipping every other step. May be repeated.
+    #[arg(long, value_name = "GLOB", global = true)]
+    pub only: Vec<String>,
+
+    /// Apply this module even when the values turn it off. May be repeated.
+    #[arg(long, value_name = "MODULE", global = true)]
+    pub enable: Vec<String>,
+
+    /// Leave out this module and the modules requiring it. May be repeated.
+    #[arg(long, value_name = "MODULE", global = true)]
+    pub disable: Vec<String>,
+
+    /// Output shell completion scripts for the given shell, by default the
+    /// one of `$SHELL`, and exit.
     #[arg(
         long = "generate-completions",
         value_name = "SHELL",
         value_parser = value_parser!(Shell),
+        num_args = 0..=1,
         id = "generate_completions"
     )]
-    pub generate_completions: Option<Shell>,
+    pub generate_completions: Option<Option<Shell>>,
+
+    /// Install the completion scripts where the shell loads them from,
+    /// adding a managed block to its startup file if needed, instead of
+    /// printing them.
+    #[arg(long = "install", requires = "generate_completions")]
+    pub install_completions: bool,
+
+    /// Write the manual pages of dotstrap and its subcommands, and of the
+    /// help topics, into the given directory and exit.
+    #[arg(
+        long = "generate-manpages",
+        value_name = "DIR",
+        conflicts_with = "generate_completions"
+    )]
+    pub generate_manpages: Option<PathBuf>,
+}
+
+impl Cli {
+    /// Source given to the `apply`, `update`, or `sync` subcommand, or else
+    /// `SOURCE`.
+    pub fn effective_source(&self) -> Option<&str> {
+        match &self.command {
+            Some(Command::Apply {
+                source: Some(source),
+                ..
+            })
+            | Some(Command::Update {
+                source: Some(source),
+            })
+            | Some(Command::Sync {
+                source: Some(source),
+                ..
+            }) => Some(source),
+            _ => self.source.as_deref(),
+        }
+    }
+
+    /// Time after which commands are killed, see `--command-timeout`.
+    pub fn command_timeout(&self) -> Option<Duration> {
+        self.command_timeout.map(Duration::from_secs)
+    }
+
+    /// Commands rendering templates may run, see `--allow-plugins` and
+    /// `--allow-template-exec`.
+    pub fn template_permissions(&self) -> Permissions {
+        Permissions {
+            plugins: self.allow_plugins,
+            exec: self.allow_template_exec,
+        }
+    }
+
+    /// Turn on the settings `--ci` implies, keeping an `--output` given as
+    /// told by `matches`.
+    pub fn apply_ci(&mut self, matches: &ArgMatches) {
+        self.non_interactive = true;
+        self.strict = true;
+        self.color = ColorChoice::Never;
+        if matches
+            .value_source("output")
+            .is_none_or(|source| source == ValueSource::DefaultValue)
+        {
+            self.output = OutputFormat::Json;
+        }
+    }
+
+    /// Fill the settings given neither as flags nor through their
+    /// environment variables, as told by `matches`, from `config`.
+    pub fn apply_user_config(&mut self, matches: &ArgMatches, config: UserConfig) {
+        let unset = |id: &str| {
+            matches
+                .value_source(id)
+                .is_none_or(|source| source == ValueSource::DefaultValue)
+        };
+        if self.effective_source().is_none() {
+            self.source = config.source;
+        }
+        if unset("profile") {
+            self.profile = self.profile.take().or(config.profile);
+        }
+        if unset("home") && self.home.is_empty() {
+            self.home = config.home.into_iter().chain(config.targets).collect();
+        }
+        if unset("staging_dir") {
+            self.staging_dir = self.staging_dir.take().or(config.staging_dir);
+        }
+        if unset("color")
+            && let Some(color) = config.color
+        {
+            self.color = color;
+        }
+        if unset("skip_brew")
+            && let Some(skip_brew) = config.skip_brew
+        {
+            self.skip_brew = skip_brew;
+        }
+        if unset("command_timeout") {
+            self.command_timeout = self.command_timeout.take().or(config.command_timeout);
+        }
+        if unset("jobs")
+            && let Some(jobs) = config.jobs
+        {
+            self.jobs = jobs;
+        }
+        if unset("escalation")
+            && let Some(escalation) = config.escalation
+        {
+            self.escalation = escalation;
+        }
+        if unset("mode")
+            && let Some(mode) = config.mode
+        {
+            self.mode = mode;
+        }
+        if unset("trusted_keys") && self.trusted_keys.is_empty() {
+            self.trusted_keys = config.trusted_keys;
+        }
+    }
+}
+
+/// Formats the run report can be printed in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable summary.
+    #[default]
+    Text,
+    /// The full report as a JSON document.
+    Json,
+    /// The full report as a YAML document.
+    Yaml,
+}
+
+/// Dotfiles managers `dotstrap import` reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImportTool {
+    /// A chezmoi source directory.
+    Chezmoi,
+    /// A GNU stow directory, one package per subdirectory.
+    Stow,
+    /// A dotbot `install.conf.yaml`.
+    Dotbot,
+}
+
+/// Resources `dotstrap list` shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ListKind {
+    /// Templates and the files they are linked to.
+    Templates,
+    /// Packages of `packages.yaml` and the manifest.
+    Packages,
+    /// Secrets of `secrets/secrets.yaml`.
+    Secrets,
+}
+
+/// Whether output is colored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorChoice {
+    /// Color terminals unless `NO_COLOR` is set.
+    #[default]
+    Auto,
+    /// Always color.
+    Always,
+    /// Never color.
+    Never,
+}
+
+/// Kind of machine a run sets up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunMode {
+    /// A workstation: files are linked and every step runs.
+    #[default]
+    Host,
+    /// A container or devcontainer: files are copied, casks, Mac App Store
+    /// apps, and macOS defaults are skipped, and a read-only or throwaway
+    /// home only draws warnings.
+    Container,
+}
+
+impl ColorChoice {
+    /// Whether to color a stream, given whether it is a terminal.
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                is_terminal && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+        }
+    }
+}
+
+/// Subcommands supported by dotstrap.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Apply the dotfiles, which is also what runs without a subcommand.
+    Apply {
+        /// Git repository URL or local path to apply (defaults to `SOURCE`).
+        #[arg(value_name = "SOURCE")]
+        source: Option<String>,
+        /// Apply the plan saved by `dotstrap plan` instead of a source.
+        #[arg(long, value_name = "FILE", conflicts_with = "source")]
+        plan: Option<PathBuf>,
+    },
+    /// Save the files a run would link and the commands it would run, for
+    /// review and `dotstrap apply --plan`.
+    Plan {
+        /// File to save the plan to, printed when omitted.
+        #[arg(short = 'o', long = "out", value_name = "FILE")]
+        out: Option<PathBuf>,
+    },
+    /// Pull the latest dotfiles, summarise upstream changes, and apply them.
+    Update {
+        /// Git repository URL or local path to update (defaults to `SOURCE`).
+        #[arg(value_name = "SOURCE")]
+        source: Option<String>,
+    },
+    /// Pull and apply the latest dotfiles like `update`, or manage a
+    /// schedule running `update` periodically.
+    Sync {
+        /// Git repository URL or local path to sync (defaults to `SOURCE`).
+        #[arg(value_name = "SOURCE")]
+        source: Option<String>,
+
+        /// Install a launchd agent (macOS) or systemd user timer running
+        /// `dotstrap update` periodically.
+        #[arg(long, conflicts_with = "uninstall_schedule")]
+        install_schedule: bool,
+
+        /// Remove the schedule installed by `--install-schedule`.
+        #[arg(long)]
+        uninstall_schedule: bool,
+
+        /// Interval of the schedule, e.g. `30m`, `1h`, or `1d`.
+        #[arg(long, value_name = "INTERVAL", default_value = "1h", value_parser = parse_interval)]
+        every: Duration,
+    },
+    /// Re-render and re-link the templates of a local source whenever its
+    /// files change.
+    Watch {
+        /// Local source to watch (defaults to `SOURCE`).
+        #[arg(value_name = "PATH")]
+        path: Option<PathBuf>,
+    },
+    /// Revert the links changed by the last applied run, restoring backups.
+    Rollback,
+    /// Check the link targets, staged file digests, and modes of the files
+    /// managed by the last run against the state it recorded.
+    Verify,
+    /// Remove the staging directory and the cached clones of sources other
+    /// than the last applied one.
+    Clean {
+        /// Also remove the `.dotstrap-backups` directories next to the
+        /// managed files.
+        #[arg(long)]
+        backups: bool,
+    },
+    /// Upgrade the manifest of a local source to the latest version in
+    /// place.
+    Migrate {
+        /// Local source to migrate (defaults to `SOURCE`).
+        #[arg(value_name = "PATH")]
+        path: Option<PathBuf>,
+    },
+    /// Add the packages installed on this machine to the `packages.yaml` of
+    /// a local source.
+    Snapshot {
+        /// Local source to update (defaults to `SOURCE`).
+        #[arg(value_name = "PATH")]
+        path: Option<PathBuf>,
+    },
+    /// Convert the package lists of a local source for other tools.
+    Export {
+        #[command(subcommand)]
+        format: ExportFormat,
+    },
+    /// Convert the dotfiles of another manager into a new source.
+    Import {
+        /// Manager the dotfiles come from.
+        #[arg(long, value_enum)]
+        from: ImportTool,
+        /// chezmoi source directory, stow directory, or dotbot directory or
+        /// configuration file.
+        #[arg(value_name = "PATH")]
+        path: PathBuf,
+        /// Directory the source is written to.
+        #[arg(value_name = "DEST", default_value = ".")]
+        into: PathBuf,
+    },
+    /// Show the template a managed file is rendered from.
+    Owner {
+        /// Managed file, relative to the target home or absolute.
+        #[arg(value_name = "DEST")]
+        destination: PathBuf,
+    },
+    /// Write the edits made to a managed file back into its template in the
+    /// local source.
+    ReAdd {
+        /// Managed file, relative to the target home or absolute.
+        #[arg(value_name = "DEST")]
+        destination: PathBuf,
+    },
+    /// Open the template a managed file is rendered from in `$EDITOR`.
+    Edit {
+        /// Managed file, relative to the target home or absolute.
+        #[arg(value_name = "DEST")]
+        destination: PathBuf,
+        /// Render and link the file again once the editor exits, if the
+        /// template changed.
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Print the directory of the source, cloning remote sources into the
+    /// cache `update` uses when they are not there yet.
+    SourcePath,
+    /// Start `$SHELL` in the directory of the source, see `source-path`.
+    Cd,
+    /// Browse the plan of a dry run in a terminal UI, with the diff of each
+    /// file, then apply it while following its progress.
+    #[cfg(feature = "tui")]
+    Tui,
+    /// List the templates, packages, and secrets the source declares, with
+    /// their status on this host.
+    List {
+        /// Kind of resources to list, all of them when omitted.
+        #[arg(value_enum)]
+        kind: Option<ListKind>,
+    },
+    /// Check the variables templates read against the values, secrets, and
+    /// facts of the source, and report the values no template reads.
+    Lint,
+    /// Render the templates against the fixture contexts under `tests/` in
+    /// the source and compare them with the expected outputs of each case.
+    Test {
+        /// Test cases to run, all of them when omitted.
+        cases: Vec<String>,
+        /// Replace the expected outputs with the rendered files.
+        #[arg(long)]
+        update: bool,
+    },
+    /// List past runs recorded in `~/.dotstrap/history.jsonl`.
+    History {
+        #[command(subcommand)]
+        action: Option<HistoryAction>,
+    },
+    /// Show the help of a subcommand, or a help topic: `manifest` or
+    /// `secrets`.
+    Help {
+        /// Help topic, or subcommand, e.g. `export brewfile`.
+        #[arg(value_name = "TOPIC")]
+        topic: Vec<String>,
+    },
+}
+
+/// Actions of the `history` subcommand.
+#[derive(Debug, Subcommand)]
+pub enum HistoryAction {
+    /// Show the details of a recorded run.
+    Show {
+        /// Identifier of the run, as listed by `dotstrap history`.
+        id: u64,
+    },
+}
+
+/// Formats of the `export` subcommand.
+#[derive(Debug, Subcommand)]
+pub enum ExportFormat {
+    /// Write the Homebrew packages as a `Brewfile` for `brew bundle`.
+    Brewfile {
+        /// Local source to export (defaults to `SOURCE`).
+        #[arg(value_name = "PATH")]
+        path: Option<PathBuf>,
+        /// Write the Brewfile to `FILE` instead of standard output.
+        #[arg(long, value_name = "FILE")]
+        file: Option<PathBuf>,
+    },
+}
+
+/// Parse an interval written as a number followed by `s`, `m`, `h`, or `d`.
+fn parse_interval(value: &str) -> Result<Duration, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("`{value}` does not start with a number"))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" | "" => 60,
+        "h" => 3_600,
+        "d" => 86_400,
+        _ => return Err(format!("unknown unit `{unit}`, expected s, m, h, or d")),
+    };
+    match amount.saturating_mul(seconds) {
+        0 => Err("the interval must be positive".into()),
+        secs => Ok(Duration::from_secs(secs)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    fn parse(args: &[&str]) -> (Cli, ArgMatches) {
+        let matches = Cli::command().try_get_matches_from(args).unwrap();
+        (Cli::from_arg_matches(&matches).unwrap(), matches)
+    }
+
+    #[test]
+    fn apply_user_config_only_fills_unset_flags() {
+        let config = UserConfig {
+            source: Some("~/dotfiles".into()),
+            profile: Some("work".into()),
+            color: Some(ColorChoice::Never),
+            skip_brew: Some(true),
+            trusted_keys: vec!["ABCD1234".into()],
+            ..UserConfig::default()
+        };
+
+        let (mut cli, matches) = parse(&["dotstrap", "apply", "--color", "always"]);
+        cli.apply_user_config(&matches, config.clone());
+        assert_eq!(cli.effective_source(), Some("~/dotfiles"));
+        assert_eq!(cli.profile.as_deref(), Some("work"));
+        assert_eq!(cli.color, ColorChoice::Always);
+        assert!(cli.skip_brew);
+        assert_eq!(cli.trusted_keys, vec!["ABCD1234"]);
+
+        let (mut cli, matches) = parse(&["dotstrap", "update", "./other", "--profile", "home"]);
+        cli.apply_user_config(&matches, config);
+        assert_eq!(cli.effective_source(), Some("./other"));
+        assert_eq!(cli.profile.as_deref(), Some("home"));
+    }
+
+    #[test]
+    fn apply_user_config_targets_every_configured_home_unless_given() {
+        let config = UserConfig {
+            home: Some("/home/me".into()),
+            targets: vec!["/srv/chroot/root".into()],
+            ..UserConfig::default()
+        };
+
+        let (mut cli, matches) = parse(&["dotstrap", "apply"]);
+        cli.apply_user_config(&matches, config.clone());
+        assert_eq!(
+            cli.home,
+            [PathBuf::from("/home/me"), PathBuf::from("/srv/chroot/root")]
+        );
+
+        let (mut cli, matches) = parse(&["dotstrap", "--home", "/a", "--home", "/b"]);
+        cli.apply_user_config(&matches, config);
+        assert_eq!(cli.home, [PathBuf::from("/a"), PathBuf::from("/b")]);
+    }
+
+    #[test]
+    fn apply_ci_implies_strict_unattended_output() {
+        let (mut cli, matches) = parse(&["dotstrap", "apply", "--ci"]);
+        cli.apply_ci(&matches);
+        assert!(cli.non_interactive && cli.strict);
+        assert_eq!(cli.color, ColorChoice::Never);
+        assert_eq!(cli.output, OutputFormat::Json);
+
+        let (mut cli, matches) = parse(&["dotstrap", "apply", "--ci", "--output", "yaml"]);
+        cli.apply_ci(&matches);
+        assert_eq!(cli.output, OutputFormat::Yaml);
+    }
+
+    #[test]
+    fn parse_interval_accepts_units() {
+        assert_eq!(parse_interval("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_interval("30m"), Ok(Duration::from_secs(1_800)));
+        assert_eq!(parse_interval("15"), Ok(Duration::from_secs(900)));
+        assert_eq!(parse_interval("1d"), Ok(Duration::from_secs(86_400)));
+        assert!(parse_interval("0h").is_err());
+        assert!(parse_interval("2w").is_err());
+        assert!(parse_interval("h").is_err());
+    }
 }
@@ -0,0 +1,256 @@
+//! Upgrading a manifest to [`MANIFEST_VERSION`] in place.
+//!
+//! Version 2 accepts everything version 1 does, so migrating bumps the
+//! `version` line, keeping comments and formatting, and folds the separate
+//! package files into the manifest's `packages` section. A `Brewfile` is left
+//! alone for `brew bundle`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use super::{
+    BREW_PATH, MANIFEST_NAME, MANIFEST_VERSION, Manifest, PACKAGES_NAME, WINGET_PATH, WingetSpec,
+    yaml_error,
+};
+use crate::errors::{DotstrapError, Result};
+
+/// Changes made, or planned in dry-run mode, by a migration.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Migration {
+    /// Manifest that was migrated.
+    pub path: PathBuf,
+    /// Version the manifest declared before migrating.
+    pub from: u8,
+    pub to: u8,
+    /// Human readable description of every change, empty when the manifest
+    /// was already up to date.
+    pub changes: Vec<String>,
+    pub dry_run: bool,
+}
+
+/// Rewrite the manifest of `repo` to the latest version.
+pub fn migrate(repo: &Path, dry_run: bool) -> Result<Migration> {
+    let path = repo.join(MANIFEST_NAME);
+    let text = fs::read_to_string(&path)?;
+    let manifest: Manifest =
+        serde_yaml::from_str(&text).map_err(|source| yaml_error(source, path.clone()))?;
+    let mut migration = Migration {
+        path: path.clone(),
+        from: manifest.version,
+        to: MANIFEST_VERSION,
+        changes: Vec::new(),
+        dry_run,
+    };
+    match manifest.version {
+        MANIFEST_VERSION => return Ok(migration),
+        1 => {}
+        0 => return Err(DotstrapError::ManifestMissingVersion(path)),
+        version => {
+            return Err(DotstrapError::UnsupportedManifestVersion { path, version });
+        }
+    }
+
+    let invalid = |reason: &str| DotstrapError::Migration {
+        path: path.clone(),
+        reason: reason.to_string(),
+    };
+    let mut migrated =
+        bump_version(&text).ok_or_else(|| invalid("no top-level `version: 1` line to rewrite"))?;
+    migration
+        .changes
+        .push(format!("set version to {MANIFEST_VERSION}"));
+
+    let mut folded = Vec::new();
+    if manifest.packages.is_none() {
+        let (section, files) = packages_section(repo)?;
+        if !section.is_empty() {
+            if !migrated.ends_with('\n') {
+                migrated.push('\n');
+            }
+            migrated.push_str("packages:\n");
+            migrated.push_str(&section);
+            for file in &files {
+                migration.changes.push(format!(
+                    "move {} into the `packages` section",
+                    file.display()
+                ));
+            }
+            folded = files;
+        }
+    }
+    // Never write a manifest that would not load.
+    serde_yaml::from_str::<Manifest>(&migrated)
+        .map_err(|err| invalid(&format!("the migrated manifest does not parse: {err}")))?;
+
+    if !dry_run {
+        fs::write(&path, migrated)?;
+        for file in &folded {
+            fs::remove_file(repo.join(file))?;
+            if let Some(parent) = file.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                // Only succeeds once the directory is empty.
+                let _ = fs::remove_dir(repo.join(parent));
+            }
+        }
+    }
+    Ok(migration)
+}
+
+/// `text` with its top-level `version: 1` line set to the latest version.
+fn bump_version(text: &str) -> Option<String> {
+    let mut found = false;
+    let lines: Vec<String> = text
+        .split_inclusive('\n')
+        .map(|line| {
+            let Some(rest) = line.strip_prefix("version:").filter(|_| !found) else {
+                return line.to_string();
+            };
+            let (value, comment) = rest.split_once('#').unwrap_or((rest, ""));
+            if value.trim() != "1" {
+                return line.to_string();
+            }
+            found = true;
+            let newline = if line.ends_with('\n') { "\n" } else { "" };
+            let comment = comment.trim_end();
+            if comment.is_empty() {
+                format!("version: {MANIFEST_VERSION}{newline}")
+            } else {
+                format!("version: {MANIFEST_VERSION} #{comment}{newline}")
+            }
+        })
+        .collect();
+    found.then(|| lines.concat())
+}
+
+/// Body of the `packages` section built from the package files of `repo`,
+/// with the files it replaces.
+fn packages_section(repo: &Path) -> Result<(String, Vec<PathBuf>)> {
+    // Refuse to fold files that would not load on their own.
+    super::load_packages_spec(repo)?;
+    let packages = repo.join(PACKAGES_NAME);
+    if packages.exists() {
+        let section = indent(&fs::read_to_string(&packages)?, 2);
+        return Ok((section, vec![PathBuf::from(PACKAGES_NAME)]));
+    }
+    let mut section = String::new();
+    let mut files = Vec::new();
+    let brew = repo.join(BREW_PATH);
+    if brew.exists() {
+        section.push_str("  brew:\n");
+        section.push_str(&indent(&fs::read_to_string(&brew)?, 4));
+        files.push(PathBuf::from(BREW_PATH));
+    }
+    let winget = repo.join(WINGET_PATH);
+    if winget.exists() {
+        let bytes = fs::read(&winget)?;
+        let spec: WingetSpec =
+            serde_yaml::from_slice(&bytes).map_err(|source| DotstrapError::Yaml {
+                source,
+                path: winget.clone(),
+            })?;
+        section.push_str("  winget:\n");
+        for package in &spec.packages {
+            let package = serde_yaml::to_string(package).map_err(|source| DotstrapError::Yaml {
+                source,
+                path: winget.clone(),
+            })?;
+            section.push_str(&format!("    - {}\n", package.trim_end()));
+        }
+        files.push(PathBuf::from(WINGET_PATH));
+    }
+    Ok((section, files))
+}
+
+/// Indent every line of a YAML document by `width` spaces, dropping document
+/// markers.
+fn indent(document: &str, width: usize) -> String {
+    document
+        .lines()
+        .filter(|line| !matches!(line.trim_end(), "---" | "..."))
+        .map(|line| {
+            if line.trim().is_empty() {
+                "\n".to_string()
+            } else {
+                format!("{:width$}{line}\n", "")
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn repo(manifest: &str) -> TempDir {
+        let repo = TempDir::new().unwrap();
+        fs::write(repo.path().join(MANIFEST_NAME), manifest).unwrap();
+        repo
+    }
+
+    #[test]
+    fn bump_version_keeps_comments_and_nested_keys() {
+        let text = "# dotfiles\nversion: 1 # schema\nbinaries:\n  - version: 1\n";
+
+        assert_eq!(
+            bump_version(text).as_deref(),
+            Some("# dotfiles\nversion: 2 # schema\nbinaries:\n  - version: 1\n")
+        );
+        assert_eq!(bump_version("templates: []\n"), None);
+    }
+
+    #[test]
+    fn migrate_folds_legacy_package_files() {
+        let repo = repo("version: 1\ntemplates:\n  - source: a\n    destination: .a\n");
+        fs::create_dir_all(repo.path().join("brew")).unwrap();
+        fs::write(
+            repo.path().join(BREW_PATH),
+            "# CLI tools\nformulae:\n  - ripgrep\n",
+        )
+        .unwrap();
+        fs::create_dir_all(repo.path().join("winget")).unwrap();
+        fs::write(repo.path().join(WINGET_PATH), "packages: [Git.Git]\n").unwrap();
+
+        let migration = migrate(repo.path(), false).unwrap();
+
+        assert_eq!((migration.from, migration.to), (1, 2));
+        assert_eq!(migration.changes.len(), 3);
+        let manifest = super::super::load_manifest(repo.path()).unwrap();
+        assert_eq!(manifest.version, 2);
+        let packages = manifest.packages.unwrap();
+        assert_eq!(packages.brew.formulae, vec!["ripgrep".into()]);
+        assert_eq!(packages.winget, vec!["Git.Git"]);
+        assert!(!repo.path().join("brew").exists());
+        assert!(
+            fs::read_to_string(repo.path().join(MANIFEST_NAME))
+                .unwrap()
+                .contains("    # CLI tools\n")
+        );
+    }
+
+    #[test]
+    fn migrate_dry_run_leaves_files_alone() {
+        let manifest = "version: 1\ntemplates:\n  - source: a\n    destination: .a\n";
+        let repo = repo(manifest);
+        fs::write(repo.path().join(PACKAGES_NAME), "apt: [ripgrep]\n").unwrap();
+
+        let migration = migrate(repo.path(), true).unwrap();
+
+        assert_eq!(migration.changes.len(), 2);
+        assert_eq!(
+            fs::read_to_string(repo.path().join(MANIFEST_NAME)).unwrap(),
+            manifest
+        );
+        assert!(repo.path().join(PACKAGES_NAME).exists());
+    }
+
+    #[test]
+    fn migrate_leaves_current_manifests_unchanged() {
+        let repo = repo("version: 2\ntemplates:\n  - source: a\n    destination: .a\n");
+
+        let migration = migrate(repo.path(), false).unwrap();
+
+        assert!(migration.changes.is_empty());
+    }
+}
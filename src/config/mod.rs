@@ -1,73 +1,1257 @@
 //! Configuration loading helpers and strongly typed configuration models.
 
-use std::collections::HashMap;
-use std::fs;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use serde::Deserialize;
 
 use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::filesystem::{FileSystem, OsFileSystem};
+use crate::infrastructure::paths::{self, IgnoreRules, KnownDirs};
+use crate::infrastructure::repository::RepoHandle;
+use values::LayeredValues;
 
-const MANIFEST_NAME: &str = "manifest.yaml";
-const VALUES_NAME: &str = "values.yaml";
+pub mod migrate;
+pub mod values;
+
+/// Latest manifest version, see [`migrate`] for upgrading older ones.
+pub const MANIFEST_VERSION: u8 = 2;
+
+pub const MANIFEST_NAME: &str = "manifest.yaml";
+pub const MANIFEST_DIR: &str = "manifest.d";
+pub const VALUES_NAME: &str = "values.yaml";
+pub const PACKAGES_NAME: &str = "packages.yaml";
+/// Ignore file of the repository, in the gitignore syntax.
+pub const IGNORE_NAME: &str = ".dotstrapignore";
+/// Size above which templates must be copied instead of rendered, unless the
+/// manifest sets `max_template_size`.
+pub const DEFAULT_MAX_TEMPLATE_SIZE: u64 = 1024 * 1024;
 const BREW_PATH: &str = "brew/packages.yaml";
+const BREWFILE_NAME: &str = "Brewfile";
+const WINGET_PATH: &str = "winget/packages.yaml";
+const MACOS_DEFAULTS_PATH: &str = "macos/defaults.yaml";
+const GNOME_SETTINGS_PATH: &str = "gnome/settings.yaml";
 
 /// Manifest describing how templates should be rendered and linked.
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Manifest {
+    /// Schema version; 0 when the file does not declare one, which only
+    /// `manifest.d` files may omit.
+    #[serde(default)]
     pub version: u8,
+    /// Source of a base layer this manifest overlays, e.g. a shared team repo.
+    #[serde(default)]
+    pub base: Option<String>,
+    /// Default branch, tag, or commit applied when `--ref` is not given.
+    #[serde(default, rename = "ref")]
+    pub git_ref: Option<String>,
+    /// Keys also allowed to sign the bases and modules of a source verified
+    /// with `--trusted-key`.
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
+    /// Initialise the source repository's submodules before rendering.
+    #[serde(default)]
+    pub submodules: bool,
+    #[serde(default)]
+    pub templates: Vec<TemplateMapping>,
+    #[serde(default)]
+    pub brew: BrewSettings,
+    #[serde(default)]
+    pub binaries: Vec<BinarySpec>,
+    #[serde(default)]
+    pub downloads: Vec<DownloadSpec>,
+    #[serde(default)]
+    pub cron: Vec<CronEntry>,
+    #[serde(default)]
+    pub ssh: Option<SshSettings>,
+    #[serde(default)]
+    pub repos: Vec<RepoSpec>,
+    /// Values asked interactively when `values.yaml` does not define them.
+    #[serde(default)]
+    pub questions: Vec<Question>,
+    /// Named sets of values selected with `--profile` (version 2).
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Commands run around the run (version 2).
+    #[serde(default)]
+    pub hooks: Hooks,
+    /// Package lists declared inline instead of in `packages.yaml` (version 2).
+    #[serde(default)]
+    pub packages: Option<PackagesSpec>,
+    /// Largest template rendered, in bytes; larger files must be declared
+    /// with `copy: true`.
+    #[serde(default)]
+    pub max_template_size: Option<u64>,
+    /// Template helpers served by commands of the repository, only run with
+    /// `--allow-plugins`.
+    #[serde(default)]
+    pub plugins: Vec<PluginSpec>,
+    /// Named bundles of entries, applied in dependency order (version 2).
+    #[serde(default)]
+    pub modules: BTreeMap<String, Module>,
+    /// Modules merged into the other entries by [`Manifest::flatten_modules`],
+    /// in the order they apply.
+    #[serde(skip)]
+    pub module_order: Vec<String>,
+    /// Modules left out by [`Manifest::flatten_modules`], in dependency order.
+    #[serde(skip)]
+    pub disabled_modules: Vec<String>,
+    /// Value defaults of the merged modules, below `values.yaml`.
+    #[serde(skip)]
+    pub module_values: HashMap<String, serde_json::Value>,
+    /// Checkouts of the merged modules, which their templates are read from.
+    #[serde(skip)]
+    pub module_checkouts: Vec<Arc<RepoHandle>>,
+}
+
+/// Named bundle of templates, packages, hooks, and value defaults, applied
+/// after the modules it requires.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Module {
+    /// Repository whose manifest provides the entries of the module, e.g.
+    /// `github.com/org/dotstrap-zsh@v2`, see [`load_module`].
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Modules whose entries must apply first, e.g. `fonts` for `terminal`.
+    #[serde(default)]
+    pub requires: Vec<String>,
     #[serde(default)]
     pub templates: Vec<TemplateMapping>,
+    #[serde(default)]
+    pub packages: Option<PackagesSpec>,
+    #[serde(default)]
+    pub hooks: Hooks,
+    /// Defaults of values, overridden by `values.yaml` and profiles.
+    #[serde(default)]
+    pub values: HashMap<String, serde_json::Value>,
+    /// Checkout of `source`, kept as long as its templates may be read.
+    #[serde(skip)]
+    pub checkout: Option<Arc<RepoHandle>>,
+}
+
+impl Module {
+    /// This module, loaded from its `source`, with the entries and values
+    /// the manifest declares for it added.
+    pub fn extend(mut self, declared: Module) -> Module {
+        self.source = declared.source;
+        self.requires = declared.requires;
+        self.templates.extend(declared.templates);
+        self.packages = match (self.packages, declared.packages) {
+            (Some(base), Some(overlay)) => Some(base.layer(overlay)),
+            (base, overlay) => base.or(overlay),
+        };
+        self.hooks.pre_apply.extend(declared.hooks.pre_apply);
+        self.hooks.post_apply.extend(declared.hooks.post_apply);
+        self.values.extend(declared.values);
+        self
+    }
+}
+
+impl Manifest {
+    /// Overlay `overlay` on top of this (base) manifest.
+    ///
+    /// Templates are replaced by destination and other lists are concatenated;
+    /// settings such as `brew` and `ssh` come from the overlay when it sets
+    /// them. Template sources must already be absolute or relative to the
+    /// overlay repository, see [`Manifest::anchor_templates`].
+    pub fn layer(mut self, overlay: Manifest) -> Manifest {
+        self.templates.retain(|base| {
+            !overlay
+                .templates
+                .iter()
+                .any(|template| template.destination == base.destination)
+        });
+        self.templates.extend(overlay.templates);
+        self.plugins.retain(|base| {
+            !overlay
+                .plugins
+                .iter()
+                .any(|plugin| plugin.name == base.name)
+        });
+        self.plugins.extend(overlay.plugins);
+        self.modules.extend(overlay.modules);
+        // Bases do not get to trust more signers than the source does.
+        self.trusted_keys = overlay.trusted_keys;
+        self.binaries.extend(overlay.binaries);
+        self.downloads.extend(overlay.downloads);
+        self.cron.extend(overlay.cron);
+        self.repos.extend(overlay.repos);
+        self.questions.extend(overlay.questions);
+        self.profiles.extend(overlay.profiles);
+        self.hooks.pre_apply.extend(overlay.hooks.pre_apply);
+        self.hooks.post_apply.extend(overlay.hooks.post_apply);
+        let packages = match (self.packages.take(), overlay.packages) {
+            (Some(base), Some(overlay)) => Some(base.layer(overlay)),
+            (base, overlay) => overlay.or(base),
+        };
+        Manifest {
+            version: overlay.version,
+            base: overlay.base,
+            git_ref: overlay.git_ref,
+            submodules: self.submodules || overlay.submodules,
+            brew: overlay.brew,
+            ssh: overlay.ssh.or(self.ssh),
+            packages,
+            max_template_size: overlay.max_template_size.or(self.max_template_size),
+            ..self
+        }
+    }
+
+    /// Largest template rendered, in bytes.
+    pub fn template_size_limit(&self) -> u64 {
+        self.max_template_size.unwrap_or(DEFAULT_MAX_TEMPLATE_SIZE)
+    }
+
+    /// Make template sources and plugin commands absolute so they resolve
+    /// from another layer.
+    pub fn anchor_templates(&mut self, repo: &Path) {
+        let modules = self.modules.values_mut();
+        for template in modules.flat_map(|module| &mut module.templates) {
+            template.source = repo.join(&template.source);
+        }
+        for template in &mut self.templates {
+            template.source = repo.join(&template.source);
+        }
+        for plugin in &mut self.plugins {
+            plugin.command = repo.join(&plugin.command);
+        }
+    }
+
+    /// Merge the entries of every module into those of the manifest, modules
+    /// first, each after the modules it requires, and record their order.
+    ///
+    /// Templates and hooks keep that order, package sections are layered in
+    /// it, and module values become defaults, later modules taking
+    /// precedence. Modules are ordered by name where `requires` leaves a
+    /// choice. Fails on a missing or circular requirement.
+    ///
+    /// `toggles` turns modules on or off by name; a module is left out when
+    /// turned off or when a module it requires is left out, and explicitly
+    /// turning on a module that requires a left out one fails. Toggles
+    /// naming no module are ignored.
+    pub fn flatten_modules(&mut self, toggles: &BTreeMap<String, bool>) -> Result<()> {
+        let mut order = module_order(&self.modules)?;
+        let mut disabled: Vec<String> = Vec::new();
+        for name in &order {
+            let requires = &self.modules[name].requires;
+            let missing = requires.iter().find(|required| disabled.contains(required));
+            match (toggles.get(name), missing) {
+                (Some(false), _) => disabled.push(name.clone()),
+                (Some(true), Some(required)) => {
+                    return Err(DotstrapError::RequiredModuleDisabled {
+                        name: name.clone(),
+                        required: required.clone(),
+                    });
+                }
+                (None, Some(_)) => disabled.push(name.clone()),
+                _ => {}
+            }
+        }
+        order.retain(|name| !disabled.contains(name));
+        let mut values = LayeredValues::default();
+        values.layer("module defaults", std::mem::take(&mut self.module_values));
+        let mut templates = Vec::new();
+        let mut hooks = Hooks::default();
+        let mut packages: Option<PackagesSpec> = None;
+        for name in &disabled {
+            self.modules.remove(name);
+        }
+        for name in &order {
+            let Some(module) = self.modules.remove(name) else {
+                continue;
+            };
+            templates.extend(module.templates);
+            hooks.pre_apply.extend(module.hooks.pre_apply);
+            hooks.post_apply.extend(module.hooks.post_apply);
+            packages = match (packages, module.packages) {
+                (Some(base), Some(overlay)) => Some(base.layer(overlay)),
+                (base, overlay) => base.or(overlay),
+            };
+            values.layer(&format!("module `{name}`"), module.values);
+            self.module_checkouts.extend(module.checkout);
+        }
+        self.module_values = values.values();
+        templates.append(&mut self.templates);
+        self.templates = templates;
+        hooks.pre_apply.append(&mut self.hooks.pre_apply);
+        hooks.post_apply.append(&mut self.hooks.post_apply);
+        self.hooks = hooks;
+        self.packages = match (packages, self.packages.take()) {
+            (Some(base), Some(overlay)) => Some(base.layer(overlay)),
+            (base, overlay) => base.or(overlay),
+        };
+        self.module_order.extend(order);
+        self.disabled_modules.extend(disabled);
+        Ok(())
+    }
+
+    /// Drop the templates and hooks whose `when` condition does not hold on
+    /// `os` with the selected `profile`.
+    ///
+    /// Returns the dropped templates, each with the reason it was dropped.
+    pub fn apply_conditions(
+        &mut self,
+        os: &str,
+        profile: Option<&str>,
+    ) -> Vec<(TemplateMapping, String)> {
+        let applies = |when: &Option<Condition>| {
+            when.as_ref()
+                .is_none_or(|condition| condition.matches(os, profile))
+        };
+        let mut dropped = Vec::new();
+        self.templates.retain(|template| {
+            let mismatch = template
+                .when
+                .as_ref()
+                .and_then(|condition| condition.mismatch(os, profile));
+            match mismatch {
+                Some(reason) => {
+                    dropped.push((template.clone(), reason));
+                    false
+                }
+                None => true,
+            }
+        });
+        self.hooks.pre_apply.retain(|hook| applies(&hook.when));
+        self.hooks.post_apply.retain(|hook| applies(&hook.when));
+        dropped
+    }
+
+    /// Drop the templates and hooks whose `enabled` expression is false,
+    /// as decided by `is_enabled`, once the template context is known.
+    ///
+    /// Returns the dropped templates, each with the reason it was dropped.
+    pub fn apply_enabled(
+        &mut self,
+        mut is_enabled: impl FnMut(&str) -> Result<bool>,
+    ) -> Result<Vec<(TemplateMapping, String)>> {
+        let mut enabled = |expression: &Option<String>| match expression {
+            Some(expression) => is_enabled(expression),
+            None => Ok(true),
+        };
+        let mut dropped = Vec::new();
+        let mut templates = Vec::with_capacity(self.templates.len());
+        for template in std::mem::take(&mut self.templates) {
+            if enabled(&template.enabled)? {
+                templates.push(template);
+            } else {
+                let reason = format!(
+                    "disabled: `{}` is false",
+                    template.enabled.as_deref().unwrap_or_default()
+                );
+                dropped.push((template, reason));
+            }
+        }
+        self.templates = templates;
+        for hooks in [&mut self.hooks.pre_apply, &mut self.hooks.post_apply] {
+            let mut kept = Vec::with_capacity(hooks.len());
+            for hook in std::mem::take(hooks) {
+                if enabled(&hook.enabled)? {
+                    kept.push(hook);
+                }
+            }
+            *hooks = kept;
+        }
+        Ok(dropped)
+    }
+
+    /// Replace the `{appdata}`-style placeholders starting template
+    /// destinations with the matching directory of `dirs`.
+    pub fn expand_destinations(&mut self, dirs: &KnownDirs) -> Result<()> {
+        for template in &mut self.templates {
+            template.destination = dirs.expand(&template.destination)?;
+        }
+        Ok(())
+    }
+
+    /// Keep only the templates whose destination or source path matches one
+    /// of the glob `patterns`, for `--only`, dropping the other entries and
+    /// steps of the manifest. Fails when no template matches.
+    pub fn retain_only(&mut self, patterns: &[String]) -> Result<()> {
+        let matches = |template: &TemplateMapping| {
+            patterns.iter().any(|pattern| {
+                let destination = pattern.strip_prefix("~/").unwrap_or(pattern);
+                paths::glob_match(destination, &template.destination)
+                    || paths::glob_match(&format!("**/{pattern}"), &template.source)
+            })
+        };
+        self.templates.retain(matches);
+        if self.templates.is_empty() {
+            return Err(DotstrapError::NothingSelected {
+                patterns: patterns.join(", "),
+            });
+        }
+        self.binaries.clear();
+        self.downloads.clear();
+        self.cron.clear();
+        self.ssh = None;
+        self.repos.clear();
+        self.hooks = Hooks::default();
+        self.packages = None;
+        Ok(())
+    }
+
+    /// First field of this manifest that requires version 2, if any.
+    fn first_v2_field(&self) -> Option<&'static str> {
+        if !self.profiles.is_empty() {
+            Some("profiles")
+        } else if !self.hooks.pre_apply.is_empty() || !self.hooks.post_apply.is_empty() {
+            Some("hooks")
+        } else if self.packages.is_some() {
+            Some("packages")
+        } else if !self.plugins.is_empty() {
+            Some("plugins")
+        } else if !self.modules.is_empty() {
+            Some("modules")
+        } else if self
+            .templates
+            .iter()
+            .any(|template| template.when.is_some())
+        {
+            Some("when")
+        } else if self
+            .templates
+            .iter()
+            .any(|template| template.enabled.is_some())
+        {
+            Some("enabled")
+        } else {
+            None
+        }
+    }
+}
+
+/// Names of `modules` ordered so each follows the modules it requires.
+fn module_order(modules: &BTreeMap<String, Module>) -> Result<Vec<String>> {
+    fn visit<'a>(
+        name: &'a str,
+        modules: &'a BTreeMap<String, Module>,
+        path: &mut Vec<&'a str>,
+        order: &mut Vec<String>,
+    ) -> Result<()> {
+        if order.iter().any(|done| done == name) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|visiting| *visiting == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name);
+            return Err(DotstrapError::ModuleCycle(cycle.join(" -> ")));
+        }
+        path.push(name);
+        for required in &modules[name].requires {
+            if !modules.contains_key(required) {
+                return Err(DotstrapError::UnknownModule {
+                    name: required.clone(),
+                    required_by: name.to_string(),
+                });
+            }
+            visit(required, modules, path, order)?;
+        }
+        path.pop();
+        order.push(name.to_string());
+        Ok(())
+    }
+
+    let mut order = Vec::with_capacity(modules.len());
+    for name in modules.keys() {
+        visit(name, modules, &mut Vec::new(), &mut order)?;
+    }
+    Ok(order)
+}
+
+/// Values overlaid on `values.yaml` when the profile is selected.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
+    pub values: HashMap<String, serde_json::Value>,
+}
+
+/// Restriction of an entry to some operating systems or profiles; an empty
+/// list places no restriction.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    /// Operating systems as named by Rust, e.g. `macos`, `linux`, `windows`.
+    #[serde(default)]
+    pub os: Vec<String>,
+    #[serde(default)]
+    pub profile: Vec<String>,
+}
+
+impl Condition {
+    /// Whether the condition holds on `os` with the selected `profile`.
+    pub fn matches(&self, os: &str, profile: Option<&str>) -> bool {
+        (self.os.is_empty() || self.os.iter().any(|name| name == os))
+            && (self.profile.is_empty()
+                || profile.is_some_and(|profile| self.profile.iter().any(|name| name == profile)))
+    }
+
+    /// Why the condition does not hold on `os` with the selected `profile`,
+    /// or `None` when it does.
+    pub fn mismatch(&self, os: &str, profile: Option<&str>) -> Option<String> {
+        if !self.os.is_empty() && !self.os.iter().any(|name| name == os) {
+            return Some(format!(
+                "platform mismatch: {os}, not {}",
+                self.os.join(" or ")
+            ));
+        }
+        if !self.matches(os, profile) {
+            return Some(format!(
+                "profile mismatch: {}, not {}",
+                profile.unwrap_or("no profile"),
+                self.profile.join(" or ")
+            ));
+        }
+        None
+    }
+}
+
+/// Shell commands run before rendering templates and after every other step.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    #[serde(default)]
+    pub pre_apply: Vec<Hook>,
+    #[serde(default)]
+    pub post_apply: Vec<Hook>,
+}
+
+/// Shell command, rendered as a Handlebars template, and its condition.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(from = "HookDef")]
+pub struct Hook {
+    pub run: String,
+    pub when: Option<Condition>,
+    /// Expression evaluated against the template context, see
+    /// [`TemplateMapping::enabled`].
+    pub enabled: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HookDef {
+    Command(String),
+    Detailed(DetailedHook),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DetailedHook {
+    run: String,
+    #[serde(default)]
+    when: Option<Condition>,
+    #[serde(default)]
+    enabled: Option<String>,
+}
+
+impl From<HookDef> for Hook {
+    fn from(def: HookDef) -> Self {
+        match def {
+            HookDef::Command(run) => Hook {
+                run,
+                when: None,
+                enabled: None,
+            },
+            HookDef::Detailed(DetailedHook { run, when, enabled }) => Hook { run, when, enabled },
+        }
+    }
+}
+
+/// Template value asked on first run when no layer's `values.yaml` sets it.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Question {
+    /// Name of the value in the template context.
+    pub name: String,
+    /// Text shown when asking, defaulting to the name.
+    #[serde(default)]
+    pub prompt: Option<String>,
+    #[serde(default, rename = "type")]
+    pub kind: QuestionKind,
+    /// Answer used when the user accepts the default or cannot be asked.
+    #[serde(default)]
+    pub default: Option<serde_json::Value>,
+    /// Allowed answers for string questions.
+    #[serde(default)]
+    pub choices: Vec<String>,
+}
+
+/// Type an answer is validated and converted to.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QuestionKind {
+    #[default]
+    String,
+    Bool,
+    Integer,
+    Number,
+}
+
+/// Git repository cloned into the home directory.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RepoSpec {
+    pub url: String,
+    /// Clone destination relative to the home directory.
+    pub path: PathBuf,
+    #[serde(default)]
+    pub branch: Option<String>,
+    /// Clone only the latest commit.
+    #[serde(default)]
+    pub shallow: bool,
+}
+
+/// SSH key generated on hosts that do not have one yet.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SshSettings {
+    /// Private key path relative to the home directory.
+    #[serde(default = "default_ssh_key")]
+    pub key: PathBuf,
+    #[serde(default)]
+    pub comment: Option<String>,
+    /// Print the public key at the end of the run.
+    #[serde(default)]
+    pub print_public_key: bool,
+    /// Copy the public key to the system clipboard.
+    #[serde(default)]
+    pub copy_public_key: bool,
+}
+
+impl Default for SshSettings {
+    fn default() -> Self {
+        Self {
+            key: default_ssh_key(),
+            comment: None,
+            print_public_key: false,
+            copy_public_key: false,
+        }
+    }
+}
+
+fn default_ssh_key() -> PathBuf {
+    PathBuf::from(".ssh/id_ed25519")
+}
+
+/// Periodic job installed in the user crontab.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CronEntry {
+    /// Five-field cron schedule, e.g. `0 3 * * *`.
+    pub schedule: String,
+    /// Command to run, rendered as a Handlebars template.
+    pub command: String,
+}
+
+/// File fetched over HTTPS and linked into the home directory.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DownloadSpec {
+    pub url: String,
+    /// Path relative to the home directory.
+    pub destination: PathBuf,
+    /// Expected hex encoded SHA-256 digest of the file.
+    pub sha256: String,
+    #[serde(default)]
+    pub mode: Option<u32>,
+}
+
+/// Handlebars helper served by a command of the repository.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PluginSpec {
+    /// Name the helper is called by in templates, e.g. `slugify`.
+    pub name: String,
+    /// Executable, relative to the repository.
+    pub command: PathBuf,
+    /// Arguments passed before the JSON encoded call.
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// Executable installed from a GitHub release asset.
+#[derive(Debug, Deserialize, Clone)]
+pub struct BinarySpec {
+    /// Repository in `owner/name` form.
+    pub repo: String,
+    /// Release tag to install, or `latest`.
+    #[serde(default = "default_latest")]
+    pub version: String,
+    /// Asset file name; `{version}`, `{os}`, and `{arch}` are substituted.
+    pub asset: String,
+    /// Expected hex encoded SHA-256 digest of the asset.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Name of the installed executable, defaulting to the repository name.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Path of the executable inside the archive, searched by name when unset.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    /// Directory, relative to the home directory, the executable is linked into.
+    #[serde(default = "default_bin_dir")]
+    pub install_dir: PathBuf,
+}
+
+fn default_latest() -> String {
+    "latest".into()
+}
+
+fn default_bin_dir() -> PathBuf {
+    PathBuf::from(".local/bin")
+}
+
+/// Manifest settings controlling how Homebrew is driven.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct BrewSettings {
+    /// Run `brew update` before installing packages.
+    #[serde(default = "default_true")]
+    pub update: bool,
+    /// Skip `brew update` when it already ran within this many hours.
+    #[serde(default)]
+    pub update_interval_hours: Option<u64>,
+    /// Install Homebrew with the official script when it is missing.
+    #[serde(default)]
+    pub install: bool,
+    /// Environment of every `brew` command, e.g. `HOMEBREW_NO_ANALYTICS: 1`.
+    #[serde(default, deserialize_with = "deserialize_env")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl Default for BrewSettings {
+    fn default() -> Self {
+        BrewSettings {
+            update: true,
+            update_interval_hours: None,
+            install: false,
+            env: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Environment variables given as a map of scalars, numbers and booleans
+/// written as they read, e.g. `HOMEBREW_NO_ANALYTICS: 1`.
+fn deserialize_env<'de, D>(
+    deserializer: D,
+) -> std::result::Result<BTreeMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let env = BTreeMap::<String, serde_json::Value>::deserialize(deserializer)?;
+    env.into_iter()
+        .map(|(key, value)| match value {
+            serde_json::Value::String(value) => Ok((key, value)),
+            serde_json::Value::Number(_) | serde_json::Value::Bool(_) => {
+                Ok((key, value.to_string()))
+            }
+            _ => Err(serde::de::Error::custom(format!(
+                "environment variable `{key}` must be a string, number, or boolean"
+            ))),
+        })
+        .collect()
 }
 
 /// Mapping between a template source file and its destination.
 #[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct TemplateMapping {
     pub source: PathBuf,
     pub destination: PathBuf,
     #[serde(default)]
     pub mode: Option<u32>,
+    /// Only link the template when the condition holds (version 2).
+    #[serde(default)]
+    pub when: Option<Condition>,
+    /// Only link the template when this Handlebars expression, evaluated
+    /// against the template context, is true (version 2).
+    #[serde(default)]
+    pub enabled: Option<String>,
+    /// Copy the source as is instead of rendering it, for binary files and
+    /// files too large to render.
+    #[serde(default)]
+    pub copy: bool,
+    /// Whether later runs replace the destination, or only create it.
+    #[serde(default)]
+    pub overwrite: Overwrite,
+}
+
+/// When a template entry writes its destination.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Overwrite {
+    /// Keep the destination linked to the latest output.
+    #[default]
+    Always,
+    /// Seed the destination with a copy of the output when it is missing,
+    /// and never touch it again, e.g. for `~/.gitconfig.local`.
+    Never,
+}
+
+impl TemplateMapping {
+    /// Whether the destination is only written when missing.
+    pub fn create_only(&self) -> bool {
+        self.overwrite == Overwrite::Never
+    }
 }
 
 /// Declarative definition of Homebrew taps, formulae, and casks.
 #[derive(Debug, Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct BrewSpec {
     #[serde(default)]
     pub taps: Vec<String>,
     #[serde(default)]
-    pub formulae: Vec<String>,
+    pub formulae: Vec<Formula>,
     #[serde(default)]
     pub casks: Vec<String>,
+    #[serde(default)]
+    pub mas: Vec<MasApp>,
+    /// Omit `brew update` from the plan; decided per run, never read from YAML.
+    #[serde(skip)]
+    pub skip_update: bool,
+    /// Environment of the `brew` commands, taken from the manifest's `brew`
+    /// settings; never read from YAML.
+    #[serde(skip)]
+    pub env: BTreeMap<String, String>,
+}
+
+/// Homebrew formula, optionally pinned to a versioned formula.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(from = "FormulaDef")]
+pub struct Formula {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FormulaDef {
+    Name(String),
+    Detailed {
+        name: String,
+        #[serde(default)]
+        version: Option<String>,
+    },
+}
+
+impl From<FormulaDef> for Formula {
+    fn from(def: FormulaDef) -> Self {
+        match def {
+            FormulaDef::Name(name) => Formula {
+                name,
+                version: None,
+            },
+            FormulaDef::Detailed { name, version } => Formula { name, version },
+        }
+    }
+}
+
+impl From<&str> for Formula {
+    fn from(name: &str) -> Self {
+        Formula {
+            name: name.to_string(),
+            version: None,
+        }
+    }
+}
+
+impl Formula {
+    /// Name passed to `brew install`, e.g. `python@3.12` when pinned.
+    pub fn installed_name(&self) -> String {
+        match &self.version {
+            Some(version) => format!("{}@{version}", self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+impl BrewSpec {
+    /// Ensure every pinned formula can be expressed as a versioned formula.
+    pub fn validate(&self) -> Result<()> {
+        for formula in &self.formulae {
+            let Some(version) = &formula.version else {
+                continue;
+            };
+            let invalid = |reason: &str| DotstrapError::InvalidPin {
+                name: formula.name.clone(),
+                reason: reason.to_string(),
+            };
+            if formula.name.contains('@') {
+                return Err(invalid("formula name already selects a version"));
+            }
+            let numeric = version
+                .split('.')
+                .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()));
+            if !numeric {
+                return Err(invalid("version must be dot-separated numbers"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Mac App Store application installed through `mas`.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct MasApp {
+    pub name: String,
+    pub id: u64,
+}
+
+/// Declarative package lists grouped by package manager.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct PackagesSpec {
+    /// Only install the packages of this file or section when this
+    /// expression, evaluated against the template context, is true.
+    #[serde(default)]
+    pub enabled: Option<String>,
+    #[serde(default)]
+    pub brew: BrewSpec,
+    #[serde(default)]
+    pub apt: Vec<String>,
+    #[serde(default)]
+    pub pacman: Vec<String>,
+    #[serde(default)]
+    pub dnf: Vec<String>,
+    #[serde(default)]
+    pub cargo: Vec<String>,
+    #[serde(default)]
+    pub winget: Vec<String>,
+    /// Sections handled by third-party package providers, keyed by name.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+impl PackagesSpec {
+    /// Overlay `overlay` on top of this (base) specification, concatenating
+    /// package lists and replacing third-party sections by name.
+    pub fn layer(mut self, overlay: PackagesSpec) -> PackagesSpec {
+        self.brew.taps.extend(overlay.brew.taps);
+        self.brew.formulae.extend(overlay.brew.formulae);
+        self.brew.casks.extend(overlay.brew.casks);
+        self.brew.mas.extend(overlay.brew.mas);
+        self.apt.extend(overlay.apt);
+        self.pacman.extend(overlay.pacman);
+        self.dnf.extend(overlay.dnf);
+        self.cargo.extend(overlay.cargo);
+        self.winget.extend(overlay.winget);
+        self.extra.extend(overlay.extra);
+        self
+    }
+}
+
+/// Declarative list of Windows Package Manager identifiers.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct WingetSpec {
+    #[serde(default)]
+    pub packages: Vec<String>,
+}
+
+/// macOS user default written with `defaults write`.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct MacDefault {
+    pub domain: String,
+    pub key: String,
+    #[serde(rename = "type")]
+    pub kind: DefaultsType,
+    pub value: serde_json::Value,
+}
+
+/// Value types supported by `defaults write`.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DefaultsType {
+    String,
+    Int,
+    Float,
+    Bool,
+}
+
+/// GNOME setting written with `gsettings set`.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct GnomeSetting {
+    pub schema: String,
+    pub key: String,
+    pub value: serde_json::Value,
 }
 
 /// Load and validate the manifest from the repository root.
+///
+/// The entries of every `*.yaml` and `*.yml` file in `manifest.d/`, in file
+/// name order, are merged after those of `manifest.yaml`, which may then be
+/// omitted.
 pub fn load_manifest(repo: &Path) -> Result<Manifest> {
-    let path = repo.join(MANIFEST_NAME);
-    let bytes = fs::read(&path)?;
-    let manifest: Manifest =
-        serde_yaml::from_slice(&bytes).map_err(|source| DotstrapError::Yaml {
-            source,
-            path: path.clone(),
-        })?;
-    if manifest.version != 1 {
+    load_manifest_in(&OsFileSystem, repo)
+}
+
+/// Load the manifest as [`load_manifest`] does, through `fs`.
+pub fn load_manifest_in(fs: &dyn FileSystem, repo: &Path) -> Result<Manifest> {
+    let (manifest, path) = read_manifest_in(fs, repo)?;
+    if manifest.templates.is_empty()
+        && manifest
+            .modules
+            .values()
+            .all(|module| module.templates.is_empty() && module.source.is_none())
+    {
+        return Err(DotstrapError::ManifestMissingTemplates(path));
+    }
+    Ok(manifest)
+}
+
+/// Load the repository of a module `source` as a module: the entries of
+/// its manifest, its own modules merged in, its `packages.yaml`, and its
+/// `values.yaml` as value defaults. Template sources are made absolute.
+///
+/// Unlike a source, a module may declare no template.
+pub fn load_module(repo: &Path) -> Result<Module> {
+    let (mut manifest, _) = read_manifest_in(&OsFileSystem, repo)?;
+    manifest.anchor_templates(repo);
+    manifest.flatten_modules(&BTreeMap::new())?;
+    let mut values = LayeredValues::default();
+    values.layer("module defaults", manifest.module_values);
+    layer_values(&mut values, repo)?;
+    let packages = match (load_packages_spec(repo)?, manifest.packages) {
+        (Some(base), Some(overlay)) => Some(base.layer(overlay)),
+        (base, overlay) => base.or(overlay),
+    };
+    Ok(Module {
+        templates: manifest.templates,
+        packages,
+        hooks: manifest.hooks,
+        values: values.values(),
+        ..Module::default()
+    })
+}
+
+/// Read and validate the manifest of `repo` through `fs`, returning it with
+/// the path naming it in errors.
+fn read_manifest_in(fs: &dyn FileSystem, repo: &Path) -> Result<(Manifest, PathBuf)> {
+    let mut path = repo.join(MANIFEST_NAME);
+    let mut files = manifest_fragments(fs, repo)?;
+    if fs.exists(&path) || files.is_empty() {
+        files.insert(0, path.clone());
+    } else {
+        path = repo.join(MANIFEST_DIR);
+    }
+    let mut merged = Manifest::default();
+    let mut declared = BTreeMap::new();
+    for file in &files {
+        let bytes = fs.read(file)?;
+        let fragment: Manifest =
+            serde_yaml::from_slice(&bytes).map_err(|source| yaml_error(source, file.clone()))?;
+        merge_fragment(&mut merged, fragment, file, &mut declared)?;
+    }
+    let path = declared.remove("version").unwrap_or(path);
+    if merged.version == 0 {
+        return Err(DotstrapError::ManifestMissingVersion(path));
+    }
+    if !(1..=MANIFEST_VERSION).contains(&merged.version) {
         return Err(DotstrapError::UnsupportedManifestVersion {
             path: path.clone(),
-            version: manifest.version,
+            version: merged.version,
         });
     }
-    if manifest.templates.is_empty() {
-        return Err(DotstrapError::ManifestMissingTemplates(path));
+    if merged.version < 2
+        && let Some(field) = merged.first_v2_field()
+    {
+        return Err(DotstrapError::ManifestNeedsMigration {
+            path,
+            field: field.to_string(),
+        });
     }
-    Ok(manifest)
+    Ok((merged, path))
+}
+
+/// Manifest files of the `manifest.d` directory, sorted by name.
+fn manifest_fragments(fs: &dyn FileSystem, repo: &Path) -> Result<Vec<PathBuf>> {
+    let dir = repo.join(MANIFEST_DIR);
+    if !fs.metadata(&dir).is_ok_and(|metadata| metadata.is_dir()) {
+        return Ok(Vec::new());
+    }
+    let ignore = load_ignore_in(fs, repo)?;
+    let mut files = Vec::new();
+    for path in fs.read_dir(&dir)? {
+        if path
+            .strip_prefix(repo)
+            .is_ok_and(|relative| ignore.is_ignored(relative, false))
+        {
+            continue;
+        }
+        let is_yaml = path
+            .extension()
+            .is_some_and(|ext| ext == "yaml" || ext == "yml");
+        if is_yaml && fs.metadata(&path).is_ok_and(|metadata| metadata.is_file()) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Merge the manifest file at `path` into `merged`.
+///
+/// Lists are concatenated and package sections layered. A setting, profile,
+/// or destination may only be declared by one file, which `declared` records
+/// by key; files may repeat the same `version`.
+fn merge_fragment(
+    merged: &mut Manifest,
+    fragment: Manifest,
+    path: &Path,
+    declared: &mut BTreeMap<String, PathBuf>,
+) -> Result<()> {
+    let mut keys = Vec::new();
+    if fragment.version != 0 && fragment.version != merged.version {
+        keys.push("version".to_string());
+    }
+    for (key, set) in [
+        ("base", fragment.base.is_some()),
+        ("ref", fragment.git_ref.is_some()),
+        ("ssh", fragment.ssh.is_some()),
+        ("brew", fragment.brew != BrewSettings::default()),
+    ] {
+        if set {
+            keys.push(key.to_string());
+        }
+    }
+    keys.extend(
+        fragment
+            .profiles
+            .keys()
+            .map(|name| format!("profiles.{name}")),
+    );
+    keys.extend(
+        fragment
+            .templates
+            .iter()
+            .map(|template| format!("destination {}", template.destination.display())),
+    );
+    keys.extend(
+        fragment
+            .plugins
+            .iter()
+            .map(|plugin| format!("plugins.{}", plugin.name)),
+    );
+    keys.extend(
+        fragment
+            .modules
+            .keys()
+            .map(|name| format!("modules.{name}")),
+    );
+    for key in keys {
+        if let Some(first) = declared.get(&key).filter(|first| first.as_path() != path) {
+            return Err(DotstrapError::ManifestConflict {
+                key,
+                first: first.clone(),
+                second: path.to_path_buf(),
+            });
+        }
+        declared.insert(key, path.to_path_buf());
+    }
+
+    if fragment.version != 0 {
+        merged.version = fragment.version;
+    }
+    merged.base = merged.base.take().or(fragment.base);
+    merged.git_ref = merged.git_ref.take().or(fragment.git_ref);
+    merged.ssh = merged.ssh.take().or(fragment.ssh);
+    if fragment.brew != BrewSettings::default() {
+        merged.brew = fragment.brew;
+    }
+    merged.submodules |= fragment.submodules;
+    merged.trusted_keys.extend(fragment.trusted_keys);
+    merged.templates.extend(fragment.templates);
+    merged.plugins.extend(fragment.plugins);
+    merged.modules.extend(fragment.modules);
+    merged.binaries.extend(fragment.binaries);
+    merged.downloads.extend(fragment.downloads);
+    merged.cron.extend(fragment.cron);
+    merged.repos.extend(fragment.repos);
+    merged.questions.extend(fragment.questions);
+    merged.profiles.extend(fragment.profiles);
+    merged.hooks.pre_apply.extend(fragment.hooks.pre_apply);
+    merged.hooks.post_apply.extend(fragment.hooks.post_apply);
+    merged.packages = match (merged.packages.take(), fragment.packages) {
+        (Some(base), Some(overlay)) => Some(base.layer(overlay)),
+        (base, overlay) => base.or(overlay),
+    };
+    Ok(())
+}
+
+/// Wrap a parse error of `path`, suggesting the closest known key when the
+/// error is about an unknown one, e.g. `destiantion` for `destination`.
+fn yaml_error(source: serde_yaml::Error, path: PathBuf) -> DotstrapError {
+    match suggest_key(&source.to_string()) {
+        Some(suggestion) => DotstrapError::UnknownKey {
+            source,
+            path,
+            suggestion,
+        },
+        None => DotstrapError::Yaml { source, path },
+    }
+}
+
+/// Closest expected field named by a serde "unknown field" message.
+fn suggest_key(message: &str) -> Option<String> {
+    let (_, rest) = message.split_once("unknown field `")?;
+    let (field, rest) = rest.split_once('`')?;
+    let (_, expected) = rest.split_once("expected ")?;
+    suggest_name(field, expected.split('`').skip(1).step_by(2))
+}
+
+/// Candidate closest to `name`, when it is close enough to be a misspelling.
+pub(crate) fn suggest_name<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<String> {
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= 2.max(candidate.len() / 3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+/// Levenshtein distance between `a` and `b`, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
 }
 
 /// Load shared values that seed the templating context.
 pub fn load_values(repo: &Path) -> Result<HashMap<String, serde_json::Value>> {
+    load_values_in(&OsFileSystem, repo)
+}
+
+/// Merge the shared values of `repo` over `values`, as the layer named after
+/// their file.
+pub fn layer_values(values: &mut LayeredValues, repo: &Path) -> Result<()> {
+    let layer = repo.join(VALUES_NAME).display().to_string();
+    values.layer(&layer, load_values(repo)?);
+    Ok(())
+}
+
+/// Load the shared values as [`load_values`] does, through `fs`.
+pub fn load_values_in(
+    fs: &dyn FileSystem,
+    repo: &Path,
+) -> Result<HashMap<String, serde_json::Value>> {
     let path = repo.join(VALUES_NAME);
-    if !path.exists() {
+    if !fs.exists(&path) {
         return Ok(HashMap::new());
     }
-    let bytes = fs::read(&path)?;
+    let bytes = fs.read(&path)?;
     let json_value: serde_json::Value =
         serde_yaml::from_slice(&bytes).map_err(|source| DotstrapError::Yaml {
             source,
@@ -79,23 +1263,207 @@ pub fn load_values(repo: &Path) -> Result<HashMap<String, serde_json::Value>> {
     }
 }
 
-/// Load the optional Homebrew specification from the repository root.
+/// Load the package specification from `packages.yaml`, falling back to the
+/// legacy `brew/packages.yaml` and `winget/packages.yaml` files when absent.
+pub fn load_packages_spec(repo: &Path) -> Result<Option<PackagesSpec>> {
+    load_packages_spec_in(&OsFileSystem, repo)
+}
+
+/// Load the rules of the repository's `.dotstrapignore`, excluding paths
+/// relative to `repo`; without one nothing is ignored.
+pub fn load_ignore(repo: &Path) -> Result<IgnoreRules> {
+    load_ignore_in(&OsFileSystem, repo)
+}
+
+/// Load the ignore rules as [`load_ignore`] does, through `fs`.
+pub fn load_ignore_in(fs: &dyn FileSystem, repo: &Path) -> Result<IgnoreRules> {
+    let path = repo.join(IGNORE_NAME);
+    if !fs.exists(&path) {
+        return Ok(IgnoreRules::default());
+    }
+    Ok(IgnoreRules::parse(&fs.read_to_string(&path)?))
+}
+
+/// Load the package specification as [`load_packages_spec`] does, through
+/// `fs`.
+pub fn load_packages_spec_in(fs: &dyn FileSystem, repo: &Path) -> Result<Option<PackagesSpec>> {
+    let path = repo.join(PACKAGES_NAME);
+    if fs.exists(&path) {
+        let bytes = fs.read(&path)?;
+        let spec: PackagesSpec =
+            serde_yaml::from_slice(&bytes).map_err(|source| yaml_error(source, path.clone()))?;
+        spec.brew.validate()?;
+        return Ok(Some(spec));
+    }
+    let brew = load_brew_spec_in(fs, repo)?;
+    let winget = load_winget_spec_in(fs, repo)?;
+    if brew.is_none() && winget.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(PackagesSpec {
+        brew: brew.unwrap_or_default(),
+        winget: winget.map(|spec| spec.packages).unwrap_or_default(),
+        ..PackagesSpec::default()
+    }))
+}
+
+/// Package files of `repo` that a `packages.yaml` would take precedence over.
+pub fn legacy_package_files(repo: &Path) -> Vec<PathBuf> {
+    legacy_package_files_in(&OsFileSystem, repo)
+}
+
+/// [`legacy_package_files`] through `fs`.
+pub fn legacy_package_files_in(fs: &dyn FileSystem, repo: &Path) -> Vec<PathBuf> {
+    [BREW_PATH, BREWFILE_NAME, WINGET_PATH]
+        .into_iter()
+        .map(PathBuf::from)
+        .filter(|path| fs.exists(&repo.join(path)))
+        .collect()
+}
+
+/// Load the optional Homebrew specification from the repository root,
+/// falling back to a `Brewfile` when `brew/packages.yaml` is absent.
 pub fn load_brew_spec(repo: &Path) -> Result<Option<BrewSpec>> {
+    load_brew_spec_in(&OsFileSystem, repo)
+}
+
+/// Load the Homebrew specification as [`load_brew_spec`] does, through `fs`.
+pub fn load_brew_spec_in(fs: &dyn FileSystem, repo: &Path) -> Result<Option<BrewSpec>> {
     let path = repo.join(BREW_PATH);
-    if !path.exists() {
+    if !fs.exists(&path) {
+        let brewfile = repo.join(BREWFILE_NAME);
+        if fs.exists(&brewfile) {
+            let contents = fs.read_to_string(&brewfile)?;
+            return parse_brewfile(&contents, &brewfile).map(Some);
+        }
+        return Ok(None);
+    }
+    let bytes = fs.read(&path)?;
+    let spec: BrewSpec =
+        serde_yaml::from_slice(&bytes).map_err(|source| yaml_error(source, path.clone()))?;
+    spec.validate()?;
+    Ok(Some(spec))
+}
+
+/// Parse the `tap`, `brew`, `cask`, and `mas` entries of a Brewfile.
+///
+/// Other Brewfile directives are ignored, as are options following the
+/// package name.
+pub fn parse_brewfile(contents: &str, path: &Path) -> Result<BrewSpec> {
+    let mut spec = BrewSpec::default();
+    for (idx, raw) in contents.lines().enumerate() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |message: &str| DotstrapError::Brewfile {
+            path: path.to_path_buf(),
+            line: idx + 1,
+            message: message.to_string(),
+        };
+        let (directive, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        if !matches!(directive, "tap" | "brew" | "cask" | "mas") {
+            continue;
+        }
+        let (name, options) =
+            quoted(rest.trim()).ok_or_else(|| invalid("expected a quoted name"))?;
+        match directive {
+            "tap" => spec.taps.push(name),
+            "brew" => spec.formulae.push(name.as_str().into()),
+            "cask" => spec.casks.push(name),
+            _ => {
+                let id = options
+                    .split(',')
+                    .filter_map(|option| option.trim().strip_prefix("id:"))
+                    .find_map(|id| id.trim().parse().ok())
+                    .ok_or_else(|| invalid("mas entries require a numeric `id:`"))?;
+                spec.mas.push(MasApp { name, id });
+            }
+        }
+    }
+    Ok(spec)
+}
+
+/// Write `spec` as a Brewfile for `brew bundle`, the inverse of
+/// [`parse_brewfile`]. Pinned formulae are written as versioned formulae.
+pub fn format_brewfile(spec: &BrewSpec) -> String {
+    let mut brewfile = String::new();
+    for tap in &spec.taps {
+        brewfile.push_str(&format!("tap \"{tap}\"\n"));
+    }
+    for formula in &spec.formulae {
+        brewfile.push_str(&format!("brew \"{}\"\n", formula.installed_name()));
+    }
+    for cask in &spec.casks {
+        brewfile.push_str(&format!("cask \"{cask}\"\n"));
+    }
+    for app in &spec.mas {
+        brewfile.push_str(&format!("mas \"{}\", id: {}\n", app.name, app.id));
+    }
+    brewfile
+}
+
+fn quoted(input: &str) -> Option<(String, &str)> {
+    let quote = input.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let body = &input[1..];
+    let end = body.find(quote)?;
+    Some((body[..end].to_string(), &body[end + 1..]))
+}
+
+/// Load the macOS defaults declared in `macos/defaults.yaml`.
+pub fn load_macos_defaults(repo: &Path) -> Result<Vec<MacDefault>> {
+    load_macos_defaults_in(&OsFileSystem, repo)
+}
+
+/// Load the macOS defaults as [`load_macos_defaults`] does, through `fs`.
+pub fn load_macos_defaults_in(fs: &dyn FileSystem, repo: &Path) -> Result<Vec<MacDefault>> {
+    let path = repo.join(MACOS_DEFAULTS_PATH);
+    if !fs.exists(&path) {
+        return Ok(Vec::new());
+    }
+    let bytes = fs.read(&path)?;
+    serde_yaml::from_slice(&bytes).map_err(|source| DotstrapError::Yaml { source, path })
+}
+
+/// Load the GNOME settings declared in `gnome/settings.yaml`.
+pub fn load_gnome_settings(repo: &Path) -> Result<Vec<GnomeSetting>> {
+    load_gnome_settings_in(&OsFileSystem, repo)
+}
+
+/// Load the GNOME settings as [`load_gnome_settings`] does, through `fs`.
+pub fn load_gnome_settings_in(fs: &dyn FileSystem, repo: &Path) -> Result<Vec<GnomeSetting>> {
+    let path = repo.join(GNOME_SETTINGS_PATH);
+    if !fs.exists(&path) {
+        return Ok(Vec::new());
+    }
+    let bytes = fs.read(&path)?;
+    serde_yaml::from_slice(&bytes).map_err(|source| DotstrapError::Yaml { source, path })
+}
+
+/// Load the optional winget specification from the repository root.
+pub fn load_winget_spec(repo: &Path) -> Result<Option<WingetSpec>> {
+    load_winget_spec_in(&OsFileSystem, repo)
+}
+
+/// Load the winget specification as [`load_winget_spec`] does, through `fs`.
+pub fn load_winget_spec_in(fs: &dyn FileSystem, repo: &Path) -> Result<Option<WingetSpec>> {
+    let path = repo.join(WINGET_PATH);
+    if !fs.exists(&path) {
         return Ok(None);
     }
-    let bytes = fs::read(&path)?;
-    let spec: BrewSpec = serde_yaml::from_slice(&bytes).map_err(|source| DotstrapError::Yaml {
-        source,
-        path: path.clone(),
-    })?;
+    let bytes = fs.read(&path)?;
+    let spec: WingetSpec =
+        serde_yaml::from_slice(&bytes).map_err(|source| DotstrapError::Yaml {
+            source,
+            path: path.clone(),
+        })?;
     Ok(Some(spec))
 }
 
 #[cfg(test)]
 mod tests {
-    use std::path::Path;
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
 
     #[test]
     fn test_manifest_incorrect_version() {
@@ -130,6 +1498,395 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_manifest_v1_rejects_v2_fields() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join("manifest.yaml"),
+            "version: 1\ntemplates:\n  - source: a\n    destination: .a\nhooks:\n  post_apply: [make]\n",
+        )
+        .unwrap();
+
+        let err = super::load_manifest(dir.path()).unwrap_err();
+
+        assert!(matches!(
+            &err,
+            super::DotstrapError::ManifestNeedsMigration { field, .. } if field == "hooks"
+        ));
+        assert!(err.to_string().contains("run `dotstrap migrate`"));
+    }
+
+    #[test]
+    fn test_manifest_v2_conditions() {
+        let mut manifest = super::load_manifest(Path::new("tests/config-v2")).unwrap();
+        assert_eq!(
+            manifest.hooks.pre_apply[0].run,
+            "echo applying {{system.profile}}"
+        );
+        assert!(manifest.packages.is_some());
+
+        manifest.apply_conditions("linux", None);
+
+        assert_eq!(manifest.templates.len(), 1);
+        assert!(manifest.hooks.post_apply.is_empty());
+    }
+
+    #[test]
+    fn test_manifest_apply_enabled_drops_disabled_entries() {
+        let mut manifest: super::Manifest = serde_yaml::from_str(
+            "version: 2\n\
+             templates:\n\
+             \x20 - source: a\n    destination: .a\n\
+             \x20 - source: vpn\n    destination: .vpn\n    enabled: \"{{work_machine}}\"\n\
+             hooks:\n  post_apply:\n    - run: vpn-setup\n      enabled: \"{{work_machine}}\"\n\
+             packages:\n  enabled: \"{{work_machine}}\"\n  cargo: [bat]\n",
+        )
+        .unwrap();
+        assert_eq!(manifest.first_v2_field(), Some("hooks"));
+        assert_eq!(
+            manifest.packages.as_ref().unwrap().enabled.as_deref(),
+            Some("{{work_machine}}")
+        );
+
+        let dropped = manifest
+            .apply_enabled(|expression| Ok(expression != "{{work_machine}}"))
+            .unwrap();
+
+        assert_eq!(manifest.templates.len(), 1);
+        assert_eq!(dropped[0].0.destination, Path::new(".vpn"));
+        assert_eq!(dropped[0].1, "disabled: `{{work_machine}}` is false");
+        assert!(manifest.hooks.post_apply.is_empty());
+        assert!(manifest.packages.unwrap().extra.is_empty());
+    }
+
+    #[test]
+    fn test_flatten_modules_orders_entries_by_requirement() {
+        let mut manifest: super::Manifest = serde_yaml::from_str(
+            "version: 2\n\
+             templates:\n  - source: zshrc\n    destination: .zshrc\n\
+             packages:\n  cargo: [bat]\n\
+             modules:\n\
+             \x20 terminal:\n\
+             \x20   requires: [fonts]\n\
+             \x20   templates:\n      - source: alacritty\n        destination: .alacritty.toml\n\
+             \x20   values:\n      font_size: 12\n\
+             \x20 fonts:\n\
+             \x20   packages:\n      brew:\n        casks: [font-fira-code]\n\
+             \x20   hooks:\n      post_apply: [fc-cache -f]\n\
+             \x20   values:\n      font: Fira Code\n      font_size: 11\n",
+        )
+        .unwrap();
+        assert_eq!(manifest.first_v2_field(), Some("packages"));
+
+        manifest.flatten_modules(&BTreeMap::new()).unwrap();
+
+        assert_eq!(manifest.module_order, ["fonts", "terminal"]);
+        let destinations: Vec<&Path> = manifest
+            .templates
+            .iter()
+            .map(|template| template.destination.as_path())
+            .collect();
+        assert_eq!(
+            destinations,
+            [Path::new(".alacritty.toml"), Path::new(".zshrc")]
+        );
+        assert_eq!(manifest.hooks.post_apply[0].run, "fc-cache -f");
+        let packages = manifest.packages.unwrap();
+        assert_eq!(packages.brew.casks, ["font-fira-code"]);
+        assert_eq!(packages.cargo, ["bat"]);
+        assert_eq!(manifest.module_values["font"], "Fira Code");
+        assert_eq!(manifest.module_values["font_size"], 12);
+        assert!(manifest.modules.is_empty());
+    }
+
+    #[test]
+    fn test_load_module_reads_a_source_without_templates() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join("manifest.yaml"),
+            "version: 2\nhooks:\n  post_apply: [fc-cache -f]\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("packages.yaml"), "cargo: [bat]\n").unwrap();
+        std::fs::write(dir.path().join("values.yaml"), "font: Fira Code\n").unwrap();
+
+        let module = super::load_module(dir.path()).unwrap();
+        let declared = super::Module {
+            source: Some("github.com/org/fonts".into()),
+            templates: vec![super::TemplateMapping {
+                source: PathBuf::from("fontconfig"),
+                destination: PathBuf::from(".fonts.conf"),
+                mode: None,
+                when: None,
+                enabled: None,
+                copy: false,
+                overwrite: Default::default(),
+            }],
+            values: [("font".to_string(), "Iosevka".into())].into(),
+            ..Default::default()
+        };
+        let module = module.extend(declared);
+
+        assert_eq!(module.source.as_deref(), Some("github.com/org/fonts"));
+        assert_eq!(module.hooks.post_apply[0].run, "fc-cache -f");
+        assert_eq!(module.packages.unwrap().cargo, ["bat"]);
+        assert_eq!(module.templates.len(), 1);
+        assert_eq!(module.values["font"], "Iosevka");
+        assert!(super::load_manifest(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_flatten_modules_rejects_missing_and_circular_requirements() {
+        let module = |requires: &[&str]| super::Module {
+            requires: requires.iter().map(|name| name.to_string()).collect(),
+            ..Default::default()
+        };
+        let mut manifest = super::Manifest {
+            modules: BTreeMap::from([("a".to_string(), module(&["b"]))]),
+            ..Default::default()
+        };
+        assert_eq!(
+            manifest
+                .flatten_modules(&BTreeMap::new())
+                .unwrap_err()
+                .to_string(),
+            "module `a` requires `b`, which is not declared"
+        );
+
+        let mut manifest = super::Manifest {
+            modules: BTreeMap::from([
+                ("a".to_string(), module(&["b"])),
+                ("b".to_string(), module(&["c"])),
+                ("c".to_string(), module(&["a"])),
+            ]),
+            ..Default::default()
+        };
+        let err = manifest.flatten_modules(&BTreeMap::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "modules require each other in a cycle: a -> b -> c -> a"
+        );
+        assert_eq!(err.exit_code(), crate::errors::exit_code::CONFIG);
+    }
+
+    #[test]
+    fn test_flatten_modules_leaves_out_disabled_modules_and_dependents() {
+        let module = |requires: &[&str], destination: &str| super::Module {
+            requires: requires.iter().map(|name| name.to_string()).collect(),
+            templates: vec![super::TemplateMapping {
+                source: PathBuf::from(destination),
+                destination: PathBuf::from(destination),
+                mode: None,
+                when: None,
+                enabled: None,
+                copy: false,
+                overwrite: Default::default(),
+            }],
+            ..Default::default()
+        };
+        let modules = BTreeMap::from([
+            ("gui".to_string(), module(&[], ".gui")),
+            ("terminal".to_string(), module(&["gui"], ".terminal")),
+            ("shell".to_string(), module(&[], ".shell")),
+        ]);
+        let mut manifest = super::Manifest {
+            modules: modules.clone(),
+            ..Default::default()
+        };
+
+        manifest
+            .flatten_modules(&BTreeMap::from([
+                ("gui".to_string(), false),
+                ("unknown".to_string(), false),
+            ]))
+            .unwrap();
+
+        assert_eq!(manifest.module_order, ["shell"]);
+        assert_eq!(manifest.disabled_modules, ["gui", "terminal"]);
+        assert_eq!(manifest.templates.len(), 1);
+        assert!(manifest.modules.is_empty());
+
+        let mut manifest = super::Manifest {
+            modules,
+            ..Default::default()
+        };
+        let err = manifest
+            .flatten_modules(&BTreeMap::from([
+                ("gui".to_string(), false),
+                ("terminal".to_string(), true),
+            ]))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "module `terminal` is enabled but requires `gui`, which is disabled"
+        );
+        assert_eq!(err.exit_code(), crate::errors::exit_code::CONFIG);
+    }
+
+    #[test]
+    fn test_condition_matches_os_and_profile() {
+        let condition = super::Condition {
+            os: vec!["macos".into()],
+            profile: vec!["work".into()],
+        };
+        assert!(condition.matches("macos", Some("work")));
+        assert_eq!(condition.mismatch("macos", Some("work")), None);
+        assert_eq!(
+            condition.mismatch("linux", Some("work")).unwrap(),
+            "platform mismatch: linux, not macos"
+        );
+        assert_eq!(
+            condition.mismatch("macos", None).unwrap(),
+            "profile mismatch: no profile, not work"
+        );
+        assert!(!condition.matches("macos", None));
+        assert!(!condition.matches("linux", Some("work")));
+        assert!(super::Condition::default().matches("linux", None));
+    }
+
+    #[test]
+    fn test_manifest_merges_manifest_d_files() {
+        let manifest = super::load_manifest(Path::new("tests/config-manifest-d")).unwrap();
+
+        let destinations: Vec<&Path> = manifest
+            .templates
+            .iter()
+            .map(|template| template.destination.as_path())
+            .collect();
+        assert_eq!(
+            destinations,
+            vec![
+                Path::new(".profile"),
+                Path::new(".gitconfig"),
+                Path::new(".zshrc")
+            ]
+        );
+        assert_eq!(
+            manifest.packages.unwrap().brew.formulae,
+            vec!["git".into(), "zsh".into()]
+        );
+    }
+
+    #[test]
+    fn test_manifest_loads_from_memory() {
+        use crate::infrastructure::filesystem::MemoryFileSystem;
+
+        let fs = MemoryFileSystem::new()
+            .with_file(
+                "/repo/manifest.yaml",
+                "version: 2\ntemplates:\n  - source: a\n    destination: .zshrc\n",
+            )
+            .with_file(
+                "/repo/manifest.d/git.yaml",
+                "templates:\n  - source: b\n    destination: .gitconfig\n",
+            )
+            .with_file("/repo/packages.yaml", "cargo: [bat]\n");
+
+        let manifest = super::load_manifest_in(&fs, Path::new("/repo")).unwrap();
+        let packages = super::load_packages_spec_in(&fs, Path::new("/repo")).unwrap();
+
+        assert_eq!(manifest.templates.len(), 2);
+        assert_eq!(packages.unwrap().cargo, vec!["bat".to_string()]);
+        assert!(super::load_manifest(Path::new("/repo")).is_err());
+    }
+
+    #[test]
+    fn test_manifest_d_skips_ignored_files() {
+        use crate::infrastructure::filesystem::MemoryFileSystem;
+
+        let template = "templates:\n  - source: a\n    destination: .zshrc\n";
+        let fs = MemoryFileSystem::new()
+            .with_file("/repo/.dotstrapignore", "# work machine only\nlocal.yaml\n")
+            .with_file(
+                "/repo/manifest.d/zsh.yaml",
+                format!("version: 2\n{template}"),
+            )
+            .with_file("/repo/manifest.d/local.yaml", template);
+
+        let manifest = super::load_manifest_in(&fs, Path::new("/repo")).unwrap();
+
+        assert_eq!(manifest.templates.len(), 1);
+        assert!(
+            super::load_ignore_in(&fs, Path::new("/repo"))
+                .unwrap()
+                .is_ignored(Path::new("manifest.d/local.yaml"), false)
+        );
+        assert_eq!(
+            super::load_ignore(Path::new("/missing")).unwrap(),
+            Default::default()
+        );
+    }
+
+    #[test]
+    fn test_manifest_d_conflicts_name_both_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fragments = dir.path().join("manifest.d");
+        std::fs::create_dir(&fragments).unwrap();
+        let template = "templates:\n  - source: a\n    destination: .zshrc\n";
+        std::fs::write(fragments.join("a.yaml"), format!("version: 2\n{template}")).unwrap();
+        std::fs::write(fragments.join("b.yaml"), template).unwrap();
+
+        let err = super::load_manifest(dir.path()).unwrap_err();
+
+        assert!(matches!(
+            &err,
+            super::DotstrapError::ManifestConflict { first, second, .. }
+                if first.ends_with("a.yaml") && second.ends_with("b.yaml")
+        ));
+        assert!(
+            err.to_string()
+                .starts_with("`destination .zshrc` is declared by both")
+        );
+    }
+
+    #[test]
+    fn test_manifest_d_requires_a_version() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fragments = dir.path().join("manifest.d");
+        std::fs::create_dir(&fragments).unwrap();
+        std::fs::write(
+            fragments.join("zsh.yaml"),
+            "templates:\n  - source: a\n    destination: .zshrc\n",
+        )
+        .unwrap();
+
+        let err = super::load_manifest(dir.path()).unwrap_err();
+
+        assert!(matches!(
+            err,
+            super::DotstrapError::ManifestMissingVersion(path) if path.ends_with("manifest.d")
+        ));
+    }
+
+    #[test]
+    fn test_manifest_unknown_key_suggests_field() {
+        let path = Path::new("tests/erroneous-config/manifest-unknown-key");
+        let err = super::load_manifest(path).unwrap_err();
+        assert!(matches!(
+            &err,
+            super::DotstrapError::UnknownKey { suggestion, .. } if suggestion == "destination"
+        ));
+        assert!(err.to_string().contains("did you mean `destination`?"));
+    }
+
+    #[test]
+    fn test_suggest_key_ignores_unrelated_fields() {
+        let message = "unknown field `colour`, expected one of `source`, `destination`, `mode`";
+        assert_eq!(super::suggest_key(message), None);
+        let message = "unknown field `formula`, expected one of `taps`, `formulae`, `casks`, `mas`";
+        assert_eq!(super::suggest_key(message).as_deref(), Some("formulae"));
+    }
+
+    #[test]
+    fn test_download_rejects_misspelled_checksum() {
+        let yaml = "url: https://example.com/tool\ndestination: .local/bin/tool\nsha265: abc\n";
+        let message = serde_yaml::from_str::<super::DownloadSpec>(yaml)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("unknown field `sha265`"));
+        assert_eq!(super::suggest_key(&message).as_deref(), Some("sha256"));
+    }
+
     #[test]
     fn test_values_invalid() {
         let path = Path::new("tests/erroneous-config/values-invalid");
@@ -167,4 +1924,266 @@ mod tests {
             super::DotstrapError::Yaml { .. }
         ));
     }
+
+    #[test]
+    fn test_winget_spec_loaded() {
+        let path = Path::new("tests/config-winget");
+        let spec = super::load_winget_spec(path)
+            .expect("winget spec should parse")
+            .expect("winget spec should be present");
+        assert_eq!(spec.packages, vec!["Git.Git", "Microsoft.PowerShell"]);
+    }
+
+    #[test]
+    fn test_winget_spec_not_found() {
+        let path = Path::new("tests/empty-config");
+        let result = super::load_winget_spec(path);
+        assert!(result.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_packages_spec_sections() {
+        let path = Path::new("tests/config-packages");
+        let spec = super::load_packages_spec(path)
+            .expect("packages spec should parse")
+            .expect("packages spec should be present");
+        assert_eq!(spec.brew.formulae, vec!["ripgrep".into()]);
+        assert_eq!(spec.apt, vec!["ripgrep", "fd-find"]);
+        assert_eq!(spec.cargo, vec!["bat"]);
+        assert!(spec.pacman.is_empty());
+    }
+
+    #[test]
+    fn test_packages_spec_falls_back_to_legacy_files() {
+        let path = Path::new("tests/config-brew");
+        let spec = super::load_packages_spec(path)
+            .expect("legacy brew spec should parse")
+            .expect("legacy brew spec should be present");
+        assert_eq!(spec.brew.taps.len(), 2);
+        assert!(spec.winget.is_empty());
+    }
+
+    #[test]
+    fn test_packages_spec_not_found() {
+        let path = Path::new("tests/empty-config");
+        assert!(super::load_packages_spec(path).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_brew_spec_from_brewfile() {
+        let path = Path::new("tests/config-brewfile");
+        let spec = super::load_brew_spec(path)
+            .expect("Brewfile should parse")
+            .expect("Brewfile should be present");
+        assert_eq!(spec.taps, vec!["homebrew/cask-fonts"]);
+        assert_eq!(spec.formulae, vec!["ripgrep".into(), "neovim".into()]);
+        assert_eq!(spec.casks, vec!["iterm2"]);
+        assert_eq!(
+            spec.mas,
+            vec![super::MasApp {
+                name: "Xcode".into(),
+                id: 497799835
+            }]
+        );
+    }
+
+    #[test]
+    fn test_brewfile_reports_invalid_lines() {
+        let result = super::parse_brewfile("brew ripgrep\n", Path::new("Brewfile"));
+        assert!(matches!(
+            result.unwrap_err(),
+            super::DotstrapError::Brewfile { line: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn test_format_brewfile_round_trips() {
+        let spec = super::load_brew_spec(Path::new("tests/config-brewfile"))
+            .unwrap()
+            .unwrap();
+
+        let brewfile = super::format_brewfile(&spec);
+
+        assert_eq!(
+            brewfile,
+            "tap \"homebrew/cask-fonts\"\nbrew \"ripgrep\"\nbrew \"neovim\"\ncask \"iterm2\"\nmas \"Xcode\", id: 497799835\n"
+        );
+        let parsed = super::parse_brewfile(&brewfile, Path::new("Brewfile")).unwrap();
+        assert_eq!(parsed.formulae, spec.formulae);
+        assert_eq!(parsed.mas, spec.mas);
+    }
+
+    #[test]
+    fn test_brew_spec_pinned_formulae() {
+        let spec: super::BrewSpec =
+            serde_yaml::from_str("formulae:\n  - git\n  - name: python\n    version: \"3.12\"\n")
+                .unwrap();
+        assert!(spec.validate().is_ok());
+        assert_eq!(spec.formulae[0].installed_name(), "git");
+        assert_eq!(spec.formulae[1].installed_name(), "python@3.12");
+    }
+
+    #[test]
+    fn test_brew_spec_rejects_impossible_pins() {
+        for yaml in [
+            "formulae:\n  - name: terraform\n    version: latest\n",
+            "formulae:\n  - name: node@18\n    version: \"18\"\n",
+        ] {
+            let spec: super::BrewSpec = serde_yaml::from_str(yaml).unwrap();
+            assert!(matches!(
+                spec.validate().unwrap_err(),
+                super::DotstrapError::InvalidPin { .. }
+            ));
+        }
+    }
+
+    #[test]
+    fn test_manifest_brew_settings() {
+        let manifest: super::Manifest =
+            serde_yaml::from_str("version: 1\nbrew:\n  update_interval_hours: 12\n").unwrap();
+        assert!(manifest.brew.update);
+        assert_eq!(manifest.brew.update_interval_hours, Some(12));
+    }
+
+    #[test]
+    fn test_manifest_brew_env_reads_scalars_as_written() {
+        let manifest: super::Manifest = serde_yaml::from_str(
+            "version: 1
+brew:
+  env:
+    HOMEBREW_NO_ANALYTICS: 1
+    HOMEBREW_NO_AUTO_UPDATE: true
+    HOMEBREW_CASK_OPTS: --no-quarantine
+",
+        )
+        .unwrap();
+        assert_eq!(manifest.brew.env["HOMEBREW_NO_ANALYTICS"], "1");
+        assert_eq!(manifest.brew.env["HOMEBREW_NO_AUTO_UPDATE"], "true");
+        assert_eq!(manifest.brew.env["HOMEBREW_CASK_OPTS"], "--no-quarantine");
+
+        let err = serde_yaml::from_str::<super::Manifest>(
+            "version: 1
+brew:
+  env:
+    HOMEBREW_NO_ANALYTICS: [1]
+",
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("`HOMEBREW_NO_ANALYTICS` must be a string")
+        );
+    }
+
+    #[test]
+    fn test_manifest_ssh_defaults() {
+        let manifest: super::Manifest =
+            serde_yaml::from_str("version: 1\nssh:\n  print_public_key: true\n").unwrap();
+        let ssh = manifest.ssh.expect("ssh section should parse");
+        assert_eq!(ssh.key, PathBuf::from(".ssh/id_ed25519"));
+        assert!(ssh.print_public_key);
+        assert!(!ssh.copy_public_key);
+    }
+
+    #[test]
+    fn test_manifest_repos() {
+        let manifest: super::Manifest = serde_yaml::from_str(
+            "version: 1\nrepos:\n  - url: https://github.com/example/app.git\n    path: src/app\n    branch: main\n",
+        )
+        .unwrap();
+        assert_eq!(manifest.repos.len(), 1);
+        assert_eq!(manifest.repos[0].path, PathBuf::from("src/app"));
+        assert_eq!(manifest.repos[0].branch.as_deref(), Some("main"));
+        assert!(!manifest.repos[0].shallow);
+    }
+
+    #[test]
+    fn test_manifest_default_ref() {
+        let manifest: super::Manifest = serde_yaml::from_str("version: 1\nref: v2.0.0\n").unwrap();
+        assert_eq!(manifest.git_ref.as_deref(), Some("v2.0.0"));
+    }
+
+    #[test]
+    fn test_manifest_layer_overrides_templates_by_destination() {
+        let mut base: super::Manifest = serde_yaml::from_str(
+            "version: 1\ntemplates:\n  - source: a.hbs\n    destination: .a\n  - source: b.hbs\n    destination: .b\ncron:\n  - schedule: \"@daily\"\n    command: base\n",
+        )
+        .unwrap();
+        base.anchor_templates(Path::new("/base"));
+        let overlay: super::Manifest = serde_yaml::from_str(
+            "version: 1\ntemplates:\n  - source: b.hbs\n    destination: .b\ncron:\n  - schedule: \"@hourly\"\n    command: overlay\n",
+        )
+        .unwrap();
+
+        let merged = base.layer(overlay);
+
+        let sources: Vec<_> = merged.templates.iter().map(|t| t.source.clone()).collect();
+        assert_eq!(
+            sources,
+            vec![PathBuf::from("/base/a.hbs"), PathBuf::from("b.hbs")]
+        );
+        assert_eq!(merged.cron.len(), 2);
+    }
+
+    #[test]
+    fn test_manifest_layer_overrides_plugins_by_name() {
+        let mut base: super::Manifest = serde_yaml::from_str(
+            "version: 2\nplugins:\n  - name: slugify\n    command: bin/slugify\n  - name: shout\n    command: bin/shout\n",
+        )
+        .unwrap();
+        base.anchor_templates(Path::new("/base"));
+        let overlay: super::Manifest = serde_yaml::from_str(
+            "version: 2\nplugins:\n  - name: shout\n    command: plugins/shout\n    args: [--loud]\n",
+        )
+        .unwrap();
+
+        let merged = base.layer(overlay);
+
+        let commands: Vec<_> = merged.plugins.iter().map(|p| p.command.clone()).collect();
+        assert_eq!(
+            commands,
+            vec![
+                PathBuf::from("/base/bin/slugify"),
+                PathBuf::from("plugins/shout")
+            ]
+        );
+        assert_eq!(merged.plugins[1].args, vec!["--loud"]);
+    }
+
+    #[test]
+    fn test_packages_spec_layer_concatenates_lists() {
+        let base: super::PackagesSpec =
+            serde_yaml::from_str("apt: [git]\nbrew:\n  formulae: [jq]\n").unwrap();
+        let overlay: super::PackagesSpec =
+            serde_yaml::from_str("apt: [htop]\nbrew:\n  casks: [firefox]\n").unwrap();
+
+        let merged = base.layer(overlay);
+
+        assert_eq!(merged.apt, vec!["git", "htop"]);
+        assert_eq!(merged.brew.formulae.len(), 1);
+        assert_eq!(merged.brew.casks, vec!["firefox"]);
+    }
+
+    #[test]
+    fn test_macos_defaults_loaded() {
+        let path = Path::new("tests/config-macos");
+        let defaults = super::load_macos_defaults(path).expect("defaults should parse");
+        assert_eq!(defaults.len(), 2);
+        assert_eq!(defaults[0].domain, "com.apple.dock");
+        assert_eq!(defaults[0].kind, super::DefaultsType::Bool);
+    }
+
+    #[test]
+    fn test_macos_defaults_not_found() {
+        let path = Path::new("tests/empty-config");
+        assert!(super::load_macos_defaults(path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_gnome_settings_loaded() {
+        let path = Path::new("tests/config-gnome");
+        let settings = super::load_gnome_settings(path).expect("settings should parse");
+        assert_eq!(settings.len(), 2);
+        assert_eq!(settings[0].schema, "org.gnome.desktop.interface");
+    }
 }
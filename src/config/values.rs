@@ -0,0 +1,337 @@
+//! Values of the template context, deep-merged from the layers declaring
+//! them, with what each layer set kept for `--trace-value`.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde_json::{Map, Value};
+
+/// Values merged from layers of increasing precedence: module defaults, the
+/// `values.yaml` of each base then of the source, the profile, and the
+/// answers to questions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayeredValues {
+    values: Value,
+    settings: Vec<Setting>,
+}
+
+/// Value a layer set at a dotted path of the values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setting {
+    pub layer: String,
+    pub path: String,
+    pub value: Value,
+}
+
+impl Default for LayeredValues {
+    fn default() -> Self {
+        LayeredValues {
+            values: Value::Object(Map::new()),
+            settings: Vec::new(),
+        }
+    }
+}
+
+impl LayeredValues {
+    /// Merge the `values` of `layer` over those of the layers before it.
+    ///
+    /// Maps are merged key by key, at any depth, while any other value, a
+    /// list included, replaces the one before it. A layer replacing a map by
+    /// something else than `null`, or a value by a map, is reported, as the
+    /// keys of one of them are then lost.
+    pub fn layer(&mut self, layer: &str, values: HashMap<String, Value>) {
+        let mut settings = Vec::new();
+        merge(
+            &mut self.values,
+            Value::Object(values.into_iter().collect()),
+            "",
+            &mut |path, previous, value| {
+                if let Some(previous) = previous.filter(|previous| conflicts(previous, value)) {
+                    let (previous, value) = (kind(previous), kind(value));
+                    tracing::warn!("{layer} replaces `{path}`, {previous}, with {value}");
+                }
+                settings.push(Setting {
+                    layer: layer.to_string(),
+                    path: path.to_string(),
+                    value: value.clone(),
+                });
+            },
+        );
+        settings.sort_by(|a, b| a.path.cmp(&b.path));
+        self.settings.append(&mut settings);
+    }
+
+    /// Merged values, by top-level key.
+    pub fn values(&self) -> HashMap<String, Value> {
+        match &self.values {
+            Value::Object(map) => map.clone().into_iter().collect(),
+            _ => HashMap::new(),
+        }
+    }
+
+    /// How the layers set the value at the dotted `path`, e.g. `git.email`.
+    pub fn trace(&self, path: &str) -> ValueTrace {
+        let value = path
+            .split('.')
+            .try_fold(&self.values, |value, key| value.get(key))
+            .cloned();
+        let within = |outer: &str, inner: &str| {
+            outer == inner
+                || inner
+                    .strip_prefix(outer)
+                    .is_some_and(|rest| rest.starts_with('.'))
+        };
+        let relevant: Vec<&Setting> = self
+            .settings
+            .iter()
+            .filter(|setting| match path.strip_prefix(&setting.path) {
+                // A parent counts when it holds the value or replaces a map
+                // holding it.
+                Some(rest) if rest.starts_with('.') => {
+                    !setting.value.is_object()
+                        || rest[1..]
+                            .split('.')
+                            .try_fold(&setting.value, |value, key| value.get(key))
+                            .is_some()
+                }
+                _ => within(&setting.path, path) || within(path, &setting.path),
+            })
+            .collect();
+        let settings = relevant
+            .iter()
+            .enumerate()
+            .map(|(at, setting)| {
+                // Later layers setting the traced value or the one set, the
+                // deeper of them, or a parent replace what this one set.
+                let scope = if within(&setting.path, path) {
+                    path
+                } else {
+                    &setting.path
+                };
+                let later = &relevant[at + 1..];
+                let status = if later.iter().any(|later| within(&later.path, scope)) {
+                    Status::Overridden
+                } else if later.iter().any(|later| within(scope, &later.path)) {
+                    Status::PartlyOverridden
+                } else {
+                    Status::Wins
+                };
+                ((*setting).clone(), status)
+            })
+            .collect();
+        ValueTrace {
+            path: path.to_string(),
+            value,
+            settings,
+        }
+    }
+}
+
+/// Layers which set a value, and the value they resulted in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueTrace {
+    pub path: String,
+    /// Merged value, `None` when no layer sets it.
+    pub value: Option<Value>,
+    /// Settings of the value, of its parents, or of its keys, in the order
+    /// they were merged, each with how much of it is still in effect.
+    pub settings: Vec<(Setting, Status)>,
+}
+
+/// How much of a setting later layers left in effect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Wins,
+    /// Some keys of the map it set were set again by later layers.
+    PartlyOverridden,
+    Overridden,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Wins => "wins",
+            Status::PartlyOverridden => "partly overridden",
+            Status::Overridden => "overridden",
+        })
+    }
+}
+
+impl fmt::Display for ValueTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "value `{}` is {value}", self.path)?,
+            None => write!(f, "value `{}` is not set", self.path)?,
+        }
+        for (setting, status) in &self.settings {
+            write!(
+                f,
+                "\n  {}: `{}` = {} ({status})",
+                setting.layer, setting.path, setting.value
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Deep-merge `overlay` into `target`, found at `path`, calling `set` with
+/// each path whose value is replaced, its previous value, and the new one.
+fn merge(
+    target: &mut Value,
+    overlay: Value,
+    path: &str,
+    set: &mut dyn FnMut(&str, Option<&Value>, &Value),
+) {
+    match (target, overlay) {
+        (Value::Object(target), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                let path = match path {
+                    "" => key.clone(),
+                    parent => format!("{parent}.{key}"),
+                };
+                match target.get_mut(&key) {
+                    Some(existing) => merge(existing, value, &path, set),
+                    None => {
+                        set(&path, None, &value);
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, value) => {
+            set(path, Some(target), &value);
+            *target = value;
+        }
+    }
+}
+
+/// Whether replacing `previous` by `value` drops the keys of a map, or
+/// some value by one, rather than clearing it with `null`.
+fn conflicts(previous: &Value, value: &Value) -> bool {
+    !previous.is_null() && !value.is_null() && previous.is_object() != value.is_object()
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "a list",
+        Value::Object(_) => "a map",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn layer(values: Value) -> HashMap<String, Value> {
+        serde_json::from_value(values).unwrap()
+    }
+
+    #[test]
+    fn layer_merges_maps_at_any_depth() {
+        let mut values = LayeredValues::default();
+        values.layer(
+            "values.yaml",
+            layer(json!({
+                "git": {"name": "Ada", "email": "ada@home.example", "signing": {"key": "A1"}},
+                "editors": ["vim", "code"],
+                "theme": "dark",
+            })),
+        );
+        values.layer(
+            "profile `work`",
+            layer(json!({
+                "git": {"email": "ada@work.example", "signing": {"format": "ssh"}},
+                "editors": ["code"],
+            })),
+        );
+
+        assert_eq!(
+            Value::Object(values.values().into_iter().collect()),
+            json!({
+                "git": {
+                    "name": "Ada",
+                    "email": "ada@work.example",
+                    "signing": {"key": "A1", "format": "ssh"},
+                },
+                "editors": ["code"],
+                "theme": "dark",
+            })
+        );
+    }
+
+    #[test]
+    fn trace_reports_the_layer_that_wins() {
+        let mut values = LayeredValues::default();
+        values.layer("module defaults", layer(json!({"git": {"email": "a@b.c"}})));
+        values.layer("values.yaml", layer(json!({"git": {"email": "me@home"}})));
+        values.layer(
+            "profile `work`",
+            layer(json!({"git": {"email": "me@work"}})),
+        );
+
+        let trace = values.trace("git.email");
+        assert_eq!(trace.value, Some(json!("me@work")));
+        let layers: Vec<(&str, Status)> = trace
+            .settings
+            .iter()
+            .map(|(setting, status)| (setting.layer.as_str(), *status))
+            .collect();
+        assert_eq!(
+            layers,
+            vec![
+                ("module defaults", Status::Overridden),
+                ("values.yaml", Status::Overridden),
+                ("profile `work`", Status::Wins)
+            ]
+        );
+        assert_eq!(
+            trace.to_string(),
+            "value `git.email` is \"me@work\"\n  \
+             module defaults: `git` = {\"email\":\"a@b.c\"} (overridden)\n  \
+             values.yaml: `git.email` = \"me@home\" (overridden)\n  \
+             profile `work`: `git.email` = \"me@work\" (wins)"
+        );
+
+        values.layer(
+            "answers to questions",
+            layer(json!({"git": {"name": "Ada"}})),
+        );
+        assert_eq!(values.trace("git.email").settings.len(), 3);
+
+        let parent = values.trace("git");
+        assert_eq!(parent.settings.len(), 4);
+        assert_eq!(parent.settings[0].1, Status::PartlyOverridden);
+        assert_eq!(
+            values.trace("gi").to_string(),
+            "value `gi` is not set",
+            "only whole keys match"
+        );
+    }
+
+    #[test]
+    fn replacing_a_map_whole_overrides_its_keys() {
+        let mut values = LayeredValues::default();
+        values.layer("values.yaml", layer(json!({"git": {"email": "a@b.c"}})));
+        values.layer("profile `work`", layer(json!({"git": "off"})));
+
+        assert_eq!(values.values()["git"], json!("off"));
+        let trace = values.trace("git.email");
+        assert_eq!(trace.value, None);
+        assert_eq!(
+            trace
+                .settings
+                .iter()
+                .map(|(_, status)| *status)
+                .collect::<Vec<_>>(),
+            vec![Status::Overridden, Status::Wins]
+        );
+        assert!(conflicts(&json!({"a": 1}), &json!("off")));
+        assert!(!conflicts(&json!({"a": 1}), &Value::Null));
+        assert!(!conflicts(&json!(1), &json!("one")));
+    }
+}
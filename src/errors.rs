@@ -10,21 +10,58 @@ pub enum DotstrapError {
     #[error("failed to determine home directory")]
     HomeNotFound,
 
+    #[error("no source given: pass a git repository URL or local path")]
+    SourceMissing,
+
+    #[error("another dotstrap run holds `{0}`; try again later or pass --wait")]
+    Locked(PathBuf),
+
+    #[error("no applied run to roll back in `{0}`")]
+    NothingToRollBack(PathBuf),
+
+    #[error("failed to watch the source files: {0}")]
+    Watch(#[from] notify::Error),
+
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
 
     #[error("command `{program}` failed with status {status}")]
     CommandFailed { program: String, status: i32 },
 
+    #[error("command `{program}` timed out after {timeout:?}")]
+    CommandTimeout {
+        program: String,
+        timeout: std::time::Duration,
+    },
+
     #[error("failed to execute command `{0}`: {1}")]
     CommandIo(String, #[source] std::io::Error),
 
+    #[error("failed to link `{path}`: {source}")]
+    Link {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("package installation failed: {0}")]
+    PackageInstall(#[source] Box<DotstrapError>),
+
+    #[error("{0} (dotfiles were linked, but the remaining steps did not run)")]
+    Partial(#[source] Box<DotstrapError>),
+
     #[error("failed to parse yaml file `{path}`: {source}")]
     Yaml {
         source: serde_yaml::Error,
         path: PathBuf,
     },
 
+    #[error("failed to parse yaml file `{path}`: {source} (did you mean `{suggestion}`?)")]
+    UnknownKey {
+        source: serde_yaml::Error,
+        path: PathBuf,
+        suggestion: String,
+    },
+
     #[error("template render failure for `{path}`: {source}")]
     Template {
         source: handlebars::RenderError,
@@ -37,20 +74,297 @@ pub enum DotstrapError {
         path: PathBuf,
     },
 
+    #[error("failed to read or write state file `{path}`: {source}")]
+    State {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+
+    #[error("source `{0}` is an archive, which has no working copy to open")]
+    NoWorkingCopy(String),
+
+    #[error("subdirectory `{subdir}` not found in source `{source_path}`")]
+    SubdirNotFound {
+        subdir: PathBuf,
+        source_path: PathBuf,
+    },
+
+    #[error("refusing to apply `{path}`: {reason}")]
+    UntrustedSource { path: PathBuf, reason: String },
+
+    #[error("manifest base `{0}` is included more than once")]
+    ManifestBaseCycle(String),
+
     #[error("manifest `{0}` is missing templates section")]
     ManifestMissingTemplates(PathBuf),
 
-    #[error("manifest `{path}` declares unsupported version {version}")]
+    #[error("module `{required_by}` requires `{name}`, which is not declared")]
+    UnknownModule { name: String, required_by: String },
+
+    #[error("modules require each other in a cycle: {0}")]
+    ModuleCycle(String),
+
+    #[error("module `{name}` is enabled but requires `{required}`, which is disabled")]
+    RequiredModuleDisabled { name: String, required: String },
+
+    #[error("no module named `{0}` to enable or disable")]
+    UnknownModuleToggle(String),
+
+    #[error(
+        "manifest `{path}` declares unsupported version {version}; this dotstrap reads versions 1 to {}{}",
+        crate::config::MANIFEST_VERSION,
+        upgrade_hint(.version)
+    )]
     UnsupportedManifestVersion { path: PathBuf, version: u8 },
 
+    #[error(
+        "manifest `{path}` uses `{field}`, which requires manifest version 2; run `dotstrap migrate` to upgrade it"
+    )]
+    ManifestNeedsMigration { path: PathBuf, field: String },
+
+    #[error("manifest `{0}` does not declare a `version`")]
+    ManifestMissingVersion(PathBuf),
+
+    #[error("`{key}` is declared by both `{first}` and `{second}`")]
+    ManifestConflict {
+        key: String,
+        first: PathBuf,
+        second: PathBuf,
+    },
+
+    #[error("cannot migrate manifest `{path}`: {reason}")]
+    Migration { path: PathBuf, reason: String },
+
+    #[error("cannot import `{path}`: {reason}")]
+    Import { path: PathBuf, reason: String },
+
+    #[error("cannot snapshot packages into `{path}`: {reason}")]
+    Snapshot { path: PathBuf, reason: String },
+
+    #[error("`{0}` is not managed by dotstrap")]
+    Unmanaged(PathBuf),
+
+    #[error("cannot re-add `{path}`: {reason}")]
+    ReAdd { path: PathBuf, reason: String },
+
+    #[error("profile `{name}` is not declared by the manifest{}", available_profiles(.available))]
+    UnknownProfile {
+        name: String,
+        available: Vec<String>,
+    },
+
+    #[error("{phase} hook `{command}` failed: {source}")]
+    Hook {
+        phase: &'static str,
+        command: String,
+        #[source]
+        source: Box<DotstrapError>,
+    },
+
+    #[error(
+        "template `{path}` is {size} bytes, above the {limit} bytes rendered; declare it with `copy: true` or raise `max_template_size`"
+    )]
+    TemplateTooLarge {
+        path: PathBuf,
+        size: u64,
+        limit: u64,
+    },
+
     #[error("secret `{name}` is not available from {provider}")]
     MissingSecret { name: String, provider: String },
 
+    #[error("secret `{name}` is read from `{provider}`, which is not a known secret provider")]
+    UnknownSecretProvider { name: String, provider: String },
+
+    #[error("no template of the manifest matches --only {patterns}")]
+    NothingSelected { patterns: String },
+
+    #[error("no test case `{name}` in `{dir}`")]
+    UnknownTestCase { name: String, dir: PathBuf },
+
+    #[error("invalid plan file `{path}`: {reason}")]
+    InvalidPlan { path: PathBuf, reason: String },
+
+    #[error("`{0}` changed since the plan was made; make a new plan")]
+    StalePlan(PathBuf),
+
+    #[error("{0}: not confirmed; pass --yes to proceed")]
+    NotConfirmed(String),
+
+    #[error("question `{0}` has no value or saved answer, which strict mode requires")]
+    UnansweredQuestion(String),
+
+    #[error("the run reported {} warning(s) in strict mode: {}", .0.len(), .0.join("; "))]
+    StrictWarnings(Vec<String>),
+
+    #[error("{0} needs the network, which --offline forbids")]
+    Offline(String),
+
+    #[error(
+        "cannot install {0} completions, redirect the output of `--generate-completions {0}` instead"
+    )]
+    UnsupportedShell(String),
+
+    #[error("invalid template destination `{destination}`: {reason}")]
+    InvalidDestination {
+        destination: PathBuf,
+        reason: String,
+    },
+
+    #[error("the manifest declares template plugins ({names}); pass --allow-plugins to run them")]
+    PluginsNotAllowed { names: String },
+
+    #[error(
+        "the template `{path}` runs commands with `exec`; pass --allow-template-exec to run them"
+    )]
+    TemplateExecNotAllowed { path: PathBuf },
+
+    #[error("checksum mismatch for `{path}`: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("refusing to download `{0}`: only https:// URLs are allowed")]
+    InsecureDownload(String),
+
+    #[error("executable `{name}` not found in release asset `{asset}`")]
+    BinaryNotFound { name: String, asset: String },
+
     #[error("Homebrew is not installed or not executable")]
     BrewUnavailable,
 
+    #[error("package manager `{0}` is not installed or not executable")]
+    PackageManagerUnavailable(String),
+
+    #[error("package manager `{0}` cannot be found after installing it")]
+    BootstrapFailed(String),
+
+    #[error("invalid Brewfile `{path}` line {line}: {message}")]
+    Brewfile {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+
+    #[error("cannot pin formula `{name}`: {reason}")]
+    InvalidPin { name: String, reason: String },
+
     #[error("brew manifest file `{0}` not found")]
     BrewManifestMissing(PathBuf),
 }
 
+/// Process exit codes returned by the `dotstrap` binary.
+pub mod exit_code {
+    /// The run completed.
+    pub const SUCCESS: i32 = 0;
+    /// A failure not covered by a more specific code.
+    pub const FAILURE: i32 = 1;
+    /// Invalid command-line arguments.
+    pub const USAGE: i32 = 2;
+    /// The source or its manifest could not be loaded or is invalid.
+    pub const CONFIG: i32 = 3;
+    /// A template failed to render.
+    pub const RENDER: i32 = 4;
+    /// A rendered template could not be linked into the home directory.
+    pub const LINK: i32 = 5;
+    /// Installing or removing packages failed.
+    pub const PACKAGE: i32 = 6;
+    /// A check found the home directory out of sync with the source.
+    pub const DRIFT: i32 = 7;
+    /// Dotfiles were linked, but a later step of the run failed.
+    pub const PARTIAL: i32 = 8;
+}
+
+impl DotstrapError {
+    /// Exit code reporting this error, see [`exit_code`].
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            DotstrapError::SourceMissing
+            | DotstrapError::Yaml { .. }
+            | DotstrapError::UnknownKey { .. }
+            | DotstrapError::SubdirNotFound { .. }
+            | DotstrapError::NoWorkingCopy(_)
+            | DotstrapError::UntrustedSource { .. }
+            | DotstrapError::ManifestBaseCycle(_)
+            | DotstrapError::ManifestMissingTemplates(_)
+            | DotstrapError::UnknownModule { .. }
+            | DotstrapError::ModuleCycle(_)
+            | DotstrapError::RequiredModuleDisabled { .. }
+            | DotstrapError::UnknownModuleToggle(_)
+            | DotstrapError::UnansweredQuestion(_)
+            | DotstrapError::UnsupportedManifestVersion { .. }
+            | DotstrapError::ManifestNeedsMigration { .. }
+            | DotstrapError::Migration { .. }
+            | DotstrapError::Import { .. }
+            | DotstrapError::Snapshot { .. }
+            | DotstrapError::ManifestMissingVersion(_)
+            | DotstrapError::ManifestConflict { .. }
+            | DotstrapError::UnknownProfile { .. }
+            | DotstrapError::Brewfile { .. }
+            | DotstrapError::InvalidPin { .. }
+            | DotstrapError::UnknownSecretProvider { .. }
+            | DotstrapError::PluginsNotAllowed { .. }
+            | DotstrapError::TemplateExecNotAllowed { .. }
+            | DotstrapError::InvalidDestination { .. }
+            | DotstrapError::NothingSelected { .. }
+            | DotstrapError::UnknownTestCase { .. }
+            | DotstrapError::InvalidPlan { .. }
+            | DotstrapError::BrewManifestMissing(_) => exit_code::CONFIG,
+            DotstrapError::Template { .. }
+            | DotstrapError::TemplateCompile { .. }
+            | DotstrapError::TemplateTooLarge { .. }
+            | DotstrapError::MissingSecret { .. } => exit_code::RENDER,
+            DotstrapError::Link { .. } => exit_code::LINK,
+            DotstrapError::PackageInstall(_)
+            | DotstrapError::BrewUnavailable
+            | DotstrapError::PackageManagerUnavailable(_)
+            | DotstrapError::BootstrapFailed(_) => exit_code::PACKAGE,
+            DotstrapError::Partial(_) => exit_code::PARTIAL,
+            _ => exit_code::FAILURE,
+        }
+    }
+}
+
+fn upgrade_hint(version: &u8) -> &'static str {
+    if *version > crate::config::MANIFEST_VERSION {
+        ", upgrade dotstrap to apply it"
+    } else {
+        ""
+    }
+}
+
+fn available_profiles(available: &[String]) -> String {
+    if available.is_empty() {
+        String::new()
+    } else {
+        format!(" (available: {})", available.join(", "))
+    }
+}
+
 pub type Result<T> = std::result::Result<T, DotstrapError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_follow_the_failing_stage() {
+        let failed = || DotstrapError::CommandFailed {
+            program: "brew".into(),
+            status: 1,
+        };
+
+        assert_eq!(DotstrapError::SourceMissing.exit_code(), exit_code::CONFIG);
+        assert_eq!(failed().exit_code(), exit_code::FAILURE);
+        assert_eq!(
+            DotstrapError::PackageInstall(Box::new(failed())).exit_code(),
+            exit_code::PACKAGE
+        );
+        assert_eq!(
+            DotstrapError::Partial(Box::new(failed())).exit_code(),
+            exit_code::PARTIAL
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! Answers to manifest questions cached in `~/.config/dotstrap/answers.yaml`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::errors::{DotstrapError, Result};
+
+const ANSWERS_PATH: &str = ".config/dotstrap/answers.yaml";
+
+/// Answers keyed by question name.
+pub type Answers = BTreeMap<String, serde_json::Value>;
+
+/// Location of the answers file for the given home directory.
+pub fn answers_path(home: &Path) -> PathBuf {
+    home.join(ANSWERS_PATH)
+}
+
+/// Load the cached answers, returning none when no question was answered yet.
+pub fn load_answers(home: &Path) -> Result<Answers> {
+    let path = answers_path(home);
+    if !path.exists() {
+        return Ok(Answers::new());
+    }
+    let contents = fs::read_to_string(&path)?;
+    if contents.trim().is_empty() {
+        return Ok(Answers::new());
+    }
+    serde_yaml::from_str(&contents).map_err(|source| DotstrapError::Yaml { source, path })
+}
+
+/// Persist the answers, creating the configuration directory if needed.
+pub fn save_answers(home: &Path, answers: &Answers) -> Result<()> {
+    let path = answers_path(home);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let contents = serde_yaml::to_string(answers).map_err(|source| DotstrapError::Yaml {
+        source,
+        path: path.clone(),
+    })?;
+    fs::write(&path, contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn answers_round_trip() {
+        let home = TempDir::new().unwrap();
+        assert!(load_answers(home.path()).unwrap().is_empty());
+
+        let mut answers = Answers::new();
+        answers.insert("email".into(), serde_json::json!("me@example.com"));
+        answers.insert("work_machine".into(), serde_json::json!(true));
+        save_answers(home.path(), &answers).unwrap();
+
+        assert_eq!(load_answers(home.path()).unwrap(), answers);
+    }
+}
@@ -0,0 +1,311 @@
+//! Async counterpart of [`CommandExecutor`], available with the `tokio`
+//! feature, for GUI wrappers and servers that must not block a thread while
+//! packages install.
+
+use std::future::Future;
+use std::process::{ExitStatus, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::runtime::Handle;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::{CommandExecutor, Invocation, command_name};
+
+/// Spawns commands without blocking the calling task.
+pub trait AsyncCommandExecutor: Send + Sync {
+    fn run(&self, program: &str, args: &[&str]) -> impl Future<Output = Result<()>> + Send;
+
+    /// Run `program` and capture its standard output.
+    fn output(&self, program: &str, args: &[&str]) -> impl Future<Output = Result<String>> + Send;
+
+    /// Whether `program` can be executed, probed via `<program> --version`.
+    fn is_available(&self, program: &str) -> impl Future<Output = bool> + Send {
+        async move { self.run(program, &["--version"]).await.is_ok() }
+    }
+
+    /// Run `invocation` in its working directory and environment.
+    ///
+    /// Executors unable to set them run the command through `run`, without
+    /// its working directory and environment.
+    fn run_invocation(&self, invocation: &Invocation) -> impl Future<Output = Result<()>> + Send {
+        async move { self.run(&invocation.program, &invocation.args()).await }
+    }
+}
+
+/// Executor spawning commands on the host through `tokio::process`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokioCommandExecutor {
+    timeout: Option<Duration>,
+}
+
+impl TokioCommandExecutor {
+    /// Executor killing commands still running after `timeout`; `None` lets
+    /// commands run indefinitely.
+    pub fn with_timeout(timeout: Option<Duration>) -> Self {
+        TokioCommandExecutor { timeout }
+    }
+
+    /// Wait for `child`, killing it once `timeout`, or else the timeout of
+    /// the executor, elapsed.
+    async fn wait(
+        &self,
+        child: &mut Child,
+        program: &str,
+        timeout: Option<Duration>,
+    ) -> Result<ExitStatus> {
+        let io_error = |err| DotstrapError::CommandIo(program.to_string(), err);
+        let Some(limit) = timeout.or(self.timeout) else {
+            return child.wait().await.map_err(io_error);
+        };
+        match tokio::time::timeout(limit, child.wait()).await {
+            Ok(status) => status.map_err(io_error),
+            Err(_) => {
+                let _ = child.kill().await;
+                Err(DotstrapError::CommandTimeout {
+                    program: program.to_string(),
+                    timeout: limit,
+                })
+            }
+        }
+    }
+}
+
+/// Log each line read from `stream`, prefixed with `label`.
+async fn log_lines(stream: impl AsyncRead + Unpin, label: String) {
+    let mut lines = BufReader::new(stream).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        tracing::info!("[{label}] {line}");
+    }
+}
+
+fn exit_result(program: &str, status: ExitStatus) -> Result<()> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(DotstrapError::CommandFailed {
+            program: program.to_string(),
+            status: status.code().unwrap_or(-1),
+        })
+    }
+}
+
+impl AsyncCommandExecutor for TokioCommandExecutor {
+    /// The output of the command is logged line by line, prefixed with the
+    /// command name, as the blocking executor does.
+    async fn run(&self, program: &str, args: &[&str]) -> Result<()> {
+        self.run_invocation(&Invocation::new(program, args)).await
+    }
+
+    async fn run_invocation(&self, invocation: &Invocation) -> Result<()> {
+        let program = invocation.program.as_str();
+        let args = invocation.args();
+        tracing::debug!("$ {}", invocation.line());
+        let mut command = Command::new(program);
+        command
+            .args(&args)
+            .envs(invocation.env.iter().map(|(key, value)| (key, value)));
+        if let Some(dir) = &invocation.cwd {
+            command.current_dir(dir);
+        }
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| DotstrapError::CommandIo(program.to_string(), err))?;
+        let label = command_name(program, &args);
+        let stdout = child.stdout.take().map(|out| log_lines(out, label.clone()));
+        let stderr = child.stderr.take().map(|err| log_lines(err, label));
+        let (status, _, _) = tokio::join!(
+            self.wait(&mut child, program, invocation.timeout),
+            async {
+                if let Some(stdout) = stdout {
+                    stdout.await;
+                }
+            },
+            async {
+                if let Some(stderr) = stderr {
+                    stderr.await;
+                }
+            }
+        );
+        exit_result(program, status?)
+    }
+
+    async fn output(&self, program: &str, args: &[&str]) -> Result<String> {
+        tracing::debug!("$ {program} {}", args.join(" "));
+        let command = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let output = match self.timeout {
+            Some(limit) => tokio::time::timeout(limit, command).await.map_err(|_| {
+                DotstrapError::CommandTimeout {
+                    program: program.to_string(),
+                    timeout: limit,
+                }
+            })?,
+            None => command.await,
+        }
+        .map_err(|err| DotstrapError::CommandIo(program.to_string(), err))?;
+        exit_result(program, output.status)?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    async fn is_available(&self, program: &str) -> bool {
+        let child = Command::new(program)
+            .arg("--version")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn();
+        match child {
+            Ok(mut child) => self
+                .wait(&mut child, program, None)
+                .await
+                .is_ok_and(|status| status.success()),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Blocking [`CommandExecutor`] running the commands of an
+/// [`AsyncCommandExecutor`] on a runtime, for the parts of a run executed on
+/// a blocking thread.
+///
+/// Commands that may run concurrently, such as package installs with
+/// `--jobs`, are spawned as tasks on the runtime.
+pub(crate) struct BlockingExecutor<E> {
+    inner: Arc<E>,
+    runtime: Handle,
+}
+
+impl<E: AsyncCommandExecutor + 'static> BlockingExecutor<E> {
+    pub(crate) fn new(inner: E, runtime: Handle) -> Self {
+        BlockingExecutor {
+            inner: Arc::new(inner),
+            runtime,
+        }
+    }
+}
+
+impl<E: AsyncCommandExecutor + 'static> CommandExecutor for BlockingExecutor<E> {
+    fn run(&self, program: &str, args: &[&str]) -> Result<()> {
+        self.runtime.block_on(self.inner.run(program, args))
+    }
+
+    fn output(&self, program: &str, args: &[&str]) -> Result<String> {
+        self.runtime.block_on(self.inner.output(program, args))
+    }
+
+    fn is_available(&self, program: &str) -> bool {
+        self.runtime.block_on(self.inner.is_available(program))
+    }
+
+    fn run_invocation(&self, invocation: &Invocation) -> Result<()> {
+        self.runtime.block_on(self.inner.run_invocation(invocation))
+    }
+
+    fn run_concurrently(&self, commands: &[Invocation], jobs: usize) -> Vec<Result<()>> {
+        let permits = Arc::new(Semaphore::new(jobs.max(1)));
+        let mut tasks = JoinSet::new();
+        for (index, invocation) in commands.iter().enumerate() {
+            let inner = Arc::clone(&self.inner);
+            let permits = Arc::clone(&permits);
+            let invocation = invocation.clone();
+            tasks.spawn_on(
+                async move {
+                    let _permit = permits.acquire().await;
+                    (index, inner.run_invocation(&invocation).await)
+                },
+                &self.runtime,
+            );
+        }
+        let mut results: Vec<Result<()>> = commands
+            .iter()
+            .map(|invocation| {
+                Err(DotstrapError::CommandIo(
+                    invocation.program.clone(),
+                    std::io::Error::other("the command task was cancelled"),
+                ))
+            })
+            .collect();
+        self.runtime.block_on(async {
+            while let Some(finished) = tasks.join_next().await {
+                if let Ok((index, result)) = finished {
+                    results[index] = result;
+                }
+            }
+        });
+        results
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn tokio_executor_runs_captures_and_times_out() {
+        let runtime = runtime();
+        let executor = TokioCommandExecutor::with_timeout(Some(Duration::from_millis(200)));
+
+        runtime.block_on(async {
+            assert_eq!(executor.output("echo", &["hi"]).await.unwrap(), "hi\n");
+            assert!(matches!(
+                executor.run("false", &[]).await,
+                Err(DotstrapError::CommandFailed { status: 1, .. })
+            ));
+            assert!(matches!(
+                executor.run("sleep", &["5"]).await,
+                Err(DotstrapError::CommandTimeout { .. })
+            ));
+            assert!(!executor.is_available("dotstrap-missing-program").await);
+        });
+    }
+
+    #[test]
+    fn blocking_executor_runs_batches_concurrently() {
+        let runtime = runtime();
+        let executor =
+            BlockingExecutor::new(TokioCommandExecutor::default(), runtime.handle().clone());
+
+        let results = runtime
+            .block_on(async {
+                tokio::task::spawn_blocking(move || {
+                    let started = std::time::Instant::now();
+                    let results = executor.run_concurrently(
+                        &[
+                            Invocation::new("sleep", &["0.3"]),
+                            Invocation::new("sleep", &["0.3"]),
+                            Invocation::new("false", &[]),
+                        ],
+                        3,
+                    );
+                    (started.elapsed(), results)
+                })
+                .await
+            })
+            .unwrap();
+
+        assert!(results.0 < Duration::from_millis(550), "{:?}", results.0);
+        assert!(results.1[0].is_ok() && results.1[1].is_ok());
+        assert!(results.1[2].is_err());
+    }
+}
@@ -1,57 +1,703 @@
 //! Command execution abstractions used by services that invoke external tools.
 
-use std::process::Command;
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use crate::errors::{DotstrapError, Result};
 
+/// A command along with the directory and environment it runs in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Invocation {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Working directory, the current one when `None`.
+    pub cwd: Option<PathBuf>,
+    /// Variables set on top of the inherited environment.
+    pub env: Vec<(String, String)>,
+    /// Time after which the command is killed, overriding the executor's.
+    pub timeout: Option<Duration>,
+}
+
+impl Invocation {
+    pub fn new(program: &str, args: &[&str]) -> Self {
+        Invocation {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            ..Invocation::default()
+        }
+    }
+
+    /// Run the command from `dir`.
+    pub fn current_dir(mut self, dir: &Path) -> Self {
+        self.cwd = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Set the environment variable `key` for the command.
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Kill the command if it still runs after `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub(crate) fn args(&self) -> Vec<&str> {
+        self.args.iter().map(String::as_str).collect()
+    }
+
+    /// The command line, as `program arg1 arg2`.
+    pub(crate) fn line(&self) -> String {
+        format!("{} {}", self.program, self.args.join(" "))
+    }
+
+    /// Process running the command in its directory and environment.
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .envs(self.env.iter().map(|(key, value)| (key, value)));
+        if let Some(dir) = &self.cwd {
+            command.current_dir(dir);
+        }
+        command
+    }
+}
+
+/// Exit status and output of a finished command.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CapturedOutput {
+    /// Exit code, -1 when the command was killed by a signal.
+    pub status: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CapturedOutput {
+    pub fn success(&self) -> bool {
+        self.status == 0
+    }
+
+    /// Standard output of a successful command, or the failure of `program`.
+    pub fn into_stdout(self, program: &str) -> Result<String> {
+        if self.success() {
+            Ok(self.stdout)
+        } else {
+            Err(DotstrapError::CommandFailed {
+                program: program.to_string(),
+                status: self.status,
+            })
+        }
+    }
+}
+
 /// Generic abstraction around spawning commands, enabling mocks during tests.
 pub trait CommandExecutor {
     fn run(&self, program: &str, args: &[&str]) -> Result<()>;
+
+    /// Run `program` and capture its standard output.
+    ///
+    /// Executors unable to capture output run the command and return an empty
+    /// string.
+    fn output(&self, program: &str, args: &[&str]) -> Result<String> {
+        self.run(program, args).map(|_| String::new())
+    }
+
+    /// Whether `program` can be executed, probed via `<program> --version`.
+    fn is_available(&self, program: &str) -> bool {
+        self.run(program, &["--version"]).is_ok()
+    }
+
+    /// Run `invocation` and capture its exit status, standard output, and
+    /// standard error. Exiting unsuccessfully is not an error; only failing
+    /// to start the command is.
+    ///
+    /// Executors unable to capture output run the command through
+    /// [`CommandExecutor::output`], without its working directory and
+    /// environment, and capture no standard error.
+    fn run_capture(&self, invocation: &Invocation) -> Result<CapturedOutput> {
+        match self.output(&invocation.program, &invocation.args()) {
+            Ok(stdout) => Ok(CapturedOutput {
+                status: 0,
+                stdout,
+                stderr: String::new(),
+            }),
+            Err(DotstrapError::CommandFailed { status, .. }) => Ok(CapturedOutput {
+                status,
+                ..CapturedOutput::default()
+            }),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Run `invocation` as [`CommandExecutor::run`] runs a command, in its
+    /// working directory and environment.
+    ///
+    /// Executors unable to set them run the command through
+    /// [`CommandExecutor::run`], without its working directory and
+    /// environment.
+    fn run_invocation(&self, invocation: &Invocation) -> Result<()> {
+        self.run(&invocation.program, &invocation.args())
+    }
+
+    /// Run independent commands with at most `jobs` of them at once, returning
+    /// the result of each command in order.
+    ///
+    /// Executors unable to run commands concurrently run them one after the
+    /// other.
+    fn run_concurrently(&self, commands: &[Invocation], _jobs: usize) -> Vec<Result<()>> {
+        commands
+            .iter()
+            .map(|invocation| self.run_invocation(invocation))
+            .collect()
+    }
 }
 
 /// Command executor that proxies to [`std::process::Command`].
-#[derive(Default)]
-pub struct SystemCommandExecutor;
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemCommandExecutor {
+    timeout: Option<Duration>,
+}
+
+impl SystemCommandExecutor {
+    /// Executor killing commands still running after `timeout`, unless an
+    /// [`Invocation`] sets its own. `None` lets commands run indefinitely.
+    pub fn with_timeout(timeout: Option<Duration>) -> Self {
+        SystemCommandExecutor { timeout }
+    }
+}
 
 impl CommandExecutor for SystemCommandExecutor {
+    /// The output of the command is logged line by line as it is printed,
+    /// prefixed with the command name, so it shows with the other messages of
+    /// the run and in the run log.
     fn run(&self, program: &str, args: &[&str]) -> Result<()> {
-        let mut cmd = Command::new(program);
-        cmd.args(args);
-        let status = cmd
-            .status()
-            .map_err(|err| DotstrapError::CommandIo(program.to_string(), err))?;
-        if status.success() {
-            Ok(())
-        } else {
-            let code = status.code().unwrap_or(-1);
-            Err(DotstrapError::CommandFailed {
-                program: program.to_string(),
-                status: code,
+        self.run_invocation(&Invocation::new(program, args))
+    }
+
+    fn output(&self, program: &str, args: &[&str]) -> Result<String> {
+        tracing::debug!("$ {program} {}", args.join(" "));
+        let mut command = Command::new(program);
+        command.args(args).stderr(Stdio::null());
+        let (status, stdout, _) = capture(&mut command, program, self.timeout)?;
+        exit_result(program, status)?;
+        Ok(stdout)
+    }
+
+    fn is_available(&self, program: &str) -> bool {
+        let child = Command::new(program)
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        child
+            .ok()
+            .and_then(|mut child| wait_for(&mut child, program, self.timeout).ok())
+            .is_some_and(|status| status.success())
+    }
+
+    fn run_capture(&self, invocation: &Invocation) -> Result<CapturedOutput> {
+        let program = &invocation.program;
+        tracing::debug!("$ {}", invocation.line());
+        let mut command = invocation.command();
+        let timeout = invocation.timeout.or(self.timeout);
+        let (status, stdout, stderr) = capture(&mut command, program, timeout)?;
+        Ok(CapturedOutput {
+            status: status.code().unwrap_or(-1),
+            stdout,
+            stderr,
+        })
+    }
+
+    fn run_invocation(&self, invocation: &Invocation) -> Result<()> {
+        let program = &invocation.program;
+        tracing::debug!("$ {}", invocation.line());
+        let mut command = invocation.command();
+        let label = command_name(program, &invocation.args());
+        let (mut child, readers) = spawn_logged(&mut command, program, &label)?;
+        let status = wait_for(&mut child, program, invocation.timeout.or(self.timeout))?;
+        for reader in readers {
+            let _ = reader.join();
+        }
+        exit_result(program, status)
+    }
+
+    /// Children running concurrently have their output captured and logged
+    /// line by line, prefixed with their command, so it does not interleave
+    /// mid-line on the terminal.
+    fn run_concurrently(&self, commands: &[Invocation], jobs: usize) -> Vec<Result<()>> {
+        if jobs <= 1 || commands.len() <= 1 {
+            return commands
+                .iter()
+                .map(|invocation| self.run_invocation(invocation))
+                .collect();
+        }
+        let timeout = |index: usize| commands[index].timeout.or(self.timeout);
+        let mut results: Vec<Option<Result<()>>> = commands.iter().map(|_| None).collect();
+        let mut pending = commands.iter().enumerate();
+        let mut running: Vec<(usize, Instant, Child, Vec<JoinHandle<()>>)> = Vec::new();
+        loop {
+            while running.len() < jobs
+                && let Some((index, invocation)) = pending.next()
+            {
+                let label = invocation.line();
+                tracing::debug!("$ {label}");
+                let mut command = invocation.command();
+                command.stdin(Stdio::null());
+                match spawn_logged(&mut command, &invocation.program, &label) {
+                    Ok((child, readers)) => running.push((index, Instant::now(), child, readers)),
+                    Err(err) => results[index] = Some(Err(err)),
+                }
+            }
+            if running.is_empty() {
+                break;
+            }
+            let finished = running.iter_mut().enumerate().find_map(
+                |(position, (index, started, child, _))| {
+                    let status = child.try_wait().transpose();
+                    let expired = timeout(*index).is_some_and(|limit| started.elapsed() >= limit);
+                    match status {
+                        Some(status) => Some((position, Some(status))),
+                        None if expired => Some((position, None)),
+                        None => None,
+                    }
+                },
+            );
+            let Some((position, status)) = finished else {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            };
+            let (index, _, mut child, readers) = running.swap_remove(position);
+            let program = &commands[index].program;
+            let Some(status) = status else {
+                results[index] = Some(Err(kill(&mut child, program, timeout(index))));
+                continue;
+            };
+            for reader in readers {
+                let _ = reader.join();
+            }
+            results[index] = Some(
+                status
+                    .map_err(|err| DotstrapError::CommandIo(program.to_string(), err))
+                    .and_then(|status| exit_result(program, status)),
+            );
+        }
+        results
+            .into_iter()
+            .map(|result| result.expect("every command was run"))
+            .collect()
+    }
+}
+
+/// How often running commands are checked for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+fn exit_result(program: &str, status: ExitStatus) -> Result<()> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(DotstrapError::CommandFailed {
+            program: program.to_string(),
+            status: status.code().unwrap_or(-1),
+        })
+    }
+}
+
+/// Wait for `child` to exit, killing it once `timeout` elapsed.
+fn wait_for(child: &mut Child, program: &str, timeout: Option<Duration>) -> Result<ExitStatus> {
+    let io_error = |err| DotstrapError::CommandIo(program.to_string(), err);
+    let Some(limit) = timeout else {
+        return child.wait().map_err(io_error);
+    };
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().map_err(io_error)? {
+            return Ok(status);
+        }
+        if started.elapsed() >= limit {
+            return Err(kill(child, program, timeout));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Kill `child`, which ran past `timeout`, returning the error reporting it.
+fn kill(child: &mut Child, program: &str, timeout: Option<Duration>) -> DotstrapError {
+    let _ = child.kill();
+    let _ = child.wait();
+    DotstrapError::CommandTimeout {
+        program: program.to_string(),
+        timeout: timeout.unwrap_or_default(),
+    }
+}
+
+/// Run `command` with its output captured, as `(status, stdout, stderr)`.
+///
+/// The streams are drained by threads while waiting, so a chatty command
+/// cannot fill the pipes and block. Threads reading the pipes of a killed
+/// command are left behind, since its own children may keep them open.
+pub(crate) fn capture(
+    command: &mut Command,
+    program: &str,
+    timeout: Option<Duration>,
+) -> Result<(ExitStatus, String, String)> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| DotstrapError::CommandIo(program.to_string(), err))?;
+    let drain = |stream: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut bytes = Vec::new();
+            if let Some(mut stream) = stream {
+                let _ = stream.read_to_end(&mut bytes);
+            }
+            String::from_utf8_lossy(&bytes).into_owned()
+        })
+    };
+    let stdout = drain(child.stdout.take().map(|out| Box::new(out) as _));
+    let stderr = drain(child.stderr.take().map(|err| Box::new(err) as _));
+    let status = wait_for(&mut child, program, timeout)?;
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    Ok((status, stdout, stderr))
+}
+
+/// Name prefixing the output of `program`: its file name, or the one of the
+/// command it elevates.
+pub(crate) fn command_name(program: &str, args: &[&str]) -> String {
+    let name = |program: &str| {
+        Path::new(program)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| program.to_string())
+    };
+    match (name(program).as_str(), args.first()) {
+        ("sudo" | "doas", Some(elevated)) => name(elevated),
+        (program, _) => program.to_string(),
+    }
+}
+
+/// Spawn `command` with threads logging each line of its output, prefixed
+/// with `label`.
+fn spawn_logged(
+    command: &mut Command,
+    program: &str,
+    label: &str,
+) -> Result<(Child, Vec<JoinHandle<()>>)> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| DotstrapError::CommandIo(program.to_string(), err))?;
+    let streams: [Option<Box<dyn Read + Send>>; 2] = [
+        child.stdout.take().map(|out| Box::new(out) as _),
+        child.stderr.take().map(|err| Box::new(err) as _),
+    ];
+    let readers = streams
+        .into_iter()
+        .flatten()
+        .map(|stream| {
+            let label = label.to_string();
+            thread::spawn(move || {
+                for line in BufReader::new(stream).lines().map_while(|line| line.ok()) {
+                    tracing::info!("[{label}] {line}");
+                }
             })
+        })
+        .collect();
+    Ok((child, readers))
+}
+
+/// Retry behaviour for commands that may fail transiently, such as network
+/// operations during a fresh machine setup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Additional attempts made after the first failure.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every following retry.
+    pub initial_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 2,
+            initial_delay: Duration::from_secs(1),
         }
     }
 }
 
+impl RetryPolicy {
+    /// Delay to wait before the given retry, starting at zero.
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.initial_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+    }
+}
+
+/// Executor decorator retrying the network-bound commands, downloads, git
+/// clones and fetches, and package installs, that exit unsuccessfully, with
+/// exponential backoff, and recording the retries it performed.
+///
+/// Other commands, such as hooks, which may not be safe to run twice, are
+/// never retried.
+pub struct RetryingExecutor<'a> {
+    inner: &'a dyn CommandExecutor,
+    policy: RetryPolicy,
+    log: RefCell<Vec<String>>,
+}
+
+impl<'a> RetryingExecutor<'a> {
+    pub fn new(inner: &'a dyn CommandExecutor, policy: RetryPolicy) -> Self {
+        RetryingExecutor {
+            inner,
+            policy,
+            log: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Human readable description of every retry performed so far.
+    pub fn retries(&self) -> Vec<String> {
+        self.log.borrow().clone()
+    }
+}
+
+impl RetryingExecutor<'_> {
+    /// Retry `invocation` until it succeeds or the retries are exhausted,
+    /// given the `result` of its first attempt.
+    fn retry(&self, invocation: &Invocation, mut result: Result<()>) -> Result<()> {
+        if !retriable(&invocation.program, &invocation.args) {
+            return result;
+        }
+        let command = invocation.line();
+        let mut retry = 0;
+        loop {
+            match result {
+                Ok(()) => {
+                    if retry > 0 {
+                        self.log
+                            .borrow_mut()
+                            .push(format!("`{command}` succeeded after {retry} retries"));
+                    }
+                    return Ok(());
+                }
+                Err(DotstrapError::CommandFailed { .. }) if retry < self.policy.retries => {
+                    std::thread::sleep(self.policy.delay(retry));
+                    retry += 1;
+                    result = self.inner.run_invocation(invocation);
+                }
+                Err(err) => {
+                    if retry > 0 {
+                        self.log
+                            .borrow_mut()
+                            .push(format!("`{command}` still failed after {retry} retries"));
+                    }
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+impl CommandExecutor for RetryingExecutor<'_> {
+    fn run(&self, program: &str, args: &[&str]) -> Result<()> {
+        let invocation = Invocation::new(program, args);
+        self.retry(&invocation, self.inner.run(program, args))
+    }
+
+    fn output(&self, program: &str, args: &[&str]) -> Result<String> {
+        self.inner.output(program, args)
+    }
+
+    fn is_available(&self, program: &str) -> bool {
+        self.inner.is_available(program)
+    }
+
+    fn run_capture(&self, invocation: &Invocation) -> Result<CapturedOutput> {
+        self.inner.run_capture(invocation)
+    }
+
+    fn run_invocation(&self, invocation: &Invocation) -> Result<()> {
+        self.retry(invocation, self.inner.run_invocation(invocation))
+    }
+
+    /// Failed commands are retried one at a time once the batch finished.
+    fn run_concurrently(&self, commands: &[Invocation], jobs: usize) -> Vec<Result<()>> {
+        self.inner
+            .run_concurrently(commands, jobs)
+            .into_iter()
+            .zip(commands)
+            .map(|(result, invocation)| self.retry(invocation, result))
+            .collect()
+    }
+}
+
+/// Executor decorator refusing the commands that reach the network, such as
+/// downloads and git clones, for offline runs.
+pub struct OfflineExecutor<'a> {
+    inner: &'a dyn CommandExecutor,
+}
+
+impl<'a> OfflineExecutor<'a> {
+    pub fn new(inner: &'a dyn CommandExecutor) -> Self {
+        OfflineExecutor { inner }
+    }
+
+    /// Fail when `invocation` would reach the network.
+    fn check(&self, invocation: &Invocation) -> Result<()> {
+        if reaches_network(&invocation.program, &invocation.args) {
+            Err(DotstrapError::Offline(format!("`{}`", invocation.line())))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Whether `program` run with `args` reaches the network, as downloads, git
+/// clones and fetches, and package installs do, so that failing may be
+/// transient.
+fn retriable(program: &str, args: &[String]) -> bool {
+    // Privileged installs run through `sudo` or `doas`.
+    let (program, args) = match (program, args.split_first()) {
+        ("sudo" | "doas", Some((elevated, args))) => (elevated.as_str(), args),
+        _ => (program, args),
+    };
+    reaches_network(program, args) || installs_packages(program, args)
+}
+
+/// Whether `program`, a package manager possibly given by path, run with
+/// `args` updates or installs packages.
+fn installs_packages(program: &str, args: &[String]) -> bool {
+    let program = Path::new(program)
+        .file_name()
+        .map_or(program.into(), |name| name.to_string_lossy());
+    let subcommand = args.first().map(String::as_str);
+    match program.as_ref() {
+        "brew" => matches!(subcommand, Some("update" | "tap" | "install" | "upgrade")),
+        "apt-get" | "dnf" => matches!(subcommand, Some("update" | "install")),
+        "pacman" => subcommand.is_some_and(|arg| arg.starts_with("-S")),
+        "cargo" | "mas" | "winget" => subcommand == Some("install"),
+        _ => false,
+    }
+}
+
+/// Whether `program` run with `args` would reach the network.
+fn reaches_network(program: &str, args: &[String]) -> bool {
+    match program {
+        "curl" | "wget" => true,
+        "git" => {
+            let mut args = args.iter();
+            // Global options come before the subcommand, some with a value.
+            let subcommand = loop {
+                match args.next().map(String::as_str) {
+                    Some("-C" | "-c") => {
+                        args.next();
+                    }
+                    Some(arg) if arg.starts_with('-') => {}
+                    other => break other,
+                }
+            };
+            matches!(
+                subcommand,
+                Some("clone" | "fetch" | "pull" | "push" | "ls-remote")
+            )
+        }
+        _ => false,
+    }
+}
+
+impl CommandExecutor for OfflineExecutor<'_> {
+    fn run(&self, program: &str, args: &[&str]) -> Result<()> {
+        self.check(&Invocation::new(program, args))?;
+        self.inner.run(program, args)
+    }
+
+    fn output(&self, program: &str, args: &[&str]) -> Result<String> {
+        self.check(&Invocation::new(program, args))?;
+        self.inner.output(program, args)
+    }
+
+    fn is_available(&self, program: &str) -> bool {
+        self.inner.is_available(program)
+    }
+
+    fn run_capture(&self, invocation: &Invocation) -> Result<CapturedOutput> {
+        self.check(invocation)?;
+        self.inner.run_capture(invocation)
+    }
+
+    fn run_invocation(&self, invocation: &Invocation) -> Result<()> {
+        self.check(invocation)?;
+        self.inner.run_invocation(invocation)
+    }
+
+    /// Batches with a refused command run one command at a time.
+    fn run_concurrently(&self, commands: &[Invocation], jobs: usize) -> Vec<Result<()>> {
+        if commands
+            .iter()
+            .all(|invocation| self.check(invocation).is_ok())
+        {
+            return self.inner.run_concurrently(commands, jobs);
+        }
+        commands
+            .iter()
+            .map(|invocation| self.run_invocation(invocation))
+            .collect()
+    }
+}
+
 /// A command executor used for tests that records invocations.
 #[cfg_attr(not(test), allow(dead_code))]
 #[derive(Default)]
 pub struct RecordingCommandExecutor {
     calls: std::cell::RefCell<Vec<(String, Vec<String>)>>,
     fail_on: std::cell::RefCell<Option<String>>,
+    outputs: std::collections::HashMap<String, String>,
+    stderr: std::collections::HashMap<String, String>,
+    invocations: std::cell::RefCell<Vec<Invocation>>,
 }
 
 #[cfg_attr(not(test), allow(dead_code))]
 impl RecordingCommandExecutor {
     pub fn with_failure(program: &str) -> Self {
         RecordingCommandExecutor {
-            calls: std::cell::RefCell::new(Vec::new()),
             fail_on: std::cell::RefCell::new(Some(program.to_string())),
+            ..RecordingCommandExecutor::default()
         }
     }
 
+    /// Stub the output returned for `command`, written as `program arg1 arg2`.
+    pub fn with_output(mut self, command: &str, output: &str) -> Self {
+        self.outputs.insert(command.to_string(), output.to_string());
+        self
+    }
+
+    /// Stub the standard error captured for `command`, written as
+    /// `program arg1 arg2`.
+    pub fn with_stderr(mut self, command: &str, stderr: &str) -> Self {
+        self.stderr.insert(command.to_string(), stderr.to_string());
+        self
+    }
+
     pub fn calls(&self) -> Vec<(String, Vec<String>)> {
         self.calls.borrow().clone()
     }
+
+    /// Commands run through [`CommandExecutor::run_capture`] or
+    /// [`CommandExecutor::run_invocation`], with their working directory and
+    /// environment.
+    pub fn invocations(&self) -> Vec<Invocation> {
+        self.invocations.borrow().clone()
+    }
 }
 
 impl CommandExecutor for RecordingCommandExecutor {
@@ -75,6 +721,32 @@ impl CommandExecutor for RecordingCommandExecutor {
             Ok(())
         }
     }
+
+    fn output(&self, program: &str, args: &[&str]) -> Result<String> {
+        self.run(program, args)?;
+        let command = format!("{program} {}", args.join(" "));
+        Ok(self.outputs.get(&command).cloned().unwrap_or_default())
+    }
+
+    fn run_capture(&self, invocation: &Invocation) -> Result<CapturedOutput> {
+        self.invocations.borrow_mut().push(invocation.clone());
+        let status = match self.run(&invocation.program, &invocation.args()) {
+            Ok(()) => 0,
+            Err(DotstrapError::CommandFailed { status, .. }) => status,
+            Err(err) => return Err(err),
+        };
+        let command = invocation.line();
+        Ok(CapturedOutput {
+            status,
+            stdout: self.outputs.get(&command).cloned().unwrap_or_default(),
+            stderr: self.stderr.get(&command).cloned().unwrap_or_default(),
+        })
+    }
+
+    fn run_invocation(&self, invocation: &Invocation) -> Result<()> {
+        self.invocations.borrow_mut().push(invocation.clone());
+        self.run(&invocation.program, &invocation.args())
+    }
 }
 
 #[cfg(test)]
@@ -104,7 +776,7 @@ mod tests {
 
     #[test]
     fn system_command_executor_returns_ok_on_success() {
-        let executor = SystemCommandExecutor;
+        let executor = SystemCommandExecutor::default();
         let (program, args) = success_command();
 
         let result = executor.run(program, args);
@@ -114,7 +786,7 @@ mod tests {
 
     #[test]
     fn system_command_executor_returns_command_failed_error_on_non_zero_exit() {
-        let executor = SystemCommandExecutor;
+        let executor = SystemCommandExecutor::default();
         let (program, args, expected_status) = failure_command();
 
         let error = executor
@@ -126,6 +798,289 @@ mod tests {
         );
     }
 
+    #[test]
+    fn system_command_executor_reports_missing_programs_as_unavailable() {
+        let executor = SystemCommandExecutor::default();
+
+        assert!(executor.is_available("cargo"));
+        assert!(!executor.is_available("dotstrap-definitely-missing-program"));
+    }
+
+    #[test]
+    fn retry_policy_doubles_delay() {
+        let policy = RetryPolicy {
+            retries: 3,
+            initial_delay: Duration::from_millis(100),
+        };
+
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn retrying_executor_retries_failed_commands_and_records_them() {
+        let inner = RecordingCommandExecutor::with_failure("git");
+        let policy = RetryPolicy {
+            retries: 2,
+            initial_delay: Duration::ZERO,
+        };
+        let executor = RetryingExecutor::new(&inner, policy);
+
+        let error = executor
+            .run("git", &["clone", "repo"])
+            .expect_err("command keeps failing");
+
+        assert!(matches!(error, DotstrapError::CommandFailed { .. }));
+        assert_eq!(inner.calls().len(), 3, "first attempt plus two retries");
+        assert_eq!(
+            executor.retries(),
+            vec!["`git clone repo` still failed after 2 retries".to_string()]
+        );
+    }
+
+    #[test]
+    fn retrying_executor_does_not_retry_successful_commands() {
+        let inner = RecordingCommandExecutor::default();
+        let executor = RetryingExecutor::new(&inner, RetryPolicy::default());
+
+        executor.run("brew", &["update"]).unwrap();
+
+        assert_eq!(inner.calls().len(), 1);
+        assert!(executor.retries().is_empty());
+    }
+
+    #[test]
+    fn retrying_executor_retries_failures_of_concurrent_commands() {
+        let inner = RecordingCommandExecutor::with_failure("brew");
+        let policy = RetryPolicy {
+            retries: 1,
+            initial_delay: Duration::ZERO,
+        };
+        let executor = RetryingExecutor::new(&inner, policy);
+
+        let results = executor.run_concurrently(
+            &[
+                Invocation::new("true", &[]),
+                Invocation::new("brew", &["install", "x"]),
+            ],
+            4,
+        );
+
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+        assert_eq!(inner.calls().len(), 3, "one retry of the failed command");
+        assert_eq!(
+            executor.retries(),
+            vec!["`brew install x` still failed after 1 retries".to_string()]
+        );
+    }
+
+    #[test]
+    fn retrying_executor_only_retries_network_bound_commands() {
+        let inner = RecordingCommandExecutor::with_failure("sh");
+        let policy = RetryPolicy {
+            retries: 2,
+            initial_delay: Duration::ZERO,
+        };
+        let executor = RetryingExecutor::new(&inner, policy);
+
+        assert!(executor.run("sh", &["-c", "mv a b"]).is_err());
+        assert_eq!(inner.calls().len(), 1, "hooks are not retried");
+        assert!(executor.retries().is_empty());
+
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        assert!(retriable("git", &args(&["clone", "repo"])));
+        assert!(retriable("curl", &args(&["-fsSL", "https://x"])));
+        assert!(retriable(
+            "sudo",
+            &args(&["apt-get", "install", "-y", "git"])
+        ));
+        assert!(retriable(
+            "/opt/homebrew/bin/brew",
+            &args(&["install", "fzf"])
+        ));
+        assert!(retriable("pacman", &args(&["-S", "--needed", "git"])));
+        assert!(!retriable("brew", &args(&["pin", "fzf"])));
+        assert!(!retriable("git", &args(&["status"])));
+        assert!(!retriable("defaults", &args(&["write", "x", "y", "1"])));
+        assert!(!retriable("crontab", &args(&["-l"])));
+    }
+
+    #[test]
+    fn offline_executor_refuses_commands_reaching_the_network() {
+        let inner = RecordingCommandExecutor::default();
+        let executor = OfflineExecutor::new(&inner);
+
+        let error = executor
+            .run("git", &["-C", "/repo", "fetch", "--quiet"])
+            .expect_err("fetching needs the network");
+        assert_eq!(
+            error.to_string(),
+            "`git -C /repo fetch --quiet` needs the network, which --offline forbids"
+        );
+        assert!(executor.run("curl", &["-fsSL", "https://x"]).is_err());
+        executor
+            .run("git", &["-C", "/repo", "checkout", "--quiet", "v1"])
+            .expect("checking out a fetched ref is local");
+        executor.run("brew", &["list"]).expect("listing is local");
+
+        assert_eq!(inner.calls().len(), 2, "refused commands never run");
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn system_command_executor_runs_commands_concurrently() {
+        let executor = SystemCommandExecutor::default();
+        let commands = [
+            Invocation::new("sh", &["-c", "sleep 0.3"]),
+            Invocation::new("sh", &["-c", "echo output; exit 3"]),
+            Invocation::new("sh", &["-c", "sleep 0.3"]),
+            Invocation::new("dotstrap-definitely-missing-program", &[]),
+        ];
+        let started = std::time::Instant::now();
+
+        let results = executor.run_concurrently(&commands, 4);
+
+        assert!(started.elapsed() < Duration::from_millis(550));
+        assert!(results[0].is_ok() && results[2].is_ok());
+        assert!(matches!(
+            results[1],
+            Err(DotstrapError::CommandFailed { status: 3, .. })
+        ));
+        assert!(matches!(results[3], Err(DotstrapError::CommandIo(..))));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn system_command_executor_captures_output() {
+        let executor = SystemCommandExecutor::default();
+
+        let output = executor.output("sh", &["-c", "echo captured"]).unwrap();
+
+        assert_eq!(output, "captured\n");
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn system_command_executor_captures_both_streams_with_cwd_and_env() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let invocation = Invocation::new(
+            "sh",
+            &[
+                "-c",
+                "echo \"$GREETING from $(pwd)\"; echo oops >&2; exit 3",
+            ],
+        )
+        .current_dir(dir.path())
+        .env("GREETING", "hello");
+
+        let captured = SystemCommandExecutor::default()
+            .run_capture(&invocation)
+            .unwrap();
+
+        let cwd = dir.path().canonicalize().unwrap();
+        assert_eq!(captured.stdout, format!("hello from {}\n", cwd.display()));
+        assert_eq!(captured.stderr, "oops\n");
+        assert_eq!(captured.status, 3);
+        assert!(matches!(
+            captured.into_stdout("sh"),
+            Err(DotstrapError::CommandFailed { status: 3, .. })
+        ));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn system_command_executor_runs_invocations_in_their_environment() {
+        let executor = SystemCommandExecutor::default();
+        let invocation =
+            Invocation::new("sh", &["-c", "test \"$GREETING\" = hello"]).env("GREETING", "hello");
+
+        executor.run_invocation(&invocation).unwrap();
+        let results = executor.run_concurrently(&[invocation.clone(), invocation], 2);
+
+        assert!(results.iter().all(Result::is_ok));
+        assert!(
+            executor
+                .run_invocation(&Invocation::new("sh", &["-c", "test -n \"$GREETING\""]))
+                .is_err()
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn system_command_executor_kills_commands_past_their_timeout() {
+        let executor = SystemCommandExecutor::with_timeout(Some(Duration::from_millis(100)));
+        let started = std::time::Instant::now();
+
+        let error = executor.run("sleep", &["5"]).unwrap_err();
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(
+            matches!(error, DotstrapError::CommandTimeout { ref program, .. } if program == "sleep")
+        );
+        assert!(executor.output("sh", &["-c", "echo fast"]).is_ok());
+        let results = executor.run_concurrently(
+            &[
+                Invocation::new("sleep", &["5"]),
+                Invocation::new("true", &[]),
+            ],
+            2,
+        );
+        assert!(matches!(
+            results[0],
+            Err(DotstrapError::CommandTimeout { .. })
+        ));
+        assert!(results[1].is_ok());
+
+        let slow = Invocation::new("sh", &["-c", "sleep 0.3; echo done"]);
+        assert!(matches!(
+            executor.run_capture(&slow),
+            Err(DotstrapError::CommandTimeout { .. })
+        ));
+        let captured = executor
+            .run_capture(&slow.timeout(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(captured.stdout, "done\n");
+    }
+
+    #[test]
+    fn command_name_names_the_elevated_command() {
+        assert_eq!(command_name("/opt/homebrew/bin/brew", &["install"]), "brew");
+        assert_eq!(
+            command_name("sudo", &["apt-get", "install", "-y"]),
+            "apt-get"
+        );
+        assert_eq!(command_name("sudo", &[]), "sudo");
+    }
+
+    #[test]
+    fn recording_executor_records_captured_invocations() {
+        let executor = RecordingCommandExecutor::with_failure("op")
+            .with_stderr("op read op://vault/token", "not signed in\n");
+        let invocation = Invocation::new("op", &["read", "op://vault/token"])
+            .env("OP_ACCOUNT", "me")
+            .current_dir(Path::new("/tmp"));
+
+        let captured = executor.run_capture(&invocation).unwrap();
+
+        assert_eq!(captured.status, 1);
+        assert_eq!(captured.stderr, "not signed in\n");
+        assert_eq!(executor.invocations(), vec![invocation]);
+        assert_eq!(executor.calls().len(), 1);
+    }
+
+    #[test]
+    fn recording_executor_returns_stubbed_output() {
+        let executor = RecordingCommandExecutor::default().with_output("defaults read dock", "1\n");
+
+        assert_eq!(
+            executor.output("defaults", &["read", "dock"]).unwrap(),
+            "1\n"
+        );
+        assert_eq!(executor.output("defaults", &["read", "other"]).unwrap(), "");
+        assert_eq!(executor.calls().len(), 2);
+    }
+
     #[test]
     fn recording_executor_tracks_invocations() {
         let executor = RecordingCommandExecutor::default();
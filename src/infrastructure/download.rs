@@ -0,0 +1,137 @@
+//! HTTPS downloads through the command executor and checksum verification.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use super::command::CommandExecutor;
+use crate::errors::{DotstrapError, Result};
+
+/// Download `url` to `destination` with `curl`, failing on HTTP errors and
+/// refusing to fetch or follow redirects over anything but HTTPS.
+pub fn download(executor: &dyn CommandExecutor, url: &str, destination: &Path) -> Result<()> {
+    let target = destination.to_string_lossy();
+    executor.run(
+        "curl",
+        &[
+            "--fail",
+            "--silent",
+            "--show-error",
+            "--location",
+            "--proto",
+            "=https",
+            "--proto-redir",
+            "=https",
+            "--output",
+            &target,
+            url,
+        ],
+    )
+}
+
+/// Whether `name` has the extension of an archive supported by [`extract`].
+pub fn is_archive(name: &str) -> bool {
+    let name = name.to_lowercase();
+    [".tar.gz", ".tgz", ".tar.xz", ".tar.bz2", ".tar", ".zip"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+}
+
+/// Extract `archive` into `target`, returning `false` for non-archive files.
+pub fn extract(archive: &Path, target: &Path, executor: &dyn CommandExecutor) -> Result<bool> {
+    let file_name = archive
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let archive_str = archive.to_string_lossy();
+    let target_str = target.to_string_lossy();
+    if !is_archive(&file_name) {
+        Ok(false)
+    } else if file_name.ends_with(".zip") {
+        executor.run("unzip", &["-o", "-q", &archive_str, "-d", &target_str])?;
+        Ok(true)
+    } else {
+        executor.run("tar", &["-xf", &archive_str, "-C", &target_str])?;
+        Ok(true)
+    }
+}
+
+/// Hex encoded SHA-256 digest of the file at `path`.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Ensure the file at `path` matches the expected hex encoded SHA-256 digest.
+pub fn verify_sha256(path: &Path, expected: &str) -> Result<()> {
+    let actual = sha256_file(path)?;
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(DotstrapError::ChecksumMismatch {
+            path: path.to_path_buf(),
+            expected: expected.trim().to_string(),
+            actual,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use tempfile::TempDir;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn download_invokes_curl() {
+        let executor = RecordingCommandExecutor::default();
+
+        download(&executor, "https://example.com/a", Path::new("/tmp/a")).unwrap();
+
+        let calls = executor.calls();
+        assert_eq!(calls[0].0, "curl");
+        assert_eq!(calls[0].1.last().unwrap(), "https://example.com/a");
+        assert!(calls[0].1.contains(&"/tmp/a".to_string()));
+        let args = calls[0].1.join(" ");
+        assert!(args.contains("--proto =https --proto-redir =https"));
+    }
+
+    #[test]
+    fn extract_picks_tool_from_extension() {
+        let executor = RecordingCommandExecutor::default();
+        let target = Path::new("/tmp/out");
+
+        assert!(extract(Path::new("/tmp/a.TAR.GZ"), target, &executor).unwrap());
+        assert!(extract(Path::new("/tmp/a.zip"), target, &executor).unwrap());
+        assert!(!extract(Path::new("/tmp/tool"), target, &executor).unwrap());
+
+        let programs: Vec<String> = executor.calls().into_iter().map(|(p, _)| p).collect();
+        assert_eq!(programs, vec!["tar", "unzip"]);
+    }
+
+    #[test]
+    fn verify_sha256_accepts_matching_digest() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("file");
+        std::fs::write(&path, "hello").unwrap();
+
+        assert!(verify_sha256(&path, &HELLO_SHA256.to_uppercase()).is_ok());
+    }
+
+    #[test]
+    fn verify_sha256_rejects_mismatching_digest() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("file");
+        std::fs::write(&path, "tampered").unwrap();
+
+        let error = verify_sha256(&path, HELLO_SHA256).expect_err("digest must not match");
+
+        assert!(matches!(error, DotstrapError::ChecksumMismatch { .. }));
+    }
+}
@@ -0,0 +1,93 @@
+//! Base64 encoding of binary data embedded in text, such as HTTP headers
+//! and plan files, and percent-encoding of URL components.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard, padded base64 encoding of `input`.
+pub fn base64_encode(input: &[u8]) -> String {
+    let mut encoded = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let triple = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                encoded.push(ALPHABET[(triple >> (18 - 6 * idx) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Bytes encoded by [`base64_encode`], ignoring whitespace, or `None` when
+/// `input` is not valid base64.
+pub fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let symbols: Vec<u8> = input
+        .bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    if !symbols.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut decoded = Vec::with_capacity(symbols.len() / 4 * 3);
+    for chunk in symbols.chunks(4) {
+        let padding = chunk.iter().rev().take_while(|&&byte| byte == b'=').count();
+        if padding > 2 {
+            return None;
+        }
+        let mut triple = 0u32;
+        for &byte in &chunk[..4 - padding] {
+            let value = ALPHABET.iter().position(|&symbol| symbol == byte)?;
+            triple = triple << 6 | value as u32;
+        }
+        triple <<= 6 * padding;
+        decoded.extend(&triple.to_be_bytes()[1..4 - padding]);
+    }
+    Some(decoded)
+}
+
+/// Percent-encoding of `input` for any component of a URL: every byte but
+/// the unreserved characters of RFC 3986 is encoded.
+pub fn percent_encode(input: &[u8]) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for &byte in input {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_round_trips_any_length() {
+        assert_eq!(
+            base64_encode(b"x-access-token:t"),
+            "eC1hY2Nlc3MtdG9rZW46dA=="
+        );
+        for input in [&b""[..], b"a", b"ab", b"abc", b"\x00\xff\x10binary"] {
+            assert_eq!(base64_decode(&base64_encode(input)).unwrap(), input);
+        }
+        assert_eq!(base64_decode("YWJj\nZA==").unwrap(), b"abcd");
+        assert_eq!(base64_decode("YWJ"), None);
+        assert_eq!(base64_decode("YW!j"), None);
+    }
+
+    #[test]
+    fn percent_encode_keeps_unreserved_characters_only() {
+        assert_eq!(
+            percent_encode("p@ss w/rd~é".as_bytes()),
+            "p%40ss%20w%2Frd~%C3%A9"
+        );
+    }
+}
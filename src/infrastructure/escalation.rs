@@ -0,0 +1,113 @@
+//! Running the commands that need root privileges.
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::errors::Result;
+use crate::infrastructure::command::CommandExecutor;
+
+/// How commands needing root privileges, such as `apt-get install`, are run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Escalation {
+    /// Run them as is, e.g. when dotstrap itself runs as root.
+    None,
+    /// Prefix them with `sudo`, which prompts whenever its credentials
+    /// expired.
+    #[default]
+    Sudo,
+    /// Prefix them with `doas`.
+    Doas,
+    /// Ask for the `sudo` password once, before the first of them, then
+    /// prefix them with `sudo`.
+    PromptOnce,
+}
+
+impl Escalation {
+    /// Program the commands are prefixed with, if any.
+    pub fn program(self) -> Option<&'static str> {
+        match self {
+            Escalation::None => None,
+            Escalation::Sudo | Escalation::PromptOnce => Some("sudo"),
+            Escalation::Doas => Some("doas"),
+        }
+    }
+
+    /// `program` and `args` rewritten to run with root privileges.
+    pub fn elevate(self, program: &str, args: &[String]) -> (String, Vec<String>) {
+        match self.program() {
+            Some(elevator) => (
+                elevator.to_string(),
+                std::iter::once(program.to_string())
+                    .chain(args.iter().cloned())
+                    .collect(),
+            ),
+            None => (program.to_string(), args.to_vec()),
+        }
+    }
+
+    /// Ask for the credentials upfront when the policy does, so that `sudo`
+    /// reuses them for the following commands instead of prompting midway.
+    pub fn authenticate(self, executor: &dyn CommandExecutor) -> Result<()> {
+        match self {
+            Escalation::PromptOnce => executor.run("sudo", &["-v"]),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether dotstrap runs as root, in which case nothing needs elevating.
+pub fn is_root(executor: &dyn CommandExecutor) -> bool {
+    cfg!(unix)
+        && executor
+            .output("id", &["-u"])
+            .is_ok_and(|uid| uid.trim() == "0")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+
+    #[test]
+    fn elevate_prefixes_the_command_with_the_policy_program() {
+        let args = vec!["install".to_string(), "-y".to_string()];
+
+        assert_eq!(
+            Escalation::Doas.elevate("apt-get", &args),
+            (
+                "doas".to_string(),
+                vec!["apt-get".into(), "install".into(), "-y".into()]
+            )
+        );
+        assert_eq!(
+            Escalation::None.elevate("apt-get", &args),
+            ("apt-get".to_string(), args)
+        );
+    }
+
+    #[test]
+    fn authenticate_only_prompts_once_upfront_when_asked_to() {
+        let executor = RecordingCommandExecutor::default();
+
+        Escalation::Sudo.authenticate(&executor).unwrap();
+        assert!(executor.calls().is_empty());
+
+        Escalation::PromptOnce.authenticate(&executor).unwrap();
+        assert_eq!(
+            executor.calls(),
+            vec![("sudo".to_string(), vec!["-v".to_string()])]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn is_root_reads_the_effective_user_id() {
+        assert!(is_root(
+            &RecordingCommandExecutor::default().with_output("id -u", "0\n")
+        ));
+        assert!(!is_root(
+            &RecordingCommandExecutor::default().with_output("id -u", "1000\n")
+        ));
+    }
+}
@@ -0,0 +1,783 @@
+//! Filesystem access behind a trait, so linking and manifest loading can run
+//! against the disk or an in-memory tree.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+/// Kind of a filesystem entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// The subset of an entry's metadata dotstrap relies on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub kind: FileKind,
+    /// Size in bytes of files, 0 for directories and links.
+    pub len: u64,
+    /// Unix permission bits, when the platform has them.
+    pub mode: Option<u32>,
+}
+
+impl Metadata {
+    pub fn is_file(&self) -> bool {
+        self.kind == FileKind::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileKind::Dir
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.kind == FileKind::Symlink
+    }
+}
+
+/// Operations the linker and the configuration loaders perform on files.
+///
+/// Errors are plain [`io::Error`]s with the kinds `std::fs` would report, so
+/// callers handle both implementations alike.
+pub trait FileSystem {
+    /// Contents of the file at `path`.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Contents of the file at `path`, which must be UTF-8.
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// A reader over the file at `path`, for contents too large to load.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(io::Cursor::new(self.read(path)?)))
+    }
+
+    /// Create or truncate the file at `path` with `contents`.
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Copy the contents and permissions of the file at `from` to `to`.
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Create `path` and its missing parents.
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Paths of the entries of the directory at `path`, in no given order.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Create a symbolic link at `destination` pointing to `source`.
+    fn symlink(&self, source: &Path, destination: &Path) -> io::Result<()>;
+
+    /// Whether the current user may create symbolic links, which Windows
+    /// only allows with Developer Mode or administrator rights.
+    fn symlinks_permitted(&self) -> bool {
+        true
+    }
+
+    /// Create a directory junction at `destination` pointing to the
+    /// directory `source`, where the platform has them.
+    fn junction(&self, _source: &Path, destination: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "cannot create a junction at {}: junctions are only available on Windows",
+                destination.display()
+            ),
+        ))
+    }
+
+    /// Target of the symbolic link at `path`.
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Move the entry at `from` to `to`, replacing a file there.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Remove the file or symbolic link at `path`.
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Metadata of the entry at `path`, following symbolic links.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Metadata of the entry at `path` itself, even when it is a link.
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Set the Unix permission bits of `path`; a no-op where there are none.
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()>;
+
+    /// Whether `path` exists, following symbolic links.
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    /// Whether `path` is a symbolic link, dangling or not.
+    fn is_symlink(&self, path: &Path) -> bool {
+        self.symlink_metadata(path)
+            .is_ok_and(|metadata| metadata.is_symlink())
+    }
+}
+
+/// The disk, through `std::fs`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsFileSystem;
+
+impl OsFileSystem {
+    fn convert(metadata: fs::Metadata) -> Metadata {
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_symlink() {
+            FileKind::Symlink
+        } else if file_type.is_dir() {
+            FileKind::Dir
+        } else {
+            FileKind::File
+        };
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            Some(metadata.permissions().mode() & 0o7777)
+        };
+        #[cfg(not(unix))]
+        let mode = None;
+        Metadata {
+            kind,
+            len: if kind == FileKind::File {
+                metadata.len()
+            } else {
+                0
+            },
+            mode,
+        }
+    }
+}
+
+impl FileSystem for OsFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::copy(from, to).map(|_| ())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+
+    fn symlink(&self, source: &Path, destination: &Path) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(source, destination)
+        }
+        #[cfg(windows)]
+        {
+            if source.is_dir() {
+                std::os::windows::fs::symlink_dir(source, destination)
+            } else {
+                std::os::windows::fs::symlink_file(source, destination)
+            }
+        }
+    }
+
+    /// Probed once per process by creating a link in the temporary
+    /// directory.
+    fn symlinks_permitted(&self) -> bool {
+        #[cfg(windows)]
+        {
+            static PERMITTED: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+            *PERMITTED.get_or_init(|| {
+                let Ok(probe) = tempfile::TempDir::new() else {
+                    return false;
+                };
+                let target = probe.path().join("target");
+                fs::write(&target, b"").is_ok()
+                    && std::os::windows::fs::symlink_file(&target, probe.path().join("link"))
+                        .is_ok()
+            })
+        }
+        #[cfg(not(windows))]
+        {
+            true
+        }
+    }
+
+    fn junction(&self, source: &Path, destination: &Path) -> io::Result<()> {
+        #[cfg(windows)]
+        {
+            let status = std::process::Command::new("cmd")
+                .arg("/C")
+                .arg("mklink")
+                .arg("/J")
+                .arg(destination)
+                .arg(source)
+                .stdout(std::process::Stdio::null())
+                .status()?;
+            if status.success() {
+                Ok(())
+            } else {
+                Err(io::Error::other(format!(
+                    "mklink /J {} failed with {status}",
+                    destination.display()
+                )))
+            }
+        }
+        #[cfg(not(windows))]
+        {
+            let _ = source;
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "cannot create a junction at {}: junctions are only available on Windows",
+                    destination.display()
+                ),
+            ))
+        }
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::metadata(path).map(Self::convert)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::symlink_metadata(path).map(Self::convert)
+    }
+
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (path, mode);
+            Ok(())
+        }
+    }
+}
+
+/// The disk, placing copies of files where links would go, for homes that
+/// must not point into the staging directory, such as those of containers.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CopyingFileSystem;
+
+impl FileSystem for CopyingFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        OsFileSystem.read(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        OsFileSystem.open(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        OsFileSystem.write(path, contents)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        OsFileSystem.copy(from, to)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        OsFileSystem.create_dir_all(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        OsFileSystem.read_dir(path)
+    }
+
+    fn symlink(&self, source: &Path, destination: &Path) -> io::Result<()> {
+        OsFileSystem.symlink(source, destination)
+    }
+
+    fn symlinks_permitted(&self) -> bool {
+        false
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        OsFileSystem.read_link(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        OsFileSystem.rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        OsFileSystem.remove_file(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        OsFileSystem.metadata(path)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        OsFileSystem.symlink_metadata(path)
+    }
+
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        OsFileSystem.set_mode(path, mode)
+    }
+}
+
+/// Symbolic links followed while resolving a path before giving up, as
+/// Linux does.
+const MAX_LINKS: usize = 40;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    File { contents: Vec<u8>, mode: u32 },
+    Dir,
+    Symlink(PathBuf),
+}
+
+/// A tree kept in memory, for tests and library consumers that must not
+/// touch the disk.
+///
+/// Paths are absolute; the root directory always exists. Relative link
+/// targets resolve against the link's directory.
+#[derive(Debug, Default)]
+pub struct MemoryFileSystem {
+    entries: Mutex<BTreeMap<PathBuf, Entry>>,
+    without_symlinks: bool,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        MemoryFileSystem::default()
+    }
+
+    /// Refuse to create symbolic links, as Windows does without Developer
+    /// Mode.
+    pub fn without_symlinks(mut self) -> Self {
+        self.without_symlinks = true;
+        self
+    }
+
+    /// Add the file at `path`, creating its parent directories.
+    pub fn with_file(self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Self {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent)
+                .expect("parent should be creatable");
+        }
+        self.write(path, contents.as_ref())
+            .expect("file should be writable");
+        self
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, BTreeMap<PathBuf, Entry>> {
+        self.entries.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// `path` with its links resolved, the last one only when `follow` is
+    /// set. The resolved path itself may not exist.
+    fn resolve(
+        entries: &BTreeMap<PathBuf, Entry>,
+        path: &Path,
+        follow: bool,
+    ) -> io::Result<PathBuf> {
+        // Components left to resolve, the next one last.
+        let mut pending = components(path);
+        let mut resolved = PathBuf::from("/");
+        let mut links = 0;
+        while let Some(name) = pending.pop() {
+            if name == ".." {
+                resolved.pop();
+                continue;
+            }
+            let candidate = resolved.join(&name);
+            match entries.get(&candidate) {
+                Some(Entry::Symlink(target)) if follow || !pending.is_empty() => {
+                    links += 1;
+                    if links > MAX_LINKS {
+                        return Err(io::Error::other("too many levels of symbolic links"));
+                    }
+                    if target.is_absolute() {
+                        resolved = PathBuf::from("/");
+                    }
+                    pending.extend(components(target));
+                }
+                Some(Entry::File { .. }) if !pending.is_empty() => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotADirectory,
+                        format!("{} is not a directory", candidate.display()),
+                    ));
+                }
+                None if !pending.is_empty() => return Err(not_found(&candidate)),
+                _ => resolved = candidate,
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Resolved `path`, after checking its parent is a directory.
+    fn resolve_child(entries: &BTreeMap<PathBuf, Entry>, path: &Path) -> io::Result<PathBuf> {
+        let resolved = Self::resolve(entries, path, false)?;
+        match resolved.parent() {
+            None => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "the root directory exists",
+            )),
+            Some(parent) if Self::is_dir(entries, parent) => Ok(resolved),
+            Some(parent) => Err(not_found(parent)),
+        }
+    }
+
+    fn is_dir(entries: &BTreeMap<PathBuf, Entry>, path: &Path) -> bool {
+        path.parent().is_none() || entries.get(path) == Some(&Entry::Dir)
+    }
+
+    fn describe(entries: &BTreeMap<PathBuf, Entry>, path: &Path) -> io::Result<Metadata> {
+        match entries.get(path) {
+            Some(Entry::File { contents, mode }) => Ok(Metadata {
+                kind: FileKind::File,
+                len: contents.len() as u64,
+                mode: Some(*mode),
+            }),
+            Some(Entry::Dir) => Ok(Metadata {
+                kind: FileKind::Dir,
+                len: 0,
+                mode: Some(0o755),
+            }),
+            Some(Entry::Symlink(_)) => Ok(Metadata {
+                kind: FileKind::Symlink,
+                len: 0,
+                mode: Some(0o777),
+            }),
+            None if path.parent().is_none() => Ok(Metadata {
+                kind: FileKind::Dir,
+                len: 0,
+                mode: Some(0o755),
+            }),
+            None => Err(not_found(path)),
+        }
+    }
+}
+
+/// Names and `..` of `path`, last first.
+fn components(path: &Path) -> Vec<OsString> {
+    path.components()
+        .rev()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_os_string()),
+            Component::ParentDir => Some(OsString::from("..")),
+            _ => None,
+        })
+        .collect()
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} does not exist", path.display()),
+    )
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let entries = self.entries();
+        let resolved = Self::resolve(&entries, path, true)?;
+        match entries.get(&resolved) {
+            Some(Entry::File { contents, .. }) => Ok(contents.clone()),
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("{} is a directory", resolved.display()),
+            )),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut entries = self.entries();
+        let resolved = Self::resolve(&entries, path, true)?;
+        let resolved = Self::resolve_child(&entries, &resolved)?;
+        let mode = match entries.get(&resolved) {
+            Some(Entry::File { mode, .. }) => *mode,
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::IsADirectory,
+                    format!("{} is a directory", resolved.display()),
+                ));
+            }
+            None => 0o644,
+        };
+        entries.insert(
+            resolved,
+            Entry::File {
+                contents: contents.to_vec(),
+                mode,
+            },
+        );
+        Ok(())
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let contents = self.read(from)?;
+        let mode = self.metadata(from)?.mode;
+        self.write(to, &contents)?;
+        match mode {
+            Some(mode) => self.set_mode(to, mode),
+            None => Ok(()),
+        }
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        let mut dir = PathBuf::from("/");
+        for component in path.components() {
+            let name = match component {
+                Component::Normal(name) => name,
+                Component::ParentDir => {
+                    dir.pop();
+                    continue;
+                }
+                _ => continue,
+            };
+            let candidate = dir.join(name);
+            dir = match entries.get(&candidate) {
+                Some(Entry::Dir) => candidate,
+                Some(Entry::Symlink(_)) => {
+                    let target = Self::resolve(&entries, &candidate, true)?;
+                    if !Self::is_dir(&entries, &target) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::NotADirectory,
+                            format!("{} is not a directory", candidate.display()),
+                        ));
+                    }
+                    target
+                }
+                Some(Entry::File { .. }) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} is a file", candidate.display()),
+                    ));
+                }
+                None => {
+                    entries.insert(candidate.clone(), Entry::Dir);
+                    candidate
+                }
+            };
+        }
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let entries = self.entries();
+        let resolved = Self::resolve(&entries, path, true)?;
+        if !Self::is_dir(&entries, &resolved) {
+            return Err(not_found(path));
+        }
+        Ok(entries
+            .keys()
+            .filter(|entry| entry.parent() == Some(&resolved))
+            .map(|entry| path.join(entry.file_name().unwrap_or_default()))
+            .collect())
+    }
+
+    fn symlink(&self, source: &Path, destination: &Path) -> io::Result<()> {
+        if self.without_symlinks {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "a required privilege is not held by the client",
+            ));
+        }
+        let mut entries = self.entries();
+        let resolved = Self::resolve_child(&entries, destination)?;
+        if entries.contains_key(&resolved) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", destination.display()),
+            ));
+        }
+        entries.insert(resolved, Entry::Symlink(source.to_path_buf()));
+        Ok(())
+    }
+
+    fn symlinks_permitted(&self) -> bool {
+        !self.without_symlinks
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        let entries = self.entries();
+        let resolved = Self::resolve(&entries, path, false)?;
+        match entries.get(&resolved) {
+            Some(Entry::Symlink(target)) => Ok(target.clone()),
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a symbolic link", path.display()),
+            )),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        let from = Self::resolve(&entries, from, false)?;
+        let to = Self::resolve_child(&entries, to)?;
+        if !entries.contains_key(&from) {
+            return Err(not_found(&from));
+        }
+        let moved: Vec<PathBuf> = entries
+            .keys()
+            .filter(|entry| entry.starts_with(&from))
+            .cloned()
+            .collect();
+        for path in moved {
+            let entry = entries.remove(&path).expect("listed entry should exist");
+            let relative = path.strip_prefix(&from).expect("entry is below `from`");
+            let target = if relative.as_os_str().is_empty() {
+                to.clone()
+            } else {
+                to.join(relative)
+            };
+            entries.insert(target, entry);
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        let resolved = Self::resolve(&entries, path, false)?;
+        match entries.get(&resolved) {
+            Some(Entry::Dir) => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("{} is a directory", path.display()),
+            )),
+            Some(_) => {
+                entries.remove(&resolved);
+                Ok(())
+            }
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let entries = self.entries();
+        let resolved = Self::resolve(&entries, path, true)?;
+        Self::describe(&entries, &resolved)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let entries = self.entries();
+        let resolved = Self::resolve(&entries, path, false)?;
+        Self::describe(&entries, &resolved)
+    }
+
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let mut entries = self.entries();
+        let resolved = Self::resolve(&entries, path, true)?;
+        match entries.get_mut(&resolved) {
+            Some(Entry::File { mode: current, .. }) => {
+                *current = mode;
+                Ok(())
+            }
+            Some(_) => Ok(()),
+            None => Err(not_found(path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Exercise `fs` below `root` the way the linker does.
+    fn link_and_back_up(fs: &dyn FileSystem, root: &Path) {
+        let staged = root.join("generated/.zshrc");
+        let home = root.join("home");
+        fs.create_dir_all(staged.parent().unwrap()).unwrap();
+        fs.create_dir_all(&home).unwrap();
+        fs.write(&staged, b"export EDITOR=vim\n").unwrap();
+        fs.write(&home.join(".zshrc"), b"old").unwrap();
+
+        fs.rename(&home.join(".zshrc"), &home.join(".zshrc.bak"))
+            .unwrap();
+        fs.symlink(&staged, &home.join(".zshrc")).unwrap();
+        fs.set_mode(&staged, 0o600).unwrap();
+
+        assert_eq!(
+            fs.read_to_string(&home.join(".zshrc")).unwrap(),
+            "export EDITOR=vim\n"
+        );
+        assert_eq!(fs.read(&home.join(".zshrc.bak")).unwrap(), b"old");
+        assert_eq!(fs.read_link(&home.join(".zshrc")).unwrap(), staged);
+        assert!(fs.is_symlink(&home.join(".zshrc")));
+        let metadata = fs.metadata(&home.join(".zshrc")).unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(metadata.len, 18);
+        if cfg!(unix) {
+            assert_eq!(metadata.mode, Some(0o600));
+        }
+        let mut listed = fs.read_dir(&home).unwrap();
+        listed.sort();
+        assert_eq!(listed, vec![home.join(".zshrc"), home.join(".zshrc.bak")]);
+
+        fs.remove_file(&staged).unwrap();
+        assert!(fs.is_symlink(&home.join(".zshrc")));
+        assert!(!fs.exists(&home.join(".zshrc")), "the link dangles");
+        assert_eq!(
+            fs.read(&home.join(".zshrc")).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn os_file_system_links_and_backs_up() {
+        let root = TempDir::new().unwrap();
+        link_and_back_up(&OsFileSystem, root.path());
+    }
+
+    #[test]
+    fn memory_file_system_behaves_like_the_disk() {
+        link_and_back_up(&MemoryFileSystem::new(), Path::new("/tmp/root"));
+    }
+
+    #[test]
+    fn memory_file_system_resolves_relative_links_and_parents() {
+        let fs = MemoryFileSystem::new().with_file("/repo/templates/zshrc", "hi");
+        fs.create_dir_all(Path::new("/home")).unwrap();
+        fs.symlink(Path::new("../repo/templates"), Path::new("/home/dots"))
+            .unwrap();
+
+        assert_eq!(fs.read(Path::new("/home/dots/zshrc")).unwrap(), b"hi");
+        assert!(fs.metadata(Path::new("/home/dots")).unwrap().is_dir());
+        assert_eq!(
+            fs.write(Path::new("/missing/file"), b"")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Log of past runs kept in `~/.dotstrap/history.jsonl`, one JSON entry per
+//! line.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{DotstrapError, Result};
+use crate::services::outcome::LinkOutcome;
+
+const HISTORY_PATH: &str = ".dotstrap/history.jsonl";
+
+/// Summary of a single run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Sequential identifier, starting at 1.
+    pub id: u64,
+    /// Seconds since the UNIX epoch when the run started.
+    pub started_at: u64,
+    pub source: String,
+    /// Commit of the source that was applied, when it is a git repository.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    /// Error that aborted the run, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    /// Files linked by the run, with their backups.
+    #[serde(default)]
+    pub linked: Vec<LinkOutcome>,
+    #[serde(default)]
+    pub package_commands: Vec<String>,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// Run undone by this entry, when it records a rollback.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollback_of: Option<u64>,
+}
+
+impl HistoryEntry {
+    /// Whether the run completed without error.
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Most recent successful run that has not been rolled back yet.
+pub fn last_applied(entries: &[HistoryEntry]) -> Option<&HistoryEntry> {
+    let rolled_back: Vec<u64> = entries
+        .iter()
+        .filter_map(|entry| entry.rollback_of)
+        .collect();
+    entries.iter().rev().find(|entry| {
+        entry.succeeded() && entry.rollback_of.is_none() && !rolled_back.contains(&entry.id)
+    })
+}
+
+/// Location of the history file for the given home directory.
+pub fn history_path(home: &Path) -> PathBuf {
+    home.join(HISTORY_PATH)
+}
+
+/// Load every recorded run, oldest first.
+pub fn load_history(home: &Path) -> Result<Vec<HistoryEntry>> {
+    let path = history_path(home);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    fs::read_to_string(&path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line).map_err(|source| DotstrapError::State {
+                source,
+                path: path.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Append `entry` under the next free identifier, which is returned.
+pub fn append_entry(home: &Path, mut entry: HistoryEntry) -> Result<u64> {
+    let path = history_path(home);
+    entry.id = load_history(home)?.last().map_or(1, |last| last.id + 1);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut line = serde_json::to_string(&entry).map_err(|source| DotstrapError::State {
+        source,
+        path: path.clone(),
+    })?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?
+        .write_all(line.as_bytes())?;
+    Ok(entry.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::outcome::ItemStatus;
+    use tempfile::TempDir;
+
+    fn entry(error: Option<&str>) -> HistoryEntry {
+        HistoryEntry {
+            id: 0,
+            started_at: 1_700_000_000,
+            source: "https://example.com/dotfiles.git".into(),
+            revision: Some("abc123".into()),
+            error: error.map(str::to_string),
+            created: 1,
+            updated: 0,
+            unchanged: 2,
+            linked: vec![LinkOutcome::new(
+                PathBuf::from("/home/me/.zshrc"),
+                ItemStatus::Created,
+            )],
+            package_commands: Vec::new(),
+            warnings: Vec::new(),
+            rollback_of: None,
+        }
+    }
+
+    #[test]
+    fn load_history_returns_nothing_when_missing() {
+        let home = TempDir::new().unwrap();
+
+        assert!(load_history(home.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn append_entry_assigns_sequential_ids() {
+        let home = TempDir::new().unwrap();
+
+        assert_eq!(append_entry(home.path(), entry(None)).unwrap(), 1);
+        assert_eq!(append_entry(home.path(), entry(Some("boom"))).unwrap(), 2);
+
+        let history = load_history(home.path()).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].id, 1);
+        assert!(history[0].succeeded());
+        assert_eq!(history[1].error.as_deref(), Some("boom"));
+        assert_eq!(history[1].linked, entry(None).linked);
+    }
+
+    #[test]
+    fn last_applied_skips_failures_and_rolled_back_runs() {
+        let home = TempDir::new().unwrap();
+        append_entry(home.path(), entry(None)).unwrap();
+        append_entry(home.path(), entry(None)).unwrap();
+        append_entry(home.path(), entry(Some("boom"))).unwrap();
+        let history = load_history(home.path()).unwrap();
+        assert_eq!(last_applied(&history).map(|entry| entry.id), Some(2));
+
+        let mut rollback = entry(None);
+        rollback.rollback_of = Some(2);
+        append_entry(home.path(), rollback).unwrap();
+        let history = load_history(home.path()).unwrap();
+        assert_eq!(last_applied(&history).map(|entry| entry.id), Some(1));
+    }
+}
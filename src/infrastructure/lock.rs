@@ -0,0 +1,61 @@
+//! Advisory lock preventing concurrent runs against the same home directory.
+
+use std::fs::{self, File, TryLockError};
+use std::path::{Path, PathBuf};
+
+use crate::errors::{DotstrapError, Result};
+
+/// Path of the lock file for `home`.
+pub fn lock_path(home: &Path) -> PathBuf {
+    home.join(".dotstrap/lock")
+}
+
+/// Exclusive lock held until dropped.
+#[derive(Debug)]
+pub struct RunLock {
+    _file: File,
+}
+
+impl RunLock {
+    /// Lock `home`, blocking until other runs finish when `wait` is set and
+    /// failing with [`DotstrapError::Locked`] otherwise.
+    pub fn acquire(home: &Path, wait: bool) -> Result<Self> {
+        let path = lock_path(home);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+        if wait {
+            file.lock()?;
+        } else {
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => return Err(DotstrapError::Locked(path)),
+                Err(TryLockError::Error(err)) => return Err(err.into()),
+            }
+        }
+        Ok(RunLock { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn second_lock_fails_until_first_is_released() {
+        let home = TempDir::new().unwrap();
+
+        let first = RunLock::acquire(home.path(), false).unwrap();
+        let error = RunLock::acquire(home.path(), false).expect_err("home is locked");
+        assert!(matches!(error, DotstrapError::Locked(path) if path == lock_path(home.path())));
+
+        drop(first);
+        RunLock::acquire(home.path(), false).expect("lock was released");
+    }
+}
@@ -0,0 +1,76 @@
+//! Per-run log files kept under `~/.dotstrap/logs`.
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use crate::errors::Result;
+
+/// Number of run logs kept, including the one being written.
+pub const KEPT_LOGS: usize = 20;
+
+/// Directory holding the run logs for `home`.
+pub fn logs_dir(home: &Path) -> PathBuf {
+    home.join(".dotstrap/logs")
+}
+
+/// Create the log file of a run started at `now` (seconds since the epoch),
+/// removing the oldest logs so that at most [`KEPT_LOGS`] remain.
+pub fn create_run_log(home: &Path, now: u64) -> Result<(PathBuf, File)> {
+    let dir = logs_dir(home);
+    fs::create_dir_all(&dir)?;
+    rotate(&dir, KEPT_LOGS - 1)?;
+    let path = dir.join(format!("run-{now}-{}.log", std::process::id()));
+    let file = File::create(&path)?;
+    Ok((path, file))
+}
+
+/// Delete the oldest `*.log` files in `dir` beyond the newest `keep`.
+pub fn rotate(dir: &Path, keep: usize) -> Result<()> {
+    let mut logs: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .collect();
+    // Names start with the run timestamp, so they sort chronologically.
+    logs.sort();
+    let excess = logs.len().saturating_sub(keep);
+    for path in &logs[..excess] {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn create_run_log_rotates_old_logs() {
+        let home = TempDir::new().unwrap();
+        let dir = logs_dir(home.path());
+        fs::create_dir_all(&dir).unwrap();
+        for idx in 0..KEPT_LOGS {
+            fs::write(dir.join(format!("run-{}-1.log", 1_700_000_000 + idx)), "").unwrap();
+        }
+        fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let (path, _file) = create_run_log(home.path(), 1_800_000_000).unwrap();
+
+        assert!(path.exists());
+        assert!(!dir.join("run-1700000000-1.log").exists());
+        assert!(dir.join("run-1700000001-1.log").exists());
+        assert!(dir.join("notes.txt").exists());
+        let logs = fs::read_dir(&dir)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .path()
+                    .extension()
+                    .is_some_and(|ext| ext == "log")
+            })
+            .count();
+        assert_eq!(logs, KEPT_LOGS);
+    }
+}
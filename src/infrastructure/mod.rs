@@ -1,5 +1,19 @@
 //! Infrastructure adapters for interacting with the host system.
 
+pub mod answers;
+#[cfg(feature = "tokio")]
+pub mod async_command;
 pub mod command;
+pub mod download;
+pub mod encoding;
+pub mod escalation;
+pub mod filesystem;
+pub mod history;
+pub mod lock;
+pub mod logs;
+pub mod paths;
+pub mod progress;
 pub mod repository;
 pub mod secrets;
+pub mod state;
+pub mod user_config;
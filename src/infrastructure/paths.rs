@@ -0,0 +1,440 @@
+//! Shell-style expansion and glob matching of user supplied paths, ignore
+//! rules, and the well-known directories template destinations can start
+//! from.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::errors::{DotstrapError, Result};
+
+/// Expand a leading `~` and `$VAR` / `${VAR}` references in `input`.
+///
+/// Scripts and config files frequently pass paths without a shell expanding
+/// them first. Unknown variables are left untouched.
+pub fn expand(input: &str) -> String {
+    expand_with(input, home::home_dir(), |name| std::env::var(name).ok())
+}
+
+/// Expand `input` into a path, see [`expand`].
+pub fn expand_path(input: &str) -> PathBuf {
+    PathBuf::from(expand(input))
+}
+
+fn expand_with(
+    input: &str,
+    home: Option<PathBuf>,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> String {
+    let mut expanded = String::with_capacity(input.len());
+    let mut rest = input;
+    if let Some(home) = home
+        && (rest == "~" || rest.starts_with("~/"))
+    {
+        expanded.push_str(&home.to_string_lossy());
+        rest = &rest[1..];
+    }
+    while let Some(idx) = rest.find('$') {
+        expanded.push_str(&rest[..idx]);
+        let after = &rest[idx + 1..];
+        let (name, len) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+        match lookup(name).filter(|_| !name.is_empty()) {
+            Some(value) => expanded.push_str(&value),
+            None => expanded.push_str(&rest[idx..idx + 1 + len]),
+        }
+        rest = &after[len..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Whether `path` matches the glob `pattern`, in which `*` and `?` match
+/// within one path component and `**` matches any number of components.
+/// Components are compared with `/` separators on every platform.
+pub fn glob_match(pattern: &str, path: &Path) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c == '\\' { '/' } else { c })
+        .collect();
+    glob_match_chars(&pattern, &path)
+}
+
+fn glob_match_chars(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', '/', rest @ ..] => {
+            glob_match_chars(rest, text)
+                || (0..text.len())
+                    .filter(|&i| text[i] == '/')
+                    .any(|i| glob_match_chars(rest, &text[i + 1..]))
+        }
+        ['*', '*', rest @ ..] => (0..=text.len()).any(|i| glob_match_chars(rest, &text[i..])),
+        ['*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != '/')
+            .any(|i| glob_match_chars(rest, &text[i..])),
+        ['?', rest @ ..] => {
+            text.first().is_some_and(|&c| c != '/') && glob_match_chars(rest, &text[1..])
+        }
+        [c, rest @ ..] => text.first() == Some(c) && glob_match_chars(rest, &text[1..]),
+    }
+}
+
+/// Paths excluded by an ignore file, such as `.dotstrapignore`, written in
+/// the gitignore syntax.
+///
+/// Each line is a glob of [`glob_match`]; `#` starts a comment. A pattern
+/// without a `/`, other than a trailing one, matches a name at any depth,
+/// while one with a `/` is relative to the directory of the file. A trailing
+/// `/` only matches directories, and everything below an ignored directory
+/// is ignored too. A leading `!` includes again what earlier patterns
+/// excluded; the last matching pattern wins.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct IgnoreRule {
+    pattern: String,
+    negated: bool,
+    directory_only: bool,
+    anchored: bool,
+}
+
+impl IgnoreRules {
+    /// Rules of an ignore file reading `text`.
+    pub fn parse(text: &str) -> Self {
+        let rules = text
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim_end();
+                if line.is_empty() || line.starts_with('#') {
+                    return None;
+                }
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, line.strip_prefix('\\').unwrap_or(line)),
+                };
+                let (directory_only, line) = match line.strip_suffix('/') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+                let anchored = line.contains('/');
+                let pattern = line.strip_prefix('/').unwrap_or(line).to_string();
+                (!pattern.is_empty()).then_some(IgnoreRule {
+                    pattern,
+                    negated,
+                    directory_only,
+                    anchored,
+                })
+            })
+            .collect();
+        IgnoreRules { rules }
+    }
+
+    /// Whether `path`, relative to the directory of the ignore file, is
+    /// ignored; `is_dir` tells whether it is a directory.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+        let components: Vec<_> = path.components().collect();
+        (1..=components.len()).any(|depth| {
+            let prefix: PathBuf = components[..depth].iter().collect();
+            self.matches(&prefix, depth < components.len() || is_dir)
+        })
+    }
+
+    /// Whether the last rule matching `path` excludes it.
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        let name = path.file_name().map(Path::new).unwrap_or(path);
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                let candidate = if rule.anchored { path } else { name };
+                (is_dir || !rule.directory_only) && glob_match(&rule.pattern, candidate)
+            })
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+/// Well-known directories of a home, named by the `{name}` placeholders
+/// template destinations can start with, e.g. `{appdata}/alacritty`.
+///
+/// Each follows the convention of the platform: `{appdata}` is
+/// `%APPDATA%` on Windows, `~/Library/Application Support` on macOS, and
+/// `$XDG_CONFIG_HOME` elsewhere. The `{xdg_*}` directories follow the XDG base
+/// directory specification on every platform.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownDirs {
+    home: PathBuf,
+    dirs: BTreeMap<&'static str, PathBuf>,
+}
+
+impl KnownDirs {
+    /// Directories of `home` on this host. Environment variables overriding
+    /// them, such as `%APPDATA%`, are only honoured when `home` is the home
+    /// of the current user.
+    pub fn for_home(home: &Path) -> Self {
+        let own = home::home_dir().is_some_and(|current| current == home);
+        KnownDirs::resolve(home, std::env::consts::OS, |name| {
+            std::env::var_os(name).filter(|_| own).map(PathBuf::from)
+        })
+    }
+
+    /// Directories of `home` on a host running `os`, ignoring the environment
+    /// variables of this one.
+    pub fn for_os(home: &Path, os: &str) -> Self {
+        KnownDirs::resolve(home, os, |_| None)
+    }
+
+    fn resolve(home: &Path, os: &str, lookup: impl Fn(&str) -> Option<PathBuf>) -> Self {
+        let under = |parts: &[&str]| {
+            parts
+                .iter()
+                .fold(home.to_path_buf(), |dir, part| dir.join(part))
+        };
+        let var = |name: &str, parts: &[&str]| {
+            lookup(name)
+                .filter(|dir| dir.is_absolute())
+                .unwrap_or_else(|| under(parts))
+        };
+        let (appdata, localappdata) = match os {
+            "windows" => (
+                var("APPDATA", &["AppData", "Roaming"]),
+                var("LOCALAPPDATA", &["AppData", "Local"]),
+            ),
+            "macos" => (
+                under(&["Library", "Application Support"]),
+                under(&["Library", "Application Support"]),
+            ),
+            _ => (
+                var("XDG_CONFIG_HOME", &[".config"]),
+                var("XDG_DATA_HOME", &[".local", "share"]),
+            ),
+        };
+        let dirs = BTreeMap::from([
+            ("appdata", appdata),
+            ("localappdata", localappdata),
+            ("documents", under(&["Documents"])),
+            ("xdg_config", var("XDG_CONFIG_HOME", &[".config"])),
+            ("xdg_data", var("XDG_DATA_HOME", &[".local", "share"])),
+            ("xdg_cache", var("XDG_CACHE_HOME", &[".cache"])),
+        ]);
+        KnownDirs {
+            home: home.to_path_buf(),
+            dirs,
+        }
+    }
+
+    /// Home directory the other directories are resolved in.
+    pub fn home(&self) -> &Path {
+        &self.home
+    }
+
+    /// Directory named `name`, as an absolute path.
+    pub fn get(&self, name: &str) -> Option<&Path> {
+        self.dirs.get(name).map(PathBuf::as_path)
+    }
+
+    /// Every directory with its name, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &Path)> {
+        self.dirs.iter().map(|(name, dir)| (*name, dir.as_path()))
+    }
+
+    /// `destination` with its leading `{name}` placeholder replaced, relative
+    /// to the home directory.
+    pub fn expand(&self, destination: &Path) -> Result<PathBuf> {
+        let invalid = |reason: String| DotstrapError::InvalidDestination {
+            destination: destination.to_path_buf(),
+            reason,
+        };
+        let text = destination.to_string_lossy();
+        let Some(placeholder) = text.strip_prefix('{') else {
+            if text.contains('{') {
+                return Err(invalid(
+                    "directory placeholders such as `{appdata}` must start the destination".into(),
+                ));
+            }
+            return Ok(destination.to_path_buf());
+        };
+        let (name, rest) = placeholder
+            .split_once('}')
+            .ok_or_else(|| invalid("`{` is not closed".into()))?;
+        let dir = self.get(name).ok_or_else(|| {
+            let known: Vec<String> = self.dirs.keys().map(|name| format!("{{{name}}}")).collect();
+            invalid(format!(
+                "unknown directory `{{{name}}}`, expected one of {}",
+                known.join(", ")
+            ))
+        })?;
+        let expanded = dir.join(rest.trim_start_matches(['/', '\\']));
+        match expanded.strip_prefix(&self.home) {
+            Ok(relative) => Ok(relative.to_path_buf()),
+            Err(_) => Err(invalid(format!(
+                "it resolves to {}, outside the home directory {}",
+                expanded.display(),
+                self.home.display()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        (name == "XDG_CONFIG_HOME").then(|| "/home/me/.config".to_string())
+    }
+
+    #[test]
+    fn expand_replaces_leading_tilde() {
+        let home = Some(PathBuf::from("/home/me"));
+
+        assert_eq!(
+            expand_with("~/dotfiles", home.clone(), lookup),
+            "/home/me/dotfiles"
+        );
+        assert_eq!(expand_with("~", home.clone(), lookup), "/home/me");
+        assert_eq!(expand_with("a/~/b", home.clone(), lookup), "a/~/b");
+        assert_eq!(expand_with("~other/x", home, lookup), "~other/x");
+    }
+
+    #[test]
+    fn expand_replaces_known_variables() {
+        assert_eq!(
+            expand_with("$XDG_CONFIG_HOME/dotstrap", None, lookup),
+            "/home/me/.config/dotstrap"
+        );
+        assert_eq!(
+            expand_with("${XDG_CONFIG_HOME}dotstrap", None, lookup),
+            "/home/me/.configdotstrap"
+        );
+        assert_eq!(
+            expand_with("$UNSET/x and $", None, lookup),
+            "$UNSET/x and $"
+        );
+        assert_eq!(expand_with("${broken", None, lookup), "${broken");
+    }
+
+    #[test]
+    fn known_dirs_follow_the_platform_conventions() {
+        let home = Path::new("/home/me");
+        let none = |_: &str| None;
+
+        let linux = KnownDirs::resolve(home, "linux", |name| {
+            (name == "XDG_CONFIG_HOME").then(|| PathBuf::from("/home/me/.cfg"))
+        });
+        assert_eq!(linux.get("appdata"), Some(Path::new("/home/me/.cfg")));
+        assert_eq!(
+            linux.get("localappdata"),
+            Some(Path::new("/home/me/.local/share"))
+        );
+        let macos = KnownDirs::resolve(home, "macos", none);
+        assert_eq!(
+            macos.get("appdata"),
+            Some(Path::new("/home/me/Library/Application Support"))
+        );
+        let windows = KnownDirs::resolve(home, "windows", none);
+        assert_eq!(
+            windows.get("localappdata"),
+            Some(home.join("AppData").join("Local").as_path())
+        );
+        assert_eq!(
+            windows.get("documents"),
+            Some(Path::new("/home/me/Documents"))
+        );
+
+        let macos = KnownDirs::resolve(home, "macos", |name| {
+            (name == "XDG_CACHE_HOME").then(|| PathBuf::from("/home/me/tmp"))
+        });
+        assert_eq!(macos.get("xdg_cache"), Some(Path::new("/home/me/tmp")));
+        assert_eq!(macos.get("xdg_config"), Some(Path::new("/home/me/.config")));
+    }
+
+    #[test]
+    fn known_dirs_expand_destinations_relative_to_home() {
+        let home = Path::new("/home/me");
+        let dirs = KnownDirs::resolve(home, "linux", |name| {
+            (name == "XDG_DATA_HOME").then(|| PathBuf::from("/srv/data"))
+        });
+
+        assert_eq!(
+            dirs.expand(Path::new("{appdata}/alacritty/alacritty.yml"))
+                .unwrap(),
+            PathBuf::from(".config/alacritty/alacritty.yml")
+        );
+        assert_eq!(
+            dirs.expand(Path::new(".zshrc")).unwrap(),
+            PathBuf::from(".zshrc")
+        );
+        for (destination, reason) in [
+            ("{localappdata}/x", "outside the home directory"),
+            ("{appdta}/x", "unknown directory `{appdta}`"),
+            ("a/{appdata}", "must start the destination"),
+            ("{appdata", "is not closed"),
+        ] {
+            let error = dirs.expand(Path::new(destination)).unwrap_err();
+            assert!(error.to_string().contains(reason), "{error}");
+        }
+    }
+
+    #[test]
+    fn ignore_rules_follow_the_gitignore_syntax() {
+        let rules = IgnoreRules::parse(
+            "# editor and OS cruft\n\
+             *.swp\n\
+             .DS_Store\n\
+             /build/\n\
+             cache/\n\
+             templates/**/*.bak\n\
+             *.log\n\
+             !keep.log\n",
+        );
+        let ignored = |path| rules.is_ignored(Path::new(path), false);
+
+        assert!(ignored("templates/.zshrc.hbs.swp"));
+        assert!(ignored(".DS_Store"));
+        assert!(ignored("templates/nvim/.DS_Store"));
+        assert!(ignored("build/zshrc"));
+        assert!(!ignored("templates/build/zshrc"));
+        assert!(!ignored("templates/build"));
+        assert!(ignored("templates/cache/file"));
+        assert!(!ignored("cache"));
+        assert!(rules.is_ignored(Path::new("cache"), true));
+        assert!(ignored("templates/nvim/init.lua.bak"));
+        assert!(!ignored("init.lua.bak"));
+        assert!(ignored("debug.log"));
+        assert!(!ignored("keep.log"));
+        assert!(!ignored("templates/zshrc.hbs"));
+        assert!(!IgnoreRules::default().is_ignored(Path::new(".DS_Store"), false));
+    }
+
+    #[test]
+    fn glob_match_handles_wildcards_and_components() {
+        let matches = |pattern, path| glob_match(pattern, Path::new(path));
+
+        assert!(matches(".zshrc", ".zshrc"));
+        assert!(matches(".config/*/init.lua", ".config/nvim/init.lua"));
+        assert!(!matches(".config/*", ".config/nvim/init.lua"));
+        assert!(matches(".config/**", ".config/nvim/init.lua"));
+        assert!(matches("**/init.lua", ".config/nvim/init.lua"));
+        assert!(matches("**/init.lua", "init.lua"));
+        assert!(matches("templates/zsh?c.hbs", "templates/zshrc.hbs"));
+        assert!(!matches("*.hbs", "templates/zshrc.hbs"));
+    }
+}
@@ -0,0 +1,98 @@
+//! Terminal spinner showing which phase of a run is in progress.
+
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Spinner currently drawn, which console output is printed above.
+static ACTIVE: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Standard error, written with the spinner hidden so log lines, such as the
+/// output of the commands being run, are not drawn over.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ConsoleWriter;
+
+impl Write for ConsoleWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let active = ACTIVE
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match active.as_ref() {
+            Some(bar) => bar.suspend(|| io::stderr().write(buf)),
+            None => io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// Spinner naming the current phase, or a no-op when hidden.
+pub struct Progress {
+    bar: Option<ProgressBar>,
+}
+
+impl Progress {
+    /// Progress that never draws anything.
+    pub fn hidden() -> Self {
+        Progress { bar: None }
+    }
+
+    /// Spinner drawn on stderr, ticking in the background.
+    pub fn spinner() -> Self {
+        let bar = ProgressBar::new_spinner();
+        bar.set_style(
+            ProgressStyle::with_template("{spinner} {msg} [{elapsed}]")
+                .unwrap_or_else(|_| ProgressStyle::default_spinner()),
+        );
+        bar.enable_steady_tick(Duration::from_millis(100));
+        *ACTIVE
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(bar.clone());
+        Progress { bar: Some(bar) }
+    }
+
+    /// Announce the phase that is starting.
+    pub fn phase(&self, message: &str) {
+        if let Some(bar) = &self.bar {
+            bar.set_message(message.to_string());
+        }
+    }
+
+    /// Hide the spinner while running `f`, e.g. to prompt the user.
+    pub fn suspend<T>(&self, f: impl FnOnce() -> T) -> T {
+        match &self.bar {
+            Some(bar) => bar.suspend(f),
+            None => f(),
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+            ACTIVE
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .take();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hidden_progress_ignores_phases() {
+        let progress = Progress::hidden();
+
+        progress.phase("Rendering templates");
+
+        assert!(progress.bar.is_none());
+    }
+}
@@ -1,8 +1,12 @@
-//! Secret resolution helpers backed by environment variables or files.
+//! Secret resolution through named providers: environment variables, files,
+//! `dotstrap-secret-<name>` executables, or providers registered by library
+//! users.
 
 use std::collections::HashMap;
+use std::ffi::OsStr;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 
 use serde::Deserialize;
 
@@ -10,50 +14,292 @@ use crate::errors::{DotstrapError, Result};
 
 const SECRETS_PATH: &str = "secrets/secrets.yaml";
 
+/// Prefix of the executables serving as secret providers, e.g.
+/// `dotstrap-secret-pass` for `from: pass`.
+pub const EXECUTABLE_PREFIX: &str = "dotstrap-secret-";
+
+/// Entry of `secrets/secrets.yaml`.
 #[derive(Debug, Deserialize)]
-#[serde(rename_all = "lowercase", tag = "from")]
-enum SecretSource {
-    Env {
-        key: String,
-        #[serde(default)]
-        optional: bool,
-    },
-    File {
-        path: PathBuf,
-    },
+struct SecretEntry {
+    from: String,
+    /// Key the provider stores the value under.
+    key: Option<String>,
+    /// Path of the file holding the value, for `from: file`.
+    path: Option<PathBuf>,
+    #[serde(default)]
+    optional: bool,
 }
 
-/// Load secrets declared in `secrets/secrets.yaml` and surface them as JSON values.
-pub fn load_secrets(repo: &Path, home: &Path) -> Result<HashMap<String, serde_json::Value>> {
+impl SecretEntry {
+    /// Key handed to the provider; file paths are resolved against `home`
+    /// (`~/`) and the repository first.
+    fn provider_key(&self, home: &Path, repo: &Path) -> String {
+        match (&self.key, &self.path) {
+            (Some(key), _) => key.clone(),
+            (None, Some(path)) => expand_path(path, home, repo).display().to_string(),
+            (None, None) => String::new(),
+        }
+    }
+
+    /// Key as declared, e.g. an unresolved file path.
+    fn declared_key(&self) -> String {
+        match (&self.key, &self.path) {
+            (Some(key), _) => key.clone(),
+            (None, Some(path)) => path.display().to_string(),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+/// Backend serving the secrets declared with `from: <name>`, such as
+/// environment variables, files, or a keychain.
+pub trait SecretProvider {
+    /// Name used in the `from` field of `secrets.yaml`.
+    fn name(&self) -> &str;
+
+    /// Value stored under `key`, or `None` when the backend has none.
+    fn fetch(&self, key: &str) -> Result<Option<String>>;
+
+    /// Whether a value is stored under `key`, without reading it. Providers
+    /// that cannot tell without fetching report `false`.
+    fn contains(&self, _key: &str) -> bool {
+        false
+    }
+
+    /// Where the value of `key` is read from, for error messages.
+    fn describe(&self, key: &str) -> String {
+        format!("{} key {key}", self.name())
+    }
+}
+
+/// Secrets stored in environment variables, declared with `from: env`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EnvSecretProvider;
+
+impl SecretProvider for EnvSecretProvider {
+    fn name(&self) -> &str {
+        "env"
+    }
+
+    fn fetch(&self, key: &str) -> Result<Option<String>> {
+        Ok(std::env::var(key).ok())
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        std::env::var_os(key).is_some()
+    }
+
+    fn describe(&self, key: &str) -> String {
+        format!("environment variable {key}")
+    }
+}
+
+/// Secrets stored in files, declared with `from: file` and a `path`; the
+/// value is the trimmed contents of the file.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FileSecretProvider;
+
+impl SecretProvider for FileSecretProvider {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn fetch(&self, key: &str) -> Result<Option<String>> {
+        let contents = fs::read_to_string(key)?;
+        Ok(Some(contents.trim().to_string()))
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        Path::new(key).is_file()
+    }
+
+    fn describe(&self, key: &str) -> String {
+        format!("file {key}")
+    }
+}
+
+/// Provider backed by an executable, `dotstrap-secret-<name>`, invoked with
+/// the key as its only argument. It prints the value on stdout, or nothing
+/// when it has none, and fails with a non-zero exit status.
+#[derive(Debug, Clone)]
+pub struct ExecutableSecretProvider {
+    name: String,
+    program: PathBuf,
+}
+
+impl ExecutableSecretProvider {
+    pub fn new(name: impl Into<String>, program: impl Into<PathBuf>) -> Self {
+        ExecutableSecretProvider {
+            name: name.into(),
+            program: program.into(),
+        }
+    }
+
+    /// Provider named `name` backed by `dotstrap-secret-<name>` in one of the
+    /// directories of `path_var`, if there is one.
+    pub fn find(name: &str, path_var: Option<&OsStr>) -> Option<Self> {
+        let file_name = format!("{EXECUTABLE_PREFIX}{name}{}", std::env::consts::EXE_SUFFIX);
+        std::env::split_paths(path_var?)
+            .map(|dir| dir.join(&file_name))
+            .find(|candidate| candidate.is_file())
+            .map(|program| ExecutableSecretProvider::new(name, program))
+    }
+}
+
+impl SecretProvider for ExecutableSecretProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn fetch(&self, key: &str) -> Result<Option<String>> {
+        let program = self.program.display().to_string();
+        let output = Command::new(&self.program)
+            .arg(key)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|err| DotstrapError::CommandIo(program.clone(), err))?;
+        if !output.status.success() {
+            return Err(DotstrapError::CommandFailed {
+                program,
+                status: output.status.code().unwrap_or(-1),
+            });
+        }
+        let value = String::from_utf8_lossy(&output.stdout);
+        let value = value.trim_end_matches(['\r', '\n']);
+        Ok((!value.is_empty()).then(|| value.to_string()))
+    }
+}
+
+/// Secret providers consulted by name, before the `dotstrap-secret-<name>`
+/// executables on `PATH`.
+pub struct SecretProviderRegistry {
+    providers: Vec<Box<dyn SecretProvider>>,
+}
+
+impl SecretProviderRegistry {
+    /// Create a registry without any provider, not even `env` and `file`.
+    pub fn empty() -> Self {
+        SecretProviderRegistry {
+            providers: Vec::new(),
+        }
+    }
+
+    /// Add a provider, replacing any registered under the same name.
+    pub fn register<P>(&mut self, provider: P)
+    where
+        P: SecretProvider + 'static,
+    {
+        self.providers
+            .retain(|registered| registered.name() != provider.name());
+        self.providers.push(Box::new(provider));
+    }
+
+    /// Registered providers in registration order.
+    pub fn providers(&self) -> impl Iterator<Item = &dyn SecretProvider> {
+        self.providers.iter().map(|provider| provider.as_ref())
+    }
+
+    /// Provider registered as `name`.
+    pub fn get(&self, name: &str) -> Option<&dyn SecretProvider> {
+        self.providers().find(|provider| provider.name() == name)
+    }
+}
+
+impl Default for SecretProviderRegistry {
+    /// Registry containing the `env` and `file` providers.
+    fn default() -> Self {
+        let mut registry = SecretProviderRegistry::empty();
+        registry.register(EnvSecretProvider);
+        registry.register(FileSecretProvider);
+        registry
+    }
+}
+
+/// Secret declared in `secrets/secrets.yaml`, without its value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecretDeclaration {
+    pub name: String,
+    /// Where the value is read from, e.g. `env GITHUB_TOKEN`.
+    pub provider: String,
+    /// Whether the value can be read on this host.
+    pub available: bool,
+    pub optional: bool,
+}
+
+/// Entries of the `secrets.yaml` of `repo`, if it has one.
+fn read_entries(repo: &Path) -> Result<Vec<(String, SecretEntry)>> {
     let path = repo.join(SECRETS_PATH);
     if !path.exists() {
-        return Ok(HashMap::new());
+        return Ok(Vec::new());
     }
     let bytes = fs::read(&path)?;
-    let entries: HashMap<String, SecretSource> =
-        serde_yaml::from_slice(&bytes).map_err(|source| DotstrapError::Yaml {
-            source,
-            path: path.clone(),
-        })?;
+    let entries: HashMap<String, SecretEntry> =
+        serde_yaml::from_slice(&bytes).map_err(|source| DotstrapError::Yaml { source, path })?;
+    Ok(entries.into_iter().collect())
+}
+
+/// List the secrets declared by `repo`, sorted by name, checking whether
+/// their values are available without reading them.
+///
+/// Secrets served by providers that cannot tell without fetching them, such
+/// as executables, are reported as unavailable.
+pub fn declared_secrets(repo: &Path, home: &Path) -> Result<Vec<SecretDeclaration>> {
+    let registry = SecretProviderRegistry::default();
+    let mut declared: Vec<SecretDeclaration> = read_entries(repo)?
+        .into_iter()
+        .map(|(name, entry)| SecretDeclaration {
+            name,
+            available: registry
+                .get(&entry.from)
+                .is_some_and(|provider| provider.contains(&entry.provider_key(home, repo))),
+            provider: format!("{} {}", entry.from, entry.declared_key()),
+            optional: entry.optional,
+        })
+        .collect();
+    declared.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(declared)
+}
+
+/// Load secrets declared in `secrets/secrets.yaml` and surface them as JSON values.
+pub fn load_secrets(repo: &Path, home: &Path) -> Result<HashMap<String, serde_json::Value>> {
+    load_secrets_with(repo, home, &SecretProviderRegistry::default())
+}
+
+/// Load secrets as [`load_secrets`] does, serving each from the provider of
+/// `registry` named by its `from` field, or else from the matching
+/// `dotstrap-secret-<name>` executable on `PATH`.
+pub fn load_secrets_with(
+    repo: &Path,
+    home: &Path,
+    registry: &SecretProviderRegistry,
+) -> Result<HashMap<String, serde_json::Value>> {
+    let path_var = std::env::var_os("PATH");
     let mut secrets = HashMap::new();
-    for (name, source) in entries {
-        match source {
-            SecretSource::Env { key, optional } => match std::env::var(&key) {
-                Ok(value) => {
-                    secrets.insert(name, serde_json::Value::String(value));
-                }
-                Err(_) if optional => {}
-                Err(_) => {
-                    return Err(DotstrapError::MissingSecret {
-                        name,
-                        provider: format!("environment variable {key}"),
-                    });
-                }
-            },
-            SecretSource::File { path: secret_path } => {
-                let resolved = expand_path(&secret_path, home, repo);
-                let contents = fs::read_to_string(&resolved)?;
-                secrets.insert(name, serde_json::Value::String(contents.trim().to_string()));
+    for (name, entry) in read_entries(repo)? {
+        let executable;
+        let provider = match registry.get(&entry.from) {
+            Some(provider) => provider,
+            None => {
+                executable = ExecutableSecretProvider::find(&entry.from, path_var.as_deref())
+                    .ok_or_else(|| DotstrapError::UnknownSecretProvider {
+                        name: name.clone(),
+                        provider: entry.from.clone(),
+                    })?;
+                &executable as &dyn SecretProvider
+            }
+        };
+        let key = entry.provider_key(home, repo);
+        match provider.fetch(&key)? {
+            Some(value) => {
+                secrets.insert(name, serde_json::Value::String(value));
+            }
+            None if entry.optional => {}
+            None => {
+                return Err(DotstrapError::MissingSecret {
+                    name,
+                    provider: provider.describe(&key),
+                });
             }
         }
     }
@@ -74,7 +320,10 @@ fn expand_path(path: &Path, home: &Path, repo: &Path) -> PathBuf {
 
 #[cfg(test)]
 mod tests {
-    use crate::infrastructure::secrets::{expand_path, load_secrets};
+    use crate::infrastructure::secrets::{
+        EnvSecretProvider, ExecutableSecretProvider, SecretProvider, SecretProviderRegistry,
+        declared_secrets, expand_path, load_secrets, load_secrets_with,
+    };
     use serial_test::serial;
     use std::collections::HashMap;
     use std::path::Path;
@@ -134,6 +383,124 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_declared_secrets_checks_availability() {
+        let repo = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(repo.path().join("secrets")).unwrap();
+        std::fs::write(
+            repo.path().join("secrets/secrets.yaml"),
+            "token:\n  from: env\n  key: DOTSTRAP_TEST_UNSET_SECRET\n  optional: true\nkey:\n  from: file\n  path: secrets/key.asc\n",
+        )
+        .unwrap();
+        std::fs::write(repo.path().join("secrets/key.asc"), "key").unwrap();
+
+        let declared = declared_secrets(repo.path(), Path::new("/home/user")).unwrap();
+
+        assert_eq!(declared.len(), 2);
+        assert_eq!(declared[0].name, "key");
+        assert!(declared[0].available);
+        assert_eq!(declared[1].provider, "env DOTSTRAP_TEST_UNSET_SECRET");
+        assert!(!declared[1].available && declared[1].optional);
+    }
+
+    struct Keychain;
+
+    impl SecretProvider for Keychain {
+        fn name(&self) -> &str {
+            "keychain"
+        }
+
+        fn fetch(&self, key: &str) -> crate::errors::Result<Option<String>> {
+            Ok((key == "github").then(|| "from-keychain".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_load_secrets_with_registered_providers() {
+        let repo = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(repo.path().join("secrets")).unwrap();
+        std::fs::write(
+            repo.path().join("secrets/secrets.yaml"),
+            "token:\n  from: keychain\n  key: github\nnpm:\n  from: keychain\n  key: npm\n  optional: true\n",
+        )
+        .unwrap();
+        let home = Path::new("/home/user");
+
+        let mut registry = SecretProviderRegistry::default();
+        registry.register(Keychain);
+
+        let secrets = load_secrets_with(repo.path(), home, &registry).unwrap();
+
+        assert_eq!(secrets.len(), 1);
+        assert_eq!(secrets["token"], serde_json::json!("from-keychain"));
+        let error = load_secrets(repo.path(), home).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .ends_with("is read from `keychain`, which is not a known secret provider"),
+            "{error}"
+        );
+        let declared = declared_secrets(repo.path(), home).unwrap();
+        assert_eq!(declared[1].provider, "keychain github");
+    }
+
+    #[test]
+    fn test_registry_replaces_providers_of_the_same_name() {
+        struct FakeEnv;
+
+        impl SecretProvider for FakeEnv {
+            fn name(&self) -> &str {
+                "env"
+            }
+
+            fn fetch(&self, _key: &str) -> crate::errors::Result<Option<String>> {
+                Ok(Some("fake".into()))
+            }
+        }
+
+        let mut registry = SecretProviderRegistry::default();
+        registry.register(FakeEnv);
+
+        let names: Vec<&str> = registry.providers().map(|p| p.name()).collect();
+        assert_eq!(names, vec!["file", "env"]);
+        assert_eq!(
+            registry.get("env").unwrap().fetch("UNSET").unwrap(),
+            Some("fake".into())
+        );
+        assert_eq!(
+            EnvSecretProvider.describe("TOKEN"),
+            "environment variable TOKEN"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_executable_providers_are_found_on_path() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let bin = tempfile::TempDir::new().unwrap();
+        let script = bin.path().join("dotstrap-secret-vault");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\n[ \"$1\" = missing ] && exit 0\n[ \"$1\" = broken ] && exit 3\necho \"secret-$1\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let path_var = std::env::join_paths([bin.path()]).unwrap();
+
+        assert!(ExecutableSecretProvider::find("pass", Some(&path_var)).is_none());
+        let vault = ExecutableSecretProvider::find("vault", Some(&path_var)).unwrap();
+
+        assert_eq!(vault.name(), "vault");
+        assert_eq!(vault.fetch("db").unwrap(), Some("secret-db".into()));
+        assert_eq!(vault.fetch("missing").unwrap(), None);
+        assert!(matches!(
+            vault.fetch("broken"),
+            Err(crate::DotstrapError::CommandFailed { status: 3, .. })
+        ));
+        assert_eq!(vault.describe("db"), "vault key db");
+    }
+
     #[test]
     fn test_expand_path_with_relative_path() {
         let home = Path::new("/home/user");
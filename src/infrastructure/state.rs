@@ -0,0 +1,157 @@
+//! Persistent record of what dotstrap manages on the host, stored under the
+//! target home directory.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{DotstrapError, Result};
+
+const STATE_PATH: &str = ".dotstrap/state.json";
+
+/// State carried between dotstrap runs.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub brew: BrewState,
+    /// Source applied by the last successful run, reused when none is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_source: Option<SourceState>,
+    /// Templates linked by the last successful run, by destination relative
+    /// to the home directory.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub templates: BTreeMap<PathBuf, TemplateOrigin>,
+}
+
+/// Source of a run as it was resolved, with local paths made absolute.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceState {
+    pub source: String,
+    #[serde(default, rename = "ref", skip_serializing_if = "Option::is_none")]
+    pub git_ref: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subdir: Option<PathBuf>,
+}
+
+/// Where the template producing a destination comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TemplateOrigin {
+    /// Source or base layer declaring the template.
+    pub source: String,
+    /// Template path, relative to `source`.
+    pub template: PathBuf,
+    /// Profile the run applied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Digest of the inputs the staged file was rendered from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inputs: Option<String>,
+    /// Digest of the staged file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Whether the staged file merges edits made to the destination, which
+    /// later runs keep rather than replace with the template output.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub merged: bool,
+    /// Permission bits the staged file was given, see the `mode` of templates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Whether the destination is a copy created once and then left alone,
+    /// see `overwrite: never`, rather than a link to the staged file.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub create_only: bool,
+}
+
+/// Homebrew packages installed by dotstrap during the last run.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BrewState {
+    #[serde(default)]
+    pub formulae: BTreeSet<String>,
+    #[serde(default)]
+    pub casks: BTreeSet<String>,
+    /// Seconds since the UNIX epoch of the last `brew update` run by dotstrap.
+    #[serde(default)]
+    pub last_update: Option<u64>,
+}
+
+impl BrewState {
+    /// Whether any formula or cask is recorded.
+    pub fn has_packages(&self) -> bool {
+        !self.formulae.is_empty() || !self.casks.is_empty()
+    }
+}
+
+/// Location of the state file for the given home directory.
+pub fn state_path(home: &Path) -> PathBuf {
+    home.join(STATE_PATH)
+}
+
+/// Load the state file, returning an empty state when none was recorded yet.
+pub fn load_state(home: &Path) -> Result<State> {
+    let path = state_path(home);
+    if !path.exists() {
+        return Ok(State::default());
+    }
+    let bytes = fs::read(&path)?;
+    serde_json::from_slice(&bytes).map_err(|source| DotstrapError::State { source, path })
+}
+
+/// Persist the state file, creating the `.dotstrap` directory if needed.
+pub fn save_state(home: &Path, state: &State) -> Result<()> {
+    let path = state_path(home);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let bytes = serde_json::to_vec_pretty(state).map_err(|source| DotstrapError::State {
+        source,
+        path: path.clone(),
+    })?;
+    fs::write(&path, bytes)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn load_state_returns_default_when_missing() {
+        let home = TempDir::new().expect("failed to create home tempdir");
+
+        let state = load_state(home.path()).expect("missing state should load");
+
+        assert_eq!(state, State::default());
+    }
+
+    #[test]
+    fn save_state_round_trips() {
+        let home = TempDir::new().expect("failed to create home tempdir");
+        let mut state = State::default();
+        state.brew.formulae.insert("ripgrep".into());
+        state.brew.casks.insert("iterm2".into());
+        state.last_source = Some(SourceState {
+            source: "git@github.com:me/dotfiles.git".into(),
+            git_ref: Some("v2".into()),
+            subdir: None,
+        });
+
+        save_state(home.path(), &state).expect("state should save");
+        let loaded = load_state(home.path()).expect("state should load");
+
+        assert_eq!(loaded, state);
+    }
+
+    #[test]
+    fn load_state_reports_corrupted_files() {
+        let home = TempDir::new().expect("failed to create home tempdir");
+        fs::create_dir_all(home.path().join(".dotstrap")).unwrap();
+        fs::write(state_path(home.path()), "not json").unwrap();
+
+        let error = load_state(home.path()).expect_err("corrupted state should fail");
+
+        assert!(matches!(error, DotstrapError::State { .. }));
+    }
+}
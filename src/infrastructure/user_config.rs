@@ -0,0 +1,122 @@
+//! Per-user defaults read from `~/.config/dotstrap/config.yaml`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::cli::{ColorChoice, RunMode};
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::escalation::Escalation;
+
+const CONFIG_PATH: &str = ".config/dotstrap/config.yaml";
+
+/// Defaults applied to flags given neither on the command line nor through
+/// their `DOTSTRAP_*` environment variable.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    /// Source applied when `SOURCE` is omitted.
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Target home directory, see `--home`.
+    #[serde(default)]
+    pub home: Option<PathBuf>,
+    /// Further homes the source is applied to, see `--home`.
+    #[serde(default)]
+    pub targets: Vec<PathBuf>,
+    #[serde(default)]
+    pub color: Option<ColorChoice>,
+    #[serde(default)]
+    pub skip_brew: Option<bool>,
+    /// Directory rendered templates are staged in, see `--staging-dir`.
+    #[serde(default)]
+    pub staging_dir: Option<PathBuf>,
+    /// Seconds after which commands are killed, see `--command-timeout`.
+    #[serde(default)]
+    pub command_timeout: Option<u64>,
+    /// Homebrew packages installed at once, see `--jobs`.
+    #[serde(default)]
+    pub jobs: Option<u16>,
+    /// How package commands needing root run, see `--escalation`.
+    #[serde(default)]
+    pub escalation: Option<Escalation>,
+    /// Kind of machine runs set up, see `--mode`.
+    #[serde(default)]
+    pub mode: Option<RunMode>,
+    /// Keys the source must be signed by, see `--trusted-key`.
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
+}
+
+/// Location of the configuration file of the user whose home is `home`.
+pub fn user_config_path(home: &Path) -> PathBuf {
+    home.join(CONFIG_PATH)
+}
+
+/// Load the user configuration, returning the defaults when there is none.
+pub fn load_user_config(home: &Path) -> Result<UserConfig> {
+    let path = user_config_path(home);
+    if !path.exists() {
+        return Ok(UserConfig::default());
+    }
+    let contents = fs::read_to_string(&path)?;
+    if contents.trim().is_empty() {
+        return Ok(UserConfig::default());
+    }
+    serde_yaml::from_str(&contents).map_err(|source| DotstrapError::Yaml { source, path })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn load_user_config_defaults_when_missing() {
+        let home = TempDir::new().unwrap();
+
+        assert_eq!(
+            load_user_config(home.path()).unwrap(),
+            UserConfig::default()
+        );
+    }
+
+    #[test]
+    fn load_user_config_reads_defaults() {
+        let home = TempDir::new().unwrap();
+        let path = user_config_path(home.path());
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            &path,
+            "source: git@github.com:me/dotfiles.git\nprofile: work\ncolor: never\nskip_brew: true\nescalation: prompt-once\n",
+        )
+        .unwrap();
+
+        let config = load_user_config(home.path()).unwrap();
+
+        assert_eq!(
+            config.source.as_deref(),
+            Some("git@github.com:me/dotfiles.git")
+        );
+        assert_eq!(config.profile.as_deref(), Some("work"));
+        assert_eq!(config.color, Some(ColorChoice::Never));
+        assert_eq!(config.skip_brew, Some(true));
+        assert_eq!(config.escalation, Some(Escalation::PromptOnce));
+    }
+
+    #[test]
+    fn load_user_config_rejects_unknown_keys() {
+        let home = TempDir::new().unwrap();
+        let path = user_config_path(home.path());
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "sourse: ~/dotfiles\n").unwrap();
+
+        assert!(matches!(
+            load_user_config(home.path()),
+            Err(DotstrapError::Yaml { .. })
+        ));
+    }
+}
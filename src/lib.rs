@@ -3,9 +3,21 @@
 
 //! Core library entry point for dotstrap.
 
-use clap::{CommandFactory, Parser};
-use clap_complete::generate;
-use std::io::{self, Write};
+use clap::{CommandFactory, FromArgMatches};
+use clap_complete::{Shell, generate};
+use std::fs::File;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing_subscriber::filter::{LevelFilter, filter_fn};
+use tracing_subscriber::prelude::*;
+
+use serde::Serialize;
+
+use crate::cli::{Command, ExportFormat, HistoryAction, ListKind};
+use crate::services::outcome::ItemStatus;
+use crate::services::schedule::{self, Scheduler};
 
 pub mod application;
 pub mod cli;
@@ -13,10 +25,17 @@ pub mod config;
 pub mod errors;
 pub mod infrastructure;
 pub mod services;
+#[cfg(feature = "tui")]
+mod tui;
 
-pub use application::{ExecutionReport, run, run_with_executor};
-pub use cli::Cli;
-pub use errors::{DotstrapError, Result};
+#[cfg(feature = "tokio")]
+pub use application::run_async;
+pub use application::{
+    Dotstrap, DotstrapBuilder, DotstrapOptions, ExecutionReport, NoopObserver, Observer, run,
+    run_with_executor, run_with_providers,
+};
+pub use cli::{Cli, ColorChoice, OutputFormat};
+pub use errors::{DotstrapError, Result, exit_code};
 
 /// Execute the CLI entrypoint using the provided iterator of arguments.
 pub fn execute_cli<I, T>(args: I) -> i32
@@ -25,39 +44,1526 @@ where
     T: Into<std::ffi::OsString> + Clone,
 {
     let args_vec: Vec<std::ffi::OsString> = args.into_iter().map(|arg| arg.into()).collect();
-    let cli = match Cli::try_parse_from(args_vec) {
-        Ok(cli) => cli,
+    let parsed = Cli::command()
+        .try_get_matches_from(args_vec)
+        .and_then(|matches| Cli::from_arg_matches(&matches).map(|cli| (cli, matches)));
+    let (mut cli, matches) = match parsed {
+        Ok(parsed) => parsed,
         Err(error) => {
             let _ = error.print();
-            return if error.use_stderr() { 1 } else { 0 };
+            return if error.use_stderr() {
+                exit_code::USAGE
+            } else {
+                exit_code::SUCCESS
+            };
         }
     };
 
     if let Some(shell) = cli.generate_completions {
+        let Some(shell) = shell.or_else(Shell::from_env) else {
+            eprintln!(
+                "dotstrap failed: cannot tell the shell from $SHELL, name it as in `--generate-completions zsh`"
+            );
+            return exit_code::USAGE;
+        };
         let mut command = Cli::command();
         command.set_bin_name("dotstrap");
+        if cli.install_completions {
+            let mut script = Vec::new();
+            generate(shell, &mut command, "dotstrap", &mut script);
+            return install_completions(shell, &script, &cli);
+        }
         let mut stdout = io::stdout();
         generate(shell, &mut command, "dotstrap", &mut stdout);
         if let Err(err) = stdout.flush() {
             eprintln!("failed to flush completions to stdout: {err}");
-            return 1;
+            return exit_code::FAILURE;
         }
-        return 0;
+        return exit_code::SUCCESS;
+    }
+    if let Some(dir) = &cli.generate_manpages {
+        return match cli::help::generate_manpages(dir) {
+            Ok(pages) => {
+                println!("Wrote {} manual page(s) to {}.", pages.len(), dir.display());
+                exit_code::SUCCESS
+            }
+            Err(err) => {
+                eprintln!("failed to write the manual pages: {err}");
+                exit_code::FAILURE
+            }
+        };
+    }
+    if let Some(Command::Help { topic }) = &cli.command {
+        return show_help(topic);
     }
 
-    match run(cli) {
+    if cli.ci {
+        cli.apply_ci(&matches);
+    }
+    // The configuration belongs to the user running dotstrap, whatever home
+    // the run targets; CI runs depend on their flags alone.
+    if !cli.ci
+        && let Some(user_home) = home::home_dir()
+    {
+        match infrastructure::user_config::load_user_config(&user_home) {
+            Ok(config) => cli.apply_user_config(&matches, config),
+            Err(err) => {
+                eprintln!("dotstrap failed: {err}");
+                return err.exit_code();
+            }
+        }
+    }
+    if cli.home.len() > 1
+        && let Err(err) = check_several_homes(&cli)
+    {
+        eprintln!("dotstrap failed: {err}");
+        return exit_code::USAGE;
+    }
+    let home = target_home(&cli);
+    let remembered = home
+        .as_deref()
+        .filter(|_| cli.effective_source().is_none())
+        .and_then(|home| remembered_source(&mut cli, home));
+    if let Some(Command::History { action }) = &cli.command {
+        return show_history(home.as_deref(), action.as_ref(), cli.output);
+    }
+    let started_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    // Runs against several homes are recorded in each of them.
+    let record_to = home.clone().filter(|_| !cli.dry_run && cli.home.len() == 1);
+    let run_log = record_to
+        .as_deref()
+        .and_then(|home| open_run_log(home, started_at));
+    // Diagnostics printed on stderr would draw over the terminal UI.
+    #[cfg(feature = "tui")]
+    if matches!(cli.command, Some(Command::Tui)) {
+        cli.quiet = true;
+    }
+    let color = cli.color;
+    init_logging(cli.verbose, cli.quiet, color, run_log);
+    tracing::info!(target: AUDIT_TARGET, "dotstrap {} started", env!("CARGO_PKG_VERSION"));
+    if let Some(source) = remembered {
+        tracing::info!("no source given, applying {source} from the last run");
+    }
+    let format = cli.output;
+    let quiet = cli.quiet;
+    let notify = cli.notify;
+    let explain = cli.explain;
+    if let Some(Command::Rollback) = &cli.command {
+        return run_rollback(home.as_deref(), started_at, &cli);
+    }
+    if let Some(Command::Verify) = &cli.command {
+        return run_verify(home.as_deref(), &cli);
+    }
+    if let Some(Command::Clean { backups }) = &cli.command {
+        return run_clean(home.as_deref(), *backups, &cli);
+    }
+    if let Some(Command::Watch { path }) = &cli.command {
+        let source = path
+            .as_ref()
+            .map(|path| path.to_string_lossy().into_owned())
+            .or_else(|| cli.source.clone());
+        return run_watch(source.as_deref(), home.as_deref(), &cli);
+    }
+    if let Some(Command::Migrate { path }) = &cli.command {
+        let source = path
+            .as_ref()
+            .map(|path| path.to_string_lossy().into_owned())
+            .or_else(|| cli.source.clone());
+        return run_migrate(source.as_deref(), &cli);
+    }
+    if let Some(Command::Export {
+        format: ExportFormat::Brewfile { path, file },
+    }) = &cli.command
+    {
+        let source = path
+            .as_ref()
+            .map(|path| path.to_string_lossy().into_owned())
+            .or_else(|| cli.source.clone());
+        return run_export_brewfile(source.as_deref(), file.as_deref());
+    }
+    if let Some(Command::Snapshot { path }) = &cli.command {
+        let source = path
+            .as_ref()
+            .map(|path| path.to_string_lossy().into_owned())
+            .or_else(|| cli.source.clone());
+        return run_snapshot(source.as_deref(), &cli);
+    }
+    if let Some(Command::Import { from, path, into }) = &cli.command {
+        return run_import(*from, path, into, &cli);
+    }
+    if let Some(Command::List { kind }) = &cli.command {
+        return run_list(home.as_deref(), *kind, &cli);
+    }
+    if let Some(Command::Lint) = &cli.command {
+        return run_lint(home.as_deref(), &cli);
+    }
+    if let Some(Command::Test { cases, update }) = &cli.command {
+        return run_test(cases, *update, &cli);
+    }
+    if let Some(Command::Owner { destination }) = &cli.command {
+        return run_owner(home.as_deref(), destination, &cli);
+    }
+    if let Some(Command::ReAdd { destination }) = &cli.command {
+        return run_re_add(home.as_deref(), destination, &cli);
+    }
+    if let Some(Command::Edit { destination, apply }) = &cli.command {
+        return run_edit(home.as_deref(), destination, *apply, &cli);
+    }
+    if let Some(command @ (Command::SourcePath | Command::Cd)) = &cli.command {
+        return run_source_path(home.as_deref(), matches!(command, Command::Cd), &cli);
+    }
+    #[cfg(feature = "tui")]
+    if let Some(Command::Tui) = &cli.command {
+        return run_tui(home.as_deref(), record_to.as_deref(), started_at, cli);
+    }
+    if let Some(Command::Sync {
+        install_schedule,
+        uninstall_schedule,
+        every,
+        ..
+    }) = &cli.command
+        && (*install_schedule || *uninstall_schedule)
+    {
+        let install = install_schedule.then_some(*every);
+        return run_schedule(home.as_deref(), install, &cli);
+    }
+    if let Some(Command::Plan { out }) = &cli.command {
+        let out = out.clone();
+        return run_plan(out.as_deref(), cli);
+    }
+    if let Some(Command::Apply {
+        plan: Some(plan), ..
+    }) = &cli.command
+    {
+        let result = home
+            .as_deref()
+            .ok_or(DotstrapError::HomeNotFound)
+            .and_then(|home| apply_saved_plan(home, plan, &cli));
+        if notify {
+            notify_finished(&result, cli.command_timeout());
+        }
+        if let Some(home) = &record_to
+            && let Err(err) =
+                application::history::record(home, started_at, &plan.to_string_lossy(), &result)
+        {
+            eprintln!("warning: cannot record the run in the history: {err}");
+        }
+        return report_run(result, format, quiet, explain, color, home.as_deref());
+    }
+    if cli.home.len() > 1 {
+        return run_targets(started_at, cli);
+    }
+    let source = cli.effective_source().map(str::to_string);
+    let timeout = cli.command_timeout();
+    let result = run(cli);
+    if notify {
+        notify_finished(&result, timeout);
+    }
+    if let (Some(home), Some(source)) = (&record_to, &source)
+        && let Err(err) = application::history::record(home, started_at, source, &result)
+    {
+        eprintln!("warning: cannot record the run in the history: {err}");
+    }
+    report_run(result, format, quiet, explain, color, home.as_deref())
+}
+
+/// Print the outcome of a run as `format` asks, returning the exit code.
+fn report_run(
+    result: Result<ExecutionReport>,
+    format: OutputFormat,
+    quiet: bool,
+    explain: bool,
+    color: ColorChoice,
+    home: Option<&Path>,
+) -> i32 {
+    match &result {
+        Ok(report) => {
+            for warning in &report.warnings {
+                tracing::warn!(target: AUDIT_TARGET, "{warning}");
+            }
+            tracing::info!(target: AUDIT_TARGET, "{}", report.summary());
+        }
+        Err(err) => tracing::error!(target: AUDIT_TARGET, "dotstrap failed: {err}"),
+    }
+    if format != OutputFormat::Text {
+        return print_structured(format, result);
+    }
+    match result {
+        Ok(_) if quiet => exit_code::SUCCESS,
         Ok(report) => {
+            for change in &report.upstream_changes {
+                println!("upstream: {change}");
+            }
+            for retry in &report.retries {
+                eprintln!("retried: {retry}");
+            }
+            for warning in &report.warnings {
+                eprintln!("warning: {warning}");
+            }
+            if let Some(key) = &report.ssh_public_key {
+                println!("SSH public key: {key}");
+            }
             if report.dry_run {
+                let steps = if explain {
+                    application::plan::explained_steps(&report, home)
+                } else {
+                    application::plan::steps(&report, home)
+                };
+                let color = color.enabled(io::stdout().is_terminal());
+                print!(
+                    "{}",
+                    application::plan::format(&steps, report.rendered.len(), color, explain)
+                );
+                if !report.modules.is_empty() {
+                    println!("Modules in order: {}.", report.modules.join(", "));
+                }
+                if !report.disabled_modules.is_empty() {
+                    println!("Modules disabled: {}.", report.disabled_modules.join(", "));
+                }
+                if !report.skipped.is_empty() {
+                    println!("Skipped {}.", report.skipped.join(", "));
+                }
+            } else {
+                println!("{}", report.summary());
+            }
+            exit_code::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Plan the run of `cli`, saving the plan to `out` or printing it.
+fn run_plan(out: Option<&Path>, cli: Cli) -> i32 {
+    let result = Dotstrap::builder()
+        .options(DotstrapOptions::from(cli))
+        .build()
+        .plan()
+        .and_then(|plan| match out {
+            Some(path) => application::planfile::save_plan(&plan, path).map(|()| plan),
+            None => {
+                print_structured(OutputFormat::Json, Ok(&plan));
+                Ok(plan)
+            }
+        });
+    match result {
+        Ok(plan) => {
+            for change in &plan.not_planned {
+                eprintln!("warning: applying the plan will not {change}");
+            }
+            if let Some(path) = out {
+                let changed = plan
+                    .files
+                    .iter()
+                    .filter(|file| file.action != application::planfile::FileAction::Unchanged)
+                    .count();
+                println!(
+                    "Saved the plan to {}: {changed} file(s) to link, {} command(s) to run.",
+                    path.display(),
+                    plan.commands.len()
+                );
+            }
+            exit_code::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Apply the plan saved at `path` to `home`, as `dotstrap apply --plan`.
+fn apply_saved_plan(home: &Path, path: &Path, cli: &Cli) -> Result<ExecutionReport> {
+    let plan = application::planfile::load_plan(path)?;
+    let stage_root = match &cli.staging_dir {
+        Some(path) => infrastructure::paths::expand_path(&path.to_string_lossy()),
+        None => services::linker::default_stage_root(home),
+    };
+    let executor =
+        infrastructure::command::SystemCommandExecutor::with_timeout(cli.command_timeout());
+    application::planfile::apply_plan(
+        &plan,
+        path,
+        home,
+        &stage_root,
+        cli.dry_run,
+        cli.wait,
+        &executor,
+    )
+}
+
+/// Send the desktop notification `--notify` asks for, warning when it cannot
+/// be shown.
+fn notify_finished(result: &Result<ExecutionReport>, timeout: Option<Duration>) {
+    let (title, message) = match result {
+        Ok(report) => ("dotstrap finished", report.summary()),
+        Err(err) => ("dotstrap failed", err.to_string()),
+    };
+    let executor = infrastructure::command::SystemCommandExecutor::with_timeout(timeout);
+    if let Err(err) = services::notify::notify(std::env::consts::OS, title, &message, &executor) {
+        eprintln!("warning: cannot send the desktop notification: {err}");
+    }
+}
+
+/// Target of events only written to the run log, not to the console.
+const AUDIT_TARGET: &str = "dotstrap::audit";
+
+/// Fill the source, ref, and subdirectory of `cli` from the last successful
+/// run into `home`, returning the source reused.
+fn remembered_source(cli: &mut Cli, home: &Path) -> Option<String> {
+    let last = infrastructure::state::load_state(home).ok()?.last_source?;
+    if cli.git_ref.is_none() {
+        cli.git_ref = last.git_ref;
+    }
+    if cli.subdir.is_none() {
+        cli.subdir = last.subdir;
+    }
+    cli.source = Some(last.source.clone());
+    Some(last.source)
+}
+
+/// Home directory the run targets.
+fn target_home(cli: &Cli) -> Option<PathBuf> {
+    match cli.home.first() {
+        Some(home) => Some(infrastructure::paths::expand_path(&home.to_string_lossy())),
+        None => home::home_dir(),
+    }
+}
+
+/// Refuse `--home` given several times for anything but applying a source,
+/// or with a staging directory the homes would share.
+fn check_several_homes(cli: &Cli) -> std::result::Result<(), &'static str> {
+    let applies = match &cli.command {
+        None | Some(Command::Apply { plan: None, .. } | Command::Update { .. }) => true,
+        Some(Command::Sync {
+            install_schedule,
+            uninstall_schedule,
+            ..
+        }) => !install_schedule && !uninstall_schedule,
+        Some(_) => false,
+    };
+    if !applies {
+        Err("only `apply`, `update`, and `sync` accept `--home` several times")
+    } else if cli.staging_dir.is_some() {
+        Err("`--staging-dir` cannot be shared by several `--home` directories")
+    } else {
+        Ok(())
+    }
+}
+
+/// Apply the source of `cli` to each of its homes in turn, reporting every
+/// home even after one failed, and exit with the code of the first failure.
+fn run_targets(started_at: u64, cli: Cli) -> i32 {
+    let homes: Vec<PathBuf> = cli
+        .home
+        .iter()
+        .map(|home| infrastructure::paths::expand_path(&home.to_string_lossy()))
+        .collect();
+    let source = cli.effective_source().map(str::to_string);
+    let (format, quiet, explain, color) = (cli.output, cli.quiet, cli.explain, cli.color);
+    let (notify, timeout) = (cli.notify, cli.command_timeout());
+    let options = DotstrapOptions::from(cli);
+    let mut documents = Vec::new();
+    let mut code = exit_code::SUCCESS;
+    for home in homes {
+        tracing::info!(target: AUDIT_TARGET, "applying to {}", home.display());
+        let result = Dotstrap::builder()
+            .options(DotstrapOptions {
+                home: Some(home.clone()),
+                ..options.clone()
+            })
+            .build()
+            .run();
+        if notify {
+            notify_finished(&result, timeout);
+        }
+        if let (false, Some(source)) = (options.dry_run, &source)
+            && let Err(err) = application::history::record(&home, started_at, source, &result)
+        {
+            eprintln!("warning: cannot record the run in the history: {err}");
+        }
+        let target_code = if format == OutputFormat::Text {
+            if !quiet {
+                println!("==> {}", home.display());
+            }
+            report_run(result, format, quiet, explain, color, Some(&home))
+        } else {
+            let (document, target_code) = match result {
+                Ok(report) => (
+                    serde_json::json!({ "home": home, "report": report }),
+                    exit_code::SUCCESS,
+                ),
+                Err(err) => (
+                    serde_json::json!({ "home": home, "error": err.to_string() }),
+                    err.exit_code(),
+                ),
+            };
+            documents.push(document);
+            target_code
+        };
+        if code == exit_code::SUCCESS {
+            code = target_code;
+        }
+    }
+    if format != OutputFormat::Text && print_structured(format, Ok(documents)) != exit_code::SUCCESS
+    {
+        return exit_code::FAILURE;
+    }
+    code
+}
+
+/// Create the log file of this run, warning instead of failing when the log
+/// directory is not writable.
+fn open_run_log(home: &Path, started_at: u64) -> Option<File> {
+    match infrastructure::logs::create_run_log(home, started_at) {
+        Ok((_, file)) => Some(file),
+        Err(err) => {
+            eprintln!("warning: cannot create the run log: {err}");
+            None
+        }
+    }
+}
+
+/// Send diagnostics to stderr at the level selected by `-v`/`-q`, and every
+/// debug event to the run log when there is one.
+fn init_logging(verbose: u8, quiet: bool, color: ColorChoice, run_log: Option<File>) {
+    let level = match (quiet, verbose) {
+        (true, _) => tracing::Level::ERROR,
+        (false, 0) => tracing::Level::INFO,
+        (false, 1) => tracing::Level::DEBUG,
+        (false, _) => tracing::Level::TRACE,
+    };
+    let console = tracing_subscriber::fmt::layer()
+        .with_writer(|| infrastructure::progress::ConsoleWriter)
+        .with_ansi(color.enabled(io::stderr().is_terminal()))
+        .with_target(false)
+        .without_time()
+        .with_filter(filter_fn(move |meta| {
+            meta.target() != AUDIT_TARGET && *meta.level() <= level
+        }));
+    let file = run_log.map(|file| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(Mutex::new(file))
+            .with_ansi(false)
+            .with_target(false)
+            .with_filter(LevelFilter::DEBUG)
+    });
+    // Ignore failures when a subscriber is already installed, e.g. in tests.
+    let _ = tracing_subscriber::registry()
+        .with(console)
+        .with(file)
+        .try_init();
+}
+
+/// Install the completion `script` of `shell` into the home of the user, or
+/// the one given with `--home`, and report where it went.
+fn install_completions(shell: Shell, script: &[u8], cli: &Cli) -> i32 {
+    let home = match cli.home.first() {
+        Some(home) => infrastructure::paths::expand_path(&home.to_string_lossy()),
+        None => match home::home_dir() {
+            Some(home) => home,
+            None => {
+                eprintln!("dotstrap failed: {}", DotstrapError::HomeNotFound);
+                return DotstrapError::HomeNotFound.exit_code();
+            }
+        },
+    };
+    match services::completions::install_completions(shell, &home, script) {
+        Ok(install) => {
+            println!(
+                "Installed {shell} completions in {}.",
+                install.script.display()
+            );
+            if let Some(rc_file) = install.rc_file {
                 println!(
-                    "Dry run complete: {} templates evaluated.",
-                    report.rendered.len()
+                    "Added a managed block loading them to {}; open a new shell to use them.",
+                    rc_file.display()
                 );
             }
-            0
+            if let Some((rc_file, lines)) = install.manual {
+                println!(
+                    "{} is a link, add these lines to the file it points to:",
+                    rc_file.display()
+                );
+                for line in lines {
+                    println!("  {line}");
+                }
+            }
+            exit_code::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Print the help topic named by `topic`, or else the help of the subcommand
+/// it names, such as `export brewfile`, or of dotstrap itself when empty.
+fn show_help(topic: &[String]) -> i32 {
+    if let [name] = topic
+        && let Some(topic) = cli::help::topic(name)
+    {
+        print!("{}", topic.body);
+        return exit_code::SUCCESS;
+    }
+    let mut command = Cli::command().bin_name("dotstrap");
+    command.build();
+    let mut current = &mut command;
+    for name in topic {
+        match current.find_subcommand_mut(name) {
+            Some(subcommand) => current = subcommand,
+            None => {
+                eprint!(
+                    "dotstrap failed: no help topic or subcommand `{}`\n\n{}",
+                    topic.join(" "),
+                    cli::help::topics_help()
+                );
+                return exit_code::USAGE;
+            }
+        }
+    }
+    match current.print_long_help() {
+        Ok(()) => exit_code::SUCCESS,
+        Err(err) => {
+            eprintln!("failed to print the help: {err}");
+            exit_code::FAILURE
+        }
+    }
+}
+
+/// List the recorded runs, or show the details of one of them.
+fn show_history(home: Option<&Path>, action: Option<&HistoryAction>, format: OutputFormat) -> i32 {
+    let Some(home) = home else {
+        eprintln!("dotstrap failed: {}", DotstrapError::HomeNotFound);
+        return exit_code::FAILURE;
+    };
+    let entries = match infrastructure::history::load_history(home) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            return err.exit_code();
+        }
+    };
+    let selected = match action {
+        None => entries,
+        Some(HistoryAction::Show { id }) => match entries.into_iter().find(|entry| entry.id == *id)
+        {
+            Some(entry) => vec![entry],
+            None => {
+                eprintln!("dotstrap failed: no run with id {id} in the history");
+                return exit_code::FAILURE;
+            }
+        },
+    };
+    match format {
+        OutputFormat::Text => match action {
+            None => print!("{}", application::history::format_list(&selected)),
+            Some(_) => print!("{}", application::history::format_entry(&selected[0])),
+        },
+        OutputFormat::Json => match serde_json::to_string_pretty(&selected) {
+            Ok(rendered) => println!("{rendered}"),
+            Err(err) => {
+                eprintln!("failed to serialize the history: {err}");
+                return exit_code::FAILURE;
+            }
+        },
+        OutputFormat::Yaml => match serde_yaml::to_string(&selected) {
+            Ok(rendered) => print!("{rendered}"),
+            Err(err) => {
+                eprintln!("failed to serialize the history: {err}");
+                return exit_code::FAILURE;
+            }
+        },
+    }
+    exit_code::SUCCESS
+}
+
+/// Re-apply the templates of a local source on every change until
+/// interrupted.
+fn run_watch(source: Option<&str>, home: Option<&Path>, cli: &Cli) -> i32 {
+    let quiet = cli.quiet;
+    let paths = source
+        .ok_or(DotstrapError::SourceMissing)
+        .and_then(|source| Ok((source, home.ok_or(DotstrapError::HomeNotFound)?)));
+    let (source, home) = match paths {
+        Ok(paths) => paths,
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            return err.exit_code();
         }
+    };
+    let source = infrastructure::paths::expand_path(source);
+    if !quiet {
+        println!(
+            "Watching {} for changes, press Ctrl-C to stop.",
+            source.display()
+        );
+    }
+    let stage_root = match &cli.staging_dir {
+        Some(path) => infrastructure::paths::expand_path(&path.to_string_lossy()),
+        None => services::linker::default_stage_root(home),
+    };
+    let executor =
+        infrastructure::command::SystemCommandExecutor::with_timeout(cli.command_timeout());
+    let profile = cli.profile.as_deref();
+    let result = application::watch::watch(
+        &source,
+        home,
+        &stage_root,
+        profile,
+        cli.template_permissions(),
+        &executor,
+        |applied| match applied {
+            Ok(links) => {
+                for link in links
+                    .iter()
+                    .filter(|link| link.status != ItemStatus::Unchanged)
+                {
+                    tracing::info!(target: AUDIT_TARGET, "{:?} {}", link.status, link.path.display());
+                    if !quiet {
+                        println!("{:?} {}", link.status, link.path.display());
+                    }
+                }
+            }
+            Err(err) => eprintln!("dotstrap failed: {err}"),
+        },
+    );
+    match result {
+        Ok(()) => exit_code::SUCCESS,
         Err(err) => {
             eprintln!("dotstrap failed: {err}");
-            1
+            err.exit_code()
+        }
+    }
+}
+
+/// Install the schedule running `dotstrap update` every `install` interval,
+/// or uninstall it when `install` is `None`.
+fn run_schedule(home: Option<&Path>, install: Option<Duration>, cli: &Cli) -> i32 {
+    let scheduler = Scheduler::native();
+    let executor =
+        infrastructure::command::SystemCommandExecutor::with_timeout(cli.command_timeout());
+    let result = home
+        .ok_or(DotstrapError::HomeNotFound)
+        .and_then(|home| match install {
+            Some(interval) => {
+                let command = scheduled_command(home, cli)?;
+                schedule::install_schedule(
+                    scheduler,
+                    home,
+                    &command,
+                    interval,
+                    &executor,
+                    cli.dry_run,
+                )
+            }
+            None => schedule::uninstall_schedule(scheduler, home, &executor, cli.dry_run),
+        });
+    match result {
+        Ok(actions) => {
+            if !cli.quiet {
+                for action in actions {
+                    println!("{action}");
+                }
+            }
+            exit_code::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Arguments of the `dotstrap update` invocation run by the schedule.
+///
+/// The access token is not persisted: scheduled runs of private HTTPS
+/// sources need `DOTSTRAP_GIT_TOKEN` in the service environment.
+fn scheduled_command(home: &Path, cli: &Cli) -> Result<Vec<String>> {
+    let source = cli.effective_source().ok_or(DotstrapError::SourceMissing)?;
+    let expanded = infrastructure::paths::expand(source);
+    // Relative local sources must survive the change of working directory.
+    let source = match Path::new(&expanded).canonicalize() {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => expanded,
+    };
+    let program = std::env::current_exe()?;
+    let mut command = vec![
+        program.to_string_lossy().into_owned(),
+        "update".to_string(),
+        source,
+        "--home".to_string(),
+        home.to_string_lossy().into_owned(),
+        "--quiet".to_string(),
+    ];
+    if let Some(git_ref) = &cli.git_ref {
+        command.extend(["--ref".to_string(), git_ref.clone()]);
+    }
+    if let Some(profile) = &cli.profile {
+        command.extend(["--profile".to_string(), profile.clone()]);
+    }
+    if let Some(staging_dir) = &cli.staging_dir {
+        let staging_dir = infrastructure::paths::expand_path(&staging_dir.to_string_lossy());
+        command.extend([
+            "--staging-dir".to_string(),
+            staging_dir.to_string_lossy().into_owned(),
+        ]);
+    }
+    if let Some(subdir) = &cli.subdir {
+        command.extend([
+            "--subdir".to_string(),
+            subdir.to_string_lossy().into_owned(),
+        ]);
+    }
+    if let Some(key) = &cli.ssh_key {
+        let key = infrastructure::paths::expand_path(&key.to_string_lossy());
+        command.extend(["--ssh-key".to_string(), key.to_string_lossy().into_owned()]);
+    }
+    for key in &cli.trusted_keys {
+        command.extend(["--trusted-key".to_string(), key.clone()]);
+    }
+    if cli.skip_brew {
+        command.push("--skip-brew".to_string());
+    }
+    if cli.skip_templates {
+        command.push("--skip-templates".to_string());
+    }
+    if cli.sync_packages {
+        command.push("--sync-packages".to_string());
+    }
+    if cli.notify {
+        command.push("--notify".to_string());
+    }
+    if let Some(timeout) = cli.command_timeout {
+        command.extend(["--command-timeout".to_string(), timeout.to_string()]);
+    }
+    if cli.jobs > 1 {
+        command.extend(["--jobs".to_string(), cli.jobs.to_string()]);
+    }
+    Ok(command)
+}
+
+/// Upgrade the manifest of a local source and print the changes.
+fn run_migrate(source: Option<&str>, cli: &Cli) -> i32 {
+    let result = source
+        .ok_or(DotstrapError::SourceMissing)
+        .and_then(|source| {
+            config::migrate::migrate(&infrastructure::paths::expand_path(source), cli.dry_run)
+        });
+    match &result {
+        Ok(migration) if !migration.dry_run && !migration.changes.is_empty() => {
+            for change in &migration.changes {
+                tracing::info!(target: AUDIT_TARGET, "migrate: {change}");
+            }
+        }
+        Ok(_) => {}
+        Err(err) => tracing::error!(target: AUDIT_TARGET, "migration failed: {err}"),
+    }
+    if cli.output != OutputFormat::Text {
+        return print_structured(cli.output, result);
+    }
+    match result {
+        Ok(_) if cli.quiet => exit_code::SUCCESS,
+        Ok(migration) if migration.changes.is_empty() => {
+            println!(
+                "{} is already at version {}.",
+                migration.path.display(),
+                migration.to
+            );
+            exit_code::SUCCESS
+        }
+        Ok(migration) => {
+            for change in &migration.changes {
+                println!("{change}");
+            }
+            let verb = if migration.dry_run {
+                "Would migrate"
+            } else {
+                "Migrated"
+            };
+            println!(
+                "{verb} {} from version {} to {}.",
+                migration.path.display(),
+                migration.from,
+                migration.to
+            );
+            exit_code::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Print the Homebrew packages of `source` as a Brewfile, or write it to
+/// `file`.
+fn run_export_brewfile(source: Option<&str>, file: Option<&Path>) -> i32 {
+    let result = source
+        .ok_or(DotstrapError::SourceMissing)
+        .and_then(|source| {
+            application::export::brewfile(&infrastructure::paths::expand_path(source))
+        })
+        .and_then(|brewfile| match file {
+            Some(file) => std::fs::write(file, brewfile).map_err(DotstrapError::from),
+            None => {
+                print!("{brewfile}");
+                Ok(())
+            }
+        });
+    match result {
+        Ok(()) => exit_code::SUCCESS,
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Add the packages installed on the host to the `packages.yaml` of `source`.
+fn run_snapshot(source: Option<&str>, cli: &Cli) -> i32 {
+    let result = source
+        .ok_or(DotstrapError::SourceMissing)
+        .and_then(|source| {
+            application::snapshot::snapshot(
+                &infrastructure::paths::expand_path(source),
+                &infrastructure::command::SystemCommandExecutor::with_timeout(
+                    cli.command_timeout(),
+                ),
+                cli.dry_run,
+            )
+        });
+    match &result {
+        Ok(snapshot) if !snapshot.dry_run => {
+            for (section, packages) in &snapshot.added {
+                tracing::info!(target: AUDIT_TARGET, "snapshot: added {} to {section}", packages.join(", "));
+            }
+        }
+        Ok(_) => {}
+        Err(err) => tracing::error!(target: AUDIT_TARGET, "snapshot failed: {err}"),
+    }
+    if cli.output != OutputFormat::Text {
+        return print_structured(cli.output, result);
+    }
+    match result {
+        Ok(snapshot) => {
+            for warning in &snapshot.warnings {
+                eprintln!("warning: {warning}");
+            }
+            if cli.quiet {
+                return exit_code::SUCCESS;
+            }
+            if snapshot.added.is_empty() {
+                println!(
+                    "{} already lists every installed package.",
+                    snapshot.path.display()
+                );
+                return exit_code::SUCCESS;
+            }
+            for (section, packages) in &snapshot.added {
+                println!("{section}: {}", packages.join(", "));
+            }
+            let verb = if snapshot.dry_run {
+                "Would add"
+            } else {
+                "Added"
+            };
+            let count: usize = snapshot.added.values().map(Vec::len).sum();
+            println!("{verb} {count} packages to {}.", snapshot.path.display());
+            exit_code::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Convert the dotfiles managed by `from` at `path` into a source in `into`.
+fn run_import(from: cli::ImportTool, path: &Path, into: &Path, cli: &Cli) -> i32 {
+    let result = application::import::import(from, path, into, cli.dry_run);
+    match &result {
+        Ok(import) if !import.dry_run => tracing::info!(
+            target: AUDIT_TARGET,
+            "imported {} files from {} into {}",
+            import.templates.len(),
+            import.from,
+            import.path.display()
+        ),
+        Ok(_) => {}
+        Err(err) => tracing::error!(target: AUDIT_TARGET, "import failed: {err}"),
+    }
+    if cli.output != OutputFormat::Text {
+        return print_structured(cli.output, result);
+    }
+    match result {
+        Ok(import) => {
+            for warning in &import.warnings {
+                eprintln!("warning: {warning}");
+            }
+            if cli.quiet {
+                return exit_code::SUCCESS;
+            }
+            for file in &import.templates {
+                println!(
+                    "{} -> ~/{}",
+                    file.source.display(),
+                    file.destination.display()
+                );
+            }
+            for hook in &import.hooks {
+                println!("post_apply hook: {hook}");
+            }
+            let verb = if import.dry_run {
+                "Would import"
+            } else {
+                "Imported"
+            };
+            println!(
+                "{verb} {} files from {} into {}.",
+                import.templates.len(),
+                import.from,
+                import.path.display()
+            );
+            exit_code::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Print the template `destination` is rendered from.
+fn run_owner(home: Option<&Path>, destination: &Path, cli: &Cli) -> i32 {
+    let result = home.ok_or(DotstrapError::HomeNotFound).and_then(|home| {
+        application::owner::owner(
+            home,
+            destination,
+            cli.effective_source(),
+            cli.profile.as_deref(),
+            &infrastructure::command::SystemCommandExecutor::with_timeout(cli.command_timeout()),
+        )
+    });
+    if cli.output != OutputFormat::Text {
+        return print_structured(cli.output, result);
+    }
+    match result {
+        Ok(owner) => {
+            println!("{}", owner.destination.display());
+            println!("  template: {}", owner.template.display());
+            println!("  source:   {}", owner.source);
+            if let Some(profile) = &owner.profile {
+                println!("  profile:  {profile}");
+            }
+            if let Some(link) = &owner.link {
+                println!("  links to: {}", link.display());
+            }
+            if !owner.applied {
+                println!("  (declared by the manifest, not applied yet)");
+            }
+            exit_code::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Open the template of `destination` in the editor, then render and link
+/// it again if `apply` is set and the template changed.
+fn run_edit(home: Option<&Path>, destination: &Path, apply: bool, cli: &Cli) -> i32 {
+    let executor =
+        infrastructure::command::SystemCommandExecutor::with_timeout(cli.command_timeout());
+    let result = home.ok_or(DotstrapError::HomeNotFound).and_then(|home| {
+        let target = application::edit::edit_target(
+            home,
+            destination,
+            cli.effective_source(),
+            cli.profile.as_deref(),
+            &executor,
+        )?;
+        let before = std::fs::read(&target.template)?;
+        let (program, args) = editor();
+        let status = std::process::Command::new(&program)
+            .args(&args)
+            .arg(&target.template)
+            .status()
+            .map_err(|err| DotstrapError::CommandIo(program.clone(), err))?;
+        if !status.success() {
+            return Err(DotstrapError::CommandFailed {
+                program,
+                status: status.code().unwrap_or(-1),
+            });
+        }
+        if !apply || std::fs::read(&target.template)? == before {
+            return Ok((target, None));
+        }
+        let stage_root = match &cli.staging_dir {
+            Some(path) => infrastructure::paths::expand_path(&path.to_string_lossy()),
+            None => services::linker::default_stage_root(home),
+        };
+        let links = application::edit::apply_target(
+            &target,
+            home,
+            &stage_root,
+            cli.template_permissions(),
+            &executor,
+        )?;
+        Ok((target, Some(links)))
+    });
+    match result {
+        Ok((_, Some(links))) => {
+            for link in links
+                .iter()
+                .filter(|link| link.status != ItemStatus::Unchanged)
+            {
+                tracing::info!(target: AUDIT_TARGET, "{:?} {}", link.status, link.path.display());
+                if !cli.quiet {
+                    println!("{:?} {}", link.status, link.path.display());
+                }
+            }
+            exit_code::SUCCESS
+        }
+        Ok((target, None)) => {
+            if apply && !cli.quiet {
+                println!(
+                    "{} is unchanged, nothing to apply.",
+                    target.template.display()
+                );
+            }
+            exit_code::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Editor command from `$VISUAL` or `$EDITOR`, which may carry arguments
+/// such as `code --wait`, defaulting to `vi` (`notepad` on Windows).
+fn editor() -> (String, Vec<String>) {
+    let configured = ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.trim().is_empty());
+    let default = if cfg!(windows) { "notepad" } else { "vi" };
+    let command = configured.unwrap_or_else(|| default.to_string());
+    let mut words = command.split_whitespace().map(str::to_string);
+    let program = words.next().unwrap_or_else(|| default.to_string());
+    (program, words.collect())
+}
+
+/// Print the directory of the source, or start a shell in it when `shell`
+/// is set, exiting with the status of the shell.
+fn run_source_path(home: Option<&Path>, shell: bool, cli: &Cli) -> i32 {
+    let result = home.ok_or(DotstrapError::HomeNotFound).and_then(|home| {
+        let source = cli.effective_source().ok_or(DotstrapError::SourceMissing)?;
+        let executor =
+            infrastructure::command::SystemCommandExecutor::with_timeout(cli.command_timeout());
+        application::source::source_path(home, source, cli.subdir.as_deref(), &executor)
+    });
+    let path = match result {
+        Ok(path) => path,
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            return err.exit_code();
+        }
+    };
+    if !shell {
+        println!("{}", path.display());
+        return exit_code::SUCCESS;
+    }
+    let program = if cfg!(windows) {
+        std::env::var_os("COMSPEC").unwrap_or_else(|| "cmd".into())
+    } else {
+        std::env::var_os("SHELL").unwrap_or_else(|| "sh".into())
+    };
+    eprintln!("Starting a shell in {}; exit it to return.", path.display());
+    match std::process::Command::new(&program)
+        .current_dir(&path)
+        .status()
+    {
+        Ok(status) => status.code().unwrap_or(exit_code::FAILURE),
+        Err(err) => {
+            let err = DotstrapError::CommandIo(program.to_string_lossy().into_owned(), err);
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Browse the plan in the terminal UI, applying it when asked to, and record
+/// the run as `apply` does.
+#[cfg(feature = "tui")]
+fn run_tui(home: Option<&Path>, record_to: Option<&Path>, started_at: u64, cli: Cli) -> i32 {
+    let source = cli.effective_source().map(str::to_string);
+    let session = home
+        .ok_or(DotstrapError::HomeNotFound)
+        .and_then(|home| tui::run(home, application::DotstrapOptions::from(cli)));
+    let result = match session {
+        Ok(tui::Session::Quit) => return exit_code::SUCCESS,
+        Ok(tui::Session::Applied(result)) => {
+            let result = *result;
+            if let (Some(home), Some(source)) = (record_to, &source)
+                && let Err(err) = application::history::record(home, started_at, source, &result)
+            {
+                eprintln!("warning: cannot record the run in the history: {err}");
+            }
+            result
+        }
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(report) => {
+            tracing::info!(target: AUDIT_TARGET, "{}", report.summary());
+            for warning in &report.warnings {
+                eprintln!("warning: {warning}");
+            }
+            println!("{}", report.summary());
+            exit_code::SUCCESS
+        }
+        Err(err) => {
+            tracing::error!(target: AUDIT_TARGET, "dotstrap failed: {err}");
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Resolve the source of `cli` at its `--ref`, narrowed to the subdirectory
+/// given by `--subdir` or the source itself.
+fn resolve_source(
+    cli: &Cli,
+    executor: &dyn infrastructure::command::CommandExecutor,
+) -> Result<infrastructure::repository::RepoHandle> {
+    let source = cli.effective_source().ok_or(DotstrapError::SourceMissing)?;
+    let (source, subdir) = infrastructure::repository::split_subdir(source);
+    let repo =
+        infrastructure::repository::resolve_repository(source, cli.git_ref.as_deref(), executor)?;
+    match cli.subdir.as_deref().or(subdir.map(Path::new)) {
+        Some(subdir) => repo.into_subdir(subdir),
+        None => Ok(repo),
+    }
+}
+
+/// Print the templates, packages, and secrets the source declares.
+fn run_list(home: Option<&Path>, kind: Option<ListKind>, cli: &Cli) -> i32 {
+    let result = home.ok_or(DotstrapError::HomeNotFound).and_then(|home| {
+        let executor =
+            infrastructure::command::SystemCommandExecutor::with_timeout(cli.command_timeout());
+        let repo = resolve_source(cli, &executor)?;
+        let stage_root = match &cli.staging_dir {
+            Some(path) => infrastructure::paths::expand_path(&path.to_string_lossy()),
+            None => services::linker::default_stage_root(home),
+        };
+        application::list::list(
+            &repo,
+            home,
+            &stage_root,
+            cli.profile.as_deref(),
+            kind,
+            &executor,
+        )
+    });
+    if cli.output != OutputFormat::Text {
+        return print_structured(cli.output, result);
+    }
+    match result {
+        Ok(listing) => {
+            print!("{}", application::list::format_listing(&listing));
+            exit_code::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Print the undefined and unused template variables of the source.
+fn run_lint(home: Option<&Path>, cli: &Cli) -> i32 {
+    let result = home.ok_or(DotstrapError::HomeNotFound).and_then(|home| {
+        let executor =
+            infrastructure::command::SystemCommandExecutor::with_timeout(cli.command_timeout());
+        let repo = resolve_source(cli, &executor)?;
+        application::lint::lint(&repo, home, cli.profile.as_deref(), &executor)
+    });
+    let code = match &result {
+        Ok(findings)
+            if findings
+                .iter()
+                .any(|finding| finding.severity == application::lint::Severity::Error) =>
+        {
+            exit_code::FAILURE
+        }
+        _ => exit_code::SUCCESS,
+    };
+    if cli.output != OutputFormat::Text {
+        return match print_structured(cli.output, result) {
+            exit_code::SUCCESS => code,
+            failed => failed,
+        };
+    }
+    match result {
+        Ok(findings) => {
+            print!("{}", application::lint::format_findings(&findings));
+            code
+        }
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Render the golden-file test cases of the source and compare them with
+/// their expected output, or update it when `update` is set.
+fn run_test(cases: &[String], update: bool, cli: &Cli) -> i32 {
+    let executor =
+        infrastructure::command::SystemCommandExecutor::with_timeout(cli.command_timeout());
+    let result = resolve_source(cli, &executor).and_then(|repo| {
+        application::golden::test_templates(
+            &repo,
+            cases,
+            update,
+            cli.template_permissions(),
+            &executor,
+        )
+    });
+    let code = match &result {
+        Ok(reports) if !reports.iter().all(application::golden::CaseReport::passed) => {
+            exit_code::FAILURE
+        }
+        _ => exit_code::SUCCESS,
+    };
+    if cli.output != OutputFormat::Text {
+        return match print_structured(cli.output, result) {
+            exit_code::SUCCESS => code,
+            failed => failed,
+        };
+    }
+    match result {
+        Ok(reports) => {
+            print!("{}", application::golden::format_reports(&reports));
+            code
+        }
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Capture the edits made to `destination` into its template.
+fn run_re_add(home: Option<&Path>, destination: &Path, cli: &Cli) -> i32 {
+    let result = home.ok_or(DotstrapError::HomeNotFound).and_then(|home| {
+        let source = cli.effective_source().ok_or(DotstrapError::SourceMissing)?;
+        application::readd::re_add(
+            source,
+            cli.subdir.as_deref(),
+            home,
+            destination,
+            cli.profile.as_deref(),
+            cli.dry_run,
+            &infrastructure::command::SystemCommandExecutor::with_timeout(cli.command_timeout()),
+        )
+    });
+    match &result {
+        Ok(report) if report.changed && !report.dry_run => tracing::info!(
+            target: AUDIT_TARGET,
+            "re-added {} into {}",
+            report.destination.display(),
+            report.template.display()
+        ),
+        Ok(_) => {}
+        Err(err) => tracing::error!(target: AUDIT_TARGET, "re-add failed: {err}"),
+    }
+    if cli.output != OutputFormat::Text {
+        return print_structured(cli.output, result);
+    }
+    match result {
+        Ok(_) if cli.quiet => exit_code::SUCCESS,
+        Ok(report) if !report.changed => {
+            println!(
+                "{} matches its template, nothing to re-add.",
+                report.destination.display()
+            );
+            exit_code::SUCCESS
+        }
+        Ok(report) => {
+            let verb = if report.dry_run {
+                "Would update"
+            } else {
+                "Updated"
+            };
+            println!(
+                "{verb} {} from {}.",
+                report.template.display(),
+                report.destination.display()
+            );
+            exit_code::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Compare the home against the state of the last run and report drift.
+fn run_verify(home: Option<&Path>, cli: &Cli) -> i32 {
+    let result = home.ok_or(DotstrapError::HomeNotFound).and_then(|home| {
+        let stage_root = match &cli.staging_dir {
+            Some(path) => infrastructure::paths::expand_path(&path.to_string_lossy()),
+            None => services::linker::default_stage_root(home),
+        };
+        application::verify::verify(home, &stage_root)
+    });
+    let code = match &result {
+        Ok(report) if !report.discrepancies.is_empty() => exit_code::DRIFT,
+        _ => exit_code::SUCCESS,
+    };
+    if cli.output != OutputFormat::Text {
+        return match print_structured(cli.output, result) {
+            exit_code::SUCCESS => code,
+            failed => failed,
+        };
+    }
+    match result {
+        Ok(report) => {
+            print!("{}", application::verify::format_report(&report));
+            code
+        }
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Remove the staging directory and the cached clones of sources no longer
+/// applied, and the backups of replaced files too when `backups` is set,
+/// printing every removed path.
+fn run_clean(home: Option<&Path>, backups: bool, cli: &Cli) -> i32 {
+    let result = home.ok_or(DotstrapError::HomeNotFound).and_then(|home| {
+        let stage_root = match &cli.staging_dir {
+            Some(path) => infrastructure::paths::expand_path(&path.to_string_lossy()),
+            None => services::linker::default_stage_root(home),
+        };
+        application::clean::clean(home, &stage_root, backups, cli.dry_run, cli.wait)
+    });
+    if let Ok(report) = &result
+        && !report.dry_run
+    {
+        for removed in &report.removed {
+            tracing::info!(target: AUDIT_TARGET, "removed {}", removed.display());
+        }
+    }
+    if cli.output != OutputFormat::Text {
+        return print_structured(cli.output, result);
+    }
+    match result {
+        Ok(_) if cli.quiet => exit_code::SUCCESS,
+        Ok(report) => {
+            for removed in &report.removed {
+                println!("{}", removed.display());
+            }
+            let verb = if report.dry_run {
+                "Would remove"
+            } else {
+                "Removed"
+            };
+            println!("{verb} {} path(s).", report.removed.len());
+            exit_code::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Undo the last applied run and print what was restored.
+fn run_rollback(home: Option<&Path>, started_at: u64, cli: &Cli) -> i32 {
+    let result = home
+        .ok_or(DotstrapError::HomeNotFound)
+        .and_then(|home| application::rollback::rollback(home, started_at, cli.dry_run, cli.wait));
+    match &result {
+        Ok(report) if !report.dry_run => {
+            tracing::info!(target: AUDIT_TARGET, "rolled back run {}", report.run);
+            for restored in &report.restored {
+                tracing::info!(target: AUDIT_TARGET, "{restored}");
+            }
+        }
+        Ok(_) => {}
+        Err(err) => tracing::error!(target: AUDIT_TARGET, "rollback failed: {err}"),
+    }
+    if cli.output != OutputFormat::Text {
+        return print_structured(cli.output, result);
+    }
+    match result {
+        Ok(_) if cli.quiet => exit_code::SUCCESS,
+        Ok(report) => {
+            for warning in &report.warnings {
+                eprintln!("warning: {warning}");
+            }
+            for restored in &report.restored {
+                println!("{restored}");
+            }
+            let verb = if report.dry_run {
+                "Would roll back"
+            } else {
+                "Rolled back"
+            };
+            println!(
+                "{verb} run {}: {} path(s) restored.",
+                report.run,
+                report.restored.len()
+            );
+            exit_code::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("dotstrap failed: {err}");
+            err.exit_code()
+        }
+    }
+}
+
+/// Print the report, or the error that aborted the command, as JSON or YAML.
+fn print_structured<T: Serialize>(format: OutputFormat, result: Result<T>) -> i32 {
+    let (document, code) = match &result {
+        Ok(report) => (serde_json::to_value(report), exit_code::SUCCESS),
+        Err(err) => (
+            Ok(serde_json::json!({ "error": err.to_string() })),
+            err.exit_code(),
+        ),
+    };
+    let rendered = document
+        .map_err(|err| err.to_string())
+        .and_then(|document| {
+            if format == OutputFormat::Yaml {
+                serde_yaml::to_string(&document).map_err(|err| err.to_string())
+            } else {
+                serde_json::to_string_pretty(&document).map_err(|err| err.to_string())
+            }
+        });
+    match rendered {
+        Ok(rendered) => {
+            println!("{}", rendered.trim_end());
+            code
+        }
+        Err(err) => {
+            eprintln!("failed to serialize the report: {err}");
+            exit_code::FAILURE
         }
     }
 }
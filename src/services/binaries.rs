@@ -0,0 +1,237 @@
+//! Service installing executables published as GitHub release assets.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tempfile::TempDir;
+use walkdir::WalkDir;
+
+use crate::config::BinarySpec;
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::CommandExecutor;
+use crate::infrastructure::download;
+use crate::infrastructure::filesystem::OsFileSystem;
+use crate::services::linker;
+use crate::services::outcome::LinkOutcome;
+
+/// Download, verify, extract, and link every declared release binary.
+///
+/// Executables are kept under `~/.dotstrap/binaries/<owner>/<repo>/<version>`
+/// and symlinked into their install directory. Pinned versions that are
+/// already present are not downloaded again.
+pub fn install_binaries(
+    home: &Path,
+    binaries: &[BinarySpec],
+    executor: &dyn CommandExecutor,
+    dry_run: bool,
+) -> Result<Vec<LinkOutcome>> {
+    let mut linked = Vec::new();
+    for spec in binaries {
+        let name = executable_name(spec);
+        let link = home.join(&spec.install_dir).join(&name);
+        let store = home
+            .join(".dotstrap/binaries")
+            .join(&spec.repo)
+            .join(&spec.version);
+        let executable = store.join(&name);
+        if dry_run {
+            linked.push(linker::planned_link(&executable, &link));
+            continue;
+        }
+        if spec.version == "latest" || !executable.exists() {
+            fetch(spec, &name, &executable, executor)?;
+        }
+        linked.push(linker::link_path(&executable, &link)?);
+    }
+    Ok(linked)
+}
+
+/// Release asset URL for the spec on the current host.
+pub fn asset_url(spec: &BinarySpec) -> String {
+    let asset = asset_name(spec, std::env::consts::OS, std::env::consts::ARCH);
+    if spec.version == "latest" {
+        format!(
+            "https://github.com/{}/releases/latest/download/{asset}",
+            spec.repo
+        )
+    } else {
+        format!(
+            "https://github.com/{}/releases/download/{}/{asset}",
+            spec.repo, spec.version
+        )
+    }
+}
+
+/// Asset file name with its placeholders substituted.
+pub fn asset_name(spec: &BinarySpec, os: &str, arch: &str) -> String {
+    let version = spec.version.strip_prefix('v').unwrap_or(&spec.version);
+    spec.asset
+        .replace("{version}", version)
+        .replace("{os}", os)
+        .replace("{arch}", arch)
+}
+
+fn executable_name(spec: &BinarySpec) -> String {
+    spec.name.clone().unwrap_or_else(|| {
+        spec.repo
+            .rsplit('/')
+            .next()
+            .unwrap_or(&spec.repo)
+            .to_string()
+    })
+}
+
+fn fetch(
+    spec: &BinarySpec,
+    name: &str,
+    executable: &Path,
+    executor: &dyn CommandExecutor,
+) -> Result<()> {
+    let workdir = TempDir::new()?;
+    let asset = asset_name(spec, std::env::consts::OS, std::env::consts::ARCH);
+    let archive = workdir.path().join(&asset);
+    download::download(executor, &asset_url(spec), &archive)?;
+    if let Some(expected) = &spec.sha256 {
+        download::verify_sha256(&archive, expected)?;
+    }
+    let extracted = workdir.path().join("extracted");
+    fs::create_dir_all(&extracted)?;
+    let source = if download::extract(&archive, &extracted, executor)? {
+        locate(spec, name, &extracted).ok_or_else(|| DotstrapError::BinaryNotFound {
+            name: name.to_string(),
+            asset: asset.clone(),
+        })?
+    } else {
+        archive
+    };
+    if let Some(parent) = executable.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(&source, executable)?;
+    linker::apply_mode(&OsFileSystem, executable, Some(0o755))
+}
+
+fn locate(spec: &BinarySpec, name: &str, extracted: &Path) -> Option<PathBuf> {
+    if let Some(path) = &spec.path {
+        let candidate = extracted.join(path);
+        return candidate.is_file().then_some(candidate);
+    }
+    WalkDir::new(extracted)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .find(|entry| entry.file_type().is_file() && entry.file_name() == name)
+        .map(|entry| entry.into_path())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::DotstrapError;
+    use crate::services::outcome::ItemStatus;
+
+    /// Executor serving a fixed payload to `curl --output <path>` invocations.
+    struct FakeCurl {
+        payload: &'static str,
+    }
+
+    impl CommandExecutor for FakeCurl {
+        fn run(&self, program: &str, args: &[&str]) -> Result<()> {
+            assert_eq!(program, "curl");
+            let output = args
+                .iter()
+                .position(|arg| *arg == "--output")
+                .map(|idx| args[idx + 1])
+                .expect("curl must be given an output path");
+            fs::write(output, self.payload)?;
+            Ok(())
+        }
+    }
+
+    fn spec(sha256: Option<&str>) -> BinarySpec {
+        BinarySpec {
+            repo: "example/tool".into(),
+            version: "v1.2.3".into(),
+            asset: "tool-{version}-{os}-{arch}".into(),
+            sha256: sha256.map(str::to_string),
+            name: None,
+            path: None,
+            install_dir: PathBuf::from(".local/bin"),
+        }
+    }
+
+    #[test]
+    fn asset_url_substitutes_placeholders() {
+        let spec = spec(None);
+
+        assert_eq!(
+            asset_name(&spec, "linux", "x86_64"),
+            "tool-1.2.3-linux-x86_64"
+        );
+        assert!(
+            asset_url(&spec)
+                .starts_with("https://github.com/example/tool/releases/download/v1.2.3/")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn install_binaries_downloads_and_links_raw_assets() {
+        let home = TempDir::new().unwrap();
+        let executor = FakeCurl { payload: "hello" };
+        let spec = spec(Some(
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+        ));
+
+        let linked = install_binaries(home.path(), &[spec], &executor, false)
+            .expect("installation should succeed");
+
+        let link = home.path().join(".local/bin/tool");
+        assert_eq!(
+            linked,
+            vec![
+                LinkOutcome::new(link.clone(), ItemStatus::Created).because("destination missing")
+            ]
+        );
+        assert!(
+            fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        assert_eq!(fs::read_to_string(&link).unwrap(), "hello");
+    }
+
+    #[test]
+    fn install_binaries_rejects_checksum_mismatch() {
+        let home = TempDir::new().unwrap();
+        let executor = FakeCurl {
+            payload: "tampered",
+        };
+        let spec = spec(Some(
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+        ));
+
+        let error = install_binaries(home.path(), &[spec], &executor, false)
+            .expect_err("checksum must be verified");
+
+        assert!(matches!(error, DotstrapError::ChecksumMismatch { .. }));
+        assert!(!home.path().join(".local/bin/tool").exists());
+    }
+
+    #[test]
+    fn install_binaries_dry_run_has_no_side_effects() {
+        let home = TempDir::new().unwrap();
+        let executor = FakeCurl { payload: "unused" };
+
+        let linked = install_binaries(home.path(), &[spec(None)], &executor, true).unwrap();
+
+        assert_eq!(
+            linked,
+            vec![
+                LinkOutcome::new(home.path().join(".local/bin/tool"), ItemStatus::Created)
+                    .because("destination missing")
+            ]
+        );
+        assert!(!home.path().join(".dotstrap").exists());
+    }
+}
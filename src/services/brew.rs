@@ -1,8 +1,388 @@
 //! Service responsible for installing Homebrew taps, formulae, and casks.
 
-use crate::config::BrewSpec;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use crate::config::{BrewSpec, Formula, PackagesSpec};
 use crate::errors::{DotstrapError, Result};
 use crate::infrastructure::command::CommandExecutor;
+use crate::infrastructure::state::BrewState;
+use crate::services::packages::{PackageProvider, PlannedCommand};
+
+const INSTALL_SCRIPT_URL: &str =
+    "https://raw.githubusercontent.com/Homebrew/install/HEAD/install.sh";
+
+/// Package provider driving Homebrew.
+pub struct BrewProvider {
+    program: String,
+    /// Prefixes searched for the `brew` installed by bootstrapping it.
+    prefixes: Vec<PathBuf>,
+}
+
+impl Default for BrewProvider {
+    /// Provider invoking the `brew` found on `PATH`.
+    fn default() -> Self {
+        BrewProvider::new("brew")
+    }
+}
+
+impl BrewProvider {
+    /// Provider invoking the given `brew` executable.
+    pub fn new(program: &str) -> Self {
+        BrewProvider {
+            program: program.to_string(),
+            prefixes: candidate_prefixes(),
+        }
+    }
+
+    /// Look for the `brew` installed by bootstrapping Homebrew under
+    /// `prefixes` instead of the well-known install prefixes.
+    pub fn with_prefixes(mut self, prefixes: Vec<PathBuf>) -> Self {
+        self.prefixes = prefixes;
+        self
+    }
+
+    /// Provider invoking `brew` from `PATH`, or by absolute path from one of
+    /// the well-known install prefixes when it is not on `PATH` yet.
+    pub fn detect() -> Self {
+        if find_on_path(std::env::var_os("PATH").as_deref()).is_some() {
+            return BrewProvider::default();
+        }
+        match find_prefix(None, &candidate_prefixes()) {
+            Some(prefix) => BrewProvider::new(&prefix.join("bin/brew").to_string_lossy()),
+            None => BrewProvider::default(),
+        }
+    }
+
+    /// Executable used to invoke Homebrew.
+    pub fn program(&self) -> &str {
+        &self.program
+    }
+
+    /// Commands required to install the given Homebrew specification.
+    pub fn plan_spec(&self, spec: &BrewSpec) -> Vec<PlannedCommand> {
+        if spec.taps.is_empty()
+            && spec.formulae.is_empty()
+            && spec.casks.is_empty()
+            && spec.mas.is_empty()
+        {
+            return Vec::new();
+        }
+        let brew = |args: &[&str]| PlannedCommand::new(&self.program, args).with_env(&spec.env);
+        let mut plan = Vec::new();
+        if !spec.skip_update {
+            plan.push(brew(&["update"]));
+        }
+        for tap in &spec.taps {
+            plan.push(brew(&["tap", tap, "--force"]));
+        }
+        for formula in &spec.formulae {
+            let name = formula.installed_name();
+            plan.push(brew(&["install", &name]));
+            if formula.version.is_some() {
+                plan.push(brew(&["pin", &name]));
+            }
+        }
+        for cask in &spec.casks {
+            plan.push(brew(&["install", "--cask", cask]));
+        }
+        if !spec.mas.is_empty() {
+            plan.push(brew(&["install", "mas"]));
+        }
+        let mas = Path::new(&self.program).with_file_name("mas");
+        for app in &spec.mas {
+            plan.push(PlannedCommand::new(
+                &mas.to_string_lossy(),
+                &["install", &app.id.to_string()],
+            ));
+        }
+        plan
+    }
+
+    /// Formulae and casks currently installed, none when Homebrew itself is
+    /// not installed yet.
+    pub fn installed(&self, executor: &dyn CommandExecutor) -> Result<BrewState> {
+        if !self.is_available(executor) {
+            return Ok(BrewState::default());
+        }
+        let list = |kind: &str| -> Result<_> {
+            let listed = executor.output(&self.program, &["list", kind, "-1"])?;
+            Ok(listed.lines().map(|name| name.trim().to_string()).collect())
+        };
+        Ok(BrewState {
+            formulae: list("--formula")?,
+            casks: list("--cask")?,
+            last_update: None,
+        })
+    }
+
+    /// Commands uninstalling packages recorded in `previous` but no longer
+    /// declared in `spec`.
+    pub fn plan_removals(&self, previous: &BrewState, spec: &BrewSpec) -> Vec<PlannedCommand> {
+        let brew = |args: &[&str]| PlannedCommand::new(&self.program, args).with_env(&spec.env);
+        let mut plan = Vec::new();
+        let declared: Vec<String> = spec.formulae.iter().map(Formula::installed_name).collect();
+        for formula in &previous.formulae {
+            if !declared.contains(formula) {
+                plan.push(brew(&["uninstall", formula]));
+            }
+        }
+        for cask in &previous.casks {
+            if !spec.casks.contains(cask) {
+                plan.push(brew(&["uninstall", "--cask", cask]));
+            }
+        }
+        plan
+    }
+
+    /// Dependencies not installed yet of the formulae `installs` install, by
+    /// formula name without its tap, or `None` when they cannot be listed.
+    fn missing_dependencies(
+        &self,
+        installs: &[PlannedCommand],
+        executor: &dyn CommandExecutor,
+    ) -> Option<HashMap<String, BTreeSet<String>>> {
+        let formulae: Vec<&str> = installs
+            .iter()
+            .filter(|command| command.args.get(1).is_some_and(|arg| arg != "--cask"))
+            .filter_map(|command| command.args.last().map(String::as_str))
+            .collect();
+        if formulae.is_empty() {
+            return Some(HashMap::new());
+        }
+        let mut args = vec!["deps", "--for-each", "--missing"];
+        args.extend(&formulae);
+        let listed = executor.output(&self.program, &args).ok()?;
+        Some(
+            listed
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .map(|(formula, deps)| {
+                    (
+                        short_name(formula.trim()),
+                        deps.split_whitespace().map(short_name).collect(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// Ensure every formula `plan` pins resolves, so a version Homebrew does
+    /// not provide fails before anything is installed rather than midway.
+    ///
+    /// Runs once the taps are added, as versioned formulae may come from them.
+    fn check_pins(&self, plan: &[PlannedCommand], executor: &dyn CommandExecutor) -> Result<()> {
+        let pinned = plan
+            .iter()
+            .filter(|command| command.program == self.program)
+            .filter(|command| command.args.first().is_some_and(|arg| arg == "pin"))
+            .filter_map(|command| command.args.get(1));
+        for formula in pinned {
+            if executor
+                .output(&self.program, &["info", "--json=v2", formula])
+                .is_err()
+            {
+                let name = formula
+                    .rsplit_once('@')
+                    .map_or(formula.as_str(), |(name, _)| name);
+                return Err(DotstrapError::InvalidPin {
+                    name: name.to_string(),
+                    reason: format!("Homebrew and the declared taps provide no `{formula}`"),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Index of the first formula or cask install of `plan`, which the taps
+    /// precede.
+    fn first_install(&self, plan: &[PlannedCommand]) -> usize {
+        plan.iter()
+            .position(|command| {
+                command.program == self.program
+                    && command.args.first().is_some_and(|arg| arg == "install")
+            })
+            .unwrap_or(plan.len())
+    }
+}
+
+/// Whether `brew update` should run given the last recorded update time and
+/// the configured interval, all expressed in seconds since the UNIX epoch.
+pub fn update_due(last_update: Option<u64>, interval_hours: Option<u64>, now: u64) -> bool {
+    match (last_update, interval_hours) {
+        (Some(last), Some(hours)) => now.saturating_sub(last) >= hours.saturating_mul(3600),
+        _ => true,
+    }
+}
+
+/// Record the formulae and casks declared in `spec` as managed by dotstrap,
+/// when already managed or absent from `preinstalled`, the packages installed
+/// before the run: dotstrap must not uninstall software it did not install.
+pub fn record_managed(state: &mut BrewState, spec: &BrewSpec, preinstalled: &BrewState) {
+    state.formulae = spec
+        .formulae
+        .iter()
+        .map(Formula::installed_name)
+        .filter(|name| {
+            // `brew list` names formulae from taps without their tap.
+            state.formulae.contains(name) || !preinstalled.formulae.contains(&short_name(name))
+        })
+        .collect();
+    state.casks = spec
+        .casks
+        .iter()
+        .filter(|cask| state.casks.contains(*cask) || !preinstalled.casks.contains(*cask))
+        .cloned()
+        .collect();
+}
+
+impl PackageProvider for BrewProvider {
+    fn name(&self) -> &str {
+        "brew"
+    }
+
+    fn supports(&self, os: &str) -> bool {
+        os == "macos" || os == "linux"
+    }
+
+    fn is_required(&self, os: &str) -> bool {
+        os == "macos"
+    }
+
+    fn is_available(&self, executor: &dyn CommandExecutor) -> bool {
+        executor.is_available(&self.program)
+    }
+
+    fn plan(&self, spec: &PackagesSpec) -> Vec<PlannedCommand> {
+        self.plan_spec(&spec.brew)
+    }
+
+    fn reason(&self, command: &PlannedCommand) -> String {
+        let reason = match command.args.first().map(String::as_str) {
+            Some("update") => "the formulae are refreshed before installing",
+            Some("tap") => "tap declared in the brew section",
+            Some("pin") => "version pinned in the brew section",
+            _ if command.program != self.program => {
+                "Mac App Store app declared in the brew section"
+            }
+            _ if command.args == ["install", "mas"] => "needed to install the Mac App Store apps",
+            _ => "declared in the brew section",
+        };
+        reason.to_string()
+    }
+
+    /// `brew update` and the taps run first, then the pinned versions are
+    /// checked to resolve before the rest of the plan runs in order.
+    fn install(&self, plan: &[PlannedCommand], executor: &dyn CommandExecutor) -> Result<()> {
+        let (prefix, rest) = plan.split_at(self.first_install(plan));
+        run_in_order(prefix, executor)?;
+        self.check_pins(plan, executor)?;
+        run_in_order(rest, executor)
+    }
+
+    /// `brew update` and the taps run first, in order, since formulae may
+    /// come from the taps. Formulae and casks are then installed concurrently,
+    /// without auto-updating Homebrew, in rounds of installs sharing no missing
+    /// dependency, and pins and Mac App Store apps last.
+    fn install_concurrently(
+        &self,
+        plan: &[PlannedCommand],
+        executor: &dyn CommandExecutor,
+        jobs: usize,
+    ) -> Result<()> {
+        let concurrent = |command: &PlannedCommand| {
+            command.program == self.program
+                && command.args.first().is_some_and(|arg| arg == "install")
+                && command.args.get(1).is_some_and(|arg| arg != "mas")
+        };
+        let first = plan.iter().position(concurrent).unwrap_or(plan.len());
+        let (prefix, rest) = plan.split_at(first);
+        let (installs, rest): (Vec<_>, Vec<_>) = rest.iter().cloned().partition(concurrent);
+        run_in_order(prefix, executor)?;
+        self.check_pins(plan, executor)?;
+        let installs: Vec<PlannedCommand> = installs
+            .into_iter()
+            .map(|mut command| {
+                command
+                    .env
+                    .push(("HOMEBREW_NO_AUTO_UPDATE".into(), "1".into()));
+                command
+            })
+            .collect();
+        let dependencies = self.missing_dependencies(&installs, executor);
+        for round in install_rounds(&installs, dependencies.as_ref()) {
+            PlannedCommand::run_concurrently(&round, executor, jobs)?;
+        }
+        run_in_order(&rest, executor)
+    }
+
+    fn bootstrap(&self) -> Vec<PlannedCommand> {
+        let script =
+            format!("NONINTERACTIVE=1 /bin/bash -c \"$(curl -fsSL {INSTALL_SCRIPT_URL})\"");
+        vec![PlannedCommand::new("/bin/bash", &["-c", &script])]
+    }
+
+    /// The installer puts `brew` in a prefix, such as `/opt/homebrew` on Apple
+    /// Silicon, which is not on `PATH` until the shell profile is reloaded, so
+    /// it is invoked by absolute path.
+    fn bootstrapped(&self) -> Option<Box<dyn PackageProvider>> {
+        let prefix = find_prefix(None, &self.prefixes)?;
+        let program = prefix.join("bin/brew");
+        Some(Box::new(BrewProvider {
+            program: program.to_string_lossy().into_owned(),
+            prefixes: self.prefixes.clone(),
+        }))
+    }
+
+    fn unavailable_error(&self) -> DotstrapError {
+        DotstrapError::BrewUnavailable
+    }
+}
+
+/// Run `commands` one after another, stopping at the first failure.
+fn run_in_order(commands: &[PlannedCommand], executor: &dyn CommandExecutor) -> Result<()> {
+    commands
+        .iter()
+        .try_for_each(|command| command.run(executor))
+}
+
+/// Split `installs` into rounds whose installs share no package, neither
+/// themselves nor a missing dependency, as Homebrew locks each package a
+/// running install touches. Without `dependencies`, each install is a round.
+fn install_rounds(
+    installs: &[PlannedCommand],
+    dependencies: Option<&HashMap<String, BTreeSet<String>>>,
+) -> Vec<Vec<PlannedCommand>> {
+    let Some(dependencies) = dependencies else {
+        return installs
+            .iter()
+            .map(|command| vec![command.clone()])
+            .collect();
+    };
+    let mut rounds: Vec<(BTreeSet<String>, Vec<PlannedCommand>)> = Vec::new();
+    for command in installs {
+        let name = short_name(command.args.last().map_or("", String::as_str));
+        let mut touched = dependencies.get(&name).cloned().unwrap_or_default();
+        touched.insert(name);
+        match rounds
+            .iter_mut()
+            .find(|(locked, _)| locked.is_disjoint(&touched))
+        {
+            Some((locked, round)) => {
+                locked.extend(touched);
+                round.push(command.clone());
+            }
+            None => rounds.push((touched, vec![command.clone()])),
+        }
+    }
+    rounds.into_iter().map(|(_, round)| round).collect()
+}
+
+/// Name of a formula or cask without its tap.
+fn short_name(name: &str) -> String {
+    name.rsplit('/').next().unwrap_or(name).to_string()
+}
 
 /// Prepare and optionally execute the Homebrew commands required by the spec.
 pub fn install_brew(
@@ -10,40 +390,52 @@ pub fn install_brew(
     executor: &dyn CommandExecutor,
     dry_run: bool,
 ) -> Result<Vec<String>> {
-    let mut executed = Vec::new();
-    if spec.taps.is_empty() && spec.formulae.is_empty() && spec.casks.is_empty() {
-        return Ok(executed);
+    let provider = BrewProvider::default();
+    let plan = provider.plan_spec(spec);
+    if plan.is_empty() {
+        return Ok(Vec::new());
     }
     ensure_available(executor)?;
-    maybe_run(executor, dry_run, &mut executed, "brew", &["update"])?;
-    for tap in &spec.taps {
-        maybe_run(
-            executor,
-            dry_run,
-            &mut executed,
-            "brew",
-            &["tap", tap, "--force"],
-        )?;
-    }
-    for formula in &spec.formulae {
-        maybe_run(
-            executor,
-            dry_run,
-            &mut executed,
-            "brew",
-            &["install", formula],
-        )?;
-    }
-    for cask in &spec.casks {
-        maybe_run(
-            executor,
-            dry_run,
-            &mut executed,
-            "brew",
-            &["install", "--cask", cask],
-        )?;
-    }
-    Ok(executed)
+    if !dry_run {
+        provider.install(&plan, executor)?;
+    }
+    Ok(plan.iter().map(PlannedCommand::to_string).collect())
+}
+
+/// Locate the Homebrew prefix, preferring the `brew` found on `PATH` over the
+/// well-known install locations.
+pub fn locate_prefix() -> Option<PathBuf> {
+    find_prefix(std::env::var_os("PATH").as_deref(), &candidate_prefixes())
+}
+
+/// Prefix owning the `brew` found on `path_var`, or else the first candidate
+/// prefix containing `bin/brew`.
+pub fn find_prefix(path_var: Option<&OsStr>, candidates: &[PathBuf]) -> Option<PathBuf> {
+    if let Some(bin) = find_on_path(path_var) {
+        return bin.parent().and_then(Path::parent).map(Path::to_path_buf);
+    }
+    candidates
+        .iter()
+        .find(|prefix| prefix.join("bin/brew").is_file())
+        .cloned()
+}
+
+fn find_on_path(path_var: Option<&OsStr>) -> Option<PathBuf> {
+    std::env::split_paths(path_var?)
+        .map(|dir| dir.join("brew"))
+        .find(|candidate| candidate.is_file())
+}
+
+fn candidate_prefixes() -> Vec<PathBuf> {
+    let mut prefixes = vec![
+        PathBuf::from("/opt/homebrew"),
+        PathBuf::from("/usr/local"),
+        PathBuf::from("/home/linuxbrew/.linuxbrew"),
+    ];
+    if let Some(home) = home::home_dir() {
+        prefixes.push(home.join(".linuxbrew"));
+    }
+    prefixes
 }
 
 fn ensure_available(executor: &dyn CommandExecutor) -> Result<()> {
@@ -52,25 +444,10 @@ fn ensure_available(executor: &dyn CommandExecutor) -> Result<()> {
         .map_err(|_| DotstrapError::BrewUnavailable)
 }
 
-fn maybe_run(
-    executor: &dyn CommandExecutor,
-    dry_run: bool,
-    log: &mut Vec<String>,
-    program: &str,
-    args: &[&str],
-) -> Result<()> {
-    let command_string = format!("{program} {}", args.join(" "));
-    log.push(command_string);
-    if dry_run {
-        return Ok(());
-    }
-    executor.run(program, args)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::BrewSpec;
+    use crate::config::{BrewSpec, Formula, MasApp};
     use crate::errors::DotstrapError;
     use crate::infrastructure::command::RecordingCommandExecutor;
 
@@ -89,6 +466,148 @@ mod tests {
         );
     }
 
+    #[test]
+    fn install_concurrently_runs_taps_first_and_pins_last() {
+        let executor = RecordingCommandExecutor::default();
+        let provider = BrewProvider::default();
+        let spec = BrewSpec {
+            taps: vec!["hashicorp/tap".into()],
+            formulae: vec![
+                Formula {
+                    name: "python".into(),
+                    version: Some("3.12".into()),
+                },
+                "fzf".into(),
+            ],
+            casks: vec!["iterm2".into()],
+            skip_update: true,
+            ..BrewSpec::default()
+        };
+
+        provider
+            .install_concurrently(&provider.plan_spec(&spec), &executor, 4)
+            .unwrap();
+
+        let calls: Vec<String> = executor
+            .calls()
+            .into_iter()
+            .map(|(program, args)| format!("{program} {}", args.join(" ")))
+            .collect();
+        assert_eq!(
+            calls,
+            vec![
+                "brew tap hashicorp/tap --force",
+                "brew info --json=v2 python@3.12",
+                "brew deps --for-each --missing python@3.12 fzf",
+                "brew install python@3.12",
+                "brew install fzf",
+                "brew install --cask iterm2",
+                "brew pin python@3.12",
+            ]
+        );
+    }
+
+    /// Executor failing `brew info`, as Homebrew does for unknown formulae.
+    #[derive(Default)]
+    struct UnknownFormula {
+        inner: RecordingCommandExecutor,
+    }
+
+    impl CommandExecutor for UnknownFormula {
+        fn run(&self, program: &str, args: &[&str]) -> Result<()> {
+            self.inner.run(program, args)?;
+            if args.first() == Some(&"info") {
+                return Err(DotstrapError::CommandFailed {
+                    program: program.into(),
+                    status: 1,
+                });
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn install_rejects_pins_homebrew_cannot_resolve() {
+        let provider = BrewProvider::default();
+        let spec = BrewSpec {
+            taps: vec!["hashicorp/tap".into()],
+            formulae: vec![
+                "fzf".into(),
+                Formula {
+                    name: "terraform".into(),
+                    version: Some("1.5.7".into()),
+                },
+            ],
+            skip_update: true,
+            ..BrewSpec::default()
+        };
+        let plan = provider.plan_spec(&spec);
+
+        for jobs in [1, 4] {
+            let executor = UnknownFormula::default();
+            let error = if jobs > 1 {
+                provider.install_concurrently(&plan, &executor, jobs)
+            } else {
+                provider.install(&plan, &executor)
+            }
+            .expect_err("an unresolvable pin must be rejected");
+
+            assert!(matches!(
+                &error,
+                DotstrapError::InvalidPin { name, .. } if name == "terraform"
+            ));
+            let calls: Vec<String> = executor
+                .inner
+                .calls()
+                .into_iter()
+                .map(|(program, args)| format!("{program} {}", args.join(" ")))
+                .collect();
+            assert_eq!(
+                calls,
+                vec![
+                    "brew tap hashicorp/tap --force",
+                    "brew info --json=v2 terraform@1.5.7",
+                ],
+                "nothing is installed once a pin fails to resolve"
+            );
+        }
+    }
+
+    #[test]
+    fn install_rounds_keep_shared_dependencies_apart() {
+        let provider = BrewProvider::default();
+        let spec = BrewSpec {
+            formulae: vec!["git".into(), "ripgrep".into(), "fzf".into()],
+            casks: vec!["iterm2".into()],
+            skip_update: true,
+            ..BrewSpec::default()
+        };
+        let installs = provider.plan_spec(&spec);
+        let executor = RecordingCommandExecutor::default().with_output(
+            "brew deps --for-each --missing git ripgrep fzf",
+            "git: gettext pcre2\nripgrep: pcre2\nfzf:\n",
+        );
+
+        let dependencies = provider.missing_dependencies(&installs, &executor);
+        let rounds: Vec<Vec<String>> = install_rounds(&installs, dependencies.as_ref())
+            .iter()
+            .map(|round| round.iter().map(PlannedCommand::to_string).collect())
+            .collect();
+
+        assert_eq!(
+            rounds,
+            vec![
+                vec![
+                    "brew install git",
+                    "brew install fzf",
+                    "brew install --cask iterm2"
+                ],
+                vec!["brew install ripgrep"],
+            ]
+        );
+        assert_eq!(install_rounds(&installs, None).len(), 4, "one at a time");
+    }
+
     #[test]
     fn install_brew_executes_commands_in_order() {
         let executor = RecordingCommandExecutor::default();
@@ -96,6 +615,7 @@ mod tests {
             taps: vec!["homebrew/cask".into()],
             formulae: vec!["fzf".into()],
             casks: vec!["iterm2".into()],
+            ..BrewSpec::default()
         };
 
         let executed =
@@ -156,13 +676,41 @@ mod tests {
         );
     }
 
+    #[test]
+    fn brew_commands_run_with_the_configured_environment() {
+        let executor = RecordingCommandExecutor::default();
+        let provider = BrewProvider::default();
+        let spec = BrewSpec {
+            formulae: vec!["fzf".into(), "ripgrep".into()],
+            skip_update: true,
+            env: [("HOMEBREW_NO_ANALYTICS".to_string(), "1".to_string())].into(),
+            ..BrewSpec::default()
+        };
+
+        let plan = provider.plan_spec(&spec);
+        provider.install_concurrently(&plan, &executor, 2).unwrap();
+
+        assert_eq!(
+            plan[0].to_string(),
+            "HOMEBREW_NO_ANALYTICS=1 brew install fzf"
+        );
+        let invocations = executor.invocations();
+        assert_eq!(invocations.len(), 2);
+        assert!(invocations.iter().all(|invocation| {
+            invocation.env
+                == [
+                    ("HOMEBREW_NO_ANALYTICS".to_string(), "1".to_string()),
+                    ("HOMEBREW_NO_AUTO_UPDATE".to_string(), "1".to_string()),
+                ]
+        }));
+    }
+
     #[test]
     fn install_brew_returns_brew_unavailable_when_version_check_fails() {
         let executor = RecordingCommandExecutor::with_failure("brew");
         let spec = BrewSpec {
             taps: vec!["tap/failed".into()],
-            formulae: vec![],
-            casks: vec![],
+            ..BrewSpec::default()
         };
 
         let error =
@@ -183,4 +731,179 @@ mod tests {
             ("brew".to_string(), vec!["--version".to_string()])
         );
     }
+
+    #[test]
+    fn plan_spec_installs_mas_apps_after_casks() {
+        let spec = BrewSpec {
+            casks: vec!["iterm2".into()],
+            mas: vec![MasApp {
+                name: "Xcode".into(),
+                id: 497799835,
+            }],
+            ..BrewSpec::default()
+        };
+
+        let plan: Vec<String> = BrewProvider::default()
+            .plan_spec(&spec)
+            .iter()
+            .map(PlannedCommand::to_string)
+            .collect();
+
+        assert_eq!(
+            plan,
+            vec![
+                "brew update",
+                "brew install --cask iterm2",
+                "brew install mas",
+                "mas install 497799835",
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_removals_uninstalls_undeclared_packages() {
+        let mut previous = BrewState::default();
+        previous.formulae.insert("fzf".into());
+        previous.formulae.insert("ripgrep".into());
+        previous.casks.insert("iterm2".into());
+        let spec = BrewSpec {
+            formulae: vec!["ripgrep".into()],
+            ..BrewSpec::default()
+        };
+
+        let plan: Vec<String> = BrewProvider::default()
+            .plan_removals(&previous, &spec)
+            .iter()
+            .map(PlannedCommand::to_string)
+            .collect();
+
+        assert_eq!(
+            plan,
+            vec!["brew uninstall fzf", "brew uninstall --cask iterm2"]
+        );
+    }
+
+    #[test]
+    fn record_managed_skips_packages_installed_before() {
+        let mut state = BrewState::default();
+        state.formulae.insert("fzf".into());
+        state.formulae.insert("htop".into());
+        let mut preinstalled = BrewState::default();
+        preinstalled.formulae.insert("fzf".into());
+        preinstalled.formulae.insert("jq".into());
+        preinstalled.formulae.insert("lazygit".into());
+        preinstalled.casks.insert("firefox".into());
+        let spec = BrewSpec {
+            formulae: vec![
+                "fzf".into(),
+                "jq".into(),
+                "ripgrep".into(),
+                "jesseduffield/lazygit/lazygit".into(),
+            ],
+            casks: vec!["firefox".into(), "iterm2".into()],
+            ..BrewSpec::default()
+        };
+
+        record_managed(&mut state, &spec, &preinstalled);
+
+        assert_eq!(
+            state.formulae.iter().collect::<Vec<_>>(),
+            vec!["fzf", "ripgrep"],
+            "fzf stays managed, jq and lazygit were not installed by dotstrap"
+        );
+        assert_eq!(state.casks.iter().collect::<Vec<_>>(), vec!["iterm2"]);
+    }
+
+    #[test]
+    fn installed_lists_formulae_and_casks() {
+        let executor = RecordingCommandExecutor::default()
+            .with_output("brew list --formula -1", "git\nnode@18\n")
+            .with_output("brew list --cask -1", "firefox\n");
+
+        let installed = BrewProvider::default().installed(&executor).unwrap();
+
+        assert_eq!(
+            installed.formulae.iter().collect::<Vec<_>>(),
+            vec!["git", "node@18"]
+        );
+        assert_eq!(installed.casks.iter().collect::<Vec<_>>(), vec!["firefox"]);
+        let missing = RecordingCommandExecutor::with_failure("brew");
+        assert_eq!(
+            BrewProvider::default().installed(&missing).unwrap(),
+            BrewState::default()
+        );
+    }
+
+    #[test]
+    fn plan_spec_installs_and_pins_versioned_formulae() {
+        let spec = BrewSpec {
+            formulae: vec![Formula {
+                name: "python".into(),
+                version: Some("3.12".into()),
+            }],
+            ..BrewSpec::default()
+        };
+
+        let plan: Vec<String> = BrewProvider::default()
+            .plan_spec(&spec)
+            .iter()
+            .map(PlannedCommand::to_string)
+            .collect();
+
+        assert_eq!(
+            plan,
+            vec![
+                "brew update",
+                "brew install python@3.12",
+                "brew pin python@3.12",
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_spec_omits_update_when_skipped() {
+        let spec = BrewSpec {
+            formulae: vec!["fzf".into()],
+            skip_update: true,
+            ..BrewSpec::default()
+        };
+
+        let plan = BrewProvider::default().plan_spec(&spec);
+
+        assert_eq!(plan, vec![PlannedCommand::new("brew", &["install", "fzf"])]);
+    }
+
+    #[test]
+    fn update_due_respects_interval() {
+        assert!(update_due(None, Some(24), 1_000));
+        assert!(update_due(Some(0), None, 1_000));
+        assert!(!update_due(Some(0), Some(1), 3_599));
+        assert!(update_due(Some(0), Some(1), 3_600));
+        assert!(!update_due(Some(0), Some(u64::MAX), 1_000));
+    }
+
+    #[test]
+    fn find_prefix_prefers_path_then_candidates() {
+        let path_root = tempfile::TempDir::new().unwrap();
+        let path_bin = path_root.path().join("bin");
+        std::fs::create_dir_all(&path_bin).unwrap();
+        std::fs::write(path_bin.join("brew"), "").unwrap();
+        let linuxbrew = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(linuxbrew.path().join("bin")).unwrap();
+        std::fs::write(linuxbrew.path().join("bin/brew"), "").unwrap();
+        let candidates = vec![
+            PathBuf::from("/nonexistent"),
+            linuxbrew.path().to_path_buf(),
+        ];
+
+        assert_eq!(
+            find_prefix(Some(path_bin.as_os_str()), &candidates),
+            Some(path_root.path().to_path_buf())
+        );
+        assert_eq!(
+            find_prefix(None, &candidates),
+            Some(linuxbrew.path().to_path_buf())
+        );
+        assert_eq!(find_prefix(None, &candidates[..1]), None);
+    }
 }
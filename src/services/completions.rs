@@ -0,0 +1,155 @@
+//! Service installing the shell completion scripts of dotstrap where the
+//! shells load them from.
+
+use std::path::{Path, PathBuf};
+
+use clap_complete::Shell;
+
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::paths::KnownDirs;
+use crate::services::cron;
+
+/// Where the completions of a shell were installed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CompletionsInstall {
+    pub script: PathBuf,
+    /// Startup file a managed block loading the completions was written to.
+    pub rc_file: Option<PathBuf>,
+    /// Lines loading the completions, to add by hand to a startup file that
+    /// is a link, such as one dotstrap manages, with the file's path.
+    pub manual: Option<(PathBuf, Vec<String>)>,
+}
+
+/// Write the completion `script` of `shell` into `home`, where the shell
+/// loads completions from, adding a managed block to its startup file when
+/// the shell does not look there by itself.
+///
+/// Bash completions are loaded on demand by bash-completion and fish ones by
+/// fish, while zsh needs `~/.zsh/completions` on its `fpath`.
+pub fn install_completions(shell: Shell, home: &Path, script: &[u8]) -> Result<CompletionsInstall> {
+    let dirs = KnownDirs::for_home(home);
+    let xdg = |name: &str| {
+        dirs.get(name)
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| home.to_path_buf())
+    };
+    let (script_path, rc) = match shell {
+        Shell::Bash => (
+            xdg("xdg_data").join("bash-completion/completions/dotstrap"),
+            None,
+        ),
+        Shell::Fish => (
+            xdg("xdg_config").join("fish/completions/dotstrap.fish"),
+            None,
+        ),
+        Shell::Zsh => (
+            home.join(".zsh/completions/_dotstrap"),
+            Some((
+                home.join(".zshrc"),
+                vec![
+                    "fpath=(\"$HOME/.zsh/completions\" $fpath)".to_string(),
+                    "autoload -Uz compinit && compinit".to_string(),
+                ],
+            )),
+        ),
+        other => return Err(DotstrapError::UnsupportedShell(other.to_string())),
+    };
+    if let Some(parent) = script_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&script_path, script)?;
+    let mut install = CompletionsInstall {
+        script: script_path,
+        ..CompletionsInstall::default()
+    };
+    let Some((rc_file, lines)) = rc else {
+        return Ok(install);
+    };
+    // Writing through a link would change its target, a staged template
+    // when dotstrap manages the file.
+    if std::fs::symlink_metadata(&rc_file).is_ok_and(|meta| meta.file_type().is_symlink()) {
+        install.manual = Some((rc_file, lines));
+        return Ok(install);
+    }
+    let existing = match std::fs::read_to_string(&rc_file) {
+        Ok(existing) => existing,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let updated = cron::merge_managed_block(&existing, &lines);
+    if updated != existing {
+        std::fs::write(&rc_file, updated)?;
+        install.rc_file = Some(rc_file);
+    }
+    Ok(install)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn install_completions_adds_zsh_directory_to_fpath_once() {
+        let home = tempfile::tempdir().unwrap();
+        std::fs::write(home.path().join(".zshrc"), "export EDITOR=vim\n").unwrap();
+
+        let install = install_completions(Shell::Zsh, home.path(), b"#compdef dotstrap\n").unwrap();
+
+        assert_eq!(
+            install.script,
+            home.path().join(".zsh/completions/_dotstrap")
+        );
+        assert_eq!(
+            std::fs::read_to_string(&install.script).unwrap(),
+            "#compdef dotstrap\n"
+        );
+        assert_eq!(install.rc_file, Some(home.path().join(".zshrc")));
+        let zshrc = std::fs::read_to_string(home.path().join(".zshrc")).unwrap();
+        assert!(zshrc.starts_with("export EDITOR=vim\n# BEGIN dotstrap managed block\n"));
+        assert!(zshrc.contains("fpath=(\"$HOME/.zsh/completions\" $fpath)\n"));
+
+        let again = install_completions(Shell::Zsh, home.path(), b"#compdef dotstrap\n").unwrap();
+        assert_eq!(again.rc_file, None, "the block is already there");
+        assert_eq!(
+            std::fs::read_to_string(home.path().join(".zshrc")).unwrap(),
+            zshrc
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn install_completions_leaves_linked_startup_files_alone() {
+        let home = tempfile::tempdir().unwrap();
+        let staged = home.path().join("zshrc");
+        std::fs::write(&staged, "").unwrap();
+        std::os::unix::fs::symlink(&staged, home.path().join(".zshrc")).unwrap();
+
+        let install = install_completions(Shell::Zsh, home.path(), b"").unwrap();
+
+        assert_eq!(install.rc_file, None);
+        let (rc_file, lines) = install.manual.expect("lines to add by hand");
+        assert_eq!(rc_file, home.path().join(".zshrc"));
+        assert_eq!(lines.len(), 2);
+        assert_eq!(std::fs::read_to_string(&staged).unwrap(), "");
+    }
+
+    #[test]
+    fn install_completions_needs_no_startup_file_for_bash_and_fish() {
+        let home = tempfile::tempdir().unwrap();
+
+        let bash = install_completions(Shell::Bash, home.path(), b"complete").unwrap();
+        let fish = install_completions(Shell::Fish, home.path(), b"complete").unwrap();
+
+        assert!(
+            bash.script
+                .ends_with("bash-completion/completions/dotstrap")
+        );
+        assert!(fish.script.ends_with("fish/completions/dotstrap.fish"));
+        assert!(bash.script.is_file() && fish.script.is_file());
+        assert_eq!((bash.rc_file, fish.rc_file), (None, None));
+        assert!(matches!(
+            install_completions(Shell::Elvish, home.path(), b""),
+            Err(DotstrapError::UnsupportedShell(_))
+        ));
+    }
+}
@@ -0,0 +1,264 @@
+//! Service installing declared cron jobs into a managed block of the user
+//! crontab.
+
+use std::path::Path;
+
+use serde_json::Value;
+use tempfile::NamedTempFile;
+
+use crate::config::CronEntry;
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::{CommandExecutor, Invocation};
+use crate::services::templating;
+
+const BEGIN_MARKER: &str = "# BEGIN dotstrap managed block";
+const END_MARKER: &str = "# END dotstrap managed block";
+
+/// Render the cron entries and install them in the user crontab.
+///
+/// Only the lines between the dotstrap markers are replaced, and the crontab
+/// is left untouched when the managed block is already up to date. Without
+/// entries, a managed block left by a previous run is removed. Returns the
+/// rendered crontab lines.
+pub fn install_cron(
+    entries: &[CronEntry],
+    context: &Value,
+    origin: &Path,
+    executor: &dyn CommandExecutor,
+    dry_run: bool,
+) -> Result<Vec<String>> {
+    let lines = entries
+        .iter()
+        .map(|entry| {
+            let command = templating::render_inline(&entry.command, context, origin)?;
+            Ok(format!("{} {}", entry.schedule.trim(), command.trim()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let existing = match current_crontab(executor) {
+        Ok(existing) => existing,
+        // Without entries, a host lacking crontab has no block to remove.
+        Err(_) if lines.is_empty() => return Ok(lines),
+        Err(err) => return Err(err),
+    };
+    if lines.is_empty() && !existing.lines().any(|line| line.trim() == BEGIN_MARKER) {
+        return Ok(lines);
+    }
+    let updated = merge_managed_block(&existing, &lines);
+    if dry_run || updated == existing {
+        return Ok(lines);
+    }
+    let mut file = NamedTempFile::new()?;
+    std::io::Write::write_all(&mut file, updated.as_bytes())?;
+    executor.run("crontab", &[&file.path().to_string_lossy()])?;
+    Ok(lines)
+}
+
+/// Crontab of the user, empty when they have none yet.
+fn current_crontab(executor: &dyn CommandExecutor) -> Result<String> {
+    let listed = executor.run_capture(&Invocation::new("crontab", &["-l"]))?;
+    if listed.success() {
+        return Ok(listed.stdout);
+    }
+    // Any other failure must not be taken for an empty crontab, which would
+    // then replace the user's.
+    if listed.stderr.contains("no crontab for") {
+        return Ok(String::new());
+    }
+    Err(DotstrapError::CommandFailed {
+        program: "crontab".into(),
+        status: listed.status,
+    })
+}
+
+/// Replace (or append) the managed block of `existing`, a crontab or a shell
+/// startup file, with `lines`.
+pub fn merge_managed_block(existing: &str, lines: &[String]) -> String {
+    let mut merged = Vec::new();
+    let mut inside = false;
+    let mut replaced = false;
+    for line in existing.lines() {
+        if line.trim() == BEGIN_MARKER {
+            inside = true;
+            continue;
+        }
+        if line.trim() == END_MARKER && inside {
+            inside = false;
+            merged.extend(managed_block(lines));
+            replaced = true;
+            continue;
+        }
+        if !inside {
+            merged.push(line.to_string());
+        }
+    }
+    if !replaced {
+        merged.extend(managed_block(lines));
+    }
+    let mut crontab = merged.join("\n");
+    crontab.push('\n');
+    crontab
+}
+
+fn managed_block(lines: &[String]) -> Vec<String> {
+    if lines.is_empty() {
+        return Vec::new();
+    }
+    let mut block = vec![BEGIN_MARKER.to_string()];
+    block.extend(lines.iter().cloned());
+    block.push(END_MARKER.to_string());
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use serde_json::json;
+
+    fn entry(command: &str) -> CronEntry {
+        CronEntry {
+            schedule: "0 3 * * *".into(),
+            command: command.into(),
+        }
+    }
+
+    #[test]
+    fn merge_managed_block_appends_block_and_keeps_user_lines() {
+        let merged =
+            merge_managed_block("MAILTO=me\n@reboot echo hi\n", &["* * * * * date".into()]);
+
+        assert_eq!(
+            merged,
+            "MAILTO=me\n@reboot echo hi\n# BEGIN dotstrap managed block\n* * * * * date\n# END dotstrap managed block\n"
+        );
+    }
+
+    #[test]
+    fn merge_managed_block_replaces_existing_block() {
+        let existing = "A\n# BEGIN dotstrap managed block\nold\n# END dotstrap managed block\nB\n";
+
+        let merged = merge_managed_block(existing, &["new".into()]);
+
+        assert_eq!(
+            merged,
+            "A\n# BEGIN dotstrap managed block\nnew\n# END dotstrap managed block\nB\n"
+        );
+    }
+
+    #[test]
+    fn install_cron_renders_and_installs_entries() {
+        let executor = RecordingCommandExecutor::default();
+        let context = json!({ "backup": "~/bin/backup.sh" });
+
+        let lines = install_cron(
+            &[entry("{{backup}} >> /tmp/backup.log")],
+            &context,
+            Path::new("manifest.yaml"),
+            &executor,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(lines, vec!["0 3 * * * ~/bin/backup.sh >> /tmp/backup.log"]);
+        let calls = executor.calls();
+        assert_eq!(calls.len(), 2, "crontab -l then crontab <file>");
+        assert_eq!(calls[1].0, "crontab");
+    }
+
+    #[test]
+    fn install_cron_treats_missing_crontab_as_empty() {
+        let executor = RecordingCommandExecutor::with_failure("crontab")
+            .with_stderr("crontab -l", "no crontab for me\n");
+
+        let err = install_cron(
+            &[entry("date")],
+            &json!({}),
+            Path::new("manifest.yaml"),
+            &executor,
+            false,
+        )
+        .expect_err("the recording executor fails installing the crontab too");
+
+        assert!(matches!(err, DotstrapError::CommandFailed { .. }));
+        assert_eq!(executor.calls().len(), 2, "crontab -l then crontab <file>");
+    }
+
+    #[test]
+    fn install_cron_keeps_crontab_it_cannot_read() {
+        let executor = RecordingCommandExecutor::with_failure("crontab")
+            .with_stderr("crontab -l", "crontab: permission denied\n");
+
+        let err = install_cron(
+            &[entry("date")],
+            &json!({}),
+            Path::new("manifest.yaml"),
+            &executor,
+            false,
+        )
+        .expect_err("an unreadable crontab must not be replaced");
+
+        assert!(matches!(err, DotstrapError::CommandFailed { .. }));
+        assert_eq!(executor.calls().len(), 1, "only the crontab is read");
+    }
+
+    #[test]
+    fn install_cron_removes_managed_block_without_entries() {
+        let executor = RecordingCommandExecutor::default().with_output(
+            "crontab -l",
+            "MAILTO=me\n# BEGIN dotstrap managed block\n0 3 * * * date\n# END dotstrap managed block\n",
+        );
+
+        let lines = install_cron(
+            &[],
+            &json!({}),
+            Path::new("manifest.yaml"),
+            &executor,
+            false,
+        )
+        .unwrap();
+
+        assert!(lines.is_empty());
+        let calls = executor.calls();
+        assert_eq!(calls.len(), 2, "the block is removed");
+        assert_eq!(calls[1].0, "crontab");
+
+        let untouched =
+            RecordingCommandExecutor::default().with_output("crontab -l", "MAILTO=me\n");
+        install_cron(
+            &[],
+            &json!({}),
+            Path::new("manifest.yaml"),
+            &untouched,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            untouched.calls().len(),
+            1,
+            "a crontab without block is left alone"
+        );
+    }
+
+    #[test]
+    fn install_cron_leaves_up_to_date_crontab_untouched() {
+        let executor = RecordingCommandExecutor::default().with_output(
+            "crontab -l",
+            "# BEGIN dotstrap managed block\n0 3 * * * date\n# END dotstrap managed block\n",
+        );
+
+        install_cron(
+            &[entry("date")],
+            &json!({}),
+            Path::new("manifest.yaml"),
+            &executor,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            executor.calls().len(),
+            1,
+            "only the current crontab is read"
+        );
+    }
+}
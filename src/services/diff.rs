@@ -0,0 +1,119 @@
+//! Line diffs shown before dotstrap replaces a file.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Unchanged lines shown around each change.
+const CONTEXT: usize = 2;
+
+/// Index pairs of a longest common subsequence of `a` and `b`, in order.
+pub fn common_lines(a: &[&str], b: &[&str]) -> Vec<(usize, usize)> {
+    let mut lengths = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i] == b[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut pairs = Vec::new();
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+/// Lines removed from `old` (`-`) and added in `new` (`+`), with a few
+/// unchanged lines (` `) around each change and `...` between distant ones.
+pub fn line_diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.split_inclusive('\n').collect();
+    let new: Vec<&str> = new.split_inclusive('\n').collect();
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (next_i, next_j) in common_lines(&old, &new)
+        .into_iter()
+        .chain([(old.len(), new.len())])
+    {
+        lines.extend(old[i..next_i].iter().map(|line| ('-', *line)));
+        lines.extend(new[j..next_j].iter().map(|line| ('+', *line)));
+        if let Some(line) = old.get(next_i) {
+            lines.push((' ', *line));
+        }
+        (i, j) = (next_i + 1, next_j + 1);
+    }
+
+    let mut shown = vec![false; lines.len()];
+    for (index, _) in lines
+        .iter()
+        .enumerate()
+        .filter(|(_, (sign, _))| *sign != ' ')
+    {
+        let end = (index + CONTEXT + 1).min(lines.len());
+        shown[index.saturating_sub(CONTEXT)..end].fill(true);
+    }
+    let mut diff = String::new();
+    let mut previous = None;
+    for (index, (sign, line)) in lines.iter().enumerate().filter(|(index, _)| shown[*index]) {
+        if previous.is_some_and(|previous| previous + 1 < index) {
+            diff.push_str("...\n");
+        }
+        diff.push(*sign);
+        diff.push_str(line.trim_end_matches(['\r', '\n']));
+        diff.push('\n');
+        previous = Some(index);
+    }
+    diff
+}
+
+/// [`line_diff`] of the files `old` and `new`, or a note when either is not
+/// text. A missing `old` is diffed as empty.
+pub fn file_diff(old: &Path, new: &Path) -> io::Result<String> {
+    let old = match fs::read(old) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+        old => old?,
+    };
+    match (String::from_utf8(old), String::from_utf8(fs::read(new)?)) {
+        (Ok(old), Ok(new)) => Ok(line_diff(&old, &new)),
+        _ => Ok("binary files differ\n".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_diff_shows_changes_with_context() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\n";
+
+        assert_eq!(line_diff(old, new), " a\n-b\n+B\n c\n d\n...\n g\n h\n+i\n");
+        assert_eq!(line_diff(old, old), "");
+        assert_eq!(line_diff("", "x"), "+x\n");
+    }
+
+    #[test]
+    fn file_diff_treats_a_missing_file_as_empty() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let new = dir.path().join("new");
+        fs::write(&new, "x\n").unwrap();
+
+        assert_eq!(
+            file_diff(&dir.path().join("missing"), &new).unwrap(),
+            "+x\n"
+        );
+        assert!(file_diff(&new, &dir.path().join("missing")).is_err());
+    }
+}
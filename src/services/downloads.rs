@@ -0,0 +1,180 @@
+//! Service fetching declared files over HTTPS and linking them into the home.
+
+use std::fs;
+use std::path::{Component, Path};
+
+use tempfile::TempDir;
+
+use crate::config::DownloadSpec;
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::CommandExecutor;
+use crate::infrastructure::download;
+use crate::infrastructure::filesystem::OsFileSystem;
+use crate::services::linker;
+use crate::services::outcome::LinkOutcome;
+
+/// Fetch every declared download into `~/.dotstrap/downloads` and link it to
+/// its destination.
+///
+/// Files whose staged copy already matches the declared checksum are not
+/// downloaded again.
+pub fn fetch_downloads(
+    home: &Path,
+    downloads: &[DownloadSpec],
+    executor: &dyn CommandExecutor,
+    dry_run: bool,
+) -> Result<Vec<LinkOutcome>> {
+    let mut linked = Vec::new();
+    for spec in downloads {
+        if !spec.url.starts_with("https://") {
+            return Err(DotstrapError::InsecureDownload(spec.url.clone()));
+        }
+        validate_destination(&spec.destination)?;
+        let destination = home.join(&spec.destination);
+        let stage_path = home.join(".dotstrap/downloads").join(&spec.destination);
+        if dry_run {
+            linked.push(linker::planned_link(&stage_path, &destination));
+            continue;
+        }
+        if !is_current(&stage_path, spec)? {
+            let workdir = TempDir::new()?;
+            let fetched = workdir.path().join("download");
+            download::download(executor, &spec.url, &fetched)?;
+            download::verify_sha256(&fetched, &spec.sha256)?;
+            if let Some(parent) = stage_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&fetched, &stage_path)?;
+        }
+        linker::apply_mode(&OsFileSystem, &stage_path, spec.mode)?;
+        linked.push(linker::link_path(&stage_path, &destination)?);
+    }
+    Ok(linked)
+}
+
+/// Reject destinations that would escape the home and staging directories.
+fn validate_destination(destination: &Path) -> Result<()> {
+    let reason = if destination.is_absolute() || destination.has_root() {
+        "downloads must be placed relative to the home directory"
+    } else if destination
+        .components()
+        .any(|component| component == Component::ParentDir)
+    {
+        "downloads must not leave the home directory through `..`"
+    } else {
+        return Ok(());
+    };
+    Err(DotstrapError::InvalidDestination {
+        destination: destination.to_path_buf(),
+        reason: reason.into(),
+    })
+}
+
+fn is_current(stage_path: &Path, spec: &DownloadSpec) -> Result<bool> {
+    if !stage_path.exists() {
+        return Ok(false);
+    }
+    Ok(download::sha256_file(stage_path)?.eq_ignore_ascii_case(spec.sha256.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use crate::services::outcome::ItemStatus;
+    use std::cell::Cell;
+    use std::path::PathBuf;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    /// Executor writing `hello` to `curl --output <path>` and counting calls.
+    #[derive(Default)]
+    struct FakeCurl {
+        calls: Cell<usize>,
+    }
+
+    impl CommandExecutor for FakeCurl {
+        fn run(&self, _program: &str, args: &[&str]) -> Result<()> {
+            self.calls.set(self.calls.get() + 1);
+            let idx = args.iter().position(|arg| *arg == "--output").unwrap();
+            fs::write(args[idx + 1], "hello")?;
+            Ok(())
+        }
+    }
+
+    fn spec(url: &str) -> DownloadSpec {
+        DownloadSpec {
+            url: url.into(),
+            destination: PathBuf::from(".local/share/git-prompt.sh"),
+            sha256: HELLO_SHA256.into(),
+            mode: Some(0o644),
+        }
+    }
+
+    #[test]
+    fn fetch_downloads_rejects_plain_http() {
+        let home = TempDir::new().unwrap();
+        let executor = RecordingCommandExecutor::default();
+
+        let error = fetch_downloads(
+            home.path(),
+            &[spec("http://example.com/git-prompt.sh")],
+            &executor,
+            true,
+        )
+        .expect_err("plain http must be rejected");
+
+        assert!(matches!(error, DotstrapError::InsecureDownload(_)));
+        assert!(executor.calls().is_empty());
+    }
+
+    #[test]
+    fn fetch_downloads_rejects_absolute_destinations() {
+        let home = TempDir::new().unwrap();
+        let executor = RecordingCommandExecutor::default();
+        let mut download = spec("https://example.com/git-prompt.sh");
+        download.destination = PathBuf::from("/etc/profile.d/git-prompt.sh");
+
+        let error = fetch_downloads(home.path(), &[download], &executor, false)
+            .expect_err("absolute destinations must be rejected");
+
+        assert!(matches!(error, DotstrapError::InvalidDestination { .. }));
+        assert!(executor.calls().is_empty());
+    }
+
+    #[test]
+    fn fetch_downloads_rejects_destinations_leaving_the_home() {
+        let home = TempDir::new().unwrap();
+        let executor = RecordingCommandExecutor::default();
+        let mut download = spec("https://example.com/git-prompt.sh");
+        download.destination = PathBuf::from(".local/../../git-prompt.sh");
+
+        let error = fetch_downloads(home.path(), &[download], &executor, false)
+            .expect_err("destinations with `..` must be rejected");
+
+        assert!(matches!(error, DotstrapError::InvalidDestination { .. }));
+        assert!(executor.calls().is_empty());
+    }
+
+    #[test]
+    fn fetch_downloads_links_verified_file_and_skips_current_copies() {
+        let home = TempDir::new().unwrap();
+        let executor = FakeCurl::default();
+        let downloads = [spec("https://example.com/git-prompt.sh")];
+
+        let linked = fetch_downloads(home.path(), &downloads, &executor, false).unwrap();
+        let relinked = fetch_downloads(home.path(), &downloads, &executor, false).unwrap();
+
+        let destination = home.path().join(".local/share/git-prompt.sh");
+        assert_eq!(
+            linked,
+            vec![
+                LinkOutcome::new(destination.clone(), ItemStatus::Created)
+                    .because("destination missing")
+            ]
+        );
+        assert_eq!(relinked[0].status, ItemStatus::Unchanged);
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "hello");
+        assert_eq!(executor.calls.get(), 1, "second run reuses the staged copy");
+    }
+}
@@ -0,0 +1,132 @@
+//! Service applying GNOME desktop settings through `gsettings`.
+
+use serde_json::Value;
+
+use crate::config::GnomeSetting;
+use crate::errors::Result;
+use crate::infrastructure::command::CommandExecutor;
+
+/// Set every GNOME setting whose current value differs from the declared one.
+///
+/// Returns one line per changed setting describing the current and desired
+/// values in GVariant text format.
+pub fn apply_settings(
+    settings: &[GnomeSetting],
+    executor: &dyn CommandExecutor,
+    dry_run: bool,
+) -> Result<Vec<String>> {
+    let mut changes = Vec::new();
+    for setting in settings {
+        let desired = gvariant(&setting.value);
+        let current = executor
+            .output("gsettings", &["get", &setting.schema, &setting.key])
+            .ok()
+            .map(|output| untyped(output.trim()).to_string())
+            .filter(|output| !output.is_empty());
+        if current.as_deref() == Some(desired.as_str()) {
+            continue;
+        }
+        changes.push(format!(
+            "{} {}: {} -> {desired}",
+            setting.schema,
+            setting.key,
+            current.as_deref().unwrap_or("(unset)")
+        ));
+        if !dry_run {
+            executor.run(
+                "gsettings",
+                &["set", &setting.schema, &setting.key, &desired],
+            )?;
+        }
+    }
+    Ok(changes)
+}
+
+/// Render a YAML value using the GVariant text format understood by gsettings.
+pub fn gvariant(value: &Value) -> String {
+    match value {
+        Value::String(text) => format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'")),
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(gvariant).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Null => "nothing".into(),
+        other => other.to_string(),
+    }
+}
+
+/// Strip the type annotation gsettings prints for some values (`uint32 30`).
+fn untyped(value: &str) -> &str {
+    const PREFIXES: [&str; 8] = [
+        "byte ", "int16 ", "uint16 ", "int32 ", "uint32 ", "int64 ", "uint64 ", "@as ",
+    ];
+    PREFIXES
+        .iter()
+        .find_map(|prefix| value.strip_prefix(prefix))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use serde_json::json;
+
+    fn setting(key: &str, value: Value) -> GnomeSetting {
+        GnomeSetting {
+            schema: "org.gnome.desktop.interface".into(),
+            key: key.into(),
+            value,
+        }
+    }
+
+    #[test]
+    fn gvariant_formats_values() {
+        assert_eq!(gvariant(&json!("it's")), "'it\\'s'");
+        assert_eq!(gvariant(&json!(true)), "true");
+        assert_eq!(gvariant(&json!(["a", "b"])), "['a', 'b']");
+    }
+
+    #[test]
+    fn apply_settings_skips_matching_values() {
+        let executor = RecordingCommandExecutor::default().with_output(
+            "gsettings get org.gnome.desktop.interface cursor-size",
+            "uint32 24\n",
+        );
+
+        let changes =
+            apply_settings(&[setting("cursor-size", json!(24))], &executor, false).unwrap();
+
+        assert!(changes.is_empty());
+        assert_eq!(executor.calls().len(), 1);
+    }
+
+    #[test]
+    fn apply_settings_sets_changed_values() {
+        let executor = RecordingCommandExecutor::default().with_output(
+            "gsettings get org.gnome.desktop.interface color-scheme",
+            "'default'\n",
+        );
+
+        let changes = apply_settings(
+            &[setting("color-scheme", json!("prefer-dark"))],
+            &executor,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            changes,
+            vec!["org.gnome.desktop.interface color-scheme: 'default' -> 'prefer-dark'"]
+        );
+        assert_eq!(
+            executor.calls()[1].1,
+            vec![
+                "set",
+                "org.gnome.desktop.interface",
+                "color-scheme",
+                "'prefer-dark'"
+            ]
+        );
+    }
+}
@@ -0,0 +1,511 @@
+//! Built-in template helpers, registered on the engine rendering the
+//! templates of the manifest.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext, RenderError,
+    RenderErrorReason, ScopedJson,
+};
+use md5::Md5;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::{CommandExecutor, Invocation};
+use crate::infrastructure::encoding;
+use crate::services::hooks;
+
+/// Names of the helpers registered by [`register_helpers`].
+pub const HELPERS: &[&str] = &[
+    "include",
+    "exec",
+    "sha256",
+    "md5",
+    "base64",
+    "urlencode",
+    "joinPath",
+    "dirname",
+    "basename",
+    "expanduser",
+];
+
+/// Function turning the argument of a helper into its output.
+type Encode = fn(&[u8]) -> String;
+
+/// Register the built-in helpers on `engine`, reading the files they name
+/// from `repo`, with no file larger than `limit` bytes.
+///
+/// The `exec` helper fails until [`register_exec`] registers the output of
+/// the commands it may run.
+pub fn register_helpers(engine: &mut Handlebars<'_>, repo: &Path, limit: u64) {
+    let include = IncludeHelper {
+        repo: repo.to_path_buf(),
+        limit,
+    };
+    engine.register_helper(
+        "include",
+        Box::new(TextHelper(move |helper: &Helper<'_>, _: &Context| {
+            include.contents(helper)
+        })),
+    );
+    register_exec(engine, None);
+    register_path_helpers(engine);
+    let encodings: [(&'static str, Encode); 4] = [
+        ("sha256", |input| format!("{:x}", Sha256::digest(input))),
+        ("md5", |input| format!("{:x}", Md5::digest(input))),
+        ("base64", encoding::base64_encode),
+        ("urlencode", encoding::percent_encode),
+    ];
+    for (name, encode) in encodings {
+        engine.register_helper(
+            name,
+            Box::new(TextHelper(move |helper: &Helper<'_>, _: &Context| {
+                Ok(encode(string_param(name, helper, 0)?.as_bytes()))
+            })),
+        );
+    }
+}
+
+/// Register `exec`, rendering the output of the commands of `outputs`, by
+/// command line, or failing when the run does not allow commands.
+pub fn register_exec(engine: &mut Handlebars<'_>, outputs: Option<HashMap<String, String>>) {
+    engine.register_helper(
+        "exec",
+        Box::new(TextHelper(move |helper: &Helper<'_>, _: &Context| {
+            let command = helper
+                .param(0)
+                .and_then(|param| param.value().as_str())
+                .ok_or_else(|| {
+                    RenderErrorReason::ParamTypeMismatchForName("exec", "0".into(), "string".into())
+                })?;
+            let Some(outputs) = &outputs else {
+                return Err(RenderErrorReason::Other(format!(
+                    "`exec` cannot run `{command}`; pass --allow-template-exec to run commands"
+                ))
+                .into());
+            };
+            outputs.get(command).cloned().ok_or_else(|| {
+                RenderErrorReason::Other(format!(
+                    "`exec` did not run `{command}`, as it only runs commands given as strings"
+                ))
+                .into()
+            })
+        })),
+    );
+}
+
+/// Register the helpers building paths, whose output uses the separators
+/// of the platform: `\` on Windows, where `/` is turned into it, and `/`
+/// elsewhere.
+fn register_path_helpers(engine: &mut Handlebars<'_>) {
+    engine.register_helper(
+        "joinPath",
+        Box::new(TextHelper(|helper: &Helper<'_>, _: &Context| {
+            let parts = (0..helper.params().len().max(1))
+                .map(|index| string_param("joinPath", helper, index))
+                .collect::<std::result::Result<PathBuf, _>>()?;
+            Ok(normalized(&parts))
+        })),
+    );
+    engine.register_helper(
+        "dirname",
+        Box::new(TextHelper(|helper: &Helper<'_>, _: &Context| {
+            let path = PathBuf::from(string_param("dirname", helper, 0)?);
+            Ok(normalized(path.parent().unwrap_or(&path)))
+        })),
+    );
+    engine.register_helper(
+        "basename",
+        Box::new(TextHelper(|helper: &Helper<'_>, _: &Context| {
+            let path = PathBuf::from(string_param("basename", helper, 0)?);
+            Ok(path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default())
+        })),
+    );
+    engine.register_helper(
+        "expanduser",
+        Box::new(TextHelper(|helper: &Helper<'_>, context: &Context| {
+            let path = string_param("expanduser", helper, 0)?;
+            // The home of the run, which `--home` may have moved.
+            let home = context
+                .data()
+                .pointer("/system/home")
+                .and_then(Value::as_str)
+                .map(PathBuf::from)
+                .or_else(home::home_dir);
+            let expanded = match (path.strip_prefix('~'), home) {
+                (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with(['/', '\\']) => {
+                    home.join(rest.trim_start_matches(['/', '\\']))
+                }
+                _ => PathBuf::from(path),
+            };
+            Ok(normalized(&expanded))
+        })),
+    );
+}
+
+/// `path` written with the separators of the platform, dropping repeated
+/// separators and `.` components past the first one.
+fn normalized(path: &Path) -> String {
+    path.components()
+        .collect::<PathBuf>()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Standard output of the command line `command` of an `exec` helper, run
+/// through the platform shell, without surrounding whitespace.
+pub fn exec_output(command: &str, executor: &dyn CommandExecutor) -> Result<String> {
+    tracing::debug!("running template command: {command}");
+    let (shell, flag) = hooks::shell();
+    let captured = executor.run_capture(&Invocation::new(shell, &[flag, command]))?;
+    let stderr = captured.stderr.trim();
+    if !captured.success() && !stderr.is_empty() {
+        tracing::warn!("[{command}] {stderr}");
+    }
+    Ok(captured.into_stdout(command)?.trim().to_string())
+}
+
+/// Arguments of the calls to the helper `name` in the template `contents`:
+/// the string literal each call is given, or `None` for a call given an
+/// expression, whose value is only known when rendering.
+pub fn call_arguments(contents: &str, name: &str) -> Vec<Option<String>> {
+    let mut arguments = Vec::new();
+    for expression in contents.split("{{").skip(1) {
+        let expression = expression.split("}}").next().unwrap_or_default();
+        for (at, _) in expression.match_indices(name) {
+            let before = expression[..at].chars().next_back();
+            let after = &expression[at + name.len()..];
+            let called = before.is_none_or(|c| c.is_whitespace() || matches!(c, '(' | '~' | '{'))
+                && after.starts_with(char::is_whitespace);
+            if !called {
+                continue;
+            }
+            arguments.push(string_literal(after.trim_start()));
+        }
+    }
+    arguments
+}
+
+/// Value of the string literal `expression` starts with, unescaped as
+/// Handlebars does: as JSON, once single quotes are turned into double ones.
+fn string_literal(expression: &str) -> Option<String> {
+    let quote = expression
+        .chars()
+        .next()
+        .filter(|c| matches!(c, '"' | '\''))?;
+    let mut escaped = false;
+    let length = expression[1..].find(|c| {
+        let end = c == quote && !escaped;
+        escaped = c == '\\' && !escaped;
+        end
+    })?;
+    let inner = &expression[1..1 + length];
+    let json = match quote {
+        '"' => inner.to_string(),
+        _ => inner.replace("\\'", "'").replace('"', "\\\""),
+    };
+    serde_json::from_str(&format!("\"{json}\"")).ok()
+}
+
+/// Paths given as string literals to the `include` helpers of the template
+/// `contents`, or `None` when one of them is given by an expression, as the
+/// file it reads is only known when rendering.
+pub fn included_paths(contents: &str) -> Option<Vec<String>> {
+    call_arguments(contents, "include").into_iter().collect()
+}
+
+/// `{{include "path" indent=N}}`: the contents of a file of the repository,
+/// as they are, with every non-empty line indented by `indent` spaces.
+struct IncludeHelper {
+    repo: PathBuf,
+    limit: u64,
+}
+
+impl IncludeHelper {
+    fn contents(&self, helper: &Helper<'_>) -> std::result::Result<String, RenderError> {
+        let path = helper
+            .param(0)
+            .and_then(|param| param.value().as_str())
+            .ok_or_else(|| {
+                RenderErrorReason::ParamTypeMismatchForName("include", "0".into(), "string".into())
+            })?;
+        let indent = match helper.hash_get("indent") {
+            None => 0,
+            Some(indent) => indent.value().as_u64().ok_or_else(|| {
+                RenderErrorReason::HashTypeMismatchForName(
+                    "include",
+                    "indent".into(),
+                    "non-negative number".into(),
+                )
+            })? as usize,
+        };
+        let contents = self
+            .read(path)
+            .map_err(|err| RenderErrorReason::Other(format!("cannot include `{path}`: {err}")))?;
+        Ok(indented(&contents, indent))
+    }
+
+    /// Read `path`, relative to the repository and within it.
+    fn read(&self, path: &str) -> Result<String> {
+        let repo = self.repo.canonicalize()?;
+        let file = repo.join(path).canonicalize()?;
+        if Path::new(path).is_absolute() || !file.starts_with(&repo) {
+            return Err(DotstrapError::Io(std::io::Error::other(
+                "the file is outside the repository",
+            )));
+        }
+        let size = std::fs::metadata(&file)?.len();
+        if size > self.limit {
+            return Err(DotstrapError::TemplateTooLarge {
+                path: file,
+                size,
+                limit: self.limit,
+            });
+        }
+        Ok(std::fs::read_to_string(file)?)
+    }
+}
+
+/// Helper computing text from its call: expressions write it as it is,
+/// never HTML escaped, and subexpressions get it as a string.
+struct TextHelper<F>(F);
+
+impl<F> HelperDef for TextHelper<F>
+where
+    F: Fn(&Helper<'_>, &Context) -> std::result::Result<String, RenderError> + Send + Sync,
+{
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        context: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> std::result::Result<ScopedJson<'rc>, RenderError> {
+        Ok(ScopedJson::Derived(Value::String((self.0)(
+            helper, context,
+        )?)))
+    }
+
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        context: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        out.write(&(self.0)(helper, context)?)?;
+        Ok(())
+    }
+}
+
+/// Parameter `index` of `helper` as text: a string as it is, and other
+/// values as JSON.
+fn string_param(
+    name: &'static str,
+    helper: &Helper<'_>,
+    index: usize,
+) -> std::result::Result<String, RenderError> {
+    match helper.param(index).map(|param| param.value()) {
+        None | Some(Value::Null) => {
+            Err(RenderErrorReason::ParamNotFoundForIndex(name, index).into())
+        }
+        Some(Value::String(text)) => Ok(text.clone()),
+        Some(value) => Ok(value.to_string()),
+    }
+}
+
+/// `text` with every non-empty line indented by `indent` spaces.
+fn indented(text: &str, indent: usize) -> String {
+    if indent == 0 {
+        return text.to_string();
+    }
+    let prefix = " ".repeat(indent);
+    text.split_inclusive('\n')
+        .map(|line| {
+            if line.trim().is_empty() {
+                line.to_string()
+            } else {
+                format!("{prefix}{line}")
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(repo: &Path, template: &str) -> std::result::Result<String, RenderError> {
+        let mut engine = Handlebars::new();
+        register_helpers(&mut engine, repo, 1024);
+        engine.render_template(template, &json!({"header": "snippets/header.txt"}))
+    }
+
+    #[test]
+    fn include_inserts_files_of_the_repository_as_they_are() {
+        let repo = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(repo.path().join("snippets")).unwrap();
+        std::fs::write(
+            repo.path().join("snippets/header.txt"),
+            "# <managed> {{not rendered}}\n\n# by dotstrap\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            render(repo.path(), "{{include \"snippets/header.txt\"}}set -e\n").unwrap(),
+            "# <managed> {{not rendered}}\n\n# by dotstrap\nset -e\n"
+        );
+        assert_eq!(
+            render(repo.path(), "run: |\n{{include header indent=2}}").unwrap(),
+            "run: |\n  # <managed> {{not rendered}}\n\n  # by dotstrap\n"
+        );
+    }
+
+    #[test]
+    fn include_refuses_files_outside_the_repository() {
+        let parent = tempfile::tempdir().unwrap();
+        let repo = parent.path().join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        std::fs::write(parent.path().join("secret"), "token").unwrap();
+        std::fs::write(repo.join("big"), "x".repeat(2048)).unwrap();
+
+        let outside = render(&repo, "{{include \"../secret\"}}").unwrap_err();
+        assert!(outside.to_string().contains("outside the repository"));
+        assert!(render(&repo, "{{include \"missing\"}}").is_err());
+        assert!(render(&repo, "{{include \"big\"}}").is_err());
+        assert!(render(&repo, "{{include \"big\" indent=\"x\"}}").is_err());
+    }
+
+    #[test]
+    fn encoding_helpers_digest_and_encode_their_argument() {
+        let repo = tempfile::tempdir().unwrap();
+        let rendered = |template| render(repo.path(), template).unwrap();
+
+        assert_eq!(
+            rendered("{{sha256 \"abc\"}}"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            rendered("{{md5 \"abc\"}}"),
+            "900150983cd24fb0d6963f7d28e17f72"
+        );
+        assert_eq!(rendered("{{base64 \"me:p@ss>\"}}"), "bWU6cEBzcz4=");
+        assert_eq!(rendered("{{urlencode header}}"), "snippets%2Fheader.txt");
+        assert_eq!(rendered("{{sha256 42}}"), rendered("{{sha256 \"42\"}}"));
+        assert_eq!(
+            rendered("{{#if (eq (md5 \"abc\") \"900150983cd24fb0d6963f7d28e17f72\")}}ok{{/if}}"),
+            "ok"
+        );
+        assert!(render(repo.path(), "{{base64 missing}}").is_err());
+    }
+
+    #[test]
+    fn exec_renders_the_output_of_allowed_commands() {
+        let mut engine = Handlebars::new();
+        register_helpers(&mut engine, Path::new("."), 1024);
+        let disabled = engine
+            .render_template("{{exec \"date\"}}", &json!({}))
+            .unwrap_err();
+        assert!(disabled.to_string().contains("--allow-template-exec"));
+
+        let outputs = HashMap::from([("theme".to_string(), "<dark>".to_string())]);
+        register_exec(&mut engine, Some(outputs));
+        let context = json!({"command": "date"});
+        assert_eq!(
+            engine
+                .render_template("{{exec \"theme\"}}", &context)
+                .unwrap(),
+            "<dark>"
+        );
+        let dynamic = engine
+            .render_template("{{exec command}}", &context)
+            .unwrap_err();
+        assert!(
+            dynamic
+                .to_string()
+                .contains("only runs commands given as strings")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exec_output_trims_the_output_of_successful_commands() {
+        let executor = crate::infrastructure::command::SystemCommandExecutor::default();
+
+        assert_eq!(
+            exec_output("printf '  dark\\n\\n'", &executor).unwrap(),
+            "dark"
+        );
+        assert!(matches!(
+            exec_output("echo broken >&2; exit 3", &executor),
+            Err(DotstrapError::CommandFailed { status: 3, .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn path_helpers_join_and_split_normalized_paths() {
+        let repo = tempfile::tempdir().unwrap();
+        let rendered = |template| render(repo.path(), template).unwrap();
+
+        assert_eq!(
+            rendered("{{joinPath \"/opt//homebrew/\" \"./bin\" 3}}"),
+            "/opt/homebrew/bin/3"
+        );
+        assert_eq!(rendered("{{joinPath \"a\" \"/etc\"}}"), "/etc");
+        assert_eq!(rendered("{{dirname header}}"), "snippets");
+        assert_eq!(rendered("{{basename header}}"), "header.txt");
+        assert_eq!(rendered("{{dirname \"header.txt\"}}"), "");
+        assert_eq!(
+            rendered("{{basename (joinPath (dirname header) \"footer.txt\")}}"),
+            "footer.txt"
+        );
+        assert!(render(repo.path(), "{{joinPath}}").is_err());
+        assert!(render(repo.path(), "{{basename missing}}").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn expanduser_expands_to_the_home_of_the_run() {
+        let mut engine = Handlebars::new();
+        register_helpers(&mut engine, Path::new("."), 1024);
+        let context = json!({"system": {"home": "/home/ada"}});
+        let expand = |path: &str| {
+            engine
+                .render_template(&format!("{{{{expanduser \"{path}\"}}}}"), &context)
+                .unwrap()
+        };
+
+        assert_eq!(expand("~"), "/home/ada");
+        assert_eq!(expand("~/.local//bin/"), "/home/ada/.local/bin");
+        assert_eq!(expand("~ada/bin"), "~ada/bin");
+        assert_eq!(expand("/usr/./local"), "/usr/local");
+    }
+
+    #[test]
+    fn included_paths_lists_literal_arguments() {
+        assert_eq!(
+            included_paths("{{include \"a.txt\"}} {{~include 'b.txt' indent=2}} {{included}}"),
+            Some(vec!["a.txt".to_string(), "b.txt".to_string()])
+        );
+        assert_eq!(included_paths("{{include header}}"), None);
+        assert_eq!(
+            call_arguments(
+                r#"{{exec "brew --prefix"}} {{upper (exec cmd)}} {{exec 'echo "a\\b\'"'}}"#,
+                "exec"
+            ),
+            vec![
+                Some("brew --prefix".to_string()),
+                None,
+                Some("echo \"a\\b'\"".to_string())
+            ]
+        );
+    }
+}
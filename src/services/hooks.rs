@@ -0,0 +1,137 @@
+//! Service running the shell commands declared as manifest hooks.
+
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::config::Hook;
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::CommandExecutor;
+use crate::services::templating;
+
+/// Render `hooks` with the template context and run them in order through
+/// the platform shell.
+///
+/// `phase` names the hooks in errors, and `origin` is the manifest declaring
+/// them. Returns the rendered commands, which are only planned in dry-run
+/// mode. The first failing hook aborts the remaining ones.
+pub fn run_hooks(
+    phase: &'static str,
+    hooks: &[Hook],
+    context: &Value,
+    origin: &Path,
+    executor: &dyn CommandExecutor,
+    dry_run: bool,
+) -> Result<Vec<String>> {
+    let mut commands = Vec::new();
+    for hook in hooks {
+        let command = templating::render_inline(&hook.run, context, origin)?;
+        let command = command.trim().to_string();
+        if !dry_run {
+            tracing::debug!("running {phase} hook: {command}");
+            let (shell, flag) = shell();
+            executor
+                .run(shell, &[flag, &command])
+                .map_err(|source| DotstrapError::Hook {
+                    phase,
+                    command: command.clone(),
+                    source: Box::new(source),
+                })?;
+        }
+        commands.push(command);
+    }
+    Ok(commands)
+}
+
+/// Shell running command lines, with the flag passing it one.
+pub(crate) fn shell() -> (&'static str, &'static str) {
+    if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use serde_json::json;
+
+    fn hook(run: &str) -> Hook {
+        Hook {
+            run: run.into(),
+            when: None,
+            enabled: None,
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_hooks_renders_and_runs_commands() {
+        let executor = RecordingCommandExecutor::default();
+        let hooks = [hook("echo {{name}}"), hook("true")];
+
+        let commands = run_hooks(
+            "post-apply",
+            &hooks,
+            &json!({ "name": "dotstrap" }),
+            Path::new("manifest.yaml"),
+            &executor,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(commands, vec!["echo dotstrap", "true"]);
+        assert_eq!(
+            executor.calls()[0],
+            (
+                "sh".to_string(),
+                vec!["-c".to_string(), "echo dotstrap".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn run_hooks_stops_at_the_first_failure() {
+        let executor =
+            RecordingCommandExecutor::with_failure(if cfg!(windows) { "cmd" } else { "sh" });
+
+        let err = run_hooks(
+            "pre-apply",
+            &[hook("exit 1"), hook("true")],
+            &json!({}),
+            Path::new("manifest.yaml"),
+            &executor,
+            false,
+        )
+        .unwrap_err();
+
+        assert!(matches!(
+            err,
+            DotstrapError::Hook {
+                phase: "pre-apply",
+                ..
+            }
+        ));
+        assert_eq!(executor.calls().len(), 1);
+    }
+
+    #[test]
+    fn run_hooks_plans_commands_in_dry_run() {
+        let executor = RecordingCommandExecutor::default();
+
+        let commands = run_hooks(
+            "pre-apply",
+            &[hook("make install")],
+            &json!({}),
+            Path::new("manifest.yaml"),
+            &executor,
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(commands, vec!["make install"]);
+        assert!(executor.calls().is_empty());
+    }
+}
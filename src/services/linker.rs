@@ -1,55 +1,322 @@
 //! Service that stages rendered templates and links them into the target home.
 
-use std::fs;
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use sha2::{Digest, Sha256};
+
 use crate::errors::{DotstrapError, Result};
-use crate::services::templating::RenderedSet;
+use crate::infrastructure::filesystem::{FileSystem, OsFileSystem};
+use crate::services::outcome::{ItemStatus, LinkOutcome, LinkStrategy};
+use crate::services::templating::{RenderedSet, RenderedTemplate};
+
+/// Default directory rendered templates are staged in before being linked.
+pub fn default_stage_root(home: &Path) -> PathBuf {
+    home.join(".dotstrap/generated")
+}
+
+/// Link all rendered templates into the provided `home` directory, staging
+/// them under `stage_root`.
+///
+/// Templates whose link and rendered contents are already current are
+/// reported as unchanged; in dry-run mode the statuses describe what a real
+/// run would do.
+pub fn link_templates(
+    home: &Path,
+    stage_root: &Path,
+    rendered: &RenderedSet,
+    dry_run: bool,
+) -> Result<Vec<LinkOutcome>> {
+    link_templates_in(&OsFileSystem, home, stage_root, rendered, dry_run)
+}
 
-/// Link all rendered templates into the provided `home` directory.
-pub fn link_templates(home: &Path, rendered: &RenderedSet, dry_run: bool) -> Result<Vec<PathBuf>> {
+/// Link all rendered templates as [`link_templates`] does, through `fs`.
+pub fn link_templates_in(
+    fs: &dyn FileSystem,
+    home: &Path,
+    stage_root: &Path,
+    rendered: &RenderedSet,
+    dry_run: bool,
+) -> Result<Vec<LinkOutcome>> {
     let mut linked = Vec::new();
-    let stage_root = home.join(".dotstrap/generated");
     if !dry_run {
-        fs::create_dir_all(&stage_root)?;
+        fs.create_dir_all(stage_root)?;
     }
     for item in &rendered.templates {
         let destination = home.join(&item.template.destination);
-        linked.push(destination.clone());
-        if dry_run {
-            continue;
+        let stage_path = stage_root.join(&item.template.destination);
+        let outcome = link_template(fs, item, &stage_path, &destination, dry_run)
+            .map_err(|err| link_error(&destination, err))?;
+        linked.push(outcome);
+    }
+    Ok(linked)
+}
+
+/// Reason given for a linked template whose rendered contents changed.
+const TEMPLATE_CHANGED: &str = "template changed";
+/// Reason given for a create-only template whose destination exists.
+pub(crate) const CREATED_ONCE: &str = "created once, never overwritten";
+
+fn link_template(
+    fs: &dyn FileSystem,
+    item: &RenderedTemplate,
+    stage_path: &Path,
+    destination: &Path,
+    dry_run: bool,
+) -> Result<LinkOutcome> {
+    if item.template.create_only() {
+        return create_once(fs, item, destination, dry_run);
+    }
+    let contents_changed = !same_contents(fs, stage_path, &item.rendered_path)?;
+    let mut outcome = if dry_run {
+        planned_link_in(fs, stage_path, destination)
+    } else {
+        if let Some(parent) = stage_path.parent() {
+            fs.create_dir_all(parent)?;
+        }
+        // Keep the contents the link resolved to, so the run can be rolled back.
+        let current = link_status_in(fs, stage_path, destination) == ItemStatus::Unchanged;
+        let previous = if contents_changed && current {
+            reconcile_existing(fs, stage_path)?
+        } else {
+            None
+        };
+        // A copy standing for the link is replaced rather than backed up.
+        let stale_copy = contents_changed
+            && current
+            && link_strategy(fs, stage_path) == LinkStrategy::Copy
+            && !fs.is_symlink(destination);
+        if stale_copy {
+            fs.remove_file(destination)?;
         }
+        fs.copy(&item.rendered_path, stage_path)?;
+        apply_mode(fs, stage_path, item.template.mode)?;
+        let mut outcome = link_path_in(fs, stage_path, destination)?;
+        outcome.previous = outcome.previous.or(previous);
+        if stale_copy {
+            outcome = outcome.because(TEMPLATE_CHANGED);
+            outcome.status = ItemStatus::Updated;
+        }
+        outcome
+    };
+    if contents_changed && outcome.status == ItemStatus::Unchanged {
+        outcome = outcome.because(TEMPLATE_CHANGED);
+        outcome.status = ItemStatus::Updated;
+    }
+    Ok(outcome)
+}
+
+/// Copy the output of the create-only `item` to `destination`, unless
+/// anything is already there.
+///
+/// The copy is the user's own file rather than a link to the staged one, so
+/// editing it changes nothing else and later runs leave it alone.
+fn create_once(
+    fs: &dyn FileSystem,
+    item: &RenderedTemplate,
+    destination: &Path,
+    dry_run: bool,
+) -> Result<LinkOutcome> {
+    if fs.symlink_metadata(destination).is_ok() {
+        return Ok(
+            LinkOutcome::new(destination.to_path_buf(), ItemStatus::Unchanged)
+                .because(CREATED_ONCE),
+        );
+    }
+    if !dry_run {
         if let Some(parent) = destination.parent() {
-            fs::create_dir_all(parent)?;
+            fs.create_dir_all(parent)?;
+        }
+        fs.copy(&item.rendered_path, destination)?;
+        apply_mode(fs, destination, item.template.mode)?;
+    }
+    let mut outcome = LinkOutcome::new(destination.to_path_buf(), ItemStatus::Created)
+        .because("destination missing");
+    outcome.strategy = LinkStrategy::Copy;
+    if !dry_run {
+        outcome.digest = Some(digest_in(fs, destination)?);
+    }
+    Ok(outcome)
+}
+
+/// Whether the file at `existing`, if any, has the contents of `expected`,
+/// compared a block at a time so large files are never loaded whole.
+pub(crate) fn same_contents(
+    fs: &dyn FileSystem,
+    existing: &Path,
+    expected: &Path,
+) -> io::Result<bool> {
+    let mut expected_file = fs.open(expected)?;
+    let Ok(mut existing_file) = fs.open(existing) else {
+        return Ok(false);
+    };
+    if fs.metadata(existing)?.len != fs.metadata(expected)?.len {
+        return Ok(false);
+    }
+    let mut left = vec![0; 64 * 1024];
+    let mut right = vec![0; 64 * 1024];
+    loop {
+        let read = read_block(&mut existing_file, &mut left)?;
+        if read != read_block(&mut expected_file, &mut right)? || left[..read] != right[..read] {
+            return Ok(false);
         }
-        if destination.exists() || destination.is_symlink() {
-            reconcile_existing(&destination)?;
+        if read == 0 {
+            return Ok(true);
         }
-        let stage_path = stage_root.join(&item.template.destination);
-        if let Some(parent) = stage_path.parent() {
-            fs::create_dir_all(parent)?;
+    }
+}
+
+/// Hex encoded SHA-256 digest of the file at `path`.
+pub(crate) fn digest_in(fs: &dyn FileSystem, path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs.open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Fill `buffer` from `reader` unless the end is reached first, returning
+/// the number of bytes read.
+fn read_block(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
         }
-        fs::copy(&item.rendered_path, &stage_path)?;
-        apply_mode(&stage_path, item.template.mode)?;
-        create_symlink(&stage_path, &destination)?;
     }
-    Ok(linked)
+    Ok(filled)
 }
 
-fn reconcile_existing(path: &Path) -> Result<()> {
-    if path.is_symlink() {
-        fs::remove_file(path)?;
-        return Ok(());
+/// Attribute I/O failures to the link being created at `destination`.
+fn link_error(destination: &Path, err: DotstrapError) -> DotstrapError {
+    match err {
+        DotstrapError::Io(source) => DotstrapError::Link {
+            path: destination.to_path_buf(),
+            source,
+        },
+        other => other,
     }
-    if !path.exists() {
-        return Ok(());
+}
+
+/// Whether linking `destination` to `source` would create, replace, or keep
+/// the current entry.
+pub fn link_status(source: &Path, destination: &Path) -> ItemStatus {
+    link_status_in(&OsFileSystem, source, destination)
+}
+
+/// [`link_status`] through `fs`.
+///
+/// Where symbolic links are not permitted, a file with the contents of
+/// `source` stands for the link.
+pub fn link_status_in(fs: &dyn FileSystem, source: &Path, destination: &Path) -> ItemStatus {
+    let copied = || {
+        link_strategy(fs, source) == LinkStrategy::Copy
+            && !fs.is_symlink(destination)
+            && same_contents(fs, destination, source).unwrap_or(false)
+    };
+    if fs
+        .read_link(destination)
+        .is_ok_and(|target| target == source)
+        || copied()
+    {
+        ItemStatus::Unchanged
+    } else if fs.exists(destination) || fs.is_symlink(destination) {
+        ItemStatus::Updated
+    } else {
+        ItemStatus::Created
+    }
+}
+
+/// Outcome linking `destination` to `source` would have, with the reason
+/// for its status.
+pub fn planned_link(source: &Path, destination: &Path) -> LinkOutcome {
+    planned_link_in(&OsFileSystem, source, destination)
+}
+
+/// [`planned_link`] through `fs`.
+pub fn planned_link_in(fs: &dyn FileSystem, source: &Path, destination: &Path) -> LinkOutcome {
+    let status = link_status_in(fs, source, destination);
+    let reason = match status {
+        ItemStatus::Created => "destination missing",
+        ItemStatus::Updated if fs.is_symlink(destination) => "link points elsewhere",
+        ItemStatus::Updated => "destination is a different file",
+        ItemStatus::Unchanged | ItemStatus::Skipped => "already linked",
+    };
+    LinkOutcome::new(destination.to_path_buf(), status).because(reason)
+}
+
+/// Symlink `destination` to `source`, creating parent directories and backing
+/// up any pre-existing file at `destination`.
+pub fn link_path(source: &Path, destination: &Path) -> Result<LinkOutcome> {
+    link_path_in(&OsFileSystem, source, destination)
+}
+
+/// [`link_path`] through `fs`.
+pub fn link_path_in(fs: &dyn FileSystem, source: &Path, destination: &Path) -> Result<LinkOutcome> {
+    replace_with_link(fs, source, destination).map_err(|err| link_error(destination, err))
+}
+
+fn replace_with_link(
+    fs: &dyn FileSystem,
+    source: &Path,
+    destination: &Path,
+) -> Result<LinkOutcome> {
+    let mut outcome = planned_link_in(fs, source, destination);
+    outcome.strategy = link_strategy(fs, source);
+    if outcome.status == ItemStatus::Unchanged {
+        return Ok(outcome);
+    }
+    if let Some(parent) = destination.parent() {
+        fs.create_dir_all(parent)?;
+    }
+    if fs.exists(destination) || fs.is_symlink(destination) {
+        outcome.previous = fs.read_link(destination).ok();
+        outcome.backup = reconcile_existing(fs, destination)?;
+    }
+    if create_symlink(fs, source, destination)? == LinkStrategy::Copy {
+        outcome.digest = Some(digest_in(fs, destination)?);
     }
+    Ok(outcome)
+}
+
+/// Move a regular file out of the way, returning its backup path.
+pub(crate) fn reconcile_existing(fs: &dyn FileSystem, path: &Path) -> Result<Option<PathBuf>> {
+    if fs.is_symlink(path) {
+        fs.remove_file(path)?;
+        return Ok(None);
+    }
+    if !fs.exists(path) {
+        return Ok(None);
+    }
+    let backup_path = backup_path(fs, path)?;
+    fs.rename(path, &backup_path)?;
+    Ok(Some(backup_path))
+}
+
+/// Copy the contents of `path`, following a link, next to the backups
+/// [`link_path`] keeps, leaving `path` in place.
+pub fn backup_copy(path: &Path) -> Result<Option<PathBuf>> {
+    backup_copy_in(&OsFileSystem, path)
+}
+
+/// [`backup_copy`] through `fs`.
+pub fn backup_copy_in(fs: &dyn FileSystem, path: &Path) -> Result<Option<PathBuf>> {
+    if !fs.metadata(path).is_ok_and(|metadata| metadata.is_file()) {
+        return Ok(None);
+    }
+    let backup_path = backup_path(fs, path)?;
+    fs.write(&backup_path, &fs.read(path)?)?;
+    Ok(Some(backup_path))
+}
+
+/// Timestamped path in the `.dotstrap-backups` directory next to `path`,
+/// creating the directory.
+fn backup_path(fs: &dyn FileSystem, path: &Path) -> Result<PathBuf> {
     let backup_dir = path
         .parent()
         .map(|p| p.join(".dotstrap-backups"))
         .unwrap_or_else(|| PathBuf::from(".dotstrap-backups"));
-    fs::create_dir_all(&backup_dir)?;
+    fs.create_dir_all(&backup_dir)?;
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap_or_default()
@@ -58,46 +325,60 @@ fn reconcile_existing(path: &Path) -> Result<()> {
         .file_name()
         .map(|n| n.to_string_lossy().to_string())
         .unwrap_or_else(|| "config".into());
-    let backup_path = backup_dir.join(format!("{file_name}.{timestamp}.bak"));
-    fs::rename(path, backup_path)?;
-    Ok(())
+    Ok(backup_dir.join(format!("{file_name}.{timestamp}.bak")))
 }
 
-fn apply_mode(rendered: &Path, mode: Option<u32>) -> Result<()> {
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        if let Some(mode) = mode {
-            let mut perms = fs::metadata(rendered)?.permissions();
-            perms.set_mode(mode);
-            fs::set_permissions(rendered, perms)?;
-        }
-    }
-    #[cfg(not(unix))]
-    {
-        let _ = (rendered, mode);
+pub(crate) fn apply_mode(fs: &dyn FileSystem, rendered: &Path, mode: Option<u32>) -> Result<()> {
+    if let Some(mode) = mode {
+        fs.set_mode(rendered, mode)?;
     }
     Ok(())
 }
 
-fn create_symlink(source: &Path, destination: &Path) -> Result<()> {
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::symlink;
-        symlink(source, destination).map_err(DotstrapError::Io)
+/// How `source` is placed into the home directory through `fs`: as a
+/// symbolic link when permitted, else as a copy of a file or a junction to a
+/// directory.
+pub fn link_strategy(fs: &dyn FileSystem, source: &Path) -> LinkStrategy {
+    if fs.symlinks_permitted() {
+        LinkStrategy::Symlink
+    } else if fs.metadata(source).is_ok_and(|metadata| metadata.is_dir()) {
+        LinkStrategy::Junction
+    } else {
+        LinkStrategy::Copy
     }
-    #[cfg(windows)]
-    {
-        use std::os::windows::fs::symlink_file;
-        symlink_file(source, destination).map_err(DotstrapError::Io)
+}
+
+/// Place `source` at `destination` with the [`link_strategy`] of `fs`,
+/// returning the strategy used.
+pub(crate) fn create_symlink(
+    fs: &dyn FileSystem,
+    source: &Path,
+    destination: &Path,
+) -> Result<LinkStrategy> {
+    let strategy = link_strategy(fs, source);
+    place(fs, strategy, source, destination)?;
+    Ok(strategy)
+}
+
+/// Place `source` at `destination` with `strategy`.
+pub(crate) fn place(
+    fs: &dyn FileSystem,
+    strategy: LinkStrategy,
+    source: &Path,
+    destination: &Path,
+) -> Result<()> {
+    match strategy {
+        LinkStrategy::Symlink => fs.symlink(source, destination)?,
+        LinkStrategy::Copy => fs.copy(source, destination)?,
+        LinkStrategy::Junction => fs.junction(source, destination)?,
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::TemplateMapping;
-    use crate::services::templating::{RenderedSet, RenderedTemplate};
     use std::fs;
     use std::path::PathBuf;
     use tempfile::TempDir;
@@ -110,6 +391,10 @@ mod tests {
             source: PathBuf::from("source.txt"),
             destination,
             mode,
+            when: None,
+            enabled: None,
+            copy: false,
+            overwrite: Default::default(),
         };
         RenderedSet {
             _tempdir: rendered_tempdir,
@@ -126,11 +411,22 @@ mod tests {
         let destination = PathBuf::from(".config/app.conf");
         let rendered_set = build_rendered_set(destination.clone(), None, "ignored");
 
-        let linked =
-            link_templates(home.path(), &rendered_set, true).expect("dry run should succeed");
+        let linked = link_templates(
+            home.path(),
+            &default_stage_root(home.path()),
+            &rendered_set,
+            true,
+        )
+        .expect("dry run should succeed");
 
         let expected_destination = home.path().join(&destination);
-        assert_eq!(linked, vec![expected_destination.clone()]);
+        assert_eq!(
+            linked,
+            vec![
+                LinkOutcome::new(expected_destination.clone(), ItemStatus::Created)
+                    .because("destination missing")
+            ]
+        );
         assert!(
             !expected_destination.exists(),
             "dry run must not create destination files"
@@ -156,11 +452,18 @@ mod tests {
         }
         fs::write(&destination_path, "old contents").expect("failed to seed existing file");
 
-        let linked =
-            link_templates(home.path(), &rendered_set, false).expect("linking should succeed");
+        let linked = link_templates(
+            home.path(),
+            &default_stage_root(home.path()),
+            &rendered_set,
+            false,
+        )
+        .expect("linking should succeed");
 
         let expected_destination = home.path().join(&destination);
-        assert_eq!(linked, vec![expected_destination.clone()]);
+        assert_eq!(linked.len(), 1);
+        assert_eq!(linked[0].path, expected_destination);
+        assert_eq!(linked[0].status, ItemStatus::Updated);
 
         let metadata = fs::symlink_metadata(&expected_destination).expect("destination metadata");
         assert!(
@@ -198,5 +501,238 @@ mod tests {
         let backup_contents =
             fs::read_to_string(&backup_path).expect("backup file should preserve contents");
         assert_eq!(backup_contents, "old contents");
+        assert_eq!(linked[0].backup.as_ref(), Some(&backup_path));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn link_templates_reports_unchanged_and_updated_templates() {
+        let home = TempDir::new().expect("failed to create home tempdir");
+        let destination = PathBuf::from(".app.conf");
+        let first = build_rendered_set(destination.clone(), None, "v1");
+        link_templates(home.path(), &default_stage_root(home.path()), &first, false)
+            .expect("linking should succeed");
+
+        let again = link_templates(home.path(), &default_stage_root(home.path()), &first, false)
+            .expect("relinking should succeed");
+        assert_eq!(again[0].status, ItemStatus::Unchanged);
+        assert_eq!(again[0].backup, None);
+        assert_eq!(again[0].previous, None);
+
+        let second = build_rendered_set(destination, None, "v2");
+        let planned = link_templates(home.path(), &default_stage_root(home.path()), &second, true)
+            .expect("dry run should succeed");
+        assert_eq!(planned[0].status, ItemStatus::Updated);
+
+        let updated = link_templates(
+            home.path(),
+            &default_stage_root(home.path()),
+            &second,
+            false,
+        )
+        .expect("update should succeed");
+        let previous = updated[0].previous.as_ref().expect("old contents are kept");
+        assert_eq!(fs::read_to_string(previous).unwrap(), "v1");
+    }
+
+    #[test]
+    fn backup_copy_keeps_the_file_in_place() {
+        use crate::infrastructure::filesystem::MemoryFileSystem;
+
+        let fs = MemoryFileSystem::new().with_file("/home/.zshrc", "old contents");
+
+        let backup = backup_copy_in(&fs, Path::new("/home/.zshrc"))
+            .unwrap()
+            .expect("a regular file is backed up");
+        assert!(backup.starts_with("/home/.dotstrap-backups"));
+        assert_eq!(fs.read(&backup).unwrap(), b"old contents");
+        assert_eq!(fs.read(Path::new("/home/.zshrc")).unwrap(), b"old contents");
+        assert_eq!(
+            backup_copy_in(&fs, Path::new("/home/.bashrc")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn link_templates_in_memory_touches_no_disk() {
+        use crate::infrastructure::filesystem::MemoryFileSystem;
+
+        let fs = MemoryFileSystem::new()
+            .with_file("/rendered/app.conf", "new contents")
+            .with_file("/home/.config/app.conf", "old contents");
+        let rendered = RenderedSet {
+            _tempdir: TempDir::new().unwrap(),
+            templates: vec![RenderedTemplate {
+                template: TemplateMapping {
+                    source: PathBuf::from("source.txt"),
+                    destination: PathBuf::from(".config/app.conf"),
+                    mode: Some(0o600),
+                    when: None,
+                    enabled: None,
+                    copy: false,
+                    overwrite: Default::default(),
+                },
+                rendered_path: PathBuf::from("/rendered/app.conf"),
+            }],
+        };
+        let home = Path::new("/home");
+        let stage_root = default_stage_root(home);
+
+        let linked = link_templates_in(&fs, home, &stage_root, &rendered, false).unwrap();
+
+        let destination = home.join(".config/app.conf");
+        let staged = stage_root.join(".config/app.conf");
+        assert_eq!(linked[0].status, ItemStatus::Updated);
+        assert_eq!(fs.read_link(&destination).unwrap(), staged);
+        assert_eq!(fs.read(&destination).unwrap(), b"new contents");
+        assert_eq!(fs.metadata(&staged).unwrap().mode, Some(0o600));
+        let backup = linked[0]
+            .backup
+            .as_ref()
+            .expect("the old file is backed up");
+        assert_eq!(fs.read(backup).unwrap(), b"old contents");
+
+        let again = link_templates_in(&fs, home, &stage_root, &rendered, false).unwrap();
+        assert_eq!(again[0].status, ItemStatus::Unchanged);
+    }
+
+    #[test]
+    fn link_templates_creates_create_only_destinations_once() {
+        use crate::config::Overwrite;
+        use crate::infrastructure::filesystem::MemoryFileSystem;
+
+        let fs = MemoryFileSystem::new().with_file("/rendered/.gitconfig.local", "[user]\n");
+        let rendered = RenderedSet {
+            _tempdir: TempDir::new().unwrap(),
+            templates: vec![RenderedTemplate {
+                template: TemplateMapping {
+                    source: PathBuf::from("gitconfig.local.hbs"),
+                    destination: PathBuf::from(".gitconfig.local"),
+                    mode: Some(0o600),
+                    when: None,
+                    enabled: None,
+                    copy: false,
+                    overwrite: Overwrite::Never,
+                },
+                rendered_path: PathBuf::from("/rendered/.gitconfig.local"),
+            }],
+        };
+        let home = Path::new("/home");
+        let stage_root = default_stage_root(home);
+        let destination = home.join(".gitconfig.local");
+
+        let planned = link_templates_in(&fs, home, &stage_root, &rendered, true).unwrap();
+        assert_eq!(planned[0].status, ItemStatus::Created);
+        assert!(!fs.exists(&destination));
+
+        let created = link_templates_in(&fs, home, &stage_root, &rendered, false).unwrap();
+        assert_eq!(created[0].status, ItemStatus::Created);
+        assert_eq!(created[0].strategy, LinkStrategy::Copy);
+        assert!(!fs.is_symlink(&destination));
+        assert_eq!(fs.metadata(&destination).unwrap().mode, Some(0o600));
+        assert!(!fs.exists(&stage_root.join(".gitconfig.local")));
+
+        fs.write(&destination, b"[user]\n  email = me@example.com\n")
+            .unwrap();
+        let again = link_templates_in(&fs, home, &stage_root, &rendered, false).unwrap();
+        assert_eq!(
+            again[0],
+            LinkOutcome::new(destination.clone(), ItemStatus::Unchanged).because(CREATED_ONCE)
+        );
+        assert_eq!(
+            fs.read(&destination).unwrap(),
+            b"[user]\n  email = me@example.com\n"
+        );
+    }
+
+    #[test]
+    fn link_templates_copy_files_where_symlinks_are_not_permitted() {
+        use crate::infrastructure::filesystem::MemoryFileSystem;
+
+        let fs = MemoryFileSystem::new()
+            .without_symlinks()
+            .with_file("/rendered/app.conf", "v1");
+        let rendered = RenderedSet {
+            _tempdir: TempDir::new().unwrap(),
+            templates: vec![RenderedTemplate {
+                template: TemplateMapping {
+                    source: PathBuf::from("source.txt"),
+                    destination: PathBuf::from(".config/app.conf"),
+                    mode: None,
+                    when: None,
+                    enabled: None,
+                    copy: false,
+                    overwrite: Default::default(),
+                },
+                rendered_path: PathBuf::from("/rendered/app.conf"),
+            }],
+        };
+        let home = Path::new("/home");
+        let stage_root = default_stage_root(home);
+        let destination = home.join(".config/app.conf");
+
+        let linked = link_templates_in(&fs, home, &stage_root, &rendered, false).unwrap();
+        assert_eq!(linked[0].status, ItemStatus::Created);
+        assert_eq!(linked[0].strategy, LinkStrategy::Copy);
+        assert!(!fs.is_symlink(&destination));
+        assert_eq!(fs.read(&destination).unwrap(), b"v1");
+
+        let again = link_templates_in(&fs, home, &stage_root, &rendered, false).unwrap();
+        assert_eq!(again[0].status, ItemStatus::Unchanged);
+
+        fs.write(Path::new("/rendered/app.conf"), b"v2").unwrap();
+        let updated = link_templates_in(&fs, home, &stage_root, &rendered, false).unwrap();
+        assert_eq!(updated[0].status, ItemStatus::Updated);
+        assert_eq!(updated[0].backup, None, "the stale copy is not backed up");
+        assert_eq!(fs.read(&destination).unwrap(), b"v2");
+        let previous = updated[0].previous.as_ref().expect("old contents are kept");
+        assert_eq!(fs.read(previous).unwrap(), b"v1");
+    }
+
+    #[test]
+    fn same_contents_compares_files_block_by_block() {
+        let dir = TempDir::new().unwrap();
+        let large = vec![7u8; 200 * 1024];
+        let mut edited = large.clone();
+        edited[150 * 1024] = 8;
+        fs::write(dir.path().join("a"), &large).unwrap();
+        fs::write(dir.path().join("b"), &large).unwrap();
+        fs::write(dir.path().join("c"), &edited).unwrap();
+
+        assert!(
+            same_contents(&OsFileSystem, &dir.path().join("a"), &dir.path().join("b")).unwrap()
+        );
+        assert!(
+            !same_contents(&OsFileSystem, &dir.path().join("c"), &dir.path().join("b")).unwrap()
+        );
+        assert!(
+            !same_contents(
+                &OsFileSystem,
+                &dir.path().join("missing"),
+                &dir.path().join("b")
+            )
+            .unwrap()
+        );
+        assert!(
+            same_contents(
+                &OsFileSystem,
+                &dir.path().join("a"),
+                &dir.path().join("missing")
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn link_path_reports_failures_with_destination() {
+        let home = TempDir::new().expect("failed to create home tempdir");
+        let blocker = home.path().join("not-a-directory");
+        fs::write(&blocker, "").unwrap();
+        let destination = blocker.join(".zshrc");
+
+        let error = link_path(&home.path().join("source"), &destination)
+            .expect_err("linking below a regular file should fail");
+
+        assert!(matches!(error, DotstrapError::Link { path, .. } if path == destination));
     }
 }
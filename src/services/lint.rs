@@ -0,0 +1,247 @@
+//! Service finding the variables templates read, to check them against the
+//! context they are rendered with, for `dotstrap lint`.
+
+use handlebars::template::{HelperTemplate, Parameter, Template, TemplateElement};
+use handlebars::{Path as VariablePath, TemplateError};
+use serde_json::Value;
+
+use crate::services::helpers;
+
+/// Helpers built into Handlebars, which are never variables, like the
+/// [`helpers::HELPERS`] of dotstrap.
+const BUILTIN_HELPERS: &[&str] = &[
+    "if", "unless", "each", "with", "lookup", "raw", "log", "eq", "ne", "gt", "gte", "lt", "lte",
+    "and", "or", "not", "len",
+];
+
+/// Block helpers rendering their block with another value as the context.
+const SCOPING_HELPERS: &[&str] = &["each", "with"];
+
+/// Variable read by a template expression, at a 1-based line and column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// Keys leading to the variable from the root of the context; empty when
+    /// the whole context is read, e.g. through `this` or `@root`.
+    pub path: Vec<String>,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Variables the template `source` reads from the root of the context.
+///
+/// Variables read relative to the value of an `each` or `with` block cannot
+/// be resolved without rendering and are left out, as are the names of
+/// Handlebars helpers and of `helpers`, e.g. template plugins.
+pub fn references(source: &str, helpers: &[String]) -> Result<Vec<Reference>, TemplateError> {
+    let template = Template::compile(source)?;
+    let mut walker = Walker {
+        helpers,
+        references: Vec::new(),
+    };
+    walker.template(&template, 0);
+    Ok(walker.references)
+}
+
+/// Whether `path` leads to an entry of `context`. Paths going through a
+/// value that is not a map, such as a list, are assumed to.
+pub fn is_defined(context: &Value, path: &[String]) -> bool {
+    let mut value = context;
+    for key in path {
+        match value {
+            Value::Object(entries) => match entries.get(key) {
+                Some(entry) => value = entry,
+                None => return false,
+            },
+            _ => return true,
+        }
+    }
+    true
+}
+
+struct Walker<'a> {
+    helpers: &'a [String],
+    references: Vec<Reference>,
+}
+
+impl Walker<'_> {
+    fn is_helper(&self, name: &str) -> bool {
+        BUILTIN_HELPERS.contains(&name)
+            || helpers::HELPERS.contains(&name)
+            || self.helpers.iter().any(|helper| helper == name)
+    }
+
+    /// Collect the references of `template`, rendered `depth` blocks below
+    /// the root of the context.
+    fn template(&mut self, template: &Template, depth: usize) {
+        for (element, mapping) in template.elements.iter().zip(&template.mapping) {
+            let at = (mapping.0, mapping.1);
+            match element {
+                TemplateElement::Expression(helper) | TemplateElement::HtmlExpression(helper) => {
+                    self.helper(helper, depth, at)
+                }
+                TemplateElement::HelperBlock(helper) => {
+                    self.helper(helper, depth, at);
+                    let scoped = helper_name(&helper.name)
+                        .is_some_and(|name| SCOPING_HELPERS.contains(&name));
+                    if let Some(block) = &helper.template {
+                        self.template(block, depth + usize::from(scoped));
+                    }
+                    if let Some(inverse) = &helper.inverse {
+                        self.template(inverse, depth);
+                    }
+                }
+                TemplateElement::DecoratorBlock(decorator)
+                | TemplateElement::PartialBlock(decorator) => {
+                    if let Some(block) = &decorator.template {
+                        self.template(block, depth);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn helper(&mut self, helper: &HelperTemplate, depth: usize, at: (usize, usize)) {
+        let call = helper.block || !helper.params.is_empty() || !helper.hash.is_empty();
+        match &helper.name {
+            Parameter::Name(name) if !call && !self.is_helper(name) => self.push(name, depth, at),
+            Parameter::Path(path) if !call => self.path(path, depth, at),
+            _ => {}
+        }
+        for param in helper.params.iter().chain(helper.hash.values()) {
+            self.parameter(param, depth, at);
+        }
+    }
+
+    fn parameter(&mut self, param: &Parameter, depth: usize, at: (usize, usize)) {
+        match param {
+            Parameter::Path(path) => self.path(path, depth, at),
+            Parameter::Subexpression(subexpression) => match subexpression.as_element() {
+                TemplateElement::Expression(helper) | TemplateElement::HtmlExpression(helper) => {
+                    self.helper(helper, depth, at)
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn path(&mut self, path: &VariablePath, depth: usize, at: (usize, usize)) {
+        // Local variables such as `@index` are not read from the context.
+        if let VariablePath::Relative((_, raw)) = path {
+            self.push(raw, depth, at);
+        }
+    }
+
+    fn push(&mut self, raw: &str, depth: usize, (line, column): (usize, usize)) {
+        if let Some(path) = resolve(raw, depth) {
+            self.references.push(Reference { path, line, column });
+        }
+    }
+}
+
+fn helper_name(name: &Parameter) -> Option<&str> {
+    match name {
+        Parameter::Name(name) => Some(name),
+        Parameter::Path(VariablePath::Relative((_, raw))) => Some(raw),
+        _ => None,
+    }
+}
+
+/// Keys the variable `raw`, read `depth` blocks below the root of the
+/// context, leads to from the root, unless it is read relative to a block.
+fn resolve(raw: &str, depth: usize) -> Option<Vec<String>> {
+    let rest = match raw.strip_prefix("@root") {
+        Some(rest) => rest.trim_start_matches(['.', '/']),
+        None => {
+            let mut rest = raw;
+            let mut up = 0;
+            while let Some(parent) = rest.strip_prefix("../") {
+                rest = parent;
+                up += 1;
+            }
+            if up < depth {
+                return None;
+            }
+            match rest.strip_prefix("this") {
+                Some(key) if key.is_empty() || key.starts_with(['.', '/']) => key,
+                _ => rest,
+            }
+            .trim_start_matches(['.', '/'])
+        }
+    };
+    Some(
+        rest.split(['.', '/'])
+            .filter(|key| !key.is_empty())
+            .map(|key| {
+                key.trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .to_string()
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn paths(source: &str) -> Vec<(String, usize, usize)> {
+        references(source, &["slugify".to_string()])
+            .unwrap()
+            .into_iter()
+            .map(|reference| (reference.path.join("."), reference.line, reference.column))
+            .collect()
+    }
+
+    #[test]
+    fn references_locate_variables_read_from_the_root() {
+        let source = "export SHELL={{shell}}\n\
+                      {{#if work}}{{secrets.token}}{{else}}{{ personal }}{{/if}}\n\
+                      {{slugify name}} {{lookup system \"os\"}} {{include \"license.txt\"}}\n";
+
+        assert_eq!(
+            paths(source),
+            [
+                ("shell".to_string(), 1, 14),
+                ("work".to_string(), 2, 1),
+                ("secrets.token".to_string(), 2, 13),
+                ("personal".to_string(), 2, 38),
+                ("name".to_string(), 3, 1),
+                ("system".to_string(), 3, 18),
+            ]
+        );
+    }
+
+    #[test]
+    fn references_skip_variables_relative_to_a_block() {
+        let source = "{{#each hosts}}{{name}} {{../domain}} {{@index}}{{/each}}\
+                      {{#with git}}{{email}} {{@root.editor}}{{/with}}{{this}}{{thistle}}";
+
+        assert_eq!(
+            paths(source)
+                .into_iter()
+                .map(|(path, _, _)| path)
+                .collect::<Vec<_>>(),
+            ["hosts", "domain", "git", "editor", "", "thistle"]
+        );
+    }
+
+    #[test]
+    fn is_defined_follows_maps_only() {
+        let context = json!({"git": {"email": "me@example.com"}, "hosts": ["a"]});
+        let path = |path: &str| path.split('.').map(str::to_string).collect::<Vec<_>>();
+
+        assert!(is_defined(&context, &path("git.email")));
+        assert!(is_defined(&context, &path("hosts.0")));
+        assert!(!is_defined(&context, &path("git.name")));
+        assert!(!is_defined(&context, &path("editor")));
+        assert!(is_defined(&context, &[]));
+    }
+
+    #[test]
+    fn references_report_syntax_errors() {
+        assert!(references("{{#if x}}", &[]).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+//! Service applying macOS user defaults (system preferences).
+
+use crate::config::{DefaultsType, MacDefault};
+use crate::errors::Result;
+use crate::infrastructure::command::CommandExecutor;
+
+/// Write every default whose current value differs from the declared one.
+///
+/// Returns one line per changed default describing the current and desired
+/// values; defaults already set to the desired value are left untouched.
+pub fn apply_defaults(
+    defaults: &[MacDefault],
+    executor: &dyn CommandExecutor,
+    dry_run: bool,
+) -> Result<Vec<String>> {
+    let mut changes = Vec::new();
+    for default in defaults {
+        let desired = desired_value(default);
+        let current = executor
+            .output("defaults", &["read", &default.domain, &default.key])
+            .ok()
+            .map(|output| output.trim().to_string())
+            .filter(|output| !output.is_empty());
+        if current.as_deref() == Some(normalized(default, &desired).as_str()) {
+            continue;
+        }
+        changes.push(format!(
+            "{} {}: {} -> {desired}",
+            default.domain,
+            default.key,
+            current.as_deref().unwrap_or("(unset)")
+        ));
+        if dry_run {
+            continue;
+        }
+        executor.run(
+            "defaults",
+            &[
+                "write",
+                &default.domain,
+                &default.key,
+                type_flag(default.kind),
+                &desired,
+            ],
+        )?;
+    }
+    Ok(changes)
+}
+
+fn desired_value(default: &MacDefault) -> String {
+    match &default.value {
+        serde_json::Value::String(value) => value.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Representation printed by `defaults read` for the desired value.
+fn normalized(default: &MacDefault, desired: &str) -> String {
+    match (default.kind, desired) {
+        (DefaultsType::Bool, "true" | "yes" | "YES") => "1".into(),
+        (DefaultsType::Bool, "false" | "no" | "NO") => "0".into(),
+        _ => desired.to_string(),
+    }
+}
+
+fn type_flag(kind: DefaultsType) -> &'static str {
+    match kind {
+        DefaultsType::String => "-string",
+        DefaultsType::Int => "-int",
+        DefaultsType::Float => "-float",
+        DefaultsType::Bool => "-bool",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use serde_json::json;
+
+    fn dock_autohide() -> MacDefault {
+        MacDefault {
+            domain: "com.apple.dock".into(),
+            key: "autohide".into(),
+            kind: DefaultsType::Bool,
+            value: json!(true),
+        }
+    }
+
+    #[test]
+    fn apply_defaults_previews_changes_in_dry_run() {
+        let executor = RecordingCommandExecutor::default()
+            .with_output("defaults read com.apple.dock autohide", "0\n");
+
+        let changes = apply_defaults(&[dock_autohide()], &executor, true).unwrap();
+
+        assert_eq!(changes, vec!["com.apple.dock autohide: 0 -> true"]);
+        assert_eq!(executor.calls().len(), 1, "only the current value is read");
+    }
+
+    #[test]
+    fn apply_defaults_skips_values_already_set() {
+        let executor = RecordingCommandExecutor::default()
+            .with_output("defaults read com.apple.dock autohide", "1\n");
+
+        let changes = apply_defaults(&[dock_autohide()], &executor, false).unwrap();
+
+        assert!(changes.is_empty());
+        assert_eq!(executor.calls().len(), 1);
+    }
+
+    #[test]
+    fn apply_defaults_writes_typed_values() {
+        let executor = RecordingCommandExecutor::default();
+        let default = MacDefault {
+            domain: "NSGlobalDomain".into(),
+            key: "AppleInterfaceStyle".into(),
+            kind: DefaultsType::String,
+            value: json!("Dark"),
+        };
+
+        let changes = apply_defaults(&[default], &executor, false).unwrap();
+
+        assert_eq!(
+            changes,
+            vec!["NSGlobalDomain AppleInterfaceStyle: (unset) -> Dark"]
+        );
+        assert_eq!(
+            executor.calls()[1].1,
+            vec![
+                "write",
+                "NSGlobalDomain",
+                "AppleInterfaceStyle",
+                "-string",
+                "Dark"
+            ]
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! Three-way merge of the edits made to a managed file with the changes of
+//! its template output.
+//!
+//! Lines are matched with a longest common subsequence of the previous
+//! output against each side. Where only one side changed a region its
+//! version is taken; where both changed it differently, both versions are
+//! kept between conflict markers.
+
+use crate::services::diff::common_lines;
+
+/// Marker opening the local side of a conflict.
+pub const LOCAL_MARKER: &str = "<<<<<<< local edits";
+/// Marker separating the two sides of a conflict.
+pub const SEPARATOR: &str = "=======";
+/// Marker closing the template side of a conflict.
+pub const TEMPLATE_MARKER: &str = ">>>>>>> template";
+
+/// Outcome of merging two descendants of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merge {
+    pub text: String,
+    /// Regions both sides changed differently, left between markers.
+    pub conflicts: usize,
+}
+
+/// Merge `local` and `new`, both derived from `base`.
+pub fn merge3(base: &str, local: &str, new: &str) -> Merge {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let local: Vec<&str> = local.split_inclusive('\n').collect();
+    let new: Vec<&str> = new.split_inclusive('\n').collect();
+
+    let mut in_local = vec![None; base.len()];
+    for (b, l) in common_lines(&base, &local) {
+        in_local[b] = Some(l);
+    }
+    let mut in_new = vec![None; base.len()];
+    for (b, n) in common_lines(&base, &new) {
+        in_new[b] = Some(n);
+    }
+    // Base lines kept by both sides anchor the regions between them.
+    let mut anchors: Vec<(usize, usize, usize)> = (0..base.len())
+        .filter_map(|b| Some((b, in_local[b]?, in_new[b]?)))
+        .collect();
+    anchors.push((base.len(), local.len(), new.len()));
+
+    let mut merge = Merge {
+        text: String::new(),
+        conflicts: 0,
+    };
+    let (mut b, mut l, mut n) = (0, 0, 0);
+    for (next_b, next_l, next_n) in anchors {
+        let original = &base[b..next_b];
+        let ours = &local[l..next_l];
+        let theirs = &new[n..next_n];
+        if ours == original || ours == theirs {
+            merge.text.extend(theirs.iter().copied());
+        } else if theirs == original {
+            merge.text.extend(ours.iter().copied());
+        } else {
+            merge.conflicts += 1;
+            for (marker, lines) in [(LOCAL_MARKER, ours), (SEPARATOR, theirs)] {
+                merge.text.push_str(marker);
+                merge.text.push('\n');
+                push_lines(&mut merge.text, lines);
+            }
+            merge.text.push_str(TEMPLATE_MARKER);
+            merge.text.push('\n');
+        }
+        if let Some(line) = base.get(next_b) {
+            merge.text.push_str(line);
+        }
+        (b, l, n) = (next_b + 1, next_l + 1, next_n + 1);
+    }
+    merge
+}
+
+/// Append `lines` to `text`, ending the last one with a newline so a marker
+/// can follow.
+fn push_lines(text: &mut String, lines: &[&str]) {
+    text.extend(lines.iter().copied());
+    if !lines.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "# zshrc\nexport EDITOR=vim\nalias ll='ls -l'\n";
+
+    #[test]
+    fn merge3_combines_changes_to_different_lines() {
+        let local = "# zshrc\nexport EDITOR=vim\nalias ll='ls -l'\nalias gs='git status'\n";
+        let new = "# zshrc\nexport EDITOR=hx\nalias ll='ls -l'\n";
+
+        let merge = merge3(BASE, local, new);
+
+        assert_eq!(
+            merge,
+            Merge {
+                text: "# zshrc\nexport EDITOR=hx\nalias ll='ls -l'\nalias gs='git status'\n".into(),
+                conflicts: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn merge3_keeps_either_side_when_the_other_did_not_change() {
+        let edited = "# zshrc\nexport EDITOR=nano\nalias ll='ls -l'\n";
+
+        assert_eq!(merge3(BASE, edited, BASE).text, edited);
+        assert_eq!(merge3(BASE, BASE, edited).text, edited);
+        assert_eq!(merge3(BASE, edited, edited).text, edited);
+    }
+
+    #[test]
+    fn merge3_marks_conflicting_changes() {
+        let local = "# zshrc\nexport EDITOR=nano\nalias ll='ls -l'\n";
+        let new = "# zshrc\nexport EDITOR=hx\nalias ll='ls -l'\n";
+
+        let merge = merge3(BASE, local, new);
+
+        assert_eq!(merge.conflicts, 1);
+        assert_eq!(
+            merge.text,
+            "# zshrc\n\
+             <<<<<<< local edits\nexport EDITOR=nano\n\
+             =======\nexport EDITOR=hx\n\
+             >>>>>>> template\n\
+             alias ll='ls -l'\n"
+        );
+    }
+}
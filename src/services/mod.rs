@@ -1,5 +1,27 @@
 //! High-level services implementing specific steps of the dotstrap workflow.
 
+pub mod binaries;
 pub mod brew;
+pub mod completions;
+pub mod cron;
+pub mod diff;
+pub mod downloads;
+pub mod gnome;
+pub mod helpers;
+pub mod hooks;
 pub mod linker;
+pub mod lint;
+pub mod macos;
+pub mod merge;
+pub mod notify;
+pub mod outcome;
+pub mod packages;
+pub mod plugins;
+pub mod questions;
+pub mod readd;
+pub mod repos;
+pub mod rollback;
+pub mod schedule;
+pub mod snapshot;
+pub mod ssh;
 pub mod templating;
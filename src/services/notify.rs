@@ -0,0 +1,95 @@
+//! Service sending a desktop notification when a run finishes, see
+//! `--notify`.
+
+use crate::errors::Result;
+use crate::infrastructure::command::CommandExecutor;
+
+/// Show a desktop notification titled `title` on `os`: through
+/// `terminal-notifier`, or else `osascript`, on macOS, and `notify-send` on
+/// other Unix hosts.
+///
+/// dotstrap knows no notifier on Windows, where nothing is shown.
+pub fn notify(os: &str, title: &str, message: &str, executor: &dyn CommandExecutor) -> Result<()> {
+    match os {
+        "macos" => executor
+            .run(
+                "terminal-notifier",
+                &["-title", title, "-message", message, "-group", "dotstrap"],
+            )
+            .or_else(|_| {
+                let script = format!(
+                    "display notification {} with title {}",
+                    applescript_string(message),
+                    applescript_string(title)
+                );
+                executor.run("osascript", &["-e", &script])
+            }),
+        "windows" => Ok(()),
+        _ => executor.run("notify-send", &["--app-name=dotstrap", title, message]),
+    }
+}
+
+/// `text` as an AppleScript string literal.
+fn applescript_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+
+    #[test]
+    fn notify_uses_the_notifier_of_the_host() {
+        let executor = RecordingCommandExecutor::default();
+
+        notify("linux", "dotstrap finished", "Linked 3 file(s)", &executor).unwrap();
+        notify(
+            "windows",
+            "dotstrap finished",
+            "Linked 3 file(s)",
+            &executor,
+        )
+        .unwrap();
+
+        assert_eq!(
+            executor.calls(),
+            [(
+                "notify-send".to_string(),
+                vec![
+                    "--app-name=dotstrap".to_string(),
+                    "dotstrap finished".to_string(),
+                    "Linked 3 file(s)".to_string()
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn notify_falls_back_to_osascript_without_terminal_notifier() {
+        let executor = RecordingCommandExecutor::with_failure("terminal-notifier");
+
+        notify(
+            "macos",
+            "dotstrap failed",
+            "source \"x\" not found",
+            &executor,
+        )
+        .unwrap();
+
+        let calls = executor.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].0, "terminal-notifier");
+        assert_eq!(
+            calls[1],
+            (
+                "osascript".to_string(),
+                vec![
+                    "-e".to_string(),
+                    r#"display notification "source \"x\" not found" with title "dotstrap failed""#
+                        .to_string()
+                ]
+            )
+        );
+    }
+}
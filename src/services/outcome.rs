@@ -0,0 +1,79 @@
+//! Per-item outcomes reported by the services.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// What a run did, or would do in dry-run mode, to an individual item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemStatus {
+    Created,
+    Updated,
+    Unchanged,
+    Skipped,
+}
+
+/// How a path is placed into the home directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStrategy {
+    /// A symbolic link to the staged file.
+    #[default]
+    Symlink,
+    /// A copy of the staged file, where symbolic links are not permitted.
+    Copy,
+    /// A junction to the staged directory, where symbolic links are not
+    /// permitted (Windows).
+    Junction,
+}
+
+impl LinkStrategy {
+    pub fn is_symlink(&self) -> bool {
+        *self == LinkStrategy::Symlink
+    }
+}
+
+/// Path linked into the home directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkOutcome {
+    pub path: PathBuf,
+    pub status: ItemStatus,
+    /// How the path was placed, when not as a symbolic link.
+    #[serde(default, skip_serializing_if = "LinkStrategy::is_symlink")]
+    pub strategy: LinkStrategy,
+    /// Backup of the file previously found at `path`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<PathBuf>,
+    /// Target of the link previously found at `path`, or a copy of the
+    /// contents it resolved to before they changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<PathBuf>,
+    /// Hex encoded SHA-256 digest of a copy placed at `path`, telling later
+    /// edits apart from the copy itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// Why the path has its status, e.g. `destination missing`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl LinkOutcome {
+    pub fn new(path: PathBuf, status: ItemStatus) -> Self {
+        LinkOutcome {
+            path,
+            status,
+            strategy: LinkStrategy::Symlink,
+            backup: None,
+            previous: None,
+            digest: None,
+            reason: None,
+        }
+    }
+
+    /// The outcome with `reason` explaining its status.
+    pub fn because(mut self, reason: &str) -> Self {
+        self.reason = Some(reason.to_string());
+        self
+    }
+}
@@ -0,0 +1,885 @@
+//! Package provider abstraction and the registry that selects the providers
+//! available on the current host.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::config::{self, PackagesSpec};
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::{CommandExecutor, Invocation};
+use crate::infrastructure::escalation::Escalation;
+use crate::services::brew::BrewProvider;
+use crate::services::outcome::ItemStatus;
+use crate::services::snapshot;
+
+/// A single command planned by a package provider.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedCommand {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Whether the command needs root privileges, granted according to the
+    /// run's [`Escalation`] policy.
+    pub privileged: bool,
+    /// Variables set on top of the inherited environment.
+    pub env: Vec<(String, String)>,
+}
+
+impl PlannedCommand {
+    pub fn new(program: &str, args: &[&str]) -> Self {
+        PlannedCommand {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            privileged: false,
+            env: Vec::new(),
+        }
+    }
+
+    /// A command that must run as root.
+    pub fn privileged(program: &str, args: &[&str]) -> Self {
+        PlannedCommand {
+            privileged: true,
+            ..PlannedCommand::new(program, args)
+        }
+    }
+
+    /// The command as it runs under `escalation`: privileged commands are
+    /// prefixed with `sudo` or `doas`, the others are left alone.
+    pub fn escalate(self, escalation: Escalation) -> Self {
+        if !self.privileged {
+            return self;
+        }
+        let (program, args) = escalation.elevate(&self.program, &self.args);
+        PlannedCommand {
+            program,
+            args,
+            privileged: false,
+            env: self.env,
+        }
+    }
+
+    /// The command with the environment variables of `env` set.
+    pub fn with_env<'a>(mut self, env: impl IntoIterator<Item = (&'a String, &'a String)>) -> Self {
+        self.env.extend(
+            env.into_iter()
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+        self
+    }
+
+    /// The command as run by [`CommandExecutor::run_invocation`].
+    pub fn invocation(&self) -> Invocation {
+        Invocation {
+            program: self.program.clone(),
+            args: self.args.clone(),
+            env: self.env.clone(),
+            ..Invocation::default()
+        }
+    }
+
+    /// Execute independent commands with at most `jobs` of them at once,
+    /// failing with the error of the first command that failed.
+    pub fn run_concurrently(
+        commands: &[PlannedCommand],
+        executor: &dyn CommandExecutor,
+        jobs: usize,
+    ) -> Result<()> {
+        let commands: Vec<Invocation> = commands.iter().map(PlannedCommand::invocation).collect();
+        executor
+            .run_concurrently(&commands, jobs)
+            .into_iter()
+            .collect()
+    }
+
+    /// Execute the command through the provided executor.
+    pub fn run(&self, executor: &dyn CommandExecutor) -> Result<()> {
+        if self.env.is_empty() {
+            let args: Vec<&str> = self.args.iter().map(String::as_str).collect();
+            return executor.run(&self.program, &args);
+        }
+        executor.run_invocation(&self.invocation())
+    }
+}
+
+/// The command as a shell would run it, its environment variables first.
+impl fmt::Display for PlannedCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.env {
+            write!(f, "{key}={value} ")?;
+        }
+        write!(f, "{} {}", self.program, self.args.join(" "))
+    }
+}
+
+/// A package manager able to install its section of the package specification.
+pub trait PackageProvider {
+    /// Section name used for this provider in `packages.yaml`.
+    fn name(&self) -> &str;
+
+    /// Whether the provider can be used on the given operating system.
+    fn supports(&self, _os: &str) -> bool {
+        true
+    }
+
+    /// Whether a declared section must fail the run, rather than be skipped,
+    /// when the provider is unavailable on the given operating system.
+    fn is_required(&self, _os: &str) -> bool {
+        false
+    }
+
+    /// Whether the provider's tooling is installed on the host.
+    fn is_available(&self, executor: &dyn CommandExecutor) -> bool;
+
+    /// Commands required to install the provider's section of `spec`.
+    fn plan(&self, spec: &PackagesSpec) -> Vec<PlannedCommand>;
+
+    /// Why `command` of the plan is run, as shown by `--explain`.
+    fn reason(&self, _command: &PlannedCommand) -> String {
+        format!("declared in the {} section", self.name())
+    }
+
+    /// Execute a plan previously produced by [`PackageProvider::plan`].
+    fn install(&self, plan: &[PlannedCommand], executor: &dyn CommandExecutor) -> Result<()> {
+        plan.iter().try_for_each(|command| command.run(executor))
+    }
+
+    /// Execute a plan with up to `jobs` of its commands running at once.
+    ///
+    /// The commands of most managers contend for a lock, so the default runs
+    /// the plan in order through [`PackageProvider::install`].
+    fn install_concurrently(
+        &self,
+        plan: &[PlannedCommand],
+        executor: &dyn CommandExecutor,
+        _jobs: usize,
+    ) -> Result<()> {
+        self.install(plan, executor)
+    }
+
+    /// Commands installing the provider itself on a host where it is missing.
+    fn bootstrap(&self) -> Vec<PlannedCommand> {
+        Vec::new()
+    }
+
+    /// Provider to use once the commands of [`PackageProvider::bootstrap`]
+    /// ran, when the tool they installed must be invoked differently, or
+    /// `None` to keep using this provider.
+    fn bootstrapped(&self) -> Option<Box<dyn PackageProvider>> {
+        None
+    }
+
+    /// Error reported when a required provider is unavailable.
+    fn unavailable_error(&self) -> DotstrapError {
+        DotstrapError::PackageManagerUnavailable(self.name().to_string())
+    }
+}
+
+/// Provider for managers whose section is a flat list of package names
+/// installed one at a time.
+pub struct ListProvider {
+    name: &'static str,
+    program: &'static str,
+    install: &'static [&'static str],
+    /// Whether installing needs root privileges.
+    privileged: bool,
+    systems: &'static [&'static str],
+    required_on: Option<&'static str>,
+    section: fn(&PackagesSpec) -> &[String],
+}
+
+impl ListProvider {
+    pub fn apt() -> Self {
+        ListProvider {
+            name: "apt",
+            program: "apt-get",
+            install: &["apt-get", "install", "-y"],
+            privileged: true,
+            systems: &["linux"],
+            required_on: None,
+            section: |spec| &spec.apt,
+        }
+    }
+
+    pub fn pacman() -> Self {
+        ListProvider {
+            name: "pacman",
+            program: "pacman",
+            install: &["pacman", "-S", "--needed", "--noconfirm"],
+            privileged: true,
+            systems: &["linux"],
+            required_on: None,
+            section: |spec| &spec.pacman,
+        }
+    }
+
+    pub fn dnf() -> Self {
+        ListProvider {
+            name: "dnf",
+            program: "dnf",
+            install: &["dnf", "install", "-y"],
+            privileged: true,
+            systems: &["linux"],
+            required_on: None,
+            section: |spec| &spec.dnf,
+        }
+    }
+
+    pub fn cargo() -> Self {
+        ListProvider {
+            name: "cargo",
+            program: "cargo",
+            install: &["cargo", "install"],
+            privileged: false,
+            systems: &[],
+            required_on: None,
+            section: |spec| &spec.cargo,
+        }
+    }
+
+    pub fn winget() -> Self {
+        ListProvider {
+            name: "winget",
+            program: "winget",
+            install: &["winget", "install", "--exact", "--silent", "--id"],
+            privileged: false,
+            systems: &["windows"],
+            required_on: Some("windows"),
+            section: |spec| &spec.winget,
+        }
+    }
+}
+
+impl PackageProvider for ListProvider {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn supports(&self, os: &str) -> bool {
+        self.systems.is_empty() || self.systems.contains(&os)
+    }
+
+    fn is_required(&self, os: &str) -> bool {
+        self.required_on == Some(os)
+    }
+
+    fn is_available(&self, executor: &dyn CommandExecutor) -> bool {
+        executor.is_available(self.program)
+    }
+
+    fn plan(&self, spec: &PackagesSpec) -> Vec<PlannedCommand> {
+        let (program, base) = self
+            .install
+            .split_first()
+            .expect("install command must not be empty");
+        (self.section)(spec)
+            .iter()
+            .map(|package| {
+                let mut args = base.to_vec();
+                args.push(package);
+                if self.privileged {
+                    PlannedCommand::privileged(program, &args)
+                } else {
+                    PlannedCommand::new(program, &args)
+                }
+            })
+            .collect()
+    }
+}
+
+/// Ordered collection of package providers consulted during installation.
+pub struct ProviderRegistry {
+    providers: Vec<Box<dyn PackageProvider>>,
+}
+
+impl ProviderRegistry {
+    /// Create a registry without any provider.
+    pub fn empty() -> Self {
+        ProviderRegistry {
+            providers: Vec::new(),
+        }
+    }
+
+    /// Append a provider, consulted after the ones already registered.
+    pub fn register<P>(&mut self, provider: P)
+    where
+        P: PackageProvider + 'static,
+    {
+        self.providers.push(Box::new(provider));
+    }
+
+    /// Registered providers in installation order.
+    pub fn providers(&self) -> impl Iterator<Item = &dyn PackageProvider> {
+        self.providers.iter().map(|provider| provider.as_ref())
+    }
+}
+
+impl ProviderRegistry {
+    /// Registry containing every built-in provider, using the given Homebrew
+    /// provider.
+    pub fn builtin(brew: BrewProvider) -> Self {
+        let mut registry = ProviderRegistry::empty();
+        registry.register(brew);
+        registry.register(ListProvider::apt());
+        registry.register(ListProvider::pacman());
+        registry.register(ListProvider::dnf());
+        registry.register(ListProvider::cargo());
+        registry.register(ListProvider::winget());
+        registry
+    }
+}
+
+impl Default for ProviderRegistry {
+    /// Registry containing every built-in provider, with Homebrew located on
+    /// `PATH` or in one of its well-known prefixes.
+    fn default() -> Self {
+        ProviderRegistry::builtin(BrewProvider::detect())
+    }
+}
+
+/// Commands and warnings produced while installing packages.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PackageOutcome {
+    pub commands: Vec<String>,
+    pub warnings: Vec<String>,
+    /// Names of the providers whose sections were installed.
+    pub providers: Vec<String>,
+    /// Outcome of every provider with a non-empty section.
+    pub reports: Vec<ProviderReport>,
+    /// Why each of `commands` is run, keyed by the command.
+    pub reasons: BTreeMap<String, String>,
+}
+
+/// What happened to the section of a single package provider.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProviderReport {
+    pub provider: String,
+    /// `updated` when its commands ran (or are planned), `skipped` when the
+    /// provider is not available on this host.
+    pub status: ItemStatus,
+    pub commands: Vec<String>,
+}
+
+/// Options controlling how package sections are installed.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct InstallOptions {
+    /// Record the commands without executing them.
+    pub dry_run: bool,
+    /// Install missing providers that know how to bootstrap themselves.
+    pub bootstrap: bool,
+    /// Packages installed at once by providers supporting it; one at a time
+    /// when 0 or 1.
+    pub jobs: usize,
+    /// How the commands needing root privileges are run.
+    pub escalation: Escalation,
+}
+
+/// Whether installing `spec` on `os` runs commands needing root privileges.
+pub fn needs_escalation(os: &str, spec: &PackagesSpec, registry: &ProviderRegistry) -> bool {
+    registry
+        .providers()
+        .filter(|provider| provider.supports(os))
+        .any(|provider| provider.plan(spec).iter().any(|command| command.privileged))
+}
+
+/// Install every section of `spec` whose provider is available on the host.
+pub fn install_packages(
+    spec: &PackagesSpec,
+    registry: &ProviderRegistry,
+    executor: &dyn CommandExecutor,
+    options: InstallOptions,
+) -> Result<PackageOutcome> {
+    install_packages_on(std::env::consts::OS, spec, registry, executor, options)
+}
+
+/// Install packages as if running on `os`.
+///
+/// Sections whose provider is missing are bootstrapped when allowed, and
+/// otherwise skipped with a warning, except when the provider is required on
+/// `os`, in which case the run fails.
+pub fn install_packages_on(
+    os: &str,
+    spec: &PackagesSpec,
+    registry: &ProviderRegistry,
+    executor: &dyn CommandExecutor,
+    options: InstallOptions,
+) -> Result<PackageOutcome> {
+    let mut outcome = PackageOutcome::default();
+    let escalate = |plan: Vec<PlannedCommand>| -> Vec<PlannedCommand> {
+        plan.into_iter()
+            .map(|command| command.escalate(options.escalation))
+            .collect()
+    };
+    let planned = |provider: &dyn PackageProvider| {
+        let plan = provider.plan(spec);
+        let reasons: Vec<String> = plan
+            .iter()
+            .map(|command| provider.reason(command))
+            .collect();
+        (escalate(plan), reasons)
+    };
+    outcome.warnings.extend(unknown_sections(spec, registry));
+    for provider in registry.providers() {
+        let (mut plan, mut reasons) = planned(provider);
+        if plan.is_empty() {
+            continue;
+        }
+        let mut located = None;
+        let mut commands = Vec::new();
+        let mut available = provider.supports(os) && provider.is_available(executor);
+        if !available && options.bootstrap && provider.supports(os) {
+            let bootstrap = escalate(provider.bootstrap());
+            if !bootstrap.is_empty() {
+                for command in &bootstrap {
+                    outcome.reasons.insert(
+                        command.to_string(),
+                        format!("{} is not installed", provider.name()),
+                    );
+                }
+                commands.extend(bootstrap.iter().map(PlannedCommand::to_string));
+                if !options.dry_run {
+                    bootstrap
+                        .iter()
+                        .try_for_each(|command| command.run(executor))?;
+                    located = provider.bootstrapped();
+                    let installed = located.as_deref().unwrap_or(provider);
+                    if !installed.is_available(executor) {
+                        return Err(DotstrapError::BootstrapFailed(provider.name().to_string()));
+                    }
+                }
+                available = true;
+            }
+        }
+        let provider = located.as_deref().unwrap_or(provider);
+        if located.is_some() {
+            (plan, reasons) = planned(provider);
+        }
+        if !available {
+            if provider.is_required(os) {
+                return Err(provider.unavailable_error());
+            }
+            outcome.warnings.push(format!(
+                "skipping {name} packages: {name} is not available on this host",
+                name = provider.name()
+            ));
+            outcome.reports.push(ProviderReport {
+                provider: provider.name().to_string(),
+                status: ItemStatus::Skipped,
+                commands: Vec::new(),
+            });
+            continue;
+        }
+        commands.extend(plan.iter().map(PlannedCommand::to_string));
+        for (command, reason) in plan.iter().zip(reasons) {
+            outcome.reasons.insert(command.to_string(), reason);
+        }
+        outcome.commands.extend(commands.iter().cloned());
+        outcome.providers.push(provider.name().to_string());
+        outcome.reports.push(ProviderReport {
+            provider: provider.name().to_string(),
+            status: ItemStatus::Updated,
+            commands,
+        });
+        if options.dry_run {
+            continue;
+        }
+        if options.jobs > 1 {
+            provider.install_concurrently(&plan, executor, options.jobs)?;
+        } else {
+            provider.install(&plan, executor)?;
+        }
+    }
+    Ok(outcome)
+}
+
+/// Warnings for the sections of `spec` no provider of `registry` handles,
+/// such as misspelled package managers, whose packages would never be
+/// installed.
+fn unknown_sections(spec: &PackagesSpec, registry: &ProviderRegistry) -> Vec<String> {
+    let names: Vec<&str> = registry
+        .providers()
+        .map(|provider| provider.name())
+        .collect();
+    spec.extra
+        .keys()
+        .filter(|section| !names.contains(&section.as_str()))
+        .map(|section| {
+            let hint = config::suggest_name(section, names.iter().copied())
+                .map(|name| format!(" (did you mean `{name}`?)"))
+                .unwrap_or_default();
+            format!(
+                "ignoring the `{section}` package section: no package provider handles it{hint}"
+            )
+        })
+        .collect()
+}
+
+/// Check, without installing anything, that the packages of `spec` are
+/// already installed on `os`, as offline runs cannot install them.
+///
+/// Returns the declared packages that are missing, and warnings naming the
+/// sections whose installed packages cannot be listed.
+pub fn missing_packages_on(
+    os: &str,
+    spec: &PackagesSpec,
+    executor: &dyn CommandExecutor,
+) -> (Vec<String>, Vec<String>) {
+    let (installed, mut warnings) = snapshot::every_installed_package(os, executor);
+    // Tapped formulae and casks are listed without their tap.
+    let short = |name: &str| name.rsplit('/').next().unwrap_or(name).to_string();
+    let declared: [(&[&str], Vec<String>); 6] = [
+        (
+            &["brew", "formulae"],
+            spec.brew
+                .formulae
+                .iter()
+                .map(|formula| short(&formula.installed_name()))
+                .collect(),
+        ),
+        (
+            &["brew", "casks"],
+            spec.brew.casks.iter().map(|cask| short(cask)).collect(),
+        ),
+        (&["apt"], spec.apt.clone()),
+        (&["pacman"], spec.pacman.clone()),
+        (&["dnf"], spec.dnf.clone()),
+        (&["cargo"], spec.cargo.clone()),
+    ];
+    let mut missing = Vec::new();
+    for (path, packages) in declared {
+        // Managers missing from the host are skipped as they are online.
+        let Some(section) = installed.iter().find(|section| section.path == path) else {
+            continue;
+        };
+        missing.extend(
+            packages
+                .into_iter()
+                .filter(|package| !section.packages.contains(package)),
+        );
+    }
+    let unchecked: Vec<&str> = [
+        ("brew.mas", !spec.brew.mas.is_empty()),
+        ("winget", !spec.winget.is_empty()),
+    ]
+    .into_iter()
+    .filter_map(|(name, declared)| declared.then_some(name))
+    .chain(spec.extra.keys().map(String::as_str))
+    .collect();
+    if !unchecked.is_empty() {
+        warnings.push(format!(
+            "left the {} package section(s) unchecked offline",
+            unchecked.join(", ")
+        ));
+    }
+    (missing, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BrewSpec, MasApp};
+    use crate::infrastructure::command::RecordingCommandExecutor;
+
+    fn builtin() -> ProviderRegistry {
+        ProviderRegistry::builtin(BrewProvider::default())
+    }
+
+    const DRY_RUN: InstallOptions = InstallOptions {
+        dry_run: true,
+        bootstrap: false,
+        jobs: 1,
+        escalation: Escalation::Sudo,
+    };
+
+    struct NixProvider;
+
+    impl PackageProvider for NixProvider {
+        fn name(&self) -> &str {
+            "nix"
+        }
+
+        fn is_available(&self, _executor: &dyn CommandExecutor) -> bool {
+            true
+        }
+
+        fn plan(&self, spec: &PackagesSpec) -> Vec<PlannedCommand> {
+            spec.extra
+                .get("nix")
+                .and_then(|value| value.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|value| value.as_str())
+                .map(|package| PlannedCommand::new("nix", &["profile", "install", package]))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn install_packages_runs_available_sections_and_warns_for_missing() {
+        let executor = RecordingCommandExecutor::with_failure("pacman");
+        let spec = PackagesSpec {
+            apt: vec!["ripgrep".into()],
+            pacman: vec!["ripgrep".into()],
+            cargo: vec!["bat".into()],
+            ..PackagesSpec::default()
+        };
+
+        let outcome = install_packages_on("linux", &spec, &builtin(), &executor, DRY_RUN)
+            .expect("package installation should succeed");
+
+        assert_eq!(
+            outcome.commands,
+            vec![
+                "sudo apt-get install -y ripgrep".to_string(),
+                "cargo install bat".to_string(),
+            ]
+        );
+        assert_eq!(
+            outcome.warnings,
+            vec!["skipping pacman packages: pacman is not available on this host".to_string()]
+        );
+        assert_eq!(outcome.providers, vec!["apt", "cargo"]);
+        let probed: Vec<String> = executor.calls().into_iter().map(|(p, _)| p).collect();
+        assert_eq!(
+            probed,
+            vec!["apt-get", "pacman", "cargo"],
+            "dry run only probes"
+        );
+    }
+
+    #[test]
+    fn install_packages_elevates_privileged_commands_per_policy() {
+        let executor = RecordingCommandExecutor::default();
+        let spec = PackagesSpec {
+            dnf: vec!["ripgrep".into()],
+            cargo: vec!["bat".into()],
+            ..PackagesSpec::default()
+        };
+        let options = InstallOptions {
+            escalation: Escalation::Doas,
+            ..InstallOptions::default()
+        };
+
+        let outcome = install_packages_on("linux", &spec, &builtin(), &executor, options)
+            .expect("package installation should succeed");
+
+        assert_eq!(
+            outcome.commands,
+            vec!["doas dnf install -y ripgrep", "cargo install bat"]
+        );
+        assert!(needs_escalation("linux", &spec, &builtin()));
+        let cargo_only = PackagesSpec {
+            cargo: vec!["bat".into()],
+            ..PackagesSpec::default()
+        };
+        assert!(!needs_escalation("linux", &cargo_only, &builtin()));
+        assert!(!needs_escalation("macos", &spec, &builtin()));
+    }
+
+    #[test]
+    fn install_packages_skips_unsupported_os_without_probing() {
+        let executor = RecordingCommandExecutor::default();
+        let spec = PackagesSpec {
+            winget: vec!["Git.Git".into()],
+            ..PackagesSpec::default()
+        };
+
+        let outcome = install_packages_on("linux", &spec, &builtin(), &executor, DRY_RUN)
+            .expect("unsupported provider should be skipped");
+
+        assert!(outcome.commands.is_empty());
+        assert_eq!(outcome.warnings.len(), 1);
+        assert!(executor.calls().is_empty());
+    }
+
+    #[test]
+    fn install_packages_requires_brew_on_macos() {
+        let executor = RecordingCommandExecutor::with_failure("brew");
+        let spec = PackagesSpec {
+            brew: BrewSpec {
+                formulae: vec!["fzf".into()],
+                ..BrewSpec::default()
+            },
+            ..PackagesSpec::default()
+        };
+
+        let error = install_packages_on("macos", &spec, &builtin(), &executor, DRY_RUN)
+            .expect_err("brew is mandatory on macOS");
+
+        assert!(matches!(error, DotstrapError::BrewUnavailable));
+    }
+
+    #[test]
+    fn install_packages_requires_winget_on_windows() {
+        let executor = RecordingCommandExecutor::with_failure("winget");
+        let spec = PackagesSpec {
+            winget: vec!["Git.Git".into()],
+            ..PackagesSpec::default()
+        };
+
+        let error = install_packages_on("windows", &spec, &builtin(), &executor, DRY_RUN)
+            .expect_err("winget is mandatory on Windows");
+
+        assert!(
+            matches!(error, DotstrapError::PackageManagerUnavailable(name) if name == "winget")
+        );
+    }
+
+    #[test]
+    fn install_packages_warns_for_sections_no_provider_handles() {
+        let executor = RecordingCommandExecutor::default();
+        let spec: PackagesSpec =
+            serde_yaml::from_str("aptt:\n  - ripgrep\nnix:\n  - bat\n").expect("spec should parse");
+        let mut registry = builtin();
+        registry.register(NixProvider);
+
+        let outcome = install_packages_on("linux", &spec, &registry, &executor, DRY_RUN)
+            .expect("package installation should succeed");
+
+        assert_eq!(
+            outcome.warnings,
+            vec![
+                "ignoring the `aptt` package section: no package provider handles it \
+                 (did you mean `apt`?)"
+                    .to_string()
+            ]
+        );
+        assert_eq!(outcome.commands, vec!["nix profile install bat"]);
+    }
+
+    #[test]
+    fn registry_accepts_third_party_providers() {
+        let executor = RecordingCommandExecutor::default();
+        let spec: PackagesSpec =
+            serde_yaml::from_str("nix:\n  - ripgrep\n").expect("spec should parse");
+        let mut registry = ProviderRegistry::empty();
+        registry.register(NixProvider);
+
+        let outcome = install_packages_on(
+            "linux",
+            &spec,
+            &registry,
+            &executor,
+            InstallOptions::default(),
+        )
+        .expect("custom provider should install");
+
+        assert_eq!(outcome.commands, vec!["nix profile install ripgrep"]);
+        assert_eq!(
+            executor.calls(),
+            vec![(
+                "nix".to_string(),
+                vec![
+                    "profile".to_string(),
+                    "install".to_string(),
+                    "ripgrep".to_string()
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn install_packages_bootstraps_missing_brew_when_allowed() {
+        let executor = RecordingCommandExecutor::with_failure("brew");
+        let spec = PackagesSpec {
+            brew: BrewSpec {
+                formulae: vec!["fzf".into()],
+                skip_update: true,
+                ..BrewSpec::default()
+            },
+            ..PackagesSpec::default()
+        };
+        let options = InstallOptions {
+            dry_run: true,
+            bootstrap: true,
+            jobs: 1,
+            escalation: Escalation::Sudo,
+        };
+
+        let outcome = install_packages_on("macos", &spec, &builtin(), &executor, options)
+            .expect("brew should be bootstrapped instead of failing");
+
+        assert_eq!(outcome.commands.len(), 2);
+        assert!(outcome.commands[0].contains("Homebrew/install/HEAD/install.sh"));
+        assert_eq!(outcome.commands[1], "brew install fzf");
+        assert_eq!(outcome.providers, vec!["brew"]);
+    }
+
+    #[test]
+    fn install_packages_invokes_bootstrapped_brew_from_its_prefix() {
+        let prefix = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(prefix.path().join("bin")).unwrap();
+        std::fs::write(prefix.path().join("bin/brew"), "").unwrap();
+        let brew = prefix
+            .path()
+            .join("bin/brew")
+            .to_string_lossy()
+            .into_owned();
+        let spec = PackagesSpec {
+            brew: BrewSpec {
+                formulae: vec!["fzf".into()],
+                skip_update: true,
+                ..BrewSpec::default()
+            },
+            ..PackagesSpec::default()
+        };
+        let options = InstallOptions {
+            dry_run: false,
+            bootstrap: true,
+            jobs: 1,
+            escalation: Escalation::Sudo,
+        };
+        let registry = |prefixes: Vec<std::path::PathBuf>| {
+            let mut registry = ProviderRegistry::empty();
+            registry.register(BrewProvider::default().with_prefixes(prefixes));
+            registry
+        };
+        // `brew` is not on `PATH`, only in the prefix the installer created.
+        let executor = RecordingCommandExecutor::with_failure("brew");
+
+        let outcome = install_packages_on(
+            "macos",
+            &spec,
+            &registry(vec![prefix.path().to_path_buf()]),
+            &executor,
+            options,
+        )
+        .expect("the bootstrapped brew should be found in its prefix");
+
+        assert_eq!(outcome.commands[1], format!("{brew} install fzf"));
+        let calls = executor.calls();
+        assert_eq!(calls.last().unwrap().0, brew);
+        assert_eq!(calls.last().unwrap().1, vec!["install", "fzf"]);
+
+        let err = install_packages_on("macos", &spec, &registry(Vec::new()), &executor, options)
+            .expect_err("brew cannot be found after installing it");
+        assert!(matches!(err, DotstrapError::BootstrapFailed(name) if name == "brew"));
+    }
+
+    #[test]
+    fn missing_packages_on_compares_declared_and_installed_packages() {
+        let spec = PackagesSpec {
+            brew: BrewSpec {
+                formulae: vec!["fzf".into(), "user/tap/tool".into()],
+                mas: vec![MasApp {
+                    name: "Xcode".into(),
+                    id: 497799835,
+                }],
+                ..BrewSpec::default()
+            },
+            apt: vec!["git".into()],
+            ..PackagesSpec::default()
+        };
+        let executor = RecordingCommandExecutor::default()
+            .with_output("brew list --formula -1", "fzf\ntool\n")
+            .with_output("dpkg-query --show --showformat ${Package}\n", "curl\n");
+
+        let (missing, warnings) = missing_packages_on("linux", &spec, &executor);
+
+        assert_eq!(missing, ["git"]);
+        assert_eq!(
+            warnings,
+            ["left the brew.mas package section(s) unchecked offline"]
+        );
+    }
+}
@@ -0,0 +1,201 @@
+//! Template helpers served by commands of the repository, declared under
+//! `plugins` in the manifest.
+//!
+//! A plugin is called with its `args` followed by a JSON document holding the
+//! helper's positional `params` and named `hash` arguments, and prints the
+//! helper's output on stdout. It runs from an empty scratch directory with
+//! only `PATH` in its environment, and is killed after
+//! [`PLUGIN_TIMEOUT`].
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, RenderContext, RenderError, RenderErrorReason,
+    ScopedJson,
+};
+use serde_json::{Map, Value, json};
+
+use crate::config::PluginSpec;
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::capture;
+
+/// Time after which a plugin still running is killed.
+pub const PLUGIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Fail unless the run allows `plugins` to execute, see `--allow-plugins`.
+pub fn ensure_allowed(plugins: &[PluginSpec], allowed: bool) -> Result<()> {
+    if allowed || plugins.is_empty() {
+        return Ok(());
+    }
+    let names: Vec<&str> = plugins.iter().map(|plugin| plugin.name.as_str()).collect();
+    Err(DotstrapError::PluginsNotAllowed {
+        names: names.join(", "),
+    })
+}
+
+/// Register `plugins`, whose commands are relative to `repo`, as helpers of
+/// `engine`, running them from `scratch`.
+pub fn register_plugins(
+    engine: &mut Handlebars<'_>,
+    repo: &Path,
+    plugins: &[PluginSpec],
+    scratch: &Path,
+) {
+    for plugin in plugins {
+        engine.register_helper(
+            &plugin.name,
+            Box::new(CommandHelper {
+                program: repo.join(&plugin.command),
+                args: plugin.args.clone(),
+                scratch: scratch.to_path_buf(),
+            }),
+        );
+    }
+}
+
+/// Helper rendering the output of a plugin command.
+struct CommandHelper {
+    program: PathBuf,
+    args: Vec<String>,
+    scratch: PathBuf,
+}
+
+impl CommandHelper {
+    /// Output of the plugin called with `call`, without its final newline.
+    fn invoke(&self, call: &Value) -> Result<String> {
+        let program = self.program.display().to_string();
+        tracing::debug!("$ {program} {}", self.args.join(" "));
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .arg(call.to_string())
+            .current_dir(&self.scratch)
+            .env_clear();
+        if let Some(path) = std::env::var_os("PATH") {
+            command.env("PATH", path);
+        }
+        let (status, stdout, stderr) = capture(&mut command, &program, Some(PLUGIN_TIMEOUT))?;
+        if !status.success() {
+            let stderr = stderr.trim();
+            if !stderr.is_empty() {
+                tracing::warn!("[{program}] {stderr}");
+            }
+            return Err(DotstrapError::CommandFailed {
+                program,
+                status: status.code().unwrap_or(-1),
+            });
+        }
+        Ok(stdout
+            .strip_suffix('\n')
+            .map(|out| out.strip_suffix('\r').unwrap_or(out))
+            .unwrap_or(&stdout)
+            .to_string())
+    }
+}
+
+impl HelperDef for CommandHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> std::result::Result<ScopedJson<'rc>, RenderError> {
+        let params: Vec<Value> = helper
+            .params()
+            .iter()
+            .map(|param| param.value().clone())
+            .collect();
+        let hash: Map<String, Value> = helper
+            .hash()
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.value().clone()))
+            .collect();
+        let output = self
+            .invoke(&json!({ "params": params, "hash": hash }))
+            .map_err(|err| {
+                RenderErrorReason::Other(format!("plugin `{}` failed: {err}", helper.name()))
+            })?;
+        Ok(ScopedJson::Derived(Value::String(output)))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    fn plugin(repo: &Path, name: &str, script: &str) -> PluginSpec {
+        let command = PathBuf::from(format!("plugins/{name}.sh"));
+        std::fs::create_dir_all(repo.join("plugins")).unwrap();
+        std::fs::write(repo.join(&command), format!("#!/bin/sh\n{script}")).unwrap();
+        std::fs::set_permissions(repo.join(&command), std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+        PluginSpec {
+            name: name.into(),
+            command,
+            args: vec!["--call".into()],
+        }
+    }
+
+    #[test]
+    fn ensure_allowed_requires_the_opt_in_for_declared_plugins() {
+        let plugins = vec![
+            PluginSpec {
+                name: "slugify".into(),
+                command: "a".into(),
+                args: Vec::new(),
+            },
+            PluginSpec {
+                name: "vault".into(),
+                command: "b".into(),
+                args: Vec::new(),
+            },
+        ];
+
+        assert!(ensure_allowed(&[], false).is_ok());
+        assert!(ensure_allowed(&plugins, true).is_ok());
+        assert_eq!(
+            ensure_allowed(&plugins, false).unwrap_err().to_string(),
+            "the manifest declares template plugins (slugify, vault); pass --allow-plugins to run them"
+        );
+    }
+
+    #[test]
+    fn plugins_render_their_output_from_a_scrubbed_environment() {
+        let repo = TempDir::new().unwrap();
+        let scratch = TempDir::new().unwrap();
+        let plugins = vec![
+            plugin(
+                repo.path(),
+                "echo",
+                "[ \"$1\" = --call ] || exit 9\necho \"$2\"\n",
+            ),
+            plugin(repo.path(), "env", "echo \"${HOME:-none} $(pwd)\"\n"),
+            plugin(repo.path(), "broken", "exit 4\n"),
+        ];
+        let mut engine = Handlebars::new();
+        engine.register_escape_fn(handlebars::no_escape);
+        register_plugins(&mut engine, repo.path(), &plugins, scratch.path());
+
+        let rendered = engine
+            .render_template(r#"{{echo name "x" upper=true}}"#, &json!({"name": "Ada"}))
+            .unwrap();
+        assert_eq!(rendered, r#"{"hash":{"upper":true},"params":["Ada","x"]}"#);
+
+        let rendered = engine.render_template("{{env}}", &json!({})).unwrap();
+        let scratch = scratch.path().canonicalize().unwrap();
+        assert_eq!(rendered, format!("none {}", scratch.display()));
+
+        let error = engine
+            .render_template("{{broken}}", &json!({}))
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("plugin `broken` failed"),
+            "{error}"
+        );
+    }
+}
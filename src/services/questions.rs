@@ -0,0 +1,353 @@
+//! Service answering manifest questions for values no layer defines, by
+//! prompting on a terminal or falling back to cached answers and defaults,
+//! confirming destructive operations and reviewing changes one by one.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use serde_json::Value;
+
+use crate::config::{Question, QuestionKind};
+use crate::errors::DotstrapError;
+use crate::infrastructure::answers::Answers;
+
+/// Source of answers typed by the user.
+pub trait Prompter {
+    /// Ask `prompt` and return the line entered, or `None` when nobody can
+    /// answer.
+    fn ask(&mut self, prompt: &str) -> Option<String>;
+
+    /// Whether the questions this prompter cannot answer take their
+    /// default rather than failing the run.
+    fn defaults_allowed(&self) -> bool {
+        true
+    }
+}
+
+/// Prompter used when stdin is not a terminal: never answers.
+pub struct NonInteractive;
+
+impl Prompter for NonInteractive {
+    fn ask(&mut self, _prompt: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Prompter of strict runs without a terminal: never answers, and fails
+/// the questions that would fall back to their default.
+pub struct Strict;
+
+impl Prompter for Strict {
+    fn ask(&mut self, _prompt: &str) -> Option<String> {
+        None
+    }
+
+    fn defaults_allowed(&self) -> bool {
+        false
+    }
+}
+
+/// Prompter asking on stderr and reading answers from stdin.
+pub struct TerminalPrompter;
+
+impl Prompter for TerminalPrompter {
+    fn ask(&mut self, prompt: &str) -> Option<String> {
+        eprint!("{prompt}");
+        io::stderr().flush().ok()?;
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+        }
+    }
+}
+
+/// How destructive operations, such as replacing a file dotstrap does not
+/// manage, are confirmed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Confirm {
+    /// Ask the prompter, going ahead when nobody can answer.
+    #[default]
+    Ask,
+    /// Go ahead without asking, see `--yes`.
+    Yes,
+    /// Fail rather than go ahead, see `--non-interactive`.
+    Refuse,
+}
+
+/// Whether to go ahead with `action`, showing `details` before asking.
+///
+/// Only `y` or `yes` confirm; an empty answer declines.
+pub fn confirm(
+    policy: Confirm,
+    action: &str,
+    details: &str,
+    prompter: &mut dyn Prompter,
+) -> crate::errors::Result<bool> {
+    match policy {
+        Confirm::Yes => Ok(true),
+        Confirm::Refuse => Err(DotstrapError::NotConfirmed(action.to_string())),
+        Confirm::Ask => {
+            let mut prompt = format!("{details}{action}? [y/N] ");
+            loop {
+                let Some(answer) = prompter.ask(&prompt) else {
+                    return Ok(true);
+                };
+                match answer.trim().to_lowercase().as_str() {
+                    "y" | "yes" => return Ok(true),
+                    "" | "n" | "no" => return Ok(false),
+                    _ => prompt = format!("{action}? [y/N] "),
+                }
+            }
+        }
+    }
+}
+
+/// What to do with a change walked through with `--interactive`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Review {
+    /// Make the change.
+    Apply,
+    /// Keep a copy of the current file, then make the change.
+    Backup,
+    /// Leave the file as it is.
+    Skip,
+    /// Leave this file and every remaining one as they are.
+    Quit,
+}
+
+/// What to do with `change`, showing `details` before asking.
+///
+/// The question is asked until one of `y`, `n`, `b` or `q` is entered; when
+/// nobody can answer, the change is skipped.
+pub fn review(change: &str, details: &str, prompter: &mut dyn Prompter) -> Review {
+    let mut prompt = format!("{details}{change}? [y,n,b,q,?] ");
+    loop {
+        let Some(answer) = prompter.ask(&prompt) else {
+            return Review::Skip;
+        };
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => return Review::Apply,
+            "n" | "no" => return Review::Skip,
+            "b" | "backup" => return Review::Backup,
+            "q" | "quit" => return Review::Quit,
+            _ => {
+                prompt = format!(
+                    "y - apply this change\nn - skip this change\n\
+                     b - back up the current file, then apply\n\
+                     q - skip this and every remaining change\n{change}? [y,n,b,q,?] "
+                )
+            }
+        }
+    }
+}
+
+/// Answer the `questions` missing from `values`.
+///
+/// Cached answers are reused; otherwise the question is asked until a valid
+/// answer is given, with an empty answer selecting the default. When the
+/// prompter cannot answer, the default is used, and questions without one are
+/// left unanswered, unless the prompter allows no defaults. New answers are
+/// added to `cached`.
+pub fn answer_questions(
+    questions: &[Question],
+    values: &HashMap<String, Value>,
+    cached: &mut Answers,
+    prompter: &mut dyn Prompter,
+) -> crate::errors::Result<HashMap<String, Value>> {
+    let mut answers = HashMap::new();
+    for question in questions {
+        if values.contains_key(&question.name) || answers.contains_key(&question.name) {
+            continue;
+        }
+        if let Some(answer) = cached.get(&question.name) {
+            answers.insert(question.name.clone(), answer.clone());
+            continue;
+        }
+        if let Some(answer) = ask(question, prompter) {
+            cached.insert(question.name.clone(), answer.clone());
+            answers.insert(question.name.clone(), answer);
+        } else if !prompter.defaults_allowed() {
+            return Err(DotstrapError::UnansweredQuestion(question.name.clone()));
+        } else if let Some(default) = &question.default {
+            answers.insert(question.name.clone(), default.clone());
+        }
+    }
+    Ok(answers)
+}
+
+fn ask(question: &Question, prompter: &mut dyn Prompter) -> Option<Value> {
+    let mut prompt = question
+        .prompt
+        .clone()
+        .unwrap_or_else(|| question.name.clone());
+    if !question.choices.is_empty() {
+        prompt.push_str(&format!(" ({})", question.choices.join("/")));
+    }
+    if let Some(default) = &question.default {
+        let default = match default {
+            Value::String(text) => text.clone(),
+            other => other.to_string(),
+        };
+        prompt.push_str(&format!(" [{default}]"));
+    }
+    prompt.push_str(": ");
+    loop {
+        let input = prompter.ask(&prompt)?;
+        let input = input.trim();
+        if input.is_empty()
+            && let Some(default) = &question.default
+        {
+            return Some(default.clone());
+        }
+        match parse_answer(question, input) {
+            Ok(answer) => return Some(answer),
+            Err(message) => eprintln!("{message}"),
+        }
+    }
+}
+
+/// Convert `input` to the type of `question`.
+pub fn parse_answer(question: &Question, input: &str) -> Result<Value, String> {
+    match question.kind {
+        QuestionKind::String if question.choices.is_empty() => Ok(Value::from(input)),
+        QuestionKind::String => question
+            .choices
+            .iter()
+            .find(|choice| choice.as_str() == input)
+            .map(|choice| Value::from(choice.as_str()))
+            .ok_or_else(|| format!("expected one of: {}", question.choices.join(", "))),
+        QuestionKind::Bool => match input.to_ascii_lowercase().as_str() {
+            "y" | "yes" | "true" => Ok(Value::Bool(true)),
+            "n" | "no" | "false" => Ok(Value::Bool(false)),
+            _ => Err("expected yes or no".into()),
+        },
+        QuestionKind::Integer => input
+            .parse::<i64>()
+            .map(Value::from)
+            .map_err(|_| "expected a whole number".into()),
+        QuestionKind::Number => input
+            .parse::<f64>()
+            .ok()
+            .and_then(|number| serde_json::Number::from_f64(number).map(Value::Number))
+            .ok_or_else(|| "expected a number".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::VecDeque;
+
+    struct Scripted(VecDeque<&'static str>);
+
+    impl Prompter for Scripted {
+        fn ask(&mut self, _prompt: &str) -> Option<String> {
+            self.0.pop_front().map(str::to_string)
+        }
+    }
+
+    fn question(name: &str, kind: QuestionKind, default: Option<Value>) -> Question {
+        Question {
+            name: name.into(),
+            prompt: None,
+            kind,
+            default,
+            choices: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn answer_questions_prompts_for_missing_values_and_caches_answers() {
+        let questions = [
+            question("editor", QuestionKind::String, None),
+            question("email", QuestionKind::String, None),
+            question("work", QuestionKind::Bool, Some(json!(false))),
+            question("font_size", QuestionKind::Integer, None),
+        ];
+        let values = HashMap::from([("editor".to_string(), json!("hx"))]);
+        let mut cached = Answers::from([("email".to_string(), json!("me@example.com"))]);
+        let mut prompter = Scripted(VecDeque::from(["", "twelve", "12"]));
+
+        let answers = answer_questions(&questions, &values, &mut cached, &mut prompter).unwrap();
+
+        assert_eq!(answers.get("editor"), None);
+        assert_eq!(answers["email"], json!("me@example.com"));
+        assert_eq!(answers["work"], json!(false));
+        assert_eq!(answers["font_size"], json!(12));
+        assert_eq!(cached["font_size"], json!(12));
+    }
+
+    #[test]
+    fn answer_questions_uses_defaults_without_a_terminal() {
+        let questions = [
+            question("work", QuestionKind::Bool, Some(json!(true))),
+            question("email", QuestionKind::String, None),
+        ];
+        let mut cached = Answers::new();
+
+        let answers = answer_questions(
+            &questions,
+            &HashMap::new(),
+            &mut cached,
+            &mut NonInteractive,
+        )
+        .unwrap();
+
+        assert_eq!(answers, HashMap::from([("work".to_string(), json!(true))]));
+        assert!(cached.is_empty(), "defaults are not cached");
+    }
+
+    #[test]
+    fn answer_questions_fails_questions_strict_runs_cannot_answer() {
+        let questions = [
+            question("editor", QuestionKind::String, None),
+            question("work", QuestionKind::Bool, Some(json!(true))),
+        ];
+        let values = HashMap::from([("editor".to_string(), json!("hx"))]);
+
+        let err =
+            answer_questions(&questions, &values, &mut Answers::new(), &mut Strict).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "question `work` has no value or saved answer, which strict mode requires"
+        );
+    }
+
+    #[test]
+    fn parse_answer_validates_choices() {
+        let mut shell = question("shell", QuestionKind::String, None);
+        shell.choices = vec!["zsh".into(), "fish".into()];
+
+        assert_eq!(parse_answer(&shell, "fish"), Ok(json!("fish")));
+        assert!(parse_answer(&shell, "bash").is_err());
+    }
+
+    #[test]
+    fn review_asks_until_a_known_answer() {
+        let mut answers = Scripted(VecDeque::from(["?", "b", "", "n", "Q"]));
+
+        assert_eq!(review("link .zshrc", "", &mut answers), Review::Backup);
+        assert_eq!(review("link .zshrc", "", &mut answers), Review::Skip);
+        assert_eq!(review("link .zshrc", "", &mut answers), Review::Quit);
+        assert_eq!(review("link .zshrc", "", &mut NonInteractive), Review::Skip);
+    }
+
+    #[test]
+    fn confirm_follows_the_policy_and_the_answer() {
+        let mut answers = Scripted(VecDeque::from(["maybe", "Y", ""]));
+
+        assert!(confirm(Confirm::Ask, "replace", "", &mut answers).unwrap());
+        assert!(!confirm(Confirm::Ask, "replace", "", &mut answers).unwrap());
+        assert!(confirm(Confirm::Ask, "replace", "", &mut NonInteractive).unwrap());
+        assert!(confirm(Confirm::Yes, "replace", "", &mut answers).unwrap());
+        assert_eq!(
+            confirm(Confirm::Refuse, "replace .zshrc", "", &mut answers)
+                .unwrap_err()
+                .to_string(),
+            "replace .zshrc: not confirmed; pass --yes to proceed"
+        );
+    }
+}
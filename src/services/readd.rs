@@ -0,0 +1,130 @@
+//! Service carrying edits made to a rendered file back into its template.
+//!
+//! Lines are matched with a longest common subsequence: template lines
+//! rendered verbatim are located in the output, and every edited line must
+//! fall on, or next to, one of them. Edits to lines produced by template
+//! expressions cannot be written back and are reported instead.
+
+use crate::services::diff::common_lines;
+use crate::services::templating;
+
+/// Apply to `template` the edits turning `rendered`, its output, into
+/// `edited`.
+///
+/// Inserted lines are escaped so Handlebars outputs them verbatim. Returns a
+/// description of the first edit that touches an expression on failure.
+pub fn carry_edits(template: &str, rendered: &str, edited: &str) -> Result<String, String> {
+    let template: Vec<&str> = template.split_inclusive('\n').collect();
+    let rendered: Vec<&str> = rendered.split_inclusive('\n').collect();
+    let edited: Vec<&str> = edited.split_inclusive('\n').collect();
+
+    // Template line each rendered line was copied from.
+    let mut origin = vec![None; rendered.len()];
+    for (t, r) in common_lines(&template, &rendered) {
+        origin[r] = Some(t);
+    }
+
+    let mut removed = vec![false; template.len()];
+    let mut inserted: Vec<Vec<String>> = vec![Vec::new(); template.len() + 1];
+    let (mut r, mut e) = (0, 0);
+    let mut anchors = common_lines(&rendered, &edited);
+    anchors.push((rendered.len(), edited.len()));
+    for (next_r, next_e) in anchors {
+        // Rendered lines r..next_r were replaced by edited lines e..next_e.
+        let mut sources = Vec::new();
+        for line in r..next_r {
+            let source = origin[line].ok_or_else(|| expression_error(line, rendered[line]))?;
+            removed[source] = true;
+            sources.push(source);
+        }
+        for (offset, line) in edited[e..next_e].iter().enumerate() {
+            // Replacements take the place of the lines they replace, in
+            // order, even inside block helpers; extra lines follow the last.
+            let at = match sources.get(offset).or(sources.last()) {
+                Some(&source) => source,
+                None => insertion_point(&origin, r, template.len())
+                    .ok_or_else(|| expression_error(r - 1, rendered[r - 1]))?,
+            };
+            inserted[at].push(templating::escape_literal(line));
+        }
+        (r, e) = (next_r + 1, next_e + 1);
+    }
+
+    let mut merged = String::new();
+    for (index, line) in template.iter().enumerate() {
+        merged.extend(inserted[index].drain(..));
+        if !removed[index] {
+            merged.push_str(line);
+        }
+    }
+    merged.extend(inserted[template.len()].drain(..));
+    Ok(merged)
+}
+
+/// Template line before which lines inserted between the rendered lines
+/// `at - 1` and `at` go.
+fn insertion_point(origin: &[Option<usize>], at: usize, template_len: usize) -> Option<usize> {
+    if at == 0 {
+        return Some(origin.first().copied().flatten().unwrap_or(0));
+    }
+    match origin[at - 1] {
+        Some(previous) => Some(previous + 1),
+        None if at == origin.len() => Some(template_len),
+        None => origin[at],
+    }
+}
+
+fn expression_error(line: usize, text: &str) -> String {
+    format!(
+        "line {} (`{}`) is produced by a template expression",
+        line + 1,
+        text.trim_end()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = "# zshrc\nexport EDITOR={{editor}}\n{{#if work}}\nsource ~/.work\n{{/if}}\nalias ll='ls -l'\n";
+    const RENDERED: &str = "# zshrc\nexport EDITOR=hx\nsource ~/.work\nalias ll='ls -l'\n";
+
+    #[test]
+    fn carry_edits_rewrites_literal_lines() {
+        let edited = "# zshrc\nexport EDITOR=hx\nsource ~/.work.zsh\nalias ll='ls -la'\nalias gs='git status'\n";
+
+        let merged = carry_edits(TEMPLATE, RENDERED, edited).unwrap();
+
+        assert_eq!(
+            merged,
+            "# zshrc\nexport EDITOR={{editor}}\n{{#if work}}\nsource ~/.work.zsh\n{{/if}}\nalias ll='ls -la'\nalias gs='git status'\n"
+        );
+    }
+
+    #[test]
+    fn carry_edits_inserts_after_the_previous_line() {
+        let edited =
+            "# zshrc\nexport PAGER={{less}}\nexport EDITOR=hx\nsource ~/.work\nalias ll='ls -l'\n";
+
+        let merged = carry_edits(TEMPLATE, RENDERED, edited).unwrap();
+
+        assert_eq!(
+            merged,
+            "# zshrc\nexport PAGER=\\{{less}}\nexport EDITOR={{editor}}\n{{#if work}}\nsource ~/.work\n{{/if}}\nalias ll='ls -l'\n"
+        );
+    }
+
+    #[test]
+    fn carry_edits_rejects_edits_to_expressions() {
+        let edited = "# zshrc\nexport EDITOR=vim\nsource ~/.work\nalias ll='ls -l'\n";
+
+        let error = carry_edits(TEMPLATE, RENDERED, edited).unwrap_err();
+
+        assert!(error.contains("line 2 (`export EDITOR=hx`)"), "{error}");
+    }
+
+    #[test]
+    fn carry_edits_without_changes_keeps_the_template() {
+        assert_eq!(carry_edits(TEMPLATE, RENDERED, RENDERED).unwrap(), TEMPLATE);
+    }
+}
@@ -0,0 +1,130 @@
+//! Service cloning the git repositories making up the user's workspace.
+
+use std::path::{Path, PathBuf};
+
+use crate::config::RepoSpec;
+use crate::errors::Result;
+use crate::infrastructure::command::CommandExecutor;
+
+/// Clone every declared repository that is not present yet.
+///
+/// Existing destinations are left untouched so local work is never lost.
+/// Returns the destinations that were cloned (or would be, in dry-run mode).
+pub fn clone_repos(
+    home: &Path,
+    repos: &[RepoSpec],
+    executor: &dyn CommandExecutor,
+    dry_run: bool,
+) -> Result<Vec<PathBuf>> {
+    let mut cloned = Vec::new();
+    for spec in repos {
+        let destination = home.join(&spec.path);
+        if destination.exists() {
+            continue;
+        }
+        if !dry_run {
+            if let Some(parent) = destination.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let destination_str = destination.to_string_lossy();
+            executor.run("git", &clone_args(spec, &destination_str))?;
+        }
+        cloned.push(destination);
+    }
+    Ok(cloned)
+}
+
+fn clone_args<'a>(spec: &'a RepoSpec, destination: &'a str) -> Vec<&'a str> {
+    let mut args = vec!["clone"];
+    if let Some(branch) = &spec.branch {
+        args.extend(["--branch", branch.as_str()]);
+    }
+    if spec.shallow {
+        args.extend(["--depth", "1"]);
+    }
+    args.extend([spec.url.as_str(), destination]);
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use tempfile::TempDir;
+
+    fn spec(path: &str, branch: Option<&str>, shallow: bool) -> RepoSpec {
+        RepoSpec {
+            url: "https://github.com/example/app.git".into(),
+            path: PathBuf::from(path),
+            branch: branch.map(str::to_string),
+            shallow,
+        }
+    }
+
+    #[test]
+    fn clone_repos_passes_branch_and_depth() {
+        let home = TempDir::new().unwrap();
+        let executor = RecordingCommandExecutor::default();
+
+        let cloned = clone_repos(
+            home.path(),
+            &[spec("src/app", Some("main"), true)],
+            &executor,
+            false,
+        )
+        .unwrap();
+
+        let destination = home.path().join("src/app");
+        assert_eq!(cloned, vec![destination.clone()]);
+        let calls = executor.calls();
+        assert_eq!(calls[0].0, "git");
+        assert_eq!(
+            calls[0].1,
+            vec![
+                "clone",
+                "--branch",
+                "main",
+                "--depth",
+                "1",
+                "https://github.com/example/app.git",
+                &destination.to_string_lossy(),
+            ]
+        );
+    }
+
+    #[test]
+    fn clone_repos_skips_existing_destinations() {
+        let home = TempDir::new().unwrap();
+        std::fs::create_dir_all(home.path().join("src/app")).unwrap();
+        let executor = RecordingCommandExecutor::default();
+
+        let cloned = clone_repos(
+            home.path(),
+            &[spec("src/app", None, false)],
+            &executor,
+            false,
+        )
+        .unwrap();
+
+        assert!(cloned.is_empty());
+        assert!(executor.calls().is_empty());
+    }
+
+    #[test]
+    fn clone_repos_dry_run_has_no_side_effects() {
+        let home = TempDir::new().unwrap();
+        let executor = RecordingCommandExecutor::default();
+
+        let cloned = clone_repos(
+            home.path(),
+            &[spec("src/app", None, false)],
+            &executor,
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(cloned, vec![home.path().join("src/app")]);
+        assert!(executor.calls().is_empty());
+        assert!(!home.path().join("src").exists());
+    }
+}
@@ -0,0 +1,194 @@
+//! Service restoring the links changed by a run to their previous state.
+
+use std::fs;
+use std::path::Path;
+
+use crate::errors::Result;
+use crate::infrastructure::filesystem::OsFileSystem;
+use crate::services::linker::{digest_in, place};
+use crate::services::outcome::{ItemStatus, LinkOutcome, LinkStrategy};
+
+/// Changes made, or planned in dry-run mode, while rolling back.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RollbackOutcome {
+    /// Human readable description of every restored path.
+    pub restored: Vec<String>,
+    /// Paths left alone because they changed since the run.
+    pub warnings: Vec<String>,
+}
+
+/// Undo `links` in reverse order: remove links the run created, restore the
+/// backups of files it replaced, and re-point links it changed.
+///
+/// Destinations that are no longer symlinks, or copies standing for them
+/// whose contents differ from what the run placed, were modified after the
+/// run and are left untouched.
+pub fn rollback_links(links: &[LinkOutcome], dry_run: bool) -> Result<RollbackOutcome> {
+    let mut outcome = RollbackOutcome::default();
+    for link in links.iter().rev() {
+        if matches!(link.status, ItemStatus::Unchanged | ItemStatus::Skipped) {
+            continue;
+        }
+        let path = &link.path;
+        let placed = match link.strategy {
+            LinkStrategy::Symlink => path.is_symlink(),
+            LinkStrategy::Copy => path.is_file() && !path.is_symlink(),
+            LinkStrategy::Junction => path.exists(),
+        };
+        if !placed {
+            outcome.warnings.push(format!(
+                "skipping {}: it is no longer a link managed by dotstrap",
+                path.display()
+            ));
+            continue;
+        }
+        if link.strategy == LinkStrategy::Copy && !copy_intact(path, link) {
+            outcome.warnings.push(format!(
+                "skipping {}: it was modified since the run",
+                path.display()
+            ));
+            continue;
+        }
+        let description = match (&link.backup, &link.previous) {
+            (Some(backup), _) if !backup.exists() => {
+                outcome.warnings.push(format!(
+                    "skipping {}: backup {} no longer exists",
+                    path.display(),
+                    backup.display()
+                ));
+                continue;
+            }
+            (Some(backup), _) => {
+                format!("restore {} from {}", path.display(), backup.display())
+            }
+            (None, Some(previous)) => {
+                format!("re-point {} to {}", path.display(), previous.display())
+            }
+            (None, None) => format!("remove {}", path.display()),
+        };
+        if !dry_run {
+            restore(path, link)?;
+        }
+        outcome.restored.push(description);
+    }
+    Ok(outcome)
+}
+
+/// Whether the copy at `path` still has the contents the run placed there.
+fn copy_intact(path: &Path, link: &LinkOutcome) -> bool {
+    link.digest
+        .as_deref()
+        .is_some_and(|digest| digest_in(&OsFileSystem, path).is_ok_and(|actual| actual == digest))
+}
+
+fn restore(path: &Path, link: &LinkOutcome) -> Result<()> {
+    if link.strategy == LinkStrategy::Junction {
+        fs::remove_dir(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    if let Some(backup) = &link.backup {
+        fs::rename(backup, path)?;
+    } else if let Some(previous) = &link.previous {
+        place(&OsFileSystem, link.strategy, previous, path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::filesystem::CopyingFileSystem;
+    use crate::services::linker::{create_symlink, link_path, link_path_in};
+    use tempfile::TempDir;
+
+    #[cfg(unix)]
+    #[test]
+    fn rollback_links_restores_previous_state() {
+        let home = TempDir::new().unwrap();
+        let stage = home.path().join("stage");
+        fs::write(&stage, "new").unwrap();
+        let old_target = home.path().join("old-target");
+        fs::write(&old_target, "old target").unwrap();
+
+        let created = home.path().join(".created");
+        let replaced = home.path().join(".replaced");
+        fs::write(&replaced, "original").unwrap();
+        let repointed = home.path().join(".repointed");
+        create_symlink(&OsFileSystem, &old_target, &repointed).unwrap();
+        let links = vec![
+            link_path(&stage, &created).unwrap(),
+            link_path(&stage, &replaced).unwrap(),
+            link_path(&stage, &repointed).unwrap(),
+        ];
+
+        let planned = rollback_links(&links, true).unwrap();
+        assert_eq!(planned.restored.len(), 3);
+        assert!(created.is_symlink(), "dry run keeps the links");
+
+        let outcome = rollback_links(&links, false).unwrap();
+        assert_eq!(outcome, planned);
+        assert!(!created.exists() && !created.is_symlink());
+        assert!(!replaced.is_symlink());
+        assert_eq!(fs::read_to_string(&replaced).unwrap(), "original");
+        assert_eq!(fs::read_link(&repointed).unwrap(), old_target);
+    }
+
+    #[test]
+    fn rollback_links_skips_files_modified_since() {
+        let home = TempDir::new().unwrap();
+        let path = home.path().join(".zshrc");
+        fs::write(&path, "edited by hand").unwrap();
+
+        let outcome = rollback_links(
+            &[LinkOutcome::new(path.clone(), ItemStatus::Created)],
+            false,
+        )
+        .unwrap();
+
+        assert!(outcome.restored.is_empty());
+        assert_eq!(outcome.warnings.len(), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "edited by hand");
+    }
+
+    #[test]
+    fn rollback_links_skips_copies_edited_since() {
+        let home = TempDir::new().unwrap();
+        let stage = home.path().join("stage");
+        fs::write(&stage, "new").unwrap();
+        let edited = home.path().join(".edited");
+        let intact = home.path().join(".intact");
+        let links = vec![
+            link_path_in(&CopyingFileSystem, &stage, &edited).unwrap(),
+            link_path_in(&CopyingFileSystem, &stage, &intact).unwrap(),
+        ];
+        assert_eq!(links[0].strategy, LinkStrategy::Copy);
+        fs::write(&edited, "edited by hand").unwrap();
+
+        let outcome = rollback_links(&links, false).unwrap();
+
+        assert_eq!(outcome.restored.len(), 1);
+        assert_eq!(outcome.warnings.len(), 1);
+        assert!(outcome.warnings[0].contains(".edited"));
+        assert_eq!(fs::read_to_string(&edited).unwrap(), "edited by hand");
+        assert!(!intact.exists());
+    }
+
+    #[test]
+    fn rollback_links_restores_previous_copies_without_symlinks() {
+        let home = TempDir::new().unwrap();
+        let stage = home.path().join("stage");
+        fs::write(&stage, "new").unwrap();
+        let previous = home.path().join("previous");
+        fs::write(&previous, "old contents").unwrap();
+        let path = home.path().join(".zshrc");
+        let mut link = link_path_in(&CopyingFileSystem, &stage, &path).unwrap();
+        link.status = ItemStatus::Updated;
+        link.previous = Some(previous.clone());
+
+        rollback_links(&[link], false).unwrap();
+
+        assert!(!path.is_symlink());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old contents");
+    }
+}
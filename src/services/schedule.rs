@@ -0,0 +1,282 @@
+//! Service installing a launchd agent (macOS) or systemd user timer (other
+//! Unix hosts) that runs `dotstrap update` periodically.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::errors::Result;
+use crate::infrastructure::command::CommandExecutor;
+
+const LAUNCHD_LABEL: &str = "dev.dotstrap.sync";
+const SYSTEMD_UNIT: &str = "dotstrap-sync";
+
+/// Service manager used to run the periodic sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheduler {
+    Launchd,
+    Systemd,
+}
+
+impl Scheduler {
+    /// Scheduler of the running host.
+    pub fn native() -> Self {
+        if cfg!(target_os = "macos") {
+            Scheduler::Launchd
+        } else {
+            Scheduler::Systemd
+        }
+    }
+
+    /// Unit files written for the schedule, relative to the home directory.
+    fn unit_paths(self, home: &Path) -> Vec<PathBuf> {
+        match self {
+            Scheduler::Launchd => vec![
+                home.join("Library/LaunchAgents")
+                    .join(format!("{LAUNCHD_LABEL}.plist")),
+            ],
+            Scheduler::Systemd => {
+                let dir = home.join(".config/systemd/user");
+                vec![
+                    dir.join(format!("{SYSTEMD_UNIT}.service")),
+                    dir.join(format!("{SYSTEMD_UNIT}.timer")),
+                ]
+            }
+        }
+    }
+}
+
+/// Write the unit files running `command` every `interval` and load them.
+///
+/// Returns a description of every file written and command run, or planned
+/// in dry-run mode.
+pub fn install_schedule(
+    scheduler: Scheduler,
+    home: &Path,
+    command: &[String],
+    interval: Duration,
+    executor: &dyn CommandExecutor,
+    dry_run: bool,
+) -> Result<Vec<String>> {
+    let paths = scheduler.unit_paths(home);
+    let contents = match scheduler {
+        Scheduler::Launchd => vec![launchd_plist(command, interval)],
+        Scheduler::Systemd => {
+            let (service, timer) = systemd_units(command, interval);
+            vec![service, timer]
+        }
+    };
+    let mut actions = Vec::new();
+    for (path, contents) in paths.iter().zip(&contents) {
+        if !dry_run {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, contents)?;
+        }
+        actions.push(format!("write {}", path.display()));
+    }
+    let plist = paths[0].to_string_lossy();
+    let timer = format!("{SYSTEMD_UNIT}.timer");
+    let commands: Vec<(&str, Vec<&str>)> = match scheduler {
+        Scheduler::Launchd => vec![
+            // Unloading fails when the agent was not loaded yet.
+            ("launchctl", vec!["unload", &plist]),
+            ("launchctl", vec!["load", "-w", &plist]),
+        ],
+        Scheduler::Systemd => vec![
+            ("systemctl", vec!["--user", "daemon-reload"]),
+            ("systemctl", vec!["--user", "enable", "--now", &timer]),
+        ],
+    };
+    for (index, (program, args)) in commands.iter().enumerate() {
+        if !dry_run {
+            let result = executor.run(program, args);
+            if !(scheduler == Scheduler::Launchd && index == 0) {
+                result?;
+            }
+        }
+        actions.push(format!("run {program} {}", args.join(" ")));
+    }
+    Ok(actions)
+}
+
+/// Stop the periodic sync and delete its unit files.
+pub fn uninstall_schedule(
+    scheduler: Scheduler,
+    home: &Path,
+    executor: &dyn CommandExecutor,
+    dry_run: bool,
+) -> Result<Vec<String>> {
+    let paths = scheduler.unit_paths(home);
+    let plist = paths[0].to_string_lossy();
+    let timer = format!("{SYSTEMD_UNIT}.timer");
+    let (program, args) = match scheduler {
+        Scheduler::Launchd => ("launchctl", vec!["unload", "-w", &plist]),
+        Scheduler::Systemd => ("systemctl", vec!["--user", "disable", "--now", &timer]),
+    };
+    let mut actions = Vec::new();
+    if paths[0].exists() {
+        if !dry_run {
+            executor.run(program, &args)?;
+        }
+        actions.push(format!("run {program} {}", args.join(" ")));
+    }
+    for path in paths.iter().filter(|path| path.exists()) {
+        if !dry_run {
+            fs::remove_file(path)?;
+        }
+        actions.push(format!("remove {}", path.display()));
+    }
+    Ok(actions)
+}
+
+/// launchd agent running `command` every `interval` and at login.
+pub fn launchd_plist(command: &[String], interval: Duration) -> String {
+    let arguments: String = command
+        .iter()
+        .map(|arg| format!("    <string>{}</string>\n", xml_escape(arg)))
+        .collect();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>Label</key>
+  <string>{LAUNCHD_LABEL}</string>
+  <key>ProgramArguments</key>
+  <array>
+{arguments}  </array>
+  <key>StartInterval</key>
+  <integer>{}</integer>
+  <key>RunAtLoad</key>
+  <true/>
+</dict>
+</plist>
+"#,
+        interval.as_secs()
+    )
+}
+
+/// systemd user service running `command` and the timer triggering it every
+/// `interval`.
+pub fn systemd_units(command: &[String], interval: Duration) -> (String, String) {
+    let exec = command
+        .iter()
+        .map(|arg| systemd_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let service = format!(
+        "[Unit]\nDescription=Synchronise dotfiles with dotstrap\n\n\
+         [Service]\nType=oneshot\nExecStart={exec}\n"
+    );
+    let timer = format!(
+        "[Unit]\nDescription=Run dotstrap update periodically\n\n\
+         [Timer]\nOnBootSec=5min\nOnUnitActiveSec={}s\n\n\
+         [Install]\nWantedBy=timers.target\n",
+        interval.as_secs()
+    );
+    (service, timer)
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn systemd_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && !arg
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | '%' | '$'))
+    {
+        return arg.to_string();
+    }
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{escaped}\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+    use tempfile::TempDir;
+
+    fn command() -> Vec<String> {
+        ["/usr/bin/dotstrap", "update", "/home/me/my dotfiles"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    #[test]
+    fn systemd_units_quote_arguments() {
+        let (service, timer) = systemd_units(&command(), Duration::from_secs(3600));
+
+        assert!(service.contains("ExecStart=/usr/bin/dotstrap update \"/home/me/my dotfiles\"\n"));
+        assert!(timer.contains("OnUnitActiveSec=3600s\n"));
+    }
+
+    #[test]
+    fn launchd_plist_lists_arguments_and_interval() {
+        let plist = launchd_plist(&command(), Duration::from_secs(1800));
+
+        assert!(plist.contains("    <string>/home/me/my dotfiles</string>\n"));
+        assert!(plist.contains("<integer>1800</integer>"));
+    }
+
+    #[test]
+    fn install_and_uninstall_systemd_schedule() {
+        let home = TempDir::new().unwrap();
+        let executor = RecordingCommandExecutor::default();
+
+        let actions = install_schedule(
+            Scheduler::Systemd,
+            home.path(),
+            &command(),
+            Duration::from_secs(600),
+            &executor,
+            false,
+        )
+        .unwrap();
+
+        let timer = home.path().join(".config/systemd/user/dotstrap-sync.timer");
+        assert!(timer.exists());
+        assert_eq!(actions.len(), 4);
+        assert_eq!(
+            executor.calls()[1].1,
+            vec!["--user", "enable", "--now", "dotstrap-sync.timer"]
+        );
+
+        uninstall_schedule(Scheduler::Systemd, home.path(), &executor, false).unwrap();
+        assert!(!timer.exists());
+        assert_eq!(
+            executor.calls()[2].1,
+            vec!["--user", "disable", "--now", "dotstrap-sync.timer"]
+        );
+    }
+
+    #[test]
+    fn install_schedule_dry_run_writes_nothing() {
+        let home = TempDir::new().unwrap();
+        let executor = RecordingCommandExecutor::default();
+
+        let actions = install_schedule(
+            Scheduler::Launchd,
+            home.path(),
+            &command(),
+            Duration::from_secs(600),
+            &executor,
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(actions.len(), 3);
+        assert!(executor.calls().is_empty());
+        assert!(!home.path().join("Library").exists());
+    }
+}
@@ -0,0 +1,268 @@
+//! Service listing the packages installed on the host, per `packages.yaml`
+//! section.
+
+use crate::infrastructure::command::CommandExecutor;
+
+/// Packages installed through one package manager.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstalledSection {
+    /// Keys of the `packages.yaml` section, e.g. `["brew", "casks"]`.
+    pub path: &'static [&'static str],
+    pub packages: Vec<String>,
+}
+
+/// Command listing the packages of a section, one per line once parsed.
+struct Query {
+    path: &'static [&'static str],
+    /// Operating systems the manager runs on, empty for all.
+    systems: &'static [&'static str],
+    program: &'static str,
+    args: &'static [&'static str],
+    parse: fn(&str) -> Vec<String>,
+}
+
+const QUERIES: &[Query] = &[
+    Query {
+        path: &["brew", "taps"],
+        systems: &["macos", "linux"],
+        program: "brew",
+        args: &["tap"],
+        parse: |output| {
+            lines(output)
+                .into_iter()
+                .filter(|tap| !matches!(tap.as_str(), "homebrew/core" | "homebrew/cask"))
+                .collect()
+        },
+    },
+    Query {
+        path: &["brew", "formulae"],
+        systems: &["macos", "linux"],
+        program: "brew",
+        args: &["leaves", "--installed-on-request"],
+        parse: lines,
+    },
+    Query {
+        path: &["brew", "casks"],
+        systems: &["macos"],
+        program: "brew",
+        args: &["list", "--cask", "-1"],
+        parse: lines,
+    },
+    Query {
+        path: &["apt"],
+        systems: &["linux"],
+        program: "apt-mark",
+        args: &["showmanual"],
+        parse: lines,
+    },
+    Query {
+        path: &["pacman"],
+        systems: &["linux"],
+        program: "pacman",
+        args: &["-Qqe"],
+        parse: lines,
+    },
+    Query {
+        path: &["dnf"],
+        systems: &["linux"],
+        program: "dnf",
+        args: &["repoquery", "--userinstalled", "--queryformat", "%{name}\n"],
+        parse: lines,
+    },
+    Query {
+        path: &["cargo"],
+        systems: &[],
+        program: "cargo",
+        args: &["install", "--list"],
+        parse: cargo_crates,
+    },
+    Query {
+        path: &["pipx"],
+        systems: &[],
+        program: "pipx",
+        args: &["list", "--short"],
+        parse: first_words,
+    },
+];
+
+/// Queries listing every installed package, dependencies included, of the
+/// sections dotstrap installs.
+const EVERY_PACKAGE: &[Query] = &[
+    Query {
+        path: &["brew", "formulae"],
+        systems: &["macos", "linux"],
+        program: "brew",
+        args: &["list", "--formula", "-1"],
+        parse: lines,
+    },
+    Query {
+        path: &["brew", "casks"],
+        systems: &["macos"],
+        program: "brew",
+        args: &["list", "--cask", "-1"],
+        parse: lines,
+    },
+    Query {
+        path: &["apt"],
+        systems: &["linux"],
+        program: "dpkg-query",
+        args: &["--show", "--showformat", "${Package}\n"],
+        parse: lines,
+    },
+    Query {
+        path: &["pacman"],
+        systems: &["linux"],
+        program: "pacman",
+        args: &["-Qq"],
+        parse: lines,
+    },
+    Query {
+        path: &["dnf"],
+        systems: &["linux"],
+        program: "rpm",
+        args: &["--query", "--all", "--queryformat", "%{NAME}\n"],
+        parse: lines,
+    },
+    Query {
+        path: &["cargo"],
+        systems: &[],
+        program: "cargo",
+        args: &["install", "--list"],
+        parse: cargo_crates,
+    },
+];
+
+/// List the packages installed on `os` by every available package manager.
+///
+/// Managers missing from the host are skipped; the ones whose listing fails
+/// are skipped with a warning.
+pub fn installed_packages(
+    os: &str,
+    executor: &dyn CommandExecutor,
+) -> (Vec<InstalledSection>, Vec<String>) {
+    run_queries(QUERIES, os, executor)
+}
+
+/// List every package installed on `os`, dependencies included, as
+/// [`installed_packages`] lists the explicitly installed ones.
+pub fn every_installed_package(
+    os: &str,
+    executor: &dyn CommandExecutor,
+) -> (Vec<InstalledSection>, Vec<String>) {
+    run_queries(EVERY_PACKAGE, os, executor)
+}
+
+fn run_queries(
+    queries: &[Query],
+    os: &str,
+    executor: &dyn CommandExecutor,
+) -> (Vec<InstalledSection>, Vec<String>) {
+    let mut sections = Vec::new();
+    let mut warnings = Vec::new();
+    for query in queries {
+        let supported = query.systems.is_empty() || query.systems.contains(&os);
+        if !supported || !executor.is_available(query.program) {
+            continue;
+        }
+        match executor.output(query.program, query.args) {
+            Ok(output) => sections.push(InstalledSection {
+                path: query.path,
+                packages: (query.parse)(&output),
+            }),
+            Err(err) => warnings.push(format!(
+                "cannot list {} packages: {err}",
+                query.path.join(".")
+            )),
+        }
+    }
+    (sections, warnings)
+}
+
+fn lines(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn first_words(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Crate names of `cargo install --list`, whose binaries are indented below
+/// `name v1.2.3:` lines.
+fn cargo_crates(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter(|line| !line.starts_with(char::is_whitespace))
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
+
+    #[test]
+    fn installed_packages_parses_each_manager() {
+        let executor = RecordingCommandExecutor::default()
+            .with_output("brew tap", "homebrew/core\nhashicorp/tap\n")
+            .with_output("brew leaves --installed-on-request", "ripgrep\nnode@18\n")
+            .with_output("brew list --cask -1", "iterm2\n")
+            .with_output(
+                "cargo install --list",
+                "bat v0.24.0:\n    bat\nripgrep v14.1.0:\n    rg\n",
+            )
+            .with_output("pipx list --short", "black 24.1.0\n");
+
+        let (sections, warnings) = installed_packages("macos", &executor);
+
+        let find = |path: &[&str]| {
+            sections
+                .iter()
+                .find(|section| section.path == path)
+                .map(|section| section.packages.clone())
+        };
+        assert!(warnings.is_empty());
+        assert_eq!(find(&["brew", "taps"]), Some(vec!["hashicorp/tap".into()]));
+        assert_eq!(
+            find(&["brew", "formulae"]),
+            Some(vec!["ripgrep".into(), "node@18".into()])
+        );
+        assert_eq!(find(&["cargo"]), Some(vec!["bat".into(), "ripgrep".into()]));
+        assert_eq!(find(&["pipx"]), Some(vec!["black".into()]));
+        assert_eq!(find(&["apt"]), None, "apt is not queried on macOS");
+    }
+
+    #[test]
+    fn every_installed_package_includes_dependencies() {
+        let executor = RecordingCommandExecutor::default()
+            .with_output(
+                "brew list --formula -1",
+                "pcre2
+ripgrep
+",
+            )
+            .with_output(
+                "dpkg-query --show --showformat ${Package}\n",
+                "libc6
+git
+",
+            );
+
+        let (sections, _) = every_installed_package("linux", &executor);
+
+        assert_eq!(sections[0].path, ["brew", "formulae"]);
+        assert_eq!(sections[0].packages, ["pcre2", "ripgrep"]);
+        assert_eq!(sections[1].path, ["apt"]);
+        assert_eq!(sections[1].packages, ["libc6", "git"]);
+    }
+}
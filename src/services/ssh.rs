@@ -0,0 +1,239 @@
+//! Service generating the SSH key used to authenticate a new machine.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::SshSettings;
+use crate::errors::Result;
+use crate::infrastructure::command::CommandExecutor;
+use crate::infrastructure::filesystem::OsFileSystem;
+use crate::services::linker;
+
+/// Clipboard tools tried in order when copying the public key.
+const CLIPBOARD_TOOLS: &[&str] = &["pbcopy", "wl-copy", "xclip -selection clipboard", "clip"];
+
+/// Result of the SSH step.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SshOutcome {
+    /// Private key path.
+    pub key: PathBuf,
+    /// Whether the key was generated (or would be, in dry-run mode).
+    pub generated: bool,
+    /// Public key contents, when it exists on disk.
+    pub public_key: Option<String>,
+    /// Clipboard tool used to copy the public key.
+    pub copied_with: Option<String>,
+    /// Why the clipboard tool failed to copy the public key.
+    pub copy_error: Option<String>,
+}
+
+/// Generate an ed25519 key when absent and tighten its permissions.
+///
+/// Existing keys are never overwritten. The public key is copied to the
+/// clipboard when requested and a clipboard tool is available, a failure of
+/// the tool being reported in the outcome rather than failing the step.
+pub fn ensure_key(
+    home: &Path,
+    settings: &SshSettings,
+    executor: &dyn CommandExecutor,
+    dry_run: bool,
+) -> Result<SshOutcome> {
+    let key = home.join(&settings.key);
+    let public = public_key_path(&key);
+    let generated = !key.exists();
+    if dry_run {
+        return Ok(SshOutcome {
+            public_key: fs::read_to_string(&public).ok(),
+            key,
+            generated,
+            copied_with: None,
+            copy_error: None,
+        });
+    }
+    if let Some(dir) = key.parent().filter(|dir| !dir.exists()) {
+        fs::create_dir_all(dir)?;
+        linker::apply_mode(&OsFileSystem, dir, Some(0o700))?;
+    }
+    if generated {
+        let key_str = key.to_string_lossy();
+        let comment = settings.comment.clone().unwrap_or_default();
+        executor.run(
+            "ssh-keygen",
+            &[
+                "-q", "-t", "ed25519", "-N", "", "-C", &comment, "-f", &key_str,
+            ],
+        )?;
+    }
+    linker::apply_mode(&OsFileSystem, &key, Some(0o600))?;
+    let public_key = match fs::read_to_string(&public) {
+        Ok(contents) => {
+            linker::apply_mode(&OsFileSystem, &public, Some(0o644))?;
+            Some(contents.trim().to_string())
+        }
+        Err(_) => None,
+    };
+    let (copied_with, copy_error) = if settings.copy_public_key && public_key.is_some() {
+        match copy_to_clipboard(&public, executor) {
+            Ok(tool) => (tool, None),
+            Err(err) => (None, Some(err.to_string())),
+        }
+    } else {
+        (None, None)
+    };
+    Ok(SshOutcome {
+        key,
+        generated,
+        public_key,
+        copied_with,
+        copy_error,
+    })
+}
+
+fn public_key_path(key: &Path) -> PathBuf {
+    let mut public = key.as_os_str().to_owned();
+    public.push(".pub");
+    PathBuf::from(public)
+}
+
+fn copy_to_clipboard(public: &Path, executor: &dyn CommandExecutor) -> Result<Option<String>> {
+    let Some(tool) = CLIPBOARD_TOOLS.iter().find(|tool| {
+        let program = tool.split_whitespace().next().unwrap_or(tool);
+        executor.is_available(program)
+    }) else {
+        return Ok(None);
+    };
+    let quoted = public.to_string_lossy().replace('\'', r"'\''");
+    executor.run("sh", &["-c", &format!("{tool} < '{quoted}'")])?;
+    Ok(Some(tool.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use tempfile::TempDir;
+
+    /// Executor writing a key pair to the `-f` path given to `ssh-keygen`.
+    #[derive(Default)]
+    struct FakeKeygen {
+        calls: RefCell<Vec<String>>,
+    }
+
+    impl CommandExecutor for FakeKeygen {
+        fn run(&self, program: &str, args: &[&str]) -> Result<()> {
+            self.calls
+                .borrow_mut()
+                .push(format!("{program} {}", args.join(" ")));
+            if program == "ssh-keygen" {
+                let key = args
+                    .iter()
+                    .position(|arg| *arg == "-f")
+                    .map(|idx| args[idx + 1])
+                    .expect("ssh-keygen must be given a key path");
+                fs::write(key, "private")?;
+                fs::write(format!("{key}.pub"), "ssh-ed25519 AAAA me@laptop\n")?;
+            }
+            Ok(())
+        }
+
+        fn is_available(&self, program: &str) -> bool {
+            program == "wl-copy"
+        }
+    }
+
+    /// Executor whose clipboard tool fails, as `wl-copy` outside Wayland.
+    struct NoDisplay(FakeKeygen);
+
+    impl CommandExecutor for NoDisplay {
+        fn run(&self, program: &str, args: &[&str]) -> Result<()> {
+            if program == "sh" {
+                return Err(crate::errors::DotstrapError::CommandFailed {
+                    program: "wl-copy".into(),
+                    status: 1,
+                });
+            }
+            self.0.run(program, args)
+        }
+
+        fn is_available(&self, program: &str) -> bool {
+            self.0.is_available(program)
+        }
+    }
+
+    #[test]
+    fn ensure_key_generates_missing_key() {
+        let home = TempDir::new().unwrap();
+        let executor = FakeKeygen::default();
+        let settings = SshSettings {
+            comment: Some("me@laptop".into()),
+            copy_public_key: true,
+            ..SshSettings::default()
+        };
+
+        let outcome = ensure_key(home.path(), &settings, &executor, false)
+            .expect("key generation should succeed");
+
+        assert!(outcome.generated);
+        assert_eq!(outcome.key, home.path().join(".ssh/id_ed25519"));
+        assert_eq!(
+            outcome.public_key.as_deref(),
+            Some("ssh-ed25519 AAAA me@laptop")
+        );
+        assert_eq!(outcome.copied_with.as_deref(), Some("wl-copy"));
+        let calls = executor.calls.borrow();
+        assert!(calls[0].starts_with("ssh-keygen -q -t ed25519 -N  -C me@laptop -f "));
+        assert!(calls[1].starts_with("sh -c wl-copy < "));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&outcome.key).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn ensure_key_reports_clipboard_failure() {
+        let home = TempDir::new().unwrap();
+        let executor = NoDisplay(FakeKeygen::default());
+        let settings = SshSettings {
+            copy_public_key: true,
+            ..SshSettings::default()
+        };
+
+        let outcome = ensure_key(home.path(), &settings, &executor, false)
+            .expect("a clipboard failure should not fail the step");
+
+        assert!(outcome.generated);
+        assert_eq!(outcome.copied_with, None);
+        assert!(outcome.copy_error.is_some());
+    }
+
+    #[test]
+    fn ensure_key_keeps_existing_key() {
+        let home = TempDir::new().unwrap();
+        fs::create_dir_all(home.path().join(".ssh")).unwrap();
+        fs::write(home.path().join(".ssh/id_ed25519"), "existing").unwrap();
+        let executor = FakeKeygen::default();
+
+        let outcome = ensure_key(home.path(), &SshSettings::default(), &executor, false).unwrap();
+
+        assert!(!outcome.generated);
+        assert!(executor.calls.borrow().is_empty());
+        assert_eq!(
+            fs::read_to_string(home.path().join(".ssh/id_ed25519")).unwrap(),
+            "existing"
+        );
+    }
+
+    #[test]
+    fn ensure_key_dry_run_has_no_side_effects() {
+        let home = TempDir::new().unwrap();
+        let executor = FakeKeygen::default();
+
+        let outcome = ensure_key(home.path(), &SshSettings::default(), &executor, true).unwrap();
+
+        assert!(outcome.generated);
+        assert!(executor.calls.borrow().is_empty());
+        assert!(!home.path().join(".ssh").exists());
+    }
+}
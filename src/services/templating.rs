@@ -3,12 +3,15 @@
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
-use handlebars::Handlebars;
+use handlebars::{Handlebars, RenderErrorReason};
 use serde_json::Value;
+use sha2::{Digest, Sha256};
 use tempfile::TempDir;
 
 use crate::config::{Manifest, TemplateMapping};
 use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::CommandExecutor;
+use crate::services::{helpers, plugins};
 
 /// Link between a manifest entry and its rendered file.
 pub struct RenderedTemplate {
@@ -22,8 +25,21 @@ pub struct RenderedSet {
     pub templates: Vec<RenderedTemplate>,
 }
 
-/// Merge declarative values and secrets into the template context.
-pub fn build_context(values: &HashMap<String, Value>, secrets: &HashMap<String, Value>) -> Value {
+/// Commands rendering templates may run on top of the built-in helpers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Permissions {
+    /// Run the plugins the manifest declares, see `--allow-plugins`.
+    pub plugins: bool,
+    /// Run the commands of `exec` helpers, see `--allow-template-exec`.
+    pub exec: bool,
+}
+
+/// Merge declarative values, secrets, and host facts into the template context.
+pub fn build_context(
+    values: &HashMap<String, Value>,
+    secrets: &HashMap<String, Value>,
+    system: &serde_json::Map<String, Value>,
+) -> Value {
     let mut root = serde_json::Map::new();
     for (key, value) in values {
         root.insert(key.clone(), value.clone());
@@ -33,18 +49,219 @@ pub fn build_context(values: &HashMap<String, Value>, secrets: &HashMap<String,
         secrets_map.insert(key.clone(), value.clone());
     }
     root.insert("secrets".into(), Value::Object(secrets_map));
+    root.insert("system".into(), Value::Object(system.clone()));
+    tracing::trace!(
+        "template context keys: {}",
+        root.keys().cloned().collect::<Vec<_>>().join(", ")
+    );
     Value::Object(root)
 }
 
+/// Render a template string declared inline in a configuration file.
+///
+/// HTML escaping is disabled since inline templates produce shell commands and
+/// other plain text. `origin` identifies the file declaring the template in
+/// error messages.
+pub fn render_inline(template: &str, context: &Value, origin: &Path) -> Result<String> {
+    let mut engine = Handlebars::new();
+    engine.register_escape_fn(handlebars::no_escape);
+    engine
+        .render_template(template, context)
+        .map_err(|source| DotstrapError::Template {
+            source,
+            path: origin.to_path_buf(),
+        })
+}
+
+/// Whether the `enabled` expression of an entry declared in `origin` holds
+/// in `context`.
+///
+/// The expression is rendered as a Handlebars template, so
+/// `"{{work_machine}}"` and `"{{#if (eq system.os \"linux\")}}yes{{/if}}"`
+/// both work. It is false when it renders to nothing, `false`, `0`, `no`,
+/// or `off`, ignoring case and surrounding whitespace.
+pub fn is_enabled(expression: &str, context: &Value, origin: &Path) -> Result<bool> {
+    let rendered = render_inline(expression, context, origin)?;
+    let value = rendered.trim().to_ascii_lowercase();
+    Ok(!matches!(value.as_str(), "" | "false" | "0" | "no" | "off"))
+}
+
+/// Escape the mustaches of `text` so Handlebars renders it verbatim.
+pub fn escape_literal(text: &str) -> String {
+    text.replace("{{", "\\{{")
+}
+
+/// Hex encoded SHA-256 digest of everything rendering `template` depends on:
+/// the template source, its manifest entry, the context values it
+/// references, and the files it includes.
+///
+/// Two runs computing the same digest render the same file, so a template
+/// whose digest did not change since the last run does not need rendering.
+/// Templates larger than `limit` bytes are refused, as they would be by
+/// [`render_templates`].
+pub fn input_digest(
+    repo: &Path,
+    template: &TemplateMapping,
+    context: &Value,
+    limit: u64,
+) -> Result<String> {
+    let template_path = repo.join(&template.source);
+    let mut hasher = Sha256::new();
+    let mut update = |part: &[u8]| {
+        // Length prefixes keep adjacent parts from running into each other.
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    };
+    update(env!("CARGO_PKG_VERSION").as_bytes());
+    update(template.source.to_string_lossy().as_bytes());
+    update(template.destination.to_string_lossy().as_bytes());
+    update(format!("{:?} {}", template.mode, template.copy).as_bytes());
+    if template.copy {
+        update(crate::infrastructure::download::sha256_file(&template_path)?.as_bytes());
+    } else {
+        let contents = read_template(&template_path, limit)?;
+        update(contents.as_bytes());
+        update(
+            referenced_context(&contents, context)
+                .to_string()
+                .as_bytes(),
+        );
+        // `expanduser` reads the home without the template naming it.
+        if !helpers::call_arguments(&contents, "expanduser").is_empty() {
+            update(
+                context
+                    .pointer("/system/home")
+                    .unwrap_or(&Value::Null)
+                    .to_string()
+                    .as_bytes(),
+            );
+        }
+        let included = helpers::included_paths(&contents);
+        for path in included.iter().flatten() {
+            update(path.as_bytes());
+            update(&std::fs::read(repo.join(path)).unwrap_or_default());
+        }
+        // Files named by expressions are only known when rendering, and
+        // commands may print something else each time, so the template is
+        // rendered again on every run.
+        if included.is_none() || !helpers::call_arguments(&contents, "exec").is_empty() {
+            update(format!("{:?}", std::time::SystemTime::now()).as_bytes());
+        }
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Read the template at `path`, refusing files larger than `limit` bytes
+/// before loading them.
+fn read_template(path: &Path, limit: u64) -> Result<String> {
+    let size = std::fs::metadata(path)?.len();
+    if size > limit {
+        return Err(DotstrapError::TemplateTooLarge {
+            path: path.to_path_buf(),
+            size,
+            limit,
+        });
+    }
+    Ok(std::fs::read_to_string(path)?)
+}
+
+/// Top-level entries of `context` that the expressions of `contents` may
+/// read. The whole context is returned when an expression can reach any
+/// entry, through `this`, `@root`, `..`, or `lookup`.
+fn referenced_context(contents: &str, context: &Value) -> Value {
+    let Some(entries) = context.as_object() else {
+        return context.clone();
+    };
+    let mut words = std::collections::BTreeSet::new();
+    for expression in contents.split("{{").skip(1) {
+        let expression = expression.split("}}").next().unwrap_or_default();
+        words.extend(
+            expression
+                .split(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '@' | '.')))
+                .flat_map(|path| path.split('.').map(move |word| (path, word))),
+        );
+    }
+    let dynamic = words.iter().any(|(path, word)| {
+        matches!(*word, "this" | "@root" | "lookup") || *path == "." || path.contains("..")
+    });
+    if dynamic {
+        return context.clone();
+    }
+    let referenced = entries
+        .iter()
+        .filter(|(key, _)| words.iter().any(|(_, word)| word == key))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    Value::Object(referenced)
+}
+
 /// Render all templates declared in the manifest into a temporary directory.
-pub fn render_templates(repo: &Path, manifest: &Manifest, context: &Value) -> Result<RenderedSet> {
+///
+/// Templates declared with `copy` are not read: their source stands for the
+/// rendered file, so it is streamed when staged. The plugins of the manifest
+/// are registered as helpers when `permissions` allow them, and fail the
+/// render otherwise.
+///
+/// Likewise, templates calling `exec` are refused unless `permissions` allow
+/// it. Its commands run through `executor`, once each, before the templates
+/// calling them are rendered.
+pub fn render_templates(
+    repo: &Path,
+    manifest: &Manifest,
+    context: &Value,
+    permissions: Permissions,
+    executor: &dyn CommandExecutor,
+) -> Result<RenderedSet> {
+    plugins::ensure_allowed(&manifest.plugins, permissions.plugins)?;
+    let exec = permissions.exec.then_some(executor);
     let tempdir = TempDir::new()?;
     let mut rendered = Vec::new();
     let mut engine = Handlebars::new();
+    helpers::register_helpers(&mut engine, repo, manifest.template_size_limit());
+    let scratch = TempDir::new()?;
+    plugins::register_plugins(&mut engine, repo, &manifest.plugins, scratch.path());
+    let mut outputs = HashMap::new();
+    if exec.is_some() {
+        helpers::register_exec(&mut engine, Some(outputs.clone()));
+    }
 
     for (idx, template) in manifest.templates.iter().enumerate() {
         let template_path = repo.join(&template.source);
-        let contents = std::fs::read_to_string(&template_path)?;
+        if template.copy {
+            rendered.push(RenderedTemplate {
+                template: template.clone(),
+                rendered_path: template_path,
+            });
+            continue;
+        }
+        let contents = read_template(&template_path, manifest.template_size_limit())?;
+        let commands = helpers::call_arguments(&contents, "exec");
+        match exec {
+            None if !commands.is_empty() => {
+                return Err(DotstrapError::TemplateExecNotAllowed {
+                    path: template_path,
+                });
+            }
+            None => {}
+            Some(executor) => {
+                for command in commands.into_iter().flatten() {
+                    if outputs.contains_key(&command) {
+                        continue;
+                    }
+                    let output = helpers::exec_output(&command, executor).map_err(|err| {
+                        DotstrapError::Template {
+                            source: RenderErrorReason::Other(format!(
+                                "`exec` of `{command}` failed: {err}"
+                            ))
+                            .into(),
+                            path: template_path.clone(),
+                        }
+                    })?;
+                    outputs.insert(command, output);
+                    helpers::register_exec(&mut engine, Some(outputs.clone()));
+                }
+            }
+        }
         let template_name = format!("template_{idx}");
         engine
             .register_template_string(&template_name, contents)
@@ -59,6 +276,11 @@ pub fn render_templates(repo: &Path, manifest: &Manifest, context: &Value) -> Re
                     source,
                     path: template_path.clone(),
                 })?;
+        tracing::trace!(
+            "rendered {} ({} bytes)",
+            template.source.display(),
+            rendered_contents.len()
+        );
         let generated_path = tempdir.path().join(format!("rendered_{idx}"));
         std::fs::write(&generated_path, rendered_contents)?;
         rendered.push(RenderedTemplate {
@@ -76,12 +298,27 @@ pub fn render_templates(repo: &Path, manifest: &Manifest, context: &Value) -> Re
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::infrastructure::command::RecordingCommandExecutor;
     use serde_json::json;
     use std::collections::HashMap;
     use std::fs;
     use std::path::PathBuf;
     use tempfile::TempDir;
 
+    #[test]
+    fn is_enabled_treats_empty_and_false_values_as_false() {
+        let context = json!({"work_machine": false, "gpu": "nvidia", "system": {"os": "linux"}});
+        let origin = Path::new("manifest.yaml");
+        let enabled = |expression| is_enabled(expression, &context, origin).unwrap();
+
+        assert!(!enabled("{{work_machine}}"));
+        assert!(!enabled("{{missing}}"));
+        assert!(!enabled(" Off "));
+        assert!(enabled("{{gpu}}"));
+        assert!(enabled("{{#if (eq system.os \"linux\")}}yes{{/if}}"));
+        assert!(is_enabled("{{#if}}", &context, origin).is_err());
+    }
+
     #[test]
     fn build_context_merges_values_and_secrets() {
         let mut values = HashMap::new();
@@ -89,9 +326,13 @@ mod tests {
         let mut secrets = HashMap::new();
         secrets.insert("token".to_string(), json!("secret"));
 
-        let context = build_context(&values, &secrets);
+        let mut system = serde_json::Map::new();
+        system.insert("brew_prefix".to_string(), json!("/opt/homebrew"));
+
+        let context = build_context(&values, &secrets, &system);
 
         assert_eq!(context["user"], json!("dotstrap"));
+        assert_eq!(context["system"]["brew_prefix"], json!("/opt/homebrew"));
         assert_eq!(context["secrets"]["token"], json!("secret"));
         assert_eq!(
             context
@@ -115,12 +356,23 @@ mod tests {
                 source: PathBuf::from("greeting.hbs"),
                 destination: PathBuf::from(".config/greeting.txt"),
                 mode: Some(0o640),
+                when: None,
+                enabled: None,
+                copy: false,
+                overwrite: Default::default(),
             }],
+            ..Manifest::default()
         };
         let context = json!({ "name": "Dotstrap" });
 
-        let rendered_set = render_templates(repo_dir.path(), &manifest, &context)
-            .expect("rendering should succeed");
+        let rendered_set = render_templates(
+            repo_dir.path(),
+            &manifest,
+            &context,
+            Permissions::default(),
+            &RecordingCommandExecutor::default(),
+        )
+        .expect("rendering should succeed");
 
         assert_eq!(rendered_set.templates.len(), 1, "one template expected");
         let rendered = &rendered_set.templates[0];
@@ -133,6 +385,121 @@ mod tests {
         assert_eq!(contents, "Hello Dotstrap!");
     }
 
+    #[test]
+    fn input_digest_only_depends_on_referenced_values() {
+        let repo = TempDir::new().unwrap();
+        fs::write(repo.path().join("gitconfig.hbs"), "email = {{user.email}}").unwrap();
+        let template = TemplateMapping {
+            source: PathBuf::from("gitconfig.hbs"),
+            destination: PathBuf::from(".gitconfig"),
+            mode: None,
+            when: None,
+            enabled: None,
+            copy: false,
+            overwrite: Default::default(),
+        };
+        let digest = |context: Value| input_digest(repo.path(), &template, &context, 1024).unwrap();
+
+        let original = digest(json!({ "user": { "email": "a@b.c" }, "theme": "dark" }));
+
+        assert_eq!(
+            original,
+            digest(json!({ "user": { "email": "a@b.c" }, "theme": "light" }))
+        );
+        assert_ne!(
+            original,
+            digest(json!({ "user": { "email": "x@b.c" }, "theme": "dark" }))
+        );
+        fs::write(repo.path().join("gitconfig.hbs"), "mail = {{user.email}}").unwrap();
+        assert_ne!(
+            original,
+            digest(json!({ "user": { "email": "a@b.c" }, "theme": "dark" }))
+        );
+    }
+
+    #[test]
+    fn input_digest_depends_on_included_files() {
+        let repo = TempDir::new().unwrap();
+        fs::write(repo.path().join("zshrc.hbs"), "{{include \"header.txt\"}}").unwrap();
+        fs::write(repo.path().join("header.txt"), "# managed\n").unwrap();
+        let template = TemplateMapping {
+            source: PathBuf::from("zshrc.hbs"),
+            destination: PathBuf::from(".zshrc"),
+            mode: None,
+            when: None,
+            enabled: None,
+            copy: false,
+            overwrite: Default::default(),
+        };
+        let digest = || input_digest(repo.path(), &template, &json!({}), 1024).unwrap();
+
+        let original = digest();
+        assert_eq!(original, digest());
+        fs::write(repo.path().join("header.txt"), "# managed by dotstrap\n").unwrap();
+        assert_ne!(original, digest());
+
+        fs::write(repo.path().join("zshrc.hbs"), "{{include header}}").unwrap();
+        assert_ne!(digest(), digest(), "dynamic includes are always rendered");
+
+        fs::write(repo.path().join("zshrc.hbs"), "{{expanduser \"~/bin\"}}").unwrap();
+        let home = |home| {
+            input_digest(
+                repo.path(),
+                &template,
+                &json!({"system": {"home": home}}),
+                1024,
+            )
+            .unwrap()
+        };
+        assert_eq!(home("/home/a"), home("/home/a"));
+        assert_ne!(home("/home/a"), home("/home/b"));
+
+        fs::write(repo.path().join("zshrc.hbs"), "{{exec \"brew --prefix\"}}").unwrap();
+        assert_ne!(digest(), digest(), "commands are run again on every run");
+    }
+
+    #[test]
+    fn referenced_context_keeps_everything_for_dynamic_lookups() {
+        let context = json!({ "a": 1, "b": 2 });
+
+        assert_eq!(
+            referenced_context("{{#each this}}{{@key}}{{/each}}", &context),
+            context
+        );
+        assert_eq!(referenced_context("{{lookup . name}}", &context), context);
+        assert_eq!(
+            referenced_context("{{#if a}}{{b}}{{/if}}", &context),
+            context
+        );
+        assert_eq!(referenced_context("{{a}} b", &context), json!({ "a": 1 }));
+    }
+
+    #[test]
+    fn escape_literal_renders_verbatim() {
+        let text = "{{name}} and {{{raw}}}";
+
+        let rendered = render_inline(&escape_literal(text), &json!({"name": "x"}), Path::new("t"));
+
+        assert_eq!(rendered.unwrap(), text);
+    }
+
+    #[test]
+    fn render_inline_does_not_escape_shell_syntax() {
+        let context = json!({ "dir": "~/backups" });
+
+        let rendered = render_inline(
+            "tar -czf {{dir}}/home.tgz ~ > /dev/null && echo ok",
+            &context,
+            Path::new("manifest.yaml"),
+        )
+        .expect("inline rendering should succeed");
+
+        assert_eq!(
+            rendered,
+            "tar -czf ~/backups/home.tgz ~ > /dev/null && echo ok"
+        );
+    }
+
     #[test]
     fn render_templates_propagates_compile_errors() {
         let repo_dir = TempDir::new().expect("failed to create repo tempdir");
@@ -145,11 +512,22 @@ mod tests {
                 source: PathBuf::from("broken.hbs"),
                 destination: PathBuf::from("ignored.txt"),
                 mode: None,
+                when: None,
+                enabled: None,
+                copy: false,
+                overwrite: Default::default(),
             }],
+            ..Manifest::default()
         };
         let context = json!({ "user": true });
 
-        let error = match render_templates(repo_dir.path(), &manifest, &context) {
+        let error = match render_templates(
+            repo_dir.path(),
+            &manifest,
+            &context,
+            Permissions::default(),
+            &RecordingCommandExecutor::default(),
+        ) {
             Err(err) => err,
             Ok(_) => panic!("expected a compile error due to mismatched block"),
         };
@@ -161,4 +539,110 @@ mod tests {
             other => panic!("unexpected error variant: {other:?}"),
         }
     }
+
+    #[test]
+    fn render_templates_copies_sources_marked_copy_and_limits_the_others() {
+        let repo_dir = TempDir::new().unwrap();
+        fs::write(repo_dir.path().join("font.ttf"), [0xff, b'{', b'{', 0x00]).unwrap();
+        fs::write(repo_dir.path().join("big.hbs"), "x".repeat(64)).unwrap();
+        let mapping = |source: &str, copy: bool| TemplateMapping {
+            source: PathBuf::from(source),
+            destination: PathBuf::from(source),
+            mode: None,
+            when: None,
+            enabled: None,
+            copy,
+            overwrite: Default::default(),
+        };
+        let mut manifest = Manifest {
+            templates: vec![mapping("font.ttf", true)],
+            max_template_size: Some(16),
+            ..Manifest::default()
+        };
+
+        let rendered = render_templates(
+            repo_dir.path(),
+            &manifest,
+            &json!({}),
+            Permissions::default(),
+            &RecordingCommandExecutor::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            rendered.templates[0].rendered_path,
+            repo_dir.path().join("font.ttf")
+        );
+
+        manifest.templates.push(mapping("big.hbs", false));
+        let error = render_templates(
+            repo_dir.path(),
+            &manifest,
+            &json!({}),
+            Permissions::default(),
+            &RecordingCommandExecutor::default(),
+        )
+        .err()
+        .expect("the template is above the limit");
+        assert!(matches!(
+            error,
+            DotstrapError::TemplateTooLarge {
+                size: 64,
+                limit: 16,
+                ..
+            }
+        ));
+        assert!(input_digest(repo_dir.path(), &manifest.templates[0], &json!({}), 16).is_ok());
+    }
+
+    #[test]
+    fn render_templates_runs_exec_commands_once_when_allowed() {
+        let repo_dir = TempDir::new().unwrap();
+        fs::write(
+            repo_dir.path().join("env.hbs"),
+            "PREFIX={{exec \"brew --prefix\"}}\nBIN={{exec \"brew --prefix\"}}/bin\n",
+        )
+        .unwrap();
+        let manifest = Manifest {
+            templates: vec![TemplateMapping {
+                source: PathBuf::from("env.hbs"),
+                destination: PathBuf::from(".env"),
+                mode: None,
+                when: None,
+                enabled: None,
+                copy: false,
+                overwrite: Default::default(),
+            }],
+            ..Manifest::default()
+        };
+        let (shell, flag) = crate::services::hooks::shell();
+        let executor = RecordingCommandExecutor::default()
+            .with_output(&format!("{shell} {flag} brew --prefix"), "/opt/homebrew\n");
+
+        let refused = render_templates(
+            repo_dir.path(),
+            &manifest,
+            &json!({}),
+            Permissions::default(),
+            &executor,
+        )
+        .err()
+        .expect("exec is off by default");
+        assert!(matches!(
+            refused,
+            DotstrapError::TemplateExecNotAllowed { .. }
+        ));
+        assert!(executor.calls().is_empty());
+
+        let allowed = Permissions {
+            exec: true,
+            ..Permissions::default()
+        };
+        let rendered =
+            render_templates(repo_dir.path(), &manifest, &json!({}), allowed, &executor).unwrap();
+        assert_eq!(
+            fs::read_to_string(&rendered.templates[0].rendered_path).unwrap(),
+            "PREFIX=/opt/homebrew\nBIN=/opt/homebrew/bin\n"
+        );
+        assert_eq!(executor.calls().len(), 1);
+    }
 }
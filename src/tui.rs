@@ -0,0 +1,604 @@
+//! Terminal UI of `dotstrap tui`: the plan of a dry run as a tree, the diff
+//! of the selected file, and the progress of the apply that follows.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::application::plan::{self, Action, Step};
+use crate::application::source::source_path;
+use crate::application::watch::render_local;
+use crate::application::{Dotstrap, DotstrapOptions, ExecutionReport, Observer};
+use crate::errors::{DotstrapError, Result};
+use crate::infrastructure::command::{CommandExecutor, SystemCommandExecutor};
+use crate::infrastructure::repository;
+use crate::services::diff;
+use crate::services::outcome::LinkOutcome;
+use crate::services::questions::Confirm;
+use crate::services::templating::Permissions;
+
+/// Lines of progress kept for the progress pane.
+const LOG_LINES: usize = 200;
+
+/// How a session of the terminal UI ended.
+pub(crate) enum Session {
+    /// The plan was closed without applying it.
+    Quit,
+    /// The plan was applied, with this outcome.
+    Applied(Box<Result<ExecutionReport>>),
+}
+
+/// Plan the run `options` describes for `home`, show the plan, and apply it
+/// when asked to.
+///
+/// No prompt can be shown over the interface: questions take their cached
+/// answer or default, and the replacements the plan lists are not confirmed
+/// again.
+pub(crate) fn run(home: &Path, options: DotstrapOptions) -> Result<Session> {
+    let options = DotstrapOptions {
+        interactive: false,
+        review: false,
+        confirm: Confirm::Yes,
+        ..options
+    };
+    let report = Dotstrap::builder()
+        .options(DotstrapOptions {
+            dry_run: true,
+            progress: io::stdout().is_terminal(),
+            ..options.clone()
+        })
+        .build()
+        .run()?;
+    let executor = SystemCommandExecutor::with_timeout(options.command_timeout);
+    let diffs = file_diffs(home, &options, &executor);
+    let mut app = App::new(&report, home, diffs);
+    let mut terminal = ratatui::try_init()?;
+    let session = app.run(
+        &mut terminal,
+        DotstrapOptions {
+            progress: false,
+            ..options
+        },
+    );
+    ratatui::try_restore()?;
+    session
+}
+
+/// Diff between each file of `home` and the template that would replace it,
+/// rendered from the working copy `dotstrap source-path` reports, or why the
+/// templates could not be rendered.
+fn file_diffs(
+    home: &Path,
+    options: &DotstrapOptions,
+    executor: &dyn CommandExecutor,
+) -> std::result::Result<BTreeMap<PathBuf, String>, String> {
+    let diffs = || -> Result<BTreeMap<PathBuf, String>> {
+        let source = options
+            .source
+            .as_deref()
+            .ok_or(DotstrapError::SourceMissing)?;
+        let layer = source_path(home, source, options.subdir.as_deref(), executor)?;
+        let repo = repository::resolve_repository(&layer.to_string_lossy(), None, executor)?;
+        let rendered = render_local(
+            &repo,
+            home,
+            options.profile.as_deref(),
+            Permissions {
+                plugins: options.allow_plugins,
+                exec: options.allow_template_exec,
+            },
+            executor,
+        )?;
+        rendered
+            .templates
+            .iter()
+            .map(|item| {
+                let destination = home.join(&item.template.destination);
+                let diff = diff::file_diff(&destination, &item.rendered_path)?;
+                Ok((destination, diff))
+            })
+            .collect()
+    };
+    diffs().map_err(|err| err.to_string())
+}
+
+/// Event of the apply, sent from the thread running it.
+enum RunEvent {
+    Phase(String),
+    Linked(PathBuf),
+    Command(String),
+    Finished(Box<Result<ExecutionReport>>),
+}
+
+/// Observer forwarding the events of the apply to the interface.
+struct ChannelObserver(Sender<RunEvent>);
+
+impl Observer for ChannelObserver {
+    fn on_phase_start(&self, phase: &str) {
+        let _ = self.0.send(RunEvent::Phase(phase.to_string()));
+    }
+
+    fn on_link_created(&self, link: &LinkOutcome) {
+        let _ = self.0.send(RunEvent::Linked(link.path.clone()));
+    }
+
+    fn on_command(&self, program: &str, args: &[&str]) {
+        let command = std::iter::once(program)
+            .chain(args.iter().copied())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let _ = self.0.send(RunEvent::Command(command));
+    }
+}
+
+/// What the interface asks of the loop driving it after a key press.
+#[derive(Debug, PartialEq, Eq)]
+enum Request {
+    Apply,
+    Quit,
+}
+
+/// Where the session stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Planned,
+    Applying,
+    Finished,
+}
+
+/// A step of the plan, with the file it changes, if any.
+struct Entry {
+    step: Step,
+    path: Option<PathBuf>,
+    done: bool,
+}
+
+/// Steps of the plan of one kind, a branch of the tree.
+struct Group {
+    title: &'static str,
+    entries: Vec<Entry>,
+    expanded: bool,
+}
+
+/// Visible row of the tree: a group, or one of the entries of an expanded
+/// group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Row {
+    Group(usize),
+    Entry(usize, usize),
+}
+
+/// State of the interface.
+struct App {
+    groups: Vec<Group>,
+    diffs: std::result::Result<BTreeMap<PathBuf, String>, String>,
+    selected: usize,
+    scroll: u16,
+    stage: Stage,
+    phase: Option<String>,
+    log: VecDeque<String>,
+    outcome: Option<Box<Result<ExecutionReport>>>,
+}
+
+impl App {
+    fn new(
+        report: &ExecutionReport,
+        home: &Path,
+        diffs: std::result::Result<BTreeMap<PathBuf, String>, String>,
+    ) -> Self {
+        let mut groups: Vec<Group> = ["Files", "Commands", "Repositories", "Settings"]
+            .into_iter()
+            .map(|title| Group {
+                title,
+                entries: Vec::new(),
+                expanded: true,
+            })
+            .collect();
+        for step in plan::steps(report, Some(home)) {
+            let (group, path) = match step.action {
+                Action::Create | Action::Overwrite => {
+                    let path = match step.target.strip_prefix("~/") {
+                        Some(relative) => home.join(relative),
+                        None => PathBuf::from(&step.target),
+                    };
+                    (0, Some(path))
+                }
+                Action::Run => (1, None),
+                Action::Clone => (2, None),
+                Action::Set => (3, None),
+                Action::Keep | Action::Skip => continue,
+            };
+            groups[group].entries.push(Entry {
+                step,
+                path,
+                done: false,
+            });
+        }
+        groups.retain(|group| !group.entries.is_empty());
+        App {
+            groups,
+            diffs,
+            selected: 0,
+            scroll: 0,
+            stage: Stage::Planned,
+            phase: None,
+            log: VecDeque::new(),
+            outcome: None,
+        }
+    }
+
+    /// Show the interface on `terminal` until it is closed, applying the run
+    /// `options` describes when asked to.
+    fn run(&mut self, terminal: &mut DefaultTerminal, options: DotstrapOptions) -> Result<Session> {
+        let (sender, receiver) = mpsc::channel();
+        let mut options = Some(options);
+        loop {
+            while let Ok(event) = receiver.try_recv() {
+                self.handle(event);
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+            if !event::poll(Duration::from_millis(100))? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match self.on_key(key.code) {
+                Some(Request::Apply) => {
+                    if let Some(options) = options.take() {
+                        spawn_apply(options, sender.clone());
+                    }
+                }
+                Some(Request::Quit) => {
+                    return Ok(match self.outcome.take() {
+                        Some(outcome) => Session::Applied(outcome),
+                        None => Session::Quit,
+                    });
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn rows(&self) -> Vec<Row> {
+        let mut rows = Vec::new();
+        for (index, group) in self.groups.iter().enumerate() {
+            rows.push(Row::Group(index));
+            if group.expanded {
+                rows.extend((0..group.entries.len()).map(|entry| Row::Entry(index, entry)));
+            }
+        }
+        rows
+    }
+
+    fn selected_row(&self) -> Option<Row> {
+        self.rows().get(self.selected).copied()
+    }
+
+    fn select(&mut self, selected: usize) {
+        if selected != self.selected {
+            self.selected = selected;
+            self.scroll = 0;
+        }
+    }
+
+    /// Expand or collapse the group of the selected row, selecting it.
+    fn set_expanded(&mut self, expanded: impl Fn(bool) -> bool) {
+        let group = match self.selected_row() {
+            Some(Row::Group(group) | Row::Entry(group, _)) => group,
+            None => return,
+        };
+        self.groups[group].expanded = expanded(self.groups[group].expanded);
+        let row = self.rows().iter().position(|row| *row == Row::Group(group));
+        self.select(row.unwrap_or(0));
+    }
+
+    fn on_key(&mut self, key: KeyCode) -> Option<Request> {
+        let last = self.rows().len().saturating_sub(1);
+        match key {
+            KeyCode::Up | KeyCode::Char('k') => self.select(self.selected.saturating_sub(1)),
+            KeyCode::Down | KeyCode::Char('j') => self.select((self.selected + 1).min(last)),
+            KeyCode::Home | KeyCode::Char('g') => self.select(0),
+            KeyCode::End | KeyCode::Char('G') => self.select(last),
+            KeyCode::Left | KeyCode::Char('h') => self.set_expanded(|_| false),
+            KeyCode::Right | KeyCode::Char('l') => self.set_expanded(|_| true),
+            KeyCode::Enter | KeyCode::Char(' ') => self.set_expanded(|expanded| !expanded),
+            KeyCode::PageDown | KeyCode::Char('J') => self.scroll = self.scroll.saturating_add(10),
+            KeyCode::PageUp | KeyCode::Char('K') => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Char('a') if self.stage == Stage::Planned => {
+                self.stage = Stage::Applying;
+                self.log.push_back("Applying the plan".to_string());
+                return Some(Request::Apply);
+            }
+            KeyCode::Char('q') | KeyCode::Esc if self.stage != Stage::Applying => {
+                return Some(Request::Quit);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn handle(&mut self, event: RunEvent) {
+        let line = match event {
+            RunEvent::Phase(phase) => {
+                self.phase = Some(phase.clone());
+                phase
+            }
+            RunEvent::Linked(path) => {
+                self.mark_done(|entry| entry.path.as_ref() == Some(&path));
+                format!("linked {}", path.display())
+            }
+            RunEvent::Command(command) => {
+                self.mark_done(|entry| {
+                    entry.path.is_none() && entry.step.target.ends_with(&command)
+                });
+                format!("$ {command}")
+            }
+            RunEvent::Finished(outcome) => {
+                self.stage = Stage::Finished;
+                self.phase = None;
+                let line = match outcome.as_ref() {
+                    Ok(report) => {
+                        self.mark_done(|_| true);
+                        report.summary()
+                    }
+                    Err(err) => format!("dotstrap failed: {err}"),
+                };
+                self.outcome = Some(outcome);
+                line
+            }
+        };
+        if self.log.len() == LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(line);
+    }
+
+    fn mark_done(&mut self, matches: impl Fn(&Entry) -> bool) {
+        self.groups
+            .iter_mut()
+            .flat_map(|group| &mut group.entries)
+            .filter(|entry| matches(entry))
+            .for_each(|entry| entry.done = true);
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, log, status] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [tree, details] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main);
+
+        let rows = self.rows();
+        let items: Vec<ListItem> = rows.iter().map(|row| self.row_line(*row).into()).collect();
+        let items = if items.is_empty() {
+            vec![ListItem::new("Nothing to change")]
+        } else {
+            items
+        };
+        let mut state = ListState::default().with_selected(Some(self.selected));
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(Block::bordered().title(" Plan "))
+                .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+            tree,
+            &mut state,
+        );
+
+        let (title, lines) = self.details(rows.get(self.selected).copied());
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(Block::bordered().title(title))
+                .scroll((self.scroll, 0)),
+            details,
+        );
+
+        let visible = usize::from(log.height.saturating_sub(2));
+        let lines: Vec<Line> = self
+            .log
+            .iter()
+            .skip(self.log.len().saturating_sub(visible))
+            .map(|line| Line::raw(line.as_str()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Progress ")),
+            log,
+        );
+
+        let help = match self.stage {
+            Stage::Planned => "↑↓ move  ←→ fold  PgUp/PgDn scroll  a apply  q quit".to_string(),
+            Stage::Applying => format!("Applying: {}", self.phase.as_deref().unwrap_or("starting")),
+            Stage::Finished => "↑↓ move  ←→ fold  PgUp/PgDn scroll  q quit".to_string(),
+        };
+        frame.render_widget(Paragraph::new(help), status);
+    }
+
+    fn row_line(&self, row: Row) -> Line<'_> {
+        match row {
+            Row::Group(group) => {
+                let group = &self.groups[group];
+                let marker = if group.expanded { "▾" } else { "▸" };
+                Line::from(format!(
+                    "{marker} {} ({})",
+                    group.title,
+                    group.entries.len()
+                ))
+                .style(Style::new().add_modifier(Modifier::BOLD))
+            }
+            Row::Entry(group, entry) => {
+                let entry = &self.groups[group].entries[entry];
+                let (verb, color) = match entry.step.action {
+                    Action::Create => ("create", Color::Green),
+                    Action::Overwrite => ("overwrite", Color::Yellow),
+                    Action::Run => ("run", Color::Cyan),
+                    Action::Clone => ("clone", Color::Green),
+                    Action::Set => ("set", Color::Magenta),
+                    Action::Keep => ("keep", Color::DarkGray),
+                    Action::Skip => ("skip", Color::DarkGray),
+                };
+                let mut spans = vec![
+                    Span::raw(if entry.done { "  ✓ " } else { "    " }),
+                    Span::styled(verb, Style::new().fg(color)),
+                    Span::raw(format!(" {}", entry.step.target)),
+                ];
+                if entry.step.backup {
+                    spans.push(Span::raw(" (backup)"));
+                }
+                Line::from(spans)
+            }
+        }
+    }
+
+    /// Title and contents of the details pane for `row`.
+    fn details(&self, row: Option<Row>) -> (String, Vec<Line<'_>>) {
+        let Some(Row::Entry(group, entry)) = row else {
+            return (" Details ".to_string(), Vec::new());
+        };
+        let entry = &self.groups[group].entries[entry];
+        let Some(path) = &entry.path else {
+            return (
+                " Details ".to_string(),
+                vec![Line::raw(entry.step.target.as_str())],
+            );
+        };
+        let lines = match &self.diffs {
+            Ok(diffs) => match diffs.get(path) {
+                Some(diff) if diff.is_empty() => vec![Line::raw("No change to the contents")],
+                Some(diff) => diff
+                    .lines()
+                    .map(|line| {
+                        let color = match line.chars().next() {
+                            Some('+') => Color::Green,
+                            Some('-') => Color::Red,
+                            _ => Color::Reset,
+                        };
+                        Line::styled(line, Style::new().fg(color))
+                    })
+                    .collect(),
+                None => vec![Line::raw("No diff for this file")],
+            },
+            Err(err) => vec![Line::raw(format!("Diffs unavailable: {err}"))],
+        };
+        (format!(" {} ", entry.step.target), lines)
+    }
+}
+
+/// Apply the run `options` describes on another thread, sending its events
+/// to `sender`.
+fn spawn_apply(options: DotstrapOptions, sender: Sender<RunEvent>) {
+    thread::spawn(move || {
+        let observer = ChannelObserver(sender.clone());
+        let result = Dotstrap::builder()
+            .options(options)
+            .observer(&observer)
+            .build()
+            .run();
+        let _ = sender.send(RunEvent::Finished(Box::new(result)));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::outcome::ItemStatus;
+    use ratatui::Terminal;
+    use ratatui::backend::TestBackend;
+
+    fn app() -> App {
+        let home = Path::new("/home/me");
+        let report = ExecutionReport {
+            linked: vec![
+                LinkOutcome::new(home.join(".zshrc"), ItemStatus::Created),
+                LinkOutcome::new(home.join(".gitconfig"), ItemStatus::Unchanged),
+            ],
+            package_commands: vec!["brew install fzf".into()],
+            dry_run: true,
+            ..ExecutionReport::default()
+        };
+        let diffs = BTreeMap::from([(home.join(".zshrc"), "+export EDITOR=vi\n".to_string())]);
+        App::new(&report, home, Ok(diffs))
+    }
+
+    fn screen(app: &App) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(80, 20)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        terminal.backend().to_string()
+    }
+
+    #[test]
+    fn plan_is_grouped_into_a_tree_that_folds() {
+        let mut app = app();
+
+        assert_eq!(
+            app.rows(),
+            [
+                Row::Group(0),
+                Row::Entry(0, 0),
+                Row::Group(1),
+                Row::Entry(1, 0)
+            ]
+        );
+        app.on_key(KeyCode::Down);
+        app.on_key(KeyCode::Left);
+        assert_eq!(app.rows(), [Row::Group(0), Row::Group(1), Row::Entry(1, 0)]);
+        assert_eq!(app.selected_row(), Some(Row::Group(0)));
+        app.on_key(KeyCode::Enter);
+        app.on_key(KeyCode::End);
+        assert_eq!(app.selected_row(), Some(Row::Entry(1, 0)));
+    }
+
+    #[test]
+    fn screen_shows_the_plan_and_the_diff_of_the_selected_file() {
+        let mut app = app();
+        app.on_key(KeyCode::Down);
+
+        let screen = screen(&app);
+
+        assert!(screen.contains("▾ Files (1)"));
+        assert!(screen.contains("create ~/.zshrc"));
+        assert!(screen.contains("run brew install fzf"));
+        assert!(screen.contains("+export EDITOR=vi"));
+    }
+
+    #[test]
+    fn apply_events_mark_steps_done_until_the_run_finishes() {
+        let mut app = app();
+
+        assert_eq!(app.on_key(KeyCode::Char('a')), Some(Request::Apply));
+        assert_eq!(app.on_key(KeyCode::Char('q')), None, "cannot quit mid-run");
+        app.handle(RunEvent::Phase("Linking dotfiles".into()));
+        app.handle(RunEvent::Linked(PathBuf::from("/home/me/.zshrc")));
+        assert!(app.groups[0].entries[0].done);
+        assert!(!app.groups[1].entries[0].done);
+        assert!(screen(&app).contains("Applying: Linking dotfiles"));
+
+        app.handle(RunEvent::Command("brew install fzf".into()));
+        assert!(app.groups[1].entries[0].done);
+        app.handle(RunEvent::Finished(Box::new(Err(
+            DotstrapError::HomeNotFound,
+        ))));
+        assert_eq!(app.stage, Stage::Finished);
+        assert_eq!(
+            app.log.back().map(String::as_str),
+            Some("dotstrap failed: failed to determine home directory")
+        );
+        assert_eq!(app.on_key(KeyCode::Char('q')), Some(Request::Quit));
+    }
+}
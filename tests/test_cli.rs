@@ -25,9 +25,9 @@ fn test_dry_run() {
         .arg("tests/config-brew")
         .arg("--dry-run")
         .assert()
-        .failure()
-        .stderr(predicates::str::contains(
-            "dotstrap failed: Homebrew is not installed or not executable\n",
+        .success()
+        .stdout(predicates::str::contains(
+            "Dry run complete: 3 templates evaluated.",
         ));
 }
 
@@ -79,3 +79,1519 @@ fn test_generate_zsh_completions() {
         .success()
         .stdout(predicates::str::contains("_dotstrap"));
 }
+
+#[test]
+#[cfg(unix)]
+fn test_install_completions_for_the_current_shell() {
+    let home = tempfile::TempDir::new().unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["--generate-completions", "--install"])
+        .env("SHELL", "/bin/zsh")
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Installed zsh completions"));
+
+    let script = home.path().join(".zsh/completions/_dotstrap");
+    assert!(
+        std::fs::read_to_string(script)
+            .unwrap()
+            .contains("_dotstrap")
+    );
+    let zshrc = std::fs::read_to_string(home.path().join(".zshrc")).unwrap();
+    assert!(zshrc.contains("# BEGIN dotstrap managed block"));
+}
+
+#[test]
+fn test_help_topics_and_manpages() {
+    let dotstrap = |args: &[&str]| {
+        let mut command = Command::cargo_bin("dotstrap").unwrap();
+        command.args(args);
+        command
+    };
+
+    dotstrap(&["help", "secrets"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("dotstrap-secret-<name>"));
+    dotstrap(&["help", "export", "brewfile"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Usage: dotstrap export brewfile"));
+    dotstrap(&["help", "nope"])
+        .assert()
+        .code(2)
+        .stderr(predicates::str::contains("manifest"));
+
+    let dir = tempfile::TempDir::new().unwrap();
+    dotstrap(&["--generate-manpages"])
+        .arg(dir.path())
+        .assert()
+        .success();
+    assert!(dir.path().join("dotstrap.1").is_file());
+    assert!(dir.path().join("dotstrap-manifest.7").is_file());
+}
+
+#[test]
+fn test_dry_run_json_output() {
+    let output = Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg("tests/empty-config")
+        .arg("--dry-run")
+        .arg("--output")
+        .arg("json")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["dry_run"], serde_json::json!(true));
+    assert_eq!(report["rendered"].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn test_yaml_output_reports_errors() {
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg("tests/config-invalid")
+        .arg("--dry-run")
+        .arg("--retries")
+        .arg("0")
+        .arg("--output")
+        .arg("yaml")
+        .assert()
+        .failure()
+        .stdout(predicates::str::starts_with("error: "));
+}
+
+#[test]
+fn test_very_verbose_shows_rendered_sizes() {
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg("tests/empty-config")
+        .arg("--dry-run")
+        .arg("-vv")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("template context keys:"))
+        .stderr(predicates::str::contains("rendered templates/zshrc.hbs ("));
+}
+
+#[test]
+fn test_quiet_prints_nothing_on_success() {
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg("tests/empty-config")
+        .arg("--dry-run")
+        .arg("--quiet")
+        .assert()
+        .success()
+        .stdout(predicates::str::is_empty())
+        .stderr(predicates::str::is_empty());
+}
+
+#[test]
+fn test_dry_run_prints_plan() {
+    let home = tempfile::TempDir::new().unwrap();
+    std::fs::write(home.path().join(".zshrc"), "old").unwrap();
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg("tests/empty-config")
+        .arg("--home")
+        .arg(home.path())
+        .arg("--dry-run")
+        .arg("--color")
+        .arg("never")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("create ~/.gitconfig\n"))
+        .stdout(predicates::str::contains("overwrite ~/.zshrc (backup)\n"))
+        .stdout(predicates::str::contains("2 to create, 1 to overwrite"));
+}
+
+#[test]
+fn test_exit_codes_distinguish_failures() {
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg("--invalid-flag")
+        .assert()
+        .code(2);
+
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::write(source.path().join("manifest.yaml"), "templates: [").unwrap();
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(source.path())
+        .arg("--dry-run")
+        .assert()
+        .code(3);
+}
+
+#[test]
+fn test_history_lists_and_shows_runs() {
+    let home = tempfile::TempDir::new().unwrap();
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg("tests/empty-config")
+        .arg("--home")
+        .arg(home.path())
+        .assert()
+        .success();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["history", "--home"])
+        .arg(home.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("   1  "))
+        .stdout(predicates::str::contains("ok      tests/empty-config"));
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["history", "show", "1", "--home"])
+        .arg(home.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("created   "));
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["history", "show", "2", "--home"])
+        .arg(home.path())
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("no run with id 2"));
+}
+
+#[test]
+fn test_rollback_restores_replaced_files() {
+    let home = tempfile::TempDir::new().unwrap();
+    let zshrc = home.path().join(".zshrc");
+    std::fs::write(&zshrc, "mine").unwrap();
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg("tests/empty-config")
+        .arg("--home")
+        .arg(home.path())
+        .assert()
+        .success();
+    assert!(zshrc.is_symlink());
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["rollback", "--home"])
+        .arg(home.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "Rolled back run 1: 3 path(s) restored.",
+        ));
+
+    assert_eq!(std::fs::read_to_string(&zshrc).unwrap(), "mine");
+    assert!(!home.path().join(".gitconfig").exists());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_sync_install_schedule_dry_run() {
+    let home = tempfile::TempDir::new().unwrap();
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args([
+            "sync",
+            "tests/empty-config",
+            "--install-schedule",
+            "--every",
+            "30m",
+        ])
+        .arg("--home")
+        .arg(home.path())
+        .arg("--dry-run")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "run systemctl --user enable --now dotstrap-sync.timer",
+        ));
+    assert!(!home.path().join(".config").exists());
+}
+
+#[test]
+fn test_questions_use_cached_answers_and_defaults() {
+    let home = tempfile::TempDir::new().unwrap();
+    let answers = home.path().join(".config/dotstrap");
+    std::fs::create_dir_all(&answers).unwrap();
+    std::fs::write(answers.join("answers.yaml"), "work_machine: true\n").unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg("tests/config-questions")
+        .arg("--home")
+        .arg(home.path())
+        .assert()
+        .success();
+
+    let profile = std::fs::read_to_string(home.path().join(".profile")).unwrap();
+    assert!(profile.contains("export EDITOR=vim\n"));
+    assert!(profile.contains("export WORK=1\n"));
+}
+
+#[test]
+fn test_migrate_upgrades_v1_manifest() {
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(source.path().join("templates")).unwrap();
+    std::fs::write(source.path().join("templates/zshrc.hbs"), "").unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 1\ntemplates:\n  - source: templates/zshrc.hbs\n    destination: .zshrc\nprofiles:\n  work: {}\n",
+    )
+    .unwrap();
+    std::fs::write(source.path().join("packages.yaml"), "cargo: [bat]\n").unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(source.path())
+        .arg("--dry-run")
+        .assert()
+        .code(3)
+        .stderr(predicates::str::contains("run `dotstrap migrate`"));
+
+    let home = tempfile::TempDir::new().unwrap();
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg("migrate")
+        .arg(source.path())
+        .arg("--home")
+        .arg(home.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("from version 1 to 2"));
+
+    let manifest = std::fs::read_to_string(source.path().join("manifest.yaml")).unwrap();
+    assert!(manifest.starts_with("version: 2\n"));
+    assert!(manifest.ends_with("packages:\n  cargo: [bat]\n"));
+    assert!(!source.path().join("packages.yaml").exists());
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(source.path())
+        .args(["--profile", "work", "--dry-run"])
+        .assert()
+        .success();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_user_config_supplies_default_flags() {
+    let user = tempfile::TempDir::new().unwrap();
+    let target = user.path().join("target");
+    let config = user.path().join(".config/dotstrap");
+    std::fs::create_dir_all(&config).unwrap();
+    let source = std::fs::canonicalize("tests/empty-config").unwrap();
+    std::fs::write(
+        config.join("config.yaml"),
+        format!(
+            "source: {}\nhome: {}\nskip_brew: true\n",
+            source.display(),
+            target.display()
+        ),
+    )
+    .unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg("apply")
+        .env("HOME", user.path())
+        .assert()
+        .success();
+    assert!(target.join(".zshrc").is_symlink());
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["apply", "--dry-run"])
+        .env("HOME", user.path())
+        .env("DOTSTRAP_SOURCE", "tests/missing-config")
+        .assert()
+        .failure();
+}
+
+#[test]
+#[cfg(unix)]
+fn test_bare_apply_reuses_last_source() {
+    let home = tempfile::TempDir::new().unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg("apply")
+        .arg("tests/empty-config")
+        .arg("--home")
+        .arg(home.path())
+        .arg("--skip-brew")
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    std::fs::remove_file(home.path().join(".zshrc")).unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["apply", "--skip-brew", "-v"])
+        .arg("--home")
+        .arg(home.path())
+        .env("HOME", home.path())
+        .current_dir(home.path())
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("from the last run"));
+    assert!(home.path().join(".zshrc").is_symlink());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_re_add_captures_edits_into_template() {
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 2\ntemplates:\n  - source: zshrc.hbs\n    destination: .zshrc\n",
+    )
+    .unwrap();
+    std::fs::write(source.path().join("values.yaml"), "editor: hx\n").unwrap();
+    std::fs::write(
+        source.path().join("zshrc.hbs"),
+        "export EDITOR={{editor}}\nalias ll='ls -l'\n",
+    )
+    .unwrap();
+    let home = tempfile::TempDir::new().unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(source.path())
+        .arg("--home")
+        .arg(home.path())
+        .arg("--skip-brew")
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    std::fs::write(
+        home.path().join(".zshrc"),
+        "export EDITOR=hx\nalias ll='ls -la'\n",
+    )
+    .unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["re-add", ".zshrc", "--home"])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Updated"));
+    assert_eq!(
+        std::fs::read_to_string(source.path().join("zshrc.hbs")).unwrap(),
+        "export EDITOR={{editor}}\nalias ll='ls -la'\n"
+    );
+}
+
+#[test]
+fn test_import_stow_directory() {
+    let stow = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(stow.path().join("zsh")).unwrap();
+    std::fs::write(stow.path().join("zsh/.zshrc"), "PROMPT='{{x}}'\n").unwrap();
+    let into = tempfile::TempDir::new().unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["import", "--from", "stow"])
+        .arg(stow.path())
+        .arg(into.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "templates/zsh/.zshrc -> ~/.zshrc",
+        ));
+
+    let home = tempfile::TempDir::new().unwrap();
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(into.path())
+        .arg("--home")
+        .arg(home.path())
+        .args(["--skip-brew", "--dry-run"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_export_brewfile_prints_brew_packages() {
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["export", "brewfile", "tests/config-brewfile"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "tap \"homebrew/cask-fonts\"\nbrew \"ripgrep\"\n",
+        ));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_owner_reports_template_of_applied_file() {
+    let home = tempfile::TempDir::new().unwrap();
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg("tests/empty-config")
+        .arg("--home")
+        .arg(home.path())
+        .arg("--skip-brew")
+        .env("HOME", home.path())
+        .assert()
+        .success();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["owner", ".gitconfig", "--home"])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "template: templates/gitconfig.hbs",
+        ))
+        .stdout(predicates::str::contains("empty-config"));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_list_templates_reports_link_status() {
+    let home = tempfile::TempDir::new().unwrap();
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg("tests/empty-config")
+        .arg("--home")
+        .arg(home.path())
+        .arg("--skip-brew")
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    std::fs::remove_file(home.path().join(".zshrc")).unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["list", "templates", "--home"])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(
+            predicates::str::is_match(r"\.gitconfig\s+templates/gitconfig\.hbs\s+-\s+linked")
+                .unwrap(),
+        )
+        .stdout(predicates::str::is_match(r"\.zshrc\s+templates/zshrc\.hbs\s+-\s+missing").unwrap())
+        .stdout(predicates::prelude::PredicateBooleanExt::not(
+            predicates::str::contains("Packages"),
+        ));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_lint_reports_undefined_variables_with_their_location() {
+    let home = tempfile::TempDir::new().unwrap();
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["tests/empty-config", "lint", "--home"])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .code(1)
+        .stdout(predicates::str::contains(
+            "templates/gitconfig.hbs:10:13: error: undefined variable `secrets.github_token`",
+        ))
+        .stdout(predicates::str::contains("2 error(s), 0 warning(s)."));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_test_compares_templates_with_their_expected_outputs() {
+    let home = tempfile::TempDir::new().unwrap();
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 2\ntemplates:\n  - source: rc.hbs\n    destination: .rc\n",
+    )
+    .unwrap();
+    std::fs::write(source.path().join("rc.hbs"), "name={{name}}\n").unwrap();
+    std::fs::create_dir_all(source.path().join("tests/default")).unwrap();
+    std::fs::write(
+        source.path().join("tests/default/context.yaml"),
+        "values:\n  name: me\n",
+    )
+    .unwrap();
+    let dotstrap = |args: &[&str]| {
+        let mut command = Command::cargo_bin("dotstrap").unwrap();
+        command
+            .arg(source.path())
+            .args(args)
+            .env("HOME", home.path());
+        command
+    };
+
+    dotstrap(&["test", "--update"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "test default ... updated (1 file(s))",
+        ));
+    assert_eq!(
+        std::fs::read_to_string(source.path().join("tests/default/expected/.rc")).unwrap(),
+        "name=me\n"
+    );
+    dotstrap(&["test"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "test result: 1 passed; 0 failed.",
+        ));
+
+    std::fs::write(source.path().join("rc.hbs"), "user={{name}}\n").unwrap();
+    dotstrap(&["test"])
+        .assert()
+        .code(1)
+        .stdout(predicates::str::contains(
+            ".rc differs:\n-name=me\n+user=me\n",
+        ));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_apply_plan_links_the_files_of_a_saved_plan() {
+    let home = tempfile::TempDir::new().unwrap();
+    let plan = home.path().join("plan.json");
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["tests/empty-config", "plan", "--skip-brew", "-o"])
+        .arg(&plan)
+        .arg("--home")
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "3 file(s) to link, 0 command(s) to run",
+        ));
+    assert!(!home.path().join(".zshrc").exists());
+
+    let apply = || {
+        let mut command = Command::cargo_bin("dotstrap").unwrap();
+        command
+            .args(["apply", "--plan"])
+            .arg(&plan)
+            .arg("--home")
+            .arg(home.path())
+            .env("HOME", home.path());
+        command
+    };
+    apply()
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("3 created"));
+    assert!(
+        std::fs::read_to_string(home.path().join(".zshrc"))
+            .unwrap()
+            .contains("export EDITOR=nvim")
+    );
+    apply()
+        .assert()
+        .code(1)
+        .stderr(predicates::str::contains("changed since the plan was made"));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_command_output_is_streamed_with_its_name() {
+    let home = tempfile::TempDir::new().unwrap();
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 2\ntemplates:\n  - source: rc\n    destination: .rc\nhooks:\n  pre_apply:\n    - echo streamed output\n",
+    )
+    .unwrap();
+    std::fs::write(source.path().join("rc"), "").unwrap();
+    let apply = |quiet: bool| {
+        let mut command = Command::cargo_bin("dotstrap").unwrap();
+        command
+            .arg(source.path())
+            .arg("--home")
+            .arg(home.path())
+            .arg("--skip-brew")
+            .env("HOME", home.path());
+        if quiet {
+            command.arg("--quiet");
+        }
+        command.assert().success()
+    };
+
+    apply(false).stderr(predicates::str::contains("[sh] streamed output"));
+    apply(true).stderr(predicates::str::is_empty());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_plugins_only_run_when_allowed() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let home = tempfile::TempDir::new().unwrap();
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 2\ntemplates:\n  - source: rc.hbs\n    destination: .rc\nplugins:\n  - name: shout\n    command: plugins/shout.sh\n",
+    )
+    .unwrap();
+    std::fs::write(source.path().join("rc.hbs"), "{{shout \"hi\"}}\n").unwrap();
+    std::fs::create_dir(source.path().join("plugins")).unwrap();
+    let plugin = source.path().join("plugins/shout.sh");
+    std::fs::write(&plugin, "#!/bin/sh\necho HI\n").unwrap();
+    std::fs::set_permissions(&plugin, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let apply = |allow: bool| {
+        let mut command = Command::cargo_bin("dotstrap").unwrap();
+        command
+            .arg(source.path())
+            .arg("--home")
+            .arg(home.path())
+            .arg("--skip-brew")
+            .env("HOME", home.path())
+            .env_remove("DOTSTRAP_ALLOW_PLUGINS");
+        if allow {
+            command.arg("--allow-plugins");
+        }
+        command.assert()
+    };
+
+    apply(false)
+        .code(3)
+        .stderr(predicates::str::contains("pass --allow-plugins"));
+    apply(true).success();
+    assert_eq!(
+        std::fs::read_to_string(home.path().join(".rc")).unwrap(),
+        "HI\n"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_template_exec_only_runs_when_allowed() {
+    let home = tempfile::TempDir::new().unwrap();
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 2\ntemplates:\n  - source: rc.hbs\n    destination: .rc\n",
+    )
+    .unwrap();
+    std::fs::write(
+        source.path().join("rc.hbs"),
+        "theme={{exec \"printf '  dark & light\\\\n'\"}}\n",
+    )
+    .unwrap();
+    let apply = |allow: bool| {
+        let mut command = Command::cargo_bin("dotstrap").unwrap();
+        command
+            .arg(source.path())
+            .arg("--home")
+            .arg(home.path())
+            .arg("--skip-brew")
+            .env("HOME", home.path())
+            .env_remove("DOTSTRAP_ALLOW_TEMPLATE_EXEC");
+        if allow {
+            command.arg("--allow-template-exec");
+        }
+        command.assert()
+    };
+
+    apply(false)
+        .code(3)
+        .stderr(predicates::str::contains("pass --allow-template-exec"));
+    assert!(!home.path().join(".rc").exists());
+    apply(true).success();
+    assert_eq!(
+        std::fs::read_to_string(home.path().join(".rc")).unwrap(),
+        "theme=dark & light\n"
+    );
+}
+
+#[test]
+fn test_profile_values_are_deep_merged_and_traced() {
+    let home = tempfile::TempDir::new().unwrap();
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 2\ntemplates:\n  - source: gitconfig.hbs\n    destination: .gitconfig\nprofiles:\n  work:\n    values:\n      git:\n        email: ada@work.example\n",
+    )
+    .unwrap();
+    std::fs::write(
+        source.path().join("values.yaml"),
+        "git:\n  name: Ada\n  email: ada@home.example\n",
+    )
+    .unwrap();
+    std::fs::write(
+        source.path().join("gitconfig.hbs"),
+        "{{git.name}} <{{git.email}}>\n",
+    )
+    .unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(source.path())
+        .arg("--home")
+        .arg(home.path())
+        .arg("--skip-brew")
+        .arg("--profile")
+        .arg("work")
+        .arg("--trace-value")
+        .arg("git.email")
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(
+            "value `git.email` is \"ada@work.example\"",
+        ))
+        .stderr(predicates::str::contains(
+            "values.yaml: `git` = {\"email\":\"ada@home.example\",\"name\":\"Ada\"} (overridden)",
+        ))
+        .stderr(predicates::str::contains(
+            "profile `work`: `git.email` = \"ada@work.example\" (wins)",
+        ));
+    assert_eq!(
+        std::fs::read_to_string(home.path().join(".gitconfig")).unwrap(),
+        "Ada <ada@work.example>\n"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_source_path_and_cd_open_the_source_directory() {
+    let home = tempfile::TempDir::new().unwrap();
+    let source = std::path::Path::new("tests/empty-config")
+        .canonicalize()
+        .unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["tests/empty-config", "source-path", "--home"])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(format!("{}\n", source.display()));
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["tests/empty-config", "cd", "--home"])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .env("SHELL", "sh")
+        .write_stdin("pwd\nexit 3\n")
+        .assert()
+        .code(3)
+        .stdout(format!("{}\n", source.display()));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_edit_opens_the_template_and_applies_it() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let home = tempfile::TempDir::new().unwrap();
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(source.path().join("templates")).unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 1\ntemplates:\n  - source: templates/zshrc.hbs\n    destination: .zshrc\n  - source: templates/vimrc.hbs\n    destination: .vimrc\n",
+    )
+    .unwrap();
+    std::fs::write(source.path().join("templates/zshrc.hbs"), "export A=1\n").unwrap();
+    std::fs::write(source.path().join("templates/vimrc.hbs"), "set nu\n").unwrap();
+    let editor = home.path().join("editor.sh");
+    std::fs::write(&editor, "#!/bin/sh\necho 'export B=2' >> \"$1\"\n").unwrap();
+    std::fs::set_permissions(&editor, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(source.path())
+        .args(["edit", ".zshrc", "--apply", "--home"])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .env_remove("VISUAL")
+        .env("EDITOR", &editor)
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Created"));
+
+    assert_eq!(
+        std::fs::read_to_string(home.path().join(".zshrc")).unwrap(),
+        "export A=1\nexport B=2\n"
+    );
+    assert!(!home.path().join(".vimrc").exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_only_applies_selected_templates_and_keeps_state() {
+    let home = tempfile::TempDir::new().unwrap();
+    let apply = |args: &[&str]| {
+        Command::cargo_bin("dotstrap")
+            .unwrap()
+            .arg("tests/empty-config")
+            .args(args)
+            .arg("--home")
+            .arg(home.path())
+            .arg("--skip-brew")
+            .env("HOME", home.path())
+            .assert()
+            .success();
+    };
+    apply(&["--only", ".zshrc"]);
+    assert!(home.path().join(".zshrc").exists());
+    assert!(!home.path().join(".gitconfig").exists());
+
+    apply(&[]);
+    apply(&["--only", "templates/zsh*"]);
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["owner", ".gitconfig", "--home"])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(predicates::prelude::PredicateBooleanExt::not(
+            predicates::str::contains("not applied yet"),
+        ));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_non_interactive_refuses_to_replace_unmanaged_files() {
+    let home = tempfile::TempDir::new().unwrap();
+    std::fs::write(home.path().join(".zshrc"), "# mine\n").unwrap();
+    let apply = |flag: &str| {
+        Command::cargo_bin("dotstrap")
+            .unwrap()
+            .args(["tests/empty-config", flag, "--skip-brew", "--home"])
+            .arg(home.path())
+            .env("HOME", home.path())
+            .assert()
+    };
+
+    apply("--non-interactive")
+        .failure()
+        .stderr(predicates::str::contains("not confirmed; pass --yes"));
+    assert_eq!(
+        std::fs::read_to_string(home.path().join(".zshrc")).unwrap(),
+        "# mine\n"
+    );
+    apply("--force").success();
+    assert!(home.path().join(".zshrc").is_symlink());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_interactive_asks_before_changing_each_file() {
+    let home = tempfile::TempDir::new().unwrap();
+    std::fs::write(home.path().join(".zshrc"), "# mine\n").unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args([
+            "tests/empty-config",
+            "--interactive",
+            "--skip-brew",
+            "--home",
+        ])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .write_stdin("n\nb\n")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("-# mine\n"));
+
+    assert!(!home.path().join(".gitconfig").exists());
+    assert!(home.path().join(".zshrc").is_symlink());
+    assert!(!home.path().join(".wezterm.lua").exists());
+    let backups: Vec<_> = std::fs::read_dir(home.path().join(".dotstrap-backups"))
+        .unwrap()
+        .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect();
+    assert_eq!(backups, ["# mine\n"]);
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["tests/empty-config", "--interactive", "--yes"])
+        .assert()
+        .failure();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_dry_run_explain_gives_reasons() {
+    let home = tempfile::TempDir::new().unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg("tests/config-v2")
+        .arg("--dry-run")
+        .arg("--explain")
+        .arg("--home")
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "create ~/.gitconfig (destination missing)\n",
+        ))
+        .stdout(predicates::str::contains(
+            "skip ~/.work-vpn (profile mismatch: no profile, not work)\n",
+        ))
+        .stdout(predicates::str::contains("(pre-apply hook)\n"));
+
+    let output = Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg("tests/config-v2")
+        .arg("--dry-run")
+        .arg("--output")
+        .arg("json")
+        .arg("--home")
+        .arg(home.path())
+        .env("HOME", home.path())
+        .output()
+        .unwrap();
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["linked"][0]["reason"], "destination missing");
+    assert_eq!(report["linked"][1]["status"], "skipped");
+}
+
+#[test]
+#[cfg(unix)]
+fn test_create_only_templates_are_never_overwritten() {
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 2\ntemplates:\n  - source: local.hbs\n    destination: .gitconfig.local\n    overwrite: never\n",
+    )
+    .unwrap();
+    std::fs::write(source.path().join("local.hbs"), "[user]\n").unwrap();
+    let home = tempfile::TempDir::new().unwrap();
+    let apply = || {
+        Command::cargo_bin("dotstrap")
+            .unwrap()
+            .arg(source.path())
+            .arg("--home")
+            .arg(home.path())
+            .arg("--skip-brew")
+            .env("HOME", home.path())
+            .assert()
+            .success();
+    };
+    let seeded = home.path().join(".gitconfig.local");
+
+    apply();
+    assert!(!seeded.is_symlink());
+    assert_eq!(std::fs::read_to_string(&seeded).unwrap(), "[user]\n");
+
+    std::fs::write(&seeded, "[user]\n  email = me@example.com\n").unwrap();
+    std::fs::write(source.path().join("local.hbs"), "[user]\n  name = me\n").unwrap();
+    apply();
+    assert_eq!(
+        std::fs::read_to_string(&seeded).unwrap(),
+        "[user]\n  email = me@example.com\n"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_enabled_expressions_toggle_entries_by_value() {
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 2\n\
+         templates:\n\
+         \x20 - source: zshrc.hbs\n    destination: .zshrc\n\
+         \x20 - source: vpn.hbs\n    destination: .vpn\n    enabled: \"{{work_machine}}\"\n\
+         hooks:\n  post_apply:\n    - run: echo vpn-setup\n      enabled: \"{{work_machine}}\"\n",
+    )
+    .unwrap();
+    std::fs::write(source.path().join("values.yaml"), "work_machine: false\n").unwrap();
+    std::fs::write(source.path().join("zshrc.hbs"), "").unwrap();
+    std::fs::write(source.path().join("vpn.hbs"), "").unwrap();
+    let home = tempfile::TempDir::new().unwrap();
+    let dry_run = || {
+        let output = Command::cargo_bin("dotstrap")
+            .unwrap()
+            .arg(source.path())
+            .args(["--dry-run", "--output", "json", "--home"])
+            .arg(home.path())
+            .env("HOME", home.path())
+            .output()
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+
+    let report = dry_run();
+    assert_eq!(report["linked"][1]["status"], "skipped");
+    assert_eq!(
+        report["linked"][1]["reason"],
+        "disabled: `{{work_machine}}` is false"
+    );
+    assert_eq!(report["hooks"], serde_json::json!([]));
+
+    std::fs::write(source.path().join("values.yaml"), "work_machine: true\n").unwrap();
+    let report = dry_run();
+    assert_eq!(report["linked"][1]["status"], "created");
+    assert_eq!(report["hooks"], serde_json::json!(["echo vpn-setup"]));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_modules_apply_in_dependency_order() {
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 2\n\
+         modules:\n\
+         \x20 terminal:\n\
+         \x20   requires: [fonts]\n\
+         \x20   templates:\n      - source: alacritty.hbs\n        destination: .alacritty.toml\n\
+         \x20 fonts:\n\
+         \x20   values:\n      font: Fira Code\n\
+         \x20   hooks:\n      post_apply: [echo fonts]\n",
+    )
+    .unwrap();
+    std::fs::write(source.path().join("alacritty.hbs"), "font = \"{{font}}\"\n").unwrap();
+    let home = tempfile::TempDir::new().unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(source.path())
+        .args(["--dry-run", "--home"])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "Modules in order: fonts, terminal.",
+        ));
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(source.path())
+        .args(["--skip-brew", "--home"])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_to_string(home.path().join(".alacritty.toml")).unwrap(),
+        "font = \"Fira Code\"\n"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_modules_toggle_from_values_and_flags() {
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 2\n\
+         modules:\n\
+         \x20 shell:\n\
+         \x20   templates:\n      - source: zshrc\n        destination: .zshrc\n\
+         \x20 gui:\n\
+         \x20   templates:\n      - source: alacritty\n        destination: .alacritty.toml\n",
+    )
+    .unwrap();
+    std::fs::write(
+        source.path().join("values.yaml"),
+        "modules:\n  gui: false\n",
+    )
+    .unwrap();
+    std::fs::write(source.path().join("zshrc"), "export EDITOR=vim\n").unwrap();
+    std::fs::write(source.path().join("alacritty"), "[font]\n").unwrap();
+    let home = tempfile::TempDir::new().unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(source.path())
+        .args(["--dry-run", "--home"])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Modules disabled: gui."));
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(source.path())
+        .args(["--skip-brew", "--disable", "missing", "--home"])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .code(3)
+        .stderr(predicates::str::contains(
+            "no module named `missing` to enable or disable",
+        ));
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(source.path())
+        .args(["--skip-brew", "--home"])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    assert!(home.path().join(".zshrc").exists());
+    assert!(!home.path().join(".alacritty.toml").exists());
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(source.path())
+        .args([
+            "--skip-brew",
+            "--enable",
+            "gui",
+            "--disable",
+            "shell",
+            "--home",
+        ])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    assert!(home.path().join(".alacritty.toml").exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_module_sources_pull_in_another_repository() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let zsh = dir.path().join("dotstrap-zsh");
+    std::fs::create_dir_all(zsh.join("templates")).unwrap();
+    std::fs::write(
+        zsh.join("manifest.yaml"),
+        "version: 2\ntemplates:\n  - source: templates/zshrc.hbs\n    destination: .zshrc\n",
+    )
+    .unwrap();
+    std::fs::write(zsh.join("values.yaml"), "prompt: '%~ '\n").unwrap();
+    std::fs::write(zsh.join("templates/zshrc.hbs"), "PROMPT='{{prompt}}'\n").unwrap();
+    let source = dir.path().join("dotfiles");
+    std::fs::create_dir(&source).unwrap();
+    std::fs::write(
+        source.join("manifest.yaml"),
+        "version: 2\nmodules:\n  zsh:\n    source: ../dotstrap-zsh\n    values:\n      prompt: '$ '\n",
+    )
+    .unwrap();
+    let home = tempfile::TempDir::new().unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(&source)
+        .args(["--skip-brew", "--home"])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_to_string(home.path().join(".zshrc")).unwrap(),
+        "PROMPT='$ '\n"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_verify_reports_changed_links_and_modes() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 1\n\
+         templates:\n\
+         \x20 - source: zshrc\n    destination: .zshrc\n\
+         \x20 - source: netrc\n    destination: .netrc\n    mode: 0o600\n",
+    )
+    .unwrap();
+    std::fs::write(source.path().join("zshrc"), "export EDITOR=vim\n").unwrap();
+    std::fs::write(source.path().join("netrc"), "machine example.com\n").unwrap();
+    let home = tempfile::TempDir::new().unwrap();
+    let verify = || {
+        let mut command = Command::cargo_bin("dotstrap").unwrap();
+        command
+            .args(["verify", "--home"])
+            .arg(home.path())
+            .env("HOME", home.path());
+        command
+    };
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(source.path())
+        .args(["--skip-brew", "--home"])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    verify()
+        .assert()
+        .success()
+        .stdout("Verified 2 managed file(s): 0 discrepancy(ies).\n");
+
+    let staged = home.path().join(".dotstrap/generated/.netrc");
+    std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o644)).unwrap();
+    std::fs::remove_file(home.path().join(".zshrc")).unwrap();
+    std::fs::write(home.path().join(".zshrc"), "edited\n").unwrap();
+    verify()
+        .assert()
+        .code(7)
+        .stdout(predicates::str::contains(
+            ".netrc: staged file has mode 0644, expected 0600",
+        ))
+        .stdout(predicates::str::contains(
+            ".zshrc: replaced by a different file",
+        ));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_clean_removes_staging_and_stale_clones() {
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 1\n\
+         templates:\n\
+         \x20 - source: zshrc\n    destination: .zshrc\n",
+    )
+    .unwrap();
+    std::fs::write(source.path().join("zshrc"), "export EDITOR=vim\n").unwrap();
+    let home = tempfile::TempDir::new().unwrap();
+    let stale = home
+        .path()
+        .join(".dotstrap/sources/https-github-com-me-old");
+    std::fs::create_dir_all(&stale).unwrap();
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(source.path())
+        .args(["--skip-brew", "--home"])
+        .arg(home.path())
+        .env("HOME", home.path())
+        .assert()
+        .success();
+    let staging = home.path().join(".dotstrap/generated");
+    let clean = |dry_run: bool| {
+        let mut command = Command::cargo_bin("dotstrap").unwrap();
+        command
+            .args(["clean", "--home"])
+            .arg(home.path())
+            .env("HOME", home.path());
+        if dry_run {
+            command.arg("--dry-run");
+        }
+        command
+    };
+
+    clean(true)
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Would remove 2 path(s)."));
+    assert!(staging.exists());
+    clean(false)
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Removed 2 path(s)."));
+    assert!(!staging.exists());
+    assert!(!stale.exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_several_homes_are_applied_in_turn() {
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 1\n\
+         templates:\n\
+         \x20 - source: zshrc\n    destination: .zshrc\n",
+    )
+    .unwrap();
+    std::fs::write(source.path().join("zshrc"), "export EDITOR=vim\n").unwrap();
+    let home = tempfile::TempDir::new().unwrap();
+    let other = tempfile::TempDir::new().unwrap();
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .arg(source.path())
+        .args(["--skip-brew", "--output", "json", "--home"])
+        .arg(home.path())
+        .arg("--home")
+        .arg(other.path())
+        .env("HOME", home.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(format!(
+            "\"home\": \"{}\"",
+            other.path().display()
+        )));
+    for home in [&home, &other] {
+        assert_eq!(
+            std::fs::read_to_string(home.path().join(".zshrc")).unwrap(),
+            "export EDITOR=vim\n"
+        );
+        assert!(home.path().join(".dotstrap/history.jsonl").exists());
+    }
+
+    Command::cargo_bin("dotstrap")
+        .unwrap()
+        .args(["verify", "--home"])
+        .arg(home.path())
+        .arg("--home")
+        .arg(other.path())
+        .env("HOME", home.path())
+        .assert()
+        .code(2)
+        .stderr(predicates::str::contains("several times"));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_container_mode_copies_templates() {
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 1\n\
+         templates:\n\
+         \x20 - source: zshrc\n    destination: .zshrc\n",
+    )
+    .unwrap();
+    std::fs::write(source.path().join("zshrc"), "export EDITOR=vim\n").unwrap();
+    let home = tempfile::TempDir::new().unwrap();
+    let dotstrap = |args: &[&str]| {
+        let mut command = Command::cargo_bin("dotstrap").unwrap();
+        command
+            .args(args)
+            .args(["--mode", "container", "--skip-brew", "--home"])
+            .arg(home.path())
+            .env("HOME", home.path());
+        command
+    };
+    let source_path = source.path().to_str().unwrap();
+
+    dotstrap(&["apply", source_path]).assert().success().stderr(
+        predicates::prelude::PredicateBooleanExt::not(predicates::str::contains("symbolic links")),
+    );
+    let zshrc = home.path().join(".zshrc");
+    assert!(!zshrc.is_symlink());
+    assert_eq!(
+        std::fs::read_to_string(&zshrc).unwrap(),
+        "export EDITOR=vim\n"
+    );
+    dotstrap(&["verify"]).assert().success();
+
+    std::fs::write(source.path().join("zshrc"), "export EDITOR=hx\n").unwrap();
+    dotstrap(&["apply", source_path]).assert().success();
+    assert!(!zshrc.is_symlink());
+    assert_eq!(
+        std::fs::read_to_string(&zshrc).unwrap(),
+        "export EDITOR=hx\n"
+    );
+    assert!(!home.path().join(".dotstrap-backups").exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_ci_mode_reports_json_and_fails_on_questions() {
+    let home = tempfile::TempDir::new().unwrap();
+    let ci = |source: &str| {
+        let mut command = Command::cargo_bin("dotstrap").unwrap();
+        command
+            .args(["apply", source, "--ci", "--skip-brew", "--home"])
+            .arg(home.path())
+            .env("HOME", home.path());
+        command
+    };
+
+    ci("tests/empty-config")
+        .assert()
+        .success()
+        .stdout(predicates::str::starts_with("{"))
+        .stdout(predicates::str::contains("\"linked\""));
+    ci("tests/config-questions")
+        .assert()
+        .code(3)
+        .stdout(predicates::str::contains(
+            "question `editor` has no value or saved answer",
+        ));
+    assert!(!home.path().join(".profile").exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_offline_apply_needs_a_cached_clone_of_remote_sources() {
+    let home = tempfile::TempDir::new().unwrap();
+    let offline = |source: &str| {
+        let mut command = Command::cargo_bin("dotstrap").unwrap();
+        command
+            .args(["apply", source, "--offline", "--skip-brew", "--home"])
+            .arg(home.path())
+            .env("HOME", home.path());
+        command
+    };
+
+    offline("https://github.com/me/dotfiles.git")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "cloning https://github.com/me/dotfiles.git",
+        ))
+        .stderr(predicates::str::contains("which --offline forbids"));
+    offline("tests/empty-config").assert().success();
+    assert!(!home.path().join(".dotstrap/sources").exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_dry_run_shows_diffs_of_changed_files_only() {
+    let source = tempfile::TempDir::new().unwrap();
+    std::fs::write(
+        source.path().join("manifest.yaml"),
+        "version: 1\n\
+         templates:\n\
+         \x20 - source: zshrc\n    destination: .zshrc\n\
+         \x20 - source: vimrc\n    destination: .vimrc\n",
+    )
+    .unwrap();
+    std::fs::write(
+        source.path().join("zshrc"),
+        "export EDITOR=vim\nalias ll='ls -l'\n",
+    )
+    .unwrap();
+    std::fs::write(source.path().join("vimrc"), "set number\n").unwrap();
+    let home = tempfile::TempDir::new().unwrap();
+    let dotstrap = |args: &[&str]| {
+        let mut command = Command::cargo_bin("dotstrap").unwrap();
+        command
+            .arg(source.path())
+            .args(args)
+            .arg("--home")
+            .arg(home.path())
+            .env("HOME", home.path());
+        command
+    };
+
+    dotstrap(&["--skip-brew"]).assert().success();
+    std::fs::write(
+        source.path().join("zshrc"),
+        "export EDITOR=vim\nalias ll='ls -la'\n",
+    )
+    .unwrap();
+
+    dotstrap(&["--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "overwrite ~/.zshrc\n     export EDITOR=vim\n    -alias ll='ls -l'\n    +alias ll='ls -la'\n",
+        ))
+        .stdout(predicates::str::contains("unchanged ~/.vimrc\n"));
+}